@@ -0,0 +1,76 @@
+//! Records the details `/api/version` reports about the build: the git
+//! commit, the build time and the enabled cargo features
+
+use std::{
+    env,
+    path::Path,
+    process::Command,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+fn main() {
+    // Rebuilding on every commit or checkout keeps the SHA current; the
+    // sources are listed too, as naming any path turns off cargo's default
+    // of rerunning on every change in the package. A missing path would
+    // rerun the script on every build, so only existing ones are named.
+    for path in [
+        ".git/HEAD",
+        ".git/refs",
+        ".git/packed-refs",
+        "src",
+        "public",
+        "Cargo.toml",
+        "build.rs",
+    ]
+    .into_iter()
+    .filter(|path| Path::new(path).exists())
+    {
+        println!("cargo:rerun-if-changed={}", path);
+    }
+    println!("cargo:rerun-if-env-changed=ROSSBY_BUILD_GIT_SHA");
+    println!("cargo:rerun-if-env-changed=SOURCE_DATE_EPOCH");
+
+    // Source archives have no repository, so packagers can pass the commit.
+    // Cargo also sets these variables when running tests, so they stay out
+    // of the ROSSBY_VIS_ prefix the settings are read from.
+    let git_sha = env::var("ROSSBY_BUILD_GIT_SHA")
+        .ok()
+        .filter(|sha| !sha.is_empty())
+        .or_else(git_head)
+        .unwrap_or_else(|| "unknown".to_string());
+    println!("cargo:rustc-env=ROSSBY_BUILD_GIT_SHA={}", git_sha);
+
+    // SOURCE_DATE_EPOCH pins the time for reproducible builds
+    let built_at = env::var("SOURCE_DATE_EPOCH")
+        .ok()
+        .and_then(|secs| secs.trim().parse::<u64>().ok())
+        .unwrap_or_else(|| {
+            SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map_or(0, |elapsed| elapsed.as_secs())
+        });
+    println!("cargo:rustc-env=ROSSBY_BUILD_EPOCH={}", built_at);
+
+    let mut features: Vec<String> = env::vars()
+        .filter_map(|(name, _)| {
+            let feature = name.strip_prefix("CARGO_FEATURE_")?;
+            Some(feature.to_ascii_lowercase().replace('_', "-"))
+        })
+        .filter(|feature| feature != "default")
+        .collect();
+    features.sort();
+    println!(
+        "cargo:rustc-env=ROSSBY_BUILD_FEATURES={}",
+        features.join(",")
+    );
+}
+
+/// Full SHA of the checked out commit, when building from a git repository
+fn git_head() -> Option<String> {
+    let output = Command::new("git")
+        .args(["rev-parse", "HEAD"])
+        .output()
+        .ok()?;
+    let sha = String::from_utf8(output.stdout).ok()?.trim().to_string();
+    (output.status.success() && !sha.is_empty()).then_some(sha)
+}
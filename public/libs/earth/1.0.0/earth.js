@@ -70,6 +70,11 @@
         return µ.newAgent().on({"reject": report.error, "fail": report.error});
     }
 
+    var preferredUnits = [];  // unit labels shown until the user toggles units, from /api/prefs
+    products.preferredUnits.then(function(labels) {
+        preferredUnits = labels;
+    });
+
     // Construct the page's main internal components:
 
     var configuration =
@@ -308,8 +313,31 @@
             return;
         }
         
-        // Fallback to original navigation logic
-        var next = gridAgent.value().primaryGrid.navigate(step);
+        // Ask the server for the neighbouring timestep so gaps and the ends of
+        // the dataset follow its actual time axis
+        var grid = gridAgent.value().primaryGrid;
+        if (grid.date) {
+            var direction = step < 0 ? "previous" : "next";
+            var reference = step < 0 ? "before" : "after";
+            var url = "api/time/" + direction + "?" + reference + "=" +
+                encodeURIComponent(grid.date.toISOString()) + "&steps=" + Math.abs(step);
+            µ.loadJson(url).then(function(result) {
+                if (result.iso) {
+                    configuration.save(µ.dateToConfig(new Date(result.iso)));
+                } else {
+                    log.debug("No timestep " + direction + " of " + grid.date.toISOString());
+                }
+            }).otherwise(function() {
+                // Older servers without the time API: guess from the product's cadence
+                var next = grid.navigate(step);
+                if (next) {
+                    configuration.save(µ.dateToConfig(next));
+                }
+            });
+            return;
+        }
+
+        var next = grid.navigate(step);
         if (next) {
             configuration.save(µ.dateToConfig(next));
         }
@@ -590,7 +618,7 @@
         // maxIntensity is the velocity at which particle color intensity is maximum
         var colorStyles = µ.windIntensityColorScale(INTENSITY_SCALE_STEP, grids.primaryGrid.particles.maxIntensity);
         var buckets = colorStyles.map(function() { return []; });
-        var particleCount = Math.round(bounds.width * PARTICLE_MULTIPLIER);
+        var particleCount = Math.round(bounds.width * PARTICLE_MULTIPLIER * (grids.primaryGrid.particleDensity || 1));
         if (µ.isMobile()) {
             particleCount *= PARTICLE_REDUCTION;
         }
@@ -767,6 +795,18 @@
         d3.select("#data-center").text(center);
     }
 
+    /**
+     * Returns the index of the first of the specified units in the deployment's preferred unit system, or 0.
+     */
+    function preferredUnitIndex(units) {
+        for (var i = 0; i < units.length; i++) {
+            if (_.contains(preferredUnits, units[i].label)) {
+                return i;
+            }
+        }
+        return 0;
+    }
+
     /**
      * Constructs a toggler for the specified product's units, storing the toggle state on the element having
      * the specified id. For example, given a product having units ["m/s", "mph"], the object returned by this
@@ -775,7 +815,8 @@
      */
     function createUnitToggle(id, product) {
         var units = product.units, size = units.length;
-        var index = +(d3.select(id).attr("data-index") || 0) % size;
+        var toggled = d3.select(id).attr("data-index");
+        var index = toggled !== null ? +toggled % size : preferredUnitIndex(units);
         return {
             value: function() {
                 return units[index];
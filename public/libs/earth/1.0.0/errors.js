@@ -0,0 +1,74 @@
+/**
+ * errors - reports uncaught errors and unhandled promise rejections to the server
+ *
+ * Reports are batched and posted to /api/client-errors a few seconds after the
+ * first one, and flushed when the page is hidden. The server logs them next to
+ * its own request logs.
+ */
+(function() {
+    "use strict";
+
+    var ENDPOINT = "api/client-errors";
+    var FLUSH_DELAY = 5000;  // milliseconds to gather a batch
+    var MAX_QUEUED = 20;     // the server drops anything beyond this per batch
+
+    var queue = [];
+    var timer = null;
+    var seen = {};
+
+    function flush() {
+        timer = null;
+        if (queue.length === 0) {
+            return;
+        }
+        var body = JSON.stringify({errors: queue});
+        queue = [];
+        var blob = new Blob([body], {type: "application/json"});
+        if (!(navigator.sendBeacon && navigator.sendBeacon(ENDPOINT, blob))) {
+            var request = new XMLHttpRequest();
+            request.open("POST", ENDPOINT);
+            request.setRequestHeader("Content-Type", "application/json");
+            request.send(body);
+        }
+    }
+
+    function report(error) {
+        // The same error repeating in an animation loop is reported once
+        var key = error.message + "|" + error.source + "|" + error.line;
+        if (seen[key] || queue.length >= MAX_QUEUED) {
+            return;
+        }
+        seen[key] = true;
+        error.url = window.location.href;
+        error.timestamp = new Date().toISOString();
+        queue.push(error);
+        if (!timer) {
+            timer = setTimeout(flush, FLUSH_DELAY);
+        }
+    }
+
+    window.addEventListener("error", function(event) {
+        report({
+            message: String(event.message || "Unknown error"),
+            source: event.filename || null,
+            line: event.lineno || null,
+            column: event.colno || null,
+            stack: event.error && event.error.stack ? String(event.error.stack) : null
+        });
+    });
+
+    window.addEventListener("unhandledrejection", function(event) {
+        var reason = event.reason || {};
+        report({
+            message: "Unhandled rejection: " + String(reason.message || reason),
+            stack: reason.stack ? String(reason.stack) : null,
+            request_id: reason.requestId || null
+        });
+    });
+
+    document.addEventListener("visibilitychange", function() {
+        if (document.visibilityState === "hidden") {
+            flush();
+        }
+    });
+})();
@@ -5,11 +5,31 @@
 var MetadataUI = (function() {
     "use strict";
 
+    // Prefix for proxy requests, from the rossby-api-base meta tag in index.html; without one,
+    // requests are relative to the page's <base href>
+    var API_BASE = (function() {
+        var meta = document.querySelector('meta[name="rossby-api-base"]');
+        var base = meta ? meta.getAttribute("content") : "";
+        return base && base.indexOf("{{") !== 0 ? base + "/" : "";
+    })();
+
+    // Dataset chosen with the switcher, see /api/datasets; the default when empty
+    var DATASET_KEY = "rossby-dataset";
+    var DATASET = (function() {
+        try {
+            return window.localStorage.getItem(DATASET_KEY) || "";
+        } catch (e) {
+            return "";
+        }
+    })();
+    var PROXY_BASE = API_BASE + "proxy" + (DATASET ? "/" + encodeURIComponent(DATASET) : "");
+
     function detectMode(metadata) {
         var variables = Object.keys(metadata.variables || {});
+        var pairs = vectorPairs(metadata);
 
         // Wind mode detection - look for u/v component pairs
-        var windPairs = detectWindPairs(variables);
+        var windPairs = pairs.wind;
         if (windPairs.length > 0) {
             return {
                 mode: 'wind',
@@ -20,7 +40,7 @@ var MetadataUI = (function() {
         }
         
         // Ocean mode detection - look for ust/vst pairs
-        var oceanPairs = detectOceanPairs(variables);
+        var oceanPairs = pairs.ocean;
         if (oceanPairs.length > 0) {
             return {
                 mode: 'ocean',
@@ -37,6 +57,33 @@ var MetadataUI = (function() {
         };
     }
 
+    /**
+     * Wind and ocean current pairs, from the naming patterns below and from the
+     * northward_component attribute the server sets on each eastward component
+     * it pairs by standard name, long name or name (UGRD/VGRD, water_u/water_v, ...).
+     */
+    function vectorPairs(metadata) {
+        var variables = metadata.variables || {};
+        var names = Object.keys(variables);
+        var pairs = {wind: detectWindPairs(names), ocean: detectOceanPairs(names)};
+        var known = function(pair) {
+            return isWindComponent(pair.u, pairs.wind) || isOceanComponent(pair.u, pairs.ocean);
+        };
+
+        names.forEach(function(u) {
+            var attributes = variables[u].attributes || {};
+            var pair = {u: u, v: attributes.northward_component};
+            if (!pair.v || !variables[pair.v] || known(pair)) {
+                return;
+            }
+            var description = (attributes.standard_name || "") + " " + (attributes.long_name || "");
+            var ocean = /current|sea_water|ocean/i.test(description);
+            (ocean ? pairs.ocean : pairs.wind).push(pair);
+        });
+
+        return pairs;
+    }
+
     function detectWindPairs(variables) {
         var pairs = [];
         var windPatterns = [
@@ -44,7 +91,8 @@ var MetadataUI = (function() {
             {u: /^u(\d+)hPa$/, v: /^v(\d+)hPa$/},  // u850hPa/v850hPa
             {u: /^uas$/, v: /^vas$/},              // Surface wind (CMIP naming)
             {u: /^ua$/, v: /^va$/},                // Generic atmospheric wind
-            {u: /^u_wind$/, v: /^v_wind$/}         // Alternative naming
+            {u: /^u_wind$/, v: /^v_wind$/},        // Alternative naming
+            {u: /^uivt$/, v: /^vivt$/}             // Integrated vapour transport (derived)
         ];
 
         windPatterns.forEach(function(pattern) {
@@ -183,9 +231,9 @@ var MetadataUI = (function() {
         
         // If we have wind pairs available but current mode is different, still mark wind as available
         if (window.lastMetadata) {
-            var variables = Object.keys(window.lastMetadata.variables || {});
-            var windPairs = detectWindPairs(variables);
-            var oceanPairs = detectOceanPairs(variables);
+            var pairs = vectorPairs(window.lastMetadata);
+            var windPairs = pairs.wind;
+            var oceanPairs = pairs.ocean;
             
             if (windPairs.length > 0 && availableModes.indexOf('wind') === -1) {
                 availableModes.push('wind');
@@ -578,7 +626,7 @@ var MetadataUI = (function() {
                                     attr.currentTime; // Use metadata time as fallback, not old GFS time
                     
                     // Build data URL with time and level parameters
-                    var dataUrl = '/proxy/data?vars=' + varName + '&time=' + currentTime;
+                    var dataUrl = PROXY_BASE + '/data?vars=' + varName + '&time=' + currentTime;
                     
                     // Add level parameter if 3D data is selected
                     if (attr.metadataLevel && attr.metadataLevel !== 'Sfc' && attr.metadataLevel !== 'surface') {
@@ -757,6 +805,60 @@ var MetadataUI = (function() {
         }
     }
 
+    /**
+     * Shows the dataset switcher when the server has several datasets. Switching
+     * remembers the choice and reloads the page, so every request follows it.
+     */
+    function setupDatasetSelection() {
+        var row = d3.select('#dataset-selection');
+        if (row.empty()) return;
+
+        fetch(API_BASE + 'api/datasets')
+            .then(function(response) {
+                return response.ok ? response.json() : {datasets: []};
+            })
+            .then(function(result) {
+                var datasets = result.datasets || [];
+                var names = datasets.map(function(d) { return d.name; });
+                var choose = function(name) {
+                    try {
+                        if (name) {
+                            window.localStorage.setItem(DATASET_KEY, name);
+                        } else {
+                            window.localStorage.removeItem(DATASET_KEY);
+                        }
+                    } catch (e) {
+                        return;
+                    }
+                    window.location.reload();
+                };
+                if (DATASET && names.indexOf(DATASET) < 0) {
+                    // The dataset is gone from the server
+                    choose("");
+                    return;
+                }
+                if (datasets.length < 2) return;
+
+                var container = d3.select('#dataset-options');
+                container.html('');
+                datasets.forEach(function(dataset) {
+                    var selected = DATASET ? dataset.name === DATASET : dataset["default"];
+                    container.append('span')
+                        .attr('class', 'text-button')
+                        .classed('highlighted', selected)
+                        .text(dataset.name)
+                        .on('click', function() {
+                            if (!selected) choose(dataset["default"] ? "" : dataset.name);
+                        });
+                    container.append('span').text(' ');
+                });
+                row.classed('invisible', false);
+            })
+            .catch(function(error) {
+                console.warn('MetadataUI: Failed to load datasets:', error);
+            });
+    }
+
     function generateHeightControls(levels, levelType) {
         var container = d3.select('#surface-level');
         
@@ -858,7 +960,8 @@ var MetadataUI = (function() {
             console.log('MetadataUI: Starting initialization...');
             
             var self = this;
-            return fetch('/proxy/metadata')
+            setupDatasetSelection();
+            return fetch(PROXY_BASE + '/metadata')
                 .then(function(response) {
                     if (!response.ok) {
                         throw new Error('HTTP ' + response.status);
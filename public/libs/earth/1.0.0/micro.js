@@ -12,7 +12,7 @@ var µ = function() {
     var τ = 2 * Math.PI;
     var H = 0.0000360;  // 0.0000360°φ ~= 4m
     var DEFAULT_CONFIG = "current/wind/surface/level/orthographic";
-    var TOPOLOGY = isMobile() ? "/data/earth-topo-mobile.json?v2" : "/data/earth-topo.json?v2";
+    var TOPOLOGY = isMobile() ? "data/earth-topo-mobile.json?v2" : "data/earth-topo.json?v2";
 
     /**
      * @returns {Boolean} true if the specified value is truthy.
@@ -436,6 +436,53 @@ var µ = function() {
         return wd.toFixed(0) + "° @ " + formatScalar(wind[2], units);
     }
 
+    /**
+     * Returns the values of a packed data array: quantized values stored as little-endian base64 deltas, the
+     * smallest value of the type marking missing values (null).
+     */
+    function unpackArray(packed) {
+        var binary = atob(packed.values), bytes = new Uint8Array(binary.length);
+        for (var i = 0; i < binary.length; i++) {
+            bytes[i] = binary.charCodeAt(i);
+        }
+        var view = new DataView(bytes.buffer);
+        var read = {
+            int8: function(j) { return view.getInt8(j); },
+            int16: function(j) { return view.getInt16(j * 2, true); },
+            int32: function(j) { return view.getInt32(j * 4, true); }
+        }[packed.type];
+        var missing = {int8: -0x80, int16: -0x8000, int32: -0x80000000}[packed.type];
+        var data = new Array(packed.length), sum = 0;
+        for (var k = 0; k < packed.length; k++) {
+            var delta = read(k);
+            if (delta === missing) {
+                data[k] = null;
+            } else {
+                sum += delta;
+                data[k] = sum * packed.scale;
+            }
+        }
+        return data;
+    }
+
+    /**
+     * Replaces the packed data arrays of Earth records, or of the records of frame bundles, by plain arrays.
+     * Other JSON is returned as is.
+     */
+    function unpackRecords(json) {
+        if (Array.isArray(json)) {
+            json.forEach(function(record) {
+                if (record && record.packed) {
+                    record.data = unpackArray(record.packed);
+                    delete record.packed;
+                }
+            });
+        } else if (json && Array.isArray(json.frames)) {
+            json.frames.forEach(function(frame) { unpackRecords(frame.records); });
+        }
+        return json;
+    }
+
     /**
      * Returns a promise for a JSON resource (URL) fetched via XHR. If the load fails, the promise rejects with an
      * object describing the reason: {status: http-status-code, message: http-status-text, resource:}.
@@ -447,7 +494,7 @@ var µ = function() {
                 !error.status ?
                     d.reject({status: -1, message: "Cannot load resource: " + resource, resource: resource}) :
                     d.reject({status: error.status, message: error.statusText, resource: resource}) :
-                d.resolve(result);
+                d.resolve(unpackRecords(result));
         });
         return d.promise;
     }
@@ -817,6 +864,8 @@ var µ = function() {
         formatCoordinates: formatCoordinates,
         formatScalar: formatScalar,
         formatVector: formatVector,
+        unpackArray: unpackArray,
+        unpackRecords: unpackRecords,
         loadJson: loadJson,
         distortion: distortion,
         newAgent: newAgent,
@@ -9,12 +9,39 @@
 var products = function() {
     "use strict";
 
-    var WEATHER_PATH = "/data/weather";
-    var OSCAR_PATH = "/data/oscar";
+    // Prefix for proxy requests, from the rossby-api-base meta tag in index.html; without one,
+    // requests are relative to the page's <base href>
+    var API_BASE = (function() {
+        var meta = document.querySelector('meta[name="rossby-api-base"]');
+        var base = meta ? meta.getAttribute("content") : "";
+        return base && base.indexOf("{{") !== 0 ? base + "/" : "";
+    })();
+    // Data of the dataset chosen with the switcher, see /api/datasets
+    var PROXY_BASE = API_BASE + "proxy" + (function() {
+        try {
+            var dataset = window.localStorage.getItem("rossby-dataset");
+            return dataset ? "/" + encodeURIComponent(dataset) : "";
+        } catch (e) {
+            return "";
+        }
+    })();
+    var WEATHER_PATH = "data/weather";
+    var OSCAR_PATH = "data/oscar";
     var catalogs = {
         // The OSCAR catalog is an array of file names, sorted and prefixed with yyyyMMdd. Last item is the
         // most recent. For example: [ 20140101-abc.json, 20140106-abc.json, 20140112-abc.json, ... ]
-        oscar: µ.loadJson([OSCAR_PATH, "catalog.json"].join("/"))
+        oscar: µ.loadJson([OSCAR_PATH, "catalog.json"].join("/")),
+        // Overlays generated by the server from the dataset's metadata, keyed by type. Servers without the
+        // catalog leave it empty and overlays fall back to the defaults guessed from the variable name.
+        products: µ.loadJson(API_BASE + "data/products.json").then(function(catalog) {
+            return _.indexBy(catalog.products || [], "type");
+        }).otherwise(function() {
+            return {};
+        }),
+        // Display defaults of the deployment, such as its preferred unit system
+        prefs: µ.loadJson(API_BASE + "api/prefs").otherwise(function() {
+            return {};
+        })
     };
 
     function buildProduct(overrides) {
@@ -55,9 +82,9 @@ var products = function() {
         if (attr.metadataTime && (type === "wind" || type === "temp")) {
             if (type === "wind") {
 
-                return '/proxy/data?vars=u10,v10&time=' + attr.metadataTime + '&format=json';
+                return PROXY_BASE + '/data?vars=u10,v10&time=' + attr.metadataTime + '&format=json';
             } else if (type === "temp") {
-                return '/proxy/data?vars=t2m&time=' + attr.metadataTime + '&format=json';
+                return PROXY_BASE + '/data?vars=t2m&time=' + attr.metadataTime + '&format=json';
             }
         }
 
@@ -323,51 +350,8 @@ var products = function() {
                 return overlayType && typeof overlayType === 'string';
             },
             create: function(attr) {
-                var overlayType = attr.overlayType;
-                var path;
-                if (attr.metadataLevel) {
-                    path = '/proxy/data?vars=' + overlayType + '&time=' + attr.metadataTime + '&level=' + attr.metadataLevel + '&format=json';
-                } else {
-                    path = '/proxy/data?vars=' + overlayType + '&time=' + attr.metadataTime + '&format=json';
-                }
-                console.log('Creating scalar overlay product for variable:', overlayType);
-                
-                return buildProduct({
-                    field: "scalar",
-                    type: overlayType,
-                    description: localize({
-                        name: {en: overlayType, ja: overlayType},
-                        qualifier: {en: " @ surface", ja: " @ 地上"}
-                    }),
-                    paths: [path],
-                    date: gfsDate(attr),
-                    builder: function(file) {
-                        console.log('Scalar overlay builder called for', overlayType, 'with file:', file);
-                        
-                        // Handle proxy response format: {data: {variable: [...], metadata: {...}}
-                        if(file && file.data && file.data[overlayType]) {
-                            var data = file.data[overlayType];
-                            var metadata = file.metadata || {};
-                            
-                            console.log(overlayType, 'data loaded, data length:', data ? data.length : 'no data', 'metadata:', metadata);
-                            
-                            // Create Earth-compatible header from metadata
-                            var header = createHeaderFromMetadata(metadata, overlayType, µ.getVariableQuantity(overlayType));
-                            
-                            return {
-                                header: header,
-                                interpolate: bilinearInterpolateScalar,
-                                data: function(i) {
-                                    return data[i];
-                                }
-                            };
-                        } else {
-                            console.error('Scalar overlay builder: Invalid file format for', overlayType, ':', file);
-                            return null;
-                        }
-                    },
-                    units: getVariableUnits(overlayType),
-                    scale: getVariableScale(overlayType)
+                return when(catalogs.products).then(function(catalog) {
+                    return scalarOverlay(attr, catalog[attr.overlayType]);
                 });
             }
         },
@@ -380,6 +364,63 @@ var products = function() {
         }
     };
 
+    /**
+     * Builds the product for a scalar overlay, described by its entry in the server's product catalog if it has one.
+     */
+    function scalarOverlay(attr, entry) {
+        var overlayType = attr.overlayType;
+        // Names the frontend recognizes keep their quantity; the server's catalog also matches on long names
+        var category = µ.getVariableQuantity(overlayType);
+        if (category === "General" && entry) {
+            category = entry.category;
+        }
+        var path;
+        if (attr.metadataLevel) {
+            path = PROXY_BASE + '/data?vars=' + overlayType + '&time=' + attr.metadataTime + '&level=' + attr.metadataLevel + '&format=json';
+        } else {
+            path = PROXY_BASE + '/data?vars=' + overlayType + '&time=' + attr.metadataTime + '&format=json';
+        }
+        console.log('Creating scalar overlay product for variable:', overlayType);
+        
+        return buildProduct({
+            field: "scalar",
+            type: overlayType,
+            description: localize({
+                name: {en: entry ? entry.name : overlayType, ja: entry ? entry.name : overlayType},
+                qualifier: {en: " @ surface", ja: " @ 地上"}
+            }),
+            paths: [path],
+            date: gfsDate(attr),
+            builder: function(file) {
+                console.log('Scalar overlay builder called for', overlayType, 'with file:', file);
+                
+                // Handle proxy response format: {data: {variable: [...], metadata: {...}}
+                if(file && file.data && file.data[overlayType]) {
+                    var data = file.data[overlayType];
+                    var metadata = file.metadata || {};
+                    
+                    console.log(overlayType, 'data loaded, data length:', data ? data.length : 'no data', 'metadata:', metadata);
+                    
+                    // Create Earth-compatible header from metadata
+                    var header = createHeaderFromMetadata(metadata, overlayType, category);
+                    
+                    return {
+                        header: header,
+                        interpolate: bilinearInterpolateScalar,
+                        data: function(i) {
+                            return data[i];
+                        }
+                    };
+                } else {
+                    console.error('Scalar overlay builder: Invalid file format for', overlayType, ':', file);
+                    return null;
+                }
+            },
+            units: getVariableUnits(category),
+            scale: withBounds(getVariableScale(category), entry && entry.bounds)
+        });
+    }
+
     /**
      * Returns the file name for the most recent OSCAR data layer to the specified date. If offset is non-zero,
      * the file name that many entries from the most recent is returned.
@@ -446,6 +487,31 @@ var products = function() {
         return g00 * rx * ry + g10 * x * ry + g01 * rx * y + g11 * x * y;
     }
 
+    /**
+     * Returns the value of the grid point closest to (x, y), for fields hinted with "interpolation": "nearest".
+     */
+    function nearestInterpolate(x, y, g00, g10, g01, g11) {
+        var g = y < 0.5 ? (x < 0.5 ? g00 : g10) : (x < 0.5 ? g01 : g11);
+        return _.isArray(g) ? [g[0], g[1], Math.sqrt(g[0] * g[0] + g[1] * g[1])] : g;
+    }
+
+    /**
+     * Returns a function applying the displayScale and displayOffset hints of a header to a scalar value, or to
+     * the components of a vector, which are only scaled.
+     */
+    function displayTransform(header) {
+        var scale = µ.coalesce(header.displayScale, 1), offset = µ.coalesce(header.displayOffset, 0);
+        if (scale === 1 && offset === 0) {
+            return _.identity;
+        }
+        return function(value) {
+            if (_.isArray(value)) {
+                return µ.isValue(value[0]) && µ.isValue(value[1]) ? [value[0] * scale, value[1] * scale] : value;
+            }
+            return µ.isValue(value) ? value * scale + offset : value;
+        };
+    }
+
     function bilinearInterpolateVector(x, y, g00, g10, g01, g11) {
         var rx = (1 - x);
         var ry = (1 - y);
@@ -491,6 +557,9 @@ var products = function() {
         var ni = header.nx, nj = header.ny;    // number of grid points W-E and N-S (e.g., 144 x 73)
         var date = new Date(header.refTime);
         date.setHours(date.getHours() + header.forecastTime);
+        // Rendering hints configured on the server
+        var display = displayTransform(header);
+        var interpolateCell = header.interpolation === "nearest" ? nearestInterpolate : builder.interpolate;
 
         // Scan mode 0 assumed. Longitude increases from λ0, and latitude decreases from φ0.
         // http://www.nco.ncep.noaa.gov/pmb/docs/grib2/grib2_table3-4.shtml
@@ -499,7 +568,7 @@ var products = function() {
         for (var j = 0; j < nj; j++) {
             var row = [];
             for (var i = 0; i < ni; i++, p++) {
-                row[i] = builder.data(p);
+                row[i] = display(builder.data(p));
             }
             if (isContinuous) {
                 // For wrapped grids, duplicate first column as last column to simplify interpolation logic
@@ -533,7 +602,7 @@ var products = function() {
                     var g11 = row[ci];
                     if (µ.isValue(g01) && µ.isValue(g11)) {
                         // All four points found, so interpolate the value.
-                        return builder.interpolate(i - fi, j - fj, g00, g10, g01, g11);
+                        return interpolateCell(i - fi, j - fj, g00, g10, g01, g11);
                     }
                 }
             }
@@ -544,6 +613,7 @@ var products = function() {
         return {
             source: dataSource(header),
             date: date,
+            particleDensity: µ.coalesce(header.particleDensity, 1),
             interpolate: interpolate,
             forEachPoint: function(cb) {
                 for (var j = 0; j < nj; j++) {
@@ -557,10 +627,9 @@ var products = function() {
     }
 
     /**
-     * Get appropriate units for variable based on category
+     * Get appropriate units for a quantity category
      */
-    function getVariableUnits(variable) {
-        var category = µ.getVariableQuantity(variable);
+    function getVariableUnits(category) {
         switch (category) {
             case 'Temperature':
                 return [
@@ -571,6 +640,7 @@ var products = function() {
             case 'Pressure':
                 return [
                     {label: "hPa", conversion: function(x) { return x / 100; }, precision: 0},
+                    {label: "inHg", conversion: function(x) { return x / 3386.389; }, precision: 2},
                     {label: "Pa", conversion: function(x) { return x; }, precision: 0}
                 ];
             case 'Humidity':
@@ -595,10 +665,9 @@ var products = function() {
     }
 
     /**
-     * Get appropriate color scale for variable based on category
+     * Get appropriate color scale for a quantity category
      */
-    function getVariableScale(variable) {
-        var category = µ.getVariableQuantity(variable);
+    function getVariableScale(category) {
         switch (category) {
             case 'Temperature':
                 return {
@@ -678,6 +747,23 @@ var products = function() {
         }
     }
 
+    /**
+     * Stretches a color scale over the bounds given by the server's product catalog, if any.
+     */
+    function withBounds(scale, bounds) {
+        if (!bounds || scale.bounds[0] === bounds[0] && scale.bounds[1] === bounds[1]) {
+            return scale;
+        }
+        var from = scale.bounds, gradient = scale.gradient;
+        return {
+            bounds: bounds,
+            gradient: function(v, a) {
+                var t = (v - bounds[0]) / (bounds[1] - bounds[0]);
+                return gradient(from[0] + t * (from[1] - from[0]), a);
+            }
+        };
+    }
+
     function productsFor(attributes) {
         var attr = _.clone(attributes), results = [];
         _.values(FACTORIES).forEach(function(factory) {
@@ -689,8 +775,27 @@ var products = function() {
     }
     productsFor.FACTORIES = FACTORIES;
 
+    // Overlays from the server's catalog are valid in the hash as well
+    var overlayTypes = d3.set(_.keys(FACTORIES));
+    catalogs.products.then(function(catalog) {
+        _.keys(catalog).forEach(function(type) {
+            overlayTypes.add(type);
+        });
+    });
+
+    // Unit labels shown first in each unit system, see /api/prefs
+    var PREFERRED_UNITS = {
+        metric: ["°C", "km/h", "hPa"],
+        imperial: ["°F", "mph", "inHg"],
+        kt: ["°C", "kn", "hPa"]
+    };
+    var preferredUnits = catalogs.prefs.then(function(prefs) {
+        return PREFERRED_UNITS[prefs.units] || [];
+    });
+
     return {
-        overlayTypes: d3.set(_.keys(FACTORIES)),
+        overlayTypes: overlayTypes,
+        preferredUnits: preferredUnits,
         productsFor: productsFor
     };
 
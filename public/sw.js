@@ -0,0 +1,155 @@
+/**
+ * sw - service worker caching the application shell and data for offline use
+ *
+ * The page, styles, scripts and topology are fetched from the network first so
+ * updates show up immediately, and served from the cache when offline. Weather
+ * data is cached the same way on the routes /api/cache-manifest marks as data,
+ * and the data cache is dropped when the server's X-Data-Version changes.
+ * API and admin responses are never cached.
+ *
+ * Paths are relative to the directory the worker is served from, so a viewer
+ * mounted under a base path keeps working.
+ */
+var CACHE = "rossby-vis-shell-v1";
+
+// Path the viewer is mounted at, e.g. "/" or "/rossby-vis/"
+var BASE = new URL("./", self.location).pathname;
+
+var SHELL = [
+    "./",
+    "styles/styles.css",
+    "libs/earth/1.0.0/errors.js",
+    "libs/underscore.js/1.6.0/underscore.js",
+    "libs/backbone.js/1.1.0/backbone.js",
+    "libs/topojson/1.1.0/topojson.js",
+    "libs/d3/3.3.10/d3.js",
+    "libs/d3.geo/0.0.0/d3.geo.projection.v0.min.js",
+    "libs/d3.geo/0.0.0/d3.geo.polyhedron.v0.min.js",
+    "libs/when/2.6.0/when.js",
+    "libs/earth/1.0.0/metadata-ui.js",
+    "libs/earth/1.0.0/micro.js",
+    "libs/earth/1.0.0/globes.js",
+    "libs/earth/1.0.0/products.js",
+    "libs/earth/1.0.0/earth.js",
+    "data/earth-topo.json"
+];
+
+var DATA_CACHE = "rossby-vis-data";
+
+// Used when /api/cache-manifest cannot be fetched, e.g. while offline
+var DEFAULT_ROUTES = [
+    {prefix: "/proxy/metadata", kind: "data", strategy: "network-first"},
+    {prefix: "/proxy/data", kind: "data", strategy: "network-first"},
+    {prefix: "/data/products.json", kind: "data", strategy: "network-first"},
+    {prefix: "/data/oscar/", kind: "data", strategy: "network-first"},
+    {prefix: "/data/weather/", kind: "data", strategy: "network-first"},
+    {prefix: "/api/", kind: "api", strategy: "network-only"},
+    {prefix: "/admin/", kind: "admin", strategy: "network-only"},
+    {prefix: "/", kind: "shell", strategy: "network-first"}
+];
+
+var routes = null;
+var dataVersion = null;
+
+// Cacheable routes, from the server's /api/cache-manifest
+function loadRoutes() {
+    if (!routes) {
+        routes = fetch("api/cache-manifest").then(function(response) {
+            return response.json();
+        }).then(function(manifest) {
+            dataVersion = dataVersion || manifest.data_version;
+            return manifest.routes;
+        }).catch(function() {
+            routes = null;
+            return DEFAULT_ROUTES;
+        });
+    }
+    return routes;
+}
+
+// Routes are named by their path below BASE, with a leading slash
+function routeFor(routes, path) {
+    if (path.indexOf(BASE) !== 0) {
+        return undefined;
+    }
+    path = "/" + path.slice(BASE.length);
+    return routes.filter(function(route) {
+        return path.indexOf(route.prefix) === 0;
+    })[0];
+}
+
+// Cached data belongs to one dataset version; drop it once the version changes
+function storeData(request, response) {
+    var version = response.headers.get("X-Data-Version");
+    var ready = Promise.resolve();
+    if (version && dataVersion && version !== dataVersion) {
+        ready = caches.delete(DATA_CACHE);
+    }
+    if (version) {
+        dataVersion = version;
+    }
+    return ready.then(function() {
+        return caches.open(DATA_CACHE);
+    }).then(function(cache) {
+        return cache.put(request, response);
+    });
+}
+
+self.addEventListener("install", function(event) {
+    event.waitUntil(
+        caches.open(CACHE).then(function(cache) {
+            return cache.addAll(SHELL);
+        }).then(function() {
+            return self.skipWaiting();
+        })
+    );
+});
+
+self.addEventListener("activate", function(event) {
+    event.waitUntil(
+        caches.keys().then(function(keys) {
+            return Promise.all(keys.filter(function(key) {
+                return key !== CACHE && key !== DATA_CACHE;
+            }).map(function(key) {
+                return caches.delete(key);
+            }));
+        }).then(function() {
+            return self.clients.claim();
+        })
+    );
+});
+
+self.addEventListener("fetch", function(event) {
+    var request = event.request;
+    var url = new URL(request.url);
+    if (request.method !== "GET" || url.origin !== self.location.origin) {
+        return;
+    }
+
+    event.respondWith(loadRoutes().then(function(routes) {
+        var route = routeFor(routes, url.pathname);
+        if (!route || route.strategy !== "network-first") {
+            return fetch(request);
+        }
+        var data = route.kind === "data";
+
+        // Data responses carry ETags, so the browser revalidates them cheaply
+        return fetch(request).then(function(response) {
+            if (response.ok) {
+                var copy = response.clone();
+                if (data) {
+                    storeData(request, copy);
+                } else {
+                    caches.open(CACHE).then(function(cache) {
+                        cache.put(request, copy);
+                    });
+                }
+            }
+            return response;
+        }).catch(function() {
+            return caches.match(request).then(function(cached) {
+                return cached || (request.mode === "navigate" ? caches.match("./") : undefined);
+            });
+        });
+    }));
+});
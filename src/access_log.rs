@@ -0,0 +1,339 @@
+//! Access log in the Apache Common or Combined Log Format
+//!
+//! Tracing events are not understood by log analyzers such as GoAccess or
+//! AWStats, so with `--access-log` every request is also written as one line
+//! of the classic format, to stdout or a file:
+//!
+//! ```text
+//! 203.0.113.7 - ops [23/Jun/2025:14:02:11 +0200] "GET /proxy/metadata HTTP/1.1" 200 5120 "https://example.org/" "Mozilla/5.0"
+//! ```
+//!
+//! The client address comes from `X-Forwarded-For`, `X-Real-IP` or
+//! `CF-Connecting-IP` and is `-` without a proxy setting them; the user is
+//! the name of HTTP Basic credentials. Lines are written on a background
+//! thread once the response body has been sent.
+
+use axum::http::{header, HeaderMap, Method, Uri, Version};
+use base64::{engine::general_purpose::STANDARD, Engine};
+use chrono::{DateTime, Local};
+use std::{
+    fmt::Write as _,
+    io::{self, Write},
+    path::PathBuf,
+    str::FromStr,
+    sync::{Mutex, OnceLock},
+};
+use tracing_appender::non_blocking::{NonBlocking, NonBlockingBuilder, WorkerGuard};
+
+static ACCESS_LOG: OnceLock<AccessLog> = OnceLock::new();
+/// Keeps the writer thread running; dropping it flushes the log
+static GUARD: Mutex<Option<WorkerGuard>> = Mutex::new(None);
+
+/// Line format of the access log
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum AccessLogFormat {
+    /// Common Log Format: client, user, time, request line, status, bytes
+    Common,
+    /// Combined Log Format: the common fields plus referer and user agent
+    #[default]
+    Combined,
+}
+
+impl FromStr for AccessLogFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "common" | "clf" => Ok(AccessLogFormat::Common),
+            "combined" => Ok(AccessLogFormat::Combined),
+            _ => Err(format!(
+                "Invalid access log format: {}. Valid options: common, combined",
+                s
+            )),
+        }
+    }
+}
+
+/// Where access log lines are written
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum AccessLogTarget {
+    Stdout,
+    /// Appended to this file, which is created if needed
+    File(PathBuf),
+}
+
+impl FromStr for AccessLogTarget {
+    type Err = String;
+
+    /// Parse `-` or `stdout`, or a file path
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "" => Err("The access log target is empty".to_string()),
+            "-" | "stdout" => Ok(AccessLogTarget::Stdout),
+            path => Ok(AccessLogTarget::File(PathBuf::from(path))),
+        }
+    }
+}
+
+/// Access log settings
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AccessLogConfig {
+    pub target: AccessLogTarget,
+    pub format: AccessLogFormat,
+}
+
+impl AccessLogConfig {
+    /// Combined format to `target`
+    pub fn new(target: AccessLogTarget) -> Self {
+        Self {
+            target,
+            format: AccessLogFormat::default(),
+        }
+    }
+}
+
+struct AccessLog {
+    format: AccessLogFormat,
+    writer: NonBlocking,
+}
+
+/// Start writing the access log; later calls are ignored
+pub fn init(config: &AccessLogConfig) -> io::Result<()> {
+    let writer: Box<dyn Write + Send> = match &config.target {
+        AccessLogTarget::Stdout => Box::new(io::stdout()),
+        AccessLogTarget::File(path) => {
+            if let Some(directory) = path.parent().filter(|p| !p.as_os_str().is_empty()) {
+                std::fs::create_dir_all(directory)?;
+            }
+            Box::new(
+                std::fs::OpenOptions::new()
+                    .create(true)
+                    .append(true)
+                    .open(path)?,
+            )
+        }
+    };
+    let (writer, guard) = NonBlockingBuilder::default()
+        .lossy(false)
+        .thread_name("rossby-vis-access-log")
+        .finish(writer);
+    if ACCESS_LOG
+        .set(AccessLog {
+            format: config.format,
+            writer,
+        })
+        .is_ok()
+    {
+        *GUARD.lock().unwrap() = Some(guard);
+    }
+    Ok(())
+}
+
+/// Whether access log lines are written
+pub fn enabled() -> bool {
+    ACCESS_LOG.get().is_some()
+}
+
+/// Write out the lines still buffered; later lines are dropped
+pub fn flush() {
+    drop(GUARD.lock().unwrap().take());
+}
+
+/// The parts of a request that go into its access log line
+#[derive(Debug, Clone)]
+pub struct AccessLogRequest {
+    remote_addr: Option<String>,
+    user: Option<String>,
+    time: DateTime<Local>,
+    request_line: String,
+    referer: Option<String>,
+    user_agent: Option<String>,
+}
+
+impl AccessLogRequest {
+    /// Capture a request arriving now from `remote_addr`
+    pub fn new(
+        method: &Method,
+        uri: &Uri,
+        version: Version,
+        headers: &HeaderMap,
+        remote_addr: Option<&str>,
+    ) -> Self {
+        let target = uri.path_and_query().map_or("/", |target| target.as_str());
+        let header = |name| {
+            headers
+                .get(name)
+                .and_then(|value| value.to_str().ok())
+                .map(str::to_string)
+        };
+        Self {
+            remote_addr: remote_addr.map(str::to_string),
+            user: basic_auth_user(headers),
+            time: Local::now(),
+            request_line: format!("{} {} {:?}", method, target, version),
+            referer: header(header::REFERER),
+            user_agent: header(header::USER_AGENT),
+        }
+    }
+
+    /// The access log line of the request, without the line break
+    pub fn format(&self, format: AccessLogFormat, status: u16, bytes: u64) -> String {
+        let mut line = format!(
+            "{} - {} [{}] \"{}\" {} {}",
+            field(self.remote_addr.as_deref()),
+            field(self.user.as_deref()),
+            self.time.format("%d/%b/%Y:%H:%M:%S %z"),
+            escape(&self.request_line),
+            status,
+            if bytes == 0 {
+                "-".to_string()
+            } else {
+                bytes.to_string()
+            },
+        );
+        if format == AccessLogFormat::Combined {
+            let _ = write!(
+                line,
+                " \"{}\" \"{}\"",
+                escape(self.referer.as_deref().unwrap_or("-")),
+                escape(self.user_agent.as_deref().unwrap_or("-"))
+            );
+        }
+        line
+    }
+}
+
+/// Write the line of a finished request, if the access log is enabled
+pub fn record(request: &AccessLogRequest, status: u16, bytes: u64) {
+    if let Some(log) = ACCESS_LOG.get() {
+        let mut line = request.format(log.format, status, bytes);
+        line.push('\n');
+        let _ = log.writer.clone().write_all(line.as_bytes());
+    }
+}
+
+/// The user name of HTTP Basic credentials
+fn basic_auth_user(headers: &HeaderMap) -> Option<String> {
+    let credentials = headers
+        .get(header::AUTHORIZATION)?
+        .to_str()
+        .ok()?
+        .strip_prefix("Basic ")?;
+    let decoded = STANDARD.decode(credentials.trim()).ok()?;
+    let decoded = String::from_utf8(decoded).ok()?;
+    let (user, _) = decoded.split_once(':')?;
+    Some(user.to_string()).filter(|user| !user.is_empty())
+}
+
+/// An unquoted field, `-` when missing; spaces would split it
+fn field(value: Option<&str>) -> String {
+    match value {
+        Some(value) if !value.is_empty() => escape(value).replace(' ', "\\x20"),
+        _ => "-".to_string(),
+    }
+}
+
+/// Escape quotes, backslashes and control characters, as Apache does
+fn escape(value: &str) -> String {
+    let mut escaped = String::with_capacity(value.len());
+    for c in value.chars() {
+        match c {
+            '"' => escaped.push_str("\\\""),
+            '\\' => escaped.push_str("\\\\"),
+            c if c.is_control() => {
+                let _ = write!(escaped, "\\x{:02x}", c as u32);
+            }
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::TimeZone;
+
+    fn request(headers: &[(&str, &str)]) -> AccessLogRequest {
+        let mut map = HeaderMap::new();
+        for (name, value) in headers {
+            map.insert(
+                header::HeaderName::from_bytes(name.as_bytes()).unwrap(),
+                value.parse().unwrap(),
+            );
+        }
+        let mut request = AccessLogRequest::new(
+            &Method::GET,
+            &"/proxy/data?vars=t2m&time=0".parse().unwrap(),
+            Version::HTTP_11,
+            &map,
+            Some("203.0.113.7"),
+        );
+        request.time = Local
+            .from_local_datetime(
+                &chrono::NaiveDate::from_ymd_opt(2025, 6, 23)
+                    .unwrap()
+                    .and_hms_opt(14, 2, 11)
+                    .unwrap(),
+            )
+            .unwrap();
+        request
+    }
+
+    #[test]
+    fn test_parse_settings() {
+        assert_eq!("CLF".parse(), Ok(AccessLogFormat::Common));
+        assert_eq!("combined".parse(), Ok(AccessLogFormat::Combined));
+        assert!("json".parse::<AccessLogFormat>().is_err());
+
+        assert_eq!("-".parse(), Ok(AccessLogTarget::Stdout));
+        assert_eq!(
+            "/var/log/access.log".parse(),
+            Ok(AccessLogTarget::File("/var/log/access.log".into()))
+        );
+        assert!("".parse::<AccessLogTarget>().is_err());
+    }
+
+    #[test]
+    fn test_combined_line() {
+        let request = request(&[
+            (
+                "authorization",
+                &format!("Basic {}", STANDARD.encode("ops:secret")),
+            ),
+            ("referer", "https://example.org/"),
+            ("user-agent", "Mozilla/5.0 \"quoted\""),
+        ]);
+        let offset = request.time.format("%z").to_string();
+
+        assert_eq!(
+            request.format(AccessLogFormat::Combined, 200, 5120),
+            format!(
+                "203.0.113.7 - ops [23/Jun/2025:14:02:11 {}] \"GET /proxy/data?vars=t2m&time=0 HTTP/1.1\" 200 5120 \"https://example.org/\" \"Mozilla/5.0 \\\"quoted\\\"\"",
+                offset
+            )
+        );
+    }
+
+    #[test]
+    fn test_common_line_without_details() {
+        let mut request = request(&[("authorization", "Bearer token")]);
+        request.remote_addr = None;
+        let offset = request.time.format("%z").to_string();
+
+        assert_eq!(
+            request.format(AccessLogFormat::Common, 304, 0),
+            format!(
+                "- - - [23/Jun/2025:14:02:11 {}] \"GET /proxy/data?vars=t2m&time=0 HTTP/1.1\" 304 -",
+                offset
+            )
+        );
+    }
+
+    #[test]
+    fn test_escape() {
+        assert_eq!(escape("a\"b\\c\nd"), "a\\\"b\\\\c\\x0ad");
+        assert_eq!(field(Some("two words")), "two\\x20words");
+        assert_eq!(field(Some("")), "-");
+    }
+}
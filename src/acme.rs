@@ -0,0 +1,832 @@
+//! Automatic certificates from an ACME certificate authority
+//!
+//! With `--acme-domain`, the server obtains a certificate for its host names
+//! from Let's Encrypt, or another authority speaking ACME (RFC 8555), and
+//! renews it 30 days before it expires. The account key and the certificate
+//! are kept in `<data-dir>/acme`, so restarts reuse them instead of ordering
+//! again.
+//!
+//! The authority checks control of each name with a challenge. By default
+//! this is `tls-alpn-01` (RFC 8737), answered by the HTTPS listener itself on
+//! port 443. `http-01` is answered at `/.well-known/acme-challenge/<token>`,
+//! which the authority requests over plain HTTP on port 80.
+//!
+//! Built with the `acme` feature; the settings are in [`crate::tls`].
+
+use axum::{
+    extract::{Path as UrlPath, State},
+    http::StatusCode,
+    response::IntoResponse,
+};
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine};
+use rcgen::{CertificateParams, CustomExtension, DistinguishedName};
+use reqwest::{header, Response};
+use ring::{
+    rand::SystemRandom,
+    signature::{
+        EcdsaKeyPair, KeyPair, ECDSA_P256_SHA256_ASN1_SIGNING, ECDSA_P256_SHA256_FIXED_SIGNING,
+    },
+};
+use rustls::{
+    sign::{self, CertifiedKey},
+    Certificate, PrivateKey,
+};
+use serde::Deserialize;
+use serde_json::{json, Value};
+use sha2::{Digest, Sha256};
+use std::{
+    collections::HashMap,
+    fmt, fs, io,
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex, RwLock},
+    time::Duration,
+};
+use tracing::{info, warn};
+
+use crate::{
+    error::AppError,
+    private_file::write_private,
+    server::AppState,
+    tls::{AcmeChallenge, AcmeConfig},
+};
+
+/// Renew certificates expiring within this time
+const RENEW_BEFORE: chrono::Duration = chrono::Duration::days(30);
+
+/// How often the expiry of the certificate is checked
+const CHECK_INTERVAL: Duration = Duration::from_secs(12 * 60 * 60);
+
+/// Wait before trying again after a failed order
+const RETRY_INTERVAL: Duration = Duration::from_secs(60 * 60);
+
+/// Polling of pending authorizations and orders
+const POLL_INTERVAL: Duration = Duration::from_secs(2);
+const POLL_ATTEMPTS: usize = 30;
+
+/// A certificate with the time it expires
+struct Issued {
+    key: Arc<CertifiedKey>,
+    not_after: chrono::DateTime<chrono::Utc>,
+}
+
+/// Obtains and renews the certificate, and answers the challenges
+pub struct Acme {
+    config: AcmeConfig,
+    dir: PathBuf,
+    client: reqwest::Client,
+    issued: RwLock<Option<Issued>>,
+    /// Validation certificates of `tls-alpn-01`, by host name
+    alpn_challenges: Mutex<HashMap<String, Arc<CertifiedKey>>>,
+    /// Key authorizations of `http-01`, by token
+    http_challenges: Mutex<HashMap<String, String>>,
+}
+
+impl fmt::Debug for Acme {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Acme")
+            .field("config", &self.config)
+            .field("dir", &self.dir)
+            .finish_non_exhaustive()
+    }
+}
+
+impl Acme {
+    /// Keep the account and certificate of `config` in `<data_dir>/acme`,
+    /// loading a certificate stored there before
+    pub fn new(mut config: AcmeConfig, data_dir: &Path) -> Result<Self, AppError> {
+        for domain in &mut config.domains {
+            domain.make_ascii_lowercase();
+        }
+        if config.domains.is_empty() {
+            return Err(AppError::ConfigError(
+                "ACME needs at least one domain".to_string(),
+            ));
+        }
+        let dir = data_dir.join("acme");
+        fs::create_dir_all(&dir).map_err(|e| {
+            AppError::StorageError(format!("Failed to create {}: {}", dir.display(), e))
+        })?;
+        let client = reqwest::Client::builder()
+            .user_agent(concat!("rossby-vis/", env!("CARGO_PKG_VERSION")))
+            .timeout(Duration::from_secs(30))
+            .build()
+            .map_err(|e| AppError::ConfigError(format!("Failed to create ACME client: {}", e)))?;
+
+        let acme = Self {
+            config,
+            dir,
+            client,
+            issued: RwLock::new(None),
+            alpn_challenges: Mutex::new(HashMap::new()),
+            http_challenges: Mutex::new(HashMap::new()),
+        };
+        match acme.load_certificate() {
+            Ok(Some(issued)) => {
+                info!(
+                    "Loaded ACME certificate for {}, valid until {}",
+                    acme.config.domains.join(", "),
+                    issued.not_after
+                );
+                *acme.issued.write().unwrap() = Some(issued);
+            }
+            Ok(None) => {}
+            Err(e) => warn!(error = %e, "Ignoring stored ACME certificate"),
+        }
+        Ok(acme)
+    }
+
+    pub fn config(&self) -> &AcmeConfig {
+        &self.config
+    }
+
+    /// The certificate for `name`, once it has been issued
+    pub(crate) fn certificate(&self, name: &str) -> Option<Arc<CertifiedKey>> {
+        if !self.covers(name) {
+            return None;
+        }
+        let issued = self.issued.read().unwrap();
+        issued.as_ref().map(|issued| issued.key.clone())
+    }
+
+    /// The `tls-alpn-01` validation certificate for `name`, while its
+    /// challenge is pending
+    pub(crate) fn challenge_certificate(&self, name: &str) -> Option<Arc<CertifiedKey>> {
+        let challenges = self.alpn_challenges.lock().unwrap();
+        challenges.get(&name.to_ascii_lowercase()).cloned()
+    }
+
+    /// The `http-01` key authorization for `token`, while its challenge is
+    /// pending
+    pub fn key_authorization(&self, token: &str) -> Option<String> {
+        self.http_challenges.lock().unwrap().get(token).cloned()
+    }
+
+    fn covers(&self, name: &str) -> bool {
+        let name = name.to_ascii_lowercase();
+        self.config.domains.contains(&name)
+    }
+
+    /// Keep the certificate current in the background
+    pub fn start(self: &Arc<Self>) {
+        let acme = self.clone();
+        tokio::spawn(async move {
+            loop {
+                let wait = match acme.renew_if_due().await {
+                    Ok(()) => CHECK_INTERVAL,
+                    Err(e) => {
+                        warn!(error = %e, "Failed to obtain ACME certificate");
+                        RETRY_INTERVAL
+                    }
+                };
+                tokio::time::sleep(wait).await;
+            }
+        });
+    }
+
+    /// Order a certificate unless the current one is valid for long enough
+    pub async fn renew_if_due(&self) -> Result<(), AppError> {
+        let due = match &*self.issued.read().unwrap() {
+            Some(issued) => issued.not_after - chrono::Utc::now() < RENEW_BEFORE,
+            None => true,
+        };
+        if !due {
+            return Ok(());
+        }
+
+        info!(
+            "Ordering ACME certificate for {} from {}",
+            self.config.domains.join(", "),
+            self.config.directory_url
+        );
+        let (chain, key) = self.order().await?;
+        let issued = issued(chain.as_bytes(), key.as_bytes())?;
+        // A crash between the two leaves a pair that does not match, which
+        // is ignored when loaded, so the certificate is ordered again
+        write_private(&self.dir.join("key.pem"), key.as_bytes())
+            .and_then(|_| write_private(&self.dir.join("cert.pem"), chain.as_bytes()))
+            .map_err(|e| AppError::StorageError(format!("Failed to store certificate: {}", e)))?;
+        info!(
+            "Obtained ACME certificate, valid until {}",
+            issued.not_after
+        );
+        *self.issued.write().unwrap() = Some(issued);
+        Ok(())
+    }
+
+    fn load_certificate(&self) -> Result<Option<Issued>, AppError> {
+        let read = |name: &str| fs::read(self.dir.join(name));
+        match (read("cert.pem"), read("key.pem")) {
+            (Ok(chain), Ok(key)) => issued(&chain, &key).map(Some),
+            _ => Ok(None),
+        }
+    }
+
+    /// The key of the ACME account, created on first use
+    fn account_key(&self) -> Result<EcdsaKeyPair, AppError> {
+        let path = self.dir.join("account.key");
+        let rng = SystemRandom::new();
+        let pkcs8 = match fs::read(&path) {
+            Ok(pkcs8) => pkcs8,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => {
+                let pkcs8 = EcdsaKeyPair::generate_pkcs8(&ECDSA_P256_SHA256_FIXED_SIGNING, &rng)
+                    .map_err(|_| {
+                        AppError::ConfigError("Failed to generate ACME account key".to_string())
+                    })?;
+                write_private(&path, pkcs8.as_ref()).map_err(|e| {
+                    AppError::StorageError(format!("Failed to store {}: {}", path.display(), e))
+                })?;
+                pkcs8.as_ref().to_vec()
+            }
+            // Replacing a key that exists would lose the account
+            Err(e) => {
+                return Err(AppError::StorageError(format!(
+                    "Failed to read {}: {}",
+                    path.display(),
+                    e
+                )))
+            }
+        };
+        EcdsaKeyPair::from_pkcs8(&ECDSA_P256_SHA256_FIXED_SIGNING, &pkcs8, &rng).map_err(|_| {
+            AppError::ConfigError(format!("Invalid ACME account key {}", path.display()))
+        })
+    }
+
+    /// Order a certificate for the configured domains, returning the PEM
+    /// chain and key
+    async fn order(&self) -> Result<(String, String), AppError> {
+        let directory: Directory = self
+            .client
+            .get(&self.config.directory_url)
+            .send()
+            .await
+            .and_then(Response::error_for_status)
+            .map_err(acme_error)?
+            .json()
+            .await
+            .map_err(acme_error)?;
+        let mut session = Session::new(self.client.clone(), directory, self.account_key()?);
+
+        let contact: Vec<String> = self
+            .config
+            .contact
+            .iter()
+            .map(|email| format!("mailto:{}", email))
+            .collect();
+        session
+            .register(json!({"termsOfServiceAgreed": true, "contact": contact}))
+            .await?;
+
+        let identifiers: Vec<Value> = self
+            .config
+            .domains
+            .iter()
+            .map(|domain| json!({"type": "dns", "value": domain}))
+            .collect();
+        let new_order = session.directory.new_order.clone();
+        let response = session
+            .post(&new_order, Some(json!({ "identifiers": identifiers })))
+            .await?;
+        let order_url = location(&response)?;
+        let order: Order = response.json().await.map_err(acme_error)?;
+
+        for authorization in &order.authorizations {
+            self.authorize(&mut session, authorization).await?;
+        }
+
+        let order = session
+            .poll(&order_url, |order: &Order| order.status != "pending")
+            .await?;
+        if order.status != "ready" {
+            return Err(order_failed(&order));
+        }
+
+        let mut params = CertificateParams::new(self.config.domains.clone());
+        params.distinguished_name = DistinguishedName::new();
+        let certificate = rcgen::Certificate::from_params(params).map_err(acme_error)?;
+        let csr = certificate.serialize_request_der().map_err(acme_error)?;
+        session
+            .post(
+                &order.finalize,
+                Some(json!({ "csr": URL_SAFE_NO_PAD.encode(csr) })),
+            )
+            .await?;
+        let order = session
+            .poll(&order_url, |order: &Order| {
+                order.status != "processing" && order.status != "ready"
+            })
+            .await?;
+        let certificate_url = match (&order.status[..], &order.certificate) {
+            ("valid", Some(url)) => url.clone(),
+            _ => return Err(order_failed(&order)),
+        };
+        let chain = session
+            .post(&certificate_url, None)
+            .await?
+            .text()
+            .await
+            .map_err(acme_error)?;
+        Ok((chain, certificate.serialize_private_key_pem()))
+    }
+
+    /// Answer the challenge of one authorization and wait for its result
+    async fn authorize(&self, session: &mut Session, url: &str) -> Result<(), AppError> {
+        let authorization: Authorization = session
+            .post(url, None)
+            .await?
+            .json()
+            .await
+            .map_err(acme_error)?;
+        if authorization.status == "valid" {
+            return Ok(());
+        }
+        let domain = authorization.identifier.value.to_ascii_lowercase();
+        let challenge_type = self.config.challenge.as_str();
+        let challenge = authorization
+            .challenges
+            .iter()
+            .find(|challenge| challenge.kind == challenge_type)
+            .ok_or_else(|| {
+                AppError::ProxyError(format!(
+                    "ACME authority offers no {} challenge for {}",
+                    challenge_type, domain
+                ))
+            })?;
+        let key_authorization = format!("{}.{}", challenge.token, session.thumbprint);
+
+        match self.config.challenge {
+            AcmeChallenge::TlsAlpn01 => {
+                let certificate = alpn_challenge_certificate(&domain, &key_authorization)?;
+                self.alpn_challenges
+                    .lock()
+                    .unwrap()
+                    .insert(domain.clone(), Arc::new(certificate));
+            }
+            AcmeChallenge::Http01 => {
+                self.http_challenges
+                    .lock()
+                    .unwrap()
+                    .insert(challenge.token.clone(), key_authorization);
+            }
+        }
+
+        let result = async {
+            session.post(&challenge.url, Some(json!({}))).await?;
+            session
+                .poll(url, |authorization: &Authorization| {
+                    authorization.status != "pending"
+                })
+                .await
+        }
+        .await;
+        self.alpn_challenges.lock().unwrap().remove(&domain);
+        self.http_challenges
+            .lock()
+            .unwrap()
+            .remove(&challenge.token);
+
+        let authorization = result?;
+        if authorization.status != "valid" {
+            let detail = authorization
+                .challenges
+                .iter()
+                .find_map(|challenge| challenge.error.as_ref())
+                .map(|error| error.to_string())
+                .unwrap_or_default();
+            return Err(AppError::ProxyError(format!(
+                "ACME authorization of {} is {} {}",
+                domain, authorization.status, detail
+            )));
+        }
+        Ok(())
+    }
+}
+
+/// Answer `http-01` challenges
+pub async fn acme_challenge(
+    State(state): State<Arc<AppState>>,
+    UrlPath(token): UrlPath<String>,
+) -> impl IntoResponse {
+    match state
+        .acme
+        .as_ref()
+        .and_then(|acme| acme.key_authorization(&token))
+    {
+        Some(key_authorization) => (StatusCode::OK, key_authorization).into_response(),
+        None => StatusCode::NOT_FOUND.into_response(),
+    }
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct Directory {
+    new_nonce: String,
+    new_account: String,
+    new_order: String,
+}
+
+#[derive(Debug, Deserialize)]
+struct Order {
+    status: String,
+    #[serde(default)]
+    authorizations: Vec<String>,
+    finalize: String,
+    certificate: Option<String>,
+    error: Option<Value>,
+}
+
+#[derive(Debug, Deserialize)]
+struct Authorization {
+    status: String,
+    identifier: Identifier,
+    challenges: Vec<Challenge>,
+}
+
+#[derive(Debug, Deserialize)]
+struct Identifier {
+    value: String,
+}
+
+#[derive(Debug, Deserialize)]
+struct Challenge {
+    #[serde(rename = "type")]
+    kind: String,
+    url: String,
+    token: String,
+    error: Option<Value>,
+}
+
+/// Requests signed with the account key
+struct Session {
+    client: reqwest::Client,
+    directory: Directory,
+    key: EcdsaKeyPair,
+    jwk: Value,
+    thumbprint: String,
+    kid: Option<String>,
+    nonce: Option<String>,
+}
+
+impl Session {
+    fn new(client: reqwest::Client, directory: Directory, key: EcdsaKeyPair) -> Self {
+        let jwk = jwk(&key);
+        let thumbprint = thumbprint(&jwk);
+        Self {
+            client,
+            directory,
+            key,
+            jwk,
+            thumbprint,
+            kid: None,
+            nonce: None,
+        }
+    }
+
+    /// Create the account, or find the existing one of the key
+    async fn register(&mut self, account: Value) -> Result<(), AppError> {
+        let url = self.directory.new_account.clone();
+        let response = self.post(&url, Some(account)).await?;
+        self.kid = Some(location(&response)?);
+        Ok(())
+    }
+
+    /// POST `payload` as a JWS, or an empty payload to fetch `url`
+    async fn post(&mut self, url: &str, payload: Option<Value>) -> Result<Response, AppError> {
+        let payload = match payload {
+            Some(payload) => URL_SAFE_NO_PAD.encode(payload.to_string()),
+            None => String::new(),
+        };
+        // A rejected nonce is retried once with the fresh one returned
+        for attempt in 0..2 {
+            let nonce = self.nonce().await?;
+            let body = self.sign(url, &nonce, &payload)?;
+            let response = self
+                .client
+                .post(url)
+                .header(header::CONTENT_TYPE, "application/jose+json")
+                .body(body.to_string())
+                .send()
+                .await
+                .map_err(acme_error)?;
+            self.nonce = response
+                .headers()
+                .get("replay-nonce")
+                .and_then(|nonce| nonce.to_str().ok())
+                .map(str::to_string);
+            if response.status().is_success() {
+                return Ok(response);
+            }
+            let status = response.status();
+            let problem: Value = response.json().await.unwrap_or_default();
+            if attempt == 0 && problem["type"] == "urn:ietf:params:acme:error:badNonce" {
+                continue;
+            }
+            return Err(AppError::ProxyError(format!(
+                "ACME request to {} failed with {}: {}",
+                url, status, problem
+            )));
+        }
+        unreachable!("the second attempt returns")
+    }
+
+    /// Fetch `url` until `done` holds for the resource
+    async fn poll<T, F>(&mut self, url: &str, done: F) -> Result<T, AppError>
+    where
+        T: serde::de::DeserializeOwned,
+        F: Fn(&T) -> bool,
+    {
+        for _ in 0..POLL_ATTEMPTS {
+            let resource: T = self
+                .post(url, None)
+                .await?
+                .json()
+                .await
+                .map_err(acme_error)?;
+            if done(&resource) {
+                return Ok(resource);
+            }
+            tokio::time::sleep(POLL_INTERVAL).await;
+        }
+        Err(AppError::ProxyError(format!(
+            "ACME resource {} is still pending",
+            url
+        )))
+    }
+
+    async fn nonce(&mut self) -> Result<String, AppError> {
+        if let Some(nonce) = self.nonce.take() {
+            return Ok(nonce);
+        }
+        let response = self
+            .client
+            .head(&self.directory.new_nonce)
+            .send()
+            .await
+            .map_err(acme_error)?;
+        response
+            .headers()
+            .get("replay-nonce")
+            .and_then(|nonce| nonce.to_str().ok())
+            .map(str::to_string)
+            .ok_or_else(|| AppError::ProxyError("ACME authority sent no nonce".to_string()))
+    }
+
+    /// The flattened JWS of `payload` for `url`
+    fn sign(&self, url: &str, nonce: &str, payload: &str) -> Result<Value, AppError> {
+        let mut protected = json!({"alg": "ES256", "nonce": nonce, "url": url});
+        match &self.kid {
+            Some(kid) => protected["kid"] = json!(kid),
+            None => protected["jwk"] = self.jwk.clone(),
+        }
+        let protected = URL_SAFE_NO_PAD.encode(protected.to_string());
+        let signature = self
+            .key
+            .sign(
+                &SystemRandom::new(),
+                format!("{}.{}", protected, payload).as_bytes(),
+            )
+            .map_err(|_| AppError::ProxyError("Failed to sign ACME request".to_string()))?;
+        Ok(json!({
+            "protected": protected,
+            "payload": payload,
+            "signature": URL_SAFE_NO_PAD.encode(signature.as_ref()),
+        }))
+    }
+}
+
+/// The public JWK of a P-256 account key
+fn jwk(key: &EcdsaKeyPair) -> Value {
+    // Uncompressed point: 0x04, then the x and y coordinates
+    let point = key.public_key().as_ref();
+    json!({
+        "crv": "P-256",
+        "kty": "EC",
+        "x": URL_SAFE_NO_PAD.encode(&point[1..33]),
+        "y": URL_SAFE_NO_PAD.encode(&point[33..65]),
+    })
+}
+
+/// The RFC 7638 thumbprint of `jwk`, hashing its members in lexical order
+fn thumbprint(jwk: &Value) -> String {
+    let canonical = format!(
+        r#"{{"crv":"{}","kty":"{}","x":"{}","y":"{}"}}"#,
+        jwk["crv"].as_str().unwrap_or_default(),
+        jwk["kty"].as_str().unwrap_or_default(),
+        jwk["x"].as_str().unwrap_or_default(),
+        jwk["y"].as_str().unwrap_or_default(),
+    );
+    URL_SAFE_NO_PAD.encode(Sha256::digest(canonical.as_bytes()))
+}
+
+/// The self-signed certificate proving control of `domain` to a
+/// `tls-alpn-01` validation handshake
+fn alpn_challenge_certificate(
+    domain: &str,
+    key_authorization: &str,
+) -> Result<CertifiedKey, AppError> {
+    let mut params = CertificateParams::new(vec![domain.to_string()]);
+    params
+        .custom_extensions
+        .push(CustomExtension::new_acme_identifier(&Sha256::digest(
+            key_authorization.as_bytes(),
+        )));
+    let certificate = rcgen::Certificate::from_params(params).map_err(acme_error)?;
+    let der = certificate.serialize_der().map_err(acme_error)?;
+    let key = sign::any_supported_type(&PrivateKey(certificate.serialize_private_key_der()))
+        .map_err(acme_error)?;
+    Ok(CertifiedKey::new(vec![Certificate(der)], key))
+}
+
+/// Load an issued PEM chain and key
+fn issued(chain: &[u8], key: &[u8]) -> Result<Issued, AppError> {
+    let invalid = |what: &str| AppError::StorageError(format!("Invalid ACME {}", what));
+    let chain: Vec<Certificate> = rustls_pemfile::certs(&mut &chain[..])
+        .map_err(|_| invalid("certificate"))?
+        .into_iter()
+        .map(Certificate)
+        .collect();
+    let leaf = chain.first().ok_or_else(|| invalid("certificate"))?;
+    let (_, parsed) =
+        x509_parser::parse_x509_certificate(&leaf.0).map_err(|_| invalid("certificate"))?;
+    let not_after = chrono::DateTime::from_timestamp(parsed.validity().not_after.timestamp(), 0)
+        .ok_or_else(|| invalid("certificate"))?;
+    let key = rustls_pemfile::pkcs8_private_keys(&mut &key[..])
+        .ok()
+        .and_then(|keys| keys.into_iter().next())
+        .ok_or_else(|| invalid("key"))?;
+    let public_key =
+        EcdsaKeyPair::from_pkcs8(&ECDSA_P256_SHA256_ASN1_SIGNING, &key, &SystemRandom::new())
+            .map_err(|_| invalid("key"))?;
+    if public_key.public_key().as_ref() != parsed.public_key().subject_public_key.data.as_ref() {
+        return Err(invalid("key, which does not match the certificate"));
+    }
+    let key = sign::any_supported_type(&PrivateKey(key)).map_err(|_| invalid("key"))?;
+    Ok(Issued {
+        key: Arc::new(CertifiedKey::new(chain, key)),
+        not_after,
+    })
+}
+
+fn location(response: &Response) -> Result<String, AppError> {
+    response
+        .headers()
+        .get(header::LOCATION)
+        .and_then(|location| location.to_str().ok())
+        .map(str::to_string)
+        .ok_or_else(|| AppError::ProxyError("ACME response without Location".to_string()))
+}
+
+fn order_failed(order: &Order) -> AppError {
+    AppError::ProxyError(format!(
+        "ACME order is {} {}",
+        order.status,
+        order.error.clone().unwrap_or_default()
+    ))
+}
+
+fn acme_error(e: impl fmt::Display) -> AppError {
+    AppError::ProxyError(format!("ACME: {}", e))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ring::signature::{UnparsedPublicKey, ECDSA_P256_SHA256_FIXED};
+
+    fn session() -> Session {
+        let rng = SystemRandom::new();
+        let pkcs8 = EcdsaKeyPair::generate_pkcs8(&ECDSA_P256_SHA256_FIXED_SIGNING, &rng).unwrap();
+        let key = EcdsaKeyPair::from_pkcs8(&ECDSA_P256_SHA256_FIXED_SIGNING, pkcs8.as_ref(), &rng)
+            .unwrap();
+        let directory = Directory {
+            new_nonce: "https://ca.test/nonce".to_string(),
+            new_account: "https://ca.test/account".to_string(),
+            new_order: "https://ca.test/order".to_string(),
+        };
+        Session::new(reqwest::Client::new(), directory, key)
+    }
+
+    #[test]
+    fn test_signed_request_verifies() {
+        let mut session = session();
+        let payload = URL_SAFE_NO_PAD.encode(r#"{"identifiers":[]}"#);
+
+        let jws = session
+            .sign("https://ca.test/order", "n0nce", &payload)
+            .unwrap();
+        let protected: Value = serde_json::from_slice(
+            &URL_SAFE_NO_PAD
+                .decode(jws["protected"].as_str().unwrap())
+                .unwrap(),
+        )
+        .unwrap();
+        assert_eq!(protected["alg"], "ES256");
+        assert_eq!(protected["nonce"], "n0nce");
+        assert_eq!(protected["jwk"], session.jwk);
+
+        let signed = format!("{}.{}", jws["protected"].as_str().unwrap(), payload);
+        let signature = URL_SAFE_NO_PAD
+            .decode(jws["signature"].as_str().unwrap())
+            .unwrap();
+        UnparsedPublicKey::new(&ECDSA_P256_SHA256_FIXED, session.key.public_key().as_ref())
+            .verify(signed.as_bytes(), &signature)
+            .unwrap();
+
+        // Once registered, the account is referred to by its URL
+        session.kid = Some("https://ca.test/account/1".to_string());
+        let jws = session.sign("https://ca.test/order", "n0nce", "").unwrap();
+        let protected = URL_SAFE_NO_PAD
+            .decode(jws["protected"].as_str().unwrap())
+            .unwrap();
+        let protected: Value = serde_json::from_slice(&protected).unwrap();
+        assert_eq!(protected["kid"], "https://ca.test/account/1");
+        assert!(protected.get("jwk").is_none());
+    }
+
+    #[test]
+    fn test_thumbprint_is_canonical() {
+        // RFC 7638 members in lexical order, without whitespace
+        let jwk = json!({"y": "yy", "x": "xx", "kty": "EC", "crv": "P-256"});
+        let expected = URL_SAFE_NO_PAD.encode(Sha256::digest(
+            br#"{"crv":"P-256","kty":"EC","x":"xx","y":"yy"}"#,
+        ));
+        assert_eq!(thumbprint(&jwk), expected);
+    }
+
+    fn acme(data_dir: &Path) -> Acme {
+        Acme::new(
+            AcmeConfig::new(vec!["weather.example.org".to_string()]),
+            data_dir,
+        )
+        .unwrap()
+    }
+
+    fn temp_dir() -> PathBuf {
+        std::env::temp_dir().join(format!("rossby-vis-acme-{}", uuid::Uuid::new_v4()))
+    }
+
+    /// A self-signed PEM certificate and its key
+    fn self_signed() -> (String, String) {
+        let certificate = rcgen::Certificate::from_params(CertificateParams::new(vec![
+            "weather.example.org".to_string(),
+        ]))
+        .unwrap();
+        (
+            certificate.serialize_pem().unwrap(),
+            certificate.serialize_private_key_pem(),
+        )
+    }
+
+    #[test]
+    fn test_account_key_is_created_once() {
+        let dir = temp_dir();
+        let acme = acme(&dir);
+
+        let key = acme.account_key().unwrap();
+        assert_eq!(
+            acme.account_key().unwrap().public_key().as_ref(),
+            key.public_key().as_ref()
+        );
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            let metadata = fs::metadata(dir.join("acme/account.key")).unwrap();
+            assert_eq!(metadata.permissions().mode() & 0o777, 0o600);
+        }
+
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn test_unreadable_account_key_is_not_replaced() {
+        let dir = temp_dir();
+        let acme = acme(&dir);
+        // Reading a directory fails with an error other than NotFound
+        let path = dir.join("acme/account.key");
+        fs::create_dir(&path).unwrap();
+
+        assert!(matches!(acme.account_key(), Err(AppError::StorageError(_))));
+        assert!(path.is_dir());
+
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn test_certificate_and_key_must_match() {
+        let (chain, key) = self_signed();
+        let (_, other_key) = self_signed();
+
+        assert!(issued(chain.as_bytes(), key.as_bytes()).is_ok());
+        assert!(issued(chain.as_bytes(), other_key.as_bytes()).is_err());
+
+        // A mismatched pair left on disk is not loaded
+        let dir = temp_dir();
+        fs::create_dir_all(dir.join("acme")).unwrap();
+        fs::write(dir.join("acme/cert.pem"), &chain).unwrap();
+        fs::write(dir.join("acme/key.pem"), &other_key).unwrap();
+        assert!(acme(&dir).certificate("weather.example.org").is_none());
+        fs::write(dir.join("acme/key.pem"), &key).unwrap();
+        assert!(acme(&dir).certificate("weather.example.org").is_some());
+
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
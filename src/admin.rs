@@ -0,0 +1,518 @@
+//! Operator endpoints under `/admin`
+//!
+//! These change the running server rather than serve data, so they are only
+//! reachable with the bearer token configured through `--admin-token`.
+
+#[cfg(feature = "sqlite")]
+use axum::{extract::Path, http::StatusCode};
+use axum::{
+    extract::{Query, State},
+    http::{header, Request},
+    middleware::Next,
+    response::{Html, IntoResponse, Json, Response},
+};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use std::{
+    collections::VecDeque,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+use utoipa::{IntoParams, ToSchema};
+
+#[cfg(feature = "sqlite")]
+use crate::{auth::IssuedApiKey, pipeline::Middleware};
+use crate::{
+    cache::{CacheFilter, CacheStats, PurgeCounts},
+    embed::{asset_listing, AssetInfo},
+    error::{AppError, ErrorBody},
+    freshness::data_version,
+    grid::coordinate_values,
+    handlers::fetch_metadata,
+    logging::status_summary,
+    server::AppState,
+    shedding::current_pressure,
+    timesteps::to_iso,
+};
+
+/// The operator page served at `/admin`
+const ADMIN_PAGE: &str = include_str!("admin.html");
+
+/// Server errors kept for `GET /admin/overview`
+const RECENT_ERRORS: usize = 50;
+
+/// Body of `PUT /admin/loglevel`
+#[derive(Debug, Deserialize, ToSchema)]
+pub struct LogLevelRequest {
+    /// Filter directives, e.g. `debug` or `info,rossby_vis=debug`
+    pub level: String,
+    /// Restore the previous level after this many seconds
+    pub duration_secs: Option<u64>,
+}
+
+/// Log level state returned by the log level endpoints
+#[derive(Debug, Serialize, ToSchema)]
+pub struct LogLevelResponse {
+    pub level: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub previous: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub revert_after_secs: Option<u64>,
+}
+
+/// Query of `GET /admin/assets`
+#[derive(Debug, Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct AssetQuery {
+    /// Only list files under this path, e.g. `/libs/earth`
+    pub prefix: Option<String>,
+}
+
+/// Embedded files returned by `GET /admin/assets`
+#[derive(Debug, Serialize, ToSchema)]
+pub struct AssetListing {
+    pub count: usize,
+    pub total_bytes: usize,
+    /// Directory the frontend is served from instead, in `--dev` mode
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub dev_dir: Option<String>,
+    pub assets: Vec<AssetInfo>,
+}
+
+/// Result of `DELETE /admin/cache`
+#[derive(Debug, Serialize, ToSchema)]
+pub struct CachePurgeResponse {
+    /// Entries dropped from each tier
+    pub purged: PurgeCounts,
+    /// The cache after the purge
+    pub cache: CacheStats,
+}
+
+/// A failed request, as listed by `GET /admin/overview`
+#[derive(Debug, Clone, Serialize)]
+pub struct ErrorRecord {
+    pub at: DateTime<Utc>,
+    pub method: String,
+    pub path: String,
+    pub status: u16,
+    pub message: String,
+}
+
+/// The most recent server errors, newest first
+#[derive(Debug, Default)]
+pub struct RecentErrors {
+    errors: Mutex<VecDeque<ErrorRecord>>,
+}
+
+impl RecentErrors {
+    /// Remember an error, forgetting the oldest beyond the limit
+    pub fn record(&self, error: ErrorRecord) {
+        let mut errors = self.errors.lock().unwrap_or_else(|e| e.into_inner());
+        errors.push_front(error);
+        errors.truncate(RECENT_ERRORS);
+    }
+
+    /// Remembered errors, newest first
+    pub fn list(&self) -> Vec<ErrorRecord> {
+        let errors = self.errors.lock().unwrap_or_else(|e| e.into_inner());
+        errors.iter().cloned().collect()
+    }
+}
+
+/// Reject admin requests without the configured bearer token
+pub async fn admin_auth_middleware<B>(
+    State(state): State<Arc<AppState>>,
+    request: Request<B>,
+    next: Next<B>,
+) -> Response {
+    let Some(expected) = &state.admin_token else {
+        return AppError::Unauthorized(
+            "the admin API is disabled; start the server with --admin-token".to_string(),
+        )
+        .into_response();
+    };
+
+    let provided = request
+        .headers()
+        .get(header::AUTHORIZATION)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.strip_prefix("Bearer "));
+    if !provided.is_some_and(|token| constant_time_eq(token.as_bytes(), expected.as_bytes())) {
+        tracing::warn!(path = %request.uri().path(), "Rejected admin request");
+        let mut response =
+            AppError::Unauthorized("missing or invalid admin token".to_string()).into_response();
+        response.headers_mut().insert(
+            header::WWW_AUTHENTICATE,
+            header::HeaderValue::from_static("Bearer"),
+        );
+        return response;
+    }
+
+    next.run(request).await
+}
+
+/// Handler for `GET /admin/loglevel`
+#[utoipa::path(
+    get,
+    path = "/admin/loglevel",
+    tag = "admin",
+    summary = "The log level",
+    responses(
+        (status = 200, body = LogLevelResponse),
+        (status = 500, description = "Runtime log level changes are not available", body = ErrorBody),
+        (status = 401, description = "Missing or invalid admin token", body = ErrorBody),
+    ),
+    security(("admin_token" = []))
+)]
+pub async fn get_log_level(
+    State(state): State<Arc<AppState>>,
+) -> Result<Json<LogLevelResponse>, AppError> {
+    let handle = log_level_handle(&state)?;
+    Ok(Json(LogLevelResponse {
+        level: handle.current(),
+        previous: None,
+        revert_after_secs: None,
+    }))
+}
+
+/// Handler for `PUT /admin/loglevel`
+#[utoipa::path(
+    put,
+    path = "/admin/loglevel",
+    tag = "admin",
+    summary = "Change the log level, for good or for a while",
+    request_body = LogLevelRequest,
+    responses(
+        (status = 200, body = LogLevelResponse),
+        (status = 400, description = "Invalid filter directives", body = ErrorBody),
+        (status = 500, description = "Runtime log level changes are not available", body = ErrorBody),
+        (status = 401, description = "Missing or invalid admin token", body = ErrorBody),
+    ),
+    security(("admin_token" = []))
+)]
+pub async fn set_log_level(
+    State(state): State<Arc<AppState>>,
+    Json(request): Json<LogLevelRequest>,
+) -> Result<Json<LogLevelResponse>, AppError> {
+    let handle = log_level_handle(&state)?;
+    let revert_after_secs = request.duration_secs.filter(|secs| *secs > 0);
+
+    let previous = match revert_after_secs {
+        Some(secs) => handle.set_temporarily(&request.level, Duration::from_secs(secs)),
+        None => handle.set(&request.level),
+    }
+    .map_err(AppError::RequestError)?;
+
+    Ok(Json(LogLevelResponse {
+        level: handle.current(),
+        previous: Some(previous),
+        revert_after_secs,
+    }))
+}
+
+/// Handler for `GET /admin/assets`
+#[utoipa::path(
+    get,
+    path = "/admin/assets",
+    tag = "admin",
+    summary = "Embedded frontend files",
+    params(AssetQuery),
+    responses(
+        (status = 200, body = AssetListing),
+        (status = 401, description = "Missing or invalid admin token", body = ErrorBody),
+    ),
+    security(("admin_token" = []))
+)]
+pub async fn list_assets(
+    State(state): State<Arc<AppState>>,
+    Query(query): Query<AssetQuery>,
+) -> Json<AssetListing> {
+    let assets = asset_listing(query.prefix.as_deref().unwrap_or_default());
+    Json(AssetListing {
+        count: assets.len(),
+        total_bytes: assets.iter().map(|asset| asset.size).sum(),
+        dev_dir: state
+            .dev_assets
+            .as_ref()
+            .map(|dev| dev.root().display().to_string()),
+        assets,
+    })
+}
+
+/// Handler for `GET /admin/cache`
+#[utoipa::path(
+    get,
+    path = "/admin/cache",
+    tag = "admin",
+    summary = "Size and use of the response cache",
+    responses(
+        (status = 200, body = CacheStats),
+        (status = 401, description = "Missing or invalid admin token", body = ErrorBody),
+    ),
+    security(("admin_token" = []))
+)]
+pub async fn cache_stats(State(state): State<Arc<AppState>>) -> Json<CacheStats> {
+    Json(state.response_cache.stats())
+}
+
+/// Handler for `DELETE /admin/cache`: drop the responses matching `pattern`
+/// and `variable`, or every response without either
+#[utoipa::path(
+    delete,
+    path = "/admin/cache",
+    tag = "admin",
+    summary = "Drop cached responses",
+    description = "Drops the responses matching every given criterion, or all of them without any.",
+    params(CacheFilter),
+    responses(
+        (status = 200, body = CachePurgeResponse),
+        (status = 401, description = "Missing or invalid admin token", body = ErrorBody),
+    ),
+    security(("admin_token" = []))
+)]
+pub async fn purge_cache(
+    State(state): State<Arc<AppState>>,
+    Query(filter): Query<CacheFilter>,
+) -> Json<CachePurgeResponse> {
+    let purged = state.response_cache.purge(&filter).await;
+    tracing::info!(
+        pattern = filter.pattern.as_deref(),
+        variable = filter.variable.as_deref(),
+        memory = purged.memory,
+        disk = purged.disk,
+        "Purged the response cache"
+    );
+    Json(CachePurgeResponse {
+        purged,
+        cache: state.response_cache.stats(),
+    })
+}
+
+/// Handler for `GET /admin/api-keys`: the keys issued through this API
+#[cfg(feature = "sqlite")]
+#[utoipa::path(
+    get,
+    path = "/admin/api-keys",
+    tag = "admin",
+    summary = "Issued API keys",
+    description = "Lists the labels of the keys issued through this API, not the keys. Needs `--database`.",
+    responses(
+        (status = 200, body = Vec<IssuedApiKey>),
+        (status = 401, description = "Missing or invalid admin token", body = ErrorBody),
+        (status = 404, description = "The server runs without a database", body = ErrorBody),
+    ),
+    security(("admin_token" = []))
+)]
+pub async fn list_api_keys(
+    State(state): State<Arc<AppState>>,
+) -> Result<Json<Vec<IssuedApiKey>>, AppError> {
+    Ok(Json(state.auth.issued_api_keys().await?))
+}
+
+/// Handler for `PUT /admin/api-keys/{label}`: issue a key, replacing the
+/// one the label had
+#[cfg(feature = "sqlite")]
+#[utoipa::path(
+    put,
+    path = "/admin/api-keys/{label}",
+    tag = "admin",
+    summary = "Issue an API key",
+    description = "Returns the new key, which is not shown again. A key the label had stops working.",
+    params(("label" = String, Path, description = "Who the key is for")),
+    responses(
+        (status = 200, body = IssuedApiKey),
+        (status = 400, description = "The label is invalid or configured on the command line", body = ErrorBody),
+        (status = 401, description = "Missing or invalid admin token", body = ErrorBody),
+        (status = 404, description = "The server runs without a database", body = ErrorBody),
+    ),
+    security(("admin_token" = []))
+)]
+pub async fn issue_api_key(
+    State(state): State<Arc<AppState>>,
+    Path(label): Path<String>,
+) -> Result<Json<IssuedApiKey>, AppError> {
+    // Without its layer, keys would be issued but never checked
+    if !state.middleware.contains(&Middleware::Auth) {
+        return Err(AppError::ConfigError(
+            "API keys need the auth middleware in the pipeline".to_string(),
+        ));
+    }
+    let issued = state.auth.issue_api_key(&label).await?;
+    tracing::info!(label = %label, "Issued an API key");
+    Ok(Json(issued))
+}
+
+/// Handler for `DELETE /admin/api-keys/{label}`: revoke an issued key
+#[cfg(feature = "sqlite")]
+#[utoipa::path(
+    delete,
+    path = "/admin/api-keys/{label}",
+    tag = "admin",
+    summary = "Revoke an API key",
+    params(("label" = String, Path, description = "Who the key is for")),
+    responses(
+        (status = 204, description = "The key no longer works"),
+        (status = 401, description = "Missing or invalid admin token", body = ErrorBody),
+        (status = 404, description = "No key was issued for the label, or the server runs without a database", body = ErrorBody),
+    ),
+    security(("admin_token" = []))
+)]
+pub async fn revoke_api_key(
+    State(state): State<Arc<AppState>>,
+    Path(label): Path<String>,
+) -> Result<StatusCode, AppError> {
+    if !state.auth.revoke_api_key(&label).await? {
+        return Err(AppError::NotFound(format!(
+            "No API key was issued for '{}'",
+            label
+        )));
+    }
+    tracing::info!(label = %label, "Revoked an API key");
+    Ok(StatusCode::NO_CONTENT)
+}
+
+/// Handler for `GET /admin`
+///
+/// The page holds no data and is served without the token; it asks the
+/// operator for the token and sends it with its requests to the guarded
+/// endpoints.
+pub async fn admin_page() -> Response {
+    (
+        [
+            (header::CACHE_CONTROL, "no-store"),
+            (header::CONTENT_TYPE, "text/html; charset=utf-8"),
+        ],
+        Html(ADMIN_PAGE),
+    )
+        .into_response()
+}
+
+/// Handler for `GET /admin/overview`: backend health, activity, jobs, cache,
+/// load, scheduled tasks, recent errors and the active configuration
+#[utoipa::path(
+    get,
+    path = "/admin/overview",
+    tag = "admin",
+    summary = "Backend health, activity, jobs, cache, load, schedule, recent errors and configuration",
+    responses(
+        (status = 200, body = serde_json::Value),
+        (status = 401, description = "Missing or invalid admin token", body = ErrorBody),
+    ),
+    security(("admin_token" = []))
+)]
+pub async fn overview(State(state): State<Arc<AppState>>) -> Json<Value> {
+    Json(json!({
+        "service": "rossby-vis",
+        "version": env!("CARGO_PKG_VERSION"),
+        "activity": status_summary(),
+        "backend": probe_backend(&state).await,
+        "jobs": state.jobs.stats(),
+        "cache": state.response_cache.stats(),
+        "load": {
+            "pressure": current_pressure(),
+            "max_memory_percent": state.load_shedder.config().max_memory_percent,
+            "max_cpu_percent": state.load_shedder.config().max_cpu_percent,
+            "shed_requests": state.load_shedder.shed_count(),
+            "max_concurrent_requests": state.load_shedder.config().max_concurrent_requests,
+            "requests_in_flight": state.load_shedder.in_flight(),
+            "refused_requests": state.load_shedder.refused_count(),
+            "backend_concurrency": state.backend_limiter.stats(),
+            "circuit_breaker": state.circuit_breaker.stats(),
+        },
+        "schedule": state.scheduler.reports(),
+        "recent_errors": state.recent_errors.list(),
+        "log_level": state.log_level.as_ref().map(|handle| handle.current()),
+        "config": active_config(&state),
+    }))
+}
+
+/// Fetch the metadata now, reporting how the backend answered
+async fn probe_backend(state: &AppState) -> Value {
+    let start = Instant::now();
+    let result = fetch_metadata(state).await;
+    let latency_ms = start.elapsed().as_millis() as u64;
+    match result {
+        Ok(metadata) => {
+            let times = coordinate_values(&metadata, "time").unwrap_or_default();
+            json!({
+                "url": state.api_url,
+                "reachable": true,
+                "latency_ms": latency_ms,
+                "schema": state.backend.version().map(|v| v.to_string()),
+                "data_version": data_version(&metadata),
+                "variables": metadata
+                    .get("variables")
+                    .and_then(Value::as_object)
+                    .map_or(0, |variables| variables.len()),
+                "timesteps": times.len(),
+                "latest_time": times.iter().copied().reduce(f64::max).map(to_iso),
+            })
+        }
+        Err(e) => json!({
+            "url": state.api_url,
+            "reachable": false,
+            "latency_ms": latency_ms,
+            "error": e.to_string(),
+        }),
+    }
+}
+
+/// Settings the server runs with, without secrets
+fn active_config(state: &AppState) -> Value {
+    json!({
+        "api_url": state.api_url,
+        "backend_credentials": state.backend_credentials.is_some(),
+        "strict_query": state.strict_query,
+        "site_title": state.site.title,
+        "dev_dir": state
+            .dev_assets
+            .as_ref()
+            .map(|dev| dev.root().display().to_string()),
+        "cross_origin_isolation": state.cross_origin_isolation,
+        "mobile_index": state.mobile_index,
+        "land_sea_mask": {
+            "variable": state.land_sea_mask.variable,
+            "auxiliary_file": state.land_sea_mask.values.is_some(),
+            "auto_mask": state.land_sea_mask.auto_mask,
+        },
+        "earth_grid_limit": {
+            "max_points": state.earth_grid_limit.max_points,
+            "downsample": state.earth_grid_limit.downsample,
+        },
+        "derived_variables": state
+            .derived_variables
+            .iter()
+            .map(|variable| variable.name())
+            .collect::<Vec<_>>(),
+        "transforms": !state.transforms.is_empty(),
+        "cache_max_age": {
+            "historical": state.cache_policy.historical_max_age,
+            "recent": state.cache_policy.recent_max_age,
+        },
+        "webhooks": state.webhooks.len(),
+    })
+}
+
+fn log_level_handle(state: &AppState) -> Result<&crate::logging::LogLevelHandle, AppError> {
+    state.log_level.as_ref().ok_or_else(|| {
+        AppError::ConfigError("runtime log level changes are not available".to_string())
+    })
+}
+
+/// Compare secrets without exiting early on the first differing byte
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0u8, |acc, (x, y)| acc | (x ^ y)) == 0
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_constant_time_eq() {
+        assert!(constant_time_eq(b"secret", b"secret"));
+        assert!(!constant_time_eq(b"secret", b"secreT"));
+        assert!(!constant_time_eq(b"secret", b"secret2"));
+    }
+}
@@ -0,0 +1,944 @@
+//! Server-side analysis endpoints
+//!
+//! These handlers fetch gridded fields from the Rossby backend and compute
+//! derived views that would be expensive or awkward to produce in the
+//! browser, such as vertical cross-sections along a path, particle
+//! trajectories through the wind field, or values at station locations.
+
+use axum::{
+    body::Bytes,
+    extract::{Query, State},
+    http::{header, HeaderMap},
+    Json,
+};
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use std::{collections::HashMap, sync::Arc, time::Instant};
+use tracing::{info, instrument};
+use utoipa::{IntoParams, ToSchema};
+
+use crate::{
+    error::{AppError, ErrorBody},
+    geo::{sample_polyline, GeoPoint},
+    grid::{coordinate_values, is_vertical_dimension, variable_dimensions, DataArray, LatLonGrid},
+    handlers::{fetch_data, fetch_metadata, find_wind_components},
+    server::AppState,
+    trajectory::{integrate_rk4, VelocityField, VelocityFrame},
+};
+
+/// Default number of samples along a cross-section path
+const DEFAULT_CROSS_SECTION_SAMPLES: usize = 100;
+/// Upper bound on samples along a cross-section path
+const MAX_CROSS_SECTION_SAMPLES: usize = 2000;
+
+/// Default trajectory integration step in minutes
+const DEFAULT_TRAJECTORY_STEP_MINUTES: f64 = 30.0;
+/// Upper bound on trajectory seed points per request
+const MAX_TRAJECTORY_SEEDS: usize = 500;
+/// Upper bound on integration steps per trajectory
+const MAX_TRAJECTORY_STEPS: usize = 5000;
+/// Upper bound on backend timesteps fetched for one trajectory request
+const MAX_TRAJECTORY_FRAMES: usize = 72;
+/// Upper bound on points per sampling request
+const MAX_SAMPLE_POINTS: usize = 10_000;
+/// Upper bound on variables per sampling request
+const MAX_SAMPLE_VARIABLES: usize = 16;
+/// Upper bound on timesteps per sampling request
+const MAX_SAMPLE_TIMES: usize = 48;
+
+/// Request body for `/api/cross-section`
+#[derive(Debug, Deserialize, ToSchema)]
+pub struct CrossSectionRequest {
+    /// Variable with a vertical dimension
+    pub variable: String,
+    /// Polyline vertices the section follows
+    pub path: Vec<GeoPoint>,
+    /// Backend time value; defaults to the first available timestep
+    pub time: Option<f64>,
+    /// Number of evenly spaced samples along the path
+    pub samples: Option<usize>,
+}
+
+/// Response body for `/api/cross-section`
+#[derive(Debug, Serialize, ToSchema)]
+pub struct CrossSectionResponse {
+    /// Variable name
+    pub variable: String,
+    /// Variable units from the metadata
+    pub units: String,
+    /// Backend time value the section was computed for
+    pub time: Option<f64>,
+    /// Name of the vertical dimension
+    pub level_dimension: String,
+    /// Vertical coordinate values (or indices when no coordinate exists)
+    pub levels: Vec<f64>,
+    /// Distance of each sample from the start of the path in kilometres
+    pub distances_km: Vec<f64>,
+    /// Location of each sample
+    pub points: Vec<GeoPoint>,
+    /// Interpolated values indexed as `values[level][sample]`; `null` where unavailable
+    pub values: Vec<Vec<Option<f64>>>,
+}
+
+/// Handler for `POST /api/cross-section`
+///
+/// Interpolates a variable with a vertical dimension along a polyline,
+/// returning a distance × level matrix.
+#[utoipa::path(
+    post,
+    path = "/api/cross-section",
+    tag = "analysis",
+    summary = "Vertical section of a variable along a path",
+    request_body = CrossSectionRequest,
+    responses(
+        (status = 200, body = CrossSectionResponse),
+        (status = 400, description = "Invalid request or variable", body = ErrorBody),
+        (status = 502, description = "The backend failed", body = ErrorBody),
+    )
+)]
+#[instrument(skip(state, request), fields(variable = %request.variable))]
+pub async fn cross_section(
+    State(state): State<Arc<AppState>>,
+    Json(request): Json<CrossSectionRequest>,
+) -> Result<Json<CrossSectionResponse>, AppError> {
+    let start_time = Instant::now();
+    let samples = validate_cross_section_request(&request)?;
+
+    let metadata = fetch_metadata(&state).await?;
+    let dimensions = variable_dimensions(&metadata, &request.variable).ok_or_else(|| {
+        AppError::RequestError(format!(
+            "Variable '{}' not found in metadata",
+            request.variable
+        ))
+    })?;
+    if !dimensions.iter().any(|d| is_vertical_dimension(d)) {
+        return Err(AppError::RequestError(format!(
+            "Variable '{}' has no vertical dimension",
+            request.variable
+        )));
+    }
+
+    let grid = LatLonGrid::from_metadata(&metadata)?;
+    let time = request.time.or_else(|| first_time(&metadata));
+    let data = fetch_data(&state, &data_query(&[&request.variable], time)).await?;
+    let array = DataArray::from_rossby_response(&data, &request.variable)?;
+
+    let response = compute_cross_section(&metadata, &grid, &array, &request, time, samples)?;
+
+    info!(
+        "Computed cross-section for {} ({} levels × {} samples) in {}ms",
+        request.variable,
+        response.levels.len(),
+        response.points.len(),
+        start_time.elapsed().as_millis()
+    );
+
+    Ok(Json(response))
+}
+
+/// Check path and sample count, returning the effective number of samples
+fn validate_cross_section_request(request: &CrossSectionRequest) -> Result<usize, AppError> {
+    if request.path.len() < 2 {
+        return Err(AppError::RequestError(
+            "Cross-section path needs at least two points".to_string(),
+        ));
+    }
+    if let Some(point) = request.path.iter().find(|p| !p.is_valid()) {
+        return Err(AppError::RequestError(format!(
+            "Invalid path point: lat={}, lon={}",
+            point.lat, point.lon
+        )));
+    }
+
+    let samples = request.samples.unwrap_or(DEFAULT_CROSS_SECTION_SAMPLES);
+    if !(2..=MAX_CROSS_SECTION_SAMPLES).contains(&samples) {
+        return Err(AppError::RequestError(format!(
+            "samples must be between 2 and {}",
+            MAX_CROSS_SECTION_SAMPLES
+        )));
+    }
+
+    Ok(samples)
+}
+
+/// Interpolate every vertical level of `array` along the request path
+fn compute_cross_section(
+    metadata: &Value,
+    grid: &LatLonGrid,
+    array: &DataArray,
+    request: &CrossSectionRequest,
+    time: Option<f64>,
+    samples: usize,
+) -> Result<CrossSectionResponse, AppError> {
+    let level_dimension = array
+        .vertical_dimension()
+        .ok_or_else(|| {
+            AppError::ProxyError(format!(
+                "Backend data for '{}' has no vertical dimension",
+                request.variable
+            ))
+        })?
+        .to_string();
+
+    let level_count = array.dimension_size(&level_dimension);
+    let levels = coordinate_values(metadata, &level_dimension)
+        .filter(|values| values.len() == level_count)
+        .unwrap_or_else(|| (0..level_count).map(|i| i as f64).collect());
+
+    let path_samples = sample_polyline(&request.path, samples);
+    let mut selection = time_selection(metadata, array, time);
+    let mut values = Vec::with_capacity(level_count);
+
+    for level_index in 0..level_count {
+        selection.insert(level_dimension.clone(), level_index);
+        let field = array.horizontal_slice(&selection)?;
+        values.push(
+            path_samples
+                .iter()
+                .map(|(point, _)| grid.bilinear(&field, point.lat, point.lon))
+                .collect(),
+        );
+    }
+
+    Ok(CrossSectionResponse {
+        variable: request.variable.clone(),
+        units: variable_units(metadata, &request.variable),
+        time,
+        level_dimension,
+        levels,
+        distances_km: path_samples.iter().map(|(_, d)| *d).collect(),
+        points: path_samples.iter().map(|(p, _)| *p).collect(),
+        values,
+    })
+}
+
+/// Request body for `/api/trajectories`
+#[derive(Debug, Deserialize, ToSchema)]
+pub struct TrajectoryRequest {
+    /// Starting positions of the particles
+    pub seeds: Vec<GeoPoint>,
+    /// Eastward component variable; defaults to the first wind vector pair
+    pub u_variable: Option<String>,
+    /// Northward component variable; must be given together with `u_variable`
+    pub v_variable: Option<String>,
+    /// Backend time to start from; defaults to the first timestep
+    pub start_time: Option<f64>,
+    /// Backend time to integrate to; defaults to the last timestep.
+    /// Integration runs backward when this is before `start_time`.
+    pub end_time: Option<f64>,
+    /// RK4 step length in minutes
+    pub step_minutes: Option<f64>,
+}
+
+/// Handler for `POST /api/trajectories`
+///
+/// Advects each seed point through the u/v field with RK4 integration and
+/// returns the paths as a GeoJSON `FeatureCollection` of `LineString`s.
+#[utoipa::path(
+    post,
+    path = "/api/trajectories",
+    tag = "analysis",
+    summary = "Paths of particles carried by a vector field",
+    request_body = TrajectoryRequest,
+    responses(
+        (
+            status = 200,
+            description = "GeoJSON `FeatureCollection` of one `LineString` per seed",
+            body = serde_json::Value
+        ),
+        (status = 400, description = "Invalid request or variables", body = ErrorBody),
+        (status = 502, description = "The backend failed", body = ErrorBody),
+    )
+)]
+#[instrument(skip(state, request), fields(seeds = request.seeds.len()))]
+pub async fn trajectories(
+    State(state): State<Arc<AppState>>,
+    Json(request): Json<TrajectoryRequest>,
+) -> Result<Json<Value>, AppError> {
+    let start_time = Instant::now();
+    let metadata = fetch_metadata(&state).await?;
+
+    let (u_variable, v_variable) = match (&request.u_variable, &request.v_variable) {
+        (Some(u), Some(v)) => (u.clone(), v.clone()),
+        (None, None) => find_wind_components(&metadata).ok_or_else(|| {
+            AppError::RequestError(
+                "No vector variable pair found; specify u_variable and v_variable".to_string(),
+            )
+        })?,
+        _ => {
+            return Err(AppError::RequestError(
+                "u_variable and v_variable must be given together".to_string(),
+            ))
+        }
+    };
+
+    let times = coordinate_values(&metadata, "time").unwrap_or_default();
+    let from = request
+        .start_time
+        .or_else(|| times.first().copied())
+        .unwrap_or(0.0);
+    let to = request
+        .end_time
+        .or_else(|| times.last().copied())
+        .unwrap_or(from);
+    let step_hours = validate_trajectory_request(&request, from, to)?;
+
+    let grid = LatLonGrid::from_metadata(&metadata)?;
+    let frame_times = frame_times_for_range(&times, from, to)?;
+    let frames = futures::future::try_join_all(
+        frame_times
+            .iter()
+            .map(|time| fetch_velocity_frame(&state, &metadata, &u_variable, &v_variable, *time)),
+    )
+    .await?;
+
+    let field = VelocityField::new(grid, frames);
+    let features: Vec<Value> = request
+        .seeds
+        .iter()
+        .enumerate()
+        .map(|(index, seed)| {
+            let trajectory = integrate_rk4(&field, *seed, from, to, step_hours);
+            json!({
+                "type": "Feature",
+                "geometry": {
+                    "type": "LineString",
+                    "coordinates": trajectory
+                        .points
+                        .iter()
+                        .map(|(p, _)| [p.lon, p.lat])
+                        .collect::<Vec<_>>(),
+                },
+                "properties": {
+                    "seed_index": index,
+                    "times": trajectory.points.iter().map(|(_, t)| *t).collect::<Vec<_>>(),
+                    "termination": trajectory.termination,
+                }
+            })
+        })
+        .collect();
+
+    info!(
+        "Integrated {} trajectories over {} timesteps in {}ms",
+        features.len(),
+        frame_times.len().max(1),
+        start_time.elapsed().as_millis()
+    );
+
+    Ok(Json(json!({
+        "type": "FeatureCollection",
+        "u_variable": u_variable,
+        "v_variable": v_variable,
+        "start_time": from,
+        "end_time": to,
+        "step_minutes": step_hours * 60.0,
+        "features": features,
+    })))
+}
+
+/// Check seeds and step settings, returning the step length in hours
+fn validate_trajectory_request(
+    request: &TrajectoryRequest,
+    from: f64,
+    to: f64,
+) -> Result<f64, AppError> {
+    if request.seeds.is_empty() || request.seeds.len() > MAX_TRAJECTORY_SEEDS {
+        return Err(AppError::RequestError(format!(
+            "Between 1 and {} seed points are required",
+            MAX_TRAJECTORY_SEEDS
+        )));
+    }
+    if let Some(seed) = request.seeds.iter().find(|p| !p.is_valid()) {
+        return Err(AppError::RequestError(format!(
+            "Invalid seed point: lat={}, lon={}",
+            seed.lat, seed.lon
+        )));
+    }
+
+    let step_minutes = request
+        .step_minutes
+        .unwrap_or(DEFAULT_TRAJECTORY_STEP_MINUTES);
+    if !step_minutes.is_finite() || step_minutes <= 0.0 {
+        return Err(AppError::RequestError(
+            "step_minutes must be a positive number".to_string(),
+        ));
+    }
+
+    let step_hours = step_minutes / 60.0;
+    let steps = ((to - from).abs() / step_hours).ceil();
+    if !steps.is_finite() || steps > MAX_TRAJECTORY_STEPS as f64 {
+        return Err(AppError::RequestError(format!(
+            "Time range requires {} steps; at most {} are allowed (increase step_minutes)",
+            steps, MAX_TRAJECTORY_STEPS
+        )));
+    }
+
+    Ok(step_hours)
+}
+
+/// Timesteps needed to cover `[from, to]`, including the bracketing steps outside it
+fn frame_times_for_range(times: &[f64], from: f64, to: f64) -> Result<Vec<Option<f64>>, AppError> {
+    if times.is_empty() {
+        return Ok(vec![None]);
+    }
+
+    let (low, high) = (from.min(to), from.max(to));
+    let first = times.iter().rposition(|t| *t <= low).unwrap_or(0);
+    let last = times
+        .iter()
+        .position(|t| *t >= high)
+        .unwrap_or(times.len() - 1);
+    let selected: Vec<Option<f64>> = times[first..=last.max(first)]
+        .iter()
+        .map(|t| Some(*t))
+        .collect();
+
+    if selected.len() > MAX_TRAJECTORY_FRAMES {
+        return Err(AppError::RequestError(format!(
+            "Time range spans {} timesteps; at most {} are allowed",
+            selected.len(),
+            MAX_TRAJECTORY_FRAMES
+        )));
+    }
+
+    Ok(selected)
+}
+
+/// Fetch u and v at one timestep as a velocity frame
+async fn fetch_velocity_frame(
+    state: &AppState,
+    metadata: &Value,
+    u_variable: &str,
+    v_variable: &str,
+    time: Option<f64>,
+) -> Result<VelocityFrame, AppError> {
+    let data = fetch_data(state, &data_query(&[u_variable, v_variable], time)).await?;
+    let u_array = DataArray::from_rossby_response(&data, u_variable)?;
+    let v_array = DataArray::from_rossby_response(&data, v_variable)?;
+    let selection = time_selection(metadata, &u_array, time);
+
+    Ok(VelocityFrame {
+        time: time.unwrap_or(0.0),
+        u: u_array.horizontal_slice(&selection)?,
+        v: v_array.horizontal_slice(&selection)?,
+    })
+}
+
+/// A location to sample, optionally labelled (e.g. with a station identifier)
+#[derive(Debug, Clone, Deserialize, ToSchema)]
+pub struct SamplePoint {
+    /// Latitude in degrees north
+    pub lat: f64,
+    /// Longitude in degrees east
+    pub lon: f64,
+    /// Optional label echoed back in the response
+    #[serde(default)]
+    pub id: Option<String>,
+}
+
+/// JSON request body for `/api/sample`
+#[derive(Debug, Deserialize, ToSchema)]
+pub struct SampleRequest {
+    /// Locations to sample
+    pub points: Vec<SamplePoint>,
+    /// Variables to interpolate
+    pub vars: Vec<String>,
+    /// Backend time values; defaults to the first available timestep
+    #[serde(default)]
+    pub times: Vec<f64>,
+}
+
+/// Query parameters for `/api/sample`, used with CSV uploads
+#[derive(Debug, Default, Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct SampleQuery {
+    /// Comma-separated list of variables
+    pub vars: Option<String>,
+    /// Comma-separated list of backend time values
+    pub time: Option<String>,
+}
+
+/// Interpolated values for one sample point
+#[derive(Debug, Serialize, ToSchema)]
+pub struct SampledPoint {
+    /// Latitude in degrees north
+    pub lat: f64,
+    /// Longitude in degrees east
+    pub lon: f64,
+    /// Label from the request, if any
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub id: Option<String>,
+    /// Values per variable, one entry per requested time; `null` where unavailable
+    pub values: HashMap<String, Vec<Option<f64>>>,
+}
+
+/// Response body for `/api/sample`
+#[derive(Debug, Serialize, ToSchema)]
+pub struct SampleResponse {
+    /// Backend time values, in the order used by each value list
+    pub times: Vec<f64>,
+    /// Units per variable
+    pub units: HashMap<String, String>,
+    /// Sampled points in request order
+    pub points: Vec<SampledPoint>,
+}
+
+/// Handler for `POST /api/sample`
+///
+/// Accepts either a JSON [`SampleRequest`] or a CSV upload (`Content-Type:
+/// text/csv`) with `lat`/`lon` columns and an optional `id` column; for CSV the
+/// variables and times come from the `vars` and `time` query parameters.
+#[utoipa::path(
+    post,
+    path = "/api/sample",
+    tag = "analysis",
+    summary = "Values of variables at points",
+    params(SampleQuery),
+    request_body(
+        description = "Points as JSON, or as CSV with `lat`, `lon` and optional `id` columns",
+        content(
+            (SampleRequest = "application/json"),
+            (String = "text/csv"),
+        )
+    ),
+    responses(
+        (status = 200, body = SampleResponse),
+        (status = 400, description = "Invalid points, variables or times", body = ErrorBody),
+        (status = 502, description = "The backend failed", body = ErrorBody),
+    )
+)]
+#[instrument(skip_all)]
+pub async fn sample(
+    State(state): State<Arc<AppState>>,
+    Query(query): Query<SampleQuery>,
+    headers: HeaderMap,
+    body: Bytes,
+) -> Result<Json<SampleResponse>, AppError> {
+    let start_time = Instant::now();
+    let is_csv = headers
+        .get(header::CONTENT_TYPE)
+        .and_then(|v| v.to_str().ok())
+        .map(|v| v.starts_with("text/csv"))
+        .unwrap_or(false);
+
+    let request = if is_csv {
+        sample_request_from_csv(&body, &query)?
+    } else {
+        serde_json::from_slice::<SampleRequest>(&body)
+            .map_err(|e| AppError::RequestError(format!("Invalid sample request: {}", e)))?
+    };
+    validate_sample_request(&request)?;
+
+    let metadata = fetch_metadata(&state).await?;
+    if let Some(missing) = request.vars.iter().find(|v| {
+        metadata
+            .get("variables")
+            .and_then(|m| m.get(v.as_str()))
+            .is_none()
+    }) {
+        return Err(AppError::RequestError(format!(
+            "Variable '{}' not found in metadata",
+            missing
+        )));
+    }
+
+    let grid = LatLonGrid::from_metadata(&metadata)?;
+    let times: Vec<Option<f64>> = if request.times.is_empty() {
+        vec![first_time(&metadata)]
+    } else {
+        request.times.iter().map(|t| Some(*t)).collect()
+    };
+    let variables: Vec<&str> = request.vars.iter().map(String::as_str).collect();
+
+    let queries: Vec<String> = times
+        .iter()
+        .map(|time| data_query(&variables, *time))
+        .collect();
+    let responses =
+        futures::future::try_join_all(queries.iter().map(|query| fetch_data(&state, query)))
+            .await?;
+
+    let mut points: Vec<SampledPoint> = request
+        .points
+        .iter()
+        .map(|p| SampledPoint {
+            lat: p.lat,
+            lon: p.lon,
+            id: p.id.clone(),
+            values: HashMap::new(),
+        })
+        .collect();
+
+    for (data, time) in responses.iter().zip(&times) {
+        for variable in &variables {
+            let array = DataArray::from_rossby_response(data, variable)?;
+            let field = array.horizontal_slice(&time_selection(&metadata, &array, *time))?;
+            for point in points.iter_mut() {
+                point
+                    .values
+                    .entry(variable.to_string())
+                    .or_default()
+                    .push(grid.bilinear(&field, point.lat, point.lon));
+            }
+        }
+    }
+
+    info!(
+        "Sampled {} points × {} variables × {} times in {}ms",
+        points.len(),
+        variables.len(),
+        times.len(),
+        start_time.elapsed().as_millis()
+    );
+
+    Ok(Json(SampleResponse {
+        times: times.iter().flatten().copied().collect(),
+        units: variables
+            .iter()
+            .map(|v| (v.to_string(), variable_units(&metadata, v)))
+            .collect(),
+        points,
+    }))
+}
+
+/// Check point, variable and time counts for a sampling request
+fn validate_sample_request(request: &SampleRequest) -> Result<(), AppError> {
+    if request.points.is_empty() || request.points.len() > MAX_SAMPLE_POINTS {
+        return Err(AppError::RequestError(format!(
+            "Between 1 and {} points are required",
+            MAX_SAMPLE_POINTS
+        )));
+    }
+    if let Some(point) = request
+        .points
+        .iter()
+        .find(|p| !GeoPoint::new(p.lat, p.lon).is_valid())
+    {
+        return Err(AppError::RequestError(format!(
+            "Invalid point: lat={}, lon={}",
+            point.lat, point.lon
+        )));
+    }
+    if request.vars.is_empty() || request.vars.len() > MAX_SAMPLE_VARIABLES {
+        return Err(AppError::RequestError(format!(
+            "Between 1 and {} variables are required",
+            MAX_SAMPLE_VARIABLES
+        )));
+    }
+    if request.times.len() > MAX_SAMPLE_TIMES {
+        return Err(AppError::RequestError(format!(
+            "At most {} times may be sampled per request",
+            MAX_SAMPLE_TIMES
+        )));
+    }
+    Ok(())
+}
+
+/// Build a sampling request from a CSV upload and the query string
+fn sample_request_from_csv(body: &[u8], query: &SampleQuery) -> Result<SampleRequest, AppError> {
+    let text = std::str::from_utf8(body)
+        .map_err(|_| AppError::RequestError("CSV upload is not valid UTF-8".to_string()))?;
+    let mut lines = text.lines().filter(|line| !line.trim().is_empty());
+
+    let header_line = lines
+        .next()
+        .ok_or_else(|| AppError::RequestError("CSV upload is empty".to_string()))?;
+    let columns: Vec<String> = split_csv_line(header_line)
+        .iter()
+        .map(|c| c.to_lowercase())
+        .collect();
+    let find_column = |names: &[&str]| columns.iter().position(|c| names.contains(&c.as_str()));
+
+    let lat_column = find_column(&["lat", "latitude"])
+        .ok_or_else(|| AppError::RequestError("CSV needs a 'lat' column".to_string()))?;
+    let lon_column = find_column(&["lon", "lng", "longitude"])
+        .ok_or_else(|| AppError::RequestError("CSV needs a 'lon' column".to_string()))?;
+    let id_column = find_column(&["id", "name", "station"]);
+
+    let mut points = Vec::new();
+    for (line_number, line) in lines.enumerate() {
+        let fields = split_csv_line(line);
+        let parse = |column: usize, name: &str| {
+            fields
+                .get(column)
+                .and_then(|v| v.parse::<f64>().ok())
+                .ok_or_else(|| {
+                    AppError::RequestError(format!(
+                        "Invalid {} on CSV data row {}",
+                        name,
+                        line_number + 1
+                    ))
+                })
+        };
+        points.push(SamplePoint {
+            lat: parse(lat_column, "lat")?,
+            lon: parse(lon_column, "lon")?,
+            id: id_column.and_then(|c| fields.get(c).cloned()),
+        });
+    }
+
+    let vars = query.vars.as_deref().map(split_list).unwrap_or_default();
+    let times = query
+        .time
+        .as_deref()
+        .map(split_list)
+        .unwrap_or_default()
+        .iter()
+        .map(|t| {
+            t.parse::<f64>()
+                .map_err(|_| AppError::RequestError(format!("Invalid time value '{}'", t)))
+        })
+        .collect::<Result<Vec<f64>, AppError>>()?;
+
+    Ok(SampleRequest {
+        points,
+        vars,
+        times,
+    })
+}
+
+/// Split one CSV line into trimmed, unquoted fields
+fn split_csv_line(line: &str) -> Vec<String> {
+    line.split(',')
+        .map(|field| field.trim().trim_matches('"').to_string())
+        .collect()
+}
+
+/// Split a comma-separated query value into non-empty entries
+fn split_list(value: &str) -> Vec<String> {
+    value
+        .split(',')
+        .map(str::trim)
+        .filter(|v| !v.is_empty())
+        .map(String::from)
+        .collect()
+}
+
+/// First value of the metadata time axis
+pub(crate) fn first_time(metadata: &Value) -> Option<f64> {
+    coordinate_values(metadata, "time").and_then(|times| times.first().copied())
+}
+
+/// Units attribute of a variable, or an empty string
+pub(crate) fn variable_units(metadata: &Value, variable: &str) -> String {
+    metadata
+        .get("variables")
+        .and_then(|v| v.get(variable))
+        .and_then(|v| v.get("attributes"))
+        .and_then(|a| a.get("units"))
+        .and_then(|u| u.as_str())
+        .unwrap_or("")
+        .to_string()
+}
+
+/// Build a backend `/data` query string for variables at an optional time
+pub(crate) fn data_query(variables: &[&str], time: Option<f64>) -> String {
+    let mut query = format!("vars={}", variables.join(","));
+    if let Some(time) = time {
+        query.push_str(&format!("&time={}", time));
+    }
+    query.push_str("&format=json");
+    query
+}
+
+/// Index selection for the time dimension when the backend returns several timesteps
+pub(crate) fn time_selection(
+    metadata: &Value,
+    array: &DataArray,
+    time: Option<f64>,
+) -> HashMap<String, usize> {
+    let mut selection = HashMap::new();
+    let time_size = array.dimension_size("time");
+
+    if let (Some(time), Some(times)) = (time, coordinate_values(metadata, "time")) {
+        if time_size > 1 && times.len() == time_size {
+            if let Some(index) = times.iter().position(|t| (t - time).abs() < 1e-9) {
+                selection.insert("time".to_string(), index);
+            }
+        }
+    }
+
+    selection
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn test_metadata() -> Value {
+        json!({
+            "coordinates": {
+                "latitude": [10.0, 0.0],
+                "longitude": [0.0, 10.0],
+                "level": [850.0, 500.0],
+                "time": [700464.0, 700465.0]
+            },
+            "variables": {
+                "t": {
+                    "dimensions": ["time", "level", "latitude", "longitude"],
+                    "attributes": {"units": "K"}
+                }
+            }
+        })
+    }
+
+    fn test_request(samples: Option<usize>) -> CrossSectionRequest {
+        CrossSectionRequest {
+            variable: "t".to_string(),
+            path: vec![GeoPoint::new(5.0, 0.0), GeoPoint::new(5.0, 10.0)],
+            time: Some(700465.0),
+            samples,
+        }
+    }
+
+    #[test]
+    fn test_compute_cross_section_levels_and_time() {
+        let metadata = test_metadata();
+        let grid = LatLonGrid::from_metadata(&metadata).unwrap();
+        // time 0 is all zeros; time 1 holds 1.0 at 850 hPa and 2.0 at 500 hPa
+        let mut values = vec![0.0; 8];
+        values.extend([1.0; 4]);
+        values.extend([2.0; 4]);
+        let array = DataArray::new(
+            vec![
+                "time".into(),
+                "level".into(),
+                "latitude".into(),
+                "longitude".into(),
+            ],
+            vec![2, 2, 2, 2],
+            values,
+        )
+        .unwrap();
+
+        let request = test_request(Some(3));
+        let section =
+            compute_cross_section(&metadata, &grid, &array, &request, request.time, 3).unwrap();
+
+        assert_eq!(section.levels, vec![850.0, 500.0]);
+        assert_eq!(section.units, "K");
+        assert_eq!(section.points.len(), 3);
+        assert_eq!(section.values[0], vec![Some(1.0); 3]);
+        assert_eq!(section.values[1], vec![Some(2.0); 3]);
+        assert!(section.distances_km[2] > 1000.0);
+    }
+
+    #[test]
+    fn test_validate_cross_section_request() {
+        assert_eq!(
+            validate_cross_section_request(&test_request(None)).unwrap(),
+            100
+        );
+        assert!(validate_cross_section_request(&test_request(Some(1))).is_err());
+        assert!(validate_cross_section_request(&test_request(Some(100_000))).is_err());
+
+        let mut request = test_request(None);
+        request.path.truncate(1);
+        assert!(validate_cross_section_request(&request).is_err());
+
+        let mut request = test_request(None);
+        request.path[0].lat = 95.0;
+        assert!(validate_cross_section_request(&request).is_err());
+    }
+
+    #[test]
+    fn test_frame_times_for_range() {
+        let times = [0.0, 6.0, 12.0, 18.0];
+
+        assert_eq!(
+            frame_times_for_range(&times, 3.0, 12.0).unwrap(),
+            vec![Some(0.0), Some(6.0), Some(12.0)]
+        );
+        assert_eq!(
+            frame_times_for_range(&times, 13.0, 7.0).unwrap(),
+            vec![Some(6.0), Some(12.0), Some(18.0)]
+        );
+        assert_eq!(frame_times_for_range(&[], 0.0, 6.0).unwrap(), vec![None]);
+    }
+
+    #[test]
+    fn test_validate_trajectory_request() {
+        let mut request = TrajectoryRequest {
+            seeds: vec![GeoPoint::new(0.0, 0.0)],
+            u_variable: None,
+            v_variable: None,
+            start_time: None,
+            end_time: None,
+            step_minutes: Some(90.0),
+        };
+        assert_eq!(
+            validate_trajectory_request(&request, 0.0, 6.0).unwrap(),
+            1.5
+        );
+        assert!(validate_trajectory_request(&request, 0.0, 1e6).is_err());
+
+        request.step_minutes = Some(0.0);
+        assert!(validate_trajectory_request(&request, 0.0, 6.0).is_err());
+
+        request.step_minutes = None;
+        request.seeds.clear();
+        assert!(validate_trajectory_request(&request, 0.0, 6.0).is_err());
+    }
+
+    #[test]
+    fn test_sample_request_from_csv() {
+        let csv = b"station,latitude,longitude\n\"KJFK\",40.64,-73.78\nEGLL, 51.47 , -0.45\n\n";
+        let query = SampleQuery {
+            vars: Some("t2m,u10".to_string()),
+            time: Some("700464,700465".to_string()),
+        };
+
+        let request = sample_request_from_csv(csv, &query).unwrap();
+        assert_eq!(request.vars, vec!["t2m", "u10"]);
+        assert_eq!(request.times, vec![700464.0, 700465.0]);
+        assert_eq!(request.points.len(), 2);
+        assert_eq!(request.points[0].id.as_deref(), Some("KJFK"));
+        assert_eq!(request.points[1].lat, 51.47);
+        assert_eq!(request.points[1].lon, -0.45);
+    }
+
+    #[test]
+    fn test_sample_request_from_csv_errors() {
+        let query = SampleQuery::default();
+        assert!(sample_request_from_csv(b"", &query).is_err());
+        assert!(sample_request_from_csv(b"x,y\n1,2", &query).is_err());
+        assert!(sample_request_from_csv(b"lat,lon\nabc,2", &query).is_err());
+
+        let query = SampleQuery {
+            vars: Some("t2m".to_string()),
+            time: Some("noon".to_string()),
+        };
+        assert!(sample_request_from_csv(b"lat,lon\n1,2", &query).is_err());
+    }
+
+    #[test]
+    fn test_validate_sample_request() {
+        let mut request = SampleRequest {
+            points: vec![SamplePoint {
+                lat: 10.0,
+                lon: 20.0,
+                id: None,
+            }],
+            vars: vec!["t2m".to_string()],
+            times: vec![],
+        };
+        assert!(validate_sample_request(&request).is_ok());
+
+        request.vars.clear();
+        assert!(validate_sample_request(&request).is_err());
+
+        request.vars.push("t2m".to_string());
+        request.points[0].lat = -100.0;
+        assert!(validate_sample_request(&request).is_err());
+    }
+
+    #[test]
+    fn test_data_query() {
+        assert_eq!(
+            data_query(&["u10", "v10"], Some(700464.0)),
+            "vars=u10,v10&time=700464&format=json"
+        );
+        assert_eq!(data_query(&["t2m"], None), "vars=t2m&format=json");
+    }
+}
@@ -0,0 +1,768 @@
+//! Password and API key protection of the server
+//!
+//! With `--basic-auth user:bcrypt-hash` (repeatable), every request but the
+//! health checks needs the HTTP Basic credentials of one of the users, so an
+//! internal instance can be put on the public internet without a proxy in
+//! front of it. `htpasswd -nbB user password` prints such a line.
+//!
+//! Checking a bcrypt hash takes tens of milliseconds, which the dozens of
+//! asset requests of a page load should not each pay, so credentials that
+//! passed are remembered by their SHA-256 for as long as the server runs.
+//!
+//! API keys, from `--api-key label=key` or a key store file named with
+//! `--api-keys-file`, give partners access to the data and API routes alone
+//! ([`DATA_ROUTE_PREFIXES`]). They are sent as `X-Api-Key` or as
+//! `Authorization: Bearer`, and the label of the key is recorded in the
+//! request's tracing span, never the key. Once any key is configured, those
+//! routes need a key or, with users configured, Basic credentials.
+//!
+//! With a database (`--database`, see [`crate::store`]), keys can also be
+//! issued and revoked while the server runs through `/admin/api-keys`. The
+//! database keeps only their SHA-256, so a key is shown once, when issued.
+//!
+//! Those routes also take bearer tokens of an OpenID Connect provider once
+//! `--jwt-issuer` or `--jwt-jwks-url` is given (see [`crate::jwt`]); the
+//! token's subject is recorded in the tracing span.
+//!
+//! The `/admin/*` endpoints are left to their own bearer token.
+
+use axum::{
+    extract::State,
+    http::{header, HeaderValue, Request},
+    middleware::Next,
+    response::{IntoResponse, Response},
+};
+use base64::{engine::general_purpose::STANDARD, Engine as _};
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+use sha2::{Digest, Sha256};
+use std::{
+    collections::{HashMap, HashSet},
+    fmt,
+    path::Path,
+    str::FromStr,
+    sync::{Arc, Mutex, RwLock},
+};
+use tracing::{debug, warn, Span};
+use utoipa::ToSchema;
+
+#[cfg(feature = "sqlite")]
+use crate::store::{Store, SHARED};
+use crate::{
+    error::AppError,
+    jwt::{JwtConfig, JwtValidator},
+    server::AppState,
+};
+
+/// Credentials remembered at most, forgotten all at once when full
+const VERIFIED_CAPACITY: usize = 1024;
+
+/// Routes API keys and bearer tokens give access to
+pub const DATA_ROUTE_PREFIXES: [&str; 3] = ["/proxy/", "/data/", "/api/"];
+
+/// Header carrying an API key, besides `Authorization: Bearer`
+pub const API_KEY_HEADER: &str = "x-api-key";
+
+/// Shortest API key accepted, so keys cannot be guessed
+const MIN_API_KEY_LEN: usize = 16;
+
+/// Store collection of the keys issued through `/admin/api-keys`
+#[cfg(feature = "sqlite")]
+const API_KEY_COLLECTION: &str = "api_keys";
+
+/// Random bytes of an issued key, 32 characters once encoded
+#[cfg(feature = "sqlite")]
+const ISSUED_KEY_BYTES: usize = 24;
+
+/// A user allowed in with HTTP Basic authentication
+#[derive(Clone, PartialEq, Eq)]
+pub struct BasicUser {
+    pub username: String,
+    /// bcrypt hash of the password
+    hash: String,
+}
+
+impl fmt::Debug for BasicUser {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("BasicUser")
+            .field("username", &self.username)
+            .finish_non_exhaustive()
+    }
+}
+
+impl FromStr for BasicUser {
+    type Err = AppError;
+
+    /// Parse `user:bcrypt-hash`, as `htpasswd -nbB` prints it
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (username, hash) = s.trim().split_once(':').ok_or_else(|| {
+            AppError::ConfigError("Invalid basic auth user; expected USER:BCRYPT-HASH".to_string())
+        })?;
+        if username.is_empty() {
+            return Err(AppError::ConfigError(
+                "Invalid basic auth user: the user name is empty".to_string(),
+            ));
+        }
+        hash.parse::<bcrypt::HashParts>().map_err(|e| {
+            AppError::ConfigError(format!(
+                "Invalid bcrypt hash for basic auth user '{}': {}",
+                username, e
+            ))
+        })?;
+        Ok(Self {
+            username: username.to_string(),
+            hash: hash.to_string(),
+        })
+    }
+}
+
+/// An API key and the label identifying its holder
+#[derive(Clone, PartialEq, Eq)]
+pub struct ApiKey {
+    pub label: String,
+    key: String,
+}
+
+impl fmt::Debug for ApiKey {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("ApiKey")
+            .field("label", &self.label)
+            .finish_non_exhaustive()
+    }
+}
+
+impl FromStr for ApiKey {
+    type Err = AppError;
+
+    /// Parse `label=key`
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (label, key) = s.trim().split_once('=').ok_or_else(|| {
+            AppError::ConfigError("Invalid API key; expected LABEL=KEY".to_string())
+        })?;
+        let (label, key) = (label.trim(), key.trim());
+        if label.is_empty() {
+            return Err(AppError::ConfigError(
+                "Invalid API key: the label is empty".to_string(),
+            ));
+        }
+        if key.len() < MIN_API_KEY_LEN {
+            return Err(AppError::ConfigError(format!(
+                "API key '{}' is too short; use at least {} characters",
+                label, MIN_API_KEY_LEN
+            )));
+        }
+        Ok(Self {
+            label: label.to_string(),
+            key: key.to_string(),
+        })
+    }
+}
+
+/// API keys of a key store file: one `label=key` per line, with blank lines
+/// and lines starting with `#` ignored
+pub fn load_api_keys(path: &Path) -> Result<Vec<ApiKey>, AppError> {
+    let contents = std::fs::read_to_string(path).map_err(|e| {
+        AppError::ConfigError(format!("Cannot read API keys {}: {}", path.display(), e))
+    })?;
+    contents
+        .lines()
+        .enumerate()
+        .map(|(number, line)| (number, line.trim()))
+        .filter(|(_, line)| !line.is_empty() && !line.starts_with('#'))
+        .map(|(number, line)| {
+            line.parse().map_err(|e: AppError| {
+                AppError::ConfigError(format!("{}:{}: {}", path.display(), number + 1, e))
+            })
+        })
+        .collect()
+}
+
+/// Who may use the server
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct AuthConfig {
+    /// Users allowed in with HTTP Basic authentication; anyone may use the
+    /// server when empty
+    pub basic: Vec<BasicUser>,
+    /// Keys allowed on the data and API routes
+    pub api_keys: Vec<ApiKey>,
+    /// Provider whose bearer tokens are allowed on the data and API routes
+    pub jwt: JwtConfig,
+}
+
+impl AuthConfig {
+    pub fn is_enabled(&self) -> bool {
+        !self.basic.is_empty() || !self.api_keys.is_empty() || self.jwt.is_enabled()
+    }
+
+    /// Check that no key is given twice and the token provider is a URL
+    pub fn validate(&self) -> Result<(), AppError> {
+        for url in [&self.jwt.issuer, &self.jwt.jwks_url].into_iter().flatten() {
+            if !url.starts_with("https://") && !url.starts_with("http://") {
+                return Err(AppError::ConfigError(format!(
+                    "Invalid JWT issuer or JWKS URL '{}'; expected an http(s) URL",
+                    url
+                )));
+            }
+        }
+        let mut keys = HashSet::new();
+        let mut labels = HashSet::new();
+        for api_key in &self.api_keys {
+            if !keys.insert(&api_key.key) {
+                return Err(AppError::ConfigError(format!(
+                    "API key '{}' is also configured under another label",
+                    api_key.label
+                )));
+            }
+            if !labels.insert(&api_key.label) {
+                return Err(AppError::ConfigError(format!(
+                    "API key label '{}' is used twice",
+                    api_key.label
+                )));
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Checks the credentials of requests against the configured users
+#[derive(Debug, Default)]
+pub struct Authenticator {
+    config: AuthConfig,
+    /// SHA-256 of `Authorization` headers that passed
+    verified: Mutex<HashSet<[u8; 32]>>,
+    /// Labels of the API keys by the keys' SHA-256, so looking a key up
+    /// takes no longer for a near miss
+    api_keys: HashMap<[u8; 32], String>,
+    /// Labels of the keys issued through `/admin/api-keys`, the same way
+    issued_keys: RwLock<HashMap<[u8; 32], String>>,
+    jwt: Option<JwtValidator>,
+    /// Database keeping the issued keys
+    #[cfg(feature = "sqlite")]
+    store: Option<Store>,
+}
+
+/// A key issued through `/admin/api-keys`
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, ToSchema)]
+pub struct IssuedApiKey {
+    pub label: String,
+    /// The key itself, only returned when it is issued
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub key: Option<String>,
+    pub issued_at: DateTime<Utc>,
+}
+
+impl Authenticator {
+    pub fn new(config: AuthConfig) -> Result<Self, AppError> {
+        let api_keys = config
+            .api_keys
+            .iter()
+            .map(|api_key| (sha256(&api_key.key), api_key.label.clone()))
+            .collect();
+        let jwt = config
+            .jwt
+            .is_enabled()
+            .then(|| JwtValidator::new(config.jwt.clone()))
+            .transpose()?;
+        Ok(Self {
+            config,
+            verified: Mutex::default(),
+            api_keys,
+            issued_keys: RwLock::default(),
+            jwt,
+            #[cfg(feature = "sqlite")]
+            store: None,
+        })
+    }
+
+    /// Keep issued keys in `store`, accepting those issued before, and
+    /// return how many there are
+    #[cfg(feature = "sqlite")]
+    pub async fn use_store(&mut self, store: Store) -> Result<usize, AppError> {
+        let mut issued = HashMap::new();
+        for record in store.collection(API_KEY_COLLECTION).list(SHARED).await? {
+            let hash = record.value["sha256"]
+                .as_str()
+                .and_then(parse_sha256)
+                .ok_or_else(|| {
+                    AppError::StorageError(format!("Invalid stored API key '{}'", record.key))
+                })?;
+            issued.insert(hash, record.key);
+        }
+        let count = issued.len();
+        *self
+            .issued_keys
+            .get_mut()
+            .unwrap_or_else(|e| e.into_inner()) = issued;
+        self.store = Some(store);
+        Ok(count)
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.config.is_enabled() || !self.issued_keys().is_empty()
+    }
+
+    /// Whether requests for `path` need credentials
+    pub fn protects(&self, path: &str) -> bool {
+        if is_public(path) {
+            return false;
+        }
+        let data_credentials =
+            !self.api_keys.is_empty() || !self.issued_keys().is_empty() || self.jwt.is_some();
+        !self.config.basic.is_empty() || (data_credentials && is_data_route(path))
+    }
+
+    /// Label of the API key `key`, if it is one
+    pub fn api_key_label(&self, key: &str) -> Option<String> {
+        let hash = sha256(key);
+        self.api_keys
+            .get(&hash)
+            .or(self.issued_keys().get(&hash))
+            .cloned()
+    }
+
+    fn issued_keys(&self) -> std::sync::RwLockReadGuard<'_, HashMap<[u8; 32], String>> {
+        self.issued_keys.read().unwrap_or_else(|e| e.into_inner())
+    }
+
+    /// The database keeping issued keys, `NotFound` without one
+    #[cfg(feature = "sqlite")]
+    fn store(&self) -> Result<&Store, AppError> {
+        self.store.as_ref().ok_or_else(|| {
+            AppError::NotFound("API keys are only issued with --database".to_string())
+        })
+    }
+
+    /// The keys issued through `/admin/api-keys`, without the keys
+    #[cfg(feature = "sqlite")]
+    pub async fn issued_api_keys(&self) -> Result<Vec<IssuedApiKey>, AppError> {
+        let records = self
+            .store()?
+            .collection(API_KEY_COLLECTION)
+            .list(SHARED)
+            .await?;
+        Ok(records
+            .into_iter()
+            .map(|record| IssuedApiKey {
+                label: record.key,
+                key: None,
+                issued_at: record.updated_at,
+            })
+            .collect())
+    }
+
+    /// Issue a new key for `label`, replacing the one it had
+    #[cfg(feature = "sqlite")]
+    pub async fn issue_api_key(&self, label: &str) -> Result<IssuedApiKey, AppError> {
+        use rand::RngCore;
+
+        let store = self.store()?;
+        if label.is_empty() || label.chars().any(|c| c.is_control()) {
+            return Err(AppError::RequestError(format!(
+                "Invalid API key label '{}'",
+                label.escape_debug()
+            )));
+        }
+        if self.config.api_keys.iter().any(|key| key.label == label) {
+            return Err(AppError::RequestError(format!(
+                "API key label '{}' is configured on the command line",
+                label
+            )));
+        }
+        let mut bytes = [0; ISSUED_KEY_BYTES];
+        rand::thread_rng().fill_bytes(&mut bytes);
+        let key = base64::engine::general_purpose::URL_SAFE_NO_PAD.encode(bytes);
+        let hash = sha256(&key);
+
+        let hex: String = hash.iter().map(|b| format!("{:02x}", b)).collect();
+        let record = store
+            .collection(API_KEY_COLLECTION)
+            .put(SHARED, label, &serde_json::json!({ "sha256": hex }))
+            .await?;
+        let mut issued = self.issued_keys.write().unwrap_or_else(|e| e.into_inner());
+        issued.retain(|_, issued_label| issued_label != label);
+        issued.insert(hash, label.to_string());
+        Ok(IssuedApiKey {
+            label: record.key,
+            key: Some(key),
+            issued_at: record.updated_at,
+        })
+    }
+
+    /// Revoke the key issued for `label`, returning whether there was one
+    #[cfg(feature = "sqlite")]
+    pub async fn revoke_api_key(&self, label: &str) -> Result<bool, AppError> {
+        let revoked = self
+            .store()?
+            .collection(API_KEY_COLLECTION)
+            .delete(SHARED, label)
+            .await?;
+        self.issued_keys
+            .write()
+            .unwrap_or_else(|e| e.into_inner())
+            .retain(|_, issued_label| issued_label != label);
+        Ok(revoked)
+    }
+
+    /// Subject of the bearer token `token`, if the provider signed it
+    ///
+    /// Tokens without a subject pass with an empty one. Fails when no
+    /// provider is configured or its signing keys cannot be fetched.
+    pub async fn check_jwt(&self, token: &str) -> Result<String, AppError> {
+        let jwt = self
+            .jwt
+            .as_ref()
+            .ok_or_else(|| AppError::Unauthorized("bearer tokens are not accepted".to_string()))?;
+        Ok(jwt.validate(token).await?.sub.unwrap_or_default())
+    }
+
+    /// Whether `authorization` holds the Basic credentials of a known user
+    pub async fn check_basic(&self, authorization: &str) -> bool {
+        let key = sha256(authorization);
+        if self.verified().contains(&key) {
+            return true;
+        }
+
+        let Some((username, password)) = authorization
+            .strip_prefix("Basic ")
+            .and_then(|encoded| STANDARD.decode(encoded.trim()).ok())
+            .and_then(|decoded| String::from_utf8(decoded).ok())
+            .and_then(|decoded| {
+                decoded
+                    .split_once(':')
+                    .map(|(user, password)| (user.to_string(), password.to_string()))
+            })
+        else {
+            return false;
+        };
+        // Unknown users are checked against some hash all the same, so the
+        // time taken does not tell which users exist
+        let Some(user) = self
+            .config
+            .basic
+            .iter()
+            .find(|user| user.username == username)
+            .or(self.config.basic.first())
+        else {
+            return false;
+        };
+        let known = user.username == username;
+        let hash = user.hash.clone();
+        let matches = tokio::task::spawn_blocking(move || bcrypt::verify(password, &hash))
+            .await
+            .ok()
+            .and_then(Result::ok)
+            .unwrap_or(false);
+
+        if known && matches {
+            let mut verified = self.verified();
+            if verified.len() >= VERIFIED_CAPACITY {
+                verified.clear();
+            }
+            verified.insert(key);
+            true
+        } else {
+            false
+        }
+    }
+
+    fn verified(&self) -> std::sync::MutexGuard<'_, HashSet<[u8; 32]>> {
+        self.verified.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+fn sha256(value: &str) -> [u8; 32] {
+    Sha256::digest(value.as_bytes()).into()
+}
+
+/// A SHA-256 written as 64 hexadecimal digits
+#[cfg(feature = "sqlite")]
+fn parse_sha256(hex: &str) -> Option<[u8; 32]> {
+    let mut hash = [0; 32];
+    if hex.len() != 64 || !hex.is_ascii() {
+        return None;
+    }
+    for (byte, digits) in hash.iter_mut().zip(hex.as_bytes().chunks(2)) {
+        *byte = u8::from_str_radix(std::str::from_utf8(digits).ok()?, 16).ok()?;
+    }
+    Some(hash)
+}
+
+/// Whether `path` is served without credentials
+fn is_public(path: &str) -> bool {
+    matches!(path, "/health" | "/healthz") || path.starts_with("/admin/")
+}
+
+/// Whether API keys and bearer tokens give access to `path`
+fn is_data_route(path: &str) -> bool {
+    DATA_ROUTE_PREFIXES
+        .iter()
+        .any(|prefix| path.starts_with(prefix))
+}
+
+/// The API key of a request, from `X-Api-Key` or `Authorization: Bearer`
+fn api_key<B>(request: &Request<B>) -> Option<&str> {
+    let headers = request.headers();
+    headers
+        .get(API_KEY_HEADER)
+        .or_else(|| headers.get(header::AUTHORIZATION))
+        .and_then(|value| value.to_str().ok())
+        .map(|value| value.strip_prefix("Bearer ").unwrap_or(value).trim())
+}
+
+/// Whether `value` has the shape of a JWT rather than of an API key
+fn is_jwt(value: &str) -> bool {
+    value.split('.').count() == 3
+}
+
+/// Reject requests without the credentials of a configured user, API key or
+/// token provider
+pub async fn auth_middleware<B>(
+    State(state): State<Arc<AppState>>,
+    request: Request<B>,
+    next: Next<B>,
+) -> Response {
+    let path = request.uri().path();
+    if !state.auth.protects(path) {
+        return next.run(request).await;
+    }
+
+    let authorization = request
+        .headers()
+        .get(header::AUTHORIZATION)
+        .and_then(|value| value.to_str().ok());
+    let key = api_key(&request).filter(|_| is_data_route(path));
+    let label = key.and_then(|key| state.auth.api_key_label(key));
+    let allowed = match (label, authorization) {
+        (Some(label), _) => {
+            Span::current().record("api_key", label.as_str());
+            true
+        }
+        (None, Some(authorization))
+            if state.auth.jwt.is_some()
+                && authorization.starts_with("Bearer ")
+                && key.is_some_and(is_jwt) =>
+        {
+            match state.auth.check_jwt(key.unwrap_or_default()).await {
+                Ok(subject) => {
+                    Span::current().record("jwt_subject", subject.as_str());
+                    true
+                }
+                Err(AppError::Unauthorized(reason)) => {
+                    warn!(path, "Rejected bearer token: {}", reason);
+                    return challenge(&state);
+                }
+                Err(e) => return e.into_response(),
+            }
+        }
+        (None, Some(authorization)) if authorization.starts_with("Basic ") => {
+            state.auth.check_basic(authorization).await
+        }
+        (None, Some(_)) => false,
+        (None, None) if request.headers().contains_key(API_KEY_HEADER) => false,
+        // Browsers ask for credentials only after a 401
+        (None, None) => {
+            debug!(path, "Asked for credentials");
+            return challenge(&state);
+        }
+    };
+    if !allowed {
+        warn!(path, "Rejected invalid credentials");
+        return challenge(&state);
+    }
+    next.run(request).await
+}
+
+/// A 401 asking for the credentials the server takes
+fn challenge(state: &AppState) -> Response {
+    let mut response =
+        AppError::Unauthorized("missing or invalid credentials".to_string()).into_response();
+    let scheme = if state.auth.config.basic.is_empty() {
+        HeaderValue::from_static("Bearer")
+    } else {
+        HeaderValue::from_static("Basic realm=\"rossby-vis\", charset=\"UTF-8\"")
+    };
+    response
+        .headers_mut()
+        .insert(header::WWW_AUTHENTICATE, scheme);
+    response
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn basic(credentials: &str) -> String {
+        format!("Basic {}", STANDARD.encode(credentials))
+    }
+
+    #[test]
+    fn test_parse_basic_user() {
+        let hash = bcrypt::hash("secret", 4).unwrap();
+        let user: BasicUser = format!("ops:{}", hash).parse().unwrap();
+        assert_eq!(user.username, "ops");
+        assert!(!format!("{:?}", user).contains(&hash));
+
+        assert!("ops".parse::<BasicUser>().is_err());
+        assert!(format!(":{}", hash).parse::<BasicUser>().is_err());
+        assert!("ops:secret".parse::<BasicUser>().is_err());
+    }
+
+    #[tokio::test]
+    async fn test_check_basic() {
+        let hash = bcrypt::hash("secret", 4).unwrap();
+        let auth = Authenticator::new(AuthConfig {
+            basic: vec![format!("ops:{}", hash).parse().unwrap()],
+            ..Default::default()
+        })
+        .unwrap();
+        assert!(auth.is_enabled());
+
+        assert!(auth.check_basic(&basic("ops:secret")).await);
+        assert_eq!(auth.verified().len(), 1);
+        // Remembered, and checked the same way the second time
+        assert!(auth.check_basic(&basic("ops:secret")).await);
+
+        assert!(!auth.check_basic(&basic("ops:wrong")).await);
+        assert!(!auth.check_basic(&basic("other:secret")).await);
+        assert!(!auth.check_basic("Bearer secret").await);
+        assert!(!auth.check_basic("Basic !!!").await);
+        assert_eq!(auth.verified().len(), 1);
+    }
+
+    #[test]
+    fn test_api_keys() {
+        let api_key: ApiKey = "partner = 0123456789abcdef".parse().unwrap();
+        assert_eq!(api_key.label, "partner");
+        assert!(!format!("{:?}", api_key).contains("0123456789abcdef"));
+        assert!("partner=short".parse::<ApiKey>().is_err());
+        assert!("=0123456789abcdef".parse::<ApiKey>().is_err());
+        assert!("0123456789abcdef".parse::<ApiKey>().is_err());
+
+        let config = AuthConfig {
+            api_keys: vec![api_key.clone()],
+            ..Default::default()
+        };
+        assert!(config.validate().is_ok());
+        let auth = Authenticator::new(config).unwrap();
+        assert_eq!(
+            auth.api_key_label("0123456789abcdef").as_deref(),
+            Some("partner")
+        );
+        assert_eq!(auth.api_key_label("0123456789abcdeF"), None);
+
+        // Keys alone protect the data and API routes only
+        assert!(auth.protects("/proxy/data"));
+        assert!(auth.protects("/api/sample"));
+        assert!(!auth.protects("/"));
+        assert!(!auth.protects("/health"));
+
+        let twice = AuthConfig {
+            api_keys: vec![api_key.clone(), "other=0123456789abcdef".parse().unwrap()],
+            ..Default::default()
+        };
+        assert!(twice.validate().is_err());
+    }
+
+    #[cfg(feature = "sqlite")]
+    #[tokio::test]
+    async fn test_issued_api_keys() {
+        let store = Store::open_in_memory().unwrap();
+        let config = AuthConfig {
+            api_keys: vec!["partner=0123456789abcdef".parse().unwrap()],
+            ..Default::default()
+        };
+        let mut auth = Authenticator::new(config.clone()).unwrap();
+        assert_eq!(auth.use_store(store.clone()).await.unwrap(), 0);
+
+        let first = auth.issue_api_key("station").await.unwrap();
+        let first_key = first.key.unwrap();
+        assert!(first_key.len() >= MIN_API_KEY_LEN);
+        assert_eq!(auth.api_key_label(&first_key).as_deref(), Some("station"));
+        // Issuing again replaces the key
+        let second_key = auth.issue_api_key("station").await.unwrap().key.unwrap();
+        assert!(auth.api_key_label(&first_key).is_none());
+        assert!(auth.issue_api_key("partner").await.is_err());
+        assert!(auth.issue_api_key("").await.is_err());
+
+        // Issued keys are accepted after a restart, and listed without keys
+        let mut restarted = Authenticator::new(config).unwrap();
+        assert_eq!(restarted.use_store(store).await.unwrap(), 1);
+        assert_eq!(
+            restarted.api_key_label(&second_key).as_deref(),
+            Some("station")
+        );
+        let issued = restarted.issued_api_keys().await.unwrap();
+        assert_eq!(issued.len(), 1);
+        assert_eq!(issued[0].key, None);
+
+        assert!(restarted.revoke_api_key("station").await.unwrap());
+        assert!(!restarted.revoke_api_key("station").await.unwrap());
+        assert!(restarted.api_key_label(&second_key).is_none());
+    }
+
+    #[cfg(feature = "sqlite")]
+    #[tokio::test]
+    async fn test_issued_keys_protect_the_data_routes() {
+        let mut auth = Authenticator::new(AuthConfig::default()).unwrap();
+        assert!(matches!(
+            auth.issue_api_key("station").await,
+            Err(AppError::NotFound(_))
+        ));
+        auth.use_store(Store::open_in_memory().unwrap())
+            .await
+            .unwrap();
+        assert!(!auth.is_enabled());
+        auth.issue_api_key("station").await.unwrap();
+        assert!(auth.is_enabled());
+        assert!(auth.protects("/proxy/data"));
+        assert!(!auth.protects("/"));
+    }
+
+    #[test]
+    fn test_load_api_keys() {
+        let path = std::env::temp_dir().join(format!("rossby-vis-keys-{}", uuid::Uuid::new_v4()));
+        std::fs::write(
+            &path,
+            "# partners\npartner-a=aaaaaaaaaaaaaaaa\n\npartner-b=bbbbbbbbbbbbbbbb\n",
+        )
+        .unwrap();
+        let keys = load_api_keys(&path).unwrap();
+        assert_eq!(keys.len(), 2);
+        assert_eq!(keys[1].label, "partner-b");
+
+        std::fs::write(&path, "partner-a=aaaaaaaaaaaaaaaa\nbroken\n").unwrap();
+        let error = load_api_keys(&path).unwrap_err().to_string();
+        assert!(error.contains(":2:"), "{}", error);
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn test_jwt_protects_the_data_routes() {
+        let auth = Authenticator::new(AuthConfig {
+            jwt: JwtConfig {
+                issuer: Some("https://sso.example.com".to_string()),
+                ..Default::default()
+            },
+            ..Default::default()
+        })
+        .unwrap();
+        assert!(auth.is_enabled());
+        assert!(auth.protects("/proxy/data"));
+        assert!(auth.protects("/data/weather/wind"));
+        assert!(!auth.protects("/"));
+        assert!(!auth.protects("/libs/earth/1.0.0/earth.js"));
+
+        assert!(is_jwt("eyJhbGciOiJFZERTQSJ9.e30.c2ln"));
+        assert!(!is_jwt("0123456789abcdef"));
+    }
+
+    #[test]
+    fn test_public_paths() {
+        assert!(is_public("/health"));
+        assert!(is_public("/healthz"));
+        assert!(is_public("/admin/overview"));
+        assert!(!is_public("/admin"));
+        assert!(!is_public("/"));
+        assert!(!is_public("/proxy/metadata"));
+    }
+}
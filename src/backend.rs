@@ -0,0 +1,394 @@
+//! Compatibility adapters for Rossby backend schema versions
+//!
+//! The rest of the proxy consumes the legacy Rossby schema described in
+//! `doc/design.md`: coordinates as plain arrays, dimensions as
+//! `{"size": n}` objects, variables keyed by name, and `/data` responses of
+//! the form `{"metadata": {"shape", "dimensions"}, "data": {var: [...]}}`.
+//!
+//! Newer Rossby servers (schema version 2) mark their documents with
+//! `"schema_version": 2`, describe coordinates as `{"values": [...]}`
+//! objects, dimensions as plain sizes and variables as a list of objects
+//! with a `name`. Their `/data` responses carry one
+//! `{"dimensions", "shape", "values"}` entry per variable under `variables`.
+//!
+//! Each schema has an adapter that translates its documents into the legacy
+//! form. The schema is either configured per backend or probed from the
+//! metadata document.
+
+use serde_json::{json, Map, Value};
+use std::{
+    fmt,
+    str::FromStr,
+    sync::{Arc, RwLock},
+};
+
+use crate::error::AppError;
+
+/// A Rossby metadata/data schema version
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SchemaVersion {
+    /// The original schema, used as the internal representation
+    Legacy,
+    /// The versioned schema with list-style variables
+    V2,
+}
+
+impl fmt::Display for SchemaVersion {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SchemaVersion::Legacy => write!(f, "legacy"),
+            SchemaVersion::V2 => write!(f, "v2"),
+        }
+    }
+}
+
+/// How the schema of a backend is chosen
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum BackendSchema {
+    /// Probe the schema from each metadata document
+    #[default]
+    Auto,
+    /// Always use the given schema
+    Fixed(SchemaVersion),
+}
+
+impl FromStr for BackendSchema {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "auto" => Ok(BackendSchema::Auto),
+            "legacy" | "v1" => Ok(BackendSchema::Fixed(SchemaVersion::Legacy)),
+            "v2" => Ok(BackendSchema::Fixed(SchemaVersion::V2)),
+            _ => Err(format!(
+                "Invalid backend schema: {}. Valid options: auto, legacy, v2",
+                s
+            )),
+        }
+    }
+}
+
+/// Translates one schema version into the legacy representation
+pub trait SchemaAdapter: Send + Sync {
+    /// Schema version handled by this adapter
+    fn version(&self) -> SchemaVersion;
+
+    /// Whether a metadata document is written in this schema
+    fn detect_metadata(&self, metadata: &Value) -> bool;
+
+    /// Whether a `/data` response is written in this schema
+    fn detect_data(&self, data: &Value) -> bool;
+
+    /// Convert a metadata document to the legacy schema
+    fn metadata(&self, metadata: Value) -> Result<Value, AppError>;
+
+    /// Convert a `/data` response to the legacy schema
+    fn data(&self, data: Value) -> Result<Value, AppError>;
+}
+
+/// Adapter for the legacy schema, which passes documents through unchanged
+#[derive(Debug, Clone, Copy, Default)]
+pub struct LegacyAdapter;
+
+impl SchemaAdapter for LegacyAdapter {
+    fn version(&self) -> SchemaVersion {
+        SchemaVersion::Legacy
+    }
+
+    fn detect_metadata(&self, metadata: &Value) -> bool {
+        metadata.get("variables").is_some_and(Value::is_object)
+    }
+
+    fn detect_data(&self, data: &Value) -> bool {
+        data.get("data").is_some_and(Value::is_object)
+    }
+
+    fn metadata(&self, metadata: Value) -> Result<Value, AppError> {
+        Ok(metadata)
+    }
+
+    fn data(&self, data: Value) -> Result<Value, AppError> {
+        Ok(data)
+    }
+}
+
+/// Adapter for schema version 2
+#[derive(Debug, Clone, Copy, Default)]
+pub struct V2Adapter;
+
+impl SchemaAdapter for V2Adapter {
+    fn version(&self) -> SchemaVersion {
+        SchemaVersion::V2
+    }
+
+    fn detect_metadata(&self, metadata: &Value) -> bool {
+        metadata
+            .get("schema_version")
+            .and_then(Value::as_u64)
+            .is_some_and(|version| version >= 2)
+            || metadata.get("variables").is_some_and(Value::is_array)
+    }
+
+    fn detect_data(&self, data: &Value) -> bool {
+        data.get("data").is_none() && data.get("variables").is_some_and(Value::is_object)
+    }
+
+    fn metadata(&self, metadata: Value) -> Result<Value, AppError> {
+        let Value::Object(mut root) = metadata else {
+            return Err(AppError::ProxyError(
+                "Backend metadata must be a JSON object".to_string(),
+            ));
+        };
+
+        if let Some(Value::Object(coordinates)) = root.get_mut("coordinates") {
+            for values in coordinates.values_mut() {
+                if let Some(array) = values.get_mut("values").map(Value::take) {
+                    *values = array;
+                }
+            }
+        }
+
+        if let Some(Value::Object(dimensions)) = root.get_mut("dimensions") {
+            for (name, dimension) in dimensions.iter_mut() {
+                if let Some(size) = dimension.as_u64() {
+                    *dimension = json!({"name": name, "size": size});
+                }
+            }
+        }
+
+        if let Some(Value::Array(list)) = root.remove("variables") {
+            let mut variables = Map::new();
+            for (index, variable) in list.into_iter().enumerate() {
+                let name = variable
+                    .get("name")
+                    .and_then(Value::as_str)
+                    .ok_or_else(|| {
+                        AppError::ProxyError(format!(
+                            "Backend metadata variable {} has no name",
+                            index
+                        ))
+                    })?
+                    .to_string();
+                variables.insert(name, variable);
+            }
+            root.insert("variables".to_string(), Value::Object(variables));
+        }
+
+        if let Some(attributes) = root.remove("attributes") {
+            root.entry("global_attributes").or_insert(attributes);
+        }
+
+        Ok(Value::Object(root))
+    }
+
+    fn data(&self, data: Value) -> Result<Value, AppError> {
+        let variables = data
+            .get("variables")
+            .and_then(Value::as_object)
+            .ok_or_else(|| AppError::ProxyError("Backend data has no variables".to_string()))?;
+
+        let mut layout: Option<(&Value, &Value)> = None;
+        let mut values = Map::new();
+        let mut attributes = Map::new();
+        for (name, variable) in variables {
+            let dimensions = variable.get("dimensions").unwrap_or(&Value::Null);
+            let shape = variable.get("shape").unwrap_or(&Value::Null);
+            match layout {
+                Some(existing) if existing != (dimensions, shape) => {
+                    return Err(AppError::ProxyError(format!(
+                        "Backend data variable '{}' has a different shape from the others",
+                        name
+                    )));
+                }
+                Some(_) => {}
+                None => layout = Some((dimensions, shape)),
+            }
+
+            values.insert(
+                name.clone(),
+                variable.get("values").cloned().unwrap_or(json!([])),
+            );
+            let mut variable_attributes = Map::new();
+            for key in ["units", "long_name"] {
+                if let Some(value) = variable.get(key) {
+                    variable_attributes.insert(key.to_string(), value.clone());
+                }
+            }
+            attributes.insert(name.clone(), Value::Object(variable_attributes));
+        }
+
+        let (dimensions, shape) = layout.unwrap_or((&Value::Null, &Value::Null));
+        Ok(json!({
+            "metadata": {
+                "query": data.get("query").cloned().unwrap_or(Value::Null),
+                "shape": shape,
+                "dimensions": dimensions,
+                "variables": attributes,
+            },
+            "data": values,
+        }))
+    }
+}
+
+/// Adapter for a schema version
+pub fn adapter(version: SchemaVersion) -> &'static dyn SchemaAdapter {
+    match version {
+        SchemaVersion::Legacy => &LegacyAdapter,
+        SchemaVersion::V2 => &V2Adapter,
+    }
+}
+
+/// Probe the schema version of a metadata document, defaulting to legacy
+pub fn detect_schema(metadata: &Value) -> SchemaVersion {
+    [adapter(SchemaVersion::V2), adapter(SchemaVersion::Legacy)]
+        .into_iter()
+        .find(|adapter| adapter.detect_metadata(metadata))
+        .map(|adapter| adapter.version())
+        .unwrap_or(SchemaVersion::Legacy)
+}
+
+/// Per-backend schema handling, remembering the version probed in auto mode
+#[derive(Debug, Clone, Default)]
+pub struct BackendCompat {
+    schema: BackendSchema,
+    detected: Arc<RwLock<Option<SchemaVersion>>>,
+}
+
+impl BackendCompat {
+    /// Create schema handling for a backend
+    pub fn new(schema: BackendSchema) -> Self {
+        Self {
+            schema,
+            detected: Arc::default(),
+        }
+    }
+
+    /// The configured or most recently probed schema version, if known
+    pub fn version(&self) -> Option<SchemaVersion> {
+        match self.schema {
+            BackendSchema::Fixed(version) => Some(version),
+            BackendSchema::Auto => *self.detected.read().unwrap_or_else(|e| e.into_inner()),
+        }
+    }
+
+    /// Whether `/data` responses can be streamed to clients without translation
+    pub fn passes_data_through(&self) -> bool {
+        self.version() != Some(SchemaVersion::V2)
+    }
+
+    /// Convert a metadata document to the legacy schema
+    pub fn normalize_metadata(&self, metadata: Value) -> Result<Value, AppError> {
+        let version = match self.schema {
+            BackendSchema::Fixed(version) => version,
+            BackendSchema::Auto => {
+                let version = detect_schema(&metadata);
+                *self.detected.write().unwrap_or_else(|e| e.into_inner()) = Some(version);
+                version
+            }
+        };
+        adapter(version).metadata(metadata)
+    }
+
+    /// Convert a `/data` response to the legacy schema
+    pub fn normalize_data(&self, data: Value) -> Result<Value, AppError> {
+        let version = match self.schema {
+            BackendSchema::Fixed(version) => version,
+            BackendSchema::Auto if V2Adapter.detect_data(&data) => SchemaVersion::V2,
+            BackendSchema::Auto => SchemaVersion::Legacy,
+        };
+        adapter(version).data(data)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn v2_metadata() -> Value {
+        json!({
+            "schema_version": 2,
+            "attributes": {"Conventions": "CF-1.8"},
+            "coordinates": {
+                "latitude": {"values": [10.0, 0.0], "units": "degrees_north"},
+                "longitude": {"values": [0.0, 10.0], "units": "degrees_east"}
+            },
+            "dimensions": {"latitude": 2, "longitude": 2},
+            "variables": [
+                {"name": "t2m", "dimensions": ["latitude", "longitude"], "attributes": {"units": "K"}}
+            ]
+        })
+    }
+
+    #[test]
+    fn test_schema_detection() {
+        assert_eq!(detect_schema(&v2_metadata()), SchemaVersion::V2);
+        assert_eq!(
+            detect_schema(&json!({"variables": {"t2m": {}}})),
+            SchemaVersion::Legacy
+        );
+        assert_eq!(detect_schema(&json!({})), SchemaVersion::Legacy);
+    }
+
+    #[test]
+    fn test_v2_metadata_conversion() {
+        let metadata = V2Adapter.metadata(v2_metadata()).unwrap();
+
+        assert_eq!(metadata["coordinates"]["latitude"], json!([10.0, 0.0]));
+        assert_eq!(metadata["dimensions"]["longitude"]["size"], 2);
+        assert_eq!(metadata["variables"]["t2m"]["attributes"]["units"], "K");
+        assert_eq!(metadata["global_attributes"]["Conventions"], "CF-1.8");
+        assert!(crate::metadata::validate_metadata(&metadata).is_empty());
+    }
+
+    #[test]
+    fn test_v2_data_conversion() {
+        let data = json!({
+            "schema_version": 2,
+            "query": {"vars": "u10,v10"},
+            "variables": {
+                "u10": {"dimensions": ["latitude", "longitude"], "shape": [1, 2], "values": [1.0, null], "units": "m s**-1"},
+                "v10": {"dimensions": ["latitude", "longitude"], "shape": [1, 2], "values": [3.0, 4.0]}
+            }
+        });
+        assert!(V2Adapter.detect_data(&data));
+
+        let converted = V2Adapter.data(data).unwrap();
+        assert_eq!(converted["metadata"]["shape"], json!([1, 2]));
+        assert_eq!(
+            converted["metadata"]["variables"]["u10"]["units"],
+            "m s**-1"
+        );
+        assert_eq!(converted["data"]["u10"], json!([1.0, null]));
+        assert!(LegacyAdapter.detect_data(&converted));
+    }
+
+    #[test]
+    fn test_v2_data_shape_mismatch() {
+        let data = json!({"variables": {
+            "a": {"dimensions": ["x"], "shape": [2], "values": [1.0, 2.0]},
+            "b": {"dimensions": ["x"], "shape": [3], "values": [1.0, 2.0, 3.0]}
+        }});
+        assert!(V2Adapter.data(data).is_err());
+    }
+
+    #[test]
+    fn test_backend_compat_probes_in_auto_mode() {
+        let compat = BackendCompat::new(BackendSchema::Auto);
+        assert_eq!(compat.version(), None);
+        assert!(compat.passes_data_through());
+
+        compat.normalize_metadata(v2_metadata()).unwrap();
+        assert_eq!(compat.version(), Some(SchemaVersion::V2));
+        assert!(!compat.passes_data_through());
+
+        let legacy = json!({"metadata": {}, "data": {"t2m": [1.0]}});
+        assert_eq!(compat.normalize_data(legacy.clone()).unwrap(), legacy);
+    }
+
+    #[test]
+    fn test_backend_schema_from_str() {
+        assert_eq!("auto".parse(), Ok(BackendSchema::Auto));
+        assert_eq!("V2".parse(), Ok(BackendSchema::Fixed(SchemaVersion::V2)));
+        assert!("v3".parse::<BackendSchema>().is_err());
+    }
+}
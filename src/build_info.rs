@@ -0,0 +1,61 @@
+//! Which build is running, for `/api/version`
+//!
+//! The commit, build time and cargo features are recorded by `build.rs`;
+//! the frontend bundle version is a hash of the embedded assets.
+
+use chrono::{DateTime, TimeZone, Utc};
+use serde::Serialize;
+use utoipa::ToSchema;
+
+use crate::embed::bundle_version;
+
+/// Details of the running build
+#[derive(Debug, Clone, Serialize, ToSchema)]
+pub struct BuildInfo {
+    pub service: &'static str,
+    /// Crate version from `Cargo.toml`
+    pub version: &'static str,
+    /// Commit the binary was built from, or `unknown` outside a git checkout
+    pub git_sha: &'static str,
+    /// When the binary was built, or `SOURCE_DATE_EPOCH` when that is set
+    pub built_at: Option<DateTime<Utc>>,
+    /// Enabled cargo features, sorted
+    pub features: Vec<&'static str>,
+    /// Short hash of every embedded frontend file, which changes whenever
+    /// any of them does
+    pub assets_version: String,
+}
+
+/// Details of the running build
+pub fn build_info() -> BuildInfo {
+    BuildInfo {
+        service: "rossby-vis",
+        version: env!("CARGO_PKG_VERSION"),
+        git_sha: env!("ROSSBY_BUILD_GIT_SHA"),
+        built_at: env!("ROSSBY_BUILD_EPOCH")
+            .parse()
+            .ok()
+            .and_then(|secs| Utc.timestamp_opt(secs, 0).single()),
+        features: env!("ROSSBY_BUILD_FEATURES")
+            .split(',')
+            .filter(|feature| !feature.is_empty())
+            .collect(),
+        assets_version: bundle_version().to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_build_info() {
+        let info = build_info();
+        assert_eq!(info.version, env!("CARGO_PKG_VERSION"));
+        assert!(!info.git_sha.is_empty());
+        assert!(info.built_at.is_some());
+        assert_eq!(info.features.contains(&"sqlite"), cfg!(feature = "sqlite"));
+        assert!(info.features.windows(2).all(|pair| pair[0] < pair[1]));
+        assert_eq!(info.assets_version.len(), 12);
+    }
+}
@@ -0,0 +1,1204 @@
+//! Cache of backend responses
+//!
+//! Every browser tab showing the globe asks for the same metadata and the
+//! same fields, and each of those requests used to reach the backend. With
+//! `--cache-size` set, backend bodies fetched for `/proxy/metadata`,
+//! `/proxy/data` and the Earth data routes are kept in memory and served
+//! again for `--cache-ttl` seconds.
+//!
+//! Entries are the raw backend bodies, keyed by the backend URL with its
+//! query parameters sorted, so requests differing only in the order of
+//! their parameters share an entry. When the cache grows past its size,
+//! the least recently used entries are dropped; bodies larger than the
+//! whole cache are not kept. Error responses are never cached.
+//!
+//! With `--disk-cache-dir`, a second tier below the memory keeps bodies in
+//! files named by the hash of their key, bounded by `--disk-cache-size` and
+//! served for `--disk-cache-ttl` seconds. It takes the large Earth fields
+//! that would crowd the memory out, and survives restarts: the directory is
+//! read back on startup, dropping expired and half-written files. Bodies
+//! found on disk are brought back into memory.
+//!
+//! With the circuit breaker enabled, expired entries are kept until they are
+//! evicted, so they can stand in for the backend while its circuit is open.
+//!
+//! `GET /admin/cache` reports the size and hit rate of both tiers, and
+//! `DELETE /admin/cache` drops entries matching a [`CacheFilter`]. The key of
+//! each file on disk is kept next to it, or in the database with
+//! `--database` (see [`crate::store`]), so filters apply to entries read back
+//! after a restart too.
+
+use axum::body::Bytes;
+use lru::LruCache;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::{
+    path::{Path, PathBuf},
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Mutex,
+    },
+    time::{Duration, Instant, SystemTime},
+};
+use tracing::{info, warn};
+use utoipa::{IntoParams, ToSchema};
+
+use crate::error::AppError;
+#[cfg(feature = "sqlite")]
+use crate::store::Store;
+
+/// How long cached responses are served by default
+pub const DEFAULT_CACHE_TTL: Duration = Duration::from_secs(60);
+/// How long responses on disk are served by default
+pub const DEFAULT_DISK_CACHE_TTL: Duration = Duration::from_secs(3600);
+
+/// Extension of cached bodies in the disk cache directory
+const BODY_EXTENSION: &str = "body";
+/// Extension of bodies still being written
+const TEMP_EXTENSION: &str = "tmp";
+/// Extension of the files holding the key of a cached body
+const KEY_EXTENSION: &str = "key";
+/// Query parameters naming the variables of a backend request
+const VARIABLE_PARAMETERS: [&str; 3] = ["vars", "var", "variable"];
+
+/// Size and lifetime of cached responses
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ResponseCacheConfig {
+    /// Total size of the bodies in memory in bytes, 0 disables the memory tier
+    pub max_bytes: usize,
+    /// How long a response is served from memory
+    pub ttl: Duration,
+    /// Files keeping responses across restarts
+    pub disk: Option<DiskCacheConfig>,
+}
+
+impl Default for ResponseCacheConfig {
+    fn default() -> Self {
+        Self {
+            max_bytes: 0,
+            ttl: DEFAULT_CACHE_TTL,
+            disk: None,
+        }
+    }
+}
+
+/// Location, size and lifetime of the disk tier
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DiskCacheConfig {
+    pub dir: PathBuf,
+    /// Total size of the cached files in bytes
+    pub max_bytes: u64,
+    /// How long a response is served from disk
+    pub ttl: Duration,
+}
+
+/// Backend response bodies by normalized URL, in memory and optionally on
+/// disk
+#[derive(Default)]
+pub struct ResponseCache {
+    memory: MemoryCache,
+    disk: Option<DiskCache>,
+    /// Keep expired entries for [`ResponseCache::get_stale`]
+    keep_stale: bool,
+    /// Lookups answered from either tier
+    hits: AtomicU64,
+    /// Lookups finding no fresh entry
+    misses: AtomicU64,
+}
+
+/// Entries to drop from the cache; a filter without criteria matches all
+#[derive(Debug, Clone, Default, PartialEq, Eq, Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct CacheFilter {
+    /// Pattern of normalized backend URLs, where `*` matches any text
+    pub pattern: Option<String>,
+    /// Entries requesting this variable, through `vars`, `var` or `variable`
+    pub variable: Option<String>,
+}
+
+impl CacheFilter {
+    /// Whether the filter matches every entry
+    pub fn is_empty(&self) -> bool {
+        self.pattern.is_none() && self.variable.is_none()
+    }
+
+    /// Whether the entry cached under `key` matches every criterion
+    pub fn matches(&self, key: &str) -> bool {
+        if let Some(pattern) = &self.pattern {
+            if !wildcard_match(pattern, key) {
+                return false;
+            }
+        }
+        match &self.variable {
+            Some(variable) => requested_variables(key).any(|name| name == *variable),
+            None => true,
+        }
+    }
+}
+
+/// Size and use of the cache, returned by `GET /admin/cache`
+#[derive(Debug, Clone, PartialEq, Serialize, ToSchema)]
+pub struct CacheStats {
+    pub enabled: bool,
+    pub hits: u64,
+    pub misses: u64,
+    /// Share of lookups answered from the cache, before any lookup `None`
+    pub hit_rate: Option<f64>,
+    pub memory: TierStats,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub disk: Option<TierStats>,
+}
+
+/// Entries of one cache tier and its limits
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, ToSchema)]
+pub struct TierStats {
+    pub entries: usize,
+    pub bytes: u64,
+    pub max_bytes: u64,
+    pub ttl_secs: u64,
+}
+
+/// Entries dropped by [`ResponseCache::purge`]
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, ToSchema)]
+pub struct PurgeCounts {
+    pub memory: usize,
+    pub disk: usize,
+}
+
+impl ResponseCache {
+    /// Set up the cache, reading back the entries of the disk tier
+    pub fn new(config: ResponseCacheConfig) -> Result<Self, AppError> {
+        Self::open(config, KeyStorage::Files)
+    }
+
+    /// Set up the cache, keeping the keys of the disk tier in `store`
+    #[cfg(feature = "sqlite")]
+    pub fn with_store(config: ResponseCacheConfig, store: &Store) -> Result<Self, AppError> {
+        Self::open(config, KeyStorage::Store(store.clone()))
+    }
+
+    fn open(config: ResponseCacheConfig, keys: KeyStorage) -> Result<Self, AppError> {
+        Ok(Self {
+            memory: MemoryCache::new(config.max_bytes, config.ttl),
+            disk: config
+                .disk
+                .map(|disk| DiskCache::open(disk, keys))
+                .transpose()?,
+            keep_stale: false,
+            hits: AtomicU64::new(0),
+            misses: AtomicU64::new(0),
+        })
+    }
+
+    /// Keep expired entries until they are evicted instead of dropping them
+    /// on lookup
+    pub fn keeping_stale(mut self) -> Self {
+        self.keep_stale = true;
+        self
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.memory.max_bytes > 0 || self.disk.is_some()
+    }
+
+    /// Size of the largest body either tier keeps
+    pub fn max_bytes(&self) -> usize {
+        let disk = self.disk.as_ref().map_or(0, |disk| disk.config.max_bytes);
+        self.memory
+            .max_bytes
+            .max(usize::try_from(disk).unwrap_or(usize::MAX))
+    }
+
+    /// The cached body of `url`, unless it is missing or expired
+    pub async fn get(&self, url: &str) -> Option<Bytes> {
+        if !self.is_enabled() {
+            return None;
+        }
+        let body = self.lookup(url, false).await;
+        let counter = if body.is_some() {
+            &self.hits
+        } else {
+            &self.misses
+        };
+        counter.fetch_add(1, Ordering::Relaxed);
+        body
+    }
+
+    /// The cached body of `url` even when it expired, as long as it was
+    /// kept
+    pub async fn get_stale(&self, url: &str) -> Option<Bytes> {
+        if !self.is_enabled() || !self.keep_stale {
+            return None;
+        }
+        self.lookup(url, true).await
+    }
+
+    async fn lookup(&self, url: &str, stale: bool) -> Option<Bytes> {
+        let key = normalize_url(url);
+        if let Some(body) = self.memory.get(&key, stale, self.keep_stale) {
+            return Some(body);
+        }
+        let body = self
+            .disk
+            .as_ref()?
+            .get(&key, stale, self.keep_stale)
+            .await?;
+        if !stale {
+            self.memory.insert(key, body.clone());
+        }
+        Some(body)
+    }
+
+    /// Keep `body` as the response of `url` in each tier with room for it
+    pub async fn insert(&self, url: &str, body: Bytes) {
+        if !self.is_enabled() {
+            return;
+        }
+        let key = normalize_url(url);
+        if let Some(disk) = &self.disk {
+            disk.insert(&key, &body).await;
+        }
+        self.memory.insert(key, body);
+    }
+
+    /// Number of responses in memory
+    pub fn len(&self) -> usize {
+        self.memory.entries().lru.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Total size of the bodies in memory in bytes
+    pub fn size_bytes(&self) -> usize {
+        self.memory.entries().bytes
+    }
+
+    /// Number of responses on disk and their total size in bytes
+    pub fn disk_usage(&self) -> Option<(usize, u64)> {
+        self.disk.as_ref().map(|disk| {
+            let index = disk.index();
+            (index.lru.len(), index.bytes)
+        })
+    }
+
+    /// Drop every cached response, from memory and disk
+    pub async fn clear(&self) {
+        self.memory.clear();
+        if let Some(disk) = &self.disk {
+            disk.clear().await;
+        }
+    }
+
+    /// Drop the responses matching `filter` from both tiers
+    pub async fn purge(&self, filter: &CacheFilter) -> PurgeCounts {
+        if filter.is_empty() {
+            let counts = PurgeCounts {
+                memory: self.len(),
+                disk: self.disk_usage().map_or(0, |(entries, _)| entries),
+            };
+            self.clear().await;
+            return counts;
+        }
+        PurgeCounts {
+            memory: self.memory.purge(filter),
+            disk: match &self.disk {
+                Some(disk) => disk.purge(filter).await,
+                None => 0,
+            },
+        }
+    }
+
+    /// Serve entries for `memory` and `disk` from now on, counted from when
+    /// they were stored; entries already expired are not brought back
+    pub fn set_ttls(&self, memory: Duration, disk: Duration) {
+        self.memory.ttl.set(memory);
+        if let Some(cache) = &self.disk {
+            cache.ttl.set(disk);
+        }
+    }
+
+    /// How long entries are served from memory and from disk, if there is
+    /// a disk tier
+    pub fn ttls(&self) -> (Duration, Option<Duration>) {
+        (
+            self.memory.ttl.get(),
+            self.disk.as_ref().map(|disk| disk.ttl.get()),
+        )
+    }
+
+    /// Entries, sizes and hit rate of both tiers
+    pub fn stats(&self) -> CacheStats {
+        let hits = self.hits.load(Ordering::Relaxed);
+        let misses = self.misses.load(Ordering::Relaxed);
+        let lookups = hits + misses;
+        CacheStats {
+            enabled: self.is_enabled(),
+            hits,
+            misses,
+            hit_rate: (lookups > 0).then(|| hits as f64 / lookups as f64),
+            memory: TierStats {
+                entries: self.len(),
+                bytes: self.size_bytes() as u64,
+                max_bytes: self.memory.max_bytes as u64,
+                ttl_secs: self.memory.ttl.get().as_secs(),
+            },
+            disk: self.disk.as_ref().map(|disk| {
+                let index = disk.index();
+                TierStats {
+                    entries: index.lru.len(),
+                    bytes: index.bytes,
+                    max_bytes: disk.config.max_bytes,
+                    ttl_secs: disk.ttl.get().as_secs(),
+                }
+            }),
+        }
+    }
+}
+
+/// Lifetime of the entries of a tier, changed when the configuration is
+/// reloaded
+struct Ttl(AtomicU64);
+
+impl Ttl {
+    fn new(ttl: Duration) -> Self {
+        Self(AtomicU64::new(ttl.as_millis() as u64))
+    }
+
+    fn get(&self) -> Duration {
+        Duration::from_millis(self.0.load(Ordering::Relaxed))
+    }
+
+    fn set(&self, ttl: Duration) {
+        self.0.store(ttl.as_millis() as u64, Ordering::Relaxed);
+    }
+}
+
+struct MemoryEntry {
+    body: Bytes,
+    stored: Instant,
+}
+
+struct MemoryEntries {
+    lru: LruCache<String, MemoryEntry>,
+    /// Total size of the cached bodies
+    bytes: usize,
+}
+
+impl MemoryEntries {
+    fn remove(&mut self, key: &str) {
+        if let Some(entry) = self.lru.pop(key) {
+            self.bytes -= entry.body.len();
+        }
+    }
+}
+
+/// The memory tier
+struct MemoryCache {
+    max_bytes: usize,
+    ttl: Ttl,
+    entries: Mutex<MemoryEntries>,
+}
+
+impl Default for MemoryCache {
+    fn default() -> Self {
+        Self::new(0, DEFAULT_CACHE_TTL)
+    }
+}
+
+impl MemoryCache {
+    fn new(max_bytes: usize, ttl: Duration) -> Self {
+        Self {
+            max_bytes,
+            ttl: Ttl::new(ttl),
+            entries: Mutex::new(MemoryEntries {
+                lru: LruCache::unbounded(),
+                bytes: 0,
+            }),
+        }
+    }
+
+    fn entries(&self) -> std::sync::MutexGuard<'_, MemoryEntries> {
+        self.entries.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    /// The body of `key`, expired or not when `stale`; expired entries are
+    /// dropped unless `keep`
+    fn get(&self, key: &str, stale: bool, keep: bool) -> Option<Bytes> {
+        let mut entries = self.entries();
+        match entries.lru.get(key) {
+            Some(entry) if stale || entry.stored.elapsed() < self.ttl.get() => {
+                Some(entry.body.clone())
+            }
+            Some(_) if keep => None,
+            Some(_) => {
+                entries.remove(key);
+                None
+            }
+            None => None,
+        }
+    }
+
+    /// Keep `body`, dropping the least recently used entries to make room
+    fn insert(&self, key: String, body: Bytes) {
+        if body.len() > self.max_bytes {
+            return;
+        }
+        let mut entries = self.entries();
+        entries.remove(&key);
+        entries.bytes += body.len();
+        entries.lru.put(
+            key,
+            MemoryEntry {
+                body,
+                stored: Instant::now(),
+            },
+        );
+        while entries.bytes > self.max_bytes {
+            match entries.lru.pop_lru() {
+                Some((_, evicted)) => entries.bytes -= evicted.body.len(),
+                None => break,
+            }
+        }
+    }
+
+    fn clear(&self) {
+        let mut entries = self.entries();
+        entries.lru.clear();
+        entries.bytes = 0;
+    }
+
+    /// Drop the entries matching `filter`, returning how many
+    fn purge(&self, filter: &CacheFilter) -> usize {
+        let mut entries = self.entries();
+        let keys: Vec<String> = entries
+            .lru
+            .iter()
+            .filter(|(key, _)| filter.matches(key))
+            .map(|(key, _)| key.clone())
+            .collect();
+        for key in &keys {
+            entries.remove(key);
+        }
+        keys.len()
+    }
+}
+
+struct DiskEntry {
+    size: u64,
+    stored: SystemTime,
+    /// Key of the body, unless its key file is missing
+    key: Option<String>,
+}
+
+impl DiskEntry {
+    fn is_expired(&self, ttl: Duration) -> bool {
+        self.stored.elapsed().map_or(true, |age| age >= ttl)
+    }
+}
+
+/// What the disk tier holds, by file stem
+struct DiskIndex {
+    lru: LruCache<String, DiskEntry>,
+    /// Total size of the cached files
+    bytes: u64,
+}
+
+impl DiskIndex {
+    fn remove(&mut self, name: &str) {
+        if let Some(entry) = self.lru.pop(name) {
+            self.bytes -= entry.size;
+        }
+    }
+
+    /// Names of the least recently used entries dropped to fit `max_bytes`
+    fn evict(&mut self, max_bytes: u64) -> Vec<String> {
+        let mut evicted = Vec::new();
+        while self.bytes > max_bytes {
+            match self.lru.pop_lru() {
+                Some((name, entry)) => {
+                    self.bytes -= entry.size;
+                    evicted.push(name);
+                }
+                None => break,
+            }
+        }
+        evicted
+    }
+}
+
+/// Where the disk tier keeps the key of each body
+enum KeyStorage {
+    /// A file next to the body
+    Files,
+    /// The `disk_cache` table of the database
+    #[cfg(feature = "sqlite")]
+    Store(Store),
+}
+
+impl KeyStorage {
+    /// Keys of the bodies by file stem, from the database
+    fn stored(&self) -> Result<std::collections::HashMap<String, String>, AppError> {
+        match self {
+            KeyStorage::Files => Ok(Default::default()),
+            #[cfg(feature = "sqlite")]
+            KeyStorage::Store(store) => store.call_blocking(|connection| {
+                let mut statement = connection.prepare("SELECT name, key FROM disk_cache")?;
+                let keys = statement
+                    .query_map([], |row| Ok((row.get(0)?, row.get(1)?)))?
+                    .collect();
+                keys
+            }),
+        }
+    }
+
+    async fn put(&self, dir: &Path, name: &str, key: &str) -> Result<(), AppError> {
+        match self {
+            KeyStorage::Files => Ok(tokio::fs::write(key_path(dir, name), key).await?),
+            #[cfg(feature = "sqlite")]
+            KeyStorage::Store(store) => {
+                let (name, key) = (name.to_string(), key.to_string());
+                store
+                    .call(move |connection| {
+                        connection.execute(
+                            "INSERT INTO disk_cache (name, key) VALUES (?1, ?2)
+                             ON CONFLICT (name) DO UPDATE SET key = excluded.key",
+                            rusqlite::params![name, key],
+                        )
+                    })
+                    .await
+                    .map(|_| ())
+            }
+        }
+    }
+
+    async fn remove(&self, dir: &Path, name: &str) {
+        match self {
+            KeyStorage::Files => {
+                let _ = tokio::fs::remove_file(key_path(dir, name)).await;
+            }
+            #[cfg(feature = "sqlite")]
+            KeyStorage::Store(store) => {
+                let name = name.to_string();
+                let _ = store
+                    .call(move |connection| {
+                        connection.execute("DELETE FROM disk_cache WHERE name = ?1", [name])
+                    })
+                    .await;
+            }
+        }
+    }
+}
+
+/// The disk tier
+struct DiskCache {
+    config: DiskCacheConfig,
+    /// `config.ttl` until the configuration is reloaded
+    ttl: Ttl,
+    index: Mutex<DiskIndex>,
+    keys: KeyStorage,
+}
+
+impl DiskCache {
+    /// Index the files left in the directory, removing expired and
+    /// half-written ones and any beyond the size limit
+    fn open(config: DiskCacheConfig, keys: KeyStorage) -> Result<Self, AppError> {
+        let unreadable = |e: std::io::Error| {
+            AppError::ConfigError(format!(
+                "Cannot use disk cache directory {}: {}",
+                config.dir.display(),
+                e
+            ))
+        };
+        std::fs::create_dir_all(&config.dir).map_err(unreadable)?;
+
+        let mut stored = keys.stored()?;
+        let mut found = Vec::new();
+        let mut stale = 0;
+        for file in std::fs::read_dir(&config.dir).map_err(unreadable)? {
+            let path = file.map_err(unreadable)?.path();
+            let extension = path.extension().and_then(|e| e.to_str());
+            let entry = match (extension, path.file_stem().and_then(|s| s.to_str())) {
+                (Some(BODY_EXTENSION), Some(name)) => std::fs::metadata(&path)
+                    .and_then(|metadata| {
+                        Ok(DiskEntry {
+                            size: metadata.len(),
+                            stored: metadata.modified()?,
+                            // Key files are still read when the database
+                            // is new, and moved into it below
+                            key: stored.remove(name).or_else(|| {
+                                std::fs::read_to_string(key_path(&config.dir, name)).ok()
+                            }),
+                        })
+                    })
+                    .ok()
+                    .map(|entry| (name.to_string(), entry)),
+                (Some(TEMP_EXTENSION), _) => None,
+                _ => continue,
+            };
+            match entry {
+                Some((name, entry)) if !entry.is_expired(config.ttl) => found.push((name, entry)),
+                _ => {
+                    stale += 1;
+                    let _ = std::fs::remove_file(&path);
+                }
+            }
+        }
+
+        // Files written last are the most recently used
+        found.sort_by_key(|(_, entry)| entry.stored);
+        let mut index = DiskIndex {
+            lru: LruCache::unbounded(),
+            bytes: 0,
+        };
+        for (name, entry) in found {
+            index.bytes += entry.size;
+            index.lru.put(name, entry);
+        }
+        let evicted = index.evict(config.max_bytes);
+        for name in &evicted {
+            let _ = std::fs::remove_file(body_path(&config.dir, name));
+        }
+        #[cfg(feature = "sqlite")]
+        if let KeyStorage::Store(store) = &keys {
+            store.call_blocking(|connection| {
+                let transaction = connection.transaction()?;
+                transaction.execute("DELETE FROM disk_cache", [])?;
+                for (name, entry) in index.lru.iter() {
+                    if let Some(key) = &entry.key {
+                        transaction.execute(
+                            "INSERT INTO disk_cache (name, key) VALUES (?1, ?2)",
+                            [name, key],
+                        )?;
+                    }
+                }
+                transaction.commit()
+            })?;
+        }
+        // Keys of bodies removed above, or lost to a crash, and any kept in
+        // the database
+        let key_files = matches!(keys, KeyStorage::Files);
+        for file in std::fs::read_dir(&config.dir).map_err(unreadable)? {
+            let path = file.map_err(unreadable)?.path();
+            if path.extension().and_then(|e| e.to_str()) == Some(KEY_EXTENSION)
+                && !(key_files
+                    && path
+                        .file_stem()
+                        .and_then(|s| s.to_str())
+                        .is_some_and(|name| index.lru.contains(name)))
+            {
+                let _ = std::fs::remove_file(&path);
+            }
+        }
+        info!(
+            "Disk cache in {}: {} responses, {} bytes, {} stale and {} over the size limit removed",
+            config.dir.display(),
+            index.lru.len(),
+            index.bytes,
+            stale,
+            evicted.len()
+        );
+
+        Ok(Self {
+            ttl: Ttl::new(config.ttl),
+            config,
+            index: Mutex::new(index),
+            keys,
+        })
+    }
+
+    fn index(&self) -> std::sync::MutexGuard<'_, DiskIndex> {
+        self.index.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    /// The body of `key`, expired or not when `stale`; files of expired
+    /// entries are removed unless `keep`
+    async fn get(&self, key: &str, stale: bool, keep: bool) -> Option<Bytes> {
+        let name = file_name(key);
+        let expired = match self.index().lru.get(&name) {
+            Some(entry) => entry.is_expired(self.ttl.get()),
+            None => return None,
+        };
+        let path = body_path(&self.config.dir, &name);
+        if expired && keep && !stale {
+            return None;
+        }
+        if stale || !expired {
+            if let Ok(body) = tokio::fs::read(&path).await {
+                return Some(body.into());
+            }
+        }
+        self.index().remove(&name);
+        self.remove_files(&name).await;
+        None
+    }
+
+    /// Write `body` to its file, removing the least recently used files to
+    /// make room
+    async fn insert(&self, key: &str, body: &Bytes) {
+        let size = body.len() as u64;
+        if size > self.config.max_bytes {
+            return;
+        }
+        let name = file_name(key);
+        let path = body_path(&self.config.dir, &name);
+        // Written aside and renamed, so no reader sees part of a body
+        let temp = self.config.dir.join(format!(
+            "{}-{}.{}",
+            name,
+            uuid::Uuid::new_v4(),
+            TEMP_EXTENSION
+        ));
+        let written = match tokio::fs::write(&temp, body).await {
+            Ok(()) => tokio::fs::rename(&temp, &path).await,
+            Err(e) => Err(e),
+        };
+        if let Err(e) = written {
+            warn!("Cannot write disk cache file {}: {}", path.display(), e);
+            let _ = tokio::fs::remove_file(&temp).await;
+            return;
+        }
+        // Without its key the body is still served, only not purged by filter
+        if let Err(e) = self.keys.put(&self.config.dir, &name, key).await {
+            warn!("Cannot write disk cache key of {}: {}", path.display(), e);
+        }
+
+        let evicted = {
+            let mut index = self.index();
+            index.remove(&name);
+            index.bytes += size;
+            index.lru.put(
+                name,
+                DiskEntry {
+                    size,
+                    stored: SystemTime::now(),
+                    key: Some(key.to_string()),
+                },
+            );
+            index.evict(self.config.max_bytes)
+        };
+        for name in evicted {
+            self.remove_files(&name).await;
+        }
+    }
+
+    async fn clear(&self) {
+        let names: Vec<String> = {
+            let mut index = self.index();
+            index.bytes = 0;
+            let names = index.lru.iter().map(|(name, _)| name.clone()).collect();
+            index.lru.clear();
+            names
+        };
+        for name in names {
+            self.remove_files(&name).await;
+        }
+    }
+
+    /// Drop the entries whose key matches `filter`, returning how many
+    async fn purge(&self, filter: &CacheFilter) -> usize {
+        let names: Vec<String> = {
+            let mut index = self.index();
+            let names: Vec<String> = index
+                .lru
+                .iter()
+                .filter(|(_, entry)| entry.key.as_deref().is_some_and(|key| filter.matches(key)))
+                .map(|(name, _)| name.clone())
+                .collect();
+            for name in &names {
+                index.remove(name);
+            }
+            names
+        };
+        for name in &names {
+            self.remove_files(name).await;
+        }
+        names.len()
+    }
+
+    /// Remove the body of `name` and its key
+    async fn remove_files(&self, name: &str) {
+        let _ = tokio::fs::remove_file(body_path(&self.config.dir, name)).await;
+        self.keys.remove(&self.config.dir, name).await;
+    }
+}
+
+/// File stem of the body cached under `key`
+fn file_name(key: &str) -> String {
+    format!("{:x}", Sha256::digest(key.as_bytes()))
+}
+
+fn body_path(dir: &Path, name: &str) -> PathBuf {
+    dir.join(format!("{}.{}", name, BODY_EXTENSION))
+}
+
+fn key_path(dir: &Path, name: &str) -> PathBuf {
+    dir.join(format!("{}.{}", name, KEY_EXTENSION))
+}
+
+/// Names in the variable parameters of the URL `key`, split at commas
+fn requested_variables(key: &str) -> impl Iterator<Item = String> {
+    let pairs: Vec<(String, String)> = reqwest::Url::parse(key)
+        .map(|url| url.query_pairs().into_owned().collect())
+        .unwrap_or_default();
+    pairs
+        .into_iter()
+        .filter(|(name, _)| VARIABLE_PARAMETERS.contains(&name.as_str()))
+        .flat_map(|(_, value)| {
+            value
+                .split(',')
+                .map(|name| name.trim().to_string())
+                .collect::<Vec<_>>()
+        })
+}
+
+/// Whether `text` matches `pattern`, where `*` stands for any text
+fn wildcard_match(pattern: &str, text: &str) -> bool {
+    let mut parts = pattern.split('*');
+    let first = parts.next().unwrap_or_default();
+    let Some(mut rest) = text.strip_prefix(first) else {
+        return false;
+    };
+    let parts: Vec<&str> = parts.collect();
+    let Some((last, middle)) = parts.split_last() else {
+        // No `*`: the whole text must match
+        return rest.is_empty();
+    };
+    for part in middle {
+        match rest.find(part) {
+            Some(at) => rest = &rest[at + part.len()..],
+            None => return false,
+        }
+    }
+    rest.len() >= last.len() && rest.ends_with(last)
+}
+
+/// `url` with its query parameters in a canonical order
+pub fn normalize_url(url: &str) -> String {
+    let Ok(mut parsed) = reqwest::Url::parse(url) else {
+        return url.to_string();
+    };
+    let mut pairs: Vec<(String, String)> = parsed
+        .query_pairs()
+        .filter(|(key, _)| !key.is_empty())
+        .map(|(key, value)| (key.into_owned(), value.into_owned()))
+        .collect();
+    if pairs.is_empty() {
+        parsed.set_query(None);
+    } else {
+        pairs.sort();
+        parsed.query_pairs_mut().clear().extend_pairs(pairs);
+    }
+    parsed.to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn cache(max_bytes: usize, ttl: Duration) -> ResponseCache {
+        ResponseCache::new(ResponseCacheConfig {
+            max_bytes,
+            ttl,
+            disk: None,
+        })
+        .unwrap()
+    }
+
+    fn disk_config(dir: &Path, max_bytes: u64) -> ResponseCacheConfig {
+        ResponseCacheConfig {
+            disk: Some(DiskCacheConfig {
+                dir: dir.to_path_buf(),
+                max_bytes,
+                ttl: DEFAULT_DISK_CACHE_TTL,
+            }),
+            ..ResponseCacheConfig::default()
+        }
+    }
+
+    fn temp_dir() -> PathBuf {
+        std::env::temp_dir().join(format!("rossby-vis-cache-{}", uuid::Uuid::new_v4()))
+    }
+
+    #[test]
+    fn test_normalize_url() {
+        assert_eq!(
+            normalize_url("http://backend:8000/data?vars=t2m&time=0&format=json"),
+            normalize_url("http://backend:8000/data?format=json&time=0&vars=t2m&")
+        );
+        assert_ne!(
+            normalize_url("http://backend:8000/data?vars=t2m"),
+            normalize_url("http://backend:8000/data?vars=u10")
+        );
+        assert_eq!(
+            normalize_url("http://backend:8000/metadata?"),
+            "http://backend:8000/metadata"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_least_recently_used_eviction() {
+        let cache = cache(10, DEFAULT_CACHE_TTL);
+        cache
+            .insert("http://b/a", Bytes::from_static(b"aaaa"))
+            .await;
+        cache
+            .insert("http://b/b", Bytes::from_static(b"bbbb"))
+            .await;
+        assert!(cache.get("http://b/a").await.is_some());
+
+        // Room for the new body is made by dropping b, used longest ago
+        cache
+            .insert("http://b/c", Bytes::from_static(b"cccc"))
+            .await;
+        assert!(cache.get("http://b/b").await.is_none());
+        assert_eq!(cache.get("http://b/a").await.unwrap(), "aaaa");
+        assert_eq!(cache.size_bytes(), 8);
+
+        // Bodies larger than the cache are not kept
+        cache.insert("http://b/d", Bytes::from(vec![0; 11])).await;
+        assert!(cache.get("http://b/d").await.is_none());
+        assert_eq!(cache.len(), 2);
+    }
+
+    #[tokio::test]
+    async fn test_expiry_and_disabled() {
+        let cache = cache(10, Duration::ZERO);
+        cache.insert("http://b/a", Bytes::from_static(b"a")).await;
+        assert!(cache.get("http://b/a").await.is_none());
+        assert!(cache.is_empty());
+
+        // A longer lifetime applies to entries stored before the change
+        cache.insert("http://b/a", Bytes::from_static(b"a")).await;
+        cache.set_ttls(Duration::from_secs(60), Duration::from_secs(60));
+        assert_eq!(cache.ttls(), (Duration::from_secs(60), None));
+        assert_eq!(cache.get("http://b/a").await.unwrap(), "a");
+
+        let disabled = ResponseCache::default();
+        disabled
+            .insert("http://b/a", Bytes::from_static(b"a"))
+            .await;
+        assert!(disabled.get("http://b/a").await.is_none());
+    }
+
+    #[tokio::test]
+    async fn test_stale_entries_are_kept_when_asked() {
+        let dropping = cache(10, Duration::ZERO);
+        dropping
+            .insert("http://b/a", Bytes::from_static(b"a"))
+            .await;
+        assert!(dropping.get_stale("http://b/a").await.is_none());
+
+        let keeping = cache(10, Duration::ZERO).keeping_stale();
+        keeping.insert("http://b/a", Bytes::from_static(b"a")).await;
+        assert!(keeping.get("http://b/a").await.is_none());
+        assert_eq!(keeping.get_stale("http://b/a").await.unwrap(), "a");
+    }
+
+    #[tokio::test]
+    async fn test_disk_tier_survives_restart() {
+        let dir = temp_dir();
+        let cache = ResponseCache::new(disk_config(&dir, 10)).unwrap();
+        cache
+            .insert("http://b/a?x=1&y=2", Bytes::from_static(b"aaaa"))
+            .await;
+        cache
+            .insert("http://b/b", Bytes::from_static(b"bbbb"))
+            .await;
+        cache
+            .insert("http://b/c", Bytes::from_static(b"cccc"))
+            .await;
+        // Only the memory tier is disabled, and a was evicted from disk
+        assert!(cache.is_empty());
+        assert_eq!(cache.disk_usage(), Some((2, 8)));
+        assert!(cache.get("http://b/a?y=2&x=1").await.is_none());
+
+        // Half-written files and files of expired entries are removed
+        std::fs::write(dir.join("partial.tmp"), b"cc").unwrap();
+        drop(cache);
+        let reopened = ResponseCache::new(disk_config(&dir, 10)).unwrap();
+        assert_eq!(reopened.get("http://b/c").await.unwrap(), "cccc");
+        assert!(!dir.join("partial.tmp").exists());
+
+        let mut expired = disk_config(&dir, 10);
+        expired.disk.as_mut().unwrap().ttl = Duration::ZERO;
+        let expired = ResponseCache::new(expired).unwrap();
+        assert_eq!(expired.disk_usage(), Some((0, 0)));
+        assert_eq!(std::fs::read_dir(&dir).unwrap().count(), 0);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_disk_hits_return_to_memory() {
+        let dir = temp_dir();
+        let config = ResponseCacheConfig {
+            max_bytes: 10,
+            ..disk_config(&dir, 100)
+        };
+        let cache = ResponseCache::new(config.clone()).unwrap();
+        cache
+            .insert("http://b/a", Bytes::from_static(b"aaaa"))
+            .await;
+        drop(cache);
+
+        let reopened = ResponseCache::new(config).unwrap();
+        assert!(reopened.is_empty());
+        assert_eq!(reopened.get("http://b/a").await.unwrap(), "aaaa");
+        assert_eq!(reopened.len(), 1);
+
+        reopened.clear().await;
+        assert_eq!(reopened.disk_usage(), Some((0, 0)));
+        assert!(reopened.get("http://b/a").await.is_none());
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[cfg(feature = "sqlite")]
+    #[tokio::test]
+    async fn test_disk_keys_in_the_store() {
+        let dir = temp_dir();
+        let config = disk_config(&dir, 100);
+        // Key files written without a database are moved into it
+        let cache = ResponseCache::new(config.clone()).unwrap();
+        cache
+            .insert("http://b/data?vars=t2m", Bytes::from_static(b"aaaa"))
+            .await;
+        drop(cache);
+
+        let store = Store::open_in_memory().unwrap();
+        let cache = ResponseCache::with_store(config.clone(), &store).unwrap();
+        cache
+            .insert("http://b/data?vars=u10", Bytes::from_static(b"bbbb"))
+            .await;
+        drop(cache);
+        let files: Vec<PathBuf> = std::fs::read_dir(&dir)
+            .unwrap()
+            .map(|file| file.unwrap().path())
+            .collect();
+        assert_eq!(files.len(), 2);
+        assert!(files
+            .iter()
+            .all(|path| path.extension().unwrap() == BODY_EXTENSION));
+
+        let reopened = ResponseCache::with_store(config, &store).unwrap();
+        for variable in ["t2m", "u10"] {
+            let purged = reopened
+                .purge(&CacheFilter {
+                    variable: Some(variable.to_string()),
+                    ..Default::default()
+                })
+                .await;
+            assert_eq!(purged, PurgeCounts { memory: 0, disk: 1 });
+        }
+        let rows: i64 = store
+            .call_blocking(|connection| {
+                connection.query_row("SELECT COUNT(*) FROM disk_cache", [], |row| row.get(0))
+            })
+            .unwrap();
+        assert_eq!(rows, 0);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_cache_filter() {
+        assert!(wildcard_match("http://b/*", "http://b/data?vars=t2m"));
+        assert!(wildcard_match(
+            "*vars=t2m*",
+            "http://b/data?time=0&vars=t2m"
+        ));
+        assert!(wildcard_match("http://b/*/a*z", "http://b/x/a/z"));
+        assert!(!wildcard_match(
+            "http://b/metadata",
+            "http://b/metadata?x=1"
+        ));
+        assert!(!wildcard_match("*ab*ba", "http://b/aba"));
+
+        let key = normalize_url("http://b/data?vars=t2m,u10&time=0");
+        let variable = |name: &str| CacheFilter {
+            variable: Some(name.to_string()),
+            ..Default::default()
+        };
+        assert!(variable("u10").matches(&key));
+        assert!(!variable("t2").matches(&key));
+        assert!(!variable("t2m").matches("http://b/metadata"));
+        assert!(!CacheFilter {
+            pattern: Some("http://b/metadata*".to_string()),
+            variable: Some("t2m".to_string()),
+        }
+        .matches(&key));
+        assert!(CacheFilter::default().matches(&key));
+    }
+
+    #[tokio::test]
+    async fn test_stats_count_hits_and_misses() {
+        let cache = cache(100, Duration::from_secs(60));
+        assert_eq!(cache.stats().hit_rate, None);
+        cache
+            .insert("http://b/a", Bytes::from_static(b"aaaa"))
+            .await;
+        cache.get("http://b/a").await;
+        cache.get("http://b/a").await;
+        cache.get("http://b/a").await;
+        cache.get("http://b/b").await;
+
+        let stats = cache.stats();
+        assert_eq!((stats.hits, stats.misses), (3, 1));
+        assert_eq!(stats.hit_rate, Some(0.75));
+        assert_eq!(
+            stats.memory,
+            TierStats {
+                entries: 1,
+                bytes: 4,
+                max_bytes: 100,
+                ttl_secs: 60,
+            }
+        );
+        assert!(stats.disk.is_none());
+    }
+
+    #[tokio::test]
+    async fn test_purge_matching_entries() {
+        let dir = temp_dir();
+        let config = ResponseCacheConfig {
+            max_bytes: 100,
+            ..disk_config(&dir, 100)
+        };
+        let cache = ResponseCache::new(config.clone()).unwrap();
+        for url in [
+            "http://b/data?vars=t2m&time=0",
+            "http://b/data?vars=u10,v10&time=0",
+            "http://b/metadata",
+        ] {
+            cache.insert(url, Bytes::from_static(b"body")).await;
+        }
+        drop(cache);
+
+        // Keys are read back from disk, so filters apply after a restart
+        let reopened = ResponseCache::new(config).unwrap();
+        reopened.get("http://b/metadata").await.unwrap();
+        let purged = reopened
+            .purge(&CacheFilter {
+                variable: Some("v10".to_string()),
+                ..Default::default()
+            })
+            .await;
+        assert_eq!(purged, PurgeCounts { memory: 0, disk: 1 });
+        let purged = reopened
+            .purge(&CacheFilter {
+                pattern: Some("http://b/metadata*".to_string()),
+                ..Default::default()
+            })
+            .await;
+        assert_eq!(purged, PurgeCounts { memory: 1, disk: 1 });
+        assert!(reopened
+            .get("http://b/data?time=0&vars=t2m")
+            .await
+            .is_some());
+        assert_eq!(std::fs::read_dir(&dir).unwrap().count(), 2);
+
+        let purged = reopened.purge(&CacheFilter::default()).await;
+        assert_eq!(purged, PurgeCounts { memory: 1, disk: 1 });
+        assert_eq!(std::fs::read_dir(&dir).unwrap().count(), 0);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,156 @@
+//! Shared view of the backend's variables for the Earth handlers
+//!
+//! Serving an Earth product needs the metadata with derived variables
+//! registered and every variable classified into scalars, vector pairs and
+//! categories. [`CatalogService`] does this once per version of the
+//! metadata: each request still fetches the metadata, so new timesteps show
+//! up immediately, but the analysis is only redone when the dataset changes.
+
+use serde_json::Value;
+use std::sync::{Arc, Mutex};
+
+use crate::{
+    derived::register_derived_variables,
+    error::AppError,
+    freshness::{data_version, DataFreshness},
+    handlers::{
+        analyze_metadata_variables, fetch_metadata, select_variable, VariableCategory,
+        VariableInfo, VariableType,
+    },
+    levels::EarthLevel,
+    server::AppState,
+};
+
+/// The metadata of one version of the dataset with its variables analyzed
+#[derive(Debug)]
+pub struct Catalog {
+    version: String,
+    metadata: Value,
+    variables: Vec<VariableInfo>,
+}
+
+impl Catalog {
+    /// Analyze `metadata` as fetched from the backend, `version` being its
+    /// data version
+    fn new(version: String, mut metadata: Value, state: &AppState) -> Self {
+        register_derived_variables(&mut metadata, &state.derived_variables);
+        let variables = analyze_metadata_variables(&metadata);
+        Self {
+            version,
+            metadata,
+            variables,
+        }
+    }
+
+    /// Version of the dataset, as in `X-Data-Version`
+    pub fn version(&self) -> &str {
+        &self.version
+    }
+
+    /// The metadata, with derived variables registered
+    pub fn metadata(&self) -> &Value {
+        &self.metadata
+    }
+
+    /// Freshness validators of a response to `request` at this version
+    pub fn freshness(&self, request: &str) -> DataFreshness {
+        DataFreshness::from_version(self.version.clone(), request)
+    }
+
+    /// The eastward component of the first wind vector
+    pub fn wind_variable(&self) -> Option<&str> {
+        self.variables
+            .iter()
+            .filter(|v| matches!(v.category, VariableCategory::Wind))
+            .find_map(|v| match &v.var_type {
+                VariableType::Vector { u_component, .. } => Some(u_component.as_str()),
+                VariableType::Scalar => None,
+            })
+    }
+
+    /// The first temperature variable
+    pub fn temperature_variable(&self) -> Option<&str> {
+        self.variables
+            .iter()
+            .find(|v| matches!(v.category, VariableCategory::Temperature))
+            .map(|v| v.name.as_str())
+    }
+
+    /// The variable serving `product` at `level`, with the coordinate value
+    /// of the level when it is isobaric
+    pub(crate) fn select_product(
+        &self,
+        product: &str,
+        level: EarthLevel,
+    ) -> Result<(VariableInfo, Option<(String, f64)>), AppError> {
+        select_variable(&self.metadata, &self.variables, product, level)
+    }
+}
+
+/// Fetches the metadata and keeps the catalog of its latest version
+#[derive(Debug, Default)]
+pub struct CatalogService {
+    current: Mutex<Option<Arc<Catalog>>>,
+}
+
+impl CatalogService {
+    /// The catalog of the backend's current metadata
+    pub async fn load(&self, state: &AppState) -> Result<Arc<Catalog>, AppError> {
+        let metadata = fetch_metadata(state).await?;
+        Ok(self.catalog(metadata, state))
+    }
+
+    /// The catalog of `metadata`, reusing the analysis while its version is
+    /// unchanged
+    pub fn catalog(&self, metadata: Value, state: &AppState) -> Arc<Catalog> {
+        let version = data_version(&metadata);
+        let mut current = self.current.lock().unwrap();
+        match current.as_ref() {
+            Some(catalog) if catalog.version == version => catalog.clone(),
+            _ => {
+                let catalog = Arc::new(Catalog::new(version, metadata, state));
+                *current = Some(catalog.clone());
+                catalog
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn metadata(times: &[f64]) -> Value {
+        json!({
+            "coordinates": {"time": times},
+            "variables": {
+                "u10": {"dimensions": ["time", "latitude", "longitude"],
+                        "attributes": {"long_name": "10 metre U wind component"}},
+                "v10": {"dimensions": ["time", "latitude", "longitude"],
+                        "attributes": {"long_name": "10 metre V wind component"}},
+                "t2m": {"dimensions": ["time", "latitude", "longitude"],
+                        "attributes": {"long_name": "2 metre temperature"}}
+            }
+        })
+    }
+
+    #[test]
+    fn test_catalog_is_reused_per_version() {
+        let state = AppState::new("http://localhost:8000".to_string(), reqwest::Client::new());
+        let service = CatalogService::default();
+
+        let catalog = service.catalog(metadata(&[700464.0]), &state);
+        assert_eq!(catalog.wind_variable(), Some("u10"));
+        assert_eq!(catalog.temperature_variable(), Some("t2m"));
+        assert_eq!(catalog.version(), data_version(&metadata(&[700464.0])));
+        assert!(Arc::ptr_eq(
+            &catalog,
+            &service.catalog(metadata(&[700464.0]), &state)
+        ));
+
+        let updated = service.catalog(metadata(&[700464.0, 700465.0]), &state);
+        assert!(!Arc::ptr_eq(&catalog, &updated));
+        assert_ne!(catalog.version(), updated.version());
+    }
+}
@@ -0,0 +1,307 @@
+//! Fault injection for backend responses
+//!
+//! `--chaos <faults>` puts a misbehaving proxy between the server and the
+//! Rossby backend, so retries, fallbacks and caching can be exercised under
+//! failure without external tooling. Faults are a comma-separated list:
+//!
+//! ```text
+//! --chaos latency=200ms,jitter=300ms,errors=0.1,truncate=0.05,malformed=0.05,seed=42
+//! ```
+//!
+//! - `latency`: delay added to every backend response
+//! - `jitter`: further random delay of up to this much
+//! - `errors`: fraction of requests answered with `status` (default 503)
+//! - `truncate`: fraction of responses cut off half-way by dropping the
+//!   connection
+//! - `malformed`: fraction of responses whose body is cut in half, leaving
+//!   invalid JSON in an otherwise complete response
+//! - `seed`: makes the faults repeat from run to run
+//!
+//! Durations are given in `ms` or `s`. Chaos is meant for testing and is
+//! combined with `--replay` for fully offline failure drills.
+
+use axum::{
+    body::{Bytes, StreamBody},
+    extract::State,
+    http::{HeaderMap, Method, StatusCode, Uri},
+    response::{IntoResponse, Response},
+    Json, Router,
+};
+use futures::{stream, StreamExt, TryStreamExt};
+use rand::{rngs::StdRng, Rng, SeedableRng};
+use serde_json::json;
+use std::{fmt, str::FromStr, sync::Arc, sync::Mutex, time::Duration};
+use tracing::{debug, warn};
+
+use crate::{
+    error::AppError,
+    replay::{serve_loopback, HOP_BY_HOP},
+};
+
+/// Faults injected into backend responses
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct ChaosConfig {
+    pub latency: Duration,
+    pub jitter: Duration,
+    /// Fraction of requests failed with `status`
+    pub errors: f64,
+    pub status: u16,
+    /// Fraction of responses dropped part-way through the body
+    pub truncate: f64,
+    /// Fraction of responses with half of their body
+    pub malformed: f64,
+    pub seed: Option<u64>,
+}
+
+/// A fault chosen for one response
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Fault {
+    Error,
+    Truncate,
+    Malformed,
+}
+
+fn parse_duration(value: &str) -> Option<Duration> {
+    if let Some(ms) = value.strip_suffix("ms") {
+        ms.parse().ok().map(Duration::from_millis)
+    } else {
+        value
+            .strip_suffix('s')
+            .and_then(|secs| secs.parse::<f64>().ok())
+            .filter(|secs| secs.is_finite() && *secs >= 0.0)
+            .map(Duration::from_secs_f64)
+    }
+}
+
+impl FromStr for ChaosConfig {
+    type Err = AppError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid =
+            |fault: &str| AppError::ConfigError(format!("Invalid chaos fault '{}'", fault));
+        let mut config = ChaosConfig {
+            status: 503,
+            ..Self::default()
+        };
+        for fault in s.split(',').map(str::trim).filter(|f| !f.is_empty()) {
+            let (name, value) = fault.split_once('=').ok_or_else(|| invalid(fault))?;
+            let rate = || {
+                value
+                    .parse::<f64>()
+                    .ok()
+                    .filter(|rate| (0.0..=1.0).contains(rate))
+                    .ok_or_else(|| invalid(fault))
+            };
+            match name {
+                "latency" => {
+                    config.latency = parse_duration(value).ok_or_else(|| invalid(fault))?
+                }
+                "jitter" => config.jitter = parse_duration(value).ok_or_else(|| invalid(fault))?,
+                "errors" => config.errors = rate()?,
+                "truncate" => config.truncate = rate()?,
+                "malformed" => config.malformed = rate()?,
+                "status" => {
+                    config.status = value
+                        .parse()
+                        .ok()
+                        .filter(|status| StatusCode::from_u16(*status).is_ok())
+                        .ok_or_else(|| invalid(fault))?
+                }
+                "seed" => config.seed = Some(value.parse().map_err(|_| invalid(fault))?),
+                _ => {
+                    return Err(AppError::ConfigError(format!(
+                        "Unknown chaos fault '{}'; expected latency, jitter, errors, status, \
+                         truncate, malformed or seed",
+                        name
+                    )))
+                }
+            }
+        }
+        Ok(config)
+    }
+}
+
+impl fmt::Display for ChaosConfig {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "latency {}ms (+{}ms jitter), {:.0}% errors ({}), {:.0}% truncated, {:.0}% malformed",
+            self.latency.as_millis(),
+            self.jitter.as_millis(),
+            self.errors * 100.0,
+            self.status,
+            self.truncate * 100.0,
+            self.malformed * 100.0
+        )
+    }
+}
+
+impl ChaosConfig {
+    /// Start the faulty proxy in front of `backend_url`, returning the URL to
+    /// use as the backend
+    pub async fn start(
+        &self,
+        backend_url: &str,
+        client: reqwest::Client,
+    ) -> Result<String, AppError> {
+        let rng = match self.seed {
+            Some(seed) => StdRng::seed_from_u64(seed),
+            None => StdRng::from_entropy(),
+        };
+        warn!("Chaos mode: injecting {} into backend responses", self);
+        let chaos = Arc::new(Chaos {
+            config: self.clone(),
+            backend_url: backend_url.trim_end_matches('/').to_string(),
+            client,
+            rng: Mutex::new(rng),
+        });
+        serve_loopback(Router::new().fallback(inject).with_state(chaos)).await
+    }
+}
+
+struct Chaos {
+    config: ChaosConfig,
+    backend_url: String,
+    client: reqwest::Client,
+    rng: Mutex<StdRng>,
+}
+
+impl Chaos {
+    /// Draw the delay and the fault, if any, of one response
+    fn draw(&self) -> (Duration, Option<Fault>) {
+        let mut rng = self.rng.lock().unwrap();
+        let jitter = if self.config.jitter.is_zero() {
+            Duration::ZERO
+        } else {
+            self.config.jitter.mul_f64(rng.gen())
+        };
+        let fault = [
+            (Fault::Error, self.config.errors),
+            (Fault::Truncate, self.config.truncate),
+            (Fault::Malformed, self.config.malformed),
+        ]
+        .into_iter()
+        .find(|(_, rate)| *rate > 0.0 && rng.gen_bool(*rate))
+        .map(|(fault, _)| fault);
+        (self.config.latency + jitter, fault)
+    }
+}
+
+async fn inject(
+    State(chaos): State<Arc<Chaos>>,
+    method: Method,
+    uri: Uri,
+    headers: HeaderMap,
+    body: Bytes,
+) -> Response {
+    let (delay, fault) = chaos.draw();
+    if !delay.is_zero() {
+        tokio::time::sleep(delay).await;
+    }
+    if let Some(fault) = fault {
+        debug!("Injecting {:?} into {}", fault, uri);
+    }
+    if fault == Some(Fault::Error) {
+        let status =
+            StatusCode::from_u16(chaos.config.status).unwrap_or(StatusCode::SERVICE_UNAVAILABLE);
+        return (status, Json(json!({"error": "Injected fault"}))).into_response();
+    }
+
+    let url = format!(
+        "{}{}",
+        chaos.backend_url,
+        uri.path_and_query().map_or("/", |pq| pq.as_str())
+    );
+    let mut request = chaos.client.request(method, url).body(body);
+    for (name, value) in &headers {
+        if !HOP_BY_HOP.contains(&name.as_str()) {
+            request = request.header(name, value);
+        }
+    }
+    let backend = match request.send().await {
+        Ok(response) => response,
+        Err(e) => return AppError::ProxyError(e.to_string()).into_response(),
+    };
+
+    let mut response = Response::builder().status(backend.status());
+    for (name, value) in backend.headers() {
+        if !HOP_BY_HOP.contains(&name.as_str()) {
+            response = response.header(name, value);
+        }
+    }
+    let body = match fault {
+        None => axum::body::boxed(StreamBody::new(
+            backend.bytes_stream().map_err(std::io::Error::other),
+        )),
+        Some(fault) => {
+            let bytes = match backend.bytes().await {
+                Ok(bytes) => bytes,
+                Err(e) => return AppError::ProxyError(e.to_string()).into_response(),
+            };
+            let half = bytes.slice(..bytes.len() / 2);
+            if fault == Fault::Truncate {
+                // The error drops the connection after the first half
+                let chunks = stream::iter([
+                    Ok(half),
+                    Err(std::io::Error::new(
+                        std::io::ErrorKind::ConnectionReset,
+                        "Injected truncation",
+                    )),
+                ]);
+                axum::body::boxed(StreamBody::new(chunks.boxed()))
+            } else {
+                axum::body::boxed(axum::body::Full::from(half))
+            }
+        }
+    };
+    response
+        .body(body)
+        .unwrap_or_else(|e| AppError::ProxyError(e.to_string()).into_response())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_chaos() {
+        let config: ChaosConfig = "latency=200ms, jitter=1.5s, errors=0.1, status=500, seed=7"
+            .parse()
+            .unwrap();
+        assert_eq!(config.latency, Duration::from_millis(200));
+        assert_eq!(config.jitter, Duration::from_millis(1500));
+        assert_eq!(config.errors, 0.1);
+        assert_eq!(config.status, 500);
+        assert_eq!(config.seed, Some(7));
+        assert_eq!("".parse::<ChaosConfig>().unwrap().status, 503);
+
+        assert!("errors=1.5".parse::<ChaosConfig>().is_err());
+        assert!("latency=fast".parse::<ChaosConfig>().is_err());
+        assert!("status=42".parse::<ChaosConfig>().is_err());
+        assert!("explode=0.5".parse::<ChaosConfig>().is_err());
+        assert!("errors".parse::<ChaosConfig>().is_err());
+    }
+
+    #[test]
+    fn test_seeded_faults_repeat() {
+        let config: ChaosConfig = "errors=0.3,truncate=0.3,jitter=100ms,seed=42"
+            .parse()
+            .unwrap();
+        let draws = || {
+            let chaos = Chaos {
+                config: config.clone(),
+                backend_url: String::new(),
+                client: reqwest::Client::new(),
+                rng: Mutex::new(StdRng::seed_from_u64(42)),
+            };
+            (0..20).map(|_| chaos.draw()).collect::<Vec<_>>()
+        };
+        let first = draws();
+        assert_eq!(first, draws());
+        assert!(first.iter().any(|(_, fault)| fault.is_none()));
+        assert!(first.iter().any(|(_, fault)| *fault == Some(Fault::Error)));
+        assert!(first
+            .iter()
+            .all(|(delay, _)| *delay <= Duration::from_millis(100)));
+    }
+}
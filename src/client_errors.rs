@@ -0,0 +1,233 @@
+//! Collection of JavaScript errors reported by the frontend
+//!
+//! The frontend batches uncaught errors and unhandled promise rejections and
+//! posts them to `/api/client-errors`. Each report is written to the
+//! structured logs under the `client_errors` target, inside the span of the
+//! reporting request and with the request ID of the failed request when the
+//! frontend knows it, so browser-side failures show up next to the server's
+//! own logs. Reports are limited per client so a broken page in a loop cannot
+//! flood the logs.
+
+use axum::{
+    extract::State,
+    http::{HeaderMap, StatusCode},
+    Json,
+};
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::HashMap,
+    sync::{
+        atomic::{AtomicU32, Ordering},
+        Arc, Mutex,
+    },
+    time::{Duration, Instant},
+};
+use tracing::warn;
+use utoipa::ToSchema;
+
+use crate::{
+    error::{AppError, ErrorBody},
+    server::AppState,
+};
+
+/// Upper bound on reports in one batch; the rest are dropped
+const MAX_BATCH_REPORTS: usize = 20;
+/// Longest message or stack trace kept, in characters
+const MAX_FIELD_CHARS: usize = 2000;
+/// Length of the rate-limiting window
+const WINDOW: Duration = Duration::from_secs(60);
+/// Clients tracked before expired windows are swept
+const MAX_TRACKED_CLIENTS: usize = 10_000;
+
+/// One error caught by the frontend
+#[derive(Debug, Clone, Deserialize, ToSchema)]
+pub struct ClientErrorReport {
+    pub message: String,
+    /// Script URL the error was raised in
+    pub source: Option<String>,
+    pub line: Option<u32>,
+    pub column: Option<u32>,
+    pub stack: Option<String>,
+    /// Page URL at the time of the error
+    pub url: Option<String>,
+    /// `X-Request-Id` of the failed request, for errors caused by one
+    pub request_id: Option<String>,
+    /// When the error happened, as reported by the browser
+    pub timestamp: Option<String>,
+}
+
+/// Body of `POST /api/client-errors`
+#[derive(Debug, Deserialize, ToSchema)]
+pub struct ClientErrorBatch {
+    pub errors: Vec<ClientErrorReport>,
+}
+
+/// Outcome of `POST /api/client-errors`
+#[derive(Debug, Serialize, PartialEq, Eq, ToSchema)]
+pub struct ClientErrorResponse {
+    pub accepted: usize,
+    /// Reports over the batch or rate limit, not logged
+    pub dropped: usize,
+}
+
+/// Fixed-window limit on the reports logged per client
+#[derive(Debug)]
+pub struct ClientErrorLimiter {
+    /// Changed when the configuration is reloaded
+    per_minute: AtomicU32,
+    windows: Mutex<HashMap<String, (Instant, u32)>>,
+}
+
+impl ClientErrorLimiter {
+    /// Allow `per_minute` reports per client
+    pub fn new(per_minute: u32) -> Self {
+        Self {
+            per_minute: AtomicU32::new(per_minute),
+            windows: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Reports allowed per client per minute
+    pub fn rate(&self) -> u32 {
+        self.per_minute.load(Ordering::Relaxed)
+    }
+
+    /// Allow `per_minute` reports per client from now on
+    pub fn set_rate(&self, per_minute: u32) {
+        self.per_minute.store(per_minute, Ordering::Relaxed);
+    }
+
+    /// How many of `requested` reports from `client` may be logged now
+    pub fn admit(&self, client: &str, requested: usize) -> usize {
+        let now = Instant::now();
+        let mut windows = self.windows.lock().unwrap_or_else(|e| e.into_inner());
+        if windows.len() >= MAX_TRACKED_CLIENTS {
+            windows.retain(|_, (start, _)| now.duration_since(*start) < WINDOW);
+        }
+
+        let (start, used) = windows.entry(client.to_string()).or_insert((now, 0));
+        if now.duration_since(*start) >= WINDOW {
+            *start = now;
+            *used = 0;
+        }
+        let admitted = requested.min(self.rate().saturating_sub(*used) as usize);
+        *used += admitted as u32;
+        admitted
+    }
+}
+
+impl Default for ClientErrorLimiter {
+    /// 60 reports per client per minute
+    fn default() -> Self {
+        Self::new(60)
+    }
+}
+
+/// Handler for `POST /api/client-errors`
+#[utoipa::path(
+    post,
+    path = "/api/client-errors",
+    tag = "service",
+    summary = "Log errors caught by the frontend",
+    request_body = ClientErrorBatch,
+    responses(
+        (status = 202, body = ClientErrorResponse),
+        (status = 400, description = "Invalid batch", body = ErrorBody),
+    )
+)]
+pub async fn report_client_errors(
+    State(state): State<Arc<AppState>>,
+    headers: HeaderMap,
+    Json(batch): Json<ClientErrorBatch>,
+) -> Result<(StatusCode, Json<ClientErrorResponse>), AppError> {
+    let client = client_key(&headers);
+    let received = batch.errors.len();
+    let batched = received.min(MAX_BATCH_REPORTS);
+    let accepted = state.client_errors.admit(&client, batched);
+    if accepted == 0 && received > 0 {
+        return Err(AppError::RateLimited(
+            "client error reports are limited, try again later".to_string(),
+        ));
+    }
+
+    let user_agent = headers
+        .get("user-agent")
+        .and_then(|v| v.to_str().ok())
+        .unwrap_or_default();
+    for report in batch.errors.into_iter().take(accepted) {
+        warn!(
+            target: "client_errors",
+            client = %client,
+            user_agent,
+            message = %truncate(&report.message),
+            source = report.source.as_deref().map(truncate).as_deref(),
+            line = report.line,
+            column = report.column,
+            stack = report.stack.as_deref().map(truncate).as_deref(),
+            page_url = report.url.as_deref().map(truncate).as_deref(),
+            failed_request_id = report.request_id.as_deref().map(truncate).as_deref(),
+            client_timestamp = report.timestamp.as_deref().map(truncate).as_deref(),
+            "Frontend error"
+        );
+    }
+
+    Ok((
+        StatusCode::ACCEPTED,
+        Json(ClientErrorResponse {
+            accepted,
+            dropped: received - accepted,
+        }),
+    ))
+}
+
+/// The client a report is counted against: the first forwarded address
+/// when behind a proxy
+fn client_key(headers: &HeaderMap) -> String {
+    ["x-forwarded-for", "x-real-ip", "cf-connecting-ip"]
+        .iter()
+        .find_map(|name| headers.get(*name)?.to_str().ok())
+        .and_then(|value| value.split(',').next())
+        .map(|value| value.trim().to_string())
+        .unwrap_or_else(|| "unknown".to_string())
+}
+
+fn truncate(value: &str) -> String {
+    value.chars().take(MAX_FIELD_CHARS).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_limiter_windows_per_client() {
+        let limiter = ClientErrorLimiter::new(5);
+        assert_eq!(limiter.admit("10.0.0.1", 3), 3);
+        assert_eq!(limiter.admit("10.0.0.1", 3), 2);
+        assert_eq!(limiter.admit("10.0.0.1", 1), 0);
+        assert_eq!(limiter.admit("10.0.0.2", 10), 5);
+
+        // An expired window starts over
+        limiter
+            .windows
+            .lock()
+            .unwrap()
+            .get_mut("10.0.0.1")
+            .unwrap()
+            .0 -= WINDOW;
+        assert_eq!(limiter.admit("10.0.0.1", 1), 1);
+
+        // A lower rate applies to windows already open
+        limiter.set_rate(2);
+        assert_eq!(limiter.admit("10.0.0.1", 3), 1);
+    }
+
+    #[test]
+    fn test_client_key() {
+        let mut headers = HeaderMap::new();
+        assert_eq!(client_key(&headers), "unknown");
+        headers.insert("x-forwarded-for", "203.0.113.7, 10.0.0.1".parse().unwrap());
+        assert_eq!(client_key(&headers), "203.0.113.7");
+        assert_eq!(truncate(&"x".repeat(5000)).len(), MAX_FIELD_CHARS);
+    }
+}
@@ -0,0 +1,427 @@
+//! Adaptive concurrency control toward the backend
+//!
+//! `--backend-concurrency` caps the number of requests in flight to the
+//! Rossby backend. A fixed number sets a static cap; `adaptive` (or
+//! `adaptive:MIN-MAX`) lets the cap follow the backend's latency:
+//!
+//! - every response is compared against the lowest latency seen recently,
+//!   the backend's unloaded baseline
+//! - while responses stay close to the baseline and the cap is in use, the
+//!   cap grows by about its square root
+//! - once latency climbs, the cap shrinks in proportion to the slowdown
+//! - failed connections and 5xx responses cut it by a tenth
+//!
+//! Requests beyond the cap wait for a slot, and fail with
+//! `503 Service Unavailable` when none frees up in time. Small Rossby
+//! instances are thereby kept near their sweet spot instead of being
+//! pushed into overload by bursts of tiles and frames.
+
+use serde::Serialize;
+use std::{
+    fmt,
+    str::FromStr,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+use tokio::sync::Notify;
+use tracing::debug;
+
+use crate::{deadline::Deadline, error::AppError};
+
+/// How long a request waits for a free slot before failing
+pub const DEFAULT_QUEUE_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Bounds of `adaptive` without explicit limits
+const DEFAULT_ADAPTIVE_MIN: usize = 1;
+const DEFAULT_ADAPTIVE_MAX: usize = 64;
+/// Starting cap of the adaptive limit, clamped to its bounds
+const ADAPTIVE_INITIAL: f64 = 8.0;
+
+/// Latency up to this multiple of the baseline counts as unloaded
+const LATENCY_TOLERANCE: f64 = 1.5;
+/// Weight of each new estimate in the smoothed limit
+const SMOOTHING: f64 = 0.2;
+/// Share of the gap to a slower sample the baseline moves per response,
+/// letting it recover when the backend becomes permanently slower
+const BASELINE_DRIFT: f64 = 0.01;
+/// Factor applied to the limit on errors
+const BACKOFF: f64 = 0.9;
+
+/// How requests to the backend are limited
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum ConcurrencyLimit {
+    /// No limit
+    #[default]
+    Unlimited,
+    /// A fixed number of requests in flight
+    Fixed(usize),
+    /// A limit adjusted to the backend's latency, within bounds
+    Adaptive { min: usize, max: usize },
+}
+
+impl FromStr for ConcurrencyLimit {
+    type Err = AppError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || {
+            AppError::ConfigError(format!(
+                "Invalid backend concurrency '{}'; expected a number, 'adaptive', \
+                 'adaptive:MIN-MAX' or 'unlimited'",
+                s
+            ))
+        };
+        let positive = |value: &str| value.trim().parse::<usize>().ok().filter(|n| *n > 0);
+        match s.trim() {
+            "unlimited" => Ok(Self::Unlimited),
+            "adaptive" => Ok(Self::Adaptive {
+                min: DEFAULT_ADAPTIVE_MIN,
+                max: DEFAULT_ADAPTIVE_MAX,
+            }),
+            spec => match spec.strip_prefix("adaptive:") {
+                Some(bounds) => {
+                    let (min, max) = bounds.split_once('-').ok_or_else(invalid)?;
+                    match (positive(min), positive(max)) {
+                        (Some(min), Some(max)) if min <= max => Ok(Self::Adaptive { min, max }),
+                        _ => Err(invalid()),
+                    }
+                }
+                None => positive(spec).map(Self::Fixed).ok_or_else(invalid),
+            },
+        }
+    }
+}
+
+impl fmt::Display for ConcurrencyLimit {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Unlimited => write!(f, "unlimited"),
+            Self::Fixed(limit) => write!(f, "{} requests", limit),
+            Self::Adaptive { min, max } => write!(f, "adaptive, {} to {} requests", min, max),
+        }
+    }
+}
+
+/// How a backend request turned out, as far as the limit is concerned
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Outcome {
+    /// The backend answered; its latency is a load signal
+    Success,
+    /// The backend failed or is struggling
+    Overloaded,
+    /// Says nothing about backend load, such as a 4xx response
+    Ignored,
+}
+
+impl Outcome {
+    /// Classify the result of sending a backend request
+    pub fn of(result: &reqwest::Result<reqwest::Response>) -> Self {
+        match result {
+            Ok(response) if response.status().is_server_error() => Self::Overloaded,
+            Ok(response) if response.status().is_client_error() => Self::Ignored,
+            Ok(_) => Self::Success,
+            Err(_) => Self::Overloaded,
+        }
+    }
+}
+
+/// Current state of the limit, for the admin overview
+#[derive(Debug, Clone, Serialize)]
+pub struct ConcurrencyStats {
+    pub mode: String,
+    pub limit: Option<usize>,
+    pub in_flight: usize,
+    pub baseline_ms: Option<f64>,
+    pub rejected: u64,
+}
+
+#[derive(Debug)]
+struct LimitState {
+    limit: f64,
+    in_flight: usize,
+    baseline: Option<Duration>,
+    rejected: u64,
+}
+
+impl LimitState {
+    fn capacity(&self) -> usize {
+        self.limit.floor() as usize
+    }
+}
+
+/// Caps the requests in flight to the backend
+#[derive(Debug)]
+pub struct BackendLimiter {
+    mode: ConcurrencyLimit,
+    queue_timeout: Duration,
+    state: Mutex<LimitState>,
+    released: Notify,
+}
+
+impl Default for BackendLimiter {
+    fn default() -> Self {
+        Self::new(ConcurrencyLimit::Unlimited, DEFAULT_QUEUE_TIMEOUT)
+    }
+}
+
+impl BackendLimiter {
+    pub fn new(mode: ConcurrencyLimit, queue_timeout: Duration) -> Self {
+        let limit = match mode {
+            ConcurrencyLimit::Unlimited => f64::INFINITY,
+            ConcurrencyLimit::Fixed(limit) => limit as f64,
+            ConcurrencyLimit::Adaptive { min, max } => {
+                ADAPTIVE_INITIAL.clamp(min as f64, max as f64)
+            }
+        };
+        Self {
+            mode,
+            queue_timeout,
+            state: Mutex::new(LimitState {
+                limit,
+                in_flight: 0,
+                baseline: None,
+                rejected: 0,
+            }),
+            released: Notify::new(),
+        }
+    }
+
+    pub fn mode(&self) -> ConcurrencyLimit {
+        self.mode
+    }
+
+    /// The number of requests currently allowed in flight
+    pub fn limit(&self) -> Option<usize> {
+        match self.mode {
+            ConcurrencyLimit::Unlimited => None,
+            _ => Some(self.state.lock().unwrap().capacity()),
+        }
+    }
+
+    pub fn stats(&self) -> ConcurrencyStats {
+        let state = self.state.lock().unwrap();
+        ConcurrencyStats {
+            mode: self.mode.to_string(),
+            limit: (self.mode != ConcurrencyLimit::Unlimited).then(|| state.capacity()),
+            in_flight: state.in_flight,
+            baseline_ms: state.baseline.map(|rtt| rtt.as_secs_f64() * 1000.0),
+            rejected: state.rejected,
+        }
+    }
+
+    /// Wait for a slot to send a backend request
+    ///
+    /// Fails with `AppError::Overloaded` when no slot frees up within the
+    /// queue timeout, or `AppError::DeadlineExceeded` when the request's
+    /// deadline comes first.
+    pub async fn acquire(self: &Arc<Self>) -> Result<BackendPermit, AppError> {
+        if self.mode == ConcurrencyLimit::Unlimited {
+            return Ok(self.permit());
+        }
+        let queue_deadline = tokio::time::Instant::now() + self.queue_timeout;
+        let request_deadline = Deadline::current()
+            .map(|deadline| deadline.instant())
+            .filter(|deadline| *deadline < queue_deadline);
+        let deadline = request_deadline.unwrap_or(queue_deadline);
+        loop {
+            // Registered before checking, so a release in between is not missed
+            let released = self.released.notified();
+            tokio::pin!(released);
+            released.as_mut().enable();
+            {
+                let mut state = self.state.lock().unwrap();
+                if state.in_flight < state.capacity() {
+                    state.in_flight += 1;
+                    return Ok(self.permit());
+                }
+            }
+            if tokio::time::timeout_at(deadline, released).await.is_err() {
+                let mut state = self.state.lock().unwrap();
+                state.rejected += 1;
+                if request_deadline.is_some() {
+                    return Err(AppError::DeadlineExceeded(
+                        "request timeout reached waiting for a backend slot".to_string(),
+                    ));
+                }
+                return Err(AppError::Overloaded(format!(
+                    "{} backend requests already in flight",
+                    state.in_flight
+                )));
+            }
+        }
+    }
+
+    fn permit(self: &Arc<Self>) -> BackendPermit {
+        BackendPermit {
+            limiter: self.clone(),
+            started: Instant::now(),
+            released: false,
+        }
+    }
+
+    fn release(&self, outcome: Outcome, latency: Duration) {
+        if self.mode == ConcurrencyLimit::Unlimited {
+            return;
+        }
+        let mut state = self.state.lock().unwrap();
+        if let ConcurrencyLimit::Adaptive { min, max } = self.mode {
+            let before = state.capacity();
+            update_limit(&mut state, outcome, latency);
+            state.limit = state.limit.clamp(min as f64, max as f64);
+            if state.capacity() != before {
+                debug!(
+                    "Backend concurrency limit {} -> {} after {:?} in {:?}",
+                    before,
+                    state.capacity(),
+                    outcome,
+                    latency
+                );
+            }
+        }
+        state.in_flight -= 1;
+        let free = state.capacity().saturating_sub(state.in_flight);
+        drop(state);
+        // A raised limit can admit more than the one waiter freed by this slot
+        for _ in 0..free.max(1) {
+            self.released.notify_one();
+        }
+    }
+}
+
+/// Adjust the limit for one finished request, counting it as in flight
+fn update_limit(state: &mut LimitState, outcome: Outcome, latency: Duration) {
+    match outcome {
+        Outcome::Ignored => {}
+        Outcome::Overloaded => state.limit *= BACKOFF,
+        Outcome::Success => {
+            let baseline = match state.baseline {
+                Some(baseline) if baseline < latency => {
+                    baseline + (latency - baseline).mul_f64(BASELINE_DRIFT)
+                }
+                _ => latency,
+            };
+            state.baseline = Some(baseline);
+            let rtt = latency.as_secs_f64().max(f64::EPSILON);
+            let gradient = (LATENCY_TOLERANCE * baseline.as_secs_f64() / rtt).clamp(0.5, 1.0);
+            let mut estimate = state.limit * gradient + state.limit.sqrt();
+            // An idle limit gives no evidence the backend could take more
+            if (state.in_flight as f64) < state.limit / 2.0 {
+                estimate = estimate.min(state.limit);
+            }
+            state.limit = state.limit * (1.0 - SMOOTHING) + estimate * SMOOTHING;
+        }
+    }
+}
+
+/// A slot for one backend request, released when dropped
+///
+/// Call [`BackendPermit::record`] once the backend answers so the latency
+/// feeds into the adaptive limit; a permit dropped without a result is
+/// released without adjusting the limit.
+#[derive(Debug)]
+pub struct BackendPermit {
+    limiter: Arc<BackendLimiter>,
+    started: Instant,
+    released: bool,
+}
+
+impl BackendPermit {
+    /// Record the backend's answer and release the slot
+    pub fn record(mut self, outcome: Outcome) {
+        self.released = true;
+        self.limiter.release(outcome, self.started.elapsed());
+    }
+}
+
+impl Drop for BackendPermit {
+    fn drop(&mut self) {
+        if !self.released {
+            self.limiter
+                .release(Outcome::Ignored, self.started.elapsed());
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn state(limit: f64, in_flight: usize) -> LimitState {
+        LimitState {
+            limit,
+            in_flight,
+            baseline: Some(Duration::from_millis(100)),
+            rejected: 0,
+        }
+    }
+
+    #[test]
+    fn test_parse_concurrency_limit() {
+        assert_eq!(
+            "16".parse::<ConcurrencyLimit>().unwrap(),
+            ConcurrencyLimit::Fixed(16)
+        );
+        assert_eq!(
+            "adaptive".parse::<ConcurrencyLimit>().unwrap(),
+            ConcurrencyLimit::Adaptive { min: 1, max: 64 }
+        );
+        assert_eq!(
+            "adaptive:2-20".parse::<ConcurrencyLimit>().unwrap(),
+            ConcurrencyLimit::Adaptive { min: 2, max: 20 }
+        );
+        assert_eq!(
+            "unlimited".parse::<ConcurrencyLimit>().unwrap(),
+            ConcurrencyLimit::Unlimited
+        );
+        assert!("0".parse::<ConcurrencyLimit>().is_err());
+        assert!("adaptive:20-2".parse::<ConcurrencyLimit>().is_err());
+        assert!("many".parse::<ConcurrencyLimit>().is_err());
+    }
+
+    #[test]
+    fn test_limit_follows_latency() {
+        // Busy and as fast as the baseline: grows
+        let mut busy = state(10.0, 10);
+        update_limit(&mut busy, Outcome::Success, Duration::from_millis(100));
+        assert!(busy.limit > 10.0);
+
+        // Idle: holds
+        let mut idle = state(10.0, 2);
+        update_limit(&mut idle, Outcome::Success, Duration::from_millis(100));
+        assert_eq!(idle.limit, 10.0);
+
+        // Four times slower than the baseline: shrinks
+        let mut slow = state(10.0, 10);
+        for _ in 0..5 {
+            update_limit(&mut slow, Outcome::Success, Duration::from_millis(400));
+        }
+        assert!(slow.limit < 9.0);
+
+        let mut failing = state(10.0, 10);
+        update_limit(&mut failing, Outcome::Overloaded, Duration::ZERO);
+        assert_eq!(failing.limit, 9.0);
+        update_limit(&mut failing, Outcome::Ignored, Duration::ZERO);
+        assert_eq!(failing.limit, 9.0);
+    }
+
+    #[tokio::test]
+    async fn test_requests_wait_for_a_slot() {
+        let limiter = Arc::new(BackendLimiter::new(
+            ConcurrencyLimit::Fixed(1),
+            Duration::from_millis(50),
+        ));
+        let first = limiter.acquire().await.unwrap();
+        assert!(matches!(
+            limiter.acquire().await,
+            Err(AppError::Overloaded(_))
+        ));
+        assert_eq!(limiter.stats().rejected, 1);
+
+        let waiting = tokio::spawn({
+            let limiter = limiter.clone();
+            async move { limiter.acquire().await.map(drop) }
+        });
+        first.record(Outcome::Success);
+        assert!(waiting.await.unwrap().is_ok());
+        assert_eq!(limiter.stats().in_flight, 0);
+    }
+}
@@ -0,0 +1,420 @@
+//! Settings files and environment variables
+//!
+//! Every command-line option can also be set in a TOML or YAML file passed
+//! with `--config <path>` (or `ROSSBY_VIS_CONFIG`), keyed by the option's
+//! long name with `_` or `-`. Tables nest with `_`, so these are the same:
+//!
+//! ```toml
+//! api_url = "http://rossby:8000"
+//! log_level = "debug"
+//! cors_origin = ["https://a.example", "https://b.example"]
+//!
+//! [backend]
+//! pool_idle_timeout = 90
+//! ```
+//!
+//! ```yaml
+//! api-url: http://rossby:8000
+//! log:
+//!   level: debug
+//! ```
+//!
+//! Options can also come from `ROSSBY_VIS_<OPTION>` environment variables,
+//! e.g. `ROSSBY_VIS_LOG_LEVEL=debug`. The command line overrides the
+//! environment, which overrides the file; an option given in a layer
+//! replaces all of its values from the layers below, including the repeated
+//! values of options such as `--cors-origin`.
+//!
+//! The layers are turned into command-line arguments placed before the real
+//! ones, so they are validated exactly like the command line.
+
+use clap::{Arg, ArgAction, Command};
+use serde_json::Value;
+use std::{collections::BTreeMap, ffi::OsString, path::Path};
+
+use crate::error::AppError;
+
+/// Prefix of the environment variables setting options
+pub const ENV_PREFIX: &str = "ROSSBY_VIS_";
+
+/// Option naming the settings file
+const CONFIG_OPTION: &str = "config";
+
+/// Arguments of one layer, by option
+type Layer = BTreeMap<String, Vec<OsString>>;
+
+/// The command-line arguments `cli`, with the settings of the file named by
+/// `--config` and of the `ROSSBY_VIS_*` variables among `env` placed before
+/// them
+pub fn layered_args(
+    command: &Command,
+    cli: Vec<OsString>,
+    env: impl IntoIterator<Item = (String, String)>,
+) -> Result<Vec<OsString>, AppError> {
+    let env: Vec<(String, String)> = env
+        .into_iter()
+        .filter(|(name, _)| name.starts_with(ENV_PREFIX))
+        .collect();
+
+    let config_path = option_value(&cli, CONFIG_OPTION).or_else(|| {
+        env.iter()
+            .find(|(name, _)| name[ENV_PREFIX.len()..].eq_ignore_ascii_case(CONFIG_OPTION))
+            .map(|(_, value)| OsString::from(value))
+    });
+    let mut layers = match &config_path {
+        Some(path) => load_file(command, Path::new(path))?,
+        None => Layer::new(),
+    };
+
+    for (name, value) in &env {
+        let key = name[ENV_PREFIX.len()..].to_ascii_lowercase();
+        if key == CONFIG_OPTION {
+            continue;
+        }
+        let arg = find_arg(command, &key)
+            .ok_or_else(|| AppError::ConfigError(format!("Unknown option in {}", name)))?;
+        let args = env_args(arg, value)
+            .map_err(|reason| AppError::ConfigError(format!("Invalid {}: {}", name, reason)))?;
+        layers.insert(arg.get_id().to_string(), args);
+    }
+
+    // Options on the command line replace the lower layers entirely
+    layers.retain(|id, _| {
+        command
+            .get_arguments()
+            .find(|arg| arg.get_id() == id.as_str())
+            .is_none_or(|arg| !given_on_command_line(arg, &cli))
+    });
+
+    let mut args: Vec<OsString> = cli.iter().take(1).cloned().collect();
+    args.extend(layers.into_values().flatten());
+    args.extend(cli.into_iter().skip(1));
+    Ok(args)
+}
+
+/// Long names of the options whose values differ between the arguments
+/// `before` and `after`, defaults included
+pub fn changed_options(
+    command: &Command,
+    before: &[OsString],
+    after: &[OsString],
+) -> Result<Vec<String>, AppError> {
+    let parse = |args: &[OsString]| {
+        command
+            .clone()
+            .try_get_matches_from(args)
+            .map_err(|e| AppError::ConfigError(e.to_string()))
+    };
+    let (before, after) = (parse(before)?, parse(after)?);
+    let values = |matches: &clap::ArgMatches, id: &str| -> Vec<OsString> {
+        matches
+            .try_get_raw(id)
+            .ok()
+            .flatten()
+            .map(|values| values.map(OsString::from).collect())
+            .unwrap_or_default()
+    };
+    Ok(command
+        .get_arguments()
+        .filter(|arg| !matches!(arg.get_action(), ArgAction::Help | ArgAction::Version))
+        .filter(|arg| {
+            let id = arg.get_id().as_str();
+            values(&before, id) != values(&after, id)
+        })
+        .map(|arg| {
+            arg.get_long()
+                .map_or_else(|| arg.get_id().to_string(), str::to_string)
+        })
+        .collect())
+}
+
+/// The value of `--{long} VALUE` or `--{long}=VALUE` on the command line
+fn option_value(cli: &[OsString], long: &str) -> Option<OsString> {
+    let flag = format!("--{}", long);
+    let prefix = format!("--{}=", long);
+    let mut tokens = cli.iter().skip(1);
+    while let Some(token) = tokens.next() {
+        let token = token.to_string_lossy();
+        if token == flag {
+            return tokens.next().cloned();
+        }
+        if let Some(value) = token.strip_prefix(&prefix) {
+            return Some(OsString::from(value));
+        }
+    }
+    None
+}
+
+fn given_on_command_line(arg: &Arg, cli: &[OsString]) -> bool {
+    cli.iter().skip(1).any(|token| {
+        let token = token.to_string_lossy();
+        let long = arg.get_long().is_some_and(|long| {
+            token
+                .strip_prefix("--")
+                .and_then(|rest| rest.strip_prefix(long))
+                .is_some_and(|rest| rest.is_empty() || rest.starts_with('='))
+        });
+        let short = arg.get_short().is_some_and(|short| {
+            token
+                .strip_prefix('-')
+                .is_some_and(|rest| !rest.starts_with('-') && rest.starts_with(short))
+        });
+        long || short
+    })
+}
+
+/// The option named `key`, in snake or kebab case
+fn find_arg<'a>(command: &'a Command, key: &str) -> Option<&'a Arg> {
+    let long = key.replace('_', "-");
+    if long == CONFIG_OPTION {
+        return None;
+    }
+    command
+        .get_arguments()
+        .find(|arg| arg.get_long() == Some(long.as_str()))
+}
+
+/// Load the settings of a TOML or YAML file
+fn load_file(command: &Command, path: &Path) -> Result<Layer, AppError> {
+    let error = |message: String| {
+        AppError::ConfigError(format!(
+            "Cannot load settings from {}: {}",
+            path.display(),
+            message
+        ))
+    };
+    let contents = std::fs::read_to_string(path).map_err(|e| error(e.to_string()))?;
+    let extension = path
+        .extension()
+        .and_then(|e| e.to_str())
+        .unwrap_or_default()
+        .to_ascii_lowercase();
+    let settings: Value = match extension.as_str() {
+        "toml" => toml::from_str(&contents).map_err(|e| error(e.to_string()))?,
+        "yaml" | "yml" => serde_yaml::from_str(&contents).map_err(|e| error(e.to_string()))?,
+        _ => return Err(error("expected a .toml, .yaml or .yml file".to_string())),
+    };
+    settings_layer(command, &settings).map_err(|e| error(e.to_string()))
+}
+
+/// Arguments for a settings document, keyed by option name
+fn settings_layer(command: &Command, settings: &Value) -> Result<Layer, AppError> {
+    let Value::Object(table) = settings else {
+        return Err(AppError::ConfigError(
+            "settings must be a table of options".to_string(),
+        ));
+    };
+    let mut flattened = Vec::new();
+    flatten("", table, &mut flattened);
+
+    let mut layer = Layer::new();
+    for (key, value) in flattened {
+        let arg = find_arg(command, &key)
+            .ok_or_else(|| AppError::ConfigError(format!("unknown option '{}'", key)))?;
+        let args = value_args(arg, value)
+            .map_err(|reason| AppError::ConfigError(format!("invalid '{}': {}", key, reason)))?;
+        if layer.insert(arg.get_id().to_string(), args).is_some() {
+            return Err(AppError::ConfigError(format!(
+                "option '{}' is given more than once",
+                key
+            )));
+        }
+    }
+    Ok(layer)
+}
+
+/// Leaf settings of nested tables, with their keys joined by `_`
+fn flatten<'a>(
+    prefix: &str,
+    table: &'a serde_json::Map<String, Value>,
+    leaves: &mut Vec<(String, &'a Value)>,
+) {
+    for (key, value) in table {
+        let key = key.replace('-', "_");
+        let key = if prefix.is_empty() {
+            key
+        } else {
+            format!("{}_{}", prefix, key)
+        };
+        match value {
+            Value::Object(nested) => flatten(&key, nested, leaves),
+            value => leaves.push((key, value)),
+        }
+    }
+}
+
+fn flag(arg: &Arg) -> OsString {
+    OsString::from(format!("--{}", arg.get_long().unwrap_or_default()))
+}
+
+/// Arguments setting `arg` to a settings value
+fn value_args(arg: &Arg, value: &Value) -> Result<Vec<OsString>, String> {
+    let scalar = |value: &Value| match value {
+        Value::String(s) => Ok(s.clone()),
+        Value::Number(n) => Ok(n.to_string()),
+        Value::Bool(b) => Ok(b.to_string()),
+        _ => Err("expected a string, number or boolean".to_string()),
+    };
+    match (arg.get_action(), value) {
+        (ArgAction::SetTrue, Value::Bool(true)) => Ok(vec![flag(arg)]),
+        (ArgAction::SetTrue, Value::Bool(false)) => Ok(Vec::new()),
+        (ArgAction::SetTrue, _) => Err("expected true or false".to_string()),
+        (ArgAction::Append, Value::Array(values)) => values
+            .iter()
+            .map(|value| Ok(vec![flag(arg), OsString::from(scalar(value)?)]))
+            .collect::<Result<Vec<_>, String>>()
+            .map(|args| args.into_iter().flatten().collect()),
+        (_, Value::Array(_)) => Err("takes a single value".to_string()),
+        (_, value) => Ok(vec![flag(arg), OsString::from(scalar(value)?)]),
+    }
+}
+
+/// Arguments setting `arg` to the value of an environment variable
+fn env_args(arg: &Arg, value: &str) -> Result<Vec<OsString>, String> {
+    match arg.get_action() {
+        ArgAction::SetTrue => match value.to_ascii_lowercase().as_str() {
+            "true" | "1" | "yes" | "on" => Ok(vec![flag(arg)]),
+            "false" | "0" | "no" | "off" | "" => Ok(Vec::new()),
+            _ => Err("expected true or false".to_string()),
+        },
+        _ => Ok(vec![flag(arg), OsString::from(value)]),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use clap::value_parser;
+    use serde_json::json;
+
+    fn command() -> Command {
+        Command::new("rossby-vis")
+            .arg(
+                Arg::new("port")
+                    .short('p')
+                    .long("port")
+                    .value_parser(value_parser!(u16)),
+            )
+            .arg(Arg::new("api_url").long("api-url"))
+            .arg(Arg::new("log_level").long("log-level"))
+            .arg(Arg::new("config").long("config"))
+            .arg(
+                Arg::new("cors_origin")
+                    .long("cors-origin")
+                    .action(ArgAction::Append),
+            )
+            .arg(
+                Arg::new("mobile_index")
+                    .long("mobile-index")
+                    .action(ArgAction::SetTrue),
+            )
+    }
+
+    fn args(values: &[&str]) -> Vec<OsString> {
+        values.iter().map(OsString::from).collect()
+    }
+
+    #[test]
+    fn test_settings_layer() {
+        let settings = json!({
+            "api-url": "http://rossby:8000",
+            "log": {"level": "debug"},
+            "cors_origin": ["https://a", "https://b"],
+            "mobile_index": true,
+            "port": 9000
+        });
+        let layer = settings_layer(&command(), &settings).unwrap();
+        let args: Vec<OsString> = layer.into_values().flatten().collect();
+        let matches = command()
+            .try_get_matches_from(std::iter::once(OsString::from("rossby-vis")).chain(args))
+            .unwrap();
+        assert_eq!(matches.get_one::<u16>("port"), Some(&9000));
+        assert_eq!(matches.get_one::<String>("log_level").unwrap(), "debug");
+        assert_eq!(
+            matches.get_many::<String>("cors_origin").unwrap().count(),
+            2
+        );
+        assert!(matches.get_flag("mobile_index"));
+    }
+
+    #[test]
+    fn test_invalid_settings() {
+        for settings in [
+            json!({"colour": "red"}),
+            json!({"config": "other.toml"}),
+            json!({"port": [1, 2]}),
+            json!({"mobile_index": "yes"}),
+            json!({"log_level": "info", "log": {"level": "debug"}}),
+            json!(["port", 8080]),
+        ] {
+            assert!(
+                settings_layer(&command(), &settings).is_err(),
+                "{}",
+                settings
+            );
+        }
+    }
+
+    #[test]
+    fn test_layering() {
+        let env = vec![
+            ("ROSSBY_VIS_LOG_LEVEL".to_string(), "warn".to_string()),
+            ("ROSSBY_VIS_MOBILE_INDEX".to_string(), "1".to_string()),
+            ("HOME".to_string(), "/root".to_string()),
+        ];
+        let cli = args(&["rossby-vis", "-p", "9000", "--api-url=http://cli"]);
+        let layered = layered_args(&command(), cli, env).unwrap();
+        assert_eq!(
+            layered,
+            args(&[
+                "rossby-vis",
+                "--log-level",
+                "warn",
+                "--mobile-index",
+                "-p",
+                "9000",
+                "--api-url=http://cli"
+            ])
+        );
+
+        // The command line replaces the environment
+        let env = vec![("ROSSBY_VIS_PORT".to_string(), "7000".to_string())];
+        let layered = layered_args(&command(), args(&["rossby-vis", "--port", "9000"]), env);
+        assert_eq!(layered.unwrap(), args(&["rossby-vis", "--port", "9000"]));
+
+        let env = vec![("ROSSBY_VIS_PROT".to_string(), "7000".to_string())];
+        assert!(layered_args(&command(), args(&["rossby-vis"]), env).is_err());
+    }
+
+    #[test]
+    fn test_changed_options() {
+        let before = args(&["rossby-vis", "--api-url", "http://a", "--log-level", "info"]);
+        let after = args(&[
+            "rossby-vis",
+            "--api-url",
+            "http://a",
+            "--log-level=debug",
+            "--cors-origin",
+            "https://a",
+            "-p",
+            "9000",
+        ]);
+        assert_eq!(
+            changed_options(&command(), &before, &after).unwrap(),
+            vec!["port", "log-level", "cors-origin"]
+        );
+        assert!(changed_options(&command(), &before, &before)
+            .unwrap()
+            .is_empty());
+        assert!(changed_options(&command(), &before, &args(&["rossby-vis", "-p", "x"])).is_err());
+    }
+
+    #[test]
+    fn test_option_value() {
+        let cli = args(&["rossby-vis", "--config", "a.toml"]);
+        assert_eq!(option_value(&cli, "config"), Some(OsString::from("a.toml")));
+        let cli = args(&["rossby-vis", "--config=b.yaml"]);
+        assert_eq!(option_value(&cli, "config"), Some(OsString::from("b.yaml")));
+        assert_eq!(option_value(&args(&["rossby-vis"]), "config"), None);
+    }
+}
@@ -0,0 +1,375 @@
+//! Several backends served side by side as named datasets
+//!
+//! `--api-url` takes `name=url` pairs, e.g.
+//! `--api-url era5=http://a:8000,gfs=http://b:8000`. The first dataset is
+//! the default and keeps the unprefixed routes; every dataset, the default
+//! included, is also reachable under its name:
+//!
+//! - `/proxy/{dataset}/metadata` and `/proxy/{dataset}/data`
+//! - `/data/weather/{dataset}/current/...` and
+//!   `/data/weather/{dataset}/{yyyy}/{mm}/{dd}/...`
+//!
+//! `/api/datasets` lists the names for the frontend's dataset switcher.
+//! Each dataset has its own schema detection, metadata catalog and circuit
+//! breaker but shares the server's caches, limits and settings.
+
+use axum::{
+    extract::{Path, Query, State},
+    http::{HeaderMap, Uri},
+    response::Response,
+    Json,
+};
+use serde_json::{json, Value};
+use std::{borrow::Cow, sync::Arc};
+use tracing::info;
+
+use crate::{
+    backend::{BackendCompat, BackendSchema},
+    catalog::CatalogService,
+    endpoint::BackendEndpoint,
+    error::{AppError, ErrorBody},
+    handlers::{
+        earth_current_data, earth_dated_data, proxy_data, proxy_metadata, DataQuery, EarthQuery,
+    },
+    resilience::CircuitBreaker,
+    server::AppState,
+};
+
+/// Names that would be read as another route in a dataset's place
+const RESERVED_NAMES: [&str; 3] = ["current", "metadata", "data"];
+
+/// A named backend
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DatasetConfig {
+    pub name: String,
+    pub api_url: String,
+}
+
+/// The backends of `--api-url`: the URL of the default backend and the
+/// datasets, empty when the one backend is not named
+pub fn parse_api_urls(values: &[String]) -> Result<(String, Vec<DatasetConfig>), AppError> {
+    let values: Vec<&str> = values
+        .iter()
+        .map(|v| v.trim())
+        .filter(|v| !v.is_empty())
+        .collect();
+    match values.as_slice() {
+        [] => Err(AppError::ConfigError("--api-url is empty".to_string())),
+        [url] if named(url).is_none() => Ok((url.to_string(), Vec::new())),
+        values => {
+            let datasets = values
+                .iter()
+                .map(|value| {
+                    let (name, api_url) = named(value).ok_or_else(|| {
+                        AppError::ConfigError(format!(
+                            "Name each backend as name=url when serving several, not '{}'",
+                            value
+                        ))
+                    })?;
+                    Ok(DatasetConfig {
+                        name: name.to_string(),
+                        api_url: api_url.to_string(),
+                    })
+                })
+                .collect::<Result<Vec<_>, AppError>>()?;
+            Ok((datasets[0].api_url.clone(), datasets))
+        }
+    }
+}
+
+/// The name and URL of a `name=url` value
+fn named(value: &str) -> Option<(&str, &str)> {
+    let (name, url) = value.split_once('=')?;
+    (!name.is_empty() && !name.contains([':', '/'])).then_some((name, url))
+}
+
+fn validate_name(name: &str) -> Result<(), AppError> {
+    let valid = name.starts_with(|c: char| c.is_ascii_alphabetic())
+        && name
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
+        && !RESERVED_NAMES.contains(&name);
+    if valid {
+        Ok(())
+    } else {
+        Err(AppError::ConfigError(format!(
+            "Invalid dataset name '{}': use letters, digits, '-' and '_', starting with a letter, other than {}",
+            name,
+            RESERVED_NAMES.join(", ")
+        )))
+    }
+}
+
+struct Dataset {
+    name: String,
+    /// State reaching the dataset's backend, `None` for the default dataset
+    /// served by the server's own state
+    state: Option<Arc<AppState>>,
+}
+
+/// The datasets of a server, in the order they were configured
+#[derive(Default)]
+pub struct Datasets {
+    entries: Vec<Dataset>,
+}
+
+impl Datasets {
+    /// Datasets for `configs`, the first being the backend of `state`
+    pub fn new(
+        state: &AppState,
+        configs: Vec<DatasetConfig>,
+        schema: BackendSchema,
+    ) -> Result<Self, AppError> {
+        let mut entries: Vec<Dataset> = Vec::new();
+        for (i, config) in configs.into_iter().enumerate() {
+            validate_name(&config.name)?;
+            if entries.iter().any(|d| d.name == config.name) {
+                return Err(AppError::ConfigError(format!(
+                    "Dataset '{}' is named twice",
+                    config.name
+                )));
+            }
+            let dataset_state = if i == 0 {
+                None
+            } else {
+                let endpoint: BackendEndpoint = config.api_url.parse()?;
+                info!("Serving dataset {} from {}", config.name, endpoint);
+                let mut dataset = state.clone();
+                dataset.api_url = endpoint.base_url;
+                dataset.backend_credentials = endpoint.credentials;
+                dataset.backend = BackendCompat::new(schema);
+                dataset.catalog = Arc::new(CatalogService::default());
+                dataset.circuit_breaker =
+                    Arc::new(CircuitBreaker::new(state.circuit_breaker.config()));
+                // A mask file matches the default backend's grid, not this one
+                dataset.land_sea_mask.values = None;
+                Some(Arc::new(dataset))
+            };
+            entries.push(Dataset {
+                name: config.name,
+                state: dataset_state,
+            });
+        }
+        Ok(Self { entries })
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    /// Names of the datasets, the default first
+    pub fn names(&self) -> impl Iterator<Item = &str> {
+        self.entries.iter().map(|d| d.name.as_str())
+    }
+
+    fn get(&self, name: &str) -> Option<&Dataset> {
+        self.entries.iter().find(|d| d.name == name)
+    }
+
+    /// The state reaching the backend of dataset `name`
+    pub fn state<'a>(&'a self, state: &'a Arc<AppState>, name: &str) -> Option<&'a Arc<AppState>> {
+        self.get(name).map(|d| d.state.as_ref().unwrap_or(state))
+    }
+}
+
+/// The state of dataset `name`, or a 404 naming the known datasets
+fn dataset_state(state: &Arc<AppState>, name: &str) -> Result<Arc<AppState>, AppError> {
+    state
+        .datasets
+        .state(state, name)
+        .cloned()
+        .ok_or_else(|| AppError::NotFound(format!("Unknown dataset '{}'", name)))
+}
+
+/// Handler for `/api/datasets` - the datasets clients can switch between
+#[utoipa::path(
+    get,
+    path = "/api/datasets",
+    tag = "service",
+    summary = "Datasets clients can switch between",
+    responses(
+        (status = 200, description = "Dataset names, the default first", body = serde_json::Value),
+    )
+)]
+pub async fn list_datasets(State(state): State<Arc<AppState>>) -> Json<Value> {
+    let datasets: Vec<Value> = state
+        .datasets
+        .names()
+        .enumerate()
+        .map(|(i, name)| json!({"name": name, "default": i == 0}))
+        .collect();
+    Json(json!({ "datasets": datasets }))
+}
+
+/// Handler for `/proxy/{dataset}/metadata`
+#[utoipa::path(
+    get,
+    path = "/proxy/{dataset}/metadata",
+    tag = "proxy",
+    summary = "Backend metadata of a dataset",
+    params(("dataset" = String, Path, description = "Name listed by `/api/datasets`")),
+    responses(
+        (status = 200, description = "Dimensions, coordinates and variables of the dataset", body = serde_json::Value),
+        (status = 304, description = "Unchanged since the request's `If-None-Match`"),
+        (status = 404, description = "No such dataset", body = ErrorBody),
+        (status = 502, description = "The backend failed or sent invalid metadata", body = ErrorBody),
+    )
+)]
+pub async fn dataset_metadata(
+    State(state): State<Arc<AppState>>,
+    Path(dataset): Path<String>,
+    uri: Uri,
+    headers: HeaderMap,
+) -> Result<Response, AppError> {
+    proxy_metadata(State(dataset_state(&state, &dataset)?), uri, headers).await
+}
+
+/// Handler for `/proxy/{dataset}/data`
+#[utoipa::path(
+    get,
+    path = "/proxy/{dataset}/data",
+    tag = "proxy",
+    summary = "Backend data of a dataset, streamed",
+    description = "Selects by coordinate value as the backend does, e.g. `time=700464` or `latitude_range=-10,10`, or by position, e.g. `time_index=5` or `lat_indices=0:360:2`. Other parameters are passed on to the backend.",
+    params(
+        ("dataset" = String, Path, description = "Name listed by `/api/datasets`"),
+        DataQuery,
+    ),
+    responses(
+        (status = 200, description = "The backend's data response", body = serde_json::Value),
+        (status = 400, description = "Invalid selection", body = ErrorBody),
+        (status = 404, description = "No such dataset", body = ErrorBody),
+        (status = 502, description = "The backend failed", body = ErrorBody),
+        (status = 504, description = "The backend did not answer in time", body = ErrorBody),
+    )
+)]
+pub async fn dataset_data(
+    State(state): State<Arc<AppState>>,
+    Path(dataset): Path<String>,
+    query: Query<DataQuery>,
+    uri: Uri,
+    headers: HeaderMap,
+) -> Result<Response, AppError> {
+    proxy_data(State(dataset_state(&state, &dataset)?), query, uri, headers).await
+}
+
+/// Handler for `/data/weather/{first}/{rest}`: a dataset's Earth paths when
+/// `first` names a dataset, otherwise Earth's dated paths of the default
+/// dataset with `first` as the year
+#[utoipa::path(
+    get,
+    path = "/data/weather/{dataset}/{path}",
+    tag = "earth",
+    summary = "Earth data of a dataset, or at a date",
+    params(
+        (
+            "dataset" = String,
+            Path,
+            description = "A dataset name, or the year of a dated path of the default dataset"
+        ),
+        (
+            "path" = String,
+            Path,
+            description = "`current/{file}` as in `/data/weather/current/{file}`, or `{mm}/{dd}/{hhmm}-{product}-{surface}-{level}-gfs-1.0.json` for the timestep nearest that time; contains slashes"
+        ),
+        EarthQuery,
+    ),
+    responses(
+        (
+            status = 200,
+            description = "Earth records: a header and a data array per component",
+            content(
+                (serde_json::Value = "application/json"),
+                (serde_json::Value = "application/msgpack"),
+                (serde_json::Value = "application/vnd.rossby.packed+json"),
+            )
+        ),
+        (status = 304, description = "Unchanged since the request's `If-None-Match` or `If-Modified-Since`"),
+        (status = 400, description = "Invalid path or query", body = ErrorBody),
+        (status = 404, description = "No such variable or level", body = ErrorBody),
+        (status = 502, description = "The backend failed", body = ErrorBody),
+    )
+)]
+pub async fn earth_weather_data(
+    State(state): State<Arc<AppState>>,
+    Path((first, rest)): Path<(String, String)>,
+    query: Query<EarthQuery>,
+    uri: Uri,
+    headers: HeaderMap,
+) -> Result<Response, AppError> {
+    let (state, path) = match state.datasets.state(&state, &first) {
+        Some(dataset) => (dataset.clone(), Cow::Borrowed(rest.as_str())),
+        None => (state.clone(), Cow::Owned(format!("{}/{}", first, rest))),
+    };
+    let segments: Vec<String> = path.split('/').map(String::from).collect();
+    match <[String; 4]>::try_from(segments) {
+        Ok([year, month, day, file]) => {
+            earth_dated_data(
+                State(state),
+                Path((year, month, day, file)),
+                query,
+                uri,
+                headers,
+            )
+            .await
+        }
+        Err(segments) => match segments.as_slice() {
+            [current, file] if current == "current" => {
+                earth_current_data(State(state), Path(file.clone()), query, uri, headers).await
+            }
+            _ => Err(AppError::NotFound(format!(
+                "No Earth data at '{}'",
+                uri.path()
+            ))),
+        },
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn urls(values: &[&str]) -> Result<(String, Vec<DatasetConfig>), AppError> {
+        parse_api_urls(&values.iter().map(|v| v.to_string()).collect::<Vec<_>>())
+    }
+
+    #[test]
+    fn test_parse_api_urls() {
+        let (url, datasets) = urls(&["http://a:8000"]).unwrap();
+        assert_eq!(url, "http://a:8000");
+        assert!(datasets.is_empty());
+
+        let (url, datasets) = urls(&["era5=http://a:8000", "gfs=http://b:8000"]).unwrap();
+        assert_eq!(url, "http://a:8000");
+        assert_eq!(
+            datasets,
+            vec![
+                DatasetConfig {
+                    name: "era5".to_string(),
+                    api_url: "http://a:8000".to_string()
+                },
+                DatasetConfig {
+                    name: "gfs".to_string(),
+                    api_url: "http://b:8000".to_string()
+                }
+            ]
+        );
+
+        // A query string is not taken for a name
+        let (url, datasets) = urls(&["http://a:8000/?token=x"]).unwrap();
+        assert_eq!(url, "http://a:8000/?token=x");
+        assert!(datasets.is_empty());
+
+        assert!(urls(&["http://a:8000", "gfs=http://b:8000"]).is_err());
+        assert!(urls(&[""]).is_err());
+    }
+
+    #[test]
+    fn test_dataset_names() {
+        assert!(validate_name("era5").is_ok());
+        assert!(validate_name("gfs_0p25-hourly").is_ok());
+        assert!(validate_name("current").is_err());
+        assert!(validate_name("2024").is_err());
+        assert!(validate_name("a/b").is_err());
+    }
+}
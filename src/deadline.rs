@@ -0,0 +1,152 @@
+//! Request deadlines from the `X-Request-Timeout` header
+//!
+//! A client may say how long it is willing to wait, in seconds (`2.5`) or
+//! with a unit (`800ms`, `3s`). The deadline it implies covers the whole
+//! request: every backend request made for it, metadata and data alike, is
+//! sent with the remaining time as its timeout and forwards that remainder
+//! in its own `X-Request-Timeout`. Once the deadline passes, the request
+//! fails with `504 Gateway Timeout` instead of leaving the client waiting.
+
+use axum::{
+    http::{HeaderMap, Request},
+    middleware::Next,
+    response::{IntoResponse, Response},
+};
+use std::{future::Future, time::Duration};
+use tokio::time::Instant;
+use tracing::debug;
+
+use crate::error::AppError;
+
+/// Header carrying the time a client is willing to wait
+pub const REQUEST_TIMEOUT_HEADER: &str = "x-request-timeout";
+
+tokio::task_local! {
+    static CURRENT: Deadline;
+}
+
+/// The point in time by which a request must be answered
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Deadline(Instant);
+
+impl Deadline {
+    /// A deadline `timeout` from now
+    pub fn after(timeout: Duration) -> Self {
+        Self(Instant::now() + timeout)
+    }
+
+    /// The deadline requested by a client's headers, if any
+    pub fn from_headers(headers: &HeaderMap) -> Result<Option<Self>, AppError> {
+        let Some(value) = headers.get(REQUEST_TIMEOUT_HEADER) else {
+            return Ok(None);
+        };
+        value
+            .to_str()
+            .ok()
+            .and_then(parse_timeout)
+            .map(|timeout| Some(Self::after(timeout)))
+            .ok_or_else(|| {
+                AppError::RequestError(format!(
+                    "Invalid {} header; expected seconds such as 2.5, or 800ms",
+                    REQUEST_TIMEOUT_HEADER
+                ))
+            })
+    }
+
+    pub fn instant(&self) -> Instant {
+        self.0
+    }
+
+    /// Time left until the deadline, zero once it has passed
+    pub fn remaining(&self) -> Duration {
+        self.0.saturating_duration_since(Instant::now())
+    }
+
+    pub fn is_expired(&self) -> bool {
+        self.remaining().is_zero()
+    }
+
+    /// Run `future` with this as the deadline of the current request
+    pub async fn scope<F: Future>(self, future: F) -> F::Output {
+        CURRENT.scope(self, future).await
+    }
+
+    /// The deadline of the request being handled, if the client set one
+    pub fn current() -> Option<Self> {
+        CURRENT.try_with(|deadline| *deadline).ok()
+    }
+}
+
+/// Fail with `504 Gateway Timeout` if the current request's deadline has
+/// passed before `stage`
+pub fn check(stage: &str) -> Result<(), AppError> {
+    match Deadline::current() {
+        Some(deadline) if deadline.is_expired() => Err(AppError::DeadlineExceeded(format!(
+            "request timeout reached before {}",
+            stage
+        ))),
+        _ => Ok(()),
+    }
+}
+
+/// Parse a timeout given in seconds, optionally with an `ms` or `s` unit
+pub fn parse_timeout(value: &str) -> Option<Duration> {
+    let value = value.trim();
+    let (number, scale) = match value.strip_suffix("ms") {
+        Some(ms) => (ms, 0.001),
+        None => (value.strip_suffix('s').unwrap_or(value), 1.0),
+    };
+    number
+        .trim()
+        .parse::<f64>()
+        .ok()
+        .filter(|n| n.is_finite() && *n > 0.0)
+        .and_then(|n| Duration::try_from_secs_f64(n * scale).ok())
+}
+
+/// Apply the `X-Request-Timeout` of each request to the handling of it
+pub async fn deadline_middleware<B>(request: Request<B>, next: Next<B>) -> Response {
+    let deadline = match Deadline::from_headers(request.headers()) {
+        Ok(Some(deadline)) => deadline,
+        Ok(None) => return next.run(request).await,
+        Err(e) => return e.into_response(),
+    };
+    let path = request.uri().path().to_string();
+    match tokio::time::timeout_at(deadline.instant(), deadline.scope(next.run(request))).await {
+        Ok(response) => response,
+        Err(_) => {
+            debug!(path = %path, "Request timeout reached");
+            AppError::DeadlineExceeded("request timeout reached".to_string()).into_response()
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_timeout() {
+        assert_eq!(parse_timeout("2.5"), Some(Duration::from_millis(2500)));
+        assert_eq!(parse_timeout("800ms"), Some(Duration::from_millis(800)));
+        assert_eq!(parse_timeout(" 3s "), Some(Duration::from_secs(3)));
+        assert_eq!(parse_timeout("0"), None);
+        assert_eq!(parse_timeout("-1"), None);
+        assert_eq!(parse_timeout("soon"), None);
+        assert_eq!(parse_timeout("1e300"), None);
+    }
+
+    #[tokio::test]
+    async fn test_scope_and_check() {
+        assert_eq!(Deadline::current(), None);
+        assert!(check("fetching data").is_ok());
+
+        let deadline = Deadline::after(Duration::from_secs(60));
+        let current = deadline.scope(async { Deadline::current() }).await;
+        assert_eq!(current, Some(deadline));
+
+        let expired = Deadline::after(Duration::ZERO);
+        let result = expired.scope(async { check("fetching data") }).await;
+        assert!(matches!(result, Err(AppError::DeadlineExceeded(_))));
+    }
+}
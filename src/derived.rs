@@ -0,0 +1,1046 @@
+//! Derived products computed from dataset fields
+//!
+//! Wind chill and heat index are computed on the fly from the surface
+//! temperature, wind and humidity fields of the dataset, and integrated vapour
+//! transport (IVT) from specific humidity and wind on pressure levels.
+//! Products whose inputs exist are registered in the metadata catalog as
+//! ordinary variables, so the frontend can list and request them like any
+//! other overlay. Custom variables from the [`DerivedRegistry`] are served
+//! the same way.
+
+use serde_json::{json, Value};
+
+use crate::{
+    analysis::{data_query, time_selection, variable_units},
+    error::AppError,
+    grid::{
+        coordinate_values, is_latitude_dimension, is_longitude_dimension, is_vertical_dimension,
+        variable_dimensions, DataArray,
+    },
+    handlers::{fetch_data, find_wind_components},
+    plugins::{DerivedRegistry, DerivedVariable, InputField},
+    server::AppState,
+    vectors::pair_vector_components,
+};
+
+/// Common names of the near-surface air temperature variable
+const TEMPERATURE_NAMES: [&str; 5] = ["t2m", "2t", "tas", "t2", "temp2m"];
+/// Common names of the near-surface dewpoint variable
+const DEWPOINT_NAMES: [&str; 4] = ["d2m", "2d", "td2m", "dpt2m"];
+/// Common names of the near-surface relative humidity variable
+const RELATIVE_HUMIDITY_NAMES: [&str; 5] = ["r2", "rh2m", "rh", "hurs", "relative_humidity"];
+/// Common names of the specific humidity variable on pressure levels
+const SPECIFIC_HUMIDITY_NAMES: [&str; 4] = ["q", "shum", "hus", "specific_humidity"];
+
+/// Attribute marking a catalog entry as computed by this server
+const DERIVED_FROM_ATTRIBUTE: &str = "derived_from";
+
+/// Standard gravity in m/s²
+const GRAVITY: f64 = 9.80665;
+/// Top of the IVT integration column in Pa
+const IVT_TOP_PA: f64 = 30_000.0;
+/// Bottom of the IVT integration column in Pa
+const IVT_BOTTOM_PA: f64 = 100_000.0;
+/// Units of the integrated vapour transport products
+const IVT_UNITS: &str = "kg m**-1 s**-1";
+
+/// A product computed from other dataset variables
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DerivedProduct {
+    /// Wind chill temperature from air temperature and wind speed
+    WindChill,
+    /// Heat index from air temperature and humidity
+    HeatIndex,
+    /// Magnitude of the integrated vapour transport
+    Ivt,
+    /// Eastward component of the integrated vapour transport
+    IvtEastward,
+    /// Northward component of the integrated vapour transport
+    IvtNorthward,
+}
+
+/// Humidity input for the heat index
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum HumidityInput {
+    /// Relative humidity in percent
+    RelativeHumidity(String),
+    /// Dewpoint temperature, in the same units as its metadata declares
+    Dewpoint(String),
+}
+
+/// Dataset variables needed to compute a derived product
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum DerivedInputs {
+    /// Temperature plus the u and v wind components
+    WindChill {
+        temperature: String,
+        u_wind: String,
+        v_wind: String,
+    },
+    /// Temperature plus a humidity field
+    HeatIndex {
+        temperature: String,
+        humidity: HumidityInput,
+    },
+    /// Specific humidity and wind components sharing a pressure-level dimension
+    VaporTransport {
+        humidity: String,
+        u_wind: String,
+        v_wind: String,
+        level_dimension: String,
+    },
+}
+
+impl DerivedProduct {
+    /// All products in catalog order
+    pub const ALL: [DerivedProduct; 5] = [
+        DerivedProduct::WindChill,
+        DerivedProduct::HeatIndex,
+        DerivedProduct::Ivt,
+        DerivedProduct::IvtEastward,
+        DerivedProduct::IvtNorthward,
+    ];
+
+    /// Variable name under which the product appears in the catalog
+    ///
+    /// The IVT components follow the `u*`/`v*` naming used to pair vector
+    /// components, so they animate like wind.
+    pub fn name(self) -> &'static str {
+        match self {
+            DerivedProduct::WindChill => "wind_chill",
+            DerivedProduct::HeatIndex => "heat_index",
+            DerivedProduct::Ivt => "ivt",
+            DerivedProduct::IvtEastward => "uivt",
+            DerivedProduct::IvtNorthward => "vivt",
+        }
+    }
+
+    /// Human-readable description used as the `long_name` attribute
+    pub fn long_name(self) -> &'static str {
+        match self {
+            DerivedProduct::WindChill => "Wind chill temperature",
+            DerivedProduct::HeatIndex => "Heat index apparent temperature",
+            DerivedProduct::Ivt => "Vertically integrated humidity flux magnitude",
+            DerivedProduct::IvtEastward => "Vertical integral of eastward humidity flux",
+            DerivedProduct::IvtNorthward => "Vertical integral of northward humidity flux",
+        }
+    }
+
+    /// Look up a product by its catalog name
+    pub fn from_name(name: &str) -> Option<Self> {
+        Self::ALL.into_iter().find(|p| p.name() == name)
+    }
+
+    /// Whether the product integrates over the vertical dimension
+    pub fn is_vertical_integral(self) -> bool {
+        matches!(
+            self,
+            DerivedProduct::Ivt | DerivedProduct::IvtEastward | DerivedProduct::IvtNorthward
+        )
+    }
+
+    /// Find the input variables for this product, if the dataset has them
+    pub fn inputs(self, metadata: &Value) -> Option<DerivedInputs> {
+        match self {
+            DerivedProduct::WindChill => {
+                let (u_wind, v_wind) = find_wind_components(metadata)?;
+                Some(DerivedInputs::WindChill {
+                    temperature: find_temperature(metadata)?,
+                    u_wind,
+                    v_wind,
+                })
+            }
+            DerivedProduct::HeatIndex => Some(DerivedInputs::HeatIndex {
+                temperature: find_temperature(metadata)?,
+                humidity: find_humidity(metadata)?,
+            }),
+            DerivedProduct::Ivt | DerivedProduct::IvtEastward | DerivedProduct::IvtNorthward => {
+                find_vapor_transport_inputs(metadata)
+            }
+        }
+    }
+
+    /// Units of the product for the given inputs
+    fn units(self, metadata: &Value, inputs: &DerivedInputs) -> String {
+        if self.is_vertical_integral() {
+            IVT_UNITS.to_string()
+        } else {
+            variable_units(metadata, inputs.template())
+        }
+    }
+}
+
+impl DerivedInputs {
+    /// Input whose dimensions describe the product's catalog entry
+    pub fn template(&self) -> &str {
+        match self {
+            DerivedInputs::WindChill { temperature, .. }
+            | DerivedInputs::HeatIndex { temperature, .. } => temperature,
+            DerivedInputs::VaporTransport { humidity, .. } => humidity,
+        }
+    }
+
+    /// All input variable names
+    pub fn variables(&self) -> Vec<&str> {
+        match self {
+            DerivedInputs::WindChill {
+                temperature,
+                u_wind,
+                v_wind,
+            } => vec![temperature, u_wind, v_wind],
+            DerivedInputs::HeatIndex {
+                temperature,
+                humidity:
+                    HumidityInput::RelativeHumidity(humidity) | HumidityInput::Dewpoint(humidity),
+            } => vec![temperature, humidity],
+            DerivedInputs::VaporTransport {
+                humidity,
+                u_wind,
+                v_wind,
+                ..
+            } => vec![humidity, u_wind, v_wind],
+        }
+    }
+}
+
+/// Dimension names and shape of a computed field
+#[derive(Debug, Clone, PartialEq)]
+struct FieldLayout {
+    dimensions: Vec<String>,
+    shape: Vec<usize>,
+}
+
+/// Whether the backend provides a variable of this name itself
+fn is_native(metadata: &Value, name: &str) -> bool {
+    metadata
+        .get("variables")
+        .and_then(|v| v.get(name))
+        .is_some_and(|entry| {
+            entry
+                .get("attributes")
+                .and_then(|a| a.get(DERIVED_FROM_ATTRIBUTE))
+                .is_none()
+        })
+}
+
+/// Resolve a requested variable to a derived product served by this proxy.
+///
+/// Returns `None` when the name is not a derived product, when the backend
+/// provides a variable with the same name itself, or when inputs are missing.
+pub fn resolve(metadata: &Value, name: &str) -> Option<(DerivedProduct, DerivedInputs)> {
+    let product = DerivedProduct::from_name(name)?;
+    if is_native(metadata, name) {
+        return None;
+    }
+    Some((product, product.inputs(metadata)?))
+}
+
+/// Resolve a requested variable to a registered custom variable and its
+/// inputs, under the same conditions as [`resolve`]. Inputs must be backend
+/// variables.
+pub fn resolve_custom<'a>(
+    registry: &'a DerivedRegistry,
+    metadata: &Value,
+    name: &str,
+) -> Option<(&'a dyn DerivedVariable, Vec<String>)> {
+    let variable = registry.get(name)?;
+    if is_native(metadata, name) {
+        return None;
+    }
+    let inputs = variable.inputs(metadata)?;
+    let usable = !inputs.is_empty() && inputs.iter().all(|input| is_native(metadata, input));
+    usable.then_some((variable, inputs))
+}
+
+/// Whether a requested variable is computed by this proxy, as a built-in
+/// product or a registered custom variable
+pub fn is_derived(registry: &DerivedRegistry, metadata: &Value, name: &str) -> bool {
+    resolve(metadata, name).is_some() || resolve_custom(registry, metadata, name).is_some()
+}
+
+/// Add catalog entries for every derived product and custom variable whose
+/// inputs are available.
+///
+/// Entries copy the dimensions of the template input, without the vertical
+/// dimension for vertically integrated products. Returns the number of
+/// products registered.
+pub fn register_derived_variables(metadata: &mut Value, registry: &DerivedRegistry) -> usize {
+    let absent = |metadata: &Value, name: &str| {
+        metadata
+            .get("variables")
+            .and_then(|v| v.get(name))
+            .is_none()
+    };
+    let mut entries: Vec<(&str, Value)> = DerivedProduct::ALL
+        .into_iter()
+        .filter(|product| absent(metadata, product.name()))
+        .filter_map(|product| {
+            let (product, inputs) = resolve(metadata, product.name())?;
+            let mut dimensions =
+                variable_dimensions(metadata, inputs.template()).unwrap_or_default();
+            if product.is_vertical_integral() {
+                dimensions.retain(|d| !is_vertical_dimension(d));
+            }
+            let entry = json!({
+                "dimensions": dimensions,
+                "attributes": {
+                    "long_name": product.long_name(),
+                    "units": product.units(metadata, &inputs),
+                    DERIVED_FROM_ATTRIBUTE: inputs.variables(),
+                }
+            });
+            Some((product.name(), entry))
+        })
+        .collect();
+    entries.extend(
+        registry
+            .iter()
+            .filter(|variable| absent(metadata, variable.name()))
+            .filter_map(|variable| {
+                let (variable, inputs) = resolve_custom(registry, metadata, variable.name())?;
+                let entry = json!({
+                    "dimensions": variable_dimensions(metadata, &inputs[0]).unwrap_or_default(),
+                    "attributes": {
+                        "long_name": variable.long_name(),
+                        "units": variable.units(metadata, &inputs),
+                        DERIVED_FROM_ATTRIBUTE: inputs,
+                    }
+                });
+                Some((variable.name(), entry))
+            }),
+    );
+
+    let Some(variables) = metadata
+        .get_mut("variables")
+        .and_then(|v| v.as_object_mut())
+    else {
+        return 0;
+    };
+
+    let registered = entries.len();
+    for (name, entry) in entries {
+        variables.insert(name.to_string(), entry);
+    }
+    registered
+}
+
+/// Fetch variables from the backend, computing any derived products among them.
+///
+/// The result has the shape of a Rossby `/data` JSON response containing
+/// exactly the requested variables. When vertically integrated products are
+/// requested, the response metadata describes their single-level layout.
+pub(crate) async fn fetch_with_derived(
+    state: &AppState,
+    metadata: &Value,
+    variables: &[&str],
+    time: Option<f64>,
+) -> Result<Value, AppError> {
+    let mut products = Vec::new();
+    let mut custom = Vec::new();
+    let mut backend_vars: Vec<&str> = Vec::new();
+
+    for variable in variables {
+        if let Some((product, inputs)) = resolve(metadata, variable) {
+            products.push((product, inputs));
+        } else if let Some(resolved) = resolve_custom(&state.derived_variables, metadata, variable)
+        {
+            custom.push(resolved);
+        } else {
+            backend_vars.push(variable);
+        }
+    }
+    for (_, inputs) in &products {
+        backend_vars.extend(inputs.variables());
+    }
+    for (_, inputs) in &custom {
+        backend_vars.extend(inputs.iter().map(String::as_str));
+    }
+    backend_vars.sort_unstable();
+    backend_vars.dedup();
+
+    let mut response = fetch_data(state, &data_query(&backend_vars, time)).await?;
+
+    let mut computed: Vec<(&str, Vec<f64>)> = Vec::new();
+    let mut layout = None;
+    for (product, inputs) in &products {
+        let (values, field_layout) = compute(metadata, &response, *product, inputs, time)?;
+        layout = field_layout.or(layout);
+        computed.push((product.name(), values));
+    }
+    for (variable, inputs) in &custom {
+        computed.push((
+            variable.name(),
+            compute_custom(metadata, &response, *variable, inputs)?,
+        ));
+    }
+
+    if let Some(layout) = layout {
+        response["metadata"]["dimensions"] = json!(layout.dimensions);
+        response["metadata"]["shape"] = json!(layout.shape);
+    }
+
+    let data = response
+        .get_mut("data")
+        .and_then(|d| d.as_object_mut())
+        .ok_or_else(|| AppError::ProxyError("Backend response has no data".to_string()))?;
+    data.retain(|name, _| variables.contains(&name.as_str()));
+    for (name, values) in computed {
+        let values: Vec<Option<f64>> = values
+            .into_iter()
+            .map(|v| v.is_finite().then_some(v))
+            .collect();
+        data.insert(name.to_string(), json!(values));
+    }
+
+    Ok(response)
+}
+
+/// Compute a product from a backend response holding its inputs.
+///
+/// Apparent temperatures are in the units of the temperature input and keep
+/// the backend layout; vertical integrals return their own layout.
+fn compute(
+    metadata: &Value,
+    response: &Value,
+    product: DerivedProduct,
+    inputs: &DerivedInputs,
+    time: Option<f64>,
+) -> Result<(Vec<f64>, Option<FieldLayout>), AppError> {
+    let result = match inputs {
+        DerivedInputs::WindChill {
+            temperature,
+            u_wind,
+            v_wind,
+        } => {
+            let temperature_unit =
+                TemperatureUnit::from_units(&variable_units(metadata, temperature));
+            let temperature = field(response, temperature)?;
+            let u = field(response, u_wind)?;
+            let v = field(response, v_wind)?;
+            check_lengths(&temperature, &[&u, &v])?;
+            let wind_to_ms = wind_speed_factor(&variable_units(metadata, u_wind));
+
+            temperature
+                .iter()
+                .zip(u.iter().zip(&v))
+                .map(|(&t, (&u, &v))| {
+                    let speed_kmh = u.hypot(v) * wind_to_ms * 3.6;
+                    let t_c = temperature_unit.to_celsius(t);
+                    temperature_unit.celsius_to(wind_chill_celsius(t_c, speed_kmh))
+                })
+                .collect()
+        }
+        DerivedInputs::HeatIndex {
+            temperature,
+            humidity,
+        } => {
+            let temperature_unit =
+                TemperatureUnit::from_units(&variable_units(metadata, temperature));
+            let temperature = field(response, temperature)?;
+            let (name, is_dewpoint) = match humidity {
+                HumidityInput::RelativeHumidity(name) => (name, false),
+                HumidityInput::Dewpoint(name) => (name, true),
+            };
+            let humidity_values = field(response, name)?;
+            check_lengths(&temperature, &[&humidity_values])?;
+            let dewpoint_unit = TemperatureUnit::from_units(&variable_units(metadata, name));
+            let humidity_scale = relative_humidity_scale(&variable_units(metadata, name));
+
+            temperature
+                .iter()
+                .zip(&humidity_values)
+                .map(|(&t, &h)| {
+                    let t_c = temperature_unit.to_celsius(t);
+                    let rh = if is_dewpoint {
+                        relative_humidity_from_dewpoint(t_c, dewpoint_unit.to_celsius(h))
+                    } else {
+                        h * humidity_scale
+                    };
+                    temperature_unit.celsius_to(heat_index_celsius(t_c, rh))
+                })
+                .collect()
+        }
+        DerivedInputs::VaporTransport {
+            humidity,
+            u_wind,
+            v_wind,
+            level_dimension,
+        } => {
+            let (eastward, northward, layout) = vapor_transport(
+                metadata,
+                response,
+                (humidity, u_wind, v_wind),
+                level_dimension,
+                time,
+            )?;
+            let values = match product {
+                DerivedProduct::IvtEastward => eastward,
+                DerivedProduct::IvtNorthward => northward,
+                _ => eastward
+                    .iter()
+                    .zip(&northward)
+                    .map(|(u, v)| u.hypot(*v))
+                    .collect(),
+            };
+            return Ok((values, Some(layout)));
+        }
+    };
+
+    Ok((result, None))
+}
+
+/// Compute a custom variable from a backend response holding its inputs,
+/// keeping the backend layout
+fn compute_custom(
+    metadata: &Value,
+    response: &Value,
+    variable: &dyn DerivedVariable,
+    inputs: &[String],
+) -> Result<Vec<f64>, AppError> {
+    let fields = inputs
+        .iter()
+        .map(|input| field(response, input))
+        .collect::<Result<Vec<_>, _>>()?;
+    check_lengths(&fields[0], &fields.iter().collect::<Vec<_>>())?;
+    let units: Vec<String> = inputs
+        .iter()
+        .map(|input| variable_units(metadata, input))
+        .collect();
+    let input_fields: Vec<InputField> = inputs
+        .iter()
+        .zip(&units)
+        .zip(&fields)
+        .map(|((name, units), values)| InputField {
+            name,
+            units,
+            values,
+        })
+        .collect();
+
+    let values = variable.compute(&input_fields)?;
+    if values.len() != fields[0].len() {
+        return Err(AppError::ConfigError(format!(
+            "Derived variable '{}' returned {} values for {} grid points",
+            variable.name(),
+            values.len(),
+            fields[0].len()
+        )));
+    }
+    Ok(values)
+}
+
+/// Eastward and northward integrated vapour transport in kg m⁻¹ s⁻¹.
+///
+/// The flux q·V is integrated over pressure between 1000 and 300 hPa with the
+/// trapezoidal rule and divided by gravity. Layers with a missing endpoint
+/// (for example below ground) are skipped; columns without any valid layer
+/// are missing.
+fn vapor_transport(
+    metadata: &Value,
+    response: &Value,
+    (humidity, u_wind, v_wind): (&str, &str, &str),
+    level_dimension: &str,
+    time: Option<f64>,
+) -> Result<(Vec<f64>, Vec<f64>, FieldLayout), AppError> {
+    let q = DataArray::from_rossby_response(response, humidity)?;
+    let u = DataArray::from_rossby_response(response, u_wind)?;
+    let v = DataArray::from_rossby_response(response, v_wind)?;
+    if q.shape != u.shape || q.shape != v.shape || q.dimensions != u.dimensions {
+        return Err(AppError::ProxyError(
+            "Derived product inputs have mismatched shapes".to_string(),
+        ));
+    }
+
+    let levels = coordinate_values(metadata, level_dimension).ok_or_else(|| {
+        AppError::ProxyError(format!(
+            "No coordinate values for dimension '{}'",
+            level_dimension
+        ))
+    })?;
+    if levels.len() != q.dimension_size(level_dimension) {
+        return Err(AppError::ProxyError(format!(
+            "Dimension '{}' does not match its coordinate values",
+            level_dimension
+        )));
+    }
+
+    // Levels are in hPa unless they are clearly Pa
+    let to_pa = if levels.iter().any(|p| *p > 2000.0) {
+        1.0
+    } else {
+        100.0
+    };
+    let mut column: Vec<(usize, f64)> = levels
+        .iter()
+        .enumerate()
+        .map(|(index, level)| (index, level * to_pa))
+        .filter(|(_, pressure)| (IVT_TOP_PA..=IVT_BOTTOM_PA).contains(pressure))
+        .collect();
+    column.sort_by(|a, b| a.1.total_cmp(&b.1));
+    if column.len() < 2 {
+        return Err(AppError::RequestError(
+            "IVT needs at least two pressure levels between 300 and 1000 hPa".to_string(),
+        ));
+    }
+
+    let q_scale = specific_humidity_scale(&variable_units(metadata, humidity));
+    let mut selection = time_selection(metadata, &q, time);
+    let mut fluxes = Vec::with_capacity(column.len());
+    for (index, pressure) in &column {
+        selection.insert(level_dimension.to_string(), *index);
+        let q_level = q.horizontal_slice(&selection)?;
+        let u_level = u.horizontal_slice(&selection)?;
+        let v_level = v.horizontal_slice(&selection)?;
+
+        let (qu, qv): (Vec<f64>, Vec<f64>) = q_level
+            .iter()
+            .zip(u_level.iter().zip(&v_level))
+            .map(|(q, (u, v))| (q * q_scale * u, q * q_scale * v))
+            .unzip();
+        fluxes.push((*pressure, qu, qv));
+    }
+
+    let points = fluxes[0].1.len();
+    let mut eastward = vec![0.0; points];
+    let mut northward = vec![0.0; points];
+    let mut valid = vec![false; points];
+    for layer in fluxes.windows(2) {
+        let (upper, lower) = (&layer[0], &layer[1]);
+        let weight = 0.5 * (lower.0 - upper.0) / GRAVITY;
+        for i in 0..points {
+            let values = [upper.1[i], lower.1[i], upper.2[i], lower.2[i]];
+            if values.iter().all(|v| v.is_finite()) {
+                eastward[i] += weight * (upper.1[i] + lower.1[i]);
+                northward[i] += weight * (upper.2[i] + lower.2[i]);
+                valid[i] = true;
+            }
+        }
+    }
+    for (i, is_valid) in valid.iter().enumerate() {
+        if !is_valid {
+            eastward[i] = f64::NAN;
+            northward[i] = f64::NAN;
+        }
+    }
+
+    let (dimensions, shape) = q
+        .dimensions
+        .iter()
+        .zip(&q.shape)
+        .filter(|(name, _)| !is_vertical_dimension(name))
+        .map(|(name, size)| {
+            let horizontal = is_latitude_dimension(name) || is_longitude_dimension(name);
+            (name.clone(), if horizontal { *size } else { 1 })
+        })
+        .unzip();
+
+    Ok((eastward, northward, FieldLayout { dimensions, shape }))
+}
+
+/// Wind chill in °C using the North American (JAG/TI) formula.
+///
+/// The index is only defined at or below 10 °C with wind of at least
+/// 4.8 km/h; the air temperature is returned otherwise.
+pub fn wind_chill_celsius(temperature_c: f64, wind_speed_kmh: f64) -> f64 {
+    if temperature_c > 10.0 || wind_speed_kmh < 4.8 {
+        return temperature_c;
+    }
+    let v = wind_speed_kmh.powf(0.16);
+    13.12 + 0.6215 * temperature_c - 11.37 * v + 0.3965 * temperature_c * v
+}
+
+/// Heat index in °C using the NWS Rothfusz regression and its adjustments.
+///
+/// Below about 80 °F, where the index is not meaningful, the air temperature
+/// is returned.
+pub fn heat_index_celsius(temperature_c: f64, relative_humidity: f64) -> f64 {
+    let t = temperature_c * 9.0 / 5.0 + 32.0;
+    let rh = relative_humidity.clamp(0.0, 100.0);
+
+    let simple = 0.5 * (t + 61.0 + (t - 68.0) * 1.2 + rh * 0.094);
+    if (simple + t) / 2.0 < 80.0 {
+        return temperature_c;
+    }
+
+    let mut hi = -42.379 + 2.049_015_23 * t + 10.143_331_27 * rh
+        - 0.224_755_41 * t * rh
+        - 0.006_837_83 * t * t
+        - 0.054_817_17 * rh * rh
+        + 0.001_228_74 * t * t * rh
+        + 0.000_852_82 * t * rh * rh
+        - 0.000_001_99 * t * t * rh * rh;
+
+    if rh < 13.0 && (80.0..=112.0).contains(&t) {
+        hi -= (13.0 - rh) / 4.0 * ((17.0 - (t - 95.0).abs()) / 17.0).sqrt();
+    } else if rh > 85.0 && (80.0..=87.0).contains(&t) {
+        hi += (rh - 85.0) / 10.0 * ((87.0 - t) / 5.0);
+    }
+
+    (hi - 32.0) * 5.0 / 9.0
+}
+
+/// Relative humidity (%) from temperature and dewpoint in °C (Magnus formula)
+pub fn relative_humidity_from_dewpoint(temperature_c: f64, dewpoint_c: f64) -> f64 {
+    let saturation = |t: f64| (17.625 * t / (243.04 + t)).exp();
+    (100.0 * saturation(dewpoint_c) / saturation(temperature_c)).min(100.0)
+}
+
+/// Temperature units recognised from CF-style `units` attributes
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum TemperatureUnit {
+    Kelvin,
+    Celsius,
+    Fahrenheit,
+}
+
+impl TemperatureUnit {
+    fn from_units(units: &str) -> Self {
+        match units
+            .trim()
+            .trim_start_matches("deg")
+            .trim_start_matches('°')
+        {
+            "C" | "c" | "_C" | "celsius" | "Celsius" => TemperatureUnit::Celsius,
+            "F" | "f" | "_F" | "fahrenheit" | "Fahrenheit" => TemperatureUnit::Fahrenheit,
+            _ => TemperatureUnit::Kelvin,
+        }
+    }
+
+    fn to_celsius(self, value: f64) -> f64 {
+        match self {
+            TemperatureUnit::Kelvin => value - 273.15,
+            TemperatureUnit::Celsius => value,
+            TemperatureUnit::Fahrenheit => (value - 32.0) * 5.0 / 9.0,
+        }
+    }
+
+    fn celsius_to(self, value: f64) -> f64 {
+        match self {
+            TemperatureUnit::Kelvin => value + 273.15,
+            TemperatureUnit::Celsius => value,
+            TemperatureUnit::Fahrenheit => value * 9.0 / 5.0 + 32.0,
+        }
+    }
+}
+
+/// Factor converting a wind speed in the given units to m/s
+fn wind_speed_factor(units: &str) -> f64 {
+    match units.trim() {
+        "knots" | "knot" | "kt" | "kts" => 0.514_444,
+        "km/h" | "km h**-1" | "km h-1" => 1.0 / 3.6,
+        _ => 1.0,
+    }
+}
+
+/// Factor converting a relative humidity in the given units to percent
+fn relative_humidity_scale(units: &str) -> f64 {
+    match units.trim() {
+        "1" | "fraction" | "(0 - 1)" => 100.0,
+        _ => 1.0,
+    }
+}
+
+fn find_temperature(metadata: &Value) -> Option<String> {
+    find_variable(
+        metadata,
+        &TEMPERATURE_NAMES,
+        &["2 metre temperature", "2m temperature"],
+    )
+}
+
+/// Factor converting a specific humidity in the given units to kg/kg
+fn specific_humidity_scale(units: &str) -> f64 {
+    match units.trim() {
+        "g kg**-1" | "g/kg" | "g kg-1" => 0.001,
+        _ => 1.0,
+    }
+}
+
+fn find_humidity(metadata: &Value) -> Option<HumidityInput> {
+    find_variable(metadata, &RELATIVE_HUMIDITY_NAMES, &["relative humidity"])
+        .map(HumidityInput::RelativeHumidity)
+        .or_else(|| {
+            find_variable(metadata, &DEWPOINT_NAMES, &["dewpoint", "dew point"])
+                .map(HumidityInput::Dewpoint)
+        })
+}
+
+/// Specific humidity and a wind pair on the same pressure-level dimension
+fn find_vapor_transport_inputs(metadata: &Value) -> Option<DerivedInputs> {
+    let humidity = find_variable(metadata, &SPECIFIC_HUMIDITY_NAMES, &["specific humidity"])?;
+    let level_dimension = variable_dimensions(metadata, &humidity)?
+        .into_iter()
+        .find(|d| is_vertical_dimension(d))?;
+    let on_levels = |name: &str| {
+        variable_dimensions(metadata, name).is_some_and(|dims| dims.contains(&level_dimension))
+    };
+
+    let variables = metadata.get("variables")?.as_object()?;
+    let (u_wind, v_wind) = pair_vector_components(variables)
+        .into_iter()
+        .find(|(u_wind, v_wind)| on_levels(u_wind) && on_levels(v_wind))?;
+
+    Some(DerivedInputs::VaporTransport {
+        humidity,
+        u_wind,
+        v_wind,
+        level_dimension,
+    })
+}
+
+/// Find a variable by its common names, falling back to `long_name` keywords
+fn find_variable(metadata: &Value, names: &[&str], long_name_keywords: &[&str]) -> Option<String> {
+    let variables = metadata.get("variables")?.as_object()?;
+    if let Some(name) = names.iter().find(|name| variables.contains_key(**name)) {
+        return Some(name.to_string());
+    }
+    variables
+        .iter()
+        .find(|(_, entry)| {
+            let long_name = entry
+                .get("attributes")
+                .and_then(|a| a.get("long_name"))
+                .and_then(|v| v.as_str())
+                .unwrap_or("")
+                .to_lowercase();
+            long_name_keywords.iter().any(|k| long_name.contains(k))
+        })
+        .map(|(name, _)| name.clone())
+}
+
+fn field(response: &Value, variable: &str) -> Result<Vec<f64>, AppError> {
+    response
+        .get("data")
+        .and_then(|d| d.get(variable))
+        .and_then(|v| v.as_array())
+        .map(|values| {
+            values
+                .iter()
+                .map(|v| v.as_f64().unwrap_or(f64::NAN))
+                .collect()
+        })
+        .ok_or_else(|| {
+            AppError::ProxyError(format!(
+                "Backend response is missing variable '{}'",
+                variable
+            ))
+        })
+}
+
+fn check_lengths(reference: &[f64], others: &[&Vec<f64>]) -> Result<(), AppError> {
+    if others.iter().any(|other| other.len() != reference.len()) {
+        return Err(AppError::ProxyError(
+            "Derived product inputs have mismatched shapes".to_string(),
+        ));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn metadata() -> Value {
+        json!({
+            "variables": {
+                "t2m": {
+                    "dimensions": ["time", "latitude", "longitude"],
+                    "attributes": {"long_name": "2 metre temperature", "units": "K"}
+                },
+                "u10": {"attributes": {"units": "m s**-1"}},
+                "v10": {"attributes": {"units": "m s**-1"}},
+                "dewpt": {"attributes": {"long_name": "2 metre dewpoint temperature", "units": "K"}}
+            }
+        })
+    }
+
+    #[test]
+    fn test_wind_chill_reference_values() {
+        // Environment Canada table: -20 °C with 30 km/h wind feels like about -33 °C
+        assert!((wind_chill_celsius(-20.0, 30.0) - -32.6).abs() < 0.1);
+        assert_eq!(wind_chill_celsius(15.0, 30.0), 15.0);
+        assert_eq!(wind_chill_celsius(-5.0, 2.0), -5.0);
+    }
+
+    #[test]
+    fn test_heat_index_reference_values() {
+        // NWS table: 90 °F at 70 % relative humidity gives a heat index of 106 °F
+        let hi_f = heat_index_celsius((90.0 - 32.0) * 5.0 / 9.0, 70.0) * 9.0 / 5.0 + 32.0;
+        assert!((hi_f - 105.9).abs() < 0.5);
+        assert_eq!(heat_index_celsius(15.0, 50.0), 15.0);
+    }
+
+    #[test]
+    fn test_relative_humidity_from_dewpoint() {
+        assert!((relative_humidity_from_dewpoint(20.0, 20.0) - 100.0).abs() < 1e-9);
+        let rh = relative_humidity_from_dewpoint(30.0, 20.0);
+        assert!((rh - 55.1).abs() < 0.5);
+    }
+
+    #[test]
+    fn test_temperature_units() {
+        assert_eq!(TemperatureUnit::from_units("K"), TemperatureUnit::Kelvin);
+        assert_eq!(
+            TemperatureUnit::from_units("degC"),
+            TemperatureUnit::Celsius
+        );
+        assert_eq!(
+            TemperatureUnit::from_units("°F"),
+            TemperatureUnit::Fahrenheit
+        );
+        assert_eq!(TemperatureUnit::Fahrenheit.to_celsius(212.0), 100.0);
+    }
+
+    #[test]
+    fn test_register_derived_variables() {
+        let mut metadata = metadata();
+        assert_eq!(
+            register_derived_variables(&mut metadata, &DerivedRegistry::default()),
+            2
+        );
+
+        let wind_chill = &metadata["variables"]["wind_chill"];
+        assert_eq!(wind_chill["attributes"]["units"], "K");
+        assert_eq!(
+            wind_chill["attributes"]["derived_from"],
+            json!(["t2m", "u10", "v10"])
+        );
+        assert_eq!(
+            wind_chill["dimensions"],
+            metadata["variables"]["t2m"]["dimensions"]
+        );
+        assert_eq!(
+            metadata["variables"]["heat_index"]["attributes"]["derived_from"],
+            json!(["t2m", "dewpt"])
+        );
+
+        // Registering again is a no-op, and registered entries still resolve
+        assert_eq!(
+            register_derived_variables(&mut metadata, &DerivedRegistry::default()),
+            0
+        );
+        assert!(resolve(&metadata, "wind_chill").is_some());
+    }
+
+    #[test]
+    fn test_missing_inputs_and_native_variables() {
+        let mut metadata = json!({"variables": {"t2m": {}, "u10": {}, "v10": {}}});
+        assert_eq!(
+            register_derived_variables(&mut metadata, &DerivedRegistry::default()),
+            1
+        );
+        assert!(metadata["variables"].get("heat_index").is_none());
+
+        let metadata = json!({"variables": {"t2m": {}, "r2": {}, "heat_index": {}}});
+        assert!(resolve(&metadata, "heat_index").is_none());
+    }
+
+    #[test]
+    fn test_compute_wind_chill_from_response() {
+        let metadata = metadata();
+        let inputs = DerivedProduct::WindChill.inputs(&metadata).unwrap();
+        let response = json!({"data": {
+            "t2m": [253.15, 300.0, null],
+            "u10": [6.0, 10.0, 1.0],
+            "v10": [8.0, 0.0, 1.0]
+        }});
+
+        let (values, layout) = compute(
+            &metadata,
+            &response,
+            DerivedProduct::WindChill,
+            &inputs,
+            None,
+        )
+        .unwrap();
+        assert!(layout.is_none());
+        // 10 m/s is 36 km/h
+        let expected = wind_chill_celsius(-20.0, 36.0) + 273.15;
+        assert!((values[0] - expected).abs() < 1e-9);
+        assert_eq!(values[1], 300.0);
+        assert!(values[2].is_nan());
+    }
+
+    fn column_metadata() -> Value {
+        let dims = json!(["time", "level", "latitude", "longitude"]);
+        json!({
+            "coordinates": {
+                "latitude": [10.0, 0.0],
+                "longitude": [0.0, 10.0],
+                "level": [1000.0, 500.0, 200.0],
+                "time": [700464.0]
+            },
+            "variables": {
+                "q": {"dimensions": dims, "attributes": {"long_name": "Specific humidity", "units": "kg kg**-1"}},
+                "u": {"dimensions": dims, "attributes": {"units": "m s**-1"}},
+                "v": {"dimensions": dims, "attributes": {"units": "m s**-1"}}
+            }
+        })
+    }
+
+    #[test]
+    fn test_register_vapor_transport() {
+        let mut metadata = column_metadata();
+        assert_eq!(
+            register_derived_variables(&mut metadata, &DerivedRegistry::default()),
+            3
+        );
+
+        for name in ["ivt", "uivt", "vivt"] {
+            let entry = &metadata["variables"][name];
+            assert_eq!(
+                entry["dimensions"],
+                json!(["time", "latitude", "longitude"])
+            );
+            assert_eq!(entry["attributes"]["units"], IVT_UNITS);
+            assert_eq!(entry["attributes"]["derived_from"], json!(["q", "u", "v"]));
+        }
+    }
+
+    #[test]
+    fn test_compute_vapor_transport() {
+        let metadata = column_metadata();
+        let inputs = DerivedProduct::Ivt.inputs(&metadata).unwrap();
+
+        // Levels 1000, 500 and 200 hPa; 200 hPa is above the column and ignored
+        let mut q = vec![0.01; 12];
+        q[0] = f64::NAN;
+        let q: Vec<Value> = q.into_iter().map(|v| json!(v)).collect();
+        let response = json!({
+            "metadata": {"shape": [1, 3, 2, 2], "dimensions": ["time", "level", "latitude", "longitude"]},
+            "data": {"q": q, "u": vec![10.0; 12], "v": vec![0.0; 12]}
+        });
+
+        let (eastward, layout) = compute(
+            &metadata,
+            &response,
+            DerivedProduct::IvtEastward,
+            &inputs,
+            None,
+        )
+        .unwrap();
+        let expected = 0.01 * 10.0 * 50_000.0 / GRAVITY;
+        assert!(eastward[0].is_nan());
+        assert!(eastward[1..].iter().all(|v| (v - expected).abs() < 1e-9));
+        assert_eq!(
+            layout,
+            Some(FieldLayout {
+                dimensions: vec!["time".into(), "latitude".into(), "longitude".into()],
+                shape: vec![1, 2, 2],
+            })
+        );
+
+        let (magnitude, _) =
+            compute(&metadata, &response, DerivedProduct::Ivt, &inputs, None).unwrap();
+        let (northward, _) = compute(
+            &metadata,
+            &response,
+            DerivedProduct::IvtNorthward,
+            &inputs,
+            None,
+        )
+        .unwrap();
+        assert!((magnitude[3] - expected).abs() < 1e-9);
+        assert_eq!(northward[3], 0.0);
+    }
+}
@@ -0,0 +1,251 @@
+//! Serving the frontend from disk during development
+//!
+//! With `--dev` the frontend is read from the source `public/` directory
+//! instead of the embedded bundle, so JS and CSS edits show up on refresh
+//! without rebuilding the binary. Files are cached in memory and a file
+//! watcher drops changed files from the cache. Production keeps serving the
+//! embedded bundle.
+//!
+//! Pages served in this mode load a small script listening on
+//! [`DEV_EVENTS_PATH`], which streams a `change` event for every file the
+//! watcher sees change. The script swaps changed stylesheets in place and
+//! reloads the page for anything else.
+
+use axum::{
+    extract::State,
+    response::sse::{Event as SseEvent, KeepAlive, Sse},
+};
+use futures::Stream;
+use notify::{Event, EventKind, RecommendedWatcher, RecursiveMode, Watcher};
+use std::{
+    collections::HashMap,
+    convert::Infallible,
+    path::{Component, Path, PathBuf},
+    sync::{Arc, RwLock},
+};
+use tokio::sync::broadcast;
+use tracing::{debug, info, warn};
+
+use crate::{error::AppError, server::AppState};
+
+/// Route streaming file changes to the pages in development mode
+pub const DEV_EVENTS_PATH: &str = "/__dev/events";
+
+/// Script added to every page in development mode
+const RELOAD_SCRIPT: &str = r#"<script>
+new EventSource("__dev/events").addEventListener("change", function (event) {
+    if (/\.css$/.test(event.data)) {
+        document.querySelectorAll('link[rel="stylesheet"]').forEach(function (link) {
+            var url = new URL(link.href);
+            if (url.pathname === new URL(event.data, document.baseURI).pathname) {
+                url.searchParams.set("reload", Date.now());
+                link.href = url.href;
+            }
+        });
+    } else {
+        location.reload();
+    }
+});
+</script>"#;
+
+/// Changes buffered for a page that is slow to read them
+const CHANGE_BUFFER: usize = 64;
+
+type FileCache = Arc<RwLock<HashMap<PathBuf, Arc<Vec<u8>>>>>;
+
+/// Frontend files read from a directory on disk
+pub struct DevAssets {
+    root: PathBuf,
+    cache: FileCache,
+    changes: broadcast::Sender<String>,
+    _watcher: RecommendedWatcher,
+}
+
+impl std::fmt::Debug for DevAssets {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("DevAssets")
+            .field("root", &self.root)
+            .finish_non_exhaustive()
+    }
+}
+
+impl DevAssets {
+    /// Serve files from `root`, watching it for changes
+    pub fn new(root: &Path) -> Result<Self, AppError> {
+        let root = root.canonicalize().map_err(|e| {
+            AppError::ConfigError(format!(
+                "Cannot serve frontend from {}: {}",
+                root.display(),
+                e
+            ))
+        })?;
+        if !root.is_dir() {
+            return Err(AppError::ConfigError(format!(
+                "Cannot serve frontend from {}: not a directory",
+                root.display()
+            )));
+        }
+
+        let cache = FileCache::default();
+        let (changes, _) = broadcast::channel(CHANGE_BUFFER);
+        let watched = cache.clone();
+        let notify_pages = changes.clone();
+        let watched_root = root.clone();
+        let mut watcher = notify::recommended_watcher(move |event: notify::Result<Event>| {
+            let mut cache = watched.write().unwrap_or_else(|e| e.into_inner());
+            match event {
+                Ok(event) if matches!(event.kind, EventKind::Access(_)) => {}
+                Ok(event) => {
+                    for path in &event.paths {
+                        if cache.remove(path).is_some() {
+                            debug!("Frontend file changed: {}", path.display());
+                        }
+                        if let Ok(relative) = path.strip_prefix(&watched_root) {
+                            // No page listening is fine
+                            let _ = notify_pages.send(relative.to_string_lossy().into_owned());
+                        }
+                    }
+                }
+                Err(e) => {
+                    warn!("Frontend file watcher error, clearing cache: {}", e);
+                    cache.clear();
+                }
+            }
+        })
+        .map_err(|e| AppError::ConfigError(format!("Cannot watch frontend files: {}", e)))?;
+        watcher
+            .watch(&root, RecursiveMode::Recursive)
+            .map_err(|e| {
+                AppError::ConfigError(format!("Cannot watch {}: {}", root.display(), e))
+            })?;
+
+        info!("Development mode: serving frontend from {}", root.display());
+        Ok(Self {
+            root,
+            cache,
+            changes,
+            _watcher: watcher,
+        })
+    }
+
+    /// Directory the files are served from
+    pub fn root(&self) -> &Path {
+        &self.root
+    }
+
+    /// Paths relative to the root of files changing from now on
+    pub fn subscribe(&self) -> broadcast::Receiver<String> {
+        self.changes.subscribe()
+    }
+
+    /// Contents of the file at URL path `path`, relative to the root
+    pub fn get(&self, path: &str) -> Option<Arc<Vec<u8>>> {
+        // Only plain relative paths, so requests cannot leave the root
+        let relative = Path::new(path);
+        if !relative
+            .components()
+            .all(|component| matches!(component, Component::Normal(_)))
+        {
+            return None;
+        }
+        let file = self.root.join(relative);
+
+        if let Some(data) = self
+            .cache
+            .read()
+            .unwrap_or_else(|e| e.into_inner())
+            .get(&file)
+        {
+            return Some(data.clone());
+        }
+
+        let data = Arc::new(std::fs::read(&file).ok()?);
+        self.cache
+            .write()
+            .unwrap_or_else(|e| e.into_inner())
+            .insert(file, data.clone());
+        Some(data)
+    }
+}
+
+/// `html` with the script reloading the page on file changes
+pub fn inject_reload_script(html: &str) -> String {
+    match html.rfind("</body>") {
+        Some(end) => format!("{}{}{}", &html[..end], RELOAD_SCRIPT, &html[end..]),
+        None => format!("{}{}", html, RELOAD_SCRIPT),
+    }
+}
+
+/// Handler for [`DEV_EVENTS_PATH`]
+///
+/// Streams a `change` event naming each file changing under the frontend
+/// directory. Outside development mode there is nothing to watch.
+pub async fn dev_events(
+    State(state): State<Arc<AppState>>,
+) -> Result<Sse<impl Stream<Item = Result<SseEvent, Infallible>>>, AppError> {
+    let dev = state.dev_assets.as_ref().ok_or_else(|| {
+        AppError::NotFound("File change events are only sent in --dev mode".to_string())
+    })?;
+    let events = futures::stream::unfold(dev.subscribe(), |mut receiver| async move {
+        let path = match receiver.recv().await {
+            Ok(path) => path,
+            // Changes were missed, which still calls for a reload
+            Err(broadcast::error::RecvError::Lagged(_)) => String::new(),
+            Err(broadcast::error::RecvError::Closed) => return None,
+        };
+        Some((Ok(SseEvent::default().event("change").data(path)), receiver))
+    });
+    Ok(Sse::new(events).keep_alive(KeepAlive::default()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_serves_fresh_files_from_disk() {
+        let root = std::env::temp_dir().join(format!("rossby-vis-dev-{}", uuid::Uuid::new_v4()));
+        std::fs::create_dir_all(root.join("libs")).unwrap();
+        std::fs::write(root.join("libs/app.js"), "v1").unwrap();
+
+        let assets = DevAssets::new(&root).unwrap();
+        assert_eq!(assets.get("libs/app.js").unwrap().as_slice(), b"v1");
+
+        // Drop the cached copy as the watcher would, and see the edit
+        std::fs::write(root.join("libs/app.js"), "v2").unwrap();
+        assets.cache.write().unwrap().clear();
+        assert_eq!(assets.get("libs/app.js").unwrap().as_slice(), b"v2");
+
+        assert!(assets.get("../etc/passwd").is_none());
+        assert!(assets.get("/etc/passwd").is_none());
+        assert!(assets.get("missing.js").is_none());
+
+        std::fs::remove_dir_all(root).unwrap();
+        assert!(DevAssets::new(Path::new("/nonexistent/public")).is_err());
+    }
+
+    #[test]
+    fn test_inject_reload_script() {
+        let html = inject_reload_script("<html><body><p>hi</p></body></html>");
+        assert!(html.starts_with("<html><body><p>hi</p><script>"));
+        assert!(html.ends_with("</script></body></html>"));
+        assert!(inject_reload_script("<p>hi</p>").ends_with("</script>"));
+    }
+
+    #[tokio::test]
+    async fn test_announces_changed_files() {
+        let root = std::env::temp_dir().join(format!("rossby-vis-dev-{}", uuid::Uuid::new_v4()));
+        std::fs::create_dir_all(root.join("styles")).unwrap();
+        let assets = DevAssets::new(&root).unwrap();
+        let mut changes = assets.subscribe();
+
+        std::fs::write(root.join("styles/app.css"), "body {}").unwrap();
+        let changed = tokio::time::timeout(std::time::Duration::from_secs(5), changes.recv())
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(changed, "styles/app.css");
+
+        std::fs::remove_dir_all(root).unwrap();
+    }
+}
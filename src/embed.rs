@@ -1,9 +1,268 @@
-use rust_embed::RustEmbed;
+use base64::{engine::general_purpose::STANDARD, Engine};
+use chrono::{DateTime, TimeZone, Utc};
+use rust_embed::{EmbeddedFile, RustEmbed};
+use serde::Serialize;
+use sha2::{Digest, Sha256};
+use std::{collections::BTreeMap, sync::OnceLock};
+use utoipa::ToSchema;
 
 #[derive(RustEmbed)]
 #[folder = "public/"]
 pub struct StaticAssets;
 
+/// Extensions of the assets that get Subresource Integrity hashes
+const SRI_EXTENSIONS: [&str; 2] = [".js", ".css"];
+
+/// Subresource Integrity value (`sha256-<base64>`) of an embedded script or
+/// stylesheet. The hash is computed when the assets are embedded at build time.
+pub fn integrity(path: &str) -> Option<String> {
+    let path = path.trim_start_matches('/');
+    if !SRI_EXTENSIONS.iter().any(|ext| path.ends_with(ext)) {
+        return None;
+    }
+    let asset = StaticAssets::get(path)?;
+    Some(format!(
+        "sha256-{}",
+        STANDARD.encode(asset.metadata.sha256_hash())
+    ))
+}
+
+/// Integrity values of all embedded scripts and stylesheets, keyed by URL path
+pub fn integrity_manifest() -> BTreeMap<String, String> {
+    StaticAssets::iter()
+        .filter_map(|path| Some((format!("/{}", path), integrity(&path)?)))
+        .collect()
+}
+
+/// Diagnostic details of one embedded file
+#[derive(Debug, Clone, Serialize, ToSchema)]
+pub struct AssetInfo {
+    /// URL path the file is served at
+    pub path: String,
+    pub size: usize,
+    /// Hex SHA-256 of the contents
+    pub sha256: String,
+    /// `Content-Type` the file is served with
+    pub mime_type: String,
+    /// Precompressed variants served in its place, e.g. `["gzip"]`
+    pub encodings: Vec<&'static str>,
+}
+
+/// Details of every embedded file whose path starts with `prefix`
+pub fn asset_listing(prefix: &str) -> Vec<AssetInfo> {
+    let prefix = prefix.trim_start_matches('/');
+    StaticAssets::iter()
+        .filter(|path| path.starts_with(prefix))
+        .filter_map(|path| {
+            let file = StaticAssets::get(&path)?;
+            Some(AssetInfo {
+                path: format!("/{}", path),
+                size: file.data.len(),
+                sha256: hex_hash(&file),
+                mime_type: mime_guess::from_path(path.as_ref())
+                    .first_or_octet_stream()
+                    .to_string(),
+                encodings: PRECOMPRESSED
+                    .iter()
+                    .filter(|(_, suffix)| {
+                        StaticAssets::get(&format!("{}{}", path, suffix)).is_some()
+                    })
+                    .map(|(coding, _)| *coding)
+                    .collect(),
+            })
+        })
+        .collect()
+}
+
+/// Version of the embedded frontend: the first 12 hex digits of a SHA-256
+/// over the path and hash of every embedded file
+pub fn bundle_version() -> &'static str {
+    static VERSION: OnceLock<String> = OnceLock::new();
+    VERSION.get_or_init(|| {
+        let mut paths: Vec<_> = StaticAssets::iter().collect();
+        paths.sort();
+        let mut hasher = Sha256::new();
+        for path in paths {
+            if let Some(file) = StaticAssets::get(&path) {
+                hasher.update(path.as_bytes());
+                hasher.update(file.metadata.sha256_hash());
+            }
+        }
+        hasher.finalize()[..6]
+            .iter()
+            .map(|b| format!("{:02x}", b))
+            .collect()
+    })
+}
+
+fn hex_hash(file: &EmbeddedFile) -> String {
+    file.metadata
+        .sha256_hash()
+        .iter()
+        .map(|b| format!("{:02x}", b))
+        .collect()
+}
+
+/// Whether `version` is the start of the hex SHA-256 of the embedded asset
+/// at `path`, at least 8 digits long, so a URL carrying it names exactly the
+/// current contents
+pub fn is_content_version(path: &str, version: &str) -> bool {
+    version.len() >= 8
+        && version.chars().all(|c| c.is_ascii_hexdigit())
+        && StaticAssets::get(path)
+            .is_some_and(|file| hex_hash(&file).starts_with(&version.to_ascii_lowercase()))
+}
+
+/// Content codings of precompressed variants and their file suffixes, in
+/// order of preference when the client accepts several equally
+const PRECOMPRESSED: [(&str, &str); 2] = [("br", ".br"), ("gzip", ".gz")];
+
+/// An embedded asset chosen for a request: the file itself or one of its
+/// precompressed variants (`foo.js.br`, `foo.js.gz`)
+pub struct NegotiatedAsset {
+    pub file: EmbeddedFile,
+    /// `Content-Encoding` of the chosen variant; `None` for the file itself
+    pub encoding: Option<&'static str>,
+    /// Whether the asset has precompressed variants, in which case every
+    /// response for it must carry `Vary: Accept-Encoding`
+    pub has_variants: bool,
+}
+
+impl NegotiatedAsset {
+    /// Strong entity tag of the bytes being served, so each encoding of an
+    /// asset has its own tag
+    pub fn etag(&self) -> String {
+        let hash = self.file.metadata.sha256_hash();
+        let hex: String = hash[..16].iter().map(|b| format!("{:02x}", b)).collect();
+        format!("\"{}\"", hex)
+    }
+
+    /// When the served file was last changed before it was embedded
+    pub fn last_modified(&self) -> Option<DateTime<Utc>> {
+        let secs = i64::try_from(self.file.metadata.last_modified()?).ok()?;
+        Utc.timestamp_opt(secs, 0).single()
+    }
+}
+
+/// Choose between an asset and its precompressed variants based on the
+/// request's `Accept-Encoding`, falling back to the uncompressed file
+pub fn negotiate(path: &str, accept_encoding: Option<&str>) -> Option<NegotiatedAsset> {
+    let file = StaticAssets::get(path)?;
+
+    let mut variants: Vec<(&'static str, EmbeddedFile)> = PRECOMPRESSED
+        .iter()
+        .filter_map(|(coding, suffix)| {
+            Some((*coding, StaticAssets::get(&format!("{}{}", path, suffix))?))
+        })
+        .collect();
+    let has_variants = !variants.is_empty();
+
+    // Highest q-value wins; `max_by` keeps the last of equals, so search in
+    // reverse preference order
+    let chosen = variants
+        .iter()
+        .enumerate()
+        .rev()
+        .filter_map(|(index, (coding, _))| {
+            let q = accept_encoding.map_or(0.0, |header| quality(header, coding));
+            (q > 0.0).then_some((index, q))
+        })
+        .max_by(|a, b| a.1.total_cmp(&b.1))
+        .map(|(index, _)| index);
+
+    Some(match chosen {
+        Some(index) => {
+            let (coding, variant) = variants.swap_remove(index);
+            NegotiatedAsset {
+                file: variant,
+                encoding: Some(coding),
+                has_variants,
+            }
+        }
+        None => NegotiatedAsset {
+            file,
+            encoding: None,
+            has_variants,
+        },
+    })
+}
+
+/// The q-value an `Accept-Encoding` header gives a content coding
+fn quality(accept_encoding: &str, coding: &str) -> f32 {
+    let mut wildcard = None;
+    for entry in accept_encoding.split(',') {
+        let mut parts = entry.split(';');
+        let name = parts.next().unwrap_or_default().trim().to_ascii_lowercase();
+        let q = parts
+            .filter_map(|param| param.trim().strip_prefix("q="))
+            .find_map(|value| value.trim().parse::<f32>().ok())
+            .unwrap_or(1.0);
+
+        if name == coding || (coding == "gzip" && name == "x-gzip") {
+            return q;
+        }
+        if name == "*" {
+            wildcard = Some(q);
+        }
+    }
+    wildcard.unwrap_or(0.0)
+}
+
+/// Add `integrity` attributes to the `<script src>` and `<link href>` tags of
+/// `html` that load embedded scripts and stylesheets.
+///
+/// Tags that already carry an `integrity` attribute, and assets from other
+/// origins, are left alone.
+pub fn add_integrity(html: &str) -> String {
+    let mut output = String::with_capacity(html.len() + 1024);
+    let mut rest = html;
+
+    while let Some(start) = rest.find('<') {
+        output.push_str(&rest[..start]);
+        rest = &rest[start..];
+        let Some(end) = rest.find('>') else {
+            break;
+        };
+        let tag = &rest[..=end];
+        rest = &rest[end + 1..];
+
+        let attribute = if tag.starts_with("<script") {
+            "src"
+        } else if tag.starts_with("<link") {
+            "href"
+        } else {
+            output.push_str(tag);
+            continue;
+        };
+
+        // Same-origin URLs, absolute or relative to the page's `<base href>`
+        // at the root of the assets
+        let hash = attribute_value(tag, attribute)
+            .filter(|url| !url.starts_with("//") && !url.contains(':'))
+            .filter(|_| !tag.contains("integrity="))
+            .and_then(|url| integrity(url.split(['?', '#']).next().unwrap_or(url)));
+        match hash {
+            Some(hash) => {
+                let close = if tag.ends_with("/>") { "/>" } else { ">" };
+                let head = tag[..tag.len() - close.len()].trim_end();
+                output.push_str(&format!("{} integrity=\"{}\"{}", head, hash, close));
+            }
+            None => output.push_str(tag),
+        }
+    }
+
+    output.push_str(rest);
+    output
+}
+
+/// Value of a double-quoted attribute within a tag
+fn attribute_value<'a>(tag: &'a str, name: &str) -> Option<&'a str> {
+    let pattern = format!(" {}=\"", name);
+    let start = tag.find(&pattern)? + pattern.len();
+    let len = tag[start..].find('"')?;
+    Some(&tag[start..start + len])
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -13,4 +272,121 @@ mod tests {
         // Test that index.html exists in the embedded assets
         assert!(StaticAssets::get("index.html").is_some());
     }
+
+    #[test]
+    fn test_integrity_of_scripts_only() {
+        let hash = integrity("/libs/earth/1.0.0/earth.js").unwrap();
+        assert!(hash.starts_with("sha256-"));
+        assert_eq!(hash.len(), "sha256-".len() + 44);
+
+        assert!(integrity("index.html").is_none());
+        assert!(integrity("missing.js").is_none());
+        assert_eq!(
+            integrity_manifest().get("/libs/earth/1.0.0/earth.js"),
+            Some(&hash)
+        );
+    }
+
+    #[test]
+    fn test_asset_listing() {
+        let listing = asset_listing("/libs/d3/");
+        let d3 = listing
+            .iter()
+            .find(|asset| asset.path == "/libs/d3/3.3.10/d3.js")
+            .unwrap();
+        assert_eq!(d3.mime_type, "text/javascript");
+        assert_eq!(d3.sha256.len(), 64);
+        assert_eq!(d3.encodings, vec!["gzip"]);
+        assert!(listing
+            .iter()
+            .all(|asset| asset.path.starts_with("/libs/d3/")));
+        assert!(asset_listing("").len() > listing.len());
+    }
+
+    #[test]
+    fn test_bundle_version() {
+        let version = bundle_version();
+        assert_eq!(version.len(), 12);
+        assert!(version.chars().all(|c| c.is_ascii_hexdigit()));
+        assert_eq!(bundle_version(), version);
+    }
+
+    #[test]
+    fn test_quality() {
+        assert_eq!(quality("gzip, deflate, br", "br"), 1.0);
+        assert_eq!(quality("gzip;q=0.5, br;q=0", "br"), 0.0);
+        assert_eq!(quality("gzip;q=0.5, br;q=0", "gzip"), 0.5);
+        assert_eq!(quality("x-gzip", "gzip"), 1.0);
+        assert_eq!(quality("*;q=0.2", "br"), 0.2);
+        assert_eq!(quality("identity", "gzip"), 0.0);
+    }
+
+    #[test]
+    fn test_negotiate_precompressed_variant() {
+        let path = "libs/d3/3.3.10/d3.js";
+        let identity = negotiate(path, None).unwrap();
+        assert_eq!(identity.encoding, None);
+        assert!(identity.has_variants);
+
+        let gzip = negotiate(path, Some("gzip, deflate, br")).unwrap();
+        assert_eq!(gzip.encoding, Some("gzip"));
+        assert_eq!(&gzip.file.data[..2], &[0x1f, 0x8b]);
+        assert_ne!(gzip.etag(), identity.etag());
+
+        let refused = negotiate(path, Some("gzip;q=0")).unwrap();
+        assert_eq!(refused.encoding, None);
+
+        let plain = negotiate("index.html", Some("gzip")).unwrap();
+        assert_eq!(plain.encoding, None);
+        assert!(!plain.has_variants);
+        assert!(negotiate("missing.js", Some("gzip")).is_none());
+    }
+
+    #[test]
+    fn test_add_integrity() {
+        let html = concat!(
+            "<link rel=\"stylesheet\" href=\"/styles/styles.css\"/>\n",
+            "<script src=\"libs/earth/1.0.0/earth.js\" charset=\"utf-8\"></script>\n",
+            "<script src=\"//cdnjs.cloudflare.com/d3.min.js\"></script>\n",
+            "<link rel=\"shortcut icon\" href=\"/favicon.ico\"/>\n",
+        );
+        let output = add_integrity(html);
+
+        let css = integrity("styles/styles.css").unwrap();
+        let js = integrity("libs/earth/1.0.0/earth.js").unwrap();
+        assert!(output.contains(&format!(
+            "href=\"/styles/styles.css\" integrity=\"{}\"/>",
+            css
+        )));
+        assert!(output.contains(&format!("charset=\"utf-8\" integrity=\"{}\">", js)));
+        assert!(output.contains("<script src=\"//cdnjs.cloudflare.com/d3.min.js\"></script>"));
+        assert!(output.contains("<link rel=\"shortcut icon\" href=\"/favicon.ico\"/>"));
+
+        // Already hashed tags are not hashed again
+        assert_eq!(add_integrity(&output), output);
+    }
+
+    #[test]
+    fn test_gzip_variants_match_their_originals() {
+        use std::io::Read;
+
+        let variants: Vec<_> = StaticAssets::iter()
+            .filter(|path| path.ends_with(".gz"))
+            .collect();
+        assert!(variants.len() >= 6);
+        for path in variants {
+            let original = StaticAssets::get(path.trim_end_matches(".gz"))
+                .unwrap_or_else(|| panic!("{} has no original", path));
+            let variant = StaticAssets::get(&path).unwrap();
+            let mut decoded = Vec::new();
+            flate2::read::GzDecoder::new(&variant.data[..])
+                .read_to_end(&mut decoded)
+                .unwrap();
+            assert!(
+                decoded == original.data.as_ref(),
+                "{} is stale, regenerate it with gzip -9 -k -n",
+                path
+            );
+        }
+    }
 }
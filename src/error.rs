@@ -3,8 +3,9 @@ use axum::{
     response::{IntoResponse, Response},
     Json,
 };
-use serde_json::json;
+use serde::Serialize;
 use thiserror::Error;
+use utoipa::ToSchema;
 
 /// Application errors
 #[derive(Error, Debug)]
@@ -20,10 +21,84 @@ pub enum AppError {
     /// Error returned when there's an issue with request parsing
     #[error("Request error: {0}")]
     RequestError(String),
+
+    /// Error returned when a request lacks valid credentials
+    #[error("Unauthorized: {0}")]
+    Unauthorized(String),
+
+    /// Error returned when a requested resource does not exist
+    #[error("Not found: {0}")]
+    NotFound(String),
+
+    /// Error returned when a client sends more requests than it is allowed
+    #[error("Too many requests: {0}")]
+    RateLimited(String),
+
+    /// Error returned when the server configuration is invalid
+    #[error("Configuration error: {0}")]
+    ConfigError(String),
+
+    /// Error returned when the persistent store cannot be read or written
+    #[error("Storage error: {0}")]
+    StorageError(String),
+
+    /// Error returned when a request is shed because the server is overloaded
+    #[error("Service unavailable: {0}")]
+    Overloaded(String),
+
+    /// Error returned when a request is not answered within the time the
+    /// client allowed
+    #[error("Gateway timeout: {0}")]
+    DeadlineExceeded(String),
+
+    /// Error returned when the backend does not answer within the
+    /// configured connect or request timeout
+    #[error("Backend timeout: {0}")]
+    Timeout(String),
+}
+
+/// A server-side failure, attached to the error response so the tracing
+/// middleware can report it with the request context
+#[derive(Debug, Clone)]
+pub struct ReportedError {
+    /// Error variant name, e.g. `ProxyError`
+    pub kind: &'static str,
+    pub message: String,
+}
+
+/// Body of every error response
+#[derive(Debug, Serialize, ToSchema)]
+pub struct ErrorBody {
+    /// What went wrong, prefixed with the kind of error
+    pub error: String,
+}
+
+impl AppError {
+    /// The report for errors on our side; client errors are not reported
+    fn reported(&self) -> Option<ReportedError> {
+        let kind = match self {
+            AppError::ServerError(_) => "ServerError",
+            AppError::ProxyError(_) => "ProxyError",
+            AppError::ConfigError(_) => "ConfigError",
+            AppError::StorageError(_) => "StorageError",
+            AppError::Timeout(_) => "Timeout",
+            AppError::RequestError(_)
+            | AppError::Unauthorized(_)
+            | AppError::NotFound(_)
+            | AppError::RateLimited(_)
+            | AppError::Overloaded(_)
+            | AppError::DeadlineExceeded(_) => return None,
+        };
+        Some(ReportedError {
+            kind,
+            message: self.to_string(),
+        })
+    }
 }
 
 impl IntoResponse for AppError {
     fn into_response(self) -> Response {
+        let reported = self.reported();
         let (status, error_message) = match self {
             AppError::ServerError(e) => (
                 StatusCode::INTERNAL_SERVER_ERROR,
@@ -33,12 +108,70 @@ impl IntoResponse for AppError {
             AppError::RequestError(msg) => {
                 (StatusCode::BAD_REQUEST, format!("Request error: {}", msg))
             }
+            AppError::Unauthorized(msg) => {
+                (StatusCode::UNAUTHORIZED, format!("Unauthorized: {}", msg))
+            }
+            AppError::NotFound(msg) => (StatusCode::NOT_FOUND, format!("Not found: {}", msg)),
+            AppError::RateLimited(msg) => (
+                StatusCode::TOO_MANY_REQUESTS,
+                format!("Too many requests: {}", msg),
+            ),
+            AppError::ConfigError(msg) => (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                format!("Configuration error: {}", msg),
+            ),
+            AppError::StorageError(msg) => (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                format!("Storage error: {}", msg),
+            ),
+            AppError::Overloaded(msg) => (
+                StatusCode::SERVICE_UNAVAILABLE,
+                format!("Service unavailable: {}", msg),
+            ),
+            AppError::DeadlineExceeded(msg) => (
+                StatusCode::GATEWAY_TIMEOUT,
+                format!("Gateway timeout: {}", msg),
+            ),
+            AppError::Timeout(msg) => (
+                StatusCode::GATEWAY_TIMEOUT,
+                format!("Backend timeout: {}", msg),
+            ),
         };
 
-        let body = Json(json!({
-            "error": error_message,
-        }));
+        let body = Json(ErrorBody {
+            error: error_message,
+        });
+
+        let mut response = (status, body).into_response();
+        if let Some(reported) = reported {
+            response.extensions_mut().insert(reported);
+        }
+        response
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_only_server_side_errors_are_reported() {
+        let response = AppError::ProxyError("backend down".to_string()).into_response();
+        let reported = response.extensions().get::<ReportedError>().unwrap();
+        assert_eq!(reported.kind, "ProxyError");
+        assert_eq!(reported.message, "Proxy error: backend down");
+
+        let response = AppError::RequestError("bad time".to_string()).into_response();
+        assert!(response.extensions().get::<ReportedError>().is_none());
+    }
 
-        (status, body).into_response()
+    #[test]
+    fn test_backend_timeouts_are_gateway_timeouts() {
+        let response = AppError::Timeout("no answer".to_string()).into_response();
+        assert_eq!(response.status(), StatusCode::GATEWAY_TIMEOUT);
+        assert_eq!(
+            response.extensions().get::<ReportedError>().unwrap().kind,
+            "Timeout"
+        );
     }
 }
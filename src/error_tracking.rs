@@ -0,0 +1,105 @@
+//! Optional reporting of server-side failures to Sentry
+//!
+//! Built with the `error-tracking` feature and given a DSN, the server sends
+//! panics and errors on its own side (backend unreachable or misbehaving, I/O
+//! and configuration errors) to Sentry or a Sentry-compatible tracker. Each
+//! report carries the request ID, trace ID, method and path, and the release
+//! and environment from `LoggingConfig`. Client mistakes such as bad query
+//! parameters are not reported. Without the feature nothing is reported.
+
+use axum::response::Response;
+use std::future::Future;
+
+/// Request details attached to reported errors
+#[derive(Debug, Clone)]
+pub struct RequestInfo {
+    pub method: String,
+    pub path: String,
+    pub request_id: String,
+    pub trace_id: String,
+}
+
+#[cfg(feature = "error-tracking")]
+static CLIENT: std::sync::OnceLock<sentry::ClientInitGuard> = std::sync::OnceLock::new();
+
+/// Start reporting to the tracker at `dsn`
+#[cfg(feature = "error-tracking")]
+pub fn init(dsn: &str, service_name: &str, environment: &str) -> Result<(), String> {
+    let dsn = dsn
+        .parse::<sentry::types::Dsn>()
+        .map_err(|e| format!("Invalid Sentry DSN: {}", e))?;
+    let guard = sentry::init(sentry::ClientOptions {
+        dsn: Some(dsn),
+        release: Some(format!("{}@{}", service_name, env!("CARGO_PKG_VERSION")).into()),
+        environment: Some(environment.to_string().into()),
+        ..Default::default()
+    });
+
+    // The server runs until the process exits, so the client is never closed;
+    // the panic handler flushes pending reports itself
+    CLIENT
+        .set(guard)
+        .map_err(|_| "Error tracking is already initialized".to_string())
+}
+
+/// Start reporting to the tracker at `dsn`
+#[cfg(not(feature = "error-tracking"))]
+pub fn init(_dsn: &str, _service_name: &str, _environment: &str) -> Result<(), String> {
+    Err("error tracking requires building with the `error-tracking` feature".to_string())
+}
+
+/// Handle `future`, a request, with `request` attached to anything reported
+/// while it runs, and report the error behind a failed response
+#[cfg(feature = "error-tracking")]
+pub async fn scope<F>(request: RequestInfo, future: F) -> Response
+where
+    F: Future<Output = Response>,
+{
+    use sentry::{Hub, SentryFutureExt};
+    use std::sync::Arc;
+
+    if Hub::current().client().is_none() {
+        return future.await;
+    }
+
+    let hub = Arc::new(Hub::new_from_top(Hub::current()));
+    hub.configure_scope(|scope| {
+        scope.set_transaction(Some(&format!("{} {}", request.method, request.path)));
+        scope.set_tag("request_id", &request.request_id);
+        scope.set_tag("trace_id", &request.trace_id);
+        scope.set_tag("http.method", &request.method);
+        scope.set_tag("http.path", &request.path);
+    });
+
+    let reporter = hub.clone();
+    async move {
+        let response = future.await;
+        if let Some(error) = response.extensions().get::<crate::error::ReportedError>() {
+            reporter.configure_scope(|scope| {
+                scope.set_tag("http.status_code", response.status().as_u16())
+            });
+            reporter.capture_event(sentry::protocol::Event {
+                level: sentry::Level::Error,
+                exception: vec![sentry::protocol::Exception {
+                    ty: error.kind.to_string(),
+                    value: Some(error.message.clone()),
+                    ..Default::default()
+                }]
+                .into(),
+                ..Default::default()
+            });
+        }
+        response
+    }
+    .bind_hub(hub)
+    .await
+}
+
+/// Handle `future`, a request; a no-op wrapper without error tracking
+#[cfg(not(feature = "error-tracking"))]
+pub async fn scope<F>(_request: RequestInfo, future: F) -> Response
+where
+    F: Future<Output = Response>,
+{
+    future.await
+}
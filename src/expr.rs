@@ -0,0 +1,474 @@
+//! Arithmetic expressions over named fields
+//!
+//! Plugin files describe derived variables as formulas such as
+//! `2 * pi * h / (g * t^2)`, and transform hooks rewrite values with
+//! formulas such as `if(x > 9000, nan, x * 1000)`. Expressions are parsed
+//! once into a tree with the variable names resolved to indices, then
+//! evaluated at every grid point. Missing values (NaN) propagate through the
+//! arithmetic.
+//!
+//! The grammar has numbers, the constants `pi`, `e` and `nan` (a missing
+//! value), the operators `+ - * / ^`, comparisons `< <= > >= == !=` yielding
+//! 1 or 0, parentheses, and the functions listed in [`Function`]. `^` binds
+//! tighter than unary minus and associates to the right, so `-x^2` is
+//! `-(x^2)`.
+
+use crate::error::AppError;
+
+/// A built-in function
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Function {
+    Abs,
+    Sqrt,
+    Exp,
+    Ln,
+    Log10,
+    Sin,
+    Cos,
+    Tan,
+    Atan2,
+    Hypot,
+    Min,
+    Max,
+    /// `if(condition, then, else)`, where any non-zero condition is true
+    If,
+}
+
+impl Function {
+    fn from_name(name: &str) -> Option<Self> {
+        Some(match name {
+            "abs" => Function::Abs,
+            "sqrt" => Function::Sqrt,
+            "exp" => Function::Exp,
+            "ln" => Function::Ln,
+            "log10" => Function::Log10,
+            "sin" => Function::Sin,
+            "cos" => Function::Cos,
+            "tan" => Function::Tan,
+            "atan2" => Function::Atan2,
+            "hypot" => Function::Hypot,
+            "min" => Function::Min,
+            "max" => Function::Max,
+            "if" => Function::If,
+            _ => return None,
+        })
+    }
+
+    fn arity(self) -> usize {
+        match self {
+            Function::Atan2 | Function::Hypot | Function::Min | Function::Max => 2,
+            Function::If => 3,
+            _ => 1,
+        }
+    }
+
+    fn apply(self, args: &[f64]) -> f64 {
+        match self {
+            Function::Abs => args[0].abs(),
+            Function::Sqrt => args[0].sqrt(),
+            Function::Exp => args[0].exp(),
+            Function::Ln => args[0].ln(),
+            Function::Log10 => args[0].log10(),
+            Function::Sin => args[0].sin(),
+            Function::Cos => args[0].cos(),
+            Function::Tan => args[0].tan(),
+            Function::Atan2 => args[0].atan2(args[1]),
+            Function::Hypot => args[0].hypot(args[1]),
+            // NaN-propagating, unlike f64::min and f64::max
+            Function::Min if args.iter().any(|v| v.is_nan()) => f64::NAN,
+            Function::Min => args[0].min(args[1]),
+            Function::Max if args.iter().any(|v| v.is_nan()) => f64::NAN,
+            Function::Max => args[0].max(args[1]),
+            Function::If if args[0].is_nan() => f64::NAN,
+            Function::If if args[0] != 0.0 => args[1],
+            Function::If => args[2],
+        }
+    }
+}
+
+/// A binary operator
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Operator {
+    Add,
+    Subtract,
+    Multiply,
+    Divide,
+    Power,
+    Less,
+    LessOrEqual,
+    Greater,
+    GreaterOrEqual,
+    Equal,
+    NotEqual,
+}
+
+impl Operator {
+    fn apply(self, a: f64, b: f64) -> f64 {
+        let compare = |result: bool| {
+            if a.is_nan() || b.is_nan() {
+                f64::NAN
+            } else if result {
+                1.0
+            } else {
+                0.0
+            }
+        };
+        match self {
+            Operator::Add => a + b,
+            Operator::Subtract => a - b,
+            Operator::Multiply => a * b,
+            Operator::Divide => a / b,
+            Operator::Power => a.powf(b),
+            Operator::Less => compare(a < b),
+            Operator::LessOrEqual => compare(a <= b),
+            Operator::Greater => compare(a > b),
+            Operator::GreaterOrEqual => compare(a >= b),
+            Operator::Equal => compare(a == b),
+            Operator::NotEqual => compare(a != b),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Node {
+    Number(f64),
+    Variable(usize),
+    Negate(Box<Node>),
+    Binary(Operator, Box<Node>, Box<Node>),
+    Call(Function, Vec<Node>),
+}
+
+impl Node {
+    fn evaluate(&self, values: &[f64]) -> f64 {
+        match self {
+            Node::Number(value) => *value,
+            Node::Variable(index) => values[*index],
+            Node::Negate(node) => -node.evaluate(values),
+            Node::Binary(operator, a, b) => operator.apply(a.evaluate(values), b.evaluate(values)),
+            Node::Call(function, args) => {
+                let args: Vec<f64> = args.iter().map(|arg| arg.evaluate(values)).collect();
+                function.apply(&args)
+            }
+        }
+    }
+}
+
+/// A parsed expression
+#[derive(Debug, Clone, PartialEq)]
+pub struct Expression {
+    root: Node,
+}
+
+impl Expression {
+    /// Parse `source`, resolving each name to its position in `variables`
+    pub fn parse(source: &str, variables: &[&str]) -> Result<Self, AppError> {
+        let tokens = tokenize(source)?;
+        let mut parser = Parser {
+            tokens: &tokens,
+            position: 0,
+            variables,
+        };
+        let root = parser.comparison()?;
+        match parser.peek() {
+            None => Ok(Self { root }),
+            Some(token) => Err(syntax_error(format!("unexpected {}", token))),
+        }
+    }
+
+    /// Value of the expression for one set of variable values, given in the
+    /// order of the names passed to [`Expression::parse`]
+    pub fn evaluate(&self, values: &[f64]) -> f64 {
+        self.root.evaluate(values)
+    }
+
+    /// Evaluate at every point of equally long fields, one per variable
+    pub fn evaluate_fields(&self, fields: &[&[f64]]) -> Vec<f64> {
+        let points = fields.first().map_or(0, |field| field.len());
+        let mut values = vec![0.0; fields.len()];
+        (0..points)
+            .map(|point| {
+                for (value, field) in values.iter_mut().zip(fields) {
+                    *value = field[point];
+                }
+                self.evaluate(&values)
+            })
+            .collect()
+    }
+}
+
+fn syntax_error(message: String) -> AppError {
+    AppError::ConfigError(format!("Invalid expression: {}", message))
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Token {
+    Number(f64),
+    Name(String),
+    Symbol(&'static str),
+}
+
+impl std::fmt::Display for Token {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Token::Number(value) => write!(f, "number {}", value),
+            Token::Name(name) => write!(f, "name '{}'", name),
+            Token::Symbol(symbol) => write!(f, "'{}'", symbol),
+        }
+    }
+}
+
+/// Symbols, longest first so `<=` is not read as `<`
+const SYMBOLS: [&str; 15] = [
+    "<=", ">=", "==", "!=", "+", "-", "*", "/", "^", "(", ")", ",", "<", ">", "!",
+];
+
+fn tokenize(source: &str) -> Result<Vec<Token>, AppError> {
+    let mut tokens = Vec::new();
+    let mut rest = source.trim_start();
+    while let Some(c) = rest.chars().next() {
+        if c.is_ascii_digit() || c == '.' {
+            let end = number_length(rest);
+            let number = rest[..end]
+                .parse()
+                .map_err(|_| syntax_error(format!("invalid number '{}'", &rest[..end])))?;
+            tokens.push(Token::Number(number));
+            rest = &rest[end..];
+        } else if c.is_ascii_alphabetic() || c == '_' {
+            let end = rest
+                .find(|c: char| !(c.is_ascii_alphanumeric() || c == '_'))
+                .unwrap_or(rest.len());
+            tokens.push(Token::Name(rest[..end].to_string()));
+            rest = &rest[end..];
+        } else if let Some(symbol) = SYMBOLS.iter().find(|s| rest.starts_with(**s)) {
+            tokens.push(Token::Symbol(symbol));
+            rest = &rest[symbol.len()..];
+        } else {
+            return Err(syntax_error(format!("unexpected character '{}'", c)));
+        }
+        rest = rest.trim_start();
+    }
+    Ok(tokens)
+}
+
+/// Length of the number at the start of `s`, exponent included
+fn number_length(s: &str) -> usize {
+    let bytes = s.as_bytes();
+    let digits = |mut i: usize| {
+        while i < bytes.len() && (bytes[i].is_ascii_digit() || bytes[i] == b'.') {
+            i += 1;
+        }
+        i
+    };
+    let end = digits(0);
+    if end < bytes.len() && (bytes[end] == b'e' || bytes[end] == b'E') {
+        let mut exponent = end + 1;
+        if exponent < bytes.len() && (bytes[exponent] == b'+' || bytes[exponent] == b'-') {
+            exponent += 1;
+        }
+        if exponent < bytes.len() && bytes[exponent].is_ascii_digit() {
+            return digits(exponent);
+        }
+    }
+    end
+}
+
+struct Parser<'a> {
+    tokens: &'a [Token],
+    position: usize,
+    variables: &'a [&'a str],
+}
+
+impl Parser<'_> {
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.position)
+    }
+
+    fn next(&mut self) -> Option<Token> {
+        let token = self.tokens.get(self.position).cloned();
+        self.position += 1;
+        token
+    }
+
+    fn eat(&mut self, symbol: &str) -> bool {
+        if matches!(self.peek(), Some(Token::Symbol(s)) if *s == symbol) {
+            self.position += 1;
+            true
+        } else {
+            false
+        }
+    }
+
+    fn expect(&mut self, symbol: &str) -> Result<(), AppError> {
+        if self.eat(symbol) {
+            Ok(())
+        } else {
+            Err(syntax_error(match self.peek() {
+                Some(token) => format!("expected '{}', found {}", symbol, token),
+                None => format!("expected '{}' at the end", symbol),
+            }))
+        }
+    }
+
+    fn comparison(&mut self) -> Result<Node, AppError> {
+        let left = self.sum()?;
+        let operator = match self.peek() {
+            Some(Token::Symbol("<")) => Operator::Less,
+            Some(Token::Symbol("<=")) => Operator::LessOrEqual,
+            Some(Token::Symbol(">")) => Operator::Greater,
+            Some(Token::Symbol(">=")) => Operator::GreaterOrEqual,
+            Some(Token::Symbol("==")) => Operator::Equal,
+            Some(Token::Symbol("!=")) => Operator::NotEqual,
+            _ => return Ok(left),
+        };
+        self.position += 1;
+        let right = self.sum()?;
+        Ok(Node::Binary(operator, Box::new(left), Box::new(right)))
+    }
+
+    fn sum(&mut self) -> Result<Node, AppError> {
+        let mut node = self.product()?;
+        loop {
+            let operator = if self.eat("+") {
+                Operator::Add
+            } else if self.eat("-") {
+                Operator::Subtract
+            } else {
+                return Ok(node);
+            };
+            node = Node::Binary(operator, Box::new(node), Box::new(self.product()?));
+        }
+    }
+
+    fn product(&mut self) -> Result<Node, AppError> {
+        let mut node = self.unary()?;
+        loop {
+            let operator = if self.eat("*") {
+                Operator::Multiply
+            } else if self.eat("/") {
+                Operator::Divide
+            } else {
+                return Ok(node);
+            };
+            node = Node::Binary(operator, Box::new(node), Box::new(self.unary()?));
+        }
+    }
+
+    fn unary(&mut self) -> Result<Node, AppError> {
+        if self.eat("-") {
+            return Ok(Node::Negate(Box::new(self.unary()?)));
+        }
+        if self.eat("+") {
+            return self.unary();
+        }
+        self.power()
+    }
+
+    fn power(&mut self) -> Result<Node, AppError> {
+        let base = self.atom()?;
+        if self.eat("^") {
+            let exponent = self.unary()?;
+            return Ok(Node::Binary(
+                Operator::Power,
+                Box::new(base),
+                Box::new(exponent),
+            ));
+        }
+        Ok(base)
+    }
+
+    fn atom(&mut self) -> Result<Node, AppError> {
+        match self.next() {
+            Some(Token::Number(value)) => Ok(Node::Number(value)),
+            Some(Token::Symbol("(")) => {
+                let node = self.comparison()?;
+                self.expect(")")?;
+                Ok(node)
+            }
+            Some(Token::Name(name)) if self.peek() == Some(&Token::Symbol("(")) => {
+                let function = Function::from_name(&name)
+                    .ok_or_else(|| syntax_error(format!("unknown function '{}'", name)))?;
+                self.position += 1;
+                let mut args = vec![self.comparison()?];
+                while self.eat(",") {
+                    args.push(self.comparison()?);
+                }
+                self.expect(")")?;
+                if args.len() != function.arity() {
+                    return Err(syntax_error(format!(
+                        "{}() takes {} arguments, not {}",
+                        name,
+                        function.arity(),
+                        args.len()
+                    )));
+                }
+                Ok(Node::Call(function, args))
+            }
+            Some(Token::Name(name)) => {
+                if let Some(index) = self.variables.iter().position(|v| *v == name) {
+                    return Ok(Node::Variable(index));
+                }
+                match name.as_str() {
+                    "pi" => Ok(Node::Number(std::f64::consts::PI)),
+                    "e" => Ok(Node::Number(std::f64::consts::E)),
+                    "nan" => Ok(Node::Number(f64::NAN)),
+                    _ => Err(syntax_error(format!("unknown name '{}'", name))),
+                }
+            }
+            Some(token) => Err(syntax_error(format!("unexpected {}", token))),
+            None => Err(syntax_error("unexpected end".to_string())),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn eval(source: &str, values: &[f64]) -> f64 {
+        Expression::parse(source, &["x", "y"])
+            .unwrap()
+            .evaluate(values)
+    }
+
+    #[test]
+    fn test_precedence() {
+        assert_eq!(eval("1 + 2 * 3", &[]), 7.0);
+        assert_eq!(eval("(1 + 2) * 3", &[]), 9.0);
+        assert_eq!(eval("-x^2", &[3.0, 0.0]), -9.0);
+        assert_eq!(eval("2^3^2", &[]), 512.0);
+        assert_eq!(eval("x - y - 1", &[5.0, 2.0]), 2.0);
+        assert_eq!(eval("1.5e2 / 3", &[]), 50.0);
+        assert_eq!(eval("x > y", &[2.0, 1.0]), 1.0);
+    }
+
+    #[test]
+    fn test_functions() {
+        assert_eq!(eval("hypot(x, y)", &[3.0, 4.0]), 5.0);
+        assert_eq!(eval("if(x <= 0, 0, sqrt(x))", &[-4.0, 0.0]), 0.0);
+        assert_eq!(eval("if(x <= 0, 0, sqrt(x))", &[16.0, 0.0]), 4.0);
+        assert!((eval("2 * pi", &[]) - std::f64::consts::TAU).abs() < 1e-12);
+        assert!(eval("max(x, y)", &[f64::NAN, 1.0]).is_nan());
+        assert!(eval("x + 1", &[f64::NAN, 0.0]).is_nan());
+        assert!(eval("if(x > 9000, nan, x)", &[9999.0, 0.0]).is_nan());
+    }
+
+    #[test]
+    fn test_parse_errors() {
+        for source in ["1 +", "sqrt(1, 2)", "z * 2", "foo(1)", "(1", "1 $ 2", "1 2"] {
+            assert!(
+                Expression::parse(source, &["x"]).is_err(),
+                "{} should not parse",
+                source
+            );
+        }
+    }
+
+    #[test]
+    fn test_evaluate_fields() {
+        let expression = Expression::parse("x * y", &["x", "y"]).unwrap();
+        assert_eq!(
+            expression.evaluate_fields(&[&[1.0, 2.0], &[3.0, 4.0]]),
+            [3.0, 8.0]
+        );
+    }
+}
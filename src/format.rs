@@ -0,0 +1,184 @@
+//! Output formats of the Earth data routes
+//!
+//! The same product can be served in several representations, picked per
+//! request with `?format=` or, without one, from the `Accept` header:
+//!
+//! | `format`  | Media type                              | Body                                   |
+//! |-----------|-----------------------------------------|----------------------------------------|
+//! | `json`    | `application/json`                      | Earth JSON, the default                |
+//! | `msgpack` | `application/msgpack`                   | The same document as MessagePack       |
+//! | `packed`  | `application/vnd.rossby.packed+json`    | Earth JSON with [packed](crate::packing) data arrays |
+//!
+//! Browsers get JSON, while CLI tools and notebooks can ask for the more
+//! compact forms. Responses name `Accept` in `Vary` so caches keep the
+//! representations apart.
+
+use axum::{
+    http::{header, HeaderMap, HeaderValue, StatusCode},
+    response::{IntoResponse, Response},
+};
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use utoipa::ToSchema;
+
+use crate::error::AppError;
+
+pub const JSON_CONTENT_TYPE: &str = "application/json";
+pub const MSGPACK_CONTENT_TYPE: &str = "application/msgpack";
+pub const PACKED_CONTENT_TYPE: &str = "application/vnd.rossby.packed+json";
+
+/// Representation of an Earth data response
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Deserialize, ToSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum OutputFormat {
+    #[default]
+    Json,
+    MsgPack,
+    Packed,
+}
+
+impl fmt::Display for OutputFormat {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            OutputFormat::Json => "json",
+            OutputFormat::MsgPack => "msgpack",
+            OutputFormat::Packed => "packed",
+        })
+    }
+}
+
+impl OutputFormat {
+    /// The format named in the query, or else the one the client accepts
+    /// most, JSON when it accepts none of them
+    pub fn negotiate(requested: Option<OutputFormat>, headers: &HeaderMap) -> Self {
+        if let Some(format) = requested {
+            return format;
+        }
+        let Some(accept) = headers.get(header::ACCEPT).and_then(|v| v.to_str().ok()) else {
+            return OutputFormat::Json;
+        };
+
+        let mut best: Option<(f32, OutputFormat)> = None;
+        for range in accept.split(',') {
+            let mut parts = range.split(';').map(str::trim);
+            let media_type = parts.next().unwrap_or_default().to_ascii_lowercase();
+            let quality = parts
+                .filter_map(|p| p.strip_prefix("q="))
+                .find_map(|q| q.parse::<f32>().ok())
+                .unwrap_or(1.0);
+            let format = match media_type.as_str() {
+                JSON_CONTENT_TYPE | "application/*" | "*/*" => OutputFormat::Json,
+                MSGPACK_CONTENT_TYPE | "application/x-msgpack" | "application/vnd.msgpack" => {
+                    OutputFormat::MsgPack
+                }
+                PACKED_CONTENT_TYPE => OutputFormat::Packed,
+                _ => continue,
+            };
+            if quality > 0.0 && best.is_none_or(|(q, _)| quality > q) {
+                best = Some((quality, format));
+            }
+        }
+        best.map(|(_, format)| format).unwrap_or_default()
+    }
+
+    pub fn content_type(self) -> &'static str {
+        match self {
+            OutputFormat::Json => JSON_CONTENT_TYPE,
+            OutputFormat::MsgPack => MSGPACK_CONTENT_TYPE,
+            OutputFormat::Packed => PACKED_CONTENT_TYPE,
+        }
+    }
+
+    /// Whether the data arrays are packed
+    pub fn is_packed(self) -> bool {
+        self == OutputFormat::Packed
+    }
+
+    /// Key distinguishing the representations of `request` for validators,
+    /// the request itself for JSON so existing ETags stay valid
+    pub fn variant(self, request: &str) -> String {
+        match self {
+            OutputFormat::Json => request.to_string(),
+            format => format!("{} ({})", request, format),
+        }
+    }
+
+    /// A `200 OK` response with `value` in this format
+    pub fn render<T: Serialize + ?Sized>(self, value: &T) -> Result<Response, AppError> {
+        let error =
+            |e: String| AppError::ProxyError(format!("Failed to serialize response: {}", e));
+        let body = match self {
+            OutputFormat::Json | OutputFormat::Packed => {
+                serde_json::to_vec(value).map_err(|e| error(e.to_string()))?
+            }
+            OutputFormat::MsgPack => {
+                rmp_serde::to_vec_named(value).map_err(|e| error(e.to_string()))?
+            }
+        };
+        Ok((
+            StatusCode::OK,
+            [
+                (
+                    header::CONTENT_TYPE,
+                    HeaderValue::from_static(self.content_type()),
+                ),
+                (header::VARY, HeaderValue::from_static("accept")),
+            ],
+            body,
+        )
+            .into_response())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn accepting(accept: &str) -> OutputFormat {
+        let mut headers = HeaderMap::new();
+        headers.insert(header::ACCEPT, accept.parse().unwrap());
+        OutputFormat::negotiate(None, &headers)
+    }
+
+    #[test]
+    fn test_negotiate() {
+        assert_eq!(
+            OutputFormat::negotiate(None, &HeaderMap::new()),
+            OutputFormat::Json
+        );
+        assert_eq!(accepting("application/msgpack"), OutputFormat::MsgPack);
+        assert_eq!(
+            accepting("application/json;q=0.5, application/x-msgpack"),
+            OutputFormat::MsgPack
+        );
+        assert_eq!(
+            accepting("application/vnd.rossby.packed+json, */*;q=0.1"),
+            OutputFormat::Packed
+        );
+        assert_eq!(
+            accepting("text/html,application/xhtml+xml,*/*;q=0.8"),
+            OutputFormat::Json
+        );
+        assert_eq!(accepting("image/png"), OutputFormat::Json);
+        assert_eq!(accepting("application/msgpack;q=0"), OutputFormat::Json);
+
+        // The query wins over the header
+        let mut headers = HeaderMap::new();
+        headers.insert(header::ACCEPT, MSGPACK_CONTENT_TYPE.parse().unwrap());
+        assert_eq!(
+            OutputFormat::negotiate(Some(OutputFormat::Packed), &headers),
+            OutputFormat::Packed
+        );
+    }
+
+    #[test]
+    fn test_render_msgpack() {
+        let value = serde_json::json!({"data": [1.5, 2.5]});
+        let response = OutputFormat::MsgPack.render(&value).unwrap();
+        assert_eq!(
+            response.headers()[header::CONTENT_TYPE],
+            MSGPACK_CONTENT_TYPE
+        );
+        assert_eq!(response.headers()[header::VARY], "accept");
+    }
+}
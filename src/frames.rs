@@ -0,0 +1,322 @@
+//! Bundles of consecutive Earth frames
+//!
+//! Scrubbing through time one `/data/weather` request per timestep stalls on
+//! every step. `/data/frames/{variable}` returns a window of consecutive
+//! timesteps at once, each frame holding the same records as the Earth data
+//! routes, so the frontend can preload the window and scrub through it
+//! locally. The frames are fetched from the backend in one `time_range`
+//! request. With `delta=true` every frame after the first carries the
+//! differences from the frame before it, which compress far better for
+//! slowly changing fields.
+
+use axum::{
+    extract::{Path, Query, State},
+    http::{HeaderMap, Uri},
+    response::Response,
+};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::{collections::HashMap, sync::Arc, time::Instant};
+use tracing::{info, instrument};
+use utoipa::IntoParams;
+
+use crate::{
+    derived::{self, fetch_with_derived},
+    error::{AppError, ErrorBody},
+    format::OutputFormat,
+    grid::{coordinate_values, DataArray},
+    handlers::{component_names, earth_records, fetch_data, EarthDataPoint, EarthLayout},
+    levels::EarthLevel,
+    mask::MaskMode,
+    server::AppState,
+    timesteps::{select_time, to_iso},
+    units::UnitSystem,
+};
+
+/// Frames served when the request does not say
+const DEFAULT_FRAME_COUNT: usize = 8;
+/// Most frames served by one request
+const MAX_FRAME_COUNT: usize = 48;
+
+/// Query parameters of `/data/frames/{variable}`
+#[derive(Debug, Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct FramesQuery {
+    /// First frame, as a backend time value or an ISO-8601 date resolved to
+    /// the nearest timestep; defaults to the first timestep
+    pub start: Option<String>,
+    /// Number of frames, fewer when the time axis ends first
+    pub count: Option<usize>,
+    /// Encode frames after the first as differences from the previous one
+    #[serde(default)]
+    pub delta: bool,
+    /// Hide values over `land` or `ocean`, or `none` to disable automatic masking
+    pub mask: Option<MaskMode>,
+    /// Pressure level in hPa, for variables on pressure levels
+    pub level: Option<f64>,
+    /// Pack the data arrays, see [`crate::packing`]
+    pub packed: Option<bool>,
+    /// Quantization step of packed data arrays
+    pub precision: Option<f64>,
+    /// Representation of the response, negotiated from `Accept` when absent
+    pub format: Option<OutputFormat>,
+    /// Unit system of the values, see [`crate::units`]
+    pub units: Option<UnitSystem>,
+}
+
+/// One timestep of a bundle
+#[derive(Serialize)]
+struct Frame {
+    /// Backend time value
+    time: f64,
+    /// The time as an ISO-8601 date-time
+    date: String,
+    /// Earth records, as served by the Earth data routes
+    records: Vec<EarthDataPoint>,
+}
+
+/// Body of `/data/frames/{variable}`
+#[derive(Serialize)]
+struct FrameBundle {
+    variable: String,
+    /// `"full"`, or `"delta"` when frames after the first hold differences
+    encoding: &'static str,
+    frames: Vec<Frame>,
+}
+
+/// Replace each field of a series after the first by its difference from
+/// the field before it. Missing values stay missing, and a value following a
+/// missing one is stored as is, so decoding adds each field to the previous
+/// decoded one with missing values taken as zero.
+fn delta_encode(series: &mut [&mut Vec<f64>]) {
+    for i in (1..series.len()).rev() {
+        let (before, after) = series.split_at_mut(i);
+        for (value, base) in after[0].iter_mut().zip(before[i - 1].iter()) {
+            if base.is_finite() {
+                *value -= base;
+            }
+        }
+    }
+}
+
+/// Horizontal fields of each component at each of `times`, fetched in one
+/// backend request, or one per frame for derived products
+async fn fetch_frame_fields(
+    state: &AppState,
+    metadata: &Value,
+    components: &[&str],
+    times: &[f64],
+    level: Option<&(String, f64)>,
+    is_derived: bool,
+) -> Result<Vec<Vec<Vec<f64>>>, AppError> {
+    let slices = |response: &Value, index: usize| -> Result<Vec<Vec<f64>>, AppError> {
+        let selection = HashMap::from([("time".to_string(), index)]);
+        components
+            .iter()
+            .map(|component| {
+                DataArray::from_rossby_response(response, component)?.horizontal_slice(&selection)
+            })
+            .collect()
+    };
+
+    if is_derived {
+        let responses = futures::future::try_join_all(
+            times
+                .iter()
+                .map(|time| fetch_with_derived(state, metadata, components, Some(*time))),
+        )
+        .await?;
+        return responses
+            .iter()
+            .map(|response| slices(response, 0))
+            .collect();
+    }
+
+    let (first, last) = (times[0], times[times.len() - 1]);
+    let mut query = format!(
+        "vars={}&time_range={},{}&format=json",
+        components.join(","),
+        first,
+        last
+    );
+    if let Some((dimension, value)) = level {
+        query.push_str(&format!("&{}={}", dimension, value));
+    }
+    let response = fetch_data(state, &query).await?;
+
+    let returned =
+        DataArray::from_rossby_response(&response, components[0])?.dimension_size("time");
+    if returned != times.len() {
+        return Err(AppError::ProxyError(format!(
+            "Backend returned {} timesteps for the {} between {} and {}",
+            returned,
+            times.len(),
+            to_iso(first),
+            to_iso(last)
+        )));
+    }
+    (0..times.len())
+        .map(|index| slices(&response, index))
+        .collect()
+}
+
+/// Handler for `GET /data/frames/{variable}`
+#[utoipa::path(
+    get,
+    path = "/data/frames/{variable}",
+    tag = "earth",
+    summary = "Consecutive timesteps of a variable, for scrubbing",
+    params(("variable" = String, Path, description = "Variable name"), FramesQuery),
+    responses(
+        (
+            status = 200,
+            description = "The frames, each holding the records of the Earth data routes",
+            content(
+                (serde_json::Value = "application/json"),
+                (serde_json::Value = "application/msgpack"),
+                (serde_json::Value = "application/vnd.rossby.packed+json"),
+            )
+        ),
+        (status = 304, description = "Unchanged since the request's `If-None-Match` or `If-Modified-Since`"),
+        (status = 400, description = "Invalid variable or query", body = ErrorBody),
+        (status = 502, description = "The backend failed", body = ErrorBody),
+    )
+)]
+#[instrument(skip(state, headers))]
+pub async fn earth_frames(
+    State(state): State<Arc<AppState>>,
+    Path(variable): Path<String>,
+    Query(query): Query<FramesQuery>,
+    uri: Uri,
+    headers: HeaderMap,
+) -> Result<Response, AppError> {
+    let start_time = Instant::now();
+    let count = query.count.unwrap_or(DEFAULT_FRAME_COUNT);
+    if count == 0 || count > MAX_FRAME_COUNT {
+        return Err(AppError::RequestError(format!(
+            "count must be between 1 and {}",
+            MAX_FRAME_COUNT
+        )));
+    }
+    let level = match query.level {
+        None => EarthLevel::Surface,
+        Some(hpa) if hpa.is_finite() && hpa > 0.0 => EarthLevel::Isobaric(hpa),
+        Some(hpa) => {
+            return Err(AppError::RequestError(format!(
+                "Invalid pressure level {}",
+                hpa
+            )))
+        }
+    };
+
+    let catalog = state.catalog.load(&state).await?;
+    let format = OutputFormat::negotiate(query.format, &headers);
+    let freshness = catalog.freshness(&format.variant(&uri.to_string()));
+    if freshness.matches(&headers) {
+        return Ok(freshness.not_modified());
+    }
+    let metadata = catalog.metadata();
+    let (var_info, level) = catalog.select_product(&variable, level)?;
+    let is_derived = derived::is_derived(&state.derived_variables, metadata, &var_info.name);
+    if level.is_some() && is_derived {
+        return Err(AppError::RequestError(format!(
+            "Derived product '{}' has no pressure levels",
+            var_info.name
+        )));
+    }
+
+    // The window of timesteps starting at the requested one
+    let start = select_time(metadata, query.start.as_deref())?;
+    let axis = coordinate_values(metadata, "time").unwrap_or_default();
+    let first = axis
+        .iter()
+        .position(|t| (t - start).abs() < 1e-9)
+        .unwrap_or(0);
+    let times: Vec<f64> = axis.iter().skip(first).take(count).copied().collect();
+    if times.is_empty() {
+        return Err(AppError::RequestError(format!(
+            "Variable '{}' has no timesteps",
+            var_info.name
+        )));
+    }
+
+    let layout = EarthLayout::new(&state, metadata, &var_info.name)?;
+    let packed = if format.is_packed() {
+        Some(true)
+    } else {
+        query.packed
+    };
+    let packing = state.packing.resolve(packed, query.precision)?;
+    let components = component_names(&var_info);
+    let fields = fetch_frame_fields(
+        &state,
+        metadata,
+        &components,
+        &times,
+        level.as_ref(),
+        is_derived,
+    )
+    .await?;
+
+    let mut frames = Vec::with_capacity(times.len());
+    for (time, fields) in times.iter().zip(fields) {
+        frames.push(Frame {
+            time: *time,
+            date: to_iso(*time),
+            records: earth_records(
+                &state, metadata, &var_info, query.mask, *time, &layout, fields,
+            )
+            .await?,
+        });
+    }
+    if let Some(units) = query.units {
+        for record in frames.iter_mut().flat_map(|frame| &mut frame.records) {
+            record.convert_units(units);
+        }
+    }
+    if query.delta {
+        for record in 0..frames[0].records.len() {
+            let mut series: Vec<&mut Vec<f64>> = frames
+                .iter_mut()
+                .map(|frame| &mut frame.records[record].data)
+                .collect();
+            delta_encode(&mut series);
+        }
+    }
+    if let Some(precision) = packing {
+        for record in frames.iter_mut().flat_map(|frame| &mut frame.records) {
+            record.pack(precision)?;
+        }
+    }
+
+    info!(
+        "Served {} Earth frames of {} in {}ms",
+        frames.len(),
+        var_info.name,
+        start_time.elapsed().as_millis()
+    );
+    let bundle = FrameBundle {
+        variable: var_info.name.clone(),
+        encoding: if query.delta { "delta" } else { "full" },
+        frames,
+    };
+    Ok(freshness.apply(format.render(&bundle)?))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_delta_encode() {
+        let mut first = vec![1.0, f64::NAN, 3.0];
+        let mut second = vec![1.5, 2.0, f64::NAN];
+        let mut third = vec![2.0, 2.5, 4.0];
+        delta_encode(&mut [&mut first, &mut second, &mut third]);
+
+        assert_eq!(first[0], 1.0);
+        assert_eq!(second[..2], [0.5, 2.0]);
+        assert!(second[2].is_nan());
+        assert_eq!(third, [0.5, 0.5, 4.0]);
+    }
+}
@@ -0,0 +1,467 @@
+//! Data freshness headers for offline caches
+//!
+//! Data responses carry `X-Data-Version`, a hash of the backend metadata that
+//! changes whenever the dataset does (new time steps, variables or grid), and
+//! a weak `ETag` combining that version with the request. A service worker can
+//! keep its cached data while the version is unchanged and drop it when the
+//! version moves on, and a conditional request whose `If-None-Match` still
+//! matches gets `304 Not Modified` without any data being fetched from the
+//! backend. `/api/cache-manifest` tells the worker which routes follow this
+//! contract.
+//!
+//! A [`CachePolicy`] can relax this for browsers and CDNs. Responses for a
+//! timestep before the latest one, named explicitly in the request, describe
+//! data that no longer changes; they may be cached for
+//! `--cache-historical-max-age` seconds and carry a `Last-Modified` derived
+//! from the timestep. Metadata and other data responses may be cached for
+//! `--cache-recent-max-age` seconds.
+//!
+//! The validators of embedded static assets, their content hash and the time
+//! they were embedded, are checked with [`etag_matches`] and
+//! [`unmodified_since`]. How long the assets themselves may be cached is set
+//! by an [`AssetCachePolicy`].
+
+use axum::{
+    http::{header, HeaderMap, HeaderValue, StatusCode},
+    response::{IntoResponse, Response},
+};
+use chrono::{DateTime, Utc};
+use serde_json::{json, Value};
+use sha2::{Digest, Sha256};
+
+use crate::{error::AppError, grid::coordinate_values, timesteps::to_datetime};
+
+/// Header carrying the dataset version
+pub const DATA_VERSION_HEADER: &str = "x-data-version";
+
+/// Seconds embedded assets may be cached by default
+pub const DEFAULT_ASSET_MAX_AGE: u64 = 3600;
+/// Seconds the index page may be cached by default
+pub const DEFAULT_INDEX_MAX_AGE: u64 = 60;
+/// Cache-Control of assets requested by their content hash
+const IMMUTABLE: &str = "public, max-age=31536000, immutable";
+
+/// Version of the `/api/cache-manifest` format
+const CACHE_MANIFEST_VERSION: u32 = 1;
+
+/// Version of the dataset described by `metadata`, as 16 hex digits
+pub fn data_version(metadata: &Value) -> String {
+    short_hash(&serde_json::to_vec(metadata).unwrap_or_default())
+}
+
+fn short_hash(bytes: &[u8]) -> String {
+    Sha256::digest(bytes)[..8]
+        .iter()
+        .map(|b| format!("{:02x}", b))
+        .collect()
+}
+
+/// Freshness validators of one data response
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DataFreshness {
+    version: String,
+    etag: String,
+}
+
+impl DataFreshness {
+    /// Validators for `request`, the path and query asked for, against the
+    /// dataset described by `metadata`
+    pub fn new(metadata: &Value, request: &str) -> Self {
+        Self::from_version(data_version(metadata), request)
+    }
+
+    /// Validators for `request` at the dataset version `version`
+    pub fn from_version(version: String, request: &str) -> Self {
+        let etag = format!("W/\"{}-{}\"", version, short_hash(request.as_bytes()));
+        Self { version, etag }
+    }
+
+    pub fn version(&self) -> &str {
+        &self.version
+    }
+
+    pub fn etag(&self) -> &str {
+        &self.etag
+    }
+
+    /// Whether the client's `If-None-Match` names the current response
+    pub fn matches(&self, headers: &HeaderMap) -> bool {
+        etag_matches(headers, &self.etag)
+    }
+
+    /// `304 Not Modified` for a client that already has the response
+    pub fn not_modified(&self) -> Response {
+        self.apply(StatusCode::NOT_MODIFIED.into_response())
+    }
+
+    /// Add the validators to `response`. Caches must revalidate before
+    /// reusing it, which is cheap while the dataset is unchanged.
+    pub fn apply(&self, mut response: Response) -> Response {
+        let headers = response.headers_mut();
+        if let Ok(version) = HeaderValue::from_str(&self.version) {
+            headers.insert(DATA_VERSION_HEADER, version);
+        }
+        if let Ok(etag) = HeaderValue::from_str(&self.etag) {
+            headers.insert(header::ETAG, etag);
+        }
+        headers.insert(header::CACHE_CONTROL, HeaderValue::from_static("no-cache"));
+        response
+    }
+}
+
+/// Whether `If-None-Match` in `headers` names `etag`, compared weakly
+pub fn etag_matches(headers: &HeaderMap, etag: &str) -> bool {
+    let opaque = etag.trim_start_matches("W/");
+    headers
+        .get_all(header::IF_NONE_MATCH)
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(','))
+        .map(str::trim)
+        .any(|tag| tag == "*" || tag.trim_start_matches("W/") == opaque)
+}
+
+/// Whether a response last modified at `modified` is unchanged since the
+/// `If-Modified-Since` of `headers`. The header is ignored next to an
+/// `If-None-Match`, which takes precedence.
+pub fn unmodified_since(headers: &HeaderMap, modified: DateTime<Utc>) -> bool {
+    if headers.contains_key(header::IF_NONE_MATCH) {
+        return false;
+    }
+    headers
+        .get(header::IF_MODIFIED_SINCE)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| DateTime::parse_from_rfc2822(value).ok())
+        .is_some_and(|since| modified.timestamp() <= since.timestamp())
+}
+
+/// `time` formatted for `Last-Modified` and similar headers
+pub fn http_date(time: DateTime<Utc>) -> String {
+    time.format("%a, %d %b %Y %H:%M:%S GMT").to_string()
+}
+
+/// How long browsers and CDNs may cache data responses without revalidating
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct CachePolicy {
+    /// Seconds for responses for an explicitly requested timestep before the
+    /// latest one
+    pub historical_max_age: Option<u64>,
+    /// Seconds for metadata and all other data responses
+    pub recent_max_age: Option<u64>,
+}
+
+impl CachePolicy {
+    pub fn is_enabled(&self) -> bool {
+        self.historical_max_age.is_some() || self.recent_max_age.is_some()
+    }
+
+    /// Add caching headers to a successful data `response` for `time`, the
+    /// timestep named by the request, given the dataset's `latest` timestep.
+    /// Without a configured max-age the response keeps
+    /// `Cache-Control: no-cache`.
+    pub fn apply(
+        &self,
+        mut response: Response,
+        latest: Option<f64>,
+        time: Option<f64>,
+    ) -> Response {
+        if !response.status().is_success() {
+            return response;
+        }
+        let historical = matches!((time, latest), (Some(time), Some(latest)) if time < latest);
+        let headers = response.headers_mut();
+        match (historical, self.historical_max_age, self.recent_max_age) {
+            (true, Some(max_age), _) => {
+                headers.insert(
+                    header::CACHE_CONTROL,
+                    HeaderValue::from_str(&format!("public, max-age={}, immutable", max_age))
+                        .unwrap(),
+                );
+                let modified = to_datetime(time.unwrap_or_default()).min(Utc::now());
+                if let Ok(value) = HeaderValue::from_str(&http_date(modified)) {
+                    headers.insert(header::LAST_MODIFIED, value);
+                }
+            }
+            (false, _, Some(max_age)) => {
+                headers.insert(
+                    header::CACHE_CONTROL,
+                    HeaderValue::from_str(&format!("public, max-age={}", max_age)).unwrap(),
+                );
+            }
+            _ => {}
+        }
+        response
+    }
+}
+
+/// How long browsers and CDNs may cache the embedded frontend
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AssetCachePolicy {
+    /// Seconds for assets, 0 to have every use revalidated
+    pub max_age: u64,
+    /// Seconds for the index pages, which name the current assets
+    pub index_max_age: u64,
+    /// `Cache-Control` by file extension, in place of `max_age`
+    pub overrides: Vec<(String, HeaderValue)>,
+}
+
+impl Default for AssetCachePolicy {
+    fn default() -> Self {
+        Self {
+            max_age: DEFAULT_ASSET_MAX_AGE,
+            index_max_age: DEFAULT_INDEX_MAX_AGE,
+            overrides: Vec::new(),
+        }
+    }
+}
+
+impl AssetCachePolicy {
+    /// Add an override from `EXT=VALUE`, e.g. `json=public, max-age=86400`
+    pub fn add_override(&mut self, spec: &str) -> Result<(), AppError> {
+        let invalid = || {
+            AppError::ConfigError(format!(
+                "Invalid asset cache override '{}'; expected EXT=CACHE-CONTROL",
+                spec
+            ))
+        };
+        let (extension, value) = spec.split_once('=').ok_or_else(invalid)?;
+        let extension = extension
+            .trim()
+            .trim_start_matches('.')
+            .to_ascii_lowercase();
+        if extension.is_empty() {
+            return Err(invalid());
+        }
+        let value = HeaderValue::from_str(value.trim()).map_err(|_| invalid())?;
+        self.overrides.retain(|(known, _)| *known != extension);
+        self.overrides.push((extension, value));
+        Ok(())
+    }
+
+    /// `Cache-Control` of the asset at `path`; `hashed` when the request
+    /// named the asset's contents, which then never change
+    pub fn asset(&self, path: &str, hashed: bool) -> HeaderValue {
+        if hashed {
+            return HeaderValue::from_static(IMMUTABLE);
+        }
+        let extension = path
+            .rsplit_once('.')
+            .map(|(_, extension)| extension.to_ascii_lowercase());
+        self.overrides
+            .iter()
+            .find(|(known, _)| Some(known) == extension.as_ref())
+            .map(|(_, value)| value.clone())
+            .unwrap_or_else(|| max_age(self.max_age))
+    }
+
+    /// `Cache-Control` of the index pages
+    pub fn index(&self) -> HeaderValue {
+        max_age(self.index_max_age)
+    }
+}
+
+fn max_age(seconds: u64) -> HeaderValue {
+    match seconds {
+        0 => HeaderValue::from_static("no-cache"),
+        seconds => HeaderValue::from_str(&format!("public, max-age={}", seconds)).unwrap(),
+    }
+}
+
+/// The latest timestep of the dataset described by `metadata`
+pub fn latest_time(metadata: &Value) -> Option<f64> {
+    coordinate_values(metadata, "time")?
+        .into_iter()
+        .reduce(f64::max)
+}
+
+/// Routes a service worker may cache and how. `data_version` is the current
+/// dataset version, if the backend could be reached.
+pub fn cache_manifest(data_version: Option<&str>) -> Value {
+    json!({
+        "version": CACHE_MANIFEST_VERSION,
+        "data_version": data_version,
+        "version_header": "X-Data-Version",
+        "routes": [
+            {
+                "prefix": "/proxy/metadata",
+                "kind": "data",
+                "strategy": "network-first",
+                "validator": "etag",
+            },
+            {
+                "prefix": "/proxy/data",
+                "kind": "data",
+                "strategy": "network-first",
+                "validator": "etag",
+            },
+            {
+                "prefix": "/data/products.json",
+                "kind": "data",
+                "strategy": "network-first",
+                "validator": "etag",
+            },
+            {
+                "prefix": "/data/oscar/",
+                "kind": "data",
+                "strategy": "network-first",
+                "validator": "etag",
+            },
+            {
+                "prefix": "/data/weather/",
+                "kind": "data",
+                "strategy": "network-first",
+                "validator": "etag",
+            },
+            {
+                "prefix": "/api/",
+                "kind": "api",
+                "strategy": "network-only",
+            },
+            {
+                "prefix": "/admin/",
+                "kind": "admin",
+                "strategy": "network-only",
+            },
+            {
+                "prefix": "/",
+                "kind": "shell",
+                "strategy": "network-first",
+                "validator": "etag",
+            },
+        ],
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_version_follows_metadata() {
+        let metadata = json!({"coordinates": {"time": [700464.0]}});
+        let version = data_version(&metadata);
+        assert_eq!(version.len(), 16);
+        assert_eq!(version, data_version(&metadata));
+        assert_ne!(
+            version,
+            data_version(&json!({"coordinates": {"time": [700464.0, 700470.0]}}))
+        );
+    }
+
+    #[test]
+    fn test_if_none_match() {
+        let metadata = json!({"coordinates": {"time": [700464.0]}});
+        let freshness = DataFreshness::new(&metadata, "/proxy/data?vars=t2m");
+        assert_ne!(
+            freshness.etag(),
+            DataFreshness::new(&metadata, "/proxy/data?vars=u10").etag()
+        );
+
+        let mut headers = HeaderMap::new();
+        assert!(!freshness.matches(&headers));
+        let opaque = freshness.etag().trim_start_matches("W/").to_string();
+        headers.insert(
+            header::IF_NONE_MATCH,
+            HeaderValue::from_str(&format!("\"other\", {}", opaque)).unwrap(),
+        );
+        assert!(freshness.matches(&headers));
+
+        let response = freshness.not_modified();
+        assert_eq!(response.status(), StatusCode::NOT_MODIFIED);
+        assert_eq!(response.headers()[DATA_VERSION_HEADER], freshness.version());
+        assert_eq!(response.headers()[header::CACHE_CONTROL], "no-cache");
+    }
+
+    #[test]
+    fn test_if_modified_since() {
+        let modified = DateTime::parse_from_rfc2822("Wed, 01 Jan 2020 00:00:00 GMT")
+            .unwrap()
+            .with_timezone(&Utc);
+        let mut headers = HeaderMap::new();
+        assert!(!unmodified_since(&headers, modified));
+
+        headers.insert(
+            header::IF_MODIFIED_SINCE,
+            HeaderValue::from_str(&http_date(modified)).unwrap(),
+        );
+        assert!(unmodified_since(&headers, modified));
+        assert!(!unmodified_since(
+            &headers,
+            modified + chrono::Duration::seconds(1)
+        ));
+
+        headers.insert(header::IF_NONE_MATCH, HeaderValue::from_static("\"other\""));
+        assert!(!unmodified_since(&headers, modified));
+    }
+
+    #[test]
+    fn test_asset_cache_policy() {
+        let mut policy = AssetCachePolicy::default();
+        assert_eq!(policy.asset("libs/d3.js", false), "public, max-age=3600");
+        assert_eq!(policy.asset("libs/d3.js", true), IMMUTABLE);
+        assert_eq!(policy.index(), "public, max-age=60");
+
+        policy.add_override(".JSON=public, max-age=86400").unwrap();
+        policy.add_override("css=no-store").unwrap();
+        policy.add_override("css=no-cache").unwrap();
+        assert_eq!(
+            policy.asset("data/earth-topo.json", false),
+            "public, max-age=86400"
+        );
+        assert_eq!(policy.asset("styles/styles.css", false), "no-cache");
+        assert_eq!(policy.overrides.len(), 2);
+        assert!(policy.add_override("json").is_err());
+        assert!(policy.add_override("=no-cache").is_err());
+
+        let revalidated = AssetCachePolicy {
+            max_age: 0,
+            index_max_age: 0,
+            overrides: Vec::new(),
+        };
+        assert_eq!(revalidated.asset("favicon.ico", false), "no-cache");
+        assert_eq!(revalidated.index(), "no-cache");
+    }
+
+    #[test]
+    fn test_cache_policy() {
+        let metadata = json!({"coordinates": {"time": [1051896.0, 1051920.0]}});
+        let policy = CachePolicy {
+            historical_max_age: Some(86400),
+            recent_max_age: Some(60),
+        };
+        let response = || StatusCode::OK.into_response();
+        let latest = latest_time(&metadata);
+        assert_eq!(latest, Some(1051920.0));
+
+        let historical = policy.apply(response(), latest, Some(1051896.0));
+        assert_eq!(
+            historical.headers()[header::CACHE_CONTROL],
+            "public, max-age=86400, immutable"
+        );
+        assert_eq!(
+            historical.headers()[header::LAST_MODIFIED],
+            "Wed, 01 Jan 2020 00:00:00 GMT"
+        );
+
+        let recent = policy.apply(response(), latest, Some(1051920.0));
+        assert_eq!(
+            recent.headers()[header::CACHE_CONTROL],
+            "public, max-age=60"
+        );
+        assert!(!recent.headers().contains_key(header::LAST_MODIFIED));
+        let unknown = policy.apply(response(), None, Some(1051896.0));
+        assert_eq!(
+            unknown.headers()[header::CACHE_CONTROL],
+            "public, max-age=60"
+        );
+
+        let untouched = CachePolicy::default().apply(response(), latest, Some(1051896.0));
+        assert!(!untouched.headers().contains_key(header::CACHE_CONTROL));
+        let error = policy.apply(
+            StatusCode::BAD_GATEWAY.into_response(),
+            latest,
+            Some(1051896.0),
+        );
+        assert!(!error.headers().contains_key(header::CACHE_CONTROL));
+    }
+}
@@ -0,0 +1,171 @@
+//! Spherical geometry helpers for path-based analysis
+//!
+//! Distances are computed on a sphere with the mean Earth radius, which is
+//! accurate to well under one percent and matches what the visualization
+//! frontend assumes when drawing paths.
+
+use serde::{Deserialize, Serialize};
+use utoipa::ToSchema;
+
+/// Mean Earth radius in kilometres
+pub const EARTH_RADIUS_KM: f64 = 6371.0;
+
+/// A geographic point in decimal degrees
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize, ToSchema)]
+pub struct GeoPoint {
+    /// Latitude in degrees north
+    pub lat: f64,
+    /// Longitude in degrees east
+    pub lon: f64,
+}
+
+impl GeoPoint {
+    /// Create a new point from latitude and longitude in degrees
+    pub fn new(lat: f64, lon: f64) -> Self {
+        Self { lat, lon }
+    }
+
+    /// Check that the point has finite coordinates with a valid latitude
+    pub fn is_valid(&self) -> bool {
+        self.lat.is_finite() && self.lon.is_finite() && (-90.0..=90.0).contains(&self.lat)
+    }
+}
+
+/// Great-circle distance between two points in kilometres (haversine formula)
+pub fn haversine_distance_km(from: GeoPoint, to: GeoPoint) -> f64 {
+    let lat1 = from.lat.to_radians();
+    let lat2 = to.lat.to_radians();
+    let delta_lat = lat2 - lat1;
+    let delta_lon = (to.lon - from.lon).to_radians();
+
+    let a =
+        (delta_lat / 2.0).sin().powi(2) + lat1.cos() * lat2.cos() * (delta_lon / 2.0).sin().powi(2);
+    2.0 * EARTH_RADIUS_KM * a.sqrt().atan2((1.0 - a).sqrt())
+}
+
+/// Point at `fraction` (0..=1) of the way along the great circle from `from` to `to`
+pub fn intermediate_point(from: GeoPoint, to: GeoPoint, fraction: f64) -> GeoPoint {
+    let angular_distance = haversine_distance_km(from, to) / EARTH_RADIUS_KM;
+    if angular_distance.abs() < 1e-12 {
+        return from;
+    }
+
+    let lat1 = from.lat.to_radians();
+    let lon1 = from.lon.to_radians();
+    let lat2 = to.lat.to_radians();
+    let lon2 = to.lon.to_radians();
+
+    let a = ((1.0 - fraction) * angular_distance).sin() / angular_distance.sin();
+    let b = (fraction * angular_distance).sin() / angular_distance.sin();
+
+    let x = a * lat1.cos() * lon1.cos() + b * lat2.cos() * lon2.cos();
+    let y = a * lat1.cos() * lon1.sin() + b * lat2.cos() * lon2.sin();
+    let z = a * lat1.sin() + b * lat2.sin();
+
+    GeoPoint {
+        lat: z.atan2((x * x + y * y).sqrt()).to_degrees(),
+        lon: y.atan2(x).to_degrees(),
+    }
+}
+
+/// Sample `count` points evenly spaced by distance along a polyline.
+///
+/// Returns each sampled point together with its distance in kilometres from
+/// the start of the path. The first and last vertices are always included.
+/// An empty vector is returned for paths with fewer than two vertices or when
+/// fewer than two samples are requested.
+pub fn sample_polyline(path: &[GeoPoint], count: usize) -> Vec<(GeoPoint, f64)> {
+    if path.len() < 2 || count < 2 {
+        return Vec::new();
+    }
+
+    let segment_lengths: Vec<f64> = path
+        .windows(2)
+        .map(|pair| haversine_distance_km(pair[0], pair[1]))
+        .collect();
+    let total_length: f64 = segment_lengths.iter().sum();
+
+    let mut samples = Vec::with_capacity(count);
+    let mut segment = 0;
+    let mut segment_start = 0.0;
+
+    for i in 0..count {
+        let target = total_length * i as f64 / (count - 1) as f64;
+
+        while segment < segment_lengths.len() - 1
+            && segment_start + segment_lengths[segment] < target
+        {
+            segment_start += segment_lengths[segment];
+            segment += 1;
+        }
+
+        let length = segment_lengths[segment];
+        let fraction = if length > 0.0 {
+            ((target - segment_start) / length).clamp(0.0, 1.0)
+        } else {
+            0.0
+        };
+
+        samples.push((
+            intermediate_point(path[segment], path[segment + 1], fraction),
+            target,
+        ));
+    }
+
+    samples
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_haversine_quarter_meridian() {
+        let distance = haversine_distance_km(GeoPoint::new(0.0, 0.0), GeoPoint::new(90.0, 0.0));
+        let expected = std::f64::consts::PI / 2.0 * EARTH_RADIUS_KM;
+        assert!((distance - expected).abs() < 1e-6);
+    }
+
+    #[test]
+    fn test_haversine_zero_distance() {
+        let point = GeoPoint::new(45.0, 10.0);
+        assert_eq!(haversine_distance_km(point, point), 0.0);
+    }
+
+    #[test]
+    fn test_intermediate_point_on_equator() {
+        let midpoint = intermediate_point(GeoPoint::new(0.0, 0.0), GeoPoint::new(0.0, 90.0), 0.5);
+        assert!(midpoint.lat.abs() < 1e-9);
+        assert!((midpoint.lon - 45.0).abs() < 1e-9);
+    }
+
+    #[test]
+    fn test_sample_polyline_includes_endpoints() {
+        let path = [
+            GeoPoint::new(0.0, 0.0),
+            GeoPoint::new(0.0, 10.0),
+            GeoPoint::new(10.0, 10.0),
+        ];
+        let samples = sample_polyline(&path, 5);
+
+        assert_eq!(samples.len(), 5);
+        assert!((samples[0].0.lon - 0.0).abs() < 1e-9);
+        assert!((samples[4].0.lat - 10.0).abs() < 1e-9);
+        assert!((samples[4].0.lon - 10.0).abs() < 1e-9);
+        assert_eq!(samples[0].1, 0.0);
+        assert!(samples.windows(2).all(|pair| pair[1].1 > pair[0].1));
+    }
+
+    #[test]
+    fn test_sample_polyline_degenerate_inputs() {
+        assert!(sample_polyline(&[GeoPoint::new(0.0, 0.0)], 10).is_empty());
+        assert!(sample_polyline(&[GeoPoint::new(0.0, 0.0), GeoPoint::new(1.0, 1.0)], 1).is_empty());
+    }
+
+    #[test]
+    fn test_geo_point_validation() {
+        assert!(GeoPoint::new(45.0, 370.0).is_valid());
+        assert!(!GeoPoint::new(91.0, 0.0).is_valid());
+        assert!(!GeoPoint::new(f64::NAN, 0.0).is_valid());
+    }
+}
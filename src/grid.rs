@@ -0,0 +1,688 @@
+//! Gridded data access and interpolation
+//!
+//! Rossby returns each variable as a flattened row-major array described by a
+//! `shape` and a list of dimension names. This module wraps those arrays so
+//! analysis handlers can select horizontal slices and interpolate values at
+//! arbitrary latitude/longitude positions.
+
+use serde_json::Value;
+use std::collections::HashMap;
+
+use crate::error::AppError;
+
+/// Dimension names recognised as latitude
+const LATITUDE_NAMES: [&str; 2] = ["latitude", "lat"];
+/// Dimension names recognised as longitude
+const LONGITUDE_NAMES: [&str; 2] = ["longitude", "lon"];
+/// Dimension names recognised as a vertical coordinate
+const VERTICAL_NAMES: [&str; 8] = [
+    "level",
+    "plev",
+    "lev",
+    "isobaric",
+    "height",
+    "pressure_level",
+    "depth",
+    "z",
+];
+
+/// Check whether a dimension name refers to latitude
+pub fn is_latitude_dimension(name: &str) -> bool {
+    LATITUDE_NAMES.contains(&name.to_lowercase().as_str())
+}
+
+/// Check whether a dimension name refers to longitude
+pub fn is_longitude_dimension(name: &str) -> bool {
+    LONGITUDE_NAMES.contains(&name.to_lowercase().as_str())
+}
+
+/// Check whether a dimension name refers to a vertical coordinate
+pub fn is_vertical_dimension(name: &str) -> bool {
+    VERTICAL_NAMES.contains(&name.to_lowercase().as_str())
+}
+
+/// Read a numeric coordinate axis from the metadata `coordinates` section
+pub fn coordinate_values(metadata: &Value, name: &str) -> Option<Vec<f64>> {
+    metadata
+        .get("coordinates")?
+        .get(name)?
+        .as_array()
+        .map(|values| values.iter().filter_map(|v| v.as_f64()).collect())
+}
+
+/// Dimension names declared for a variable in the metadata document
+pub fn variable_dimensions(metadata: &Value, variable: &str) -> Option<Vec<String>> {
+    metadata
+        .get("variables")?
+        .get(variable)?
+        .get("dimensions")?
+        .as_array()
+        .map(|dims| {
+            dims.iter()
+                .filter_map(|d| d.as_str().map(String::from))
+                .collect()
+        })
+}
+
+/// An N-dimensional variable as returned by the Rossby `/data` endpoint
+#[derive(Debug, Clone)]
+pub struct DataArray {
+    /// Dimension names, outermost first
+    pub dimensions: Vec<String>,
+    /// Size of each dimension
+    pub shape: Vec<usize>,
+    /// Flattened values in row-major order; missing values are NaN
+    pub values: Vec<f64>,
+}
+
+impl DataArray {
+    /// Extract a variable from a Rossby data response.
+    ///
+    /// The shape and dimension names are taken from the response `metadata`
+    /// section. Non-numeric entries (e.g. `null` fill values) become NaN.
+    pub fn from_rossby_response(response: &Value, variable: &str) -> Result<Self, AppError> {
+        let values: Vec<f64> = response
+            .get("data")
+            .and_then(|d| d.get(variable))
+            .and_then(|v| v.as_array())
+            .ok_or_else(|| {
+                AppError::ProxyError(format!("Backend response has no data for '{}'", variable))
+            })?
+            .iter()
+            .map(|v| v.as_f64().unwrap_or(f64::NAN))
+            .collect();
+
+        let metadata = response.get("metadata");
+        let shape: Vec<usize> = metadata
+            .and_then(|m| m.get("shape"))
+            .and_then(|s| s.as_array())
+            .map(|s| {
+                s.iter()
+                    .filter_map(|v| v.as_u64())
+                    .map(|v| v as usize)
+                    .collect()
+            })
+            .unwrap_or_default();
+        let dimensions: Vec<String> = metadata
+            .and_then(|m| m.get("dimensions"))
+            .and_then(|d| d.as_array())
+            .map(|d| {
+                d.iter()
+                    .filter_map(|v| v.as_str().map(String::from))
+                    .collect()
+            })
+            .unwrap_or_default();
+
+        Self::new(dimensions, shape, values)
+    }
+
+    /// Create a data array, checking that the shape matches the values
+    pub fn new(
+        dimensions: Vec<String>,
+        shape: Vec<usize>,
+        values: Vec<f64>,
+    ) -> Result<Self, AppError> {
+        if dimensions.len() != shape.len() {
+            return Err(AppError::ProxyError(format!(
+                "Backend returned {} dimension names for a shape of rank {}",
+                dimensions.len(),
+                shape.len()
+            )));
+        }
+
+        let expected: usize = shape.iter().product();
+        if expected != values.len() {
+            return Err(AppError::ProxyError(format!(
+                "Backend returned {} values for shape {:?}",
+                values.len(),
+                shape
+            )));
+        }
+
+        Ok(Self {
+            dimensions,
+            shape,
+            values,
+        })
+    }
+
+    /// Position of a dimension by name
+    pub fn dimension_position(&self, name: &str) -> Option<usize> {
+        self.dimensions.iter().position(|d| d == name)
+    }
+
+    /// Name of the first vertical dimension, if any
+    pub fn vertical_dimension(&self) -> Option<&str> {
+        self.dimensions
+            .iter()
+            .find(|d| is_vertical_dimension(d))
+            .map(String::as_str)
+    }
+
+    /// Size of a dimension, or 1 if the array does not have it
+    pub fn dimension_size(&self, name: &str) -> usize {
+        self.dimension_position(name)
+            .map(|position| self.shape[position])
+            .unwrap_or(1)
+    }
+
+    /// Extract a latitude × longitude slice in latitude-major order.
+    ///
+    /// Every non-horizontal dimension is fixed at the index given in
+    /// `selection`, or at index 0 when it is not listed.
+    pub fn horizontal_slice(
+        &self,
+        selection: &HashMap<String, usize>,
+    ) -> Result<Vec<f64>, AppError> {
+        let lat_position = self
+            .dimensions
+            .iter()
+            .position(|d| is_latitude_dimension(d))
+            .ok_or_else(|| AppError::ProxyError("Data has no latitude dimension".to_string()))?;
+        let lon_position = self
+            .dimensions
+            .iter()
+            .position(|d| is_longitude_dimension(d))
+            .ok_or_else(|| AppError::ProxyError("Data has no longitude dimension".to_string()))?;
+
+        let mut strides = vec![1; self.shape.len()];
+        for i in (0..self.shape.len().saturating_sub(1)).rev() {
+            strides[i] = strides[i + 1] * self.shape[i + 1];
+        }
+
+        let mut base = 0;
+        for (position, name) in self.dimensions.iter().enumerate() {
+            if position == lat_position || position == lon_position {
+                continue;
+            }
+            let index = selection.get(name).copied().unwrap_or(0);
+            if index >= self.shape[position] {
+                return Err(AppError::RequestError(format!(
+                    "Index {} is out of range for dimension '{}' of size {}",
+                    index, name, self.shape[position]
+                )));
+            }
+            base += index * strides[position];
+        }
+
+        let ny = self.shape[lat_position];
+        let nx = self.shape[lon_position];
+        let mut slice = Vec::with_capacity(nx * ny);
+        for y in 0..ny {
+            for x in 0..nx {
+                slice.push(
+                    self.values[base + y * strides[lat_position] + x * strides[lon_position]],
+                );
+            }
+        }
+
+        Ok(slice)
+    }
+}
+
+/// A rectilinear latitude/longitude grid
+#[derive(Debug, Clone)]
+pub struct LatLonGrid {
+    /// Latitude axis, ascending or descending
+    pub latitudes: Vec<f64>,
+    /// Longitude axis, ascending
+    pub longitudes: Vec<f64>,
+}
+
+impl LatLonGrid {
+    /// Build the grid from the metadata `coordinates` section
+    pub fn from_metadata(metadata: &Value) -> Result<Self, AppError> {
+        let latitudes = coordinate_values(metadata, "latitude")
+            .or_else(|| coordinate_values(metadata, "lat"))
+            .ok_or_else(|| {
+                AppError::ProxyError("Metadata has no latitude coordinate".to_string())
+            })?;
+        let longitudes = coordinate_values(metadata, "longitude")
+            .or_else(|| coordinate_values(metadata, "lon"))
+            .ok_or_else(|| {
+                AppError::ProxyError("Metadata has no longitude coordinate".to_string())
+            })?;
+
+        if latitudes.is_empty() || longitudes.is_empty() {
+            return Err(AppError::ProxyError(
+                "Metadata has empty latitude or longitude coordinates".to_string(),
+            ));
+        }
+
+        Ok(Self {
+            latitudes,
+            longitudes,
+        })
+    }
+
+    /// Number of grid points (latitude × longitude)
+    pub fn len(&self) -> usize {
+        self.latitudes.len() * self.longitudes.len()
+    }
+
+    /// Whether the grid has no points
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Whether the longitude axis wraps around the globe
+    pub fn is_global(&self) -> bool {
+        let n = self.longitudes.len();
+        if n < 2 {
+            return false;
+        }
+        let spacing = (self.longitudes[n - 1] - self.longitudes[0]) / (n - 1) as f64;
+        (self.longitudes[n - 1] - self.longitudes[0] + spacing) >= 360.0 - 1e-6
+    }
+
+    /// Bilinearly interpolate a latitude-major field at the given position.
+    ///
+    /// Returns `None` when the point lies outside the grid or when any of the
+    /// surrounding grid values is missing.
+    pub fn bilinear(&self, field: &[f64], lat: f64, lon: f64) -> Option<f64> {
+        if field.len() != self.len() {
+            return None;
+        }
+
+        let (y0, y1, wy) = axis_position(&self.latitudes, lat)?;
+        let (x0, x1, wx) = self.longitude_position(lon)?;
+        let nx = self.longitudes.len();
+
+        let v00 = field[y0 * nx + x0];
+        let v01 = field[y0 * nx + x1];
+        let v10 = field[y1 * nx + x0];
+        let v11 = field[y1 * nx + x1];
+
+        let value = v00 * (1.0 - wx) * (1.0 - wy)
+            + v01 * wx * (1.0 - wy)
+            + v10 * (1.0 - wx) * wy
+            + v11 * wx * wy;
+
+        value.is_finite().then_some(value)
+    }
+
+    /// Whether both axes are evenly spaced, each step within `tolerance`
+    /// times the mean spacing of its axis
+    pub fn is_uniform(&self, tolerance: f64) -> bool {
+        is_uniform_axis(&self.latitudes, tolerance) && is_uniform_axis(&self.longitudes, tolerance)
+    }
+
+    /// Bilinearly resample a latitude-major field onto the evenly spaced grid
+    /// with the same end points and number of points
+    pub fn resample_uniform(&self, field: &[f64]) -> Result<Vec<f64>, AppError> {
+        if field.len() != self.len() {
+            return Err(AppError::ProxyError(format!(
+                "Expected {} values for a {}x{} grid, got {}",
+                self.len(),
+                self.longitudes.len(),
+                self.latitudes.len(),
+                field.len()
+            )));
+        }
+
+        let nx = self.longitudes.len();
+        let rows: Vec<_> = uniform_axis(&self.latitudes)
+            .into_iter()
+            .map(|lat| axis_position(&self.latitudes, lat))
+            .collect();
+        let columns: Vec<_> = uniform_axis(&self.longitudes)
+            .into_iter()
+            .map(|lon| axis_position(&self.longitudes, lon))
+            .collect();
+
+        Ok(rows
+            .iter()
+            .flat_map(|row| {
+                columns.iter().map(move |column| match (row, column) {
+                    (Some((y0, y1, wy)), Some((x0, x1, wx))) => {
+                        field[y0 * nx + x0] * (1.0 - wx) * (1.0 - wy)
+                            + field[y0 * nx + x1] * wx * (1.0 - wy)
+                            + field[y1 * nx + x0] * (1.0 - wx) * wy
+                            + field[y1 * nx + x1] * wx * wy
+                    }
+                    _ => f64::NAN,
+                })
+            })
+            .collect())
+    }
+
+    /// Locate a longitude on the axis, wrapping across the dateline for global grids
+    fn longitude_position(&self, lon: f64) -> Option<(usize, usize, f64)> {
+        let first = self.longitudes[0];
+        let n = self.longitudes.len();
+        let normalized = first + (lon - first).rem_euclid(360.0);
+
+        if let Some(position) = axis_position(&self.longitudes, normalized) {
+            return Some(position);
+        }
+
+        if self.is_global() {
+            let last = self.longitudes[n - 1];
+            let gap = first + 360.0 - last;
+            if normalized >= last && gap > 0.0 {
+                return Some((n - 1, 0, (normalized - last) / gap));
+            }
+        }
+
+        // Regional grids may be expressed in the other longitude convention
+        let alternate = if lon > 180.0 {
+            lon - 360.0
+        } else {
+            lon + 360.0
+        };
+        axis_position(&self.longitudes, alternate)
+    }
+}
+
+/// Whether the steps of an axis are all within `tolerance` times its mean step
+fn is_uniform_axis(axis: &[f64], tolerance: f64) -> bool {
+    let n = axis.len();
+    if n < 3 {
+        return true;
+    }
+    let spacing = (axis[n - 1] - axis[0]) / (n - 1) as f64;
+    axis.windows(2)
+        .all(|pair| (pair[1] - pair[0] - spacing).abs() <= tolerance * spacing.abs())
+}
+
+/// Evenly spaced values from the first to the last of an axis, as many as it has
+fn uniform_axis(axis: &[f64]) -> Vec<f64> {
+    let n = axis.len();
+    if n < 2 {
+        return axis.to_vec();
+    }
+    let (first, last) = (axis[0], axis[n - 1]);
+    let spacing = (last - first) / (n - 1) as f64;
+    (0..n)
+        .map(|i| {
+            if i == n - 1 {
+                last
+            } else {
+                first + spacing * i as f64
+            }
+        })
+        .collect()
+}
+
+/// Find the bracketing indices and interpolation weight of `value` on a monotonic axis
+fn axis_position(axis: &[f64], value: f64) -> Option<(usize, usize, f64)> {
+    let n = axis.len();
+    if n == 1 {
+        return ((axis[0] - value).abs() < 1e-9).then_some((0, 0, 0.0));
+    }
+
+    let ascending = axis[n - 1] >= axis[0];
+    let (low, high) = if ascending {
+        (axis[0], axis[n - 1])
+    } else {
+        (axis[n - 1], axis[0])
+    };
+    if !value.is_finite() || value < low || value > high {
+        return None;
+    }
+
+    let upper = if ascending {
+        axis.partition_point(|&v| v < value)
+    } else {
+        axis.partition_point(|&v| v > value)
+    };
+    if upper == 0 {
+        return Some((0, 0, 0.0));
+    }
+
+    let lower = upper - 1;
+    let upper = upper.min(n - 1);
+    let span = axis[upper] - axis[lower];
+    let weight = if span != 0.0 {
+        (value - axis[lower]) / span
+    } else {
+        0.0
+    };
+
+    Some((lower, upper, weight))
+}
+
+/// Largest deviation of a grid step from the mean step, as a fraction of
+/// it, for an axis to count as evenly spaced
+pub const SPACING_TOLERANCE: f64 = 0.01;
+
+/// Default limit on the points of a grid served as Earth JSON, 2048 by 2048
+pub const DEFAULT_MAX_GRID_POINTS: usize = 4_194_304;
+
+/// Limit on the size of the grids served as Earth JSON
+///
+/// Very fine grids produce documents too large for the browser to download
+/// and draw, so they are thinned to every nth point in each direction or,
+/// when downsampling is disabled, refused with an error.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct EarthGridLimit {
+    /// Largest number of points served; no limit when zero
+    pub max_points: usize,
+    /// Downsample larger grids instead of rejecting them
+    pub downsample: bool,
+}
+
+impl Default for EarthGridLimit {
+    fn default() -> Self {
+        Self {
+            max_points: DEFAULT_MAX_GRID_POINTS,
+            downsample: true,
+        }
+    }
+}
+
+impl EarthGridLimit {
+    /// The smallest stride keeping an `nx` by `ny` grid within the limit
+    pub fn stride(&self, nx: usize, ny: usize) -> Result<usize, AppError> {
+        let points = nx.saturating_mul(ny);
+        if self.max_points == 0 || points <= self.max_points {
+            return Ok(1);
+        }
+        if !self.downsample {
+            return Err(AppError::ConfigError(format!(
+                "The {}x{} grid has {} points, more than the limit of {}; \
+                 raise --earth-max-grid-points or drop --reject-large-grids \
+                 to serve it downsampled",
+                nx, ny, points, self.max_points
+            )));
+        }
+
+        let thinned = |stride: usize| nx.div_ceil(stride) * ny.div_ceil(stride);
+        let mut stride = ((points as f64 / self.max_points as f64).sqrt().ceil() as usize).max(2);
+        while thinned(stride) > self.max_points {
+            stride += 1;
+        }
+        Ok(stride)
+    }
+}
+
+/// Every `stride`th point in each direction of a row-major `nx` by `ny`
+/// field, starting from the first
+pub fn downsample_grid(
+    values: &[f64],
+    nx: usize,
+    ny: usize,
+    stride: usize,
+) -> Result<Vec<f64>, AppError> {
+    if stride <= 1 {
+        return Ok(values.to_vec());
+    }
+    if values.len() != nx * ny {
+        return Err(AppError::ProxyError(format!(
+            "Expected {} values for a {}x{} grid, got {}",
+            nx * ny,
+            nx,
+            ny,
+            values.len()
+        )));
+    }
+    Ok((0..ny)
+        .step_by(stride)
+        .flat_map(|y| (0..nx).step_by(stride).map(move |x| values[y * nx + x]))
+        .collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn test_grid() -> LatLonGrid {
+        LatLonGrid {
+            latitudes: vec![10.0, 0.0, -10.0],
+            longitudes: vec![0.0, 90.0, 180.0, 270.0],
+        }
+    }
+
+    #[test]
+    fn test_bilinear_at_grid_points() {
+        let grid = test_grid();
+        let field: Vec<f64> = (0..12).map(|v| v as f64).collect();
+
+        assert_eq!(grid.bilinear(&field, 10.0, 0.0), Some(0.0));
+        assert_eq!(grid.bilinear(&field, 0.0, 90.0), Some(5.0));
+        assert_eq!(grid.bilinear(&field, -10.0, 270.0), Some(11.0));
+    }
+
+    #[test]
+    fn test_bilinear_between_points() {
+        let grid = test_grid();
+        let field: Vec<f64> = (0..12).map(|v| v as f64).collect();
+
+        // Halfway between rows 0 and 1 and columns 0 and 1
+        assert_eq!(grid.bilinear(&field, 5.0, 45.0), Some(2.5));
+    }
+
+    #[test]
+    fn test_bilinear_wraps_dateline() {
+        let grid = test_grid();
+        let field: Vec<f64> = vec![0.0, 0.0, 0.0, 4.0, 0.0, 0.0, 0.0, 4.0, 0.0, 0.0, 0.0, 4.0];
+
+        assert_eq!(grid.bilinear(&field, 0.0, 315.0), Some(2.0));
+        assert_eq!(grid.bilinear(&field, 0.0, -45.0), Some(2.0));
+    }
+
+    #[test]
+    fn test_bilinear_outside_and_missing() {
+        let grid = test_grid();
+        let mut field: Vec<f64> = (0..12).map(|v| v as f64).collect();
+
+        assert_eq!(grid.bilinear(&field, 20.0, 0.0), None);
+
+        field[0] = f64::NAN;
+        assert_eq!(grid.bilinear(&field, 5.0, 45.0), None);
+        assert_eq!(grid.bilinear(&field, -5.0, 135.0), Some(7.5));
+    }
+
+    #[test]
+    fn test_data_array_horizontal_slice() {
+        let array = DataArray::new(
+            vec!["level".into(), "latitude".into(), "longitude".into()],
+            vec![2, 2, 3],
+            (0..12).map(|v| v as f64).collect(),
+        )
+        .unwrap();
+
+        let mut selection = HashMap::new();
+        assert_eq!(
+            array.horizontal_slice(&selection).unwrap(),
+            vec![0.0, 1.0, 2.0, 3.0, 4.0, 5.0]
+        );
+
+        selection.insert("level".to_string(), 1);
+        assert_eq!(
+            array.horizontal_slice(&selection).unwrap(),
+            vec![6.0, 7.0, 8.0, 9.0, 10.0, 11.0]
+        );
+
+        selection.insert("level".to_string(), 2);
+        assert!(array.horizontal_slice(&selection).is_err());
+        assert_eq!(array.vertical_dimension(), Some("level"));
+    }
+
+    #[test]
+    fn test_data_array_shape_mismatch() {
+        let result = DataArray::new(vec!["latitude".into()], vec![3], vec![1.0, 2.0]);
+        assert!(result.is_err());
+    }
+
+    #[test]
+    fn test_data_array_from_response() {
+        let response = json!({
+            "metadata": {"shape": [1, 2], "dimensions": ["latitude", "longitude"]},
+            "data": {"t2m": [280.0, null]}
+        });
+
+        let array = DataArray::from_rossby_response(&response, "t2m").unwrap();
+        assert_eq!(array.shape, vec![1, 2]);
+        assert_eq!(array.values[0], 280.0);
+        assert!(array.values[1].is_nan());
+        assert!(DataArray::from_rossby_response(&response, "u10").is_err());
+    }
+
+    #[test]
+    fn test_lat_lon_grid_from_metadata() {
+        let metadata = json!({
+            "coordinates": {"latitude": [90.0, 0.0, -90.0], "longitude": [0.0, 120.0, 240.0]}
+        });
+        let grid = LatLonGrid::from_metadata(&metadata).unwrap();
+        assert_eq!(grid.len(), 9);
+        assert!(grid.is_global());
+        assert!(LatLonGrid::from_metadata(&json!({})).is_err());
+    }
+
+    #[test]
+    fn test_earth_grid_limit() {
+        let limit = EarthGridLimit {
+            max_points: 100,
+            downsample: true,
+        };
+        assert_eq!(limit.stride(10, 10).unwrap(), 1);
+        assert_eq!(limit.stride(11, 10).unwrap(), 2);
+        assert_eq!(limit.stride(100, 100).unwrap(), 10);
+
+        let unlimited = EarthGridLimit {
+            max_points: 0,
+            ..limit
+        };
+        assert_eq!(unlimited.stride(100, 100).unwrap(), 1);
+
+        let strict = EarthGridLimit {
+            downsample: false,
+            ..limit
+        };
+        let error = strict.stride(11, 10).unwrap_err();
+        assert!(error.to_string().contains("--earth-max-grid-points"));
+    }
+
+    #[test]
+    fn test_downsample_grid() {
+        let values: Vec<f64> = (0..15).map(f64::from).collect();
+        assert_eq!(
+            downsample_grid(&values, 5, 3, 2).unwrap(),
+            [0.0, 2.0, 4.0, 10.0, 12.0, 14.0]
+        );
+        assert_eq!(downsample_grid(&values, 5, 3, 1).unwrap(), values);
+        assert!(downsample_grid(&values, 4, 3, 2).is_err());
+    }
+
+    #[test]
+    fn test_resample_irregular_grid() {
+        assert!(test_grid().is_uniform(0.01));
+
+        // Latitudes crowd towards the equator
+        let grid = LatLonGrid {
+            latitudes: vec![10.0, 2.5, -10.0],
+            longitudes: vec![0.0, 10.0, 20.0],
+        };
+        assert!(!grid.is_uniform(0.01));
+
+        let field = [0.0, 1.0, 2.0, 7.5, 8.5, 9.5, 20.0, 21.0, 22.0];
+        let resampled = grid.resample_uniform(&field).unwrap();
+        assert_eq!(resampled[..3], field[..3]);
+        assert!((resampled[3] - 10.0).abs() < 1e-9);
+        assert!((resampled[5] - 12.0).abs() < 1e-9);
+        assert_eq!(resampled[6..], field[6..]);
+        assert!(grid.resample_uniform(&field[..4]).is_err());
+    }
+}
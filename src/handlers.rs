@@ -1,20 +1,51 @@
 use axum::{
-    body::Body,
+    body::{Body, Bytes},
     extract::{Path, Query, State},
-    http::{header, Response as HttpResponse, StatusCode},
-    response::{Html, IntoResponse, Response},
+    http::{header, HeaderMap, HeaderValue, Response as HttpResponse, StatusCode, Uri},
+    response::{Html, IntoResponse, Json, Response},
 };
 use futures::StreamExt;
 use mime_guess::from_path;
 use serde::{Deserialize, Serialize};
 use serde_json::{json, Value};
-use std::{collections::HashMap, sync::Arc, time::Instant};
-use tracing::{error, info, instrument, warn};
-
-use crate::{embed::StaticAssets, error::AppError, log_error, log_proxy_request, server::AppState};
+use std::{borrow::Cow, collections::HashMap, sync::Arc, time::Instant};
+use tracing::{debug, error, info, instrument, warn};
+use utoipa::IntoParams;
+
+use crate::{
+    analysis::{data_query, time_selection},
+    backend::SchemaVersion,
+    build_info::{build_info, BuildInfo},
+    cache::ResponseCache,
+    catalog::Catalog,
+    derived::{self, fetch_with_derived, register_derived_variables, DerivedProduct},
+    dev_assets::inject_reload_script,
+    embed::{add_integrity, integrity_manifest, is_content_version, negotiate, StaticAssets},
+    error::{AppError, ErrorBody},
+    format::OutputFormat,
+    freshness::{
+        cache_manifest as route_manifest, data_version, etag_matches, http_date, latest_time,
+        unmodified_since, DataFreshness,
+    },
+    grid::{downsample_grid, is_vertical_dimension, DataArray, LatLonGrid, SPACING_TOLERANCE},
+    hints::RenderHint,
+    levels::{select_pressure_level, EarthFileName, EarthLevel},
+    log_error, log_proxy_request,
+    logging::status_summary,
+    mask::{apply_mask, MaskMode},
+    metadata::{invalid_metadata_error, validate_metadata},
+    packing::PackedArray,
+    server::AppState,
+    site::{base_href, is_mobile, set_base_href, MOBILE_INDEX},
+    slicing::{is_slicing_param, IndexSlicing},
+    timesteps::{select_time, to_iso, with_data_time, TimeRequest},
+    units::UnitSystem,
+    vectors::{pair_vector_components, register_vector_pairs},
+};
 
 /// Query parameters for the data proxy endpoint
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
 pub struct DataQuery {
     /// Comma-separated list of variables to fetch
     vars: Option<String>,
@@ -22,133 +53,480 @@ pub struct DataQuery {
     time: Option<String>,
     /// Time range for data selection
     time_range: Option<String>,
+    /// Unit system of the values, see [`crate::units`]
+    units: Option<UnitSystem>,
     /// Any additional query parameters
     #[serde(flatten)]
+    #[param(ignore)]
     extra: HashMap<String, String>,
 }
 
-/// Handler for the root path - serves index.html
-pub async fn index() -> Response {
-    match StaticAssets::get("index.html") {
-        Some(content) => match std::str::from_utf8(&content.data) {
-            Ok(html) => Html(html.to_string()).into_response(),
+/// Query parameters for the Earth data routes
+#[derive(Debug, Clone, Default, Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct EarthQuery {
+    /// Hide values over `land` or `ocean`, or `none` to disable automatic masking
+    pub mask: Option<MaskMode>,
+    /// Timestep to show, as a backend time value or an ISO-8601 date
+    /// resolved to the nearest timestep; defaults to the first
+    pub time: Option<String>,
+    /// Pack the data arrays, see [`crate::packing`]
+    pub packed: Option<bool>,
+    /// Quantization step of packed data arrays
+    pub precision: Option<f64>,
+    /// Representation of the response, negotiated from `Accept` when absent
+    pub format: Option<OutputFormat>,
+    /// Unit system of the values, see [`crate::units`]
+    pub units: Option<UnitSystem>,
+}
+
+/// Handler for `/api/status` - activity and backend health since startup
+#[utoipa::path(
+    get,
+    path = "/api/status",
+    tag = "service",
+    summary = "Activity and backend health since startup",
+    responses(
+        (status = 200, description = "Uptime, requests served, transfer totals and backend health", body = serde_json::Value),
+    )
+)]
+pub async fn status() -> Json<Value> {
+    Json(json!({
+        "service": "rossby-vis",
+        "version": env!("CARGO_PKG_VERSION"),
+        "activity": status_summary(),
+    }))
+}
+
+/// Handler for `/api/version` - crate version, commit, build time, cargo
+/// features and frontend bundle version of the running build
+#[utoipa::path(
+    get,
+    path = "/api/version",
+    tag = "service",
+    summary = "The running build",
+    responses((status = 200, body = BuildInfo))
+)]
+pub async fn version_info() -> Json<BuildInfo> {
+    Json(build_info())
+}
+
+/// Handler for `/api/assets` - Subresource Integrity hashes of embedded
+/// scripts and stylesheets
+#[utoipa::path(
+    get,
+    path = "/api/assets",
+    tag = "service",
+    summary = "Subresource Integrity hashes of the embedded scripts and stylesheets",
+    responses(
+        (status = 200, description = "`sha256-` hashes keyed by URL path", body = serde_json::Value),
+    )
+)]
+pub async fn asset_manifest() -> Json<Value> {
+    Json(json!({
+        "algorithm": "sha256",
+        "assets": integrity_manifest(),
+    }))
+}
+
+/// Handler for `/api/cache-manifest` - the routes a service worker may cache
+/// and the current dataset version
+#[utoipa::path(
+    get,
+    path = "/api/cache-manifest",
+    tag = "service",
+    summary = "Routes a service worker may cache, and the dataset version",
+    responses((status = 200, body = serde_json::Value))
+)]
+pub async fn cache_manifest(State(state): State<Arc<AppState>>) -> Json<Value> {
+    let version = fetch_metadata(&state)
+        .await
+        .ok()
+        .map(|metadata| data_version(&metadata));
+    Json(route_manifest(version.as_deref()))
+}
+
+/// Handler for the root path - serves index.html with the site's
+/// placeholder values and integrity attributes, or its lighter mobile variant
+/// to phones when that is enabled
+pub async fn index(State(state): State<Arc<AppState>>, headers: HeaderMap) -> Response {
+    if !state.mobile_index {
+        return page(&state, "index.html", &headers);
+    }
+
+    let mut response = if is_mobile(&headers) && page_exists(&state, MOBILE_INDEX) {
+        page(&state, MOBILE_INDEX, &headers)
+    } else {
+        page(&state, "index.html", &headers)
+    };
+    // The page depends on the device, and Chromium only sends the mobile
+    // hint to sites that ask for it
+    let headers = response.headers_mut();
+    headers.append(
+        header::VARY,
+        HeaderValue::from_static("User-Agent, Sec-CH-UA-Mobile"),
+    );
+    headers.insert("accept-ch", HeaderValue::from_static("Sec-CH-UA-Mobile"));
+    response
+}
+
+fn page_exists(state: &AppState, name: &str) -> bool {
+    match &state.dev_assets {
+        Some(dev) => dev.get(name).is_some(),
+        None => StaticAssets::get(name).is_some(),
+    }
+}
+
+/// An HTML page of the frontend with the site's placeholder values and its
+/// `<base href>` filled in
+fn page(state: &AppState, name: &str, headers: &HeaderMap) -> Response {
+    let content = match &state.dev_assets {
+        Some(dev) => dev.get(name).map(|data| Cow::Owned(data.to_vec())),
+        None => StaticAssets::get(name).map(|file| file.data),
+    };
+
+    match content {
+        Some(content) => match std::str::from_utf8(&content) {
+            Ok(html) => {
+                let html = set_base_href(
+                    &state.site.render(html),
+                    &base_href(&state.base_path, headers),
+                );
+                // Files on disk change during development, so skip the hashes
+                let mut response = match &state.dev_assets {
+                    Some(_) => no_store(Html(inject_reload_script(&html)).into_response()),
+                    None => (
+                        [(header::CACHE_CONTROL, state.asset_cache.index())],
+                        Html(add_integrity(&html)),
+                    )
+                        .into_response(),
+                };
+                response
+                    .headers_mut()
+                    .append(header::VARY, HeaderValue::from_static("X-Forwarded-Prefix"));
+                response
+            }
             Err(_) => HttpResponse::builder()
                 .status(StatusCode::INTERNAL_SERVER_ERROR)
-                .body(Body::from("Failed to decode index.html"))
+                .body(Body::from(format!("Failed to decode {}", name)))
                 .unwrap()
                 .into_response(),
         },
         None => HttpResponse::builder()
             .status(StatusCode::NOT_FOUND)
-            .body(Body::from("index.html not found"))
+            .body(Body::from(format!("{} not found", name)))
             .unwrap()
             .into_response(),
     }
 }
 
+/// Handler for `/manifest.json`, the web app manifest
+pub async fn web_manifest(State(state): State<Arc<AppState>>) -> Response {
+    (
+        [(header::CONTENT_TYPE, "application/manifest+json")],
+        Json(state.site.web_manifest()),
+    )
+        .into_response()
+}
+
+/// Handler for `/sw.js`, the service worker script. Browsers only let a
+/// worker control paths under the one it is served from unless the response
+/// says otherwise, and must check for a new version on every load.
+pub async fn service_worker(State(state): State<Arc<AppState>>, headers: HeaderMap) -> Response {
+    let mut response = static_asset(
+        State(state),
+        Path("sw.js".to_string()),
+        Uri::from_static("/sw.js"),
+        headers,
+    )
+    .await;
+    if response.status().is_success() {
+        response
+            .headers_mut()
+            .insert("service-worker-allowed", HeaderValue::from_static("/"));
+    }
+    if response.status().is_success() || response.status() == StatusCode::NOT_MODIFIED {
+        response
+            .headers_mut()
+            .insert(header::CACHE_CONTROL, HeaderValue::from_static("no-cache"));
+    }
+    response
+}
+
 /// Handler for other static assets
-pub async fn static_asset(Path(path): Path<String>) -> Response {
-    match StaticAssets::get(&path) {
-        Some(content) => {
-            let mime = from_path(&path).first_or_octet_stream();
-            HttpResponse::builder()
+pub async fn static_asset(
+    State(state): State<Arc<AppState>>,
+    Path(path): Path<String>,
+    uri: Uri,
+    headers: HeaderMap,
+) -> Response {
+    if path == "index.html" || path == MOBILE_INDEX {
+        return page(&state, &path, &headers);
+    }
+
+    let mime = from_path(&path).first_or_octet_stream();
+
+    // Development mode serves the files on disk as they are
+    if let Some(dev) = &state.dev_assets {
+        return match dev.get(&path) {
+            Some(data) => no_store(
+                HttpResponse::builder()
+                    .status(StatusCode::OK)
+                    .header(header::CONTENT_TYPE, mime.as_ref().to_string())
+                    .body(Body::from(data.to_vec()))
+                    .unwrap()
+                    .into_response(),
+            ),
+            None => asset_not_found(),
+        };
+    }
+
+    let accept_encoding = headers
+        .get(header::ACCEPT_ENCODING)
+        .and_then(|value| value.to_str().ok());
+
+    match negotiate(&path, accept_encoding) {
+        Some(asset) => {
+            let etag = asset.etag();
+            let last_modified = asset.last_modified();
+            // `?v=<hash>` names the contents, which then never change
+            let hashed = uri
+                .query()
+                .and_then(|query| query.split('&').find_map(|pair| pair.strip_prefix("v=")))
+                .is_some_and(|version| is_content_version(&path, version));
+            let mut response = HttpResponse::builder().header(header::ETAG, &etag).header(
+                header::CACHE_CONTROL,
+                state.asset_cache.asset(&path, hashed),
+            );
+            if let Some(modified) = last_modified {
+                response = response.header(header::LAST_MODIFIED, http_date(modified));
+            }
+            if asset.has_variants {
+                response = response.header(header::VARY, "Accept-Encoding");
+            }
+            let unchanged = etag_matches(&headers, &etag)
+                || last_modified.is_some_and(|modified| unmodified_since(&headers, modified));
+            if unchanged {
+                return response
+                    .status(StatusCode::NOT_MODIFIED)
+                    .body(Body::empty())
+                    .unwrap()
+                    .into_response();
+            }
+            response = response
                 .status(StatusCode::OK)
-                .header(header::CONTENT_TYPE, mime.as_ref().to_string())
-                .body(Body::from(content.data.to_vec()))
+                .header(header::CONTENT_TYPE, mime.as_ref().to_string());
+            if let Some(encoding) = asset.encoding {
+                response = response.header(header::CONTENT_ENCODING, encoding);
+            }
+            response
+                .body(Body::from(asset.file.data.to_vec()))
                 .unwrap()
                 .into_response()
         }
-        None => HttpResponse::builder()
-            .status(StatusCode::NOT_FOUND)
-            .header(header::CONTENT_TYPE, "text/plain")
-            .body(Body::from("Asset not found"))
-            .unwrap()
-            .into_response(),
+        None => asset_not_found(),
     }
 }
 
+fn asset_not_found() -> Response {
+    HttpResponse::builder()
+        .status(StatusCode::NOT_FOUND)
+        .header(header::CONTENT_TYPE, "text/plain")
+        .body(Body::from("Asset not found"))
+        .unwrap()
+        .into_response()
+}
+
+/// Keep browsers from caching a response
+fn no_store(mut response: Response) -> Response {
+    response.headers_mut().insert(
+        header::CACHE_CONTROL,
+        header::HeaderValue::from_static("no-store"),
+    );
+    response
+}
+
 /// Handler for the metadata proxy endpoint
-#[instrument(skip(state), fields(backend_url))]
-pub async fn proxy_metadata(State(state): State<Arc<AppState>>) -> Result<Response, AppError> {
-    let start_time = Instant::now();
+#[utoipa::path(
+    get,
+    path = "/proxy/metadata",
+    tag = "proxy",
+    summary = "Backend metadata",
+    responses(
+        (status = 200, description = "Dimensions, coordinates and variables of the dataset", body = serde_json::Value),
+        (status = 304, description = "Unchanged since the request's `If-None-Match`"),
+        (status = 502, description = "The backend failed or sent invalid metadata", body = ErrorBody),
+    )
+)]
+#[instrument(skip(state, headers), fields(backend_url))]
+pub async fn proxy_metadata(
+    State(state): State<Arc<AppState>>,
+    uri: Uri,
+    headers: HeaderMap,
+) -> Result<Response, AppError> {
     let metadata_url = format!("{}/metadata", state.api_url);
 
     tracing::Span::current().record("backend_url", &metadata_url);
     info!("Proxying metadata request to Rossby server");
 
-    match state.http_client.get(&metadata_url).send().await {
-        Ok(response) => {
-            let status_code = response.status().as_u16();
-
-            if response.status().is_success() {
-                // Get the response body as bytes and stream it
-                match response.bytes().await {
-                    Ok(body) => {
-                        let duration = start_time.elapsed();
-                        let bytes_transferred = body.len() as u64;
-
-                        log_proxy_request!(
-                            &metadata_url,
-                            status_code,
-                            duration.as_millis() as u64,
-                            bytes_transferred
-                        );
-
-                        Ok(HttpResponse::builder()
-                            .status(StatusCode::OK)
-                            .header(header::CONTENT_TYPE, "application/json")
-                            .body(Body::from(body.to_vec()))
-                            .unwrap()
-                            .into_response())
-                    }
-                    Err(e) => {
-                        let duration = start_time.elapsed();
-                        log_error!(e, "Failed to read metadata response body");
-                        log_proxy_request!(
-                            &metadata_url,
-                            status_code,
-                            duration.as_millis() as u64,
-                            0
-                        );
-
-                        Err(AppError::ProxyError(
-                            "Failed to read response body".to_string(),
-                        ))
-                    }
-                }
+    let body = fetch_backend_bytes(&state, &metadata_url, "metadata").await?;
+
+    // Translate newer schemas, apply the deployment's transforms and
+    // advertise derived products alongside the backend variables
+    let mut freshness = None;
+    let body = match serde_json::from_slice::<Value>(&body) {
+        Ok(metadata) => {
+            let mut metadata = state.backend.normalize_metadata(metadata)?;
+            let transformed = state.transforms.apply_metadata(&mut metadata)?;
+            freshness = Some(DataFreshness::new(&metadata, &uri.to_string()));
+            for issue in validate_metadata(&metadata) {
+                warn!("Backend metadata problem: {}", issue);
+            }
+            let translated = state.backend.version() == Some(SchemaVersion::V2);
+            let registered = transformed
+                + register_derived_variables(&mut metadata, &state.derived_variables)
+                + register_vector_pairs(&mut metadata);
+            if registered > 0 || translated {
+                serde_json::to_vec(&metadata).unwrap_or_else(|_| body.to_vec())
             } else {
-                let duration = start_time.elapsed();
-                log_proxy_request!(&metadata_url, status_code, duration.as_millis() as u64, 0);
-
-                warn!("Rossby server returned error status: {}", response.status());
-                Err(AppError::ProxyError(format!(
-                    "Backend server error: {}",
-                    response.status()
-                )))
+                body.to_vec()
             }
         }
-        Err(e) => {
-            let duration = start_time.elapsed();
-            log_error!(e, "Failed to connect to Rossby server");
-            log_proxy_request!(&metadata_url, 0, duration.as_millis() as u64, 0);
+        Err(_) => body.to_vec(),
+    };
 
-            Err(AppError::ProxyError(
-                "Failed to connect to backend server".to_string(),
-            ))
+    Ok(match freshness {
+        Some(freshness) if freshness.matches(&headers) => freshness.not_modified(),
+        freshness => {
+            let response = HttpResponse::builder()
+                .status(StatusCode::OK)
+                .header(header::CONTENT_TYPE, "application/json")
+                .body(Body::from(body))
+                .unwrap()
+                .into_response();
+            let response = match freshness {
+                Some(freshness) => freshness.apply(response),
+                None => response,
+            };
+            state.cache_policy.apply(response, None, None)
         }
-    }
+    })
 }
 
 /// Handler for the data proxy endpoint with streaming support
-#[instrument(skip(state), fields(backend_url, vars, time))]
+#[utoipa::path(
+    get,
+    path = "/proxy/data",
+    tag = "proxy",
+    summary = "Backend data, streamed",
+    description = "Selects by coordinate value as the backend does, e.g. `time=700464` or `latitude_range=-10,10`, or by position, e.g. `time_index=5` or `lat_indices=0:360:2`. Other parameters are passed on to the backend.",
+    params(DataQuery),
+    responses(
+        (status = 200, description = "The backend's data response", body = serde_json::Value),
+        (status = 400, description = "Invalid selection", body = ErrorBody),
+        (status = 404, description = "A requested variable is hidden by the deployment's transforms", body = ErrorBody),
+        (status = 502, description = "The backend failed", body = ErrorBody),
+        (status = 504, description = "The backend did not answer in time", body = ErrorBody),
+    )
+)]
+#[instrument(skip(state, headers), fields(backend_url, vars, time))]
 pub async fn proxy_data(
     State(state): State<Arc<AppState>>,
     Query(params): Query<DataQuery>,
+    uri: Uri,
+    headers: HeaderMap,
 ) -> Result<Response, AppError> {
     let start_time = Instant::now();
 
     info!("Proxying data request to Rossby server: {:?}", params);
 
+    // Responses are versioned by the metadata; without it they are still
+    // served, just without validators
+    let metadata = fetch_metadata(&state).await.ok();
+    let freshness = metadata
+        .as_ref()
+        .map(|metadata| DataFreshness::new(metadata, &uri.to_string()));
+    if let Some(freshness) = freshness.as_ref().filter(|f| f.matches(&headers)) {
+        return Ok(freshness.not_modified());
+    }
+
+    // Dates resolve to the nearest timestep; raw values go to the backend as given
+    let time = match params.time.as_deref().map(TimeRequest::parse).transpose()? {
+        Some(TimeRequest::Value(time)) => Some(time),
+        Some(TimeRequest::Instant(_)) => {
+            let time = match &metadata {
+                Some(metadata) => select_time(metadata, params.time.as_deref())?,
+                None => select_time(&fetch_metadata(&state).await?, params.time.as_deref())?,
+            };
+            Some(time)
+        }
+        None => None,
+    };
+    let latest = metadata.as_ref().and_then(latest_time);
+    let versioned = |response: Response| {
+        let response = match time {
+            Some(time) => with_data_time(response, time),
+            None => response,
+        };
+        let response = match &freshness {
+            Some(freshness) => freshness.apply(response),
+            None => response,
+        };
+        // Only a timestep named in the request stays the same over time
+        let requested_time = params.time.as_ref().and(time);
+        state.cache_policy.apply(response, latest, requested_time)
+    };
+
+    // Positions map to coordinate selections, see crate::slicing
+    let slicing = match &metadata {
+        _ if !params.extra.keys().any(|k| is_slicing_param(k)) => IndexSlicing::default(),
+        Some(metadata) => IndexSlicing::parse(metadata, &params.extra)?,
+        None => IndexSlicing::parse(&fetch_metadata(&state).await?, &params.extra)?,
+    };
+    for dimension in slicing.dimensions() {
+        let by_value = params.extra.contains_key(dimension)
+            || params.extra.contains_key(&format!("{}_range", dimension))
+            || (dimension == "time" && (params.time.is_some() || params.time_range.is_some()));
+        if by_value {
+            return Err(AppError::RequestError(format!(
+                "Dimension {} is selected both by index and by value",
+                dimension
+            )));
+        }
+    }
+
+    // Derived products are computed here rather than streamed from the backend
+    let requested_vars: Vec<&str> = params
+        .vars
+        .as_deref()
+        .map(|vars| vars.split(',').collect())
+        .unwrap_or_default();
+    state.transforms.check_served(&requested_vars)?;
+    if requested_vars
+        .iter()
+        .any(|v| DerivedProduct::from_name(v).is_some() || state.derived_variables.contains(v))
+    {
+        let metadata = match metadata {
+            Some(metadata) => metadata,
+            None => fetch_metadata(&state).await?,
+        };
+        if requested_vars
+            .iter()
+            .any(|v| derived::is_derived(&state.derived_variables, &metadata, v))
+        {
+            if !slicing.is_empty() {
+                return Err(AppError::RequestError(
+                    "Derived variables cannot be sliced by index".to_string(),
+                ));
+            }
+            let mut data = fetch_with_derived(&state, &metadata, &requested_vars, time).await?;
+            if let Some(units) = params.units {
+                units.convert_data_response(&mut data);
+            }
+            return Ok(versioned(Json(data).into_response()));
+        }
+    }
+
     // Build the query string for the Rossby server
     let mut query_params = Vec::new();
 
@@ -157,9 +535,15 @@ pub async fn proxy_data(
         tracing::Span::current().record("vars", vars);
     }
 
-    if let Some(time) = &params.time {
+    if let (Some(requested), Some(time)) = (&params.time, time) {
+        // Keep raw values exactly as the client wrote them
+        let time = if requested.trim().parse::<f64>().is_ok() {
+            requested.trim().to_string()
+        } else {
+            time.to_string()
+        };
+        tracing::Span::current().record("time", &time);
         query_params.push(format!("time={}", time));
-        tracing::Span::current().record("time", time);
     }
 
     if let Some(time_range) = &params.time_range {
@@ -171,18 +555,53 @@ pub async fn proxy_data(
 
     // Add any extra parameters (except format, which we already set)
     for (key, value) in &params.extra {
-        if key != "format" {
+        if key != "format" && !is_slicing_param(key) {
             query_params.push(format!("{}={}", key, value));
         }
     }
+    query_params.extend(slicing.backend_params().map(String::from));
 
     let query_string = query_params.join("&");
+
+    // Responses in newer schemas, rewritten by transforms, strided or
+    // converted here cannot be streamed
+    if !state.backend.passes_data_through()
+        || state.transforms.affects(&requested_vars)
+        || slicing.is_strided()
+        || params.units.is_some_and(|units| units != UnitSystem::Si)
+    {
+        let mut data = fetch_data(&state, &query_string).await?;
+        slicing.apply_steps(&mut data)?;
+        if let Some(units) = params.units {
+            units.convert_data_response(&mut data);
+        }
+        return Ok(versioned(Json(data).into_response()));
+    }
+
     let data_url = format!("{}/data?{}", state.api_url, query_string);
 
     tracing::Span::current().record("backend_url", &data_url);
+    let cached = |body: Bytes| {
+        versioned(
+            HttpResponse::builder()
+                .status(StatusCode::OK)
+                .header(header::CONTENT_TYPE, "application/json")
+                .body(Body::from(body))
+                .unwrap()
+                .into_response(),
+        )
+    };
+    if let Some(body) = state.response_cache.get(&data_url).await {
+        debug!(backend_url = %data_url, "Serving data from the response cache");
+        return Ok(cached(body));
+    }
     info!("Requesting data from: {}", data_url);
 
-    match state.http_client.get(&data_url).send().await {
+    let result = match state.backend_send(state.backend_get(&data_url)).await {
+        Ok(result) => result,
+        Err(e) => return stale_or(&state, &data_url, e).await.map(cached),
+    };
+    match result {
         Ok(response) => {
             let status_code = response.status().as_u16();
 
@@ -194,30 +613,57 @@ pub async fn proxy_data(
                     "Starting data stream from Rossby server"
                 );
 
-                // Stream the response using chunked transfer encoding
-                let stream = response.bytes_stream().map(|result| {
-                    result.map_err(|e| {
-                        error!("Stream error: {}", e);
-                        std::io::Error::other(e)
-                    })
-                });
+                // Stream the response using chunked transfer encoding, logging
+                // the transfer once the stream ends or the client goes away
+                // and caching the body once it arrived completely
+                let transfer = StreamedTransfer {
+                    backend_url: data_url.clone(),
+                    status_code,
+                    start_time,
+                    bytes: 0,
+                    capture: state
+                        .response_cache
+                        .is_enabled()
+                        .then(|| (state.response_cache.clone(), Vec::new())),
+                };
+                let stream = futures::stream::unfold(
+                    (response.bytes_stream(), transfer),
+                    |(mut chunks, mut transfer)| async move {
+                        match chunks.next().await {
+                            Some(Ok(chunk)) => {
+                                transfer.record(&chunk);
+                                Some((Ok(chunk), (chunks, transfer)))
+                            }
+                            Some(Err(e)) => {
+                                error!("Stream error: {}", e);
+                                transfer.capture = None;
+                                Some((Err(std::io::Error::other(e)), (chunks, transfer)))
+                            }
+                            None => {
+                                transfer.complete().await;
+                                None
+                            }
+                        }
+                    },
+                );
 
-                Ok(HttpResponse::builder()
-                    .status(StatusCode::OK)
-                    .header(header::CONTENT_TYPE, "application/json")
-                    .header(header::TRANSFER_ENCODING, "chunked")
-                    .body(Body::wrap_stream(stream))
-                    .unwrap()
-                    .into_response())
+                Ok(versioned(
+                    HttpResponse::builder()
+                        .status(StatusCode::OK)
+                        .header(header::CONTENT_TYPE, "application/json")
+                        .header(header::TRANSFER_ENCODING, "chunked")
+                        .body(Body::wrap_stream(stream))
+                        .unwrap()
+                        .into_response(),
+                ))
             } else {
                 let duration = start_time.elapsed();
                 log_proxy_request!(&data_url, status_code, duration.as_millis() as u64, 0);
 
                 warn!("Rossby server returned error status: {}", response.status());
-                Err(AppError::ProxyError(format!(
-                    "Backend server error: {}",
-                    response.status()
-                )))
+                let error =
+                    AppError::ProxyError(format!("Backend server error: {}", response.status()));
+                stale_or(&state, &data_url, error).await.map(cached)
             }
         }
         Err(e) => {
@@ -225,13 +671,150 @@ pub async fn proxy_data(
             log_error!(e, "Failed to connect to Rossby server");
             log_proxy_request!(&data_url, 0, duration.as_millis() as u64, 0);
 
-            Err(AppError::ProxyError(
-                "Failed to connect to backend server".to_string(),
-            ))
+            let error = AppError::ProxyError("Failed to connect to backend server".to_string());
+            stale_or(&state, &data_url, error).await.map(cached)
         }
     }
 }
 
+/// Fetch the backend metadata document as JSON in the legacy schema
+pub(crate) async fn fetch_metadata(state: &AppState) -> Result<Value, AppError> {
+    let metadata_url = format!("{}/metadata", state.api_url);
+    let metadata = fetch_backend_json(state, &metadata_url, "metadata").await?;
+    let mut metadata = state.backend.normalize_metadata(metadata)?;
+    state.transforms.apply_metadata(&mut metadata)?;
+    Ok(metadata)
+}
+
+/// Fetch a `/data` query from the backend as JSON in the legacy schema
+///
+/// `query` is the already-encoded query string without the leading `?`,
+/// naming variables as they are served to clients.
+pub(crate) async fn fetch_data(state: &AppState, query: &str) -> Result<Value, AppError> {
+    let data_url = format!(
+        "{}/data?{}",
+        state.api_url,
+        state.transforms.backend_query(query)?
+    );
+    let data = fetch_backend_json(state, &data_url, "data").await?;
+    let mut data = state.backend.normalize_data(data)?;
+    state.transforms.apply_data(query, &mut data)?;
+    Ok(data)
+}
+
+/// A backend response being streamed to the client
+struct StreamedTransfer {
+    backend_url: String,
+    status_code: u16,
+    start_time: Instant,
+    bytes: u64,
+    /// The body received so far, for the response cache
+    capture: Option<(Arc<ResponseCache>, Vec<u8>)>,
+}
+
+impl StreamedTransfer {
+    fn record(&mut self, chunk: &Bytes) {
+        self.bytes += chunk.len() as u64;
+        if let Some((cache, body)) = &mut self.capture {
+            if body.len() + chunk.len() > cache.max_bytes() {
+                self.capture = None;
+            } else {
+                body.extend_from_slice(chunk);
+            }
+        }
+    }
+
+    /// Cache the body once the backend sent all of it
+    async fn complete(&mut self) {
+        if let Some((cache, body)) = self.capture.take() {
+            cache.insert(&self.backend_url, body.into()).await;
+        }
+    }
+}
+
+impl Drop for StreamedTransfer {
+    fn drop(&mut self) {
+        log_proxy_request!(
+            &self.backend_url,
+            self.status_code,
+            self.start_time.elapsed().as_millis() as u64,
+            self.bytes
+        );
+    }
+}
+
+async fn fetch_backend_json(state: &AppState, url: &str, what: &str) -> Result<Value, AppError> {
+    let body = fetch_backend_bytes(state, url, what).await?;
+    serde_json::from_slice(&body)
+        .map_err(|e| AppError::ProxyError(format!("Failed to parse {}: {}", what, e)))
+}
+
+/// The body of a successful backend response, from the response cache when
+/// an identical request was answered recently, or when the backend fails
+/// while its circuit is open
+async fn fetch_backend_bytes(state: &AppState, url: &str, what: &str) -> Result<Bytes, AppError> {
+    if let Some(body) = state.response_cache.get(url).await {
+        debug!(backend_url = %url, "Serving {} from the response cache", what);
+        return Ok(body);
+    }
+    match fetch_uncached(state, url, what).await {
+        Ok(body) => {
+            state.response_cache.insert(url, body.clone()).await;
+            Ok(body)
+        }
+        Err(e) => stale_or(state, url, e).await,
+    }
+}
+
+/// The expired cached body of `url` while the backend's circuit is not
+/// closed, otherwise `error`
+async fn stale_or(state: &AppState, url: &str, error: AppError) -> Result<Bytes, AppError> {
+    if !state.circuit_breaker.is_closed() {
+        if let Some(body) = state.response_cache.get_stale(url).await {
+            warn!(backend_url = %url, "Serving a stale cached response: {}", error);
+            return Ok(body);
+        }
+    }
+    Err(error)
+}
+
+async fn fetch_uncached(state: &AppState, url: &str, what: &str) -> Result<Bytes, AppError> {
+    let start_time = Instant::now();
+    let response = state
+        .backend_send(state.backend_get(url))
+        .await?
+        .map_err(|e| {
+            log_error!(e, "Failed to connect to Rossby server");
+            log_proxy_request!(url, 0, start_time.elapsed().as_millis() as u64, 0);
+            AppError::ProxyError(format!("Failed to fetch {}: {}", what, e))
+        })?;
+
+    let status_code = response.status().as_u16();
+    if !response.status().is_success() {
+        log_proxy_request!(url, status_code, start_time.elapsed().as_millis() as u64, 0);
+        return Err(AppError::ProxyError(format!(
+            "Backend server error: {}",
+            response.status()
+        )));
+    }
+
+    let body = response.bytes().await.map_err(|e| {
+        let message = format!("Failed to read {}: {}", what, e);
+        if e.is_timeout() {
+            AppError::Timeout(message)
+        } else {
+            AppError::ProxyError(message)
+        }
+    })?;
+    log_proxy_request!(
+        url,
+        status_code,
+        start_time.elapsed().as_millis() as u64,
+        body.len() as u64
+    );
+    Ok(body)
+}
+
 /// Earth frontend compatible data structures
 #[derive(Serialize)]
 struct EarthHeader {
@@ -250,28 +833,59 @@ struct EarthHeader {
     parameter_number_name: String,
     #[serde(rename = "parameterUnit")]
     parameter_unit: String,
-    nx: u16,
-    ny: u16,
+    nx: u32,
+    ny: u32,
     lo1: f64,
     la1: f64,
     lo2: f64,
     la2: f64,
     dx: f64,
     dy: f64,
+    /// Rendering hints configured for the variable
+    #[serde(flatten)]
+    hints: RenderHint,
 }
 
 #[derive(Serialize)]
-struct EarthDataPoint {
+pub(crate) struct EarthDataPoint {
     header: EarthHeader,
-    data: Vec<f64>,
+    /// Moved to `packed` when packing
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub(crate) data: Vec<f64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    packed: Option<PackedArray>,
     meta: serde_json::Value,
 }
 
+impl EarthDataPoint {
+    /// Replace the data array by its packed form
+    pub(crate) fn pack(&mut self, precision: f64) -> Result<(), AppError> {
+        self.packed = Some(PackedArray::pack(&self.data, precision)?);
+        self.data = Vec::new();
+        Ok(())
+    }
+
+    /// Convert the data array to `units`, when its units have a counterpart
+    /// there
+    pub(crate) fn convert_units(&mut self, units: UnitSystem) {
+        let Some(conversion) = units.conversion(&self.header.parameter_unit) else {
+            return;
+        };
+        for value in &mut self.data {
+            *value = conversion.apply(*value);
+        }
+        self.header.parameter_unit = conversion.units.to_string();
+        // Display hints are written for the stored units
+        self.header.hints.display_scale = None;
+        self.header.hints.display_offset = None;
+    }
+}
+
 /// Grid parameters for Earth headers
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq)]
 struct GridParams {
-    nx: u16,
-    ny: u16,
+    nx: u32,
+    ny: u32,
     lo1: f64,
     la1: f64,
     lo2: f64,
@@ -280,18 +894,41 @@ struct GridParams {
     dy: f64,
 }
 
-/// Converts Rossby metadata to Earth grid parameters
-type EarthGridParams = (u16, u16, f64, f64, f64, f64, f64, f64);
+impl GridParams {
+    /// The grid of every `stride`th point in each direction, starting from
+    /// the first
+    fn downsampled(&self, stride: usize) -> Self {
+        if stride <= 1 {
+            return self.clone();
+        }
+        let stride = stride as u32;
+        let nx = (self.nx - 1) / stride + 1;
+        let ny = (self.ny - 1) / stride + 1;
+        let dx = self.dx * stride as f64;
+        let dy = self.dy * stride as f64;
+        Self {
+            nx,
+            ny,
+            lo1: self.lo1,
+            la1: self.la1,
+            lo2: self.lo1 + dx * (nx - 1) as f64,
+            la2: self.la1 - dy * (ny - 1) as f64,
+            dx,
+            dy,
+        }
+    }
+}
 
-fn rossby_to_earth_grid(metadata: &Value) -> Option<EarthGridParams> {
+/// Converts Rossby metadata to Earth grid parameters
+fn rossby_to_earth_grid(metadata: &Value) -> Option<GridParams> {
     let coords = metadata.get("coordinates")?;
     let dims = metadata.get("dimensions")?;
 
     let lat_array = coords.get("latitude")?.as_array()?;
     let lon_array = coords.get("longitude")?.as_array()?;
 
-    let ny = dims.get("latitude")?.get("size")?.as_u64()? as u16;
-    let nx = dims.get("longitude")?.get("size")?.as_u64()? as u16;
+    let ny = u32::try_from(dims.get("latitude")?.get("size")?.as_u64()?).ok()?;
+    let nx = u32::try_from(dims.get("longitude")?.get("size")?.as_u64()?).ok()?;
 
     let la1 = lat_array.first()?.as_f64()?;
     let la2 = lat_array.last()?.as_f64()?;
@@ -310,33 +947,32 @@ fn rossby_to_earth_grid(metadata: &Value) -> Option<EarthGridParams> {
         1.0
     };
 
-    Some((nx, ny, lo1, la1, lo2, la2, dx, dy))
-}
-
-/// Converts Rossby time to ISO string
-fn rossby_time_to_iso(time_val: f64) -> String {
-    // Rossby time is hours since 1900-01-01
-    let base = chrono::DateTime::parse_from_rfc3339("1900-01-01T00:00:00Z").unwrap();
-    let datetime = base + chrono::Duration::hours(time_val as i64);
-    datetime.to_rfc3339()
+    Some(GridParams {
+        nx,
+        ny,
+        lo1,
+        la1,
+        lo2,
+        la2,
+        dx,
+        dy,
+    })
 }
 
 /// Enhanced metadata service for variable discovery and categorization
 #[derive(Debug, Clone)]
-struct VariableInfo {
-    name: String,
-    #[allow(dead_code)]
-    display_name: String,
-    long_name: String,
-    units: String,
-    category: VariableCategory,
-    var_type: VariableType,
-    #[allow(dead_code)]
-    dimensions: Vec<String>,
+pub(crate) struct VariableInfo {
+    pub(crate) name: String,
+    pub(crate) display_name: String,
+    pub(crate) long_name: String,
+    pub(crate) units: String,
+    pub(crate) category: VariableCategory,
+    pub(crate) var_type: VariableType,
+    pub(crate) dimensions: Vec<String>,
 }
 
 #[derive(Debug, Clone)]
-enum VariableCategory {
+pub(crate) enum VariableCategory {
     Temperature,
     Wind,
     Pressure,
@@ -348,7 +984,7 @@ enum VariableCategory {
 }
 
 #[derive(Debug, Clone)]
-enum VariableType {
+pub(crate) enum VariableType {
     Scalar,
     Vector {
         u_component: String,
@@ -357,7 +993,7 @@ enum VariableType {
 }
 
 /// Analyzes metadata to discover available variables and their characteristics
-fn analyze_metadata_variables(metadata: &Value) -> Vec<VariableInfo> {
+pub(crate) fn analyze_metadata_variables(metadata: &Value) -> Vec<VariableInfo> {
     let empty_map = serde_json::Map::new();
     let variables = match metadata.get("variables") {
         Some(vars) => vars.as_object().unwrap_or(&empty_map),
@@ -365,7 +1001,7 @@ fn analyze_metadata_variables(metadata: &Value) -> Vec<VariableInfo> {
     };
 
     let mut result = Vec::new();
-    let mut processed_vectors = std::collections::HashSet::new();
+    let vector_pairs = pair_vector_components(variables);
 
     // Filter out coordinate variables
     let coordinate_vars = ["longitude", "latitude", "time", "level"];
@@ -375,7 +1011,8 @@ fn analyze_metadata_variables(metadata: &Value) -> Vec<VariableInfo> {
             continue;
         }
 
-        if processed_vectors.contains(var_name) {
+        // Northward components are served with their eastward partner
+        if vector_pairs.iter().any(|(_, v)| v == var_name) {
             continue;
         }
 
@@ -403,24 +1040,20 @@ fn analyze_metadata_variables(metadata: &Value) -> Vec<VariableInfo> {
 
         let category = categorize_variable(var_name, long_name);
 
-        // Check for vector pairs (wind components)
-        if let Some(v_component) = find_vector_pair(var_name, variables.keys()) {
-            if var_name.starts_with('u') || var_name.starts_with('U') {
-                result.push(VariableInfo {
-                    name: var_name.clone(),
-                    display_name: create_vector_display_name(var_name, &v_component),
-                    long_name: long_name.to_string(),
-                    units: units.to_string(),
-                    category,
-                    var_type: VariableType::Vector {
-                        u_component: var_name.clone(),
-                        v_component: v_component.clone(),
-                    },
-                    dimensions,
-                });
-                processed_vectors.insert(var_name.clone());
-                processed_vectors.insert(v_component);
-            }
+        // Check for vector pairs (wind and current components)
+        if let Some((_, v_component)) = vector_pairs.iter().find(|(u, _)| u == var_name) {
+            result.push(VariableInfo {
+                name: var_name.clone(),
+                display_name: create_vector_display_name(var_name, v_component),
+                long_name: long_name.to_string(),
+                units: units.to_string(),
+                category,
+                var_type: VariableType::Vector {
+                    u_component: var_name.clone(),
+                    v_component: v_component.clone(),
+                },
+                dimensions,
+            });
         } else {
             // Scalar variable
             result.push(VariableInfo {
@@ -438,6 +1071,20 @@ fn analyze_metadata_variables(metadata: &Value) -> Vec<VariableInfo> {
     result
 }
 
+/// First wind vector pair `(u, v)` discovered in the metadata
+pub(crate) fn find_wind_components(metadata: &Value) -> Option<(String, String)> {
+    analyze_metadata_variables(metadata)
+        .into_iter()
+        .filter(|v| matches!(v.category, VariableCategory::Wind))
+        .find_map(|v| match v.var_type {
+            VariableType::Vector {
+                u_component,
+                v_component,
+            } => Some((u_component, v_component)),
+            VariableType::Scalar => None,
+        })
+}
+
 fn categorize_variable(var_name: &str, long_name: &str) -> VariableCategory {
     let search_text = format!("{} {}", var_name, long_name).to_lowercase();
 
@@ -481,22 +1128,6 @@ fn categorize_variable(var_name: &str, long_name: &str) -> VariableCategory {
     }
 }
 
-fn find_vector_pair(
-    var_name: &str,
-    available_vars: impl Iterator<Item = impl AsRef<str>>,
-) -> Option<String> {
-    let available: Vec<String> = available_vars.map(|v| v.as_ref().to_string()).collect();
-
-    if var_name.starts_with('u') || var_name.starts_with('U') {
-        let v_component = var_name.replacen('u', "v", 1).replacen('U', "V", 1);
-        if available.contains(&v_component) {
-            return Some(v_component);
-        }
-    }
-
-    None
-}
-
 fn create_vector_display_name(u_var: &str, _v_var: &str) -> String {
     if u_var.contains("10") {
         "Wind".to_string()
@@ -521,198 +1152,412 @@ fn get_category_name(category: &VariableCategory) -> &'static str {
 }
 
 /// Dynamic Earth frontend data handler that adapts to any variable from metadata
-#[instrument(skip(state), fields(variable = %variable))]
+#[instrument(skip(state, headers), fields(variable = %variable))]
 pub async fn earth_dynamic_data(
     State(state): State<Arc<AppState>>,
     Path(variable): Path<String>,
+    Query(query): Query<EarthQuery>,
+    uri: Uri,
+    headers: HeaderMap,
 ) -> Result<Response, AppError> {
-    let start_time = Instant::now();
     info!("Serving Earth-compatible data for variable: {}", variable);
+    earth_product_data(
+        &state,
+        &variable,
+        EarthLevel::Surface,
+        &query,
+        &uri,
+        &headers,
+    )
+    .await
+}
 
+/// Earth JSON for a product of Earth's data paths: a variable name, or
+/// `wind` or `temp` for the dataset's wind or temperature at that level
+async fn earth_product_data(
+    state: &AppState,
+    product: &str,
+    level: EarthLevel,
+    query: &EarthQuery,
+    uri: &Uri,
+    headers: &HeaderMap,
+) -> Result<Response, AppError> {
     // Request metadata first to get grid info and variable details
-    let metadata_url = format!("{}/metadata", state.api_url);
-    let metadata_response = state
-        .http_client
-        .get(&metadata_url)
-        .send()
-        .await
-        .map_err(|e| AppError::ProxyError(format!("Failed to fetch metadata: {}", e)))?;
-
-    let metadata: Value = metadata_response
-        .json()
-        .await
-        .map_err(|e| AppError::ProxyError(format!("Failed to parse metadata: {}", e)))?;
-
-    // Analyze available variables
-    let variables = analyze_metadata_variables(&metadata);
-
-    // Find the requested variable
-    let var_info = variables.iter()
-        .find(|v| v.name == variable || matches!(&v.var_type, VariableType::Vector { u_component, .. } if u_component == &variable))
-        .ok_or_else(|| AppError::ProxyError(format!("Variable '{}' not found in metadata", variable)))?;
-
-    // Get first available time
-    let time = metadata
-        .get("coordinates")
-        .and_then(|c| c.get("time"))
-        .and_then(|t| t.as_array())
-        .and_then(|arr| arr.first())
-        .and_then(|t| t.as_f64())
-        .unwrap_or(700464.0);
-
-    // Extract grid parameters
-    let (nx, ny, lo1, la1, lo2, la2, dx, dy) = rossby_to_earth_grid(&metadata)
-        .ok_or_else(|| AppError::ProxyError("Invalid grid metadata".to_string()))?;
-
-    let ref_time = rossby_time_to_iso(time);
-
-    match &var_info.var_type {
-        VariableType::Vector {
-            u_component,
-            v_component,
-        } => {
-            // Handle vector data (wind components)
-            let data_url = format!(
-                "{}/data?vars={},{}&time={}&format=json",
-                state.api_url, u_component, v_component, time
-            );
-
-            let data_response =
-                state.http_client.get(&data_url).send().await.map_err(|e| {
-                    AppError::ProxyError(format!("Failed to fetch vector data: {}", e))
-                })?;
-
-            let rossby_data: Value = data_response
-                .json()
-                .await
-                .map_err(|e| AppError::ProxyError(format!("Failed to parse vector data: {}", e)))?;
-
-            // Create grid parameters
-            let grid = GridParams {
-                nx,
-                ny,
-                lo1,
-                la1,
-                lo2,
-                la2,
-                dx,
-                dy,
-            };
+    let catalog = state.catalog.load(state).await?;
+    earth_catalog_data(state, &catalog, product, level, query, uri, headers).await
+}
 
-            // Create U component data point
-            let u_data = extract_variable_data(&rossby_data, u_component);
-            let u_header = create_earth_header(var_info, "U-component", 2, &grid, &ref_time);
+/// Earth JSON for a product of the dataset described by `catalog`
+async fn earth_catalog_data(
+    state: &AppState,
+    catalog: &Catalog,
+    product: &str,
+    level: EarthLevel,
+    query: &EarthQuery,
+    uri: &Uri,
+    headers: &HeaderMap,
+) -> Result<Response, AppError> {
+    let format = OutputFormat::negotiate(query.format, headers);
+    let freshness = catalog.freshness(&format.variant(&uri.to_string()));
+    if freshness.matches(headers) {
+        return Ok(freshness.not_modified());
+    }
+    let metadata = catalog.metadata();
+    let (var_info, level) = catalog.select_product(product, level)?;
+    let query = &EarthQuery {
+        format: Some(format),
+        ..query.clone()
+    };
 
-            // Create V component data point
-            let v_data = extract_variable_data(&rossby_data, v_component);
-            let v_header = create_earth_header(var_info, "V-component", 3, &grid, &ref_time);
+    // The requested timestep, or the first one
+    let time = select_time(metadata, query.time.as_deref())?;
+
+    let response = earth_variable_response(
+        state,
+        metadata,
+        &var_info,
+        query,
+        time,
+        level
+            .as_ref()
+            .map(|(dimension, value)| (dimension.as_str(), *value)),
+        &freshness,
+    )
+    .await?;
+    // Only a timestep named in the request stays the same over time
+    let requested_time = query.time.as_ref().map(|_| time);
+    Ok(state
+        .cache_policy
+        .apply(response, latest_time(metadata), requested_time))
+}
 
-            let earth_data = vec![
-                EarthDataPoint {
-                    header: u_header,
-                    data: u_data,
-                    meta: json!({"date": ref_time}),
-                },
-                EarthDataPoint {
-                    header: v_header,
-                    data: v_data,
-                    meta: json!({"date": ref_time}),
-                },
-            ];
+/// The variable serving `product`: the variable of that name, or for `wind`
+/// and `temp` the first wind or temperature field, preferring ones with
+/// pressure levels exactly when an isobaric level is requested
+fn find_product_variable<'a>(
+    variables: &'a [VariableInfo],
+    product: &str,
+    level: EarthLevel,
+) -> Option<&'a VariableInfo> {
+    let named = variables.iter().find(|v| {
+        v.name == product
+            || matches!(&v.var_type, VariableType::Vector { u_component, .. } if u_component == product)
+    });
+    let candidates: Vec<&VariableInfo> = match product {
+        _ if named.is_some() => return named,
+        "wind" => variables
+            .iter()
+            .filter(|v| matches!(v.category, VariableCategory::Wind))
+            .filter(|v| matches!(v.var_type, VariableType::Vector { .. }))
+            .collect(),
+        "temp" => variables
+            .iter()
+            .filter(|v| matches!(v.category, VariableCategory::Temperature))
+            .filter(|v| matches!(v.var_type, VariableType::Scalar))
+            .collect(),
+        _ => return None,
+    };
+    candidates
+        .iter()
+        .find(|v| v.dimensions.iter().any(|d| is_vertical_dimension(d)) == level.is_isobaric())
+        .or(candidates.first())
+        .copied()
+}
 
-            let response_json = serde_json::to_string(&earth_data).map_err(|e| {
-                AppError::ProxyError(format!("Failed to serialize response: {}", e))
-            })?;
+/// Grid of the Earth records for a dataset: the header describing it and
+/// how the backend's fields are brought onto it
+pub(crate) struct EarthLayout {
+    grid: GridParams,
+    nx: usize,
+    ny: usize,
+    stride: usize,
+    irregular: Option<LatLonGrid>,
+}
 
-            let duration = start_time.elapsed();
+impl EarthLayout {
+    /// Layout of `variable`'s fields, refusing oversized grids before they
+    /// are fetched
+    pub(crate) fn new(
+        state: &AppState,
+        metadata: &Value,
+        variable: &str,
+    ) -> Result<Self, AppError> {
+        let full_grid = rossby_to_earth_grid(metadata)
+            .ok_or_else(|| invalid_metadata_error(metadata, "Invalid grid metadata"))?;
+        let (nx, ny) = (full_grid.nx as usize, full_grid.ny as usize);
+        let stride = state.earth_grid_limit.stride(nx, ny)?;
+        let grid = full_grid.downsampled(stride);
+        if stride > 1 {
             info!(
-                "Served Earth vector data for {} in {}ms",
-                variable,
-                duration.as_millis()
+                "Downsampling {} from {}x{} to {}x{} points",
+                variable, nx, ny, grid.nx, grid.ny
             );
-
-            Ok(HttpResponse::builder()
-                .status(StatusCode::OK)
-                .header(header::CONTENT_TYPE, "application/json")
-                .body(Body::from(response_json))
-                .unwrap()
-                .into_response())
+        }
+        // Earth assumes even spacing, so irregular grids are resampled to it
+        let irregular = LatLonGrid::from_metadata(metadata)
+            .ok()
+            .filter(|grid| !grid.is_uniform(SPACING_TOLERANCE));
+        if irregular.is_some() {
+            info!("Resampling {} to an evenly spaced grid", variable);
         }
 
-        VariableType::Scalar => {
-            // Handle scalar data
-            let data_url = format!(
-                "{}/data?vars={}&time={}&format=json",
-                state.api_url, variable, time
-            );
-
-            let data_response =
-                state.http_client.get(&data_url).send().await.map_err(|e| {
-                    AppError::ProxyError(format!("Failed to fetch scalar data: {}", e))
-                })?;
-
-            let rossby_data: Value = data_response
-                .json()
-                .await
-                .map_err(|e| AppError::ProxyError(format!("Failed to parse scalar data: {}", e)))?;
-
-            // Create grid parameters
-            let grid = GridParams {
-                nx,
-                ny,
-                lo1,
-                la1,
-                lo2,
-                la2,
-                dx,
-                dy,
-            };
+        Ok(Self {
+            grid,
+            nx,
+            ny,
+            stride,
+            irregular,
+        })
+    }
 
-            let var_data = extract_variable_data(&rossby_data, &variable);
-            let header = create_earth_header(var_info, &var_info.long_name, 0, &grid, &ref_time);
+    /// A backend field on the grid of the header
+    fn field(&self, values: Vec<f64>) -> Result<Vec<f64>, AppError> {
+        match &self.irregular {
+            Some(source) => downsample_grid(
+                &source.resample_uniform(&values)?,
+                self.nx,
+                self.ny,
+                self.stride,
+            ),
+            None => downsample_grid(&values, self.nx, self.ny, self.stride),
+        }
+    }
+}
 
-            let earth_data = vec![EarthDataPoint {
-                header,
-                data: var_data,
+/// Earth records for one timestep of a variable, from the backend fields of
+/// its components: the scalar, or the u and v components of a vector
+pub(crate) async fn earth_records(
+    state: &AppState,
+    metadata: &Value,
+    var_info: &VariableInfo,
+    mask: Option<MaskMode>,
+    time: f64,
+    layout: &EarthLayout,
+    mut fields: Vec<Vec<f64>>,
+) -> Result<Vec<EarthDataPoint>, AppError> {
+    let mut masked: Vec<&mut Vec<f64>> = fields.iter_mut().collect();
+    apply_land_sea_mask(state, metadata, var_info, mask, time, &mut masked).await?;
+
+    let ref_time = to_iso(time);
+    let hints = state
+        .render_hints
+        .read()
+        .unwrap_or_else(|e| e.into_inner())
+        .for_field(&component_names(var_info));
+    let scalar_name = hints.display_name.as_deref().unwrap_or(&var_info.long_name);
+    let parameters: &[(&str, u8)] = match var_info.var_type {
+        VariableType::Vector { .. } => &[("U-component", 2), ("V-component", 3)],
+        VariableType::Scalar => &[(scalar_name, 0)],
+    };
+    parameters
+        .iter()
+        .zip(fields)
+        .map(|((name, number), values)| {
+            Ok(EarthDataPoint {
+                header: create_earth_header(
+                    var_info,
+                    name,
+                    *number,
+                    &layout.grid,
+                    &ref_time,
+                    hints.clone(),
+                ),
+                data: layout.field(values)?,
+                packed: None,
                 meta: json!({"date": ref_time}),
-            }];
+            })
+        })
+        .collect()
+}
 
-            let response_json = serde_json::to_string(&earth_data).map_err(|e| {
-                AppError::ProxyError(format!("Failed to serialize response: {}", e))
-            })?;
+/// The variable of `variables`, as analyzed from `metadata`, serving
+/// `product` at `level`, with the coordinate value of the level when it is
+/// isobaric
+pub(crate) fn select_variable(
+    metadata: &Value,
+    variables: &[VariableInfo],
+    product: &str,
+    level: EarthLevel,
+) -> Result<(VariableInfo, Option<(String, f64)>), AppError> {
+    let var_info = find_product_variable(variables, product, level).ok_or_else(|| {
+        AppError::ProxyError(format!("Variable '{}' not found in metadata", product))
+    })?;
+
+    let level = match level {
+        EarthLevel::Surface => None,
+        EarthLevel::Isobaric(hpa) => {
+            let dimension = var_info
+                .dimensions
+                .iter()
+                .find(|d| is_vertical_dimension(d))
+                .ok_or_else(|| {
+                    AppError::RequestError(format!(
+                        "Variable '{}' has no pressure levels",
+                        var_info.name
+                    ))
+                })?;
+            Some((
+                dimension.clone(),
+                select_pressure_level(metadata, dimension, hpa)?,
+            ))
+        }
+    };
+    Ok((var_info.clone(), level))
+}
 
-            let duration = start_time.elapsed();
-            info!(
-                "Served Earth scalar data for {} in {}ms",
-                variable,
-                duration.as_millis()
-            );
+/// The components fetched from the backend for a variable
+pub(crate) fn component_names(var_info: &VariableInfo) -> Vec<&str> {
+    match &var_info.var_type {
+        VariableType::Vector {
+            u_component,
+            v_component,
+        } => vec![u_component, v_component],
+        VariableType::Scalar => vec![&var_info.name],
+    }
+}
 
-            Ok(HttpResponse::builder()
-                .status(StatusCode::OK)
-                .header(header::CONTENT_TYPE, "application/json")
-                .body(Body::from(response_json))
-                .unwrap()
-                .into_response())
+/// Earth JSON for one timestep of a scalar or vector variable, masked and
+/// packed as `query` asks, with the freshness validators applied
+pub(crate) async fn earth_variable_response(
+    state: &AppState,
+    metadata: &Value,
+    var_info: &VariableInfo,
+    query: &EarthQuery,
+    time: f64,
+    level: Option<(&str, f64)>,
+    freshness: &DataFreshness,
+) -> Result<Response, AppError> {
+    let start_time = Instant::now();
+    let variable = &var_info.name;
+    let is_derived = derived::is_derived(&state.derived_variables, metadata, variable);
+    if level.is_some() && is_derived {
+        return Err(AppError::RequestError(format!(
+            "Derived product '{}' has no pressure levels",
+            variable
+        )));
+    }
+    let layout = EarthLayout::new(state, metadata, variable)?;
+    let format = query.format.unwrap_or_default();
+    let packed = if format.is_packed() {
+        Some(true)
+    } else {
+        query.packed
+    };
+    let packing = state.packing.resolve(packed, query.precision)?;
+
+    // Compute derived products from their inputs, and select the level from
+    // the backend with a dimension selector
+    let components = component_names(var_info);
+    let rossby_data: Value = if is_derived {
+        fetch_with_derived(state, metadata, &components, Some(time)).await?
+    } else {
+        let mut query = data_query(&components, Some(time));
+        if let Some((dimension, value)) = level {
+            query.push_str(&format!("&{}={}", dimension, value));
+        }
+        fetch_data(state, &query).await?
+    };
+    let fields = components
+        .iter()
+        .map(|component| extract_variable_data(&rossby_data, component))
+        .collect();
+
+    let mut earth_data =
+        earth_records(state, metadata, var_info, query.mask, time, &layout, fields).await?;
+    if let Some(units) = query.units {
+        for record in &mut earth_data {
+            record.convert_units(units);
+        }
+    }
+    if let Some(precision) = packing {
+        for record in &mut earth_data {
+            record.pack(precision)?;
         }
     }
+    info!(
+        "Served Earth {} data for {} in {}ms",
+        match var_info.var_type {
+            VariableType::Vector { .. } => "vector",
+            VariableType::Scalar => "scalar",
+        },
+        variable,
+        start_time.elapsed().as_millis()
+    );
+
+    Ok(freshness.apply(with_data_time(format.render(&earth_data)?, time)))
 }
 
+/// Extract a flattened variable, keeping missing values as NaN so the grid stays aligned
 fn extract_variable_data(rossby_data: &Value, variable: &str) -> Vec<f64> {
     rossby_data
         .get("data")
         .and_then(|d| d.get(variable))
         .and_then(|v| v.as_array())
-        .map(|arr| arr.iter().filter_map(|v| v.as_f64()).collect::<Vec<f64>>())
+        .map(|arr| {
+            arr.iter()
+                .map(|v| v.as_f64().unwrap_or(f64::NAN))
+                .collect::<Vec<f64>>()
+        })
         .unwrap_or_default()
 }
 
+/// Hide land or ocean cells according to the request and the server mask settings.
+///
+/// An explicitly requested mask fails when no land-sea mask is available,
+/// while automatic masking is skipped with a warning.
+async fn apply_land_sea_mask(
+    state: &AppState,
+    metadata: &Value,
+    var_info: &VariableInfo,
+    requested: Option<MaskMode>,
+    time: f64,
+    fields: &mut [&mut Vec<f64>],
+) -> Result<(), AppError> {
+    let Some(mode) =
+        state
+            .land_sea_mask
+            .effective_mode(requested, &var_info.name, &var_info.long_name)
+    else {
+        return Ok(());
+    };
+
+    let mask = match (
+        &state.land_sea_mask.values,
+        state.land_sea_mask.mask_variable(metadata),
+    ) {
+        (Some(values), _) => values.clone(),
+        (None, Some(mask_variable)) => {
+            let mask_data = fetch_data(state, &data_query(&[&mask_variable], Some(time))).await?;
+            let array = DataArray::from_rossby_response(&mask_data, &mask_variable)?;
+            array.horizontal_slice(&time_selection(metadata, &array, Some(time)))?
+        }
+        (None, None) if requested.is_some() => {
+            return Err(AppError::RequestError(
+                "No land-sea mask is available for this dataset".to_string(),
+            ))
+        }
+        (None, None) => {
+            warn!(
+                "Skipping automatic land-sea masking of {}: no mask available",
+                var_info.name
+            );
+            return Ok(());
+        }
+    };
+
+    for field in fields.iter_mut() {
+        apply_mask(field, &mask, mode)?;
+    }
+    Ok(())
+}
+
 fn create_earth_header(
     var_info: &VariableInfo,
     parameter_name: &str,
     parameter_number: u8,
     grid: &GridParams,
     ref_time: &str,
+    hints: RenderHint,
 ) -> EarthHeader {
     EarthHeader {
         discipline: 0,
@@ -737,75 +1582,191 @@ fn create_earth_header(
         la2: grid.la2,
         dx: grid.dx,
         dy: grid.dy,
+        hints,
     }
 }
 
 /// Legacy handler for Earth frontend wind data requests - redirects to dynamic handler
-#[instrument(skip(state))]
-pub async fn earth_wind_data(State(state): State<Arc<AppState>>) -> Result<Response, AppError> {
+#[utoipa::path(
+    get,
+    path = "/data/weather/current/current-wind-surface-level-gfs-1.0.json",
+    tag = "earth",
+    summary = "Surface wind",
+    description = "The first wind vector pair of the dataset.",
+    params(EarthQuery),
+    responses(
+        (
+            status = 200,
+            description = "Earth records: a header and a data array per component",
+            content(
+                (serde_json::Value = "application/json"),
+                (serde_json::Value = "application/msgpack"),
+                (serde_json::Value = "application/vnd.rossby.packed+json"),
+            )
+        ),
+        (status = 304, description = "Unchanged since the request's `If-None-Match` or `If-Modified-Since`"),
+        (status = 400, description = "Invalid path or query", body = ErrorBody),
+        (status = 404, description = "No such variable or level", body = ErrorBody),
+        (status = 502, description = "The backend failed", body = ErrorBody),
+    )
+)]
+#[instrument(skip(state, headers))]
+pub async fn earth_wind_data(
+    State(state): State<Arc<AppState>>,
+    Query(query): Query<EarthQuery>,
+    uri: Uri,
+    headers: HeaderMap,
+) -> Result<Response, AppError> {
     info!("Legacy wind data request - redirecting to dynamic handler");
 
-    // Find the first available wind variable from metadata
-    let metadata_url = format!("{}/metadata", state.api_url);
-    let metadata_response = state
-        .http_client
-        .get(&metadata_url)
-        .send()
-        .await
-        .map_err(|e| AppError::ProxyError(format!("Failed to fetch metadata: {}", e)))?;
-
-    let metadata: Value = metadata_response
-        .json()
-        .await
-        .map_err(|e| AppError::ProxyError(format!("Failed to parse metadata: {}", e)))?;
-
-    let variables = analyze_metadata_variables(&metadata);
-
-    // Find first wind vector variable
-    let wind_var = variables
-        .iter()
-        .find(|v| {
-            matches!(v.category, VariableCategory::Wind)
-                && matches!(v.var_type, VariableType::Vector { .. })
-        })
-        .map(|v| match &v.var_type {
-            VariableType::Vector { u_component, .. } => u_component.clone(),
-            _ => v.name.clone(),
-        })
-        .unwrap_or_else(|| "u10".to_string()); // Fallback to common wind variable
-
-    earth_dynamic_data(State(state), Path(wind_var)).await
+    // Find the first wind vector variable in the catalog
+    let catalog = state.catalog.load(&state).await?;
+    let wind_var = catalog.wind_variable().unwrap_or("u10"); // Fallback to common wind variable
+
+    earth_catalog_data(
+        &state,
+        &catalog,
+        wind_var,
+        EarthLevel::Surface,
+        &query,
+        &uri,
+        &headers,
+    )
+    .await
 }
 
 /// Legacy handler for Earth frontend temperature data requests - redirects to dynamic handler
-#[instrument(skip(state))]
-pub async fn earth_temp_data(State(state): State<Arc<AppState>>) -> Result<Response, AppError> {
+#[utoipa::path(
+    get,
+    path = "/data/weather/current/current-temp-surface-level-gfs-1.0.json",
+    tag = "earth",
+    summary = "Surface temperature",
+    description = "The first temperature variable of the dataset.",
+    params(EarthQuery),
+    responses(
+        (
+            status = 200,
+            description = "Earth records: a header and a data array per component",
+            content(
+                (serde_json::Value = "application/json"),
+                (serde_json::Value = "application/msgpack"),
+                (serde_json::Value = "application/vnd.rossby.packed+json"),
+            )
+        ),
+        (status = 304, description = "Unchanged since the request's `If-None-Match` or `If-Modified-Since`"),
+        (status = 400, description = "Invalid path or query", body = ErrorBody),
+        (status = 404, description = "No such variable or level", body = ErrorBody),
+        (status = 502, description = "The backend failed", body = ErrorBody),
+    )
+)]
+#[instrument(skip(state, headers))]
+pub async fn earth_temp_data(
+    State(state): State<Arc<AppState>>,
+    Query(query): Query<EarthQuery>,
+    uri: Uri,
+    headers: HeaderMap,
+) -> Result<Response, AppError> {
     info!("Legacy temperature data request - redirecting to dynamic handler");
 
-    // Find the first available temperature variable from metadata
-    let metadata_url = format!("{}/metadata", state.api_url);
-    let metadata_response = state
-        .http_client
-        .get(&metadata_url)
-        .send()
-        .await
-        .map_err(|e| AppError::ProxyError(format!("Failed to fetch metadata: {}", e)))?;
-
-    let metadata: Value = metadata_response
-        .json()
-        .await
-        .map_err(|e| AppError::ProxyError(format!("Failed to parse metadata: {}", e)))?;
+    // Find the first temperature variable in the catalog
+    let catalog = state.catalog.load(&state).await?;
+    let temp_var = catalog.temperature_variable().unwrap_or("t2m"); // Fallback to common temperature variable
+
+    earth_catalog_data(
+        &state,
+        &catalog,
+        temp_var,
+        EarthLevel::Surface,
+        &query,
+        &uri,
+        &headers,
+    )
+    .await
+}
 
-    let variables = analyze_metadata_variables(&metadata);
+/// Handler for Earth's current data paths,
+/// `/data/weather/current/current-{product}-{surface}-{level}-gfs-1.0.json`.
+/// `wind` and `temp` pick a variable as the legacy routes do; other products
+/// name a variable. Isobaric levels such as `isobaric-850hPa` select that
+/// pressure level.
+#[utoipa::path(
+    get,
+    path = "/data/weather/current/{file}",
+    tag = "earth",
+    summary = "Any variable, at the surface or a pressure level",
+    params(
+        (
+            "file" = String,
+            Path,
+            description = "`current-{product}-{surface}-{level}-gfs-1.0.json`, e.g. `current-temp-isobaric-850hPa-gfs-1.0.json`"
+        ),
+        EarthQuery,
+    ),
+    responses(
+        (
+            status = 200,
+            description = "Earth records: a header and a data array per component",
+            content(
+                (serde_json::Value = "application/json"),
+                (serde_json::Value = "application/msgpack"),
+                (serde_json::Value = "application/vnd.rossby.packed+json"),
+            )
+        ),
+        (status = 304, description = "Unchanged since the request's `If-None-Match` or `If-Modified-Since`"),
+        (status = 400, description = "Invalid path or query", body = ErrorBody),
+        (status = 404, description = "No such variable or level", body = ErrorBody),
+        (status = 502, description = "The backend failed", body = ErrorBody),
+    )
+)]
+#[instrument(skip(state, headers))]
+pub async fn earth_current_data(
+    State(state): State<Arc<AppState>>,
+    Path(file): Path<String>,
+    Query(query): Query<EarthQuery>,
+    uri: Uri,
+    headers: HeaderMap,
+) -> Result<Response, AppError> {
+    let name = EarthFileName::parse(&file)
+        .filter(|name| name.prefix == "current")
+        .ok_or_else(|| {
+            AppError::RequestError(format!("Invalid Earth data path '{}'", uri.path()))
+        })?;
 
-    // Find first temperature variable
-    let temp_var = variables
-        .iter()
-        .find(|v| matches!(v.category, VariableCategory::Temperature))
-        .map(|v| v.name.clone())
-        .unwrap_or_else(|| "t2m".to_string()); // Fallback to common temperature variable
+    earth_product_data(&state, &name.product, name.level, &query, &uri, &headers).await
+}
 
-    earth_dynamic_data(State(state), Path(temp_var)).await
+/// Handler for Earth's dated data paths,
+/// `/data/weather/{yyyy}/{mm}/{dd}/{hhmm}-{product}-{surface}-{level}-gfs-1.0.json`,
+/// serving the timestep nearest that time with the products and levels of
+/// the current paths
+#[instrument(skip(state, headers))]
+pub async fn earth_dated_data(
+    State(state): State<Arc<AppState>>,
+    Path((year, month, day, file)): Path<(String, String, String, String)>,
+    Query(query): Query<EarthQuery>,
+    uri: Uri,
+    headers: HeaderMap,
+) -> Result<Response, AppError> {
+    let invalid = || AppError::RequestError(format!("Invalid Earth data path '{}'", uri.path()));
+    let name = EarthFileName::parse(&file)
+        .filter(|name| name.prefix.len() == 4)
+        .ok_or_else(invalid)?;
+    let hhmm = &name.prefix;
+    let time = format!(
+        "{}-{}-{}T{}:{}:00Z",
+        year,
+        month,
+        day,
+        &hhmm[..2],
+        &hhmm[2..]
+    );
+    TimeRequest::parse(&time).map_err(|_| invalid())?;
+
+    let query = EarthQuery {
+        time: Some(time),
+        ..query
+    };
+    earth_product_data(&state, &name.product, name.level, &query, &uri, &headers).await
 }
 
 #[cfg(test)]
@@ -815,7 +1776,8 @@ mod tests {
     #[tokio::test]
     async fn test_index_handler() {
         // We can only test the handler if the embedded assets are available
-        let response = index().await;
+        let state = AppState::new("http://localhost:8000".to_string(), reqwest::Client::new());
+        let response = index(State(Arc::new(state)), HeaderMap::new()).await;
 
         // The status will depend on whether index.html exists in the embedded assets
         if StaticAssets::get("index.html").is_some() {
@@ -824,4 +1786,24 @@ mod tests {
             assert_eq!(response.status(), StatusCode::NOT_FOUND);
         }
     }
+
+    #[test]
+    fn test_downsampled_grid_params() {
+        let grid = GridParams {
+            nx: 3600,
+            ny: 1801,
+            lo1: 0.0,
+            la1: 90.0,
+            lo2: 359.9,
+            la2: -90.0,
+            dx: 0.1,
+            dy: 0.1,
+        };
+        let thinned = grid.downsampled(2);
+        assert_eq!((thinned.nx, thinned.ny), (1800, 901));
+        assert!((thinned.dx - 0.2).abs() < 1e-9);
+        assert!((thinned.lo2 - 359.8).abs() < 1e-9);
+        assert!((thinned.la2 + 90.0).abs() < 1e-9);
+        assert_eq!(grid.downsampled(1), grid);
+    }
 }
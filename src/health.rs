@@ -0,0 +1,185 @@
+//! Backend connectivity reported by `/health`
+//!
+//! The health check asks the backend for `{api_url}/metadata` and reports
+//! the outcome as `backend.status`:
+//!
+//! - `healthy`: the metadata came back in time and parses
+//! - `degraded`: the backend answered, but with an error status, unusable
+//!   metadata or slower than the slow threshold
+//! - `unreachable`: no answer, because of a connection error or timeout
+//!
+//! The result is kept for `--health-probe-ttl` seconds so frequent health
+//! checks from orchestrators and load balancers do not load the backend;
+//! concurrent checks share one probe.
+
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+use serde_json::Value;
+use std::time::{Duration, Instant};
+use tokio::sync::Mutex;
+
+use crate::server::AppState;
+
+/// How long a probe result is reused by default
+pub const DEFAULT_PROBE_TTL: Duration = Duration::from_secs(10);
+/// How long a probe waits for the backend by default
+pub const DEFAULT_PROBE_TIMEOUT: Duration = Duration::from_secs(5);
+/// Answers slower than this are degraded by default
+pub const DEFAULT_SLOW_THRESHOLD: Duration = Duration::from_secs(2);
+
+/// Settings of the backend probe
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct HealthProbeConfig {
+    /// How long a probe result is reused
+    pub ttl: Duration,
+    /// How long a probe waits for the backend
+    pub timeout: Duration,
+    /// Answers slower than this are degraded
+    pub slow_threshold: Duration,
+}
+
+impl Default for HealthProbeConfig {
+    fn default() -> Self {
+        Self {
+            ttl: DEFAULT_PROBE_TTL,
+            timeout: DEFAULT_PROBE_TIMEOUT,
+            slow_threshold: DEFAULT_SLOW_THRESHOLD,
+        }
+    }
+}
+
+/// State of the backend as seen by the last probe
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum BackendStatus {
+    Healthy,
+    Degraded,
+    Unreachable,
+}
+
+/// Outcome of a probe, as reported under `backend` by `/health`
+#[derive(Debug, Clone, Serialize)]
+pub struct ProbeResult {
+    pub status: BackendStatus,
+    pub checked_at: DateTime<Utc>,
+    /// Time until the backend answered, absent when it did not
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub latency_ms: Option<u64>,
+    /// Why the backend is not healthy
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+    /// When a probe last found the backend healthy
+    pub last_success: Option<DateTime<Utc>>,
+}
+
+/// The cached probe of a server's backend
+#[derive(Debug, Default)]
+pub struct BackendProbe {
+    config: HealthProbeConfig,
+    /// The last result with the time it was taken, locked while probing
+    last: Mutex<Option<(Instant, ProbeResult)>>,
+}
+
+impl BackendProbe {
+    pub fn new(config: HealthProbeConfig) -> Self {
+        Self {
+            config,
+            last: Mutex::new(None),
+        }
+    }
+
+    /// The backend's state, probing it unless a recent result is cached
+    pub async fn check(&self, state: &AppState) -> ProbeResult {
+        let mut last = self.last.lock().await;
+        if let Some((taken, result)) = last.as_ref() {
+            if taken.elapsed() < self.config.ttl {
+                return result.clone();
+            }
+        }
+
+        let previous_success = last.as_ref().and_then(|(_, result)| result.last_success);
+        let result = self.probe(state, previous_success).await;
+        *last = Some((Instant::now(), result.clone()));
+        result
+    }
+
+    async fn probe(&self, state: &AppState, last_success: Option<DateTime<Utc>>) -> ProbeResult {
+        let checked_at = Utc::now();
+        let start = Instant::now();
+        let url = format!("{}/metadata", state.api_url);
+        let response = state
+            .backend_get(&url)
+            .timeout(self.config.timeout)
+            .send()
+            .await;
+
+        let outcome = match response {
+            Err(e) if e.is_timeout() => Err((
+                BackendStatus::Unreachable,
+                format!("no answer within {}ms", self.config.timeout.as_millis()),
+            )),
+            Err(e) => Err((BackendStatus::Unreachable, e.to_string())),
+            Ok(response) if !response.status().is_success() => Err((
+                BackendStatus::Degraded,
+                format!("metadata request returned {}", response.status()),
+            )),
+            Ok(response) => match response.json::<Value>().await {
+                Ok(metadata) if metadata.is_object() => Ok(()),
+                Ok(_) => Err((
+                    BackendStatus::Degraded,
+                    "metadata is not a JSON object".to_string(),
+                )),
+                Err(e) => Err((
+                    BackendStatus::Degraded,
+                    format!("unreadable metadata: {}", e),
+                )),
+            },
+        };
+        let latency = start.elapsed();
+
+        let (status, error) = match outcome {
+            Ok(()) if latency > self.config.slow_threshold => (
+                BackendStatus::Degraded,
+                Some(format!("metadata took {}ms", latency.as_millis())),
+            ),
+            Ok(()) => (BackendStatus::Healthy, None),
+            Err((status, error)) => (status, Some(error)),
+        };
+        if status != BackendStatus::Healthy {
+            tracing::warn!(backend_url = %url, ?status, error = ?error, "Backend health probe failed");
+        }
+        ProbeResult {
+            status,
+            checked_at,
+            latency_ms: (status != BackendStatus::Unreachable)
+                .then_some(latency.as_millis() as u64),
+            error,
+            last_success: if status == BackendStatus::Healthy {
+                Some(checked_at)
+            } else {
+                last_success
+            },
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_result_serialization() {
+        let checked_at = Utc::now();
+        let result = ProbeResult {
+            status: BackendStatus::Unreachable,
+            checked_at,
+            latency_ms: None,
+            error: Some("connection refused".to_string()),
+            last_success: None,
+        };
+        let value = serde_json::to_value(&result).unwrap();
+        assert_eq!(value["status"], "unreachable");
+        assert_eq!(value["last_success"], Value::Null);
+        assert!(value.get("latency_ms").is_none());
+    }
+}
@@ -0,0 +1,215 @@
+//! Per-variable rendering hints in Earth headers
+//!
+//! The frontend draws every field the same way: bilinear interpolation,
+//! values as served, a fixed particle count for vector fields. Some fields
+//! look better otherwise, e.g. categorical fields such as precipitation type
+//! without interpolation, or sparse ocean currents with more particles.
+//! Operators can tune this per variable in a hints file passed with
+//! `--render-hints`:
+//!
+//! ```json
+//! [
+//!     {"variable": "ptype", "interpolation": "nearest"},
+//!     {"variable": "t2m", "display_offset": -273.15, "display_name": "Air temperature"},
+//!     {"variable": "uo", "particle_density": 2.0}
+//! ]
+//! ```
+//!
+//! The hints are added to the headers of the Earth records as
+//! `interpolation`, `displayScale`, `displayOffset` and `particleDensity`.
+//! The frontend shows `value * displayScale + displayOffset` (vector
+//! components are only scaled) and multiplies its particle count by
+//! `particleDensity`. Vector variables are matched by the name of either
+//! component; particle density only applies to them. `display_name`
+//! replaces the `long_name` of the backend as the name of the overlay in
+//! `/data/products.json` and of scalar fields in their Earth headers.
+//!
+//! The files are read again when the configuration is reloaded.
+
+use serde::{Deserialize, Serialize};
+use std::{collections::HashMap, path::Path};
+
+use crate::error::AppError;
+
+/// How the frontend interpolates between grid points
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Interpolation {
+    Bilinear,
+    /// The value of the closest grid point, for categorical fields
+    Nearest,
+}
+
+/// Hints for one variable, as emitted in Earth headers
+#[derive(Debug, Clone, Default, PartialEq, Serialize)]
+pub struct RenderHint {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub interpolation: Option<Interpolation>,
+    #[serde(rename = "displayScale", skip_serializing_if = "Option::is_none")]
+    pub display_scale: Option<f64>,
+    #[serde(rename = "displayOffset", skip_serializing_if = "Option::is_none")]
+    pub display_offset: Option<f64>,
+    /// Multiplier of the particle count of vector fields
+    #[serde(rename = "particleDensity", skip_serializing_if = "Option::is_none")]
+    pub particle_density: Option<f64>,
+    /// Name shown instead of the backend's `long_name`; Earth headers carry
+    /// it as their parameter name
+    #[serde(skip)]
+    pub display_name: Option<String>,
+}
+
+impl RenderHint {
+    fn validate(&self, variable: &str) -> Result<(), AppError> {
+        let invalid = |field: &str| {
+            Err(AppError::ConfigError(format!(
+                "Invalid {} in render hints of '{}'",
+                field, variable
+            )))
+        };
+        if self
+            .display_scale
+            .is_some_and(|scale| !scale.is_finite() || scale == 0.0)
+        {
+            return invalid("display_scale");
+        }
+        if self
+            .display_offset
+            .is_some_and(|offset| !offset.is_finite())
+        {
+            return invalid("display_offset");
+        }
+        if self
+            .particle_density
+            .is_some_and(|density| !density.is_finite() || density <= 0.0)
+        {
+            return invalid("particle_density");
+        }
+        if self
+            .display_name
+            .as_ref()
+            .is_some_and(|name| name.trim().is_empty())
+        {
+            return invalid("display_name");
+        }
+        Ok(())
+    }
+}
+
+/// A hint as written in the hints file
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct HintDefinition {
+    variable: String,
+    interpolation: Option<Interpolation>,
+    display_scale: Option<f64>,
+    display_offset: Option<f64>,
+    particle_density: Option<f64>,
+    display_name: Option<String>,
+}
+
+/// The rendering hints of a deployment, by variable
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct RenderHints {
+    hints: HashMap<String, RenderHint>,
+}
+
+impl RenderHints {
+    /// Load the hints of a JSON file, returning the number loaded
+    pub fn load(&mut self, path: &Path) -> Result<usize, AppError> {
+        let error = |message: String| {
+            AppError::ConfigError(format!(
+                "Cannot load render hints from {}: {}",
+                path.display(),
+                message
+            ))
+        };
+        let contents = std::fs::read_to_string(path).map_err(|e| error(e.to_string()))?;
+        self.add_json(&contents).map_err(|e| error(e.to_string()))
+    }
+
+    /// Add the hints of a JSON document, returning the number added
+    pub fn add_json(&mut self, json: &str) -> Result<usize, AppError> {
+        let definitions: Vec<HintDefinition> = serde_json::from_str(json)
+            .map_err(|e| AppError::ConfigError(format!("Invalid render hints: {}", e)))?;
+
+        let count = definitions.len();
+        for definition in definitions {
+            let hint = RenderHint {
+                interpolation: definition.interpolation,
+                display_scale: definition.display_scale,
+                display_offset: definition.display_offset,
+                particle_density: definition.particle_density,
+                display_name: definition.display_name,
+            };
+            hint.validate(&definition.variable)?;
+            if self.hints.contains_key(&definition.variable) {
+                return Err(AppError::ConfigError(format!(
+                    "Render hints of '{}' are given more than once",
+                    definition.variable
+                )));
+            }
+            self.hints.insert(definition.variable, hint);
+        }
+        Ok(count)
+    }
+
+    /// The hints for the field of `components`, the first variable with
+    /// hints winning; particle density is dropped for scalar fields
+    pub fn for_field(&self, components: &[&str]) -> RenderHint {
+        let Some(hint) = components.iter().find_map(|name| self.hints.get(*name)) else {
+            return RenderHint::default();
+        };
+        let mut hint = hint.clone();
+        if components.len() < 2 {
+            hint.particle_density = None;
+        }
+        hint
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_hints_for_field() {
+        let mut hints = RenderHints::default();
+        let count = hints
+            .add_json(
+                r#"[
+                    {"variable": "ptype", "interpolation": "nearest", "particle_density": 2},
+                    {"variable": "vo", "particle_density": 2, "display_scale": 100}
+                ]"#,
+            )
+            .unwrap();
+        assert_eq!(count, 2);
+
+        let scalar = hints.for_field(&["ptype"]);
+        assert_eq!(scalar.interpolation, Some(Interpolation::Nearest));
+        assert_eq!(scalar.particle_density, None);
+
+        let vector = hints.for_field(&["uo", "vo"]);
+        assert_eq!(vector.particle_density, Some(2.0));
+        assert_eq!(
+            serde_json::to_value(&vector).unwrap(),
+            serde_json::json!({"displayScale": 100.0, "particleDensity": 2.0})
+        );
+
+        assert_eq!(hints.for_field(&["t2m"]), RenderHint::default());
+    }
+
+    #[test]
+    fn test_invalid_hints() {
+        let mut hints = RenderHints::default();
+        for json in [
+            r#"[{"variable": "t2m", "interpolation": "cubic"}]"#,
+            r#"[{"variable": "t2m", "display_scale": 0}]"#,
+            r#"[{"variable": "u10", "particle_density": -1}]"#,
+            r#"[{"variable": "t2m", "colour": "red"}]"#,
+            r#"[{"variable": "t2m", "display_name": " "}]"#,
+            r#"[{"variable": "t2m"}, {"variable": "t2m"}]"#,
+        ] {
+            assert!(hints.add_json(json).is_err(), "{}", json);
+        }
+    }
+}
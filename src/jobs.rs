@@ -0,0 +1,672 @@
+//! Background jobs for expensive analyses
+//!
+//! Trajectory runs over many seeds, cross-sections through deep columns or
+//! sampling thousands of points can take longer than clients, proxies and
+//! load balancers are willing to hold a request open. The same analyses can
+//! be submitted to `POST /api/jobs` instead, which answers at once with a job
+//! id. Clients then poll `GET /api/jobs/{id}` or follow the Server-Sent
+//! Events of `GET /api/jobs/{id}/events`, and fetch the output from
+//! `GET /api/jobs/{id}/result` once the job has succeeded.
+//!
+//! A fixed number of workers run jobs in priority order, first come first
+//! served within a priority. The queue is bounded, and finished jobs are
+//! forgotten after a retention period.
+
+use axum::{
+    body::Bytes,
+    extract::{Path, Query, State},
+    http::{header, HeaderMap, StatusCode},
+    response::{
+        sse::{Event, KeepAlive, Sse},
+        IntoResponse, Response,
+    },
+    Json,
+};
+use chrono::{DateTime, Utc};
+use futures::Stream;
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use std::{
+    cmp::Ordering,
+    collections::{BinaryHeap, HashMap},
+    convert::Infallible,
+    sync::{Arc, Mutex, MutexGuard},
+    time::{Duration, Instant},
+};
+use tokio::{sync::watch, task::AbortHandle};
+use tracing::{info, instrument};
+use utoipa::ToSchema;
+use uuid::Uuid;
+
+use crate::{
+    analysis::{
+        cross_section, sample, trajectories, CrossSectionRequest, SampleRequest, TrajectoryRequest,
+    },
+    error::{AppError, ErrorBody},
+    server::AppState,
+};
+
+/// The analysis run by a job, named after its `/api` route
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+#[serde(rename_all = "kebab-case")]
+pub enum JobKind {
+    CrossSection,
+    Trajectories,
+    Sample,
+}
+
+/// Scheduling priority of a job
+#[derive(
+    Debug, Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize, ToSchema,
+)]
+#[serde(rename_all = "lowercase")]
+pub enum JobPriority {
+    Low,
+    #[default]
+    Normal,
+    High,
+}
+
+/// Lifecycle state of a job
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, ToSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum JobState {
+    Queued,
+    Running,
+    Succeeded,
+    Failed,
+    Cancelled,
+}
+
+impl JobState {
+    /// Whether the job will not change any more
+    pub fn is_finished(self) -> bool {
+        matches!(
+            self,
+            JobState::Succeeded | JobState::Failed | JobState::Cancelled
+        )
+    }
+}
+
+/// Body of `POST /api/jobs`
+#[derive(Debug, Deserialize, ToSchema)]
+pub struct JobSubmission {
+    pub kind: JobKind,
+    /// The request body the analysis route would take
+    pub request: Value,
+    #[serde(default)]
+    pub priority: JobPriority,
+}
+
+/// Status of a job, as served by the job routes
+#[derive(Debug, Clone, Serialize, ToSchema)]
+pub struct JobStatus {
+    pub id: String,
+    pub kind: JobKind,
+    pub priority: JobPriority,
+    pub state: JobState,
+    pub submitted_at: DateTime<Utc>,
+    pub started_at: Option<DateTime<Utc>>,
+    pub finished_at: Option<DateTime<Utc>>,
+    /// Why the job failed
+    pub error: Option<String>,
+}
+
+/// Job counts reported by `GET /admin/overview`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+pub struct JobStats {
+    pub queued: usize,
+    pub running: usize,
+    /// Jobs known to the queue, finished ones included
+    pub retained: usize,
+    pub workers: usize,
+    pub max_queued: usize,
+}
+
+/// Limits of the job queue
+#[derive(Debug, Clone)]
+pub struct JobsConfig {
+    /// Jobs run at the same time
+    pub workers: usize,
+    /// Jobs waiting for a worker before submissions are refused
+    pub max_queued: usize,
+    /// How long finished jobs and their results are kept
+    pub retention: Duration,
+}
+
+impl Default for JobsConfig {
+    fn default() -> Self {
+        Self {
+            workers: 2,
+            max_queued: 64,
+            retention: Duration::from_secs(3600),
+        }
+    }
+}
+
+/// A failed job's error response
+#[derive(Debug, Clone)]
+struct JobError {
+    status: StatusCode,
+    message: String,
+}
+
+impl From<AppError> for JobError {
+    fn from(error: AppError) -> Self {
+        let message = error.to_string();
+        Self {
+            status: error.into_response().status(),
+            message,
+        }
+    }
+}
+
+struct Job {
+    status: watch::Sender<JobStatus>,
+    /// Taken when the job starts
+    submission: Option<JobSubmission>,
+    result: Option<Result<Value, JobError>>,
+    task: Option<AbortHandle>,
+    finished: Option<Instant>,
+}
+
+/// A waiting job in the priority queue
+#[derive(PartialEq, Eq)]
+struct Queued {
+    priority: JobPriority,
+    sequence: u64,
+    id: Uuid,
+}
+
+impl Ord for Queued {
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.priority
+            .cmp(&other.priority)
+            .then_with(|| other.sequence.cmp(&self.sequence))
+    }
+}
+
+impl PartialOrd for Queued {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+#[derive(Default)]
+struct Jobs {
+    jobs: HashMap<Uuid, Job>,
+    queue: BinaryHeap<Queued>,
+    running: usize,
+    sequence: u64,
+}
+
+impl Jobs {
+    fn get(&mut self, id: Uuid) -> Result<&mut Job, AppError> {
+        self.jobs
+            .get_mut(&id)
+            .ok_or_else(|| AppError::NotFound(format!("No job {}", id)))
+    }
+
+    /// Move a job into a finished state, freeing its worker if it was running
+    fn finish(&mut self, id: Uuid, state: JobState, result: Option<Result<Value, JobError>>) {
+        let Some(job) = self.jobs.get_mut(&id) else {
+            return;
+        };
+        let previous = job.status.borrow().state;
+        if previous.is_finished() {
+            return;
+        }
+        if previous == JobState::Running {
+            self.running -= 1;
+        }
+        job.status.send_modify(|status| {
+            status.state = state;
+            status.finished_at = Some(Utc::now());
+            status.error = match &result {
+                Some(Err(error)) => Some(error.message.clone()),
+                _ => None,
+            };
+        });
+        job.submission = None;
+        job.task = None;
+        job.result = result;
+        job.finished = Some(Instant::now());
+    }
+}
+
+/// The queue of background jobs
+pub struct JobQueue {
+    config: JobsConfig,
+    jobs: Mutex<Jobs>,
+}
+
+impl std::fmt::Debug for JobQueue {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("JobQueue")
+            .field("config", &self.config)
+            .finish_non_exhaustive()
+    }
+}
+
+impl Default for JobQueue {
+    fn default() -> Self {
+        Self::new(JobsConfig::default())
+    }
+}
+
+impl JobQueue {
+    pub fn new(config: JobsConfig) -> Self {
+        Self {
+            config,
+            jobs: Mutex::default(),
+        }
+    }
+
+    fn lock(&self) -> MutexGuard<'_, Jobs> {
+        self.jobs.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    /// Queue a job, refusing it when the queue is full
+    pub fn submit(&self, submission: JobSubmission) -> Result<JobStatus, AppError> {
+        validate(&submission)?;
+        let mut jobs = self.lock();
+
+        // Forget jobs finished longer than the retention period ago
+        let retention = self.config.retention;
+        jobs.jobs
+            .retain(|_, job| job.finished.is_none_or(|at| at.elapsed() < retention));
+
+        if jobs.queue.len() >= self.config.max_queued {
+            return Err(AppError::RateLimited(format!(
+                "{} jobs are already queued",
+                jobs.queue.len()
+            )));
+        }
+
+        let id = Uuid::new_v4();
+        let status = JobStatus {
+            id: id.to_string(),
+            kind: submission.kind,
+            priority: submission.priority,
+            state: JobState::Queued,
+            submitted_at: Utc::now(),
+            started_at: None,
+            finished_at: None,
+            error: None,
+        };
+        jobs.sequence += 1;
+        let queued = Queued {
+            priority: submission.priority,
+            sequence: jobs.sequence,
+            id,
+        };
+        jobs.queue.push(queued);
+        jobs.jobs.insert(
+            id,
+            Job {
+                status: watch::channel(status.clone()).0,
+                submission: Some(submission),
+                result: None,
+                task: None,
+                finished: None,
+            },
+        );
+        Ok(status)
+    }
+
+    /// Counts of jobs and the queue limits
+    pub fn stats(&self) -> JobStats {
+        let jobs = self.lock();
+        JobStats {
+            queued: jobs.queue.len(),
+            running: jobs.running,
+            retained: jobs.jobs.len(),
+            workers: self.config.workers,
+            max_queued: self.config.max_queued,
+        }
+    }
+
+    /// Current status of a job
+    pub fn status(&self, id: Uuid) -> Result<JobStatus, AppError> {
+        Ok(self.lock().get(id)?.status.borrow().clone())
+    }
+
+    /// Cancel a queued or running job
+    pub fn cancel(&self, id: Uuid) -> Result<JobStatus, AppError> {
+        let mut jobs = self.lock();
+        let job = jobs.get(id)?;
+        if let Some(task) = job.task.take() {
+            task.abort();
+        }
+        jobs.finish(id, JobState::Cancelled, None);
+        jobs.queue.retain(|queued| queued.id != id);
+        let status = jobs.get(id)?.status.borrow().clone();
+        Ok(status)
+    }
+
+    fn subscribe(&self, id: Uuid) -> Result<watch::Receiver<JobStatus>, AppError> {
+        Ok(self.lock().get(id)?.status.subscribe())
+    }
+
+    fn result(&self, id: Uuid) -> Result<(JobStatus, Option<Result<Value, JobError>>), AppError> {
+        let mut jobs = self.lock();
+        let job = jobs.get(id)?;
+        let status = job.status.borrow().clone();
+        Ok((status, job.result.clone()))
+    }
+
+    /// Take the next job off the queue if a worker is free
+    fn start_next(&self) -> Option<(Uuid, JobSubmission)> {
+        let mut jobs = self.lock();
+        while jobs.running < self.config.workers.max(1) {
+            let queued = jobs.queue.pop()?;
+            let Some(job) = jobs.jobs.get_mut(&queued.id) else {
+                continue;
+            };
+            let Some(submission) = job.submission.take() else {
+                continue;
+            };
+            job.status.send_modify(|status| {
+                status.state = JobState::Running;
+                status.started_at = Some(Utc::now());
+            });
+            jobs.running += 1;
+            return Some((queued.id, submission));
+        }
+        None
+    }
+
+    fn set_task(&self, id: Uuid, task: AbortHandle) {
+        let mut jobs = self.lock();
+        match jobs.jobs.get_mut(&id) {
+            Some(job) if job.status.borrow().state == JobState::Running => job.task = Some(task),
+            _ => task.abort(),
+        }
+    }
+
+    fn complete(&self, id: Uuid, result: Result<Value, AppError>) {
+        let state = match result {
+            Ok(_) => JobState::Succeeded,
+            Err(_) => JobState::Failed,
+        };
+        self.lock()
+            .finish(id, state, Some(result.map_err(JobError::from)));
+    }
+}
+
+/// Check that a submission's request is valid for its kind
+fn validate(submission: &JobSubmission) -> Result<(), AppError> {
+    let request = submission.request.clone();
+    let valid = match submission.kind {
+        JobKind::CrossSection => serde_json::from_value::<CrossSectionRequest>(request).map(drop),
+        JobKind::Trajectories => serde_json::from_value::<TrajectoryRequest>(request).map(drop),
+        JobKind::Sample => serde_json::from_value::<SampleRequest>(request).map(drop),
+    };
+    valid.map_err(|e| AppError::RequestError(format!("Invalid job request: {}", e)))
+}
+
+/// Run an analysis with the handler of its route
+async fn run(state: Arc<AppState>, submission: JobSubmission) -> Result<Value, AppError> {
+    let request = submission.request;
+    let invalid = |e: serde_json::Error| AppError::RequestError(e.to_string());
+    let output = match submission.kind {
+        JobKind::CrossSection => {
+            let request = serde_json::from_value(request).map_err(invalid)?;
+            serde_json::to_value(cross_section(State(state), Json(request)).await?.0)
+        }
+        JobKind::Trajectories => {
+            let request = serde_json::from_value(request).map_err(invalid)?;
+            Ok(trajectories(State(state), Json(request)).await?.0)
+        }
+        JobKind::Sample => {
+            let body = Bytes::from(request.to_string());
+            let response = sample(
+                State(state),
+                Query(Default::default()),
+                HeaderMap::new(),
+                body,
+            )
+            .await?;
+            serde_json::to_value(response.0)
+        }
+    };
+    output.map_err(|e| AppError::ConfigError(format!("Cannot encode job result: {}", e)))
+}
+
+/// Start queued jobs while workers are free
+fn dispatch(state: &Arc<AppState>) {
+    while let Some((id, submission)) = state.jobs.start_next() {
+        info!("Starting {:?} job {}", submission.kind, id);
+        let task_state = state.clone();
+        let task = tokio::spawn(async move {
+            let start_time = Instant::now();
+            let result = run(task_state.clone(), submission).await;
+            info!(
+                "Job {} {} in {}ms",
+                id,
+                if result.is_ok() {
+                    "succeeded"
+                } else {
+                    "failed"
+                },
+                start_time.elapsed().as_millis()
+            );
+            task_state.jobs.complete(id, result);
+            dispatch(&task_state);
+        });
+        state.jobs.set_task(id, task.abort_handle());
+    }
+}
+
+fn parse_id(id: &str) -> Result<Uuid, AppError> {
+    id.parse()
+        .map_err(|_| AppError::NotFound(format!("No job {}", id)))
+}
+
+/// Handler for `POST /api/jobs`
+#[utoipa::path(
+    post,
+    path = "/api/jobs",
+    tag = "jobs",
+    summary = "Queue an analysis to run in the background",
+    request_body = JobSubmission,
+    responses(
+        (
+            status = 202,
+            description = "Queued; `Location` is the job's URL",
+            body = JobStatus,
+            headers(("Location" = String, description = "URL of the job"))
+        ),
+        (status = 400, description = "Invalid analysis request", body = ErrorBody),
+        (status = 429, description = "The queue is full", body = ErrorBody),
+    )
+)]
+#[instrument(skip_all)]
+pub async fn submit_job(
+    State(state): State<Arc<AppState>>,
+    Json(submission): Json<JobSubmission>,
+) -> Result<Response, AppError> {
+    let status = state.jobs.submit(submission)?;
+    dispatch(&state);
+    let status = parse_id(&status.id)
+        .and_then(|id| state.jobs.status(id))
+        .unwrap_or(status);
+    Ok((
+        StatusCode::ACCEPTED,
+        [(header::LOCATION, format!("/api/jobs/{}", status.id))],
+        Json(status),
+    )
+        .into_response())
+}
+
+/// Handler for `GET /api/jobs/{id}`
+#[utoipa::path(
+    get,
+    path = "/api/jobs/{id}",
+    tag = "jobs",
+    summary = "Status of a job",
+    params(("id" = String, Path, description = "Job ID")),
+    responses(
+        (status = 200, body = JobStatus),
+        (status = 404, description = "No such job", body = ErrorBody),
+    )
+)]
+pub async fn job_status(
+    State(state): State<Arc<AppState>>,
+    Path(id): Path<String>,
+) -> Result<Json<JobStatus>, AppError> {
+    Ok(Json(state.jobs.status(parse_id(&id)?)?))
+}
+
+/// Handler for `DELETE /api/jobs/{id}`
+#[utoipa::path(
+    delete,
+    path = "/api/jobs/{id}",
+    tag = "jobs",
+    summary = "Cancel a job",
+    params(("id" = String, Path, description = "Job ID")),
+    responses(
+        (status = 200, body = JobStatus),
+        (status = 404, description = "No such job", body = ErrorBody),
+    )
+)]
+pub async fn cancel_job(
+    State(state): State<Arc<AppState>>,
+    Path(id): Path<String>,
+) -> Result<Json<JobStatus>, AppError> {
+    let status = state.jobs.cancel(parse_id(&id)?)?;
+    dispatch(&state);
+    Ok(Json(status))
+}
+
+/// Handler for `GET /api/jobs/{id}/result`
+///
+/// Serves the analysis output of a succeeded job, the error response of a
+/// failed one, and `202 Accepted` with the status while the job is pending.
+#[utoipa::path(
+    get,
+    path = "/api/jobs/{id}/result",
+    tag = "jobs",
+    summary = "Output of a job",
+    params(("id" = String, Path, description = "Job ID")),
+    responses(
+        (
+            status = 200,
+            description = "The response the analysis route would have sent",
+            body = serde_json::Value
+        ),
+        (status = 202, description = "The job is still queued or running", body = JobStatus),
+        (status = 404, description = "No such job, or it was cancelled", body = ErrorBody),
+        (status = "4XX", description = "The job failed; the error it failed with", body = ErrorBody),
+        (status = "5XX", description = "The job failed; the error it failed with", body = ErrorBody),
+    )
+)]
+pub async fn job_result(
+    State(state): State<Arc<AppState>>,
+    Path(id): Path<String>,
+) -> Result<Response, AppError> {
+    let (status, result) = state.jobs.result(parse_id(&id)?)?;
+    Ok(match result {
+        Some(Ok(output)) => Json(output).into_response(),
+        Some(Err(error)) => (error.status, Json(json!({"error": error.message}))).into_response(),
+        None if status.state == JobState::Cancelled => {
+            return Err(AppError::NotFound(format!("Job {} was cancelled", id)))
+        }
+        None => (StatusCode::ACCEPTED, Json(status)).into_response(),
+    })
+}
+
+/// Handler for `GET /api/jobs/{id}/events`
+///
+/// Streams a `status` event with the job status now and at every change,
+/// ending after the job finishes.
+#[utoipa::path(
+    get,
+    path = "/api/jobs/{id}/events",
+    tag = "jobs",
+    summary = "Status changes of a job, as server-sent events",
+    params(("id" = String, Path, description = "Job ID")),
+    responses(
+        (
+            status = 200,
+            description = "A `status` event now and at every change, until the job finishes",
+            content_type = "text/event-stream",
+            body = String
+        ),
+        (status = 404, description = "No such job", body = ErrorBody),
+    )
+)]
+pub async fn job_events(
+    State(state): State<Arc<AppState>>,
+    Path(id): Path<String>,
+) -> Result<Sse<impl Stream<Item = Result<Event, Infallible>>>, AppError> {
+    let receiver = state.jobs.subscribe(parse_id(&id)?)?;
+    let events = futures::stream::unfold(Some((receiver, true)), |next| async move {
+        let (mut receiver, first) = next?;
+        if !first && receiver.changed().await.is_err() {
+            return None;
+        }
+        let status = receiver.borrow_and_update().clone();
+        let event = Event::default()
+            .event("status")
+            .data(serde_json::to_string(&status).unwrap_or_default());
+        let next = (!status.state.is_finished()).then_some((receiver, false));
+        Some((Ok(event), next))
+    });
+    Ok(Sse::new(events).keep_alive(KeepAlive::default()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn submission(priority: JobPriority) -> JobSubmission {
+        JobSubmission {
+            kind: JobKind::Sample,
+            request: json!({"points": [{"lat": 0.0, "lon": 0.0}], "vars": ["t2m"]}),
+            priority,
+        }
+    }
+
+    #[test]
+    fn test_priority_order_and_limits() {
+        let queue = JobQueue::new(JobsConfig {
+            workers: 1,
+            max_queued: 3,
+            ..JobsConfig::default()
+        });
+        let submit = |priority| {
+            let status = queue.submit(submission(priority)).unwrap();
+            parse_id(&status.id).unwrap()
+        };
+        let low = submit(JobPriority::Low);
+        let normal = submit(JobPriority::Normal);
+        let high = submit(JobPriority::High);
+        assert!(matches!(
+            queue.submit(submission(JobPriority::High)),
+            Err(AppError::RateLimited(_))
+        ));
+
+        assert_eq!(queue.start_next().unwrap().0, high);
+        // The only worker is busy
+        assert!(queue.start_next().is_none());
+        queue.complete(high, Ok(json!(1)));
+        assert_eq!(queue.status(high).unwrap().state, JobState::Succeeded);
+
+        queue.cancel(normal).unwrap();
+        assert_eq!(queue.start_next().unwrap().0, low);
+        assert_eq!(queue.status(low).unwrap().state, JobState::Running);
+        assert!(queue.start_next().is_none());
+    }
+
+    #[test]
+    fn test_invalid_requests_are_refused() {
+        let queue = JobQueue::default();
+        let result = queue.submit(JobSubmission {
+            kind: JobKind::CrossSection,
+            request: json!({"path": "nowhere"}),
+            priority: JobPriority::Normal,
+        });
+        assert!(matches!(result, Err(AppError::RequestError(_))));
+    }
+}
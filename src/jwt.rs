@@ -0,0 +1,307 @@
+//! Bearer tokens from an OpenID Connect provider
+//!
+//! With `--jwt-issuer https://sso.example.com/realms/weather`, the data
+//! routes accept JSON Web Tokens the provider signed, such as the access
+//! tokens Keycloak issues. The signing keys are read from the provider's JWKS,
+//! found through its discovery document or named with `--jwt-jwks-url`, and
+//! fetched again every `--jwt-jwks-refresh` seconds and whenever a token names
+//! a key not seen yet, so rotated keys are picked up. A token must carry an
+//! `exp` claim and, when configured, the issuer and one of the
+//! `--jwt-audience` values. Only asymmetric signatures are accepted.
+
+use jsonwebtoken::{
+    decode, decode_header,
+    jwk::{Jwk, JwkSet},
+    Algorithm, DecodingKey, Validation,
+};
+use serde::Deserialize;
+use std::time::{Duration, Instant};
+use tokio::sync::{Mutex, OnceCell, RwLock};
+use tracing::{info, warn};
+
+use crate::error::AppError;
+
+/// How often the signing keys are fetched by default
+pub const DEFAULT_JWKS_REFRESH: Duration = Duration::from_secs(3600);
+/// Shortest time between fetches for tokens naming unknown keys, so forged
+/// key IDs cannot make the server hammer the provider
+const MIN_REFETCH_INTERVAL: Duration = Duration::from_secs(60);
+/// Clock skew allowed when checking `exp` and `nbf`, in seconds
+const LEEWAY: u64 = 60;
+/// Timeout of requests to the provider
+const FETCH_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Signatures accepted; shared-secret algorithms would let anyone holding
+/// a symmetric key from the JWKS sign tokens
+const ALGORITHMS: [Algorithm; 9] = [
+    Algorithm::RS256,
+    Algorithm::RS384,
+    Algorithm::RS512,
+    Algorithm::PS256,
+    Algorithm::PS384,
+    Algorithm::PS512,
+    Algorithm::ES256,
+    Algorithm::ES384,
+    Algorithm::EdDSA,
+];
+
+/// Where tokens come from and whom they must be for
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct JwtConfig {
+    /// Required `iss` claim, and where the discovery document is found
+    pub issuer: Option<String>,
+    /// JWKS with the signing keys, discovered from the issuer when unset
+    pub jwks_url: Option<String>,
+    /// Accepted `aud` claims; any audience is accepted when empty
+    pub audiences: Vec<String>,
+    /// How often the signing keys are fetched again
+    pub jwks_refresh: Duration,
+}
+
+impl Default for JwtConfig {
+    fn default() -> Self {
+        Self {
+            issuer: None,
+            jwks_url: None,
+            audiences: Vec::new(),
+            jwks_refresh: DEFAULT_JWKS_REFRESH,
+        }
+    }
+}
+
+impl JwtConfig {
+    pub fn is_enabled(&self) -> bool {
+        self.issuer.is_some() || self.jwks_url.is_some()
+    }
+}
+
+/// Claims of a valid token the server looks at
+#[derive(Debug, Clone, Deserialize)]
+pub struct Claims {
+    /// Who the token was issued to
+    pub sub: Option<String>,
+}
+
+#[derive(Debug)]
+struct Keys {
+    set: JwkSet,
+    fetched: Instant,
+}
+
+#[derive(Deserialize)]
+struct Discovery {
+    jwks_uri: String,
+}
+
+/// Checks tokens against the provider's current signing keys
+#[derive(Debug)]
+pub struct JwtValidator {
+    config: JwtConfig,
+    /// Separate from the backend client, whose credentials and headers must
+    /// not reach third parties
+    client: reqwest::Client,
+    jwks_url: OnceCell<String>,
+    keys: RwLock<Option<Keys>>,
+    /// Held while fetching, so concurrent requests fetch once
+    fetching: Mutex<()>,
+}
+
+impl JwtValidator {
+    pub fn new(config: JwtConfig) -> Result<Self, AppError> {
+        let client = reqwest::Client::builder()
+            .timeout(FETCH_TIMEOUT)
+            .user_agent(concat!("rossby-vis/", env!("CARGO_PKG_VERSION")))
+            .build()
+            .map_err(|e| AppError::ConfigError(format!("Cannot create JWKS client: {}", e)))?;
+        let jwks_url = OnceCell::new_with(config.jwks_url.clone());
+        Ok(Self {
+            config,
+            client,
+            jwks_url,
+            keys: RwLock::new(None),
+            fetching: Mutex::new(()),
+        })
+    }
+
+    /// The claims of `token`, if a current key signed it and its claims pass
+    ///
+    /// Fails with [`AppError::Unauthorized`] for tokens that do not pass, and
+    /// with [`AppError::ProxyError`] when the keys cannot be fetched.
+    pub async fn validate(&self, token: &str) -> Result<Claims, AppError> {
+        let invalid = |reason: String| AppError::Unauthorized(format!("invalid token: {}", reason));
+        let header = decode_header(token).map_err(|e| invalid(e.to_string()))?;
+        if !ALGORITHMS.contains(&header.alg) {
+            return Err(invalid(format!(
+                "{:?} signatures are not accepted",
+                header.alg
+            )));
+        }
+
+        let jwk = self
+            .key(header.kid.as_deref())
+            .await?
+            .ok_or_else(|| invalid("signed with an unknown key".to_string()))?;
+        let key = DecodingKey::from_jwk(&jwk).map_err(|e| invalid(e.to_string()))?;
+
+        let mut validation = Validation::new(header.alg);
+        validation.leeway = LEEWAY;
+        validation.validate_nbf = true;
+        if let Some(issuer) = &self.config.issuer {
+            validation.set_issuer(&[issuer]);
+        }
+        if self.config.audiences.is_empty() {
+            validation.validate_aud = false;
+        } else {
+            validation.set_audience(&self.config.audiences);
+        }
+        decode::<Claims>(token, &key, &validation)
+            .map(|data| data.claims)
+            .map_err(|e| invalid(e.to_string()))
+    }
+
+    /// The signing key `kid`, or the only key when the token names none
+    async fn key(&self, kid: Option<&str>) -> Result<Option<Jwk>, AppError> {
+        let find = |keys: &Keys| match kid {
+            Some(kid) => keys.set.find(kid).cloned(),
+            None if keys.set.keys.len() == 1 => keys.set.keys.first().cloned(),
+            None => None,
+        };
+
+        if let Some(keys) = &*self.keys.read().await {
+            let stale = keys.fetched.elapsed() >= self.config.jwks_refresh;
+            let found = find(keys);
+            if !stale && (found.is_some() || keys.fetched.elapsed() < MIN_REFETCH_INTERVAL) {
+                return Ok(found);
+            }
+        }
+
+        let _fetching = self.fetching.lock().await;
+        // Another request may have fetched the keys while this one waited
+        if let Some(keys) = &*self.keys.read().await {
+            if keys.fetched.elapsed() < MIN_REFETCH_INTERVAL {
+                return Ok(find(keys));
+            }
+        }
+        match self.fetch_keys().await {
+            Ok(set) => {
+                let keys = Keys {
+                    set,
+                    fetched: Instant::now(),
+                };
+                let found = find(&keys);
+                *self.keys.write().await = Some(keys);
+                Ok(found)
+            }
+            // Keep using the keys there are while the provider is unreachable
+            Err(e) => {
+                let mut keys = self.keys.write().await;
+                match keys.as_mut() {
+                    Some(keys) => {
+                        warn!("Cannot refresh the JWT signing keys: {}", e);
+                        // Tried again a minute later rather than a whole
+                        // refresh interval later
+                        let retry = self
+                            .config
+                            .jwks_refresh
+                            .saturating_sub(MIN_REFETCH_INTERVAL);
+                        keys.fetched = Instant::now()
+                            .checked_sub(retry)
+                            .unwrap_or_else(Instant::now);
+                        Ok(find(keys))
+                    }
+                    None => Err(e),
+                }
+            }
+        }
+    }
+
+    async fn fetch_keys(&self) -> Result<JwkSet, AppError> {
+        let url = self
+            .jwks_url
+            .get_or_try_init(|| self.discover_jwks_url())
+            .await?;
+        let set: JwkSet = self.fetch_json(url).await?;
+        info!("Fetched {} JWT signing key(s) from {}", set.keys.len(), url);
+        Ok(set)
+    }
+
+    /// The JWKS URL from the issuer's OpenID Connect discovery document
+    async fn discover_jwks_url(&self) -> Result<String, AppError> {
+        let issuer = self.config.issuer.as_deref().unwrap_or_default();
+        let url = format!(
+            "{}/.well-known/openid-configuration",
+            issuer.trim_end_matches('/')
+        );
+        let discovery: Discovery = self.fetch_json(&url).await?;
+        Ok(discovery.jwks_uri)
+    }
+
+    async fn fetch_json<T: serde::de::DeserializeOwned>(&self, url: &str) -> Result<T, AppError> {
+        let failed = |e: reqwest::Error| {
+            AppError::ProxyError(format!("cannot fetch JWT signing keys from {}: {}", url, e))
+        };
+        self.client
+            .get(url)
+            .send()
+            .await
+            .and_then(reqwest::Response::error_for_status)
+            .map_err(failed)?
+            .json()
+            .await
+            .map_err(failed)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use jsonwebtoken::{encode, EncodingKey, Header};
+
+    #[tokio::test]
+    async fn test_rejects_shared_secret_tokens() {
+        let validator = JwtValidator::new(JwtConfig {
+            jwks_url: Some("http://127.0.0.1:9/jwks".to_string()),
+            ..Default::default()
+        })
+        .unwrap();
+        let token = encode(
+            &Header::new(Algorithm::HS256),
+            &serde_json::json!({"sub": "mallory", "exp": 4102444800u64}),
+            &EncodingKey::from_secret(b"secret"),
+        )
+        .unwrap();
+        // Refused before any key is fetched
+        assert!(matches!(
+            validator.validate(&token).await,
+            Err(AppError::Unauthorized(_))
+        ));
+        assert!(matches!(
+            validator.validate("not a token").await,
+            Err(AppError::Unauthorized(_))
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_unreachable_provider() {
+        let validator = JwtValidator::new(JwtConfig {
+            jwks_url: Some("http://127.0.0.1:9/jwks".to_string()),
+            ..Default::default()
+        })
+        .unwrap();
+        let header = Header {
+            kid: Some("k1".to_string()),
+            ..Header::new(Algorithm::RS256)
+        };
+        let token = format!(
+            "{}.e30.c2ln",
+            base64::Engine::encode(
+                &base64::engine::general_purpose::URL_SAFE_NO_PAD,
+                serde_json::to_vec(&header).unwrap()
+            )
+        );
+        assert!(matches!(
+            validator.validate(&token).await,
+            Err(AppError::ProxyError(_))
+        ));
+    }
+}
@@ -0,0 +1,175 @@
+//! Vertical levels named in Earth's data paths
+//!
+//! Earth names its GFS files `{time}-{product}-{surface}-{level}-gfs-1.0.json`,
+//! where the surface and level are either `surface-level` or an isobaric
+//! level such as `isobaric-850hPa`. Isobaric levels are matched against the
+//! dataset's vertical coordinate, which may be stored in hPa or in Pa.
+
+use serde_json::Value;
+
+use crate::{error::AppError, grid::coordinate_values};
+
+/// Suffix of Earth's GFS file names
+const GFS_FILE_SUFFIX: &str = "-gfs-1.0.json";
+/// Largest difference, in hPa, for a coordinate value to match a level
+const LEVEL_TOLERANCE_HPA: f64 = 0.5;
+
+/// Level of an Earth data path
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum EarthLevel {
+    /// `surface-level`: single-level fields
+    Surface,
+    /// `isobaric-{n}hPa`: a pressure level, in hPa
+    Isobaric(f64),
+}
+
+impl EarthLevel {
+    /// Parse the surface and level segments of a path
+    pub fn parse(surface: &str, level: &str) -> Option<Self> {
+        match (surface, level) {
+            ("surface", "level") => Some(EarthLevel::Surface),
+            ("isobaric", level) => {
+                let hpa: f64 = level.strip_suffix("hPa")?.parse().ok()?;
+                (hpa.is_finite() && hpa > 0.0).then_some(EarthLevel::Isobaric(hpa))
+            }
+            _ => None,
+        }
+    }
+
+    pub fn is_isobaric(self) -> bool {
+        matches!(self, EarthLevel::Isobaric(_))
+    }
+}
+
+/// The parts of an Earth GFS file name
+#[derive(Debug, Clone, PartialEq)]
+pub struct EarthFileName {
+    /// `current` or the `hhmm` of a dated path
+    pub prefix: String,
+    /// `wind`, `temp` or a variable name
+    pub product: String,
+    pub level: EarthLevel,
+}
+
+impl EarthFileName {
+    /// Parse a file name such as `current-wind-isobaric-850hPa-gfs-1.0.json`
+    pub fn parse(file: &str) -> Option<Self> {
+        let name = file.strip_suffix(GFS_FILE_SUFFIX)?;
+        let (name, level) = name.rsplit_once('-')?;
+        let (name, surface) = name.rsplit_once('-')?;
+        let (prefix, product) = name.split_once('-')?;
+        if prefix.is_empty() || product.is_empty() {
+            return None;
+        }
+        Some(Self {
+            prefix: prefix.to_string(),
+            product: product.to_string(),
+            level: EarthLevel::parse(surface, level)?,
+        })
+    }
+}
+
+/// Factor converting the values of a vertical coordinate to hPa
+///
+/// Uses the coordinate's `units` attribute when the metadata has one, and
+/// otherwise treats the levels as hPa unless they are clearly Pa.
+fn hpa_per_unit(metadata: &Value, dimension: &str, levels: &[f64]) -> f64 {
+    let units = metadata
+        .get("variables")
+        .and_then(|v| v.get(dimension))
+        .and_then(|v| v.get("attributes"))
+        .and_then(|a| a.get("units"))
+        .and_then(Value::as_str)
+        .map(str::to_lowercase);
+    match units.as_deref() {
+        Some("pa") => 0.01,
+        Some("kpa") => 10.0,
+        Some("hpa" | "mb" | "mbar" | "millibar" | "millibars") => 1.0,
+        _ if levels.iter().any(|p| *p > 2000.0) => 0.01,
+        _ => 1.0,
+    }
+}
+
+/// The value of the `dimension` coordinate at `hpa`, in the coordinate's
+/// own units
+pub fn select_pressure_level(metadata: &Value, dimension: &str, hpa: f64) -> Result<f64, AppError> {
+    let levels = coordinate_values(metadata, dimension).unwrap_or_default();
+    let scale = hpa_per_unit(metadata, dimension, &levels);
+    levels
+        .iter()
+        .copied()
+        .find(|level| (level * scale - hpa).abs() <= LEVEL_TOLERANCE_HPA)
+        .ok_or_else(|| {
+            let available: Vec<String> = levels
+                .iter()
+                .map(|level| format!("{}hPa", level * scale))
+                .collect();
+            AppError::RequestError(format!(
+                "Level {}hPa is not available; the dataset has {}",
+                hpa,
+                if available.is_empty() {
+                    "no pressure levels".to_string()
+                } else {
+                    available.join(", ")
+                }
+            ))
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn test_parse_file_names() {
+        assert_eq!(
+            EarthFileName::parse("current-wind-isobaric-850hPa-gfs-1.0.json"),
+            Some(EarthFileName {
+                prefix: "current".to_string(),
+                product: "wind".to_string(),
+                level: EarthLevel::Isobaric(850.0),
+            })
+        );
+        let dated =
+            EarthFileName::parse("0300-total_precipitable_water-surface-level-gfs-1.0.json")
+                .unwrap();
+        assert_eq!(dated.prefix, "0300");
+        assert_eq!(dated.product, "total_precipitable_water");
+        assert_eq!(dated.level, EarthLevel::Surface);
+
+        assert_eq!(
+            EarthFileName::parse("current-wind-isobaric-850-gfs-1.0.json"),
+            None
+        );
+        assert_eq!(
+            EarthFileName::parse("current-wind-surface-850hPa-gfs-1.0.json"),
+            None
+        );
+        assert_eq!(
+            EarthFileName::parse("wind-surface-level-gfs-1.0.json"),
+            None
+        );
+    }
+
+    #[test]
+    fn test_select_pressure_level() {
+        let hpa = json!({"coordinates": {"level": [1000.0, 850.0, 500.0]}});
+        assert_eq!(select_pressure_level(&hpa, "level", 850.0).unwrap(), 850.0);
+
+        let pa = json!({"coordinates": {"plev": [100000.0, 85000.0, 50000.0]}});
+        assert_eq!(select_pressure_level(&pa, "plev", 500.0).unwrap(), 50000.0);
+
+        let labelled = json!({
+            "coordinates": {"level": [10.0, 1.0]},
+            "variables": {"level": {"attributes": {"units": "Pa"}}}
+        });
+        assert_eq!(
+            select_pressure_level(&labelled, "level", 0.1).unwrap(),
+            10.0
+        );
+
+        let error = select_pressure_level(&hpa, "level", 700.0).unwrap_err();
+        assert!(error.to_string().contains("1000hPa, 850hPa, 500hPa"));
+    }
+}
@@ -3,12 +3,85 @@
 //! This library provides a web server that embeds the Earth visualization frontend
 //! and serves as a streaming proxy to Rossby NetCDF data servers.
 
+pub mod access_log;
+#[cfg(feature = "acme")]
+pub mod acme;
+pub mod admin;
+pub mod analysis;
+pub mod auth;
+pub mod backend;
+pub mod build_info;
+pub mod cache;
+pub mod catalog;
+pub mod chaos;
+pub mod client;
+pub mod client_errors;
+pub mod concurrency;
+pub mod config;
+pub mod datasets;
+pub mod deadline;
+pub mod derived;
+pub mod dev_assets;
 pub mod embed;
+pub mod endpoint;
 pub mod error;
+pub mod error_tracking;
+pub mod expr;
+pub mod format;
+pub mod frames;
+pub mod freshness;
+pub mod geo;
+pub mod grid;
 pub mod handlers;
+pub mod health;
+pub mod hints;
+pub mod jobs;
+pub mod jwt;
+pub mod levels;
+pub mod limits;
+pub mod listen;
+pub mod log_file;
 pub mod logging;
+pub mod mask;
+pub mod metadata;
 pub mod middleware;
+pub mod openapi;
+pub mod oscar;
+pub mod packing;
+pub mod pipeline;
+pub mod plugins;
+pub mod private_file;
+pub mod products;
+pub mod reload;
+pub mod replay;
+pub mod resilience;
+pub mod retry;
+pub mod scheduler;
+#[cfg(feature = "scripting")]
+pub mod scripting;
 pub mod server;
+pub mod shedding;
+pub mod signing;
+pub mod site;
+pub mod slicing;
+pub mod statsd;
+#[cfg(feature = "sqlite")]
+pub mod store;
+pub mod syslog;
+pub mod systemd;
+#[cfg(feature = "testing")]
+pub mod testing;
+pub mod timesteps;
+pub mod tls;
+pub mod trace_context;
+pub mod trajectory;
+pub mod transforms;
+pub mod units;
+pub mod vectors;
+pub mod webhooks;
 
 pub use error::AppError;
-pub use server::{run_server, AppState};
+pub use server::{
+    build_admin_router, build_public_router, build_router, run_server, run_server_with_config,
+    AppState, ServerConfig, ServerConfigBuilder, ServerHandle,
+};
@@ -0,0 +1,168 @@
+//! Size limits of proxied requests
+//!
+//! The `/proxy/` routes pass their query strings on to the backend, which
+//! would otherwise be asked for whatever a buggy client or a scanner makes
+//! up: thousands of variables, or selectors many kilobytes long. Requests
+//! with a query string longer than `--max-query-length` bytes, more than
+//! `--max-vars` variables or headers larger than `--max-header-bytes` in
+//! total are answered with `400 Bad Request` and never reach the backend.
+//! A limit of 0 disables that check.
+
+use axum::{
+    extract::{Query, State},
+    http::Request,
+    middleware::Next,
+    response::{IntoResponse, Response},
+};
+use std::sync::Arc;
+use tracing::warn;
+
+use crate::{error::AppError, server::AppState};
+
+/// Longest query string accepted by default, in bytes
+pub const DEFAULT_MAX_QUERY_LENGTH: usize = 4096;
+/// Most variables accepted in `vars` by default
+pub const DEFAULT_MAX_VARS: usize = 32;
+/// Largest total size of the request headers accepted by default, in bytes
+pub const DEFAULT_MAX_HEADER_BYTES: usize = 16 * 1024;
+
+/// Limits of the requests passed on to the backend
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RequestLimits {
+    /// Longest query string, in bytes
+    pub max_query_length: usize,
+    /// Most variables requested with `vars`, counting every `vars` parameter
+    pub max_vars: usize,
+    /// Largest total size of the request headers, in bytes
+    pub max_header_bytes: usize,
+}
+
+impl Default for RequestLimits {
+    fn default() -> Self {
+        Self {
+            max_query_length: DEFAULT_MAX_QUERY_LENGTH,
+            max_vars: DEFAULT_MAX_VARS,
+            max_header_bytes: DEFAULT_MAX_HEADER_BYTES,
+        }
+    }
+}
+
+impl RequestLimits {
+    /// Check `request` against the limits
+    pub fn check<B>(&self, request: &Request<B>) -> Result<(), AppError> {
+        let exceeds = |limit: usize, value: usize| limit > 0 && value > limit;
+
+        let query = request.uri().query().unwrap_or_default();
+        if exceeds(self.max_query_length, query.len()) {
+            return Err(AppError::RequestError(format!(
+                "Query string of {} bytes is longer than the limit of {} bytes",
+                query.len(),
+                self.max_query_length
+            )));
+        }
+
+        let header_bytes: usize = request
+            .headers()
+            .iter()
+            // As sent on the wire: `name: value\r\n`
+            .map(|(name, value)| name.as_str().len() + value.len() + 4)
+            .sum();
+        if exceeds(self.max_header_bytes, header_bytes) {
+            return Err(AppError::RequestError(format!(
+                "Request headers of {} bytes are larger than the limit of {} bytes",
+                header_bytes, self.max_header_bytes
+            )));
+        }
+
+        let params = Query::<Vec<(String, String)>>::try_from_uri(request.uri())
+            .map(|Query(params)| params)
+            .unwrap_or_default();
+        let vars = params
+            .iter()
+            .filter(|(name, _)| name == "vars")
+            .flat_map(|(_, value)| value.split(','))
+            .filter(|var| !var.trim().is_empty())
+            .count();
+        if exceeds(self.max_vars, vars) {
+            return Err(AppError::RequestError(format!(
+                "{} variables requested; at most {} are allowed per request",
+                vars, self.max_vars
+            )));
+        }
+        Ok(())
+    }
+}
+
+/// Reject proxy requests exceeding the configured limits
+pub async fn request_limits_middleware<B>(
+    State(state): State<Arc<AppState>>,
+    request: Request<B>,
+    next: Next<B>,
+) -> Response {
+    if !request.uri().path().starts_with("/proxy/") {
+        return next.run(request).await;
+    }
+    if let Err(error) = state.request_limits.check(&request) {
+        warn!(
+            path = request.uri().path(),
+            "Rejected oversized request: {}", error
+        );
+        return error.into_response();
+    }
+    next.run(request).await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn request(uri: &str) -> Request<()> {
+        Request::builder().uri(uri).body(()).unwrap()
+    }
+
+    #[test]
+    fn test_query_limits() {
+        let limits = RequestLimits {
+            max_query_length: 64,
+            max_vars: 2,
+            ..Default::default()
+        };
+        assert!(limits
+            .check(&request("/proxy/data?vars=u,v&time=0"))
+            .is_ok());
+        assert!(limits.check(&request("/proxy/data?vars=u,v,t")).is_err());
+        assert!(limits
+            .check(&request("/proxy/data?vars=u&vars=v,t"))
+            .is_err());
+        // Trailing commas do not count as variables
+        assert!(limits.check(&request("/proxy/data?vars=u,v,")).is_ok());
+
+        let long = format!("/proxy/data?vars=u&time_range={}", "0".repeat(64));
+        let error = limits.check(&request(&long)).unwrap_err().to_string();
+        assert!(error.contains("limit of 64 bytes"), "{}", error);
+
+        let unlimited = RequestLimits {
+            max_query_length: 0,
+            max_vars: 0,
+            max_header_bytes: 0,
+        };
+        assert!(unlimited.check(&request(&long)).is_ok());
+    }
+
+    #[test]
+    fn test_header_limit() {
+        let limits = RequestLimits {
+            max_header_bytes: 100,
+            ..Default::default()
+        };
+        let request = |cookie: &str| {
+            Request::builder()
+                .uri("/proxy/metadata")
+                .header("cookie", cookie)
+                .body(())
+                .unwrap()
+        };
+        assert!(limits.check(&request("a=b")).is_ok());
+        assert!(limits.check(&request(&"a".repeat(100))).is_err());
+    }
+}
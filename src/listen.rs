@@ -0,0 +1,231 @@
+//! Addresses the server listens on
+//!
+//! By default the server listens on `--bind-address` and `--port` only.
+//! `--listen` configures any number of listeners instead, each given as
+//! `ADDR[,tls][,admin]`:
+//!
+//! ```text
+//! --listen [::]:8080 --listen 0.0.0.0:8443,tls --listen 127.0.0.1:9090,admin
+//! ```
+//!
+//! `tls` serves HTTPS with the certificates of [`crate::tls`]. `admin` serves
+//! the admin page and endpoints alone; once a listener is dedicated to them,
+//! the others no longer serve them, so they can be kept on a local port.
+//!
+//! `--unix-socket` serves plain HTTP on a Unix domain socket instead, for a
+//! reverse proxy on the same host; TCP listeners are then only opened when
+//! `--listen` asks for them. The socket file is created with
+//! `--unix-socket-mode`, replaced if left over from an earlier run, and
+//! removed on shutdown.
+
+#[cfg(unix)]
+use std::{
+    ffi::OsString,
+    io,
+    os::unix::fs::{FileTypeExt, PermissionsExt},
+    path::{Path, PathBuf},
+};
+use std::{fmt, net::SocketAddr, str::FromStr};
+
+/// Permissions of the Unix socket: read and write for owner and group
+pub const DEFAULT_UNIX_SOCKET_MODE: u32 = 0o660;
+
+/// One address the server listens on
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ListenAddress {
+    pub addr: SocketAddr,
+    /// Serve HTTPS instead of plain HTTP
+    pub tls: bool,
+    /// Serve the admin page and endpoints only
+    pub admin: bool,
+}
+
+impl ListenAddress {
+    /// Plain HTTP with every route on `addr`
+    pub fn new(addr: SocketAddr) -> Self {
+        Self {
+            addr,
+            tls: false,
+            admin: false,
+        }
+    }
+}
+
+impl FromStr for ListenAddress {
+    type Err = String;
+
+    /// Parse `ADDR[,tls][,admin]`
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut parts = s.split(',').map(str::trim);
+        let addr = parts.next().unwrap_or_default();
+        let mut listener = Self::new(addr.parse().map_err(|_| {
+            format!(
+                "Invalid listen address: {}. Expected e.g. 0.0.0.0:8080 or [::]:8443,tls",
+                addr
+            )
+        })?);
+        for option in parts {
+            match option.to_lowercase().as_str() {
+                "tls" => listener.tls = true,
+                "admin" => listener.admin = true,
+                _ => {
+                    return Err(format!(
+                        "Invalid listen option '{}'. Valid options: tls, admin",
+                        option
+                    ))
+                }
+            }
+        }
+        Ok(listener)
+    }
+}
+
+impl fmt::Display for ListenAddress {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let scheme = if self.tls { "https" } else { "http" };
+        write!(f, "{}://{}", scheme, self.addr)?;
+        if self.admin {
+            write!(f, " (admin)")?;
+        }
+        Ok(())
+    }
+}
+
+/// Parse file permissions given in octal, e.g. `660`
+pub fn parse_socket_mode(s: &str) -> Result<u32, String> {
+    u32::from_str_radix(s.trim_start_matches("0o"), 8)
+        .ok()
+        .filter(|mode| *mode <= 0o777)
+        .ok_or_else(|| format!("Invalid socket mode: {}. Expected octal, e.g. 660", s))
+}
+
+/// Listen on the Unix socket at `path` with permissions `mode`
+///
+/// A socket file left at `path` by an earlier run is replaced; any other
+/// file is not. The socket is bound in a directory only the owner can
+/// enter and moved to `path` once it has its permissions, so no one else
+/// can connect in between.
+#[cfg(unix)]
+pub(crate) fn bind_unix(path: &Path, mode: u32) -> io::Result<tokio::net::UnixListener> {
+    use std::os::unix::fs::DirBuilderExt;
+
+    match std::fs::symlink_metadata(path) {
+        Ok(metadata) if metadata.file_type().is_socket() => {}
+        Ok(_) => {
+            return Err(io::Error::new(
+                io::ErrorKind::AlreadyExists,
+                format!("{} exists and is not a socket", path.display()),
+            ))
+        }
+        Err(e) if e.kind() == io::ErrorKind::NotFound => {}
+        Err(e) => return Err(e),
+    }
+
+    // A random name, as anyone may create files next to the socket
+    let mut private = OsString::from(".");
+    private.push(path.file_name().unwrap_or_default());
+    private.push(format!(
+        ".{}",
+        &uuid::Uuid::new_v4().simple().to_string()[..12]
+    ));
+    let private = path.with_file_name(private);
+    std::fs::DirBuilder::new().mode(0o700).create(&private)?;
+    let private = PrivateDir(private);
+
+    let staged = private.socket();
+    let listener = tokio::net::UnixListener::bind(&staged)?;
+    std::fs::set_permissions(&staged, std::fs::Permissions::from_mode(mode))?;
+    // Replaces a socket left over at `path` in one step
+    std::fs::rename(&staged, path)?;
+    Ok(listener)
+}
+
+/// A directory only the owner can enter, removed with the socket in it
+/// unless the socket was moved out
+#[cfg(unix)]
+struct PrivateDir(PathBuf);
+
+#[cfg(unix)]
+impl PrivateDir {
+    fn socket(&self) -> PathBuf {
+        self.0.join("socket")
+    }
+}
+
+#[cfg(unix)]
+impl Drop for PrivateDir {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_file(self.socket());
+        let _ = std::fs::remove_dir(&self.0);
+    }
+}
+
+/// Connections accepted on a Unix socket, for serving with hyper
+#[cfg(unix)]
+pub(crate) fn unix_incoming(
+    listener: tokio::net::UnixListener,
+) -> impl hyper::server::accept::Accept<Conn = tokio::net::UnixStream, Error = io::Error> {
+    hyper::server::accept::from_stream(futures::stream::poll_fn(move |cx| {
+        listener
+            .poll_accept(cx)
+            .map(|accepted| Some(accepted.map(|(stream, _)| stream)))
+    }))
+}
+
+/// Removes the socket file when the server on it stops
+#[cfg(unix)]
+pub(crate) struct UnixSocketFile(pub PathBuf);
+
+#[cfg(unix)]
+impl Drop for UnixSocketFile {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_file(&self.0);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_listen_address() {
+        assert_eq!(
+            "[::]:8080".parse(),
+            Ok(ListenAddress::new("[::]:8080".parse().unwrap()))
+        );
+
+        let listener: ListenAddress = "127.0.0.1:9443, TLS, admin".parse().unwrap();
+        assert_eq!(listener.addr, "127.0.0.1:9443".parse().unwrap());
+        assert!(listener.tls && listener.admin);
+        assert_eq!(listener.to_string(), "https://127.0.0.1:9443 (admin)");
+
+        assert!("localhost:8080".parse::<ListenAddress>().is_err());
+        assert!("0.0.0.0:8080,h2".parse::<ListenAddress>().is_err());
+    }
+
+    #[test]
+    fn test_parse_socket_mode() {
+        assert_eq!(parse_socket_mode("660"), Ok(0o660));
+        assert_eq!(parse_socket_mode("0o600"), Ok(0o600));
+        assert_eq!(parse_socket_mode("0777"), Ok(0o777));
+        assert!(parse_socket_mode("689").is_err());
+        assert!(parse_socket_mode("1777").is_err());
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn test_bind_unix_leaves_only_the_socket() {
+        let dir = std::env::temp_dir().join(format!("rossby-vis-{}", uuid::Uuid::new_v4()));
+        std::fs::create_dir(&dir).unwrap();
+        let path = dir.join("vis.sock");
+
+        let _listener = bind_unix(&path, 0o600).unwrap();
+
+        let metadata = std::fs::metadata(&path).unwrap();
+        assert!(metadata.file_type().is_socket());
+        assert_eq!(metadata.permissions().mode() & 0o777, 0o600);
+        // The directory it was bound in is gone
+        assert_eq!(std::fs::read_dir(&dir).unwrap().count(), 1);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
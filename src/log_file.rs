@@ -0,0 +1,241 @@
+//! Log file rotated by size as well as by time
+//!
+//! `tracing-appender` only rolls files over by time. With a size limit, the
+//! log file is written by [`SizeRotatingFile`] instead, which names files
+//! the same way, `rossby-vis.2025-06-23.log` for daily rotation, and moves a
+//! file that reaches the limit aside as `rossby-vis.2025-06-23.1.log`,
+//! `.2.log` and so on before starting a new one.
+
+use crate::logging::LogRotation;
+use chrono::Utc;
+use std::{
+    fs::{self, File, OpenOptions},
+    io::{self, Write},
+    path::{Path, PathBuf},
+};
+
+/// Writer of a log file rotated by time and size
+#[derive(Debug)]
+pub struct SizeRotatingFile {
+    directory: PathBuf,
+    prefix: String,
+    suffix: Option<String>,
+    rotation: LogRotation,
+    max_size: u64,
+    max_files: Option<usize>,
+    period: String,
+    file: File,
+    size: u64,
+}
+
+impl SizeRotatingFile {
+    /// Open the file of the current period in `directory`, appending to it
+    pub fn new(
+        directory: impl Into<PathBuf>,
+        prefix: impl Into<String>,
+        suffix: Option<String>,
+        rotation: LogRotation,
+        max_size: u64,
+        max_files: Option<usize>,
+    ) -> io::Result<Self> {
+        let directory = directory.into();
+        let prefix = prefix.into();
+        let period = current_period(rotation);
+        let path = directory.join(file_name(&prefix, &period, None, suffix.as_deref()));
+        let (file, size) = open(&path)?;
+        Ok(Self {
+            directory,
+            prefix,
+            suffix,
+            rotation,
+            max_size,
+            max_files,
+            period,
+            file,
+            size,
+        })
+    }
+
+    /// The file currently written to
+    pub fn path(&self) -> PathBuf {
+        self.path_of(None)
+    }
+
+    fn path_of(&self, index: Option<u32>) -> PathBuf {
+        self.directory.join(file_name(
+            &self.prefix,
+            &self.period,
+            index,
+            self.suffix.as_deref(),
+        ))
+    }
+
+    /// Start a new file when the period is over or `len` more bytes would
+    /// exceed the size limit
+    fn roll_over_if_needed(&mut self, len: usize) -> io::Result<()> {
+        let period = current_period(self.rotation);
+        if period != self.period {
+            self.period = period;
+        } else if self.size > 0 && self.size + len as u64 > self.max_size {
+            self.file.flush()?;
+            let index = (1..)
+                .find(|index| !self.path_of(Some(*index)).exists())
+                .expect("a free index");
+            fs::rename(self.path(), self.path_of(Some(index)))?;
+        } else {
+            return Ok(());
+        }
+        (self.file, self.size) = open(&self.path())?;
+        self.remove_old_files()
+    }
+
+    /// Delete the oldest files beyond `max_files`, counting the current one
+    fn remove_old_files(&self) -> io::Result<()> {
+        let Some(max_files) = self.max_files else {
+            return Ok(());
+        };
+        let current = self.path();
+        let start = format!("{}.", self.prefix);
+        let end = self
+            .suffix
+            .as_ref()
+            .map(|suffix| format!(".{}", suffix))
+            .unwrap_or_default();
+        let mut files = Vec::new();
+        for entry in fs::read_dir(&self.directory)? {
+            let entry = entry?;
+            let name = entry.file_name().to_string_lossy().into_owned();
+            let is_log =
+                entry.path() == current || (name.starts_with(&start) && name.ends_with(&end));
+            if is_log && entry.file_type()?.is_file() {
+                files.push((entry.metadata()?.modified()?, entry.path()));
+            }
+        }
+        files.sort_by(|a, b| b.cmp(a));
+        for (_, path) in files.into_iter().skip(max_files) {
+            if path != current {
+                fs::remove_file(path)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+impl Write for SizeRotatingFile {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.roll_over_if_needed(buf.len())?;
+        let written = self.file.write(buf)?;
+        self.size += written as u64;
+        Ok(written)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.file.flush()
+    }
+}
+
+fn open(path: &Path) -> io::Result<(File, u64)> {
+    let file = OpenOptions::new().create(true).append(true).open(path)?;
+    let size = file.metadata()?.len();
+    Ok((file, size))
+}
+
+/// The date, or date and hour, in file names, as `tracing-appender` has it
+fn current_period(rotation: LogRotation) -> String {
+    let now = Utc::now();
+    match rotation {
+        LogRotation::Hourly => now.format("%Y-%m-%d-%H").to_string(),
+        LogRotation::Daily => now.format("%Y-%m-%d").to_string(),
+        LogRotation::Never => String::new(),
+    }
+}
+
+fn file_name(prefix: &str, period: &str, index: Option<u32>, suffix: Option<&str>) -> String {
+    let index = index.map(|index| index.to_string());
+    [Some(prefix), Some(period), index.as_deref(), suffix]
+        .into_iter()
+        .flatten()
+        .filter(|part| !part.is_empty())
+        .collect::<Vec<_>>()
+        .join(".")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn temp_dir() -> PathBuf {
+        let directory =
+            std::env::temp_dir().join(format!("rossby-vis-log-file-{}", uuid::Uuid::new_v4()));
+        fs::create_dir_all(&directory).unwrap();
+        directory
+    }
+
+    #[test]
+    fn test_file_name() {
+        assert_eq!(
+            file_name("rossby-vis", "2025-06-23", None, Some("log")),
+            "rossby-vis.2025-06-23.log"
+        );
+        assert_eq!(
+            file_name("rossby-vis", "2025-06-23", Some(2), Some("log")),
+            "rossby-vis.2025-06-23.2.log"
+        );
+        assert_eq!(file_name("rossby-vis", "", Some(1), None), "rossby-vis.1");
+    }
+
+    #[test]
+    fn test_rolls_over_at_size_limit() {
+        let directory = temp_dir();
+        let mut file = SizeRotatingFile::new(
+            &directory,
+            "rossby-vis",
+            Some("log".to_string()),
+            LogRotation::Never,
+            10,
+            None,
+        )
+        .unwrap();
+
+        file.write_all(b"12345678\n").unwrap();
+        file.write_all(b"abc\n").unwrap();
+        file.write_all(b"defghijk\n").unwrap();
+        file.flush().unwrap();
+
+        let read = |name: &str| fs::read_to_string(directory.join(name)).unwrap();
+        assert_eq!(read("rossby-vis.1.log"), "12345678\n");
+        assert_eq!(read("rossby-vis.2.log"), "abc\n");
+        assert_eq!(read("rossby-vis.log"), "defghijk\n");
+        fs::remove_dir_all(directory).unwrap();
+    }
+
+    #[test]
+    fn test_keeps_max_files() {
+        let directory = temp_dir();
+        let mut file = SizeRotatingFile::new(
+            &directory,
+            "rossby-vis",
+            Some("log".to_string()),
+            LogRotation::Daily,
+            4,
+            Some(2),
+        )
+        .unwrap();
+
+        for line in ["one\n", "two\n", "six\n"] {
+            file.write_all(line.as_bytes()).unwrap();
+            // Modification times order the files
+            std::thread::sleep(std::time::Duration::from_millis(20));
+        }
+
+        let mut names: Vec<_> = fs::read_dir(&directory)
+            .unwrap()
+            .map(|entry| entry.unwrap().file_name().into_string().unwrap())
+            .collect();
+        names.sort();
+        assert_eq!(names.len(), 2, "{:?}", names);
+        assert_eq!(fs::read_to_string(file.path()).unwrap(), "six\n");
+        assert!(names.iter().any(|name| name.ends_with(".2.log")));
+        fs::remove_dir_all(directory).unwrap();
+    }
+}
@@ -3,14 +3,37 @@
 //! This module provides structured logging, request tracing, metrics collection,
 //! and observability features suitable for production deployments.
 
+use serde::Serialize;
+use std::{
+    io::Write,
+    path::PathBuf,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc, Mutex, OnceLock, RwLock,
+    },
+    time::{Duration, Instant},
+};
 use tracing::info;
+use tracing_appender::{
+    non_blocking::{NonBlockingBuilder, WorkerGuard},
+    rolling::{RollingFileAppender, Rotation},
+};
 use tracing_subscriber::{
     fmt::{self, time::ChronoUtc},
     layer::SubscriberExt,
+    reload,
     util::SubscriberInitExt,
     EnvFilter, Layer, Registry,
 };
 
+use crate::{
+    access_log::{self, AccessLogConfig},
+    log_file::SizeRotatingFile,
+    shedding,
+    statsd::{self, StatsdConfig},
+    syslog::{parse_facility, SyslogConfig, SyslogWriter},
+};
+
 /// Logging output format options
 #[derive(Debug, Clone, Copy)]
 pub enum LogFormat {
@@ -38,38 +61,208 @@ impl std::str::FromStr for LogFormat {
     }
 }
 
+/// How often the log file is rotated
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LogRotation {
+    Hourly,
+    Daily,
+    /// Keep appending to a single file
+    Never,
+}
+
+impl std::str::FromStr for LogRotation {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "hourly" => Ok(LogRotation::Hourly),
+            "daily" => Ok(LogRotation::Daily),
+            "never" => Ok(LogRotation::Never),
+            _ => Err(format!(
+                "Invalid log rotation: {}. Valid options: hourly, daily, never",
+                s
+            )),
+        }
+    }
+}
+
+/// Transport of the OTLP trace exporter
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum OtlpProtocol {
+    /// gRPC, usually on port 4317
+    #[default]
+    Grpc,
+    /// Protobuf over HTTP, usually on port 4318; `/v1/traces` is appended
+    /// to the endpoint
+    Http,
+}
+
+impl std::str::FromStr for OtlpProtocol {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "grpc" => Ok(OtlpProtocol::Grpc),
+            "http" | "http/protobuf" => Ok(OtlpProtocol::Http),
+            _ => Err(format!(
+                "Invalid OTLP protocol: {}. Valid options: grpc, http",
+                s
+            )),
+        }
+    }
+}
+
+/// Parse the fraction of traces to sample, between 0 and 1
+pub fn parse_sample_ratio(s: &str) -> Result<f64, String> {
+    s.parse::<f64>()
+        .ok()
+        .filter(|ratio| (0.0..=1.0).contains(ratio))
+        .ok_or_else(|| format!("Invalid trace sample ratio: {}. Expected 0 to 1", s))
+}
+
+/// JSON log file settings
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FileLogConfig {
+    /// Log file path; rotated files insert the date before the extension,
+    /// e.g. `rossby-vis.2025-06-23.log`
+    pub path: PathBuf,
+    pub rotation: LogRotation,
+    /// Delete the oldest rotated files beyond this many
+    pub max_files: Option<usize>,
+    /// Also rotate a file once it reaches this many bytes
+    pub max_size: Option<u64>,
+}
+
+impl FileLogConfig {
+    /// Daily rotation without a retention limit
+    pub fn new(path: impl Into<PathBuf>) -> Self {
+        Self {
+            path: path.into(),
+            rotation: LogRotation::Daily,
+            max_files: None,
+            max_size: None,
+        }
+    }
+
+    /// Create the log directory and the rotating appender
+    fn appender(&self) -> Result<Box<dyn Write + Send>, String> {
+        let directory = self
+            .path
+            .parent()
+            .filter(|p| !p.as_os_str().is_empty())
+            .map(PathBuf::from)
+            .unwrap_or_else(|| PathBuf::from("."));
+        let prefix = self
+            .path
+            .file_stem()
+            .and_then(|s| s.to_str())
+            .filter(|s| !s.is_empty())
+            .ok_or_else(|| format!("Invalid log file path: {}", self.path.display()))?;
+
+        std::fs::create_dir_all(&directory).map_err(|e| {
+            format!(
+                "Failed to create log directory {}: {}",
+                directory.display(),
+                e
+            )
+        })?;
+        let open_error = |e: &dyn std::fmt::Display| {
+            format!("Failed to open log file {}: {}", self.path.display(), e)
+        };
+        let extension = self.path.extension().and_then(|s| s.to_str());
+
+        if let Some(max_size) = self.max_size {
+            let file = SizeRotatingFile::new(
+                directory,
+                prefix,
+                extension.map(str::to_string),
+                self.rotation,
+                max_size,
+                self.max_files,
+            )
+            .map_err(|e| open_error(&e))?;
+            return Ok(Box::new(file));
+        }
+
+        let mut builder = RollingFileAppender::builder()
+            .rotation(match self.rotation {
+                LogRotation::Hourly => Rotation::HOURLY,
+                LogRotation::Daily => Rotation::DAILY,
+                LogRotation::Never => Rotation::NEVER,
+            })
+            .filename_prefix(prefix);
+        if let Some(extension) = extension {
+            builder = builder.filename_suffix(extension);
+        }
+        if let Some(max_files) = self.max_files {
+            builder = builder.max_log_files(max_files);
+        }
+        let appender = builder.build(&directory).map_err(|e| open_error(&e))?;
+        Ok(Box::new(appender))
+    }
+}
+
 /// Logging configuration for production deployment
 #[derive(Debug, Clone)]
 pub struct LoggingConfig {
     /// Log level filter (e.g., "info", "debug", "warn")
     pub level: String,
+    /// Per-target levels overriding `level`, as `(target, level)` pairs,
+    /// e.g. `("proxy", "debug")` or `("hyper", "error")`
+    pub targets: Vec<(String, String)>,
     /// Output format
     pub format: LogFormat,
     /// Enable request tracing with correlation IDs
     pub enable_request_tracing: bool,
     /// Enable system metrics logging
     pub enable_metrics: bool,
-    /// Enable distributed tracing (Jaeger)
+    /// Enable distributed tracing (requires the `distributed-tracing` feature)
     pub enable_distributed_tracing: bool,
-    /// Jaeger endpoint for distributed tracing
-    pub jaeger_endpoint: Option<String>,
+    /// OTLP collector receiving traces, e.g. `http://otel-collector:4317`
+    pub otlp_endpoint: Option<String>,
+    /// Transport used to export traces
+    pub otlp_protocol: OtlpProtocol,
+    /// Fraction of traces sampled, decided at their root span
+    pub trace_sample_ratio: f64,
     /// Application name for tracing
     pub service_name: String,
     /// Environment name (development, staging, production)
     pub environment: String,
+    /// Sentry DSN for reporting server errors and panics
+    /// (requires the `error-tracking` feature)
+    pub sentry_dsn: Option<String>,
+    /// Also send logs to syslog
+    pub syslog: Option<SyslogConfig>,
+    /// Also write JSON logs to a rotating file
+    pub file: Option<FileLogConfig>,
+    /// Push metrics to a StatsD agent
+    pub statsd: Option<StatsdConfig>,
+    /// Also write one Apache-style line per request
+    pub access_log: Option<AccessLogConfig>,
+    /// Log a summary of activity since startup this often
+    pub heartbeat_interval: Option<Duration>,
 }
 
 impl Default for LoggingConfig {
     fn default() -> Self {
         Self {
             level: "info".to_string(),
+            targets: Vec::new(),
             format: LogFormat::Text,
             enable_request_tracing: true,
             enable_metrics: true,
             enable_distributed_tracing: false,
-            jaeger_endpoint: None,
+            otlp_endpoint: None,
+            otlp_protocol: OtlpProtocol::default(),
+            trace_sample_ratio: 1.0,
             service_name: "rossby-vis".to_string(),
             environment: "development".to_string(),
+            sentry_dsn: None,
+            syslog: None,
+            file: None,
+            statsd: None,
+            access_log: None,
+            heartbeat_interval: Some(Duration::from_secs(300)),
         }
     }
 }
@@ -86,6 +279,14 @@ impl LoggingConfig {
             config.level = level;
         }
 
+        // Per-target levels from LOG_TARGETS, e.g. "proxy=debug,metrics=warn"
+        if let Ok(targets) = std::env::var("LOG_TARGETS") {
+            match parse_log_targets(&targets) {
+                Ok(targets) => config.targets = targets,
+                Err(e) => eprintln!("Ignoring LOG_TARGETS: {}", e),
+            }
+        }
+
         // Log format from LOG_FORMAT
         if let Ok(format_str) = std::env::var("LOG_FORMAT") {
             if let Ok(format) = format_str.parse() {
@@ -108,17 +309,125 @@ impl LoggingConfig {
             config.enable_distributed_tracing = enable.parse().unwrap_or(false);
         }
 
-        // Jaeger endpoint from JAEGER_ENDPOINT
-        if let Ok(endpoint) = std::env::var("JAEGER_ENDPOINT") {
-            config.jaeger_endpoint = Some(endpoint);
+        // OTLP collector from OTEL_EXPORTER_OTLP_ENDPOINT
+        if let Ok(endpoint) = std::env::var("OTEL_EXPORTER_OTLP_ENDPOINT") {
+            config.otlp_endpoint = Some(endpoint);
             config.enable_distributed_tracing = true; // Auto-enable if endpoint is provided
         }
 
+        // OTLP transport from OTEL_EXPORTER_OTLP_PROTOCOL
+        if let Ok(protocol) = std::env::var("OTEL_EXPORTER_OTLP_PROTOCOL") {
+            match protocol.parse() {
+                Ok(protocol) => config.otlp_protocol = protocol,
+                Err(e) => eprintln!("Ignoring OTEL_EXPORTER_OTLP_PROTOCOL: {}", e),
+            }
+        }
+
+        // Trace sampling from OTEL_TRACES_SAMPLER_ARG
+        if let Ok(ratio) = std::env::var("OTEL_TRACES_SAMPLER_ARG") {
+            match parse_sample_ratio(&ratio) {
+                Ok(ratio) => config.trace_sample_ratio = ratio,
+                Err(e) => eprintln!("Ignoring OTEL_TRACES_SAMPLER_ARG: {}", e),
+            }
+        }
+
         // Service name from SERVICE_NAME
         if let Ok(name) = std::env::var("SERVICE_NAME") {
             config.service_name = name;
         }
 
+        // Error tracking from SENTRY_DSN
+        if let Ok(dsn) = std::env::var("SENTRY_DSN") {
+            config.sentry_dsn = Some(dsn).filter(|dsn| !dsn.is_empty());
+        }
+
+        // Syslog output from SYSLOG_TARGET and SYSLOG_FACILITY
+        if let Ok(target) = std::env::var("SYSLOG_TARGET") {
+            match target.parse() {
+                Ok(target) => {
+                    let mut syslog = SyslogConfig::new(target, &config.service_name);
+                    if let Ok(facility) = std::env::var("SYSLOG_FACILITY") {
+                        match parse_facility(&facility) {
+                            Ok(code) => syslog.facility = code,
+                            Err(e) => eprintln!("Ignoring SYSLOG_FACILITY: {}", e),
+                        }
+                    }
+                    config.syslog = Some(syslog);
+                }
+                Err(e) => eprintln!("Ignoring SYSLOG_TARGET: {}", e),
+            }
+        }
+
+        // File logging from LOG_FILE, LOG_ROTATION, LOG_MAX_FILES and LOG_MAX_SIZE
+        if let Ok(path) = std::env::var("LOG_FILE") {
+            let mut file = FileLogConfig::new(path);
+            if let Ok(rotation) = std::env::var("LOG_ROTATION") {
+                match rotation.parse() {
+                    Ok(rotation) => file.rotation = rotation,
+                    Err(e) => eprintln!("Ignoring LOG_ROTATION: {}", e),
+                }
+            }
+            if let Ok(max_files) = std::env::var("LOG_MAX_FILES") {
+                file.max_files = max_files.parse().ok().filter(|n| *n > 0);
+            }
+            if let Ok(max_size) = std::env::var("LOG_MAX_SIZE") {
+                file.max_size = match max_size.parse::<u64>() {
+                    Ok(0) | Err(_) => None,
+                    Ok(mb) => mb.checked_mul(1024 * 1024).or_else(|| {
+                        eprintln!("Ignoring LOG_MAX_SIZE: {} megabytes is too large", mb);
+                        None
+                    }),
+                };
+            }
+            config.file = Some(file);
+        }
+
+        // Access log from ACCESS_LOG and ACCESS_LOG_FORMAT
+        if let Ok(target) = std::env::var("ACCESS_LOG") {
+            match target.parse() {
+                Ok(target) => {
+                    let mut access_log = AccessLogConfig::new(target);
+                    if let Ok(format) = std::env::var("ACCESS_LOG_FORMAT") {
+                        match format.parse() {
+                            Ok(format) => access_log.format = format,
+                            Err(e) => eprintln!("Ignoring ACCESS_LOG_FORMAT: {}", e),
+                        }
+                    }
+                    config.access_log = Some(access_log);
+                }
+                Err(e) => eprintln!("Ignoring ACCESS_LOG: {}", e),
+            }
+        }
+
+        // StatsD from STATSD_ADDRESS, STATSD_PREFIX, STATSD_FLAVOR and STATSD_TAGS
+        if let Ok(address) = std::env::var("STATSD_ADDRESS") {
+            let mut statsd = StatsdConfig::new(&address);
+            if let Ok(prefix) = std::env::var("STATSD_PREFIX") {
+                statsd.prefix = prefix;
+            }
+            if let Ok(flavor) = std::env::var("STATSD_FLAVOR") {
+                match flavor.parse() {
+                    Ok(flavor) => statsd.flavor = flavor,
+                    Err(e) => eprintln!("Ignoring STATSD_FLAVOR: {}", e),
+                }
+            }
+            if let Ok(tags) = std::env::var("STATSD_TAGS") {
+                match statsd::parse_tags(&tags) {
+                    Ok(tags) => statsd.tags = tags,
+                    Err(e) => eprintln!("Ignoring STATSD_TAGS: {}", e),
+                }
+            }
+            config.statsd = Some(statsd);
+        }
+
+        // Heartbeat from HEARTBEAT_INTERVAL, in seconds; 0 disables it
+        if let Ok(secs) = std::env::var("HEARTBEAT_INTERVAL") {
+            if let Ok(secs) = secs.parse::<u64>() {
+                config.heartbeat_interval =
+                    Some(Duration::from_secs(secs)).filter(|d| !d.is_zero());
+            }
+        }
+
         // Environment from ENVIRONMENT or DEPLOYMENT_ENV
         if let Ok(env) = std::env::var("ENVIRONMENT") {
             config.environment = env;
@@ -128,12 +437,129 @@ impl LoggingConfig {
 
         config
     }
+
+    /// Filter directives combining the global level with per-target levels
+    pub fn filter_directives(&self) -> String {
+        std::iter::once(self.level.clone())
+            .chain(
+                self.targets
+                    .iter()
+                    .map(|(target, level)| format!("{}={}", target, level)),
+            )
+            .collect::<Vec<_>>()
+            .join(",")
+    }
+}
+
+/// Parse comma-separated `target=level` pairs, e.g. `proxy=debug,hyper=error`
+pub fn parse_log_targets(s: &str) -> Result<Vec<(String, String)>, String> {
+    const LEVELS: [&str; 6] = ["trace", "debug", "info", "warn", "error", "off"];
+
+    s.split(',')
+        .map(str::trim)
+        .filter(|directive| !directive.is_empty())
+        .map(|directive| {
+            let (target, level) = directive
+                .split_once('=')
+                .map(|(t, l)| (t.trim(), l.trim().to_lowercase()))
+                .filter(|(t, _)| !t.is_empty())
+                .ok_or_else(|| {
+                    format!(
+                        "Invalid log target: {}. Expected target=level, e.g. proxy=debug",
+                        directive
+                    )
+                })?;
+            if !LEVELS.contains(&level.as_str()) {
+                return Err(format!(
+                    "Invalid level for log target {}: {}. Valid options: {}",
+                    target,
+                    level,
+                    LEVELS.join(", ")
+                ));
+            }
+            Ok((target.to_string(), level))
+        })
+        .collect()
+}
+
+/// Handle for changing the active log filter while the server is running
+#[derive(Clone)]
+pub struct LogLevelHandle {
+    handle: reload::Handle<EnvFilter, Registry>,
+    level: Arc<RwLock<String>>,
+    /// Bumped on every change so a pending revert can tell it was superseded
+    generation: Arc<AtomicU64>,
+}
+
+impl std::fmt::Debug for LogLevelHandle {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("LogLevelHandle")
+            .field("level", &self.current())
+            .finish()
+    }
+}
+
+impl LogLevelHandle {
+    /// The active filter directives, e.g. `info` or `debug,hyper=warn`
+    pub fn current(&self) -> String {
+        self.level.read().unwrap_or_else(|e| e.into_inner()).clone()
+    }
+
+    /// Replace the active filter, returning the previous directives
+    pub fn set(&self, level: &str) -> Result<String, String> {
+        let filter = EnvFilter::try_new(level)
+            .map_err(|e| format!("Invalid log level '{}': {}", level, e))?;
+        self.handle
+            .reload(filter)
+            .map_err(|e| format!("Failed to change log level: {}", e))?;
+        self.generation.fetch_add(1, Ordering::SeqCst);
+
+        let mut current = self.level.write().unwrap_or_else(|e| e.into_inner());
+        let previous = std::mem::replace(&mut *current, level.to_string());
+        info!("Log level changed from '{}' to '{}'", previous, level);
+        Ok(previous)
+    }
+
+    /// Replace the active filter and restore `previous` after `duration`,
+    /// unless the level is changed again in the meantime
+    pub fn set_temporarily(&self, level: &str, duration: Duration) -> Result<String, String> {
+        let previous = self.set(level)?;
+        let generation = self.generation.load(Ordering::SeqCst);
+        let handle = self.clone();
+        let restore = previous.clone();
+        tokio::spawn(async move {
+            tokio::time::sleep(duration).await;
+            if handle.generation.load(Ordering::SeqCst) == generation {
+                let _ = handle.set(&restore);
+            }
+        });
+        Ok(previous)
+    }
+}
+
+/// Create the reloadable filter layer installed by `init_logging`
+pub fn log_level_layer(level: &str) -> (reload::Layer<EnvFilter, Registry>, LogLevelHandle) {
+    let (filter, level) = match EnvFilter::try_new(level) {
+        Ok(filter) => (filter, level.to_string()),
+        Err(_) => (EnvFilter::new("info"), "info".to_string()),
+    };
+    let (layer, handle) = reload::Layer::new(filter);
+    let handle = LogLevelHandle {
+        handle,
+        level: Arc::new(RwLock::new(level)),
+        generation: Arc::new(AtomicU64::new(0)),
+    };
+    (layer, handle)
 }
 
 /// Initialize comprehensive logging system
-pub fn init_logging(config: LoggingConfig) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
-    // Create base filter
-    let filter = EnvFilter::try_new(&config.level).unwrap_or_else(|_| EnvFilter::new("info"));
+///
+/// Returns a handle for changing the log level at runtime.
+pub fn init_logging(
+    config: LoggingConfig,
+) -> Result<LogLevelHandle, Box<dyn std::error::Error + Send + Sync>> {
+    // Create base filter, reloadable at runtime
+    let (filter, log_level) = log_level_layer(&config.filter_directives());
 
     // Create registry
     let registry = Registry::default().with(filter);
@@ -164,26 +590,75 @@ pub fn init_logging(config: LoggingConfig) -> Result<(), Box<dyn std::error::Err
             .boxed(),
     };
 
-    #[cfg(feature = "distributed-tracing")]
     let mut layers = vec![logging_layer];
-    #[cfg(not(feature = "distributed-tracing"))]
-    let layers = vec![logging_layer];
+
+    // Add syslog output if configured; the syslog header carries the timestamp
+    if let Some(syslog) = &config.syslog {
+        let writer = SyslogWriter::connect(syslog.clone())
+            .map_err(|e| format!("Failed to connect to syslog {:?}: {}", syslog.target, e))?;
+        let syslog_layer = match config.format {
+            LogFormat::Json => fmt::Layer::default()
+                .json()
+                .with_writer(writer)
+                .with_target(true)
+                .boxed(),
+            LogFormat::Text | LogFormat::Compact => fmt::Layer::default()
+                .compact()
+                .without_time()
+                .with_ansi(false)
+                .with_writer(writer)
+                .with_target(true)
+                .boxed(),
+        };
+        layers.push(syslog_layer);
+    }
+
+    // Add JSON file output if configured, written on a background thread
+    if let Some(file) = &config.file {
+        let (writer, guard) = NonBlockingBuilder::default()
+            .lossy(false)
+            .thread_name("rossby-vis-log-file")
+            .finish(file.appender()?);
+        *LOG_FILE_GUARD.lock().unwrap() = Some(guard);
+        let file_layer = fmt::Layer::default()
+            .json()
+            .with_writer(writer)
+            .with_timer(ChronoUtc::rfc_3339())
+            .with_target(true)
+            .with_thread_ids(true)
+            .with_file(true)
+            .with_line_number(true)
+            .boxed();
+        layers.push(file_layer);
+    }
+
+    // Start pushing metrics if configured
+    if let Some(statsd) = &config.statsd {
+        statsd::init(statsd.clone())
+            .map_err(|e| format!("Failed to set up StatsD {}: {}", statsd.address, e))?;
+    }
+
+    // Start the access log if configured
+    if let Some(access) = &config.access_log {
+        access_log::init(access)
+            .map_err(|e| format!("Failed to open access log {:?}: {}", access.target, e))?;
+    }
 
     // Add distributed tracing layer if enabled
     #[cfg(feature = "distributed-tracing")]
     if config.enable_distributed_tracing {
-        if let Some(endpoint) = &config.jaeger_endpoint {
-            match setup_jaeger_tracing(&config.service_name, endpoint) {
+        if let Some(endpoint) = &config.otlp_endpoint {
+            match setup_otlp_tracing(&config, endpoint) {
                 Ok(tracer) => {
                     let telemetry_layer = tracing_opentelemetry::layer().with_tracer(tracer);
                     layers.push(telemetry_layer.boxed());
                     info!(
-                        "Distributed tracing enabled with Jaeger endpoint: {}",
-                        endpoint
+                        "Distributed tracing enabled with OTLP endpoint: {} ({:?}, sample ratio {})",
+                        endpoint, config.otlp_protocol, config.trace_sample_ratio
                     );
                 }
                 Err(e) => {
-                    tracing::warn!("Failed to setup Jaeger tracing: {}", e);
+                    tracing::warn!("Failed to setup OTLP tracing: {}", e);
                 }
             }
         }
@@ -192,15 +667,40 @@ pub fn init_logging(config: LoggingConfig) -> Result<(), Box<dyn std::error::Err
     // Initialize the subscriber with all layers
     registry.with(layers).init();
 
+    if let Some(dsn) = &config.sentry_dsn {
+        match crate::error_tracking::init(dsn, &config.service_name, &config.environment) {
+            Ok(()) => info!("Error tracking enabled"),
+            Err(e) => tracing::warn!("Failed to set up error tracking: {}", e),
+        }
+    }
+
     // Log startup information
     info!("Logging system initialized");
     info!("Service: {}", config.service_name);
     info!("Environment: {}", config.environment);
-    info!("Log level: {}", config.level);
+    info!("Log level: {}", config.filter_directives());
     info!("Log format: {:?}", config.format);
     info!("Request tracing: {}", config.enable_request_tracing);
     info!("System metrics: {}", config.enable_metrics);
     info!("Distributed tracing: {}", config.enable_distributed_tracing);
+    if let Some(syslog) = &config.syslog {
+        info!("Syslog output: {:?}", syslog.target);
+    }
+    if let Some(file) = &config.file {
+        info!(
+            "Log file: {} (rotation: {:?}, max files: {:?}, max size: {:?})",
+            file.path.display(),
+            file.rotation,
+            file.max_files,
+            file.max_size
+        );
+    }
+    if let Some(statsd) = &config.statsd {
+        info!("StatsD output: {} ({:?})", statsd.address, statsd.flavor);
+    }
+    if let Some(access) = &config.access_log {
+        info!("Access log: {:?} ({:?})", access.target, access.format);
+    }
 
     // Start metrics collection if enabled
     if config.enable_metrics {
@@ -209,19 +709,74 @@ pub fn init_logging(config: LoggingConfig) -> Result<(), Box<dyn std::error::Err
         });
     }
 
-    Ok(())
+    // Start the heartbeat if enabled
+    record_start();
+    if let Some(interval) = config.heartbeat_interval {
+        tokio::spawn(async move {
+            heartbeat(interval).await;
+        });
+    }
+
+    Ok(log_level)
 }
 
-/// Setup Jaeger distributed tracing
+/// Setup distributed tracing exported over OTLP
 #[cfg(feature = "distributed-tracing")]
-fn setup_jaeger_tracing(
-    service_name: &str,
+fn setup_otlp_tracing(
+    config: &LoggingConfig,
     endpoint: &str,
 ) -> Result<opentelemetry::sdk::trace::Tracer, opentelemetry::trace::TraceError> {
-    opentelemetry_jaeger::new_agent_pipeline()
-        .with_service_name(service_name)
-        .with_endpoint(endpoint)
-        .install_simple()
+    use opentelemetry::{
+        sdk::{
+            trace::{self, Sampler},
+            Resource,
+        },
+        KeyValue,
+    };
+    use opentelemetry_otlp::WithExportConfig;
+
+    let trace_config = trace::config()
+        .with_sampler(Sampler::ParentBased(Box::new(Sampler::TraceIdRatioBased(
+            config.trace_sample_ratio,
+        ))))
+        .with_resource(Resource::new([
+            KeyValue::new("service.name", config.service_name.clone()),
+            KeyValue::new("service.version", env!("CARGO_PKG_VERSION")),
+            KeyValue::new("deployment.environment", config.environment.clone()),
+        ]));
+    let pipeline = opentelemetry_otlp::new_pipeline()
+        .tracing()
+        .with_trace_config(trace_config);
+    match config.otlp_protocol {
+        OtlpProtocol::Grpc => pipeline
+            .with_exporter(
+                opentelemetry_otlp::new_exporter()
+                    .tonic()
+                    .with_endpoint(endpoint),
+            )
+            .install_batch(opentelemetry::runtime::Tokio),
+        OtlpProtocol::Http => pipeline
+            .with_exporter(
+                opentelemetry_otlp::new_exporter()
+                    .http()
+                    .with_endpoint(endpoint),
+            )
+            .install_batch(opentelemetry::runtime::Tokio),
+    }
+}
+
+/// Keeps the log file writer thread running; dropping it flushes the file
+static LOG_FILE_GUARD: Mutex<Option<WorkerGuard>> = Mutex::new(None);
+
+/// Write out the log lines and spans still buffered
+///
+/// Call before the process exits; log lines after this are no longer
+/// written to the log file.
+pub fn shutdown_logging() {
+    #[cfg(feature = "distributed-tracing")]
+    opentelemetry::global::shutdown_tracer_provider();
+    drop(LOG_FILE_GUARD.lock().unwrap().take());
+    access_log::flush();
 }
 
 /// Collect and log system metrics periodically
@@ -250,6 +805,9 @@ async fn metrics_collector() {
             system_cpu_usage_percent = cpu_usage,
             "System metrics"
         );
+        statsd::gauge("system.memory.used", used_memory as f64, &[]);
+        statsd::gauge("system.cpu.usage", cpu_usage as f64, &[]);
+        shedding::record_pressure(memory_usage_percent as f32, cpu_usage);
 
         // Log process-specific metrics if available
         if let Some(pid) = pid {
@@ -262,14 +820,194 @@ async fn metrics_collector() {
                     process_cpu_usage = process.cpu_usage(),
                     "Process metrics"
                 );
+                statsd::gauge("process.memory", process.memory() as f64, &[]);
+                statsd::gauge("process.cpu.usage", process.cpu_usage() as f64, &[]);
             }
         }
 
+        let transfer = transfer_totals();
+        tracing::info!(
+            target: "metrics",
+            http_request_bytes_total = transfer.request_bytes,
+            http_response_bytes_total = transfer.response_bytes,
+            backend_bytes_total = transfer.backend_bytes,
+            "Transfer metrics"
+        );
+
         // Sleep for 30 seconds before next collection
         tokio::time::sleep(Duration::from_secs(30)).await;
     }
 }
 
+/// Log a summary of activity since startup every `interval`
+async fn heartbeat(interval: Duration) {
+    let mut ticker = tokio::time::interval(interval);
+    // The first tick completes immediately; the first heartbeat is one interval in
+    ticker.tick().await;
+
+    loop {
+        ticker.tick().await;
+
+        let summary = status_summary();
+        tracing::info!(
+            target: "heartbeat",
+            uptime_secs = summary.uptime_secs,
+            requests_total = summary.requests.total,
+            requests_client_errors = summary.requests.client_errors,
+            requests_server_errors = summary.requests.server_errors,
+            backend_status = summary.backend.status,
+            backend_requests = summary.backend.requests,
+            backend_failures = summary.backend.failures,
+            backend_consecutive_failures = summary.backend.consecutive_failures,
+            response_bytes_total = summary.transfer.response_bytes,
+            backend_bytes_total = summary.transfer.backend_bytes,
+            "Heartbeat"
+        );
+    }
+}
+
+/// When the server started, for uptime
+static STARTED_AT: OnceLock<Instant> = OnceLock::new();
+/// Client requests completed since startup, by outcome
+static REQUESTS_TOTAL: AtomicU64 = AtomicU64::new(0);
+static REQUESTS_CLIENT_ERRORS: AtomicU64 = AtomicU64::new(0);
+static REQUESTS_SERVER_ERRORS: AtomicU64 = AtomicU64::new(0);
+/// Backend requests since startup; failures are 5xx responses and requests
+/// that got no response
+static BACKEND_REQUESTS: AtomicU64 = AtomicU64::new(0);
+static BACKEND_FAILURES: AtomicU64 = AtomicU64::new(0);
+static BACKEND_CONSECUTIVE_FAILURES: AtomicU64 = AtomicU64::new(0);
+/// Uptime in milliseconds at the last successful backend request, plus one
+/// so that zero means never
+static BACKEND_LAST_SUCCESS: AtomicU64 = AtomicU64::new(0);
+
+/// Client request counts since startup
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+pub struct RequestTotals {
+    pub total: u64,
+    pub client_errors: u64,
+    pub server_errors: u64,
+}
+
+/// Backend health as seen through the requests made to it
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct BackendHealth {
+    /// `unknown` before the first request, then `healthy` or `failing`
+    /// depending on the most recent request
+    pub status: &'static str,
+    pub requests: u64,
+    pub failures: u64,
+    pub consecutive_failures: u64,
+    /// Seconds since the last successful backend request
+    pub last_success_secs_ago: Option<u64>,
+}
+
+/// Summary of activity since startup, logged by the heartbeat and served by
+/// `/api/status`
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct StatusSummary {
+    pub uptime_secs: u64,
+    pub requests: RequestTotals,
+    pub backend: BackendHealth,
+    pub transfer: TransferTotals,
+}
+
+/// Mark the start of the server, from which uptime is counted
+pub fn record_start() {
+    STARTED_AT.get_or_init(Instant::now);
+}
+
+fn uptime() -> Duration {
+    STARTED_AT.get_or_init(Instant::now).elapsed()
+}
+
+/// Count a completed client request by its response status
+pub fn record_request(status_code: u16) {
+    REQUESTS_TOTAL.fetch_add(1, Ordering::Relaxed);
+    match status_code {
+        400..=499 => REQUESTS_CLIENT_ERRORS.fetch_add(1, Ordering::Relaxed),
+        500..=599 => REQUESTS_SERVER_ERRORS.fetch_add(1, Ordering::Relaxed),
+        _ => 0,
+    };
+}
+
+/// Count a backend request; status 0 means no response was received
+pub fn record_backend_request(status_code: u16) {
+    BACKEND_REQUESTS.fetch_add(1, Ordering::Relaxed);
+    if status_code == 0 || status_code >= 500 {
+        BACKEND_FAILURES.fetch_add(1, Ordering::Relaxed);
+        BACKEND_CONSECUTIVE_FAILURES.fetch_add(1, Ordering::Relaxed);
+    } else {
+        BACKEND_CONSECUTIVE_FAILURES.store(0, Ordering::Relaxed);
+        BACKEND_LAST_SUCCESS.store(uptime().as_millis() as u64 + 1, Ordering::Relaxed);
+    }
+}
+
+/// Current activity summary
+pub fn status_summary() -> StatusSummary {
+    let uptime = uptime();
+    let requests = BACKEND_REQUESTS.load(Ordering::Relaxed);
+    let consecutive_failures = BACKEND_CONSECUTIVE_FAILURES.load(Ordering::Relaxed);
+    let last_success = BACKEND_LAST_SUCCESS.load(Ordering::Relaxed);
+
+    StatusSummary {
+        uptime_secs: uptime.as_secs(),
+        requests: RequestTotals {
+            total: REQUESTS_TOTAL.load(Ordering::Relaxed),
+            client_errors: REQUESTS_CLIENT_ERRORS.load(Ordering::Relaxed),
+            server_errors: REQUESTS_SERVER_ERRORS.load(Ordering::Relaxed),
+        },
+        backend: BackendHealth {
+            status: match (requests, consecutive_failures) {
+                (0, _) => "unknown",
+                (_, 0) => "healthy",
+                _ => "failing",
+            },
+            requests,
+            failures: BACKEND_FAILURES.load(Ordering::Relaxed),
+            consecutive_failures,
+            last_success_secs_ago: (last_success > 0)
+                .then(|| (uptime.as_millis() as u64).saturating_sub(last_success - 1) / 1000),
+        },
+        transfer: transfer_totals(),
+    }
+}
+
+/// Bytes received in request bodies since startup
+static REQUEST_BYTES_TOTAL: AtomicU64 = AtomicU64::new(0);
+/// Bytes sent in response bodies since startup, including streamed responses
+static RESPONSE_BYTES_TOTAL: AtomicU64 = AtomicU64::new(0);
+/// Bytes received from the Rossby backend since startup
+static BACKEND_BYTES_TOTAL: AtomicU64 = AtomicU64::new(0);
+
+/// Byte counts accumulated since startup
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+pub struct TransferTotals {
+    pub request_bytes: u64,
+    pub response_bytes: u64,
+    pub backend_bytes: u64,
+}
+
+/// Add a completed client request to the transfer totals
+pub fn record_transfer(request_bytes: u64, response_bytes: u64) {
+    REQUEST_BYTES_TOTAL.fetch_add(request_bytes, Ordering::Relaxed);
+    RESPONSE_BYTES_TOTAL.fetch_add(response_bytes, Ordering::Relaxed);
+}
+
+/// Add bytes received from the backend to the transfer totals
+pub fn record_backend_transfer(bytes: u64) {
+    BACKEND_BYTES_TOTAL.fetch_add(bytes, Ordering::Relaxed);
+}
+
+/// Current transfer totals
+pub fn transfer_totals() -> TransferTotals {
+    TransferTotals {
+        request_bytes: REQUEST_BYTES_TOTAL.load(Ordering::Relaxed),
+        response_bytes: RESPONSE_BYTES_TOTAL.load(Ordering::Relaxed),
+        backend_bytes: BACKEND_BYTES_TOTAL.load(Ordering::Relaxed),
+    }
+}
+
 /// Create a request correlation ID for tracing
 pub fn generate_request_id() -> String {
     uuid::Uuid::new_v4().to_string()
@@ -299,11 +1037,42 @@ macro_rules! log_request {
             "HTTP request completed"
         );
     };
+    (
+        $method:expr,
+        $path:expr,
+        $status:expr,
+        $duration_ms:expr,
+        $request_id:expr,
+        $request_bytes:expr,
+        $response_bytes:expr
+    ) => {
+        tracing::info!(
+            target: "request",
+            http_method = $method,
+            http_path = $path,
+            http_status_code = $status,
+            duration_ms = $duration_ms,
+            request_id = $request_id,
+            request_bytes = $request_bytes,
+            response_bytes = $response_bytes,
+            "HTTP request completed"
+        );
+    };
 }
 
 #[macro_export]
 macro_rules! log_proxy_request {
     ($backend_url:expr, $status:expr, $duration_ms:expr, $bytes_transferred:expr) => {
+        $crate::logging::record_backend_transfer($bytes_transferred);
+        $crate::logging::record_backend_request($status);
+        let status = $status.to_string();
+        $crate::statsd::count("backend.requests", 1, &[("status", &status)]);
+        $crate::statsd::timing(
+            "backend.request.duration",
+            $duration_ms,
+            &[("status", &status)],
+        );
+        $crate::statsd::count("backend.bytes", $bytes_transferred, &[]);
         tracing::info!(
             target: "proxy",
             backend_url = $backend_url,
@@ -367,12 +1136,124 @@ mod tests {
         assert!(!config.enable_distributed_tracing);
     }
 
+    #[test]
+    fn test_parse_log_targets() {
+        assert_eq!(
+            parse_log_targets("proxy=debug, metrics=WARN,,hyper=error").unwrap(),
+            vec![
+                ("proxy".to_string(), "debug".to_string()),
+                ("metrics".to_string(), "warn".to_string()),
+                ("hyper".to_string(), "error".to_string()),
+            ]
+        );
+        assert!(parse_log_targets("proxy").is_err());
+        assert!(parse_log_targets("=debug").is_err());
+        assert!(parse_log_targets("proxy=loud")
+            .unwrap_err()
+            .contains("Valid options"));
+    }
+
+    #[test]
+    fn test_filter_directives() {
+        let mut config = LoggingConfig::default();
+        assert_eq!(config.filter_directives(), "info");
+
+        config.targets = parse_log_targets("proxy=debug,hyper=error").unwrap();
+        assert_eq!(config.filter_directives(), "info,proxy=debug,hyper=error");
+        assert!(EnvFilter::try_new(config.filter_directives()).is_ok());
+    }
+
+    #[tokio::test]
+    async fn test_log_level_handle() {
+        let (layer, handle) = log_level_layer("info");
+        let _subscriber = Registry::default().with(layer);
+
+        assert_eq!(handle.set("debug,hyper=warn"), Ok("info".to_string()));
+        assert_eq!(handle.current(), "debug,hyper=warn");
+        assert!(handle.set("not a level=").is_err());
+        assert_eq!(handle.current(), "debug,hyper=warn");
+
+        handle
+            .set_temporarily("trace", Duration::from_millis(10))
+            .unwrap();
+        assert_eq!(handle.current(), "trace");
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        assert_eq!(handle.current(), "debug,hyper=warn");
+    }
+
+    #[test]
+    fn test_log_rotation_parsing() {
+        assert_eq!("HOURLY".parse(), Ok(LogRotation::Hourly));
+        assert_eq!("daily".parse(), Ok(LogRotation::Daily));
+        assert_eq!("never".parse(), Ok(LogRotation::Never));
+        assert!("weekly".parse::<LogRotation>().is_err());
+    }
+
+    #[test]
+    fn test_otlp_settings_parsing() {
+        assert_eq!("gRPC".parse(), Ok(OtlpProtocol::Grpc));
+        assert_eq!("http".parse(), Ok(OtlpProtocol::Http));
+        assert_eq!("http/protobuf".parse(), Ok(OtlpProtocol::Http));
+        assert!("thrift".parse::<OtlpProtocol>().is_err());
+
+        assert_eq!(parse_sample_ratio("0.25"), Ok(0.25));
+        assert_eq!(parse_sample_ratio("1"), Ok(1.0));
+        assert!(parse_sample_ratio("1.5").is_err());
+        assert!(parse_sample_ratio("-0.1").is_err());
+        assert!(parse_sample_ratio("half").is_err());
+    }
+
+    #[test]
+    fn test_file_appender_creates_directory() {
+        use std::io::Write;
+
+        let directory =
+            std::env::temp_dir().join(format!("rossby-vis-logs-{}", uuid::Uuid::new_v4()));
+        let mut file = FileLogConfig::new(directory.join("rossby-vis.log"));
+        file.rotation = LogRotation::Never;
+
+        let mut appender = file.appender().unwrap();
+        appender.write_all(b"{}\n").unwrap();
+        appender.flush().unwrap();
+        assert!(directory.join("rossby-vis.log").exists());
+
+        std::fs::remove_dir_all(directory).unwrap();
+        assert!(FileLogConfig::new("/").appender().is_err());
+    }
+
+    #[test]
+    fn test_backend_health() {
+        record_backend_request(200);
+        record_backend_request(503);
+        record_backend_request(0);
+        let backend = status_summary().backend;
+        assert_eq!(backend.status, "failing");
+        assert!(backend.consecutive_failures >= 2);
+        assert!(backend.last_success_secs_ago.is_some());
+
+        record_backend_request(404);
+        let backend = status_summary().backend;
+        assert_eq!(backend.status, "healthy");
+        assert_eq!(backend.consecutive_failures, 0);
+    }
+
+    #[test]
+    fn test_transfer_totals() {
+        let before = transfer_totals();
+        record_transfer(10, 200);
+        record_backend_transfer(150);
+        let after = transfer_totals();
+        assert!(after.request_bytes >= before.request_bytes + 10);
+        assert!(after.response_bytes >= before.response_bytes + 200);
+        assert!(after.backend_bytes >= before.backend_bytes + 150);
+    }
+
     #[test]
     fn test_request_id_generation() {
         let id1 = generate_request_id();
         let id2 = generate_request_id();
         assert_ne!(id1, id2);
-        assert!(id1.len() > 0);
-        assert!(id2.len() > 0);
+        assert!(!id1.is_empty());
+        assert!(!id2.is_empty());
     }
 }
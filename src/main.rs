@@ -1,8 +1,36 @@
-use clap::Parser;
+use clap::{CommandFactory, Parser};
 use rossby_vis::{
-    logging::{init_logging, LogFormat, LoggingConfig},
-    run_server,
+    access_log::{AccessLogConfig, AccessLogFormat, AccessLogTarget},
+    backend::BackendSchema,
+    cache::{DiskCacheConfig, ResponseCacheConfig},
+    client::{BackendHeader, BackendProxy},
+    config::{changed_options, layered_args},
+    datasets::parse_api_urls,
+    endpoint::BackendEndpoint,
+    grid::DEFAULT_MAX_GRID_POINTS,
+    listen::{parse_socket_mode, ListenAddress},
+    logging::{
+        init_logging, parse_log_targets, parse_sample_ratio, shutdown_logging, FileLogConfig,
+        LogFormat, LogRotation, LoggingConfig, OtlpProtocol,
+    },
+    oscar::parse_current_components,
+    packing::PackingConfig,
+    pipeline::parse_pipeline,
+    reload::{self, ReloadableSettings},
+    replay::Recording,
+    resilience::CircuitBreakerConfig,
+    retry::RetryPolicy,
+    run_server_with_config,
+    shedding::parse_max_concurrent_requests,
+    signing::SigningConfig,
+    statsd::{parse_tags, StatsdConfig, StatsdFlavor},
+    syslog::{parse_facility, SyslogConfig, SyslogTarget},
+    systemd,
+    tls::{AcmeConfig, TlsCertificate},
+    AppState, ServerConfig,
 };
+use std::{ffi::OsString, path::PathBuf, time::Duration};
+use tracing::{error, info, warn};
 
 #[derive(Parser, Debug)]
 #[command(
@@ -11,18 +39,28 @@ use rossby_vis::{
     about = "Interactive visualization frontend for the rossby data server"
 )]
 struct Args {
+    /// TOML or YAML file of settings, overridden by ROSSBY_VIS_* environment
+    /// variables and the command line
+    #[arg(long)]
+    config: Option<PathBuf>,
+
     /// Port to run the server on
     #[arg(short, long, default_value_t = 8080)]
     port: u16,
 
-    /// URL of the Rossby backend server
-    #[arg(long, required = true)]
-    api_url: String,
+    /// URL of the Rossby backend server, or name=url pairs of several
+    /// backends served as datasets, the first being the default
+    #[arg(long, required = true, value_delimiter = ',')]
+    api_url: Vec<String>,
 
     /// Log level (trace, debug, info, warn, error)
     #[arg(long, default_value = "info")]
     log_level: String,
 
+    /// Per-target log levels, e.g. proxy=debug,metrics=warn,hyper=error
+    #[arg(long)]
+    log_targets: Option<String>,
+
     /// Log format (text, json, compact)
     #[arg(long, default_value = "text")]
     log_format: String,
@@ -43,40 +81,895 @@ struct Args {
     #[arg(long, default_value = "rossby-vis")]
     service_name: String,
 
-    /// Jaeger endpoint for distributed tracing
+    /// OTLP collector for distributed tracing, e.g. http://otel-collector:4317
+    /// (requires the distributed-tracing feature)
+    #[arg(long)]
+    otlp_endpoint: Option<String>,
+
+    /// OTLP transport (grpc, http)
+    #[arg(long)]
+    otlp_protocol: Option<String>,
+
+    /// Fraction of new traces to sample, from 0 to 1
+    #[arg(long, value_parser = parse_sample_ratio)]
+    trace_sample_ratio: Option<f64>,
+
+    /// Sentry DSN for reporting server errors and panics
+    /// (requires the error-tracking feature)
+    #[arg(long)]
+    sentry_dsn: Option<String>,
+
+    /// Also send logs to syslog (local, unix:///path, udp://host:port, tcp://host:port)
+    #[arg(long)]
+    syslog: Option<String>,
+
+    /// Syslog facility (user, daemon, local0-local7, ...)
+    #[arg(long, default_value = "daemon")]
+    syslog_facility: String,
+
+    /// Also write JSON logs to this file, rotated per --log-rotation
+    #[arg(long)]
+    log_file: Option<PathBuf>,
+
+    /// Log file rotation (hourly, daily, never)
+    #[arg(long, default_value = "daily")]
+    log_rotation: String,
+
+    /// Number of rotated log files to keep (all when omitted)
+    #[arg(long)]
+    log_max_files: Option<usize>,
+
+    /// Also rotate the log file once it reaches this many megabytes
+    #[arg(long)]
+    log_max_size: Option<u64>,
+
+    /// Also write one Apache-style line per request to this file, or - for stdout
+    #[arg(long)]
+    access_log: Option<String>,
+
+    /// Access log line format (combined, common)
+    #[arg(long, default_value = "combined")]
+    access_log_format: String,
+
+    /// Seconds between heartbeat log lines summarizing activity (0 disables)
+    #[arg(long, default_value_t = 300)]
+    heartbeat_interval: u64,
+
+    /// Push metrics to a StatsD agent at host:port
+    #[arg(long)]
+    statsd: Option<String>,
+
+    /// Prefix for StatsD metric names
+    #[arg(long, default_value = "rossby_vis")]
+    statsd_prefix: String,
+
+    /// StatsD wire format (statsd, dogstatsd)
+    #[arg(long, default_value = "statsd")]
+    statsd_flavor: String,
+
+    /// Tags added to every StatsD metric, e.g. env:production,region:eu (dogstatsd only)
+    #[arg(long)]
+    statsd_tags: Option<String>,
+
+    /// Dataset variable holding the land-sea mask (auto-detected if omitted)
+    #[arg(long)]
+    land_sea_mask_var: Option<String>,
+
+    /// JSON file with land-sea mask values matching the dataset grid
+    #[arg(long)]
+    land_sea_mask_file: Option<PathBuf>,
+
+    /// Automatically mask ocean-only variables over land (and vice versa)
+    #[arg(long)]
+    auto_land_sea_mask: bool,
+
+    /// Backend metadata/data schema (auto, legacy, v2)
+    #[arg(long, default_value = "auto")]
+    backend_schema: String,
+
+    /// Reject requests with unrecognized query parameters (e.g. `var=` instead of `vars=`)
+    #[arg(long)]
+    strict_query: bool,
+
+    /// Longest query string accepted on the /proxy routes, in bytes (0 for no limit)
+    #[arg(long, default_value = "4096")]
+    max_query_length: usize,
+
+    /// Most variables accepted in `vars` on the /proxy routes (0 for no limit)
+    #[arg(long, default_value = "32")]
+    max_vars: usize,
+
+    /// Largest total size of request headers accepted on the /proxy routes, in bytes (0 for no limit)
+    #[arg(long, default_value = "16384")]
+    max_header_bytes: usize,
+
+    /// PEM file with extra CA certificates to trust for the backend (repeatable)
+    #[arg(long)]
+    backend_ca_cert: Vec<PathBuf>,
+
+    /// DANGEROUS: skip TLS certificate verification for the backend
+    #[arg(long)]
+    backend_insecure_tls: bool,
+
+    /// Proxy for backend requests (system, none, or an http/https/socks5 URL)
+    #[arg(long, default_value = "system")]
+    backend_proxy: String,
+
+    /// Seconds before idle pooled backend connections are closed
+    #[arg(long)]
+    backend_pool_idle_timeout: Option<u64>,
+
+    /// Maximum idle pooled connections kept per backend host
+    #[arg(long)]
+    backend_pool_max_idle: Option<usize>,
+
+    /// Seconds between TCP keepalive probes on backend connections
+    #[arg(long)]
+    backend_tcp_keepalive: Option<u64>,
+
+    /// HTTP version for backend requests (auto, http1 or http2)
+    #[arg(long, default_value = "auto")]
+    backend_http_version: String,
+
+    /// Seconds to wait for a connection to the backend, 0 for no limit
+    #[arg(long, default_value_t = 10)]
+    backend_connect_timeout: u64,
+
+    /// Seconds to wait for a backend response, body included, 0 for no limit
+    #[arg(long, default_value_t = 60)]
+    backend_request_timeout: u64,
+
+    /// Seconds after which all backend connections are recycled and DNS re-resolved
+    #[arg(long)]
+    backend_connection_max_age: Option<u64>,
+
+    /// User-Agent for backend requests (default: rossby-vis/<version>)
+    #[arg(long)]
+    backend_user_agent: Option<String>,
+
+    /// Extra header for backend requests as 'Name: value' (repeatable)
+    #[arg(long)]
+    backend_header: Vec<String>,
+
+    /// Bearer token enabling the /admin endpoints (disabled when omitted)
+    #[arg(long)]
+    admin_token: Option<String>,
+
+    /// User allowed in with HTTP Basic authentication, as USER:BCRYPT-HASH
+    /// (repeatable); every route but /health then needs credentials
+    #[arg(long)]
+    basic_auth: Vec<String>,
+
+    /// API key allowed on the /proxy, /data and /api routes, as LABEL=KEY
+    /// (repeatable)
+    #[arg(long)]
+    api_key: Vec<String>,
+
+    /// Key store file with one LABEL=KEY API key per line
+    #[arg(long)]
+    api_keys_file: Option<PathBuf>,
+
+    /// OpenID Connect issuer whose bearer tokens are allowed on the /proxy,
+    /// /data and /api routes; its JWKS is found through discovery
+    #[arg(long)]
+    jwt_issuer: Option<String>,
+
+    /// JWKS URL with the signing keys of accepted bearer tokens
+    #[arg(long)]
+    jwt_jwks_url: Option<String>,
+
+    /// Audience bearer tokens must be issued for (repeatable)
+    #[arg(long)]
+    jwt_audience: Vec<String>,
+
+    /// Seconds between fetches of the JWT signing keys
+    #[arg(long, default_value = "3600")]
+    jwt_jwks_refresh: u64,
+
+    /// Page title of the viewer
+    #[arg(long)]
+    site_title: Option<String>,
+
+    /// Path to serve the viewer under, e.g. /rossby-vis behind a reverse proxy
+    #[arg(long, default_value = "")]
+    base_path: String,
+
+    /// URL prefix the frontend uses for /proxy requests (defaults to this server)
+    #[arg(long)]
+    frontend_api_base: Option<String>,
+
+    /// Contact email shown in the viewer menu
+    #[arg(long)]
+    contact_email: Option<String>,
+
+    /// HTML file inserted at the end of the page, e.g. an analytics script
+    #[arg(long)]
+    analytics_snippet: Option<PathBuf>,
+
+    /// Serve the frontend from --dev-dir on disk, re-reading changed files
+    /// and disabling browser caching
+    #[arg(long)]
+    dev: bool,
+
+    /// Frontend directory served in --dev mode
+    #[arg(long, default_value = "public")]
+    dev_dir: PathBuf,
+
+    /// Send COOP/COEP headers so WebAssembly workers can use SharedArrayBuffer
+    #[arg(long)]
+    cross_origin_isolation: bool,
+
+    /// Serve the lighter index.mobile.html to phones and tablets
+    #[arg(long)]
+    mobile_index: bool,
+
+    /// Frontend error reports logged per client per minute
+    #[arg(long, default_value = "60")]
+    client_error_rate: u32,
+
+    /// Rossby backend serving ocean currents for the OSCAR routes, if not the main one
+    #[arg(long)]
+    ocean_currents_url: Option<String>,
+
+    /// Eastward and northward ocean current variables, as `u,v` (auto-detected by default)
+    #[arg(long)]
+    ocean_current_vars: Option<String>,
+
+    /// Largest grid, in points, served as Earth JSON; 0 for no limit
+    #[arg(long, default_value_t = DEFAULT_MAX_GRID_POINTS)]
+    earth_max_grid_points: usize,
+
+    /// Refuse grids over --earth-max-grid-points instead of downsampling them
+    #[arg(long)]
+    reject_large_grids: bool,
+
+    /// JSON file of custom derived variables defined by formulas (repeatable)
+    #[arg(long)]
+    derived_variables: Vec<PathBuf>,
+
+    /// JSON file of hooks renaming, rescaling or hiding backend variables, or a
+    /// Rhai script (.rhai, requires the scripting feature) (repeatable)
+    #[arg(long)]
+    transforms: Vec<PathBuf>,
+
+    /// JSON file of per-variable rendering hints for the frontend, such as
+    /// interpolation or particle density (repeatable)
+    #[arg(long)]
+    render_hints: Vec<PathBuf>,
+
+    /// Background jobs run at the same time
+    #[arg(long, default_value_t = 2)]
+    job_workers: usize,
+
+    /// Background jobs waiting for a worker before submissions are refused
+    #[arg(long, default_value_t = 64)]
+    max_queued_jobs: usize,
+
+    /// Seconds finished background jobs and their results are kept
+    #[arg(long, default_value_t = 3600)]
+    job_retention: u64,
+
+    /// JSON file of tasks run periodically, such as prefetching new model cycles
+    #[arg(long)]
+    schedule: Option<PathBuf>,
+
+    /// URL receiving a POST when new timesteps arrive (repeatable)
+    #[arg(long)]
+    webhook: Vec<String>,
+
+    /// JSON file replacing the webhook payload, with {dataset}, {new_times}... placeholders
     #[arg(long)]
-    jaeger_endpoint: Option<String>,
+    webhook_template: Option<PathBuf>,
+
+    /// SQLite database keeping issued API keys and the disk cache index across
+    /// restarts (requires the sqlite feature)
+    #[arg(long)]
+    database: Option<PathBuf>,
+
+    /// Middleware run on every request, outermost first, e.g. trace,request-tracing,health-check
+    #[arg(long)]
+    middleware: Option<String>,
+
+    /// Origin allowed to read responses cross-origin, or * for any (repeatable)
+    #[arg(long)]
+    cors_origin: Vec<String>,
+
+    /// Smallest response in bytes compressed for clients accepting gzip,
+    /// Brotli or zstd
+    #[arg(long, default_value_t = rossby_vis::pipeline::DEFAULT_COMPRESSION_MIN_SIZE)]
+    compression_min_size: u16,
+
+    /// Save every backend request and response as fixtures in this directory
+    #[arg(long, conflicts_with = "replay")]
+    record: Option<PathBuf>,
+
+    /// Answer backend requests from fixtures saved with --record, without contacting the backend
+    #[arg(long)]
+    replay: Option<PathBuf>,
+
+    /// Inject faults into backend responses for testing, e.g. latency=200ms,errors=0.1,truncate=0.05
+    #[arg(long)]
+    chaos: Option<String>,
+
+    /// Shed low-priority requests (analysis, frame bundles) while system memory use is above this percentage
+    #[arg(long)]
+    shed_memory_percent: Option<f32>,
+
+    /// Shed low-priority requests while CPU use is above this percentage
+    #[arg(long)]
+    shed_cpu_percent: Option<f32>,
+
+    /// Refuse requests with 503 while this many are already being handled
+    #[arg(long, value_parser = parse_max_concurrent_requests)]
+    max_concurrent_requests: Option<usize>,
+
+    /// Requests in flight to the backend: a number, adaptive, adaptive:MIN-MAX or unlimited
+    #[arg(long, default_value = "unlimited")]
+    backend_concurrency: String,
+
+    /// Seconds browsers and CDNs may cache data for a past timestep named in the request
+    #[arg(long)]
+    cache_historical_max_age: Option<u64>,
+
+    /// Seconds browsers and CDNs may cache metadata and other data responses
+    #[arg(long)]
+    cache_recent_max_age: Option<u64>,
+
+    /// Seconds browsers and CDNs may cache frontend assets, 0 to revalidate every use
+    #[arg(long, default_value_t = rossby_vis::freshness::DEFAULT_ASSET_MAX_AGE)]
+    asset_max_age: u64,
+
+    /// Seconds browsers and CDNs may cache the index pages
+    #[arg(long, default_value_t = rossby_vis::freshness::DEFAULT_INDEX_MAX_AGE)]
+    index_max_age: u64,
+
+    /// Cache-Control for frontend assets by extension, as EXT=VALUE (repeatable)
+    #[arg(long)]
+    asset_cache_control: Vec<String>,
+
+    /// Address to listen on, e.g. 0.0.0.0 for all interfaces
+    #[arg(long, default_value = "127.0.0.1")]
+    bind_address: std::net::IpAddr,
+
+    /// Serve HTTPS with a certificate as '[HOST[,HOST...]=]CERT_FILE:KEY_FILE'
+    /// (repeatable); certificates are selected by SNI, one without hosts
+    /// being the default
+    #[arg(long)]
+    tls_cert: Vec<String>,
+
+    /// Obtain a certificate for this host name from an ACME authority
+    /// such as Let's Encrypt (repeatable; requires the acme feature)
+    #[arg(long)]
+    acme_domain: Vec<String>,
+
+    /// Contact email for the ACME account (repeatable)
+    #[arg(long)]
+    acme_email: Vec<String>,
+
+    /// Directory URL of the ACME authority
+    #[arg(long, default_value = rossby_vis::tls::LETS_ENCRYPT_DIRECTORY)]
+    acme_directory: String,
+
+    /// ACME challenge: tls-alpn-01 (on the HTTPS port) or http-01 (on port 80)
+    #[arg(long, default_value = "tls-alpn-01")]
+    acme_challenge: String,
+
+    /// Directory for state kept across restarts, such as ACME certificates
+    #[arg(long, default_value = "data")]
+    data_dir: PathBuf,
+
+    /// Listen on 'ADDR[,tls][,admin]' instead of --bind-address and --port
+    /// (repeatable), e.g. [::]:8080, 0.0.0.0:8443,tls or 127.0.0.1:9090,admin
+    #[arg(long)]
+    listen: Vec<String>,
+
+    /// Serve on this Unix domain socket, e.g. /run/rossby-vis.sock, instead
+    /// of TCP unless --listen is also given
+    #[arg(long)]
+    unix_socket: Option<PathBuf>,
+
+    /// Permissions of the Unix socket file, in octal
+    #[arg(long, default_value = "660", value_parser = parse_socket_mode)]
+    unix_socket_mode: u32,
+
+    /// Run as a systemd Type=notify service: report readiness, ping the
+    /// watchdog and serve sockets passed by socket activation
+    #[arg(long)]
+    systemd: bool,
+
+    /// Sign data responses: none, hmac-sha256 or ed25519
+    #[arg(long, default_value = "none")]
+    sign_responses: String,
+
+    /// Shared secret for hmac-sha256 signatures
+    #[arg(long)]
+    signing_secret: Option<String>,
+
+    /// PKCS#8 file with the ed25519 signing key (created in --data-dir when
+    /// omitted)
+    #[arg(long)]
+    signing_key: Option<PathBuf>,
+
+    /// Key id sent with signatures, e.g. to tell rotated hmac-sha256
+    /// secrets apart (default: "hmac", or the ed25519 key's fingerprint)
+    #[arg(long)]
+    signing_key_id: Option<String>,
+
+    /// Pack Earth data arrays as quantized deltas unless a request asks for
+    /// plain arrays with packed=false
+    #[arg(long)]
+    packed_data: bool,
+
+    /// Quantization step of packed data arrays, unless a request gives its
+    /// own precision
+    #[arg(long, default_value_t = rossby_vis::packing::DEFAULT_PRECISION)]
+    pack_precision: f64,
+
+    /// Unit system the frontend shows values in by default: si, metric,
+    /// imperial or kt
+    #[arg(long, default_value = "si")]
+    units: String,
+
+    /// Seconds a backend probe result is reused by /health
+    #[arg(long, default_value_t = 10)]
+    health_probe_ttl: u64,
+
+    /// Seconds the /health backend probe waits for the metadata
+    #[arg(long, default_value_t = 5)]
+    health_probe_timeout: u64,
+
+    /// Megabytes of backend responses kept in memory for identical
+    /// requests, 0 to disable the cache
+    #[arg(long, default_value_t = 0)]
+    cache_size: usize,
+
+    /// Seconds a backend response is served from the cache
+    #[arg(long, default_value_t = 60)]
+    cache_ttl: u64,
+
+    /// Directory keeping backend responses across restarts, below the
+    /// memory cache
+    #[arg(long)]
+    disk_cache_dir: Option<PathBuf>,
+
+    /// Megabytes of backend responses kept in the disk cache
+    #[arg(long, default_value_t = 1024)]
+    disk_cache_size: u64,
+
+    /// Seconds a backend response is served from the disk cache
+    #[arg(long, default_value_t = 3600)]
+    disk_cache_ttl: u64,
+
+    /// Times a GET to the backend is retried after a connection failure,
+    /// timeout or 502/503/504, 0 to disable retries
+    #[arg(long, default_value_t = 0)]
+    backend_retries: u32,
+
+    /// Milliseconds before the first retry, doubled for each further one
+    #[arg(long, default_value_t = 100)]
+    backend_retry_delay_ms: u64,
+
+    /// Fraction of each retry delay, between 0 and 1, taken off at random
+    #[arg(long, default_value_t = rossby_vis::retry::DEFAULT_RETRY_JITTER)]
+    backend_retry_jitter: f64,
+
+    /// Backend failures in a row that open the circuit, stopping backend
+    /// requests for a while, 0 to disable the circuit breaker
+    #[arg(long, default_value_t = 0)]
+    circuit_breaker_failures: u32,
+
+    /// Seconds the circuit stays open before the backend is probed
+    #[arg(long, default_value_t = 30)]
+    circuit_breaker_cooldown: u64,
+
+    /// Probe requests let through at once while the circuit is half-open
+    #[arg(long, default_value_t = rossby_vis::resilience::DEFAULT_PROBES)]
+    circuit_breaker_probes: u32,
 }
 
-#[tokio::main]
-async fn main() -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
-    // Parse command line arguments
-    let args = Args::parse();
+fn main() -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    // Parse command line arguments, over the settings file and environment
+    let cli: Vec<OsString> = std::env::args_os().collect();
+    let startup_args = layered_args(&Args::command(), cli.clone(), std::env::vars())?;
+    let args = Args::parse_from(&startup_args);
+
+    // Taking the sockets systemd passed clears their variables, which is
+    // only sound before the runtime starts its threads
+    let listen_fds = if args.systemd {
+        systemd::take_listen_fds()
+    } else {
+        0
+    };
+
+    let runtime = tokio::runtime::Builder::new_multi_thread()
+        .enable_all()
+        .build()?;
+    let result = runtime.block_on(run(cli, startup_args, args, listen_fds));
+    // However the server stopped, write out the log lines still buffered
+    shutdown_logging();
+    result
+}
+
+/// Start logging and the server, and run until asked to stop
+async fn run(
+    cli: Vec<OsString>,
+    startup_args: Vec<OsString>,
+    args: Args,
+    listen_fds: usize,
+) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    let mut reloadable = reloadable_settings(&args)?;
 
     // Create logging configuration
     let mut logging_config = LoggingConfig::from_env();
 
     // Override with command line arguments
     logging_config.level = args.log_level;
+    if let Some(targets) = args.log_targets {
+        logging_config.targets = parse_log_targets(&targets)?;
+    }
     logging_config.environment = args.environment;
     logging_config.service_name = args.service_name;
     logging_config.enable_request_tracing = !args.disable_request_tracing;
     logging_config.enable_metrics = !args.disable_metrics;
+    logging_config.heartbeat_interval =
+        Some(Duration::from_secs(args.heartbeat_interval)).filter(|d| !d.is_zero());
 
     if let Ok(format) = args.log_format.parse::<LogFormat>() {
         logging_config.format = format;
     }
 
-    if let Some(endpoint) = args.jaeger_endpoint {
-        logging_config.jaeger_endpoint = Some(endpoint);
+    if let Some(endpoint) = args.otlp_endpoint {
+        logging_config.otlp_endpoint = Some(endpoint);
         logging_config.enable_distributed_tracing = true;
     }
+    if let Some(protocol) = args.otlp_protocol {
+        logging_config.otlp_protocol = protocol.parse::<OtlpProtocol>()?;
+    }
+    if let Some(ratio) = args.trace_sample_ratio {
+        logging_config.trace_sample_ratio = ratio;
+    }
+
+    if let Some(dsn) = args.sentry_dsn {
+        logging_config.sentry_dsn = Some(dsn);
+    }
+
+    if let Some(target) = args.syslog {
+        let mut syslog = SyslogConfig::new(
+            target.parse::<SyslogTarget>()?,
+            &logging_config.service_name,
+        );
+        syslog.facility = parse_facility(&args.syslog_facility)?;
+        logging_config.syslog = Some(syslog);
+    }
+
+    if let Some(path) = args.log_file {
+        let mut file = FileLogConfig::new(path);
+        file.rotation = args.log_rotation.parse::<LogRotation>()?;
+        file.max_files = args.log_max_files.filter(|n| *n > 0);
+        file.max_size = args
+            .log_max_size
+            .filter(|mb| *mb > 0)
+            .map(|mb| {
+                mb.checked_mul(1024 * 1024)
+                    .ok_or("--log-max-size is too large")
+            })
+            .transpose()?;
+        logging_config.file = Some(file);
+    }
+
+    if let Some(address) = args.statsd {
+        let mut statsd = StatsdConfig::new(&address);
+        statsd.prefix = args.statsd_prefix;
+        statsd.flavor = args.statsd_flavor.parse::<StatsdFlavor>()?;
+        if let Some(tags) = args.statsd_tags {
+            statsd.tags = parse_tags(&tags)?;
+        }
+        logging_config.statsd = Some(statsd);
+    }
+
+    if let Some(target) = args.access_log {
+        let mut access_log = AccessLogConfig::new(target.parse::<AccessLogTarget>()?);
+        access_log.format = args.access_log_format.parse::<AccessLogFormat>()?;
+        logging_config.access_log = Some(access_log);
+    }
 
     // Initialize comprehensive logging system
-    init_logging(logging_config)?;
+    let log_level = init_logging(logging_config)?;
 
-    // Run the server
-    run_server(args.port, args.api_url).await?;
+    // Build the server configuration
+    let (api_url, datasets) = parse_api_urls(&args.api_url)?;
+    let mut server_config = ServerConfig::new(args.port, api_url);
+    server_config.datasets = datasets;
+    server_config.land_sea_mask.variable = args.land_sea_mask_var;
+    server_config.land_sea_mask.auto_mask = args.auto_land_sea_mask;
+    if let Some(path) = args.land_sea_mask_file {
+        server_config.land_sea_mask.load_file(&path)?;
+    }
+    server_config.backend_schema = args.backend_schema.parse::<BackendSchema>()?;
+    server_config.strict_query = args.strict_query;
+    server_config.request_limits = rossby_vis::limits::RequestLimits {
+        max_query_length: args.max_query_length,
+        max_vars: args.max_vars,
+        max_header_bytes: args.max_header_bytes,
+    };
+    server_config.admin_token = args.admin_token;
+    server_config.auth.basic = args
+        .basic_auth
+        .iter()
+        .map(|user| user.parse())
+        .collect::<Result<_, _>>()?;
+    server_config.auth.api_keys = args
+        .api_key
+        .iter()
+        .map(|api_key| api_key.parse())
+        .collect::<Result<_, _>>()?;
+    if let Some(path) = &args.api_keys_file {
+        server_config
+            .auth
+            .api_keys
+            .extend(rossby_vis::auth::load_api_keys(path)?);
+    }
+    server_config.auth.jwt = rossby_vis::jwt::JwtConfig {
+        issuer: args.jwt_issuer,
+        jwks_url: args.jwt_jwks_url,
+        audiences: args.jwt_audience,
+        jwks_refresh: Duration::from_secs(args.jwt_jwks_refresh),
+    };
+    if let Some(title) = args.site_title {
+        server_config.site.title = title;
+    }
+    server_config.base_path = args.base_path;
+    if let Some(api_base) = args.frontend_api_base {
+        server_config.site.api_base = api_base;
+    }
+    server_config.site.contact_email = args.contact_email;
+    if args.dev {
+        server_config.dev_assets = Some(args.dev_dir);
+    }
+    server_config.cross_origin_isolation = args.cross_origin_isolation;
+    server_config.mobile_index = args.mobile_index;
+    server_config.client_error_rate = args.client_error_rate;
+    if let Some(url) = args.ocean_currents_url {
+        server_config.ocean_currents.endpoint = Some(url.parse::<BackendEndpoint>()?);
+    }
+    if let Some(vars) = args.ocean_current_vars {
+        server_config.ocean_currents.components = Some(parse_current_components(&vars)?);
+    }
+    server_config.earth_grid_limit.max_points = args.earth_max_grid_points;
+    server_config.earth_grid_limit.downsample = !args.reject_large_grids;
+    for path in &args.derived_variables {
+        server_config.derived_variables.load(path)?;
+    }
+    for path in &args.transforms {
+        server_config.transforms.load(path)?;
+    }
+    server_config.render_hints = reloadable.render_hints.clone();
+    server_config.jobs.workers = args.job_workers;
+    server_config.jobs.max_queued = args.max_queued_jobs;
+    server_config.jobs.retention = Duration::from_secs(args.job_retention);
+    if let Some(path) = &args.schedule {
+        server_config.schedule.load(path)?;
+    }
+    for url in &args.webhook {
+        server_config.webhooks.add_url(url)?;
+    }
+    if let Some(path) = &args.webhook_template {
+        server_config.webhooks.load_template(path)?;
+    }
+    server_config.database = args.database;
+    if let Some(pipeline) = &args.middleware {
+        server_config.middleware = parse_pipeline(pipeline)?;
+    }
+    server_config.cors.origins = args.cors_origin;
+    server_config.compression.min_size = args.compression_min_size;
+    server_config.recording = match (args.record, args.replay) {
+        (Some(dir), _) => Some(Recording::Record(dir)),
+        (None, Some(dir)) => Some(Recording::Replay(dir)),
+        (None, None) => None,
+    };
+    server_config.chaos = args.chaos.as_deref().map(str::parse).transpose()?;
+    server_config.load_shedding.max_memory_percent = args.shed_memory_percent;
+    server_config.load_shedding.max_cpu_percent = args.shed_cpu_percent;
+    server_config.load_shedding.max_concurrent_requests = args.max_concurrent_requests;
+    server_config.backend_concurrency = args.backend_concurrency.parse()?;
+    server_config.cache_policy.historical_max_age = args.cache_historical_max_age;
+    server_config.cache_policy.recent_max_age = args.cache_recent_max_age;
+    server_config.asset_cache.max_age = args.asset_max_age;
+    server_config.asset_cache.index_max_age = args.index_max_age;
+    for spec in &args.asset_cache_control {
+        server_config.asset_cache.add_override(spec)?;
+    }
+    server_config.bind_address = args.bind_address;
+    server_config.tls.certificates = args
+        .tls_cert
+        .iter()
+        .map(|certificate| certificate.parse::<TlsCertificate>())
+        .collect::<Result<_, _>>()?;
+    if !args.acme_domain.is_empty() {
+        let mut acme = AcmeConfig::new(args.acme_domain);
+        acme.contact = args.acme_email;
+        acme.directory_url = args.acme_directory;
+        acme.challenge = args.acme_challenge.parse()?;
+        server_config.tls.acme = Some(acme);
+    }
+    server_config.data_dir = args.data_dir;
+    server_config.listeners = args
+        .listen
+        .iter()
+        .map(|listener| listener.parse::<ListenAddress>())
+        .collect::<Result<_, _>>()?;
+    server_config.unix_socket = args.unix_socket;
+    server_config.unix_socket_mode = args.unix_socket_mode;
+    server_config.systemd = args.systemd;
+    server_config.listen_fds = listen_fds;
+    server_config.signing = SigningConfig {
+        algorithm: args.sign_responses.parse()?,
+        secret: args.signing_secret,
+        key_file: args.signing_key,
+        key_id: args.signing_key_id,
+    };
+    server_config.packing = PackingConfig {
+        default: args.packed_data,
+        precision: args.pack_precision,
+    };
+    server_config.units = args.units.parse()?;
+    server_config.health_probe.ttl = Duration::from_secs(args.health_probe_ttl);
+    server_config.health_probe.timeout = Duration::from_secs(args.health_probe_timeout);
+    let disk_cache_bytes = args
+        .disk_cache_size
+        .checked_mul(1024 * 1024)
+        .ok_or("--disk-cache-size is too large")?;
+    server_config.response_cache = ResponseCacheConfig {
+        max_bytes: args
+            .cache_size
+            .checked_mul(1024 * 1024)
+            .ok_or("--cache-size is too large")?,
+        ttl: Duration::from_secs(args.cache_ttl),
+        disk: args.disk_cache_dir.map(|dir| DiskCacheConfig {
+            dir,
+            max_bytes: disk_cache_bytes,
+            ttl: Duration::from_secs(args.disk_cache_ttl),
+        }),
+    };
+    if !(0.0..=1.0).contains(&args.backend_retry_jitter) {
+        return Err("--backend-retry-jitter must be between 0 and 1".into());
+    }
+    server_config.backend_retry = RetryPolicy {
+        retries: args.backend_retries,
+        base_delay: Duration::from_millis(args.backend_retry_delay_ms),
+        jitter: args.backend_retry_jitter,
+    };
+    if args.circuit_breaker_probes == 0 {
+        return Err("--circuit-breaker-probes must be at least 1".into());
+    }
+    server_config.circuit_breaker = CircuitBreakerConfig {
+        failure_threshold: args.circuit_breaker_failures,
+        cooldown: Duration::from_secs(args.circuit_breaker_cooldown),
+        probes: args.circuit_breaker_probes,
+    };
+    if let Some(path) = args.analytics_snippet {
+        server_config.site.analytics_snippet = Some(
+            std::fs::read_to_string(&path)
+                .map_err(|e| format!("Cannot read analytics snippet {}: {}", path.display(), e))?,
+        );
+    }
+    server_config.log_level = Some(log_level);
+    server_config.backend_client.ca_certs = args.backend_ca_cert;
+    server_config.backend_client.insecure_tls = args.backend_insecure_tls;
+    server_config.backend_client.proxy = args.backend_proxy.parse::<BackendProxy>()?;
+    server_config.backend_client.pool_idle_timeout =
+        args.backend_pool_idle_timeout.map(Duration::from_secs);
+    server_config.backend_client.pool_max_idle_per_host = args.backend_pool_max_idle;
+    server_config.backend_client.tcp_keepalive =
+        args.backend_tcp_keepalive.map(Duration::from_secs);
+    server_config.backend_client.http_version = args.backend_http_version.parse()?;
+    let timeout = |secs: u64| (secs > 0).then(|| Duration::from_secs(secs));
+    server_config.backend_client.connect_timeout = timeout(args.backend_connect_timeout);
+    server_config.backend_client.request_timeout = timeout(args.backend_request_timeout);
+    server_config.backend_client.max_connection_age = args
+        .backend_connection_max_age
+        .filter(|secs| *secs > 0)
+        .map(Duration::from_secs);
+    server_config.backend_client.user_agent = args.backend_user_agent;
+    server_config.backend_client.headers = args
+        .backend_header
+        .iter()
+        .map(|header| header.parse::<BackendHeader>())
+        .collect::<Result<_, _>>()?;
+
+    // Run the server, reloading what can be reloaded on SIGHUP and stopping
+    // gracefully on SIGINT or SIGTERM
+    let server = run_server_with_config(server_config).await?;
+    let state = server.state().clone();
+    tokio::spawn(reload::on_hangup(move || {
+        reload_configuration(&state, &cli, &startup_args, &mut reloadable)
+    }));
+    server.stop_on(shutdown_requested()).await
+}
+
+/// Resolves once the process is asked to stop, by SIGINT or SIGTERM
+async fn shutdown_requested() {
+    let interrupt = async {
+        if let Err(e) = tokio::signal::ctrl_c().await {
+            warn!("Cannot stop gracefully on SIGINT: {}", e);
+            std::future::pending::<()>().await;
+        }
+    };
+    #[cfg(unix)]
+    let terminate = async {
+        use tokio::signal::unix::{signal, SignalKind};
+        match signal(SignalKind::terminate()) {
+            Ok(mut terminate) => {
+                terminate.recv().await;
+            }
+            Err(e) => {
+                warn!("Cannot stop gracefully on SIGTERM: {}", e);
+                std::future::pending::<()>().await;
+            }
+        }
+    };
+    #[cfg(not(unix))]
+    let terminate = std::future::pending::<()>();
+
+    tokio::select! {
+        _ = interrupt => info!("Received SIGINT, shutting down"),
+        _ = terminate => info!("Received SIGTERM, shutting down"),
+    }
+}
 
-    Ok(())
+/// The settings of `args` that a reload applies
+fn reloadable_settings(
+    args: &Args,
+) -> Result<ReloadableSettings, Box<dyn std::error::Error + Send + Sync>> {
+    // The filter is made as for init_logging, environment included
+    let mut logging_config = LoggingConfig::from_env();
+    logging_config.level = args.log_level.clone();
+    if let Some(targets) = &args.log_targets {
+        logging_config.targets = parse_log_targets(targets)?;
+    }
+    let mut settings = ReloadableSettings {
+        log_filter: logging_config.filter_directives(),
+        cache_ttl: Duration::from_secs(args.cache_ttl),
+        disk_cache_ttl: Duration::from_secs(args.disk_cache_ttl),
+        client_error_rate: args.client_error_rate,
+        render_hints: Default::default(),
+    };
+    for path in &args.render_hints {
+        settings.render_hints.load(path)?;
+    }
+    Ok(settings)
+}
+
+/// Read the configuration again and apply its reloadable settings over
+/// `current`, logging what changed and what needs a restart
+fn reload_configuration(
+    state: &AppState,
+    cli: &[OsString],
+    startup_args: &[OsString],
+    current: &mut ReloadableSettings,
+) {
+    let reload = || -> Result<_, Box<dyn std::error::Error + Send + Sync>> {
+        let command = Args::command();
+        let layered = layered_args(&command, cli.to_vec(), std::env::vars())?;
+        // The first line of clap's message, without its usage hint
+        let args = Args::try_parse_from(&layered).map_err(|e| {
+            let message = e.to_string();
+            let first = message.lines().next().unwrap_or_default();
+            first.trim_start_matches("error: ").to_string()
+        })?;
+        let next = reloadable_settings(&args)?;
+        let restart = reload::requiring_restart(changed_options(&command, startup_args, &layered)?);
+        let changes = reload::apply(state, current, &next)?;
+        Ok((next, changes, restart))
+    };
+    match reload() {
+        Ok((next, changes, restart)) => {
+            *current = next;
+            info!("{}", reload::summary(&changes, &restart));
+        }
+        Err(e) => error!(
+            "Configuration not reloaded, keeping the previous one: {}",
+            e
+        ),
+    }
 }
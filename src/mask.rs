@@ -0,0 +1,244 @@
+//! Land/sea masking for Earth overlays
+//!
+//! Ocean-only fields such as sea surface temperature are usually stored with
+//! meaningless or fill values over land. Masking them with the dataset's
+//! land-sea mask (or one loaded from an auxiliary file) produces much cleaner
+//! visualizations. Masked cells are emitted as `null` in Earth JSON.
+
+use serde::Deserialize;
+use serde_json::Value;
+use std::path::Path;
+use utoipa::ToSchema;
+
+use crate::error::AppError;
+
+/// Variable names commonly used for a land-sea mask
+const MASK_VARIABLE_NAMES: [&str; 5] = ["lsm", "land_sea_mask", "landmask", "LANDMASK", "mask"];
+/// Mask values at or above this fraction are treated as land
+pub const LAND_FRACTION_THRESHOLD: f64 = 0.5;
+
+/// Keywords identifying variables that are only meaningful over the ocean
+const OCEAN_KEYWORDS: [&str; 9] = [
+    "sst",
+    "sea surface",
+    "sea_surface",
+    "wave",
+    "swh",
+    "ocean",
+    "current",
+    "salinity",
+    "sea ice",
+];
+/// Keywords identifying variables that are only meaningful over land
+const LAND_KEYWORDS: [&str; 5] = ["soil", "swvl", "vegetation", "leaf area", "snow depth"];
+
+/// Which surface type to hide
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, ToSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum MaskMode {
+    /// Hide values over land (for ocean-only variables)
+    Land,
+    /// Hide values over the ocean (for land-only variables)
+    Ocean,
+    /// Disable masking, including automatic masking
+    None,
+}
+
+/// Land-sea mask settings
+#[derive(Debug, Clone, Default)]
+pub struct LandSeaMaskConfig {
+    /// Dataset variable holding the mask; auto-detected when not set
+    pub variable: Option<String>,
+    /// Mask values loaded from an auxiliary file, in latitude-major grid order
+    pub values: Option<Vec<f64>>,
+    /// Mask ocean-only variables over land (and land-only variables over
+    /// the ocean) when the request does not specify a mask
+    pub auto_mask: bool,
+}
+
+impl LandSeaMaskConfig {
+    /// Load mask values from an auxiliary JSON file.
+    ///
+    /// The file may contain either a bare array of land fractions or a Rossby
+    /// `/data` response whose first data variable is the mask.
+    pub fn load_file(&mut self, path: &Path) -> Result<(), AppError> {
+        let contents = std::fs::read(path)?;
+        let document: Value = serde_json::from_slice(&contents).map_err(|e| {
+            AppError::ConfigError(format!(
+                "Invalid land-sea mask file {}: {}",
+                path.display(),
+                e
+            ))
+        })?;
+        self.values = Some(parse_mask_document(&document).ok_or_else(|| {
+            AppError::ConfigError(format!(
+                "Land-sea mask file {} contains no mask array",
+                path.display()
+            ))
+        })?);
+        Ok(())
+    }
+
+    /// Determine which mask variable to read from the dataset metadata
+    pub fn mask_variable(&self, metadata: &Value) -> Option<String> {
+        let variables = metadata.get("variables")?.as_object()?;
+        if let Some(configured) = &self.variable {
+            return variables
+                .contains_key(configured)
+                .then(|| configured.clone());
+        }
+        MASK_VARIABLE_NAMES
+            .iter()
+            .find(|name| variables.contains_key(**name))
+            .map(|name| name.to_string())
+    }
+
+    /// Resolve the effective mask mode for a variable
+    ///
+    /// An explicit request mode wins; otherwise automatic masking applies when enabled.
+    pub fn effective_mode(
+        &self,
+        requested: Option<MaskMode>,
+        variable: &str,
+        long_name: &str,
+    ) -> Option<MaskMode> {
+        match requested {
+            Some(MaskMode::None) => None,
+            Some(mode) => Some(mode),
+            None if self.auto_mask => default_mask_for(variable, long_name),
+            None => None,
+        }
+    }
+}
+
+/// Suggested mask for a variable based on its name and long name
+pub fn default_mask_for(variable: &str, long_name: &str) -> Option<MaskMode> {
+    let search_text = format!("{} {}", variable, long_name).to_lowercase();
+
+    if OCEAN_KEYWORDS.iter().any(|k| search_text.contains(k)) {
+        Some(MaskMode::Land)
+    } else if LAND_KEYWORDS.iter().any(|k| search_text.contains(k)) {
+        Some(MaskMode::Ocean)
+    } else {
+        None
+    }
+}
+
+/// Replace masked cells with NaN.
+///
+/// Returns an error when the mask does not cover the same grid as the data.
+pub fn apply_mask(data: &mut [f64], mask: &[f64], mode: MaskMode) -> Result<(), AppError> {
+    if data.len() != mask.len() {
+        return Err(AppError::ProxyError(format!(
+            "Land-sea mask has {} points but the field has {}",
+            mask.len(),
+            data.len()
+        )));
+    }
+
+    for (value, land_fraction) in data.iter_mut().zip(mask) {
+        let is_land = *land_fraction >= LAND_FRACTION_THRESHOLD;
+        let hide = match mode {
+            MaskMode::Land => is_land,
+            MaskMode::Ocean => !is_land,
+            MaskMode::None => false,
+        };
+        if hide {
+            *value = f64::NAN;
+        }
+    }
+
+    Ok(())
+}
+
+fn parse_mask_document(document: &Value) -> Option<Vec<f64>> {
+    let array = match document {
+        Value::Array(values) => values,
+        _ => document
+            .get("data")?
+            .as_object()?
+            .values()
+            .next()?
+            .as_array()?,
+    };
+    Some(
+        array
+            .iter()
+            .map(|v| v.as_f64().unwrap_or(f64::NAN))
+            .collect(),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn test_apply_mask_modes() {
+        let mask = [1.0, 0.0, 0.7, 0.2];
+
+        let mut data = vec![1.0, 2.0, 3.0, 4.0];
+        apply_mask(&mut data, &mask, MaskMode::Land).unwrap();
+        assert!(data[0].is_nan() && data[2].is_nan());
+        assert_eq!((data[1], data[3]), (2.0, 4.0));
+
+        let mut data = vec![1.0, 2.0, 3.0, 4.0];
+        apply_mask(&mut data, &mask, MaskMode::Ocean).unwrap();
+        assert!(data[1].is_nan() && data[3].is_nan());
+        assert_eq!((data[0], data[2]), (1.0, 3.0));
+
+        let mut data = vec![1.0];
+        assert!(apply_mask(&mut data, &mask, MaskMode::Land).is_err());
+    }
+
+    #[test]
+    fn test_default_mask_for() {
+        assert_eq!(
+            default_mask_for("sst", "Sea surface temperature"),
+            Some(MaskMode::Land)
+        );
+        assert_eq!(
+            default_mask_for("swvl1", "Volumetric soil water layer 1"),
+            Some(MaskMode::Ocean)
+        );
+        assert_eq!(default_mask_for("t2m", "2 metre temperature"), None);
+    }
+
+    #[test]
+    fn test_effective_mode() {
+        let mut config = LandSeaMaskConfig::default();
+        assert_eq!(config.effective_mode(None, "sst", ""), None);
+        assert_eq!(
+            config.effective_mode(Some(MaskMode::Ocean), "sst", ""),
+            Some(MaskMode::Ocean)
+        );
+
+        config.auto_mask = true;
+        assert_eq!(config.effective_mode(None, "sst", ""), Some(MaskMode::Land));
+        assert_eq!(config.effective_mode(Some(MaskMode::None), "sst", ""), None);
+    }
+
+    #[test]
+    fn test_mask_variable_detection() {
+        let metadata = json!({"variables": {"lsm": {}, "sst": {}}});
+        let mut config = LandSeaMaskConfig::default();
+        assert_eq!(config.mask_variable(&metadata), Some("lsm".to_string()));
+
+        config.variable = Some("landmask".to_string());
+        assert_eq!(config.mask_variable(&metadata), None);
+    }
+
+    #[test]
+    fn test_parse_mask_document() {
+        assert_eq!(
+            parse_mask_document(&json!([0.0, 1.0])),
+            Some(vec![0.0, 1.0])
+        );
+        assert_eq!(
+            parse_mask_document(&json!({"data": {"lsm": [1.0]}})),
+            Some(vec![1.0])
+        );
+        assert_eq!(parse_mask_document(&json!({"other": 1})), None);
+    }
+}
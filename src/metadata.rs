@@ -0,0 +1,333 @@
+//! Validation of the backend `/metadata` document
+//!
+//! Handlers read metadata through `Option` chains, which can only tell that
+//! *something* is wrong. This module walks the document once and reports each
+//! missing or malformed field by its JSON path, so backend problems can be
+//! fixed without reading the proxy source.
+
+use serde_json::{Map, Value};
+use std::{collections::HashMap, fmt};
+
+use crate::{
+    error::AppError,
+    grid::{is_latitude_dimension, is_longitude_dimension},
+};
+
+/// A single problem found in the metadata document
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MetadataIssue {
+    /// Dotted path of the offending field, e.g. `dimensions.latitude.size`
+    pub path: String,
+    /// What is wrong with the field
+    pub problem: String,
+}
+
+impl MetadataIssue {
+    fn new(path: impl Into<String>, problem: impl Into<String>) -> Self {
+        Self {
+            path: path.into(),
+            problem: problem.into(),
+        }
+    }
+}
+
+impl fmt::Display for MetadataIssue {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}: {}", self.path, self.problem)
+    }
+}
+
+/// Check the metadata document and return every problem found.
+///
+/// An empty result means the document has everything the proxy relies on:
+/// numeric coordinate arrays, sized dimensions, latitude and longitude axes
+/// that agree with their dimensions, and variables whose dimensions exist.
+pub fn validate_metadata(metadata: &Value) -> Vec<MetadataIssue> {
+    let mut issues = Vec::new();
+
+    let Some(root) = metadata.as_object() else {
+        issues.push(MetadataIssue::new("$", "document must be a JSON object"));
+        return issues;
+    };
+
+    let coordinates = required_object(root, "coordinates", &mut issues);
+    let dimensions = required_object(root, "dimensions", &mut issues);
+    let variables = required_object(root, "variables", &mut issues);
+
+    let mut coordinate_lengths = HashMap::new();
+    if let Some(coordinates) = coordinates {
+        for (name, values) in coordinates {
+            let path = format!("coordinates.{}", name);
+            match values.as_array() {
+                Some(array) => {
+                    if let Some(index) = array.iter().position(|v| !v.is_number()) {
+                        issues.push(MetadataIssue::new(
+                            format!("{}[{}]", path, index),
+                            "coordinate values must be numbers",
+                        ));
+                    }
+                    coordinate_lengths.insert(name.as_str(), array.len() as u64);
+                }
+                None => issues.push(MetadataIssue::new(path, "must be an array of numbers")),
+            }
+        }
+    }
+
+    if let Some(dimensions) = dimensions {
+        for (name, dimension) in dimensions {
+            let path = format!("dimensions.{}.size", name);
+            match dimension.get("size") {
+                Some(size) if size.is_u64() => {
+                    let expected = coordinate_lengths.get(name.as_str()).copied();
+                    if let Some(length) = expected.filter(|length| Some(*length) != size.as_u64()) {
+                        issues.push(MetadataIssue::new(
+                            path,
+                            format!("is {} but coordinates.{} has {} values", size, name, length),
+                        ));
+                    }
+                }
+                Some(_) => issues.push(MetadataIssue::new(path, "must be a non-negative integer")),
+                None => issues.push(MetadataIssue::new(path, "is missing")),
+            }
+        }
+    }
+
+    if coordinates.is_some() && dimensions.is_some() {
+        for (axis, is_axis) in [
+            ("latitude", is_latitude_dimension as fn(&str) -> bool),
+            ("longitude", is_longitude_dimension),
+        ] {
+            let coordinate = coordinates
+                .into_iter()
+                .flat_map(|c| c.keys())
+                .find(|name| is_axis(name));
+            match coordinate {
+                Some(name) if dimensions.is_some_and(|d| !d.contains_key(name)) => {
+                    issues.push(MetadataIssue::new(
+                        format!("dimensions.{}", name),
+                        format!("is missing for the {} coordinate", axis),
+                    ));
+                }
+                Some(_) => {}
+                None => issues.push(MetadataIssue::new(
+                    format!("coordinates.{}", axis),
+                    format!("is missing; a {} axis is required for map overlays", axis),
+                )),
+            }
+        }
+    }
+
+    if let Some(variables) = variables {
+        for (name, variable) in variables {
+            validate_variable(name, variable, dimensions, &mut issues);
+        }
+    }
+
+    issues
+}
+
+/// Error describing why the metadata cannot be used, listing every issue.
+///
+/// Falls back to `fallback` when validation finds nothing, which means the
+/// caller needs something beyond the structural checks.
+pub fn invalid_metadata_error(metadata: &Value, fallback: &str) -> AppError {
+    let issues = validate_metadata(metadata);
+    if issues.is_empty() {
+        return AppError::ProxyError(fallback.to_string());
+    }
+
+    let details: Vec<String> = issues.iter().map(ToString::to_string).collect();
+    AppError::ProxyError(format!(
+        "Invalid backend metadata ({} problem{}): {}",
+        issues.len(),
+        if issues.len() == 1 { "" } else { "s" },
+        details.join("; ")
+    ))
+}
+
+fn required_object<'a>(
+    root: &'a Map<String, Value>,
+    key: &str,
+    issues: &mut Vec<MetadataIssue>,
+) -> Option<&'a Map<String, Value>> {
+    match root.get(key) {
+        Some(Value::Object(map)) => Some(map),
+        Some(_) => {
+            issues.push(MetadataIssue::new(key, "must be an object"));
+            None
+        }
+        None => {
+            issues.push(MetadataIssue::new(key, "is missing"));
+            None
+        }
+    }
+}
+
+fn validate_variable(
+    name: &str,
+    variable: &Value,
+    dimensions: Option<&Map<String, Value>>,
+    issues: &mut Vec<MetadataIssue>,
+) {
+    let path = format!("variables.{}", name);
+    let Some(variable) = variable.as_object() else {
+        issues.push(MetadataIssue::new(path, "must be an object"));
+        return;
+    };
+
+    match variable.get("dimensions") {
+        Some(Value::Array(names)) => {
+            for (index, dimension) in names.iter().enumerate() {
+                let dimension_path = format!("{}.dimensions[{}]", path, index);
+                match dimension.as_str() {
+                    Some(dimension) if dimensions.is_some_and(|d| !d.contains_key(dimension)) => {
+                        issues.push(MetadataIssue::new(
+                            dimension_path,
+                            format!("refers to unknown dimension '{}'", dimension),
+                        ));
+                    }
+                    Some(_) => {}
+                    None => issues.push(MetadataIssue::new(
+                        dimension_path,
+                        "must be a dimension name",
+                    )),
+                }
+            }
+        }
+        Some(_) => issues.push(MetadataIssue::new(
+            format!("{}.dimensions", path),
+            "must be an array of dimension names",
+        )),
+        None => issues.push(MetadataIssue::new(
+            format!("{}.dimensions", path),
+            "is missing",
+        )),
+    }
+
+    match variable.get("attributes") {
+        Some(Value::Object(attributes)) => {
+            for key in ["units", "long_name"] {
+                if attributes.get(key).is_some_and(|v| !v.is_string()) {
+                    issues.push(MetadataIssue::new(
+                        format!("{}.attributes.{}", path, key),
+                        "must be a string",
+                    ));
+                }
+            }
+        }
+        Some(_) => issues.push(MetadataIssue::new(
+            format!("{}.attributes", path),
+            "must be an object",
+        )),
+        None => {}
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn valid_metadata() -> Value {
+        json!({
+            "coordinates": {
+                "latitude": [10.0, 0.0],
+                "longitude": [0.0, 10.0, 20.0],
+                "time": [700464.0]
+            },
+            "dimensions": {
+                "latitude": {"size": 2},
+                "longitude": {"size": 3},
+                "time": {"size": 1}
+            },
+            "variables": {
+                "t2m": {
+                    "dimensions": ["time", "latitude", "longitude"],
+                    "attributes": {"units": "K", "long_name": "2 metre temperature"}
+                }
+            }
+        })
+    }
+
+    fn paths(metadata: &Value) -> Vec<String> {
+        validate_metadata(metadata)
+            .into_iter()
+            .map(|issue| issue.path)
+            .collect()
+    }
+
+    #[test]
+    fn test_valid_metadata_has_no_issues() {
+        assert!(validate_metadata(&valid_metadata()).is_empty());
+    }
+
+    #[test]
+    fn test_missing_sections() {
+        assert_eq!(paths(&json!([])), vec!["$"]);
+        assert_eq!(
+            paths(&json!({"coordinates": {}})),
+            vec!["dimensions", "variables"]
+        );
+    }
+
+    #[test]
+    fn test_malformed_coordinates_and_dimensions() {
+        let mut metadata = valid_metadata();
+        metadata["coordinates"]["longitude"] = json!([0.0, "10", 20.0]);
+        metadata["dimensions"]["latitude"]["size"] = json!(5);
+        metadata["dimensions"]["time"] = json!({});
+
+        let issues = validate_metadata(&metadata);
+        let rendered: Vec<String> = issues.iter().map(ToString::to_string).collect();
+        assert_eq!(
+            rendered,
+            vec![
+                "coordinates.longitude[1]: coordinate values must be numbers",
+                "dimensions.latitude.size: is 5 but coordinates.latitude has 2 values",
+                "dimensions.time.size: is missing",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_missing_horizontal_axis() {
+        let mut metadata = valid_metadata();
+        metadata["coordinates"]
+            .as_object_mut()
+            .unwrap()
+            .remove("longitude");
+
+        assert_eq!(paths(&metadata), vec!["coordinates.longitude"]);
+    }
+
+    #[test]
+    fn test_variable_issues() {
+        let mut metadata = valid_metadata();
+        metadata["variables"]["bad"] = json!({
+            "dimensions": ["time", "level"],
+            "attributes": {"units": 1}
+        });
+        metadata["variables"]["worse"] = json!({"attributes": []});
+
+        assert_eq!(
+            paths(&metadata),
+            vec![
+                "variables.bad.dimensions[1]",
+                "variables.bad.attributes.units",
+                "variables.worse.dimensions",
+                "variables.worse.attributes",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_invalid_metadata_error_message() {
+        let error = invalid_metadata_error(&json!({}), "Invalid grid metadata");
+        let message = error.to_string();
+        assert!(message.contains("3 problems"));
+        assert!(message.contains("coordinates: is missing"));
+
+        let error = invalid_metadata_error(&valid_metadata(), "Invalid grid metadata");
+        assert_eq!(error.to_string(), "Proxy error: Invalid grid metadata");
+    }
+}
@@ -4,15 +4,59 @@
 //! structured logging, and performance monitoring.
 
 use axum::{
-    extract::State,
-    http::{HeaderMap, HeaderValue, Request},
+    body::{boxed, BoxBody, Bytes, HttpBody},
+    extract::{MatchedPath, Query, State},
+    http::{header, HeaderMap, HeaderValue, Method, Request},
     middleware::Next,
     response::{IntoResponse, Response},
 };
-use std::{sync::Arc, time::Instant};
-use tracing::{info_span, Instrument};
+use chrono::Utc;
+use std::{
+    pin::Pin,
+    sync::Arc,
+    task::{Context, Poll},
+    time::Instant,
+};
+use tracing::{info_span, Instrument, Span};
+
+use crate::{
+    access_log::{self, AccessLogRequest},
+    admin::ErrorRecord,
+    error::{AppError, ReportedError},
+    error_tracking::{self, RequestInfo},
+    handlers::fetch_metadata,
+    log_request,
+    logging::{generate_request_id, record_request, record_transfer},
+    server::AppState,
+    slicing::slicing_param_names,
+    statsd,
+    trace_context::TraceContext,
+};
 
-use crate::{log_request, logging::generate_request_id, server::AppState};
+/// Query parameters understood by `/proxy/data`, besides dimension selectors
+const DATA_QUERY_PARAMS: [&str; 5] = ["vars", "time", "time_range", "format", "units"];
+/// Query parameters understood by `/api/sample`
+const SAMPLE_QUERY_PARAMS: [&str; 2] = ["vars", "time"];
+/// Query parameters understood by the Earth data routes
+const EARTH_QUERY_PARAMS: [&str; 6] = ["mask", "time", "packed", "precision", "format", "units"];
+/// Query parameters understood by the OSCAR ocean currents routes
+const OSCAR_QUERY_PARAMS: [&str; 5] = ["mask", "packed", "precision", "format", "units"];
+/// Query parameters understood by the Earth frame bundles
+const FRAMES_QUERY_PARAMS: [&str; 9] = [
+    "start",
+    "count",
+    "delta",
+    "mask",
+    "level",
+    "packed",
+    "precision",
+    "format",
+    "units",
+];
+/// Query parameters understood by `/api/time/next`
+const NEXT_TIME_QUERY_PARAMS: [&str; 3] = ["after", "var", "steps"];
+/// Query parameters understood by `/api/time/previous`
+const PREVIOUS_TIME_QUERY_PARAMS: [&str; 3] = ["before", "var", "steps"];
 
 /// Request tracing middleware that adds correlation IDs and measures request duration
 pub async fn request_tracing_middleware<B>(
@@ -24,16 +68,31 @@ pub async fn request_tracing_middleware<B>(
     let method = request.method().clone();
     let uri = request.uri().clone();
     let path = uri.path().to_string();
+    let request_bytes = content_length(request.headers());
 
     // Generate or extract request ID
     let request_id = extract_or_generate_request_id(request.headers());
 
+    // Continue the caller's W3C trace, or start one keyed by the request ID
+    let trace_context = extract_trace_context(request.headers())
+        .unwrap_or_else(|| TraceContext::from_request_id(&request_id));
+
     // Add request ID to headers for downstream services
     request.headers_mut().insert(
         "x-request-id",
         HeaderValue::from_str(&request_id).unwrap_or_else(|_| HeaderValue::from_static("invalid")),
     );
 
+    let access = access_log::enabled().then(|| {
+        AccessLogRequest::new(
+            &method,
+            &uri,
+            request.version(),
+            request.headers(),
+            extract_remote_addr(request.headers()),
+        )
+    });
+
     // Create tracing span with request context
     let span = info_span!(
         "http_request",
@@ -42,15 +101,32 @@ pub async fn request_tracing_middleware<B>(
         http_scheme = uri.scheme_str(),
         http_host = uri.host(),
         request_id = %request_id,
+        trace_id = %trace_context.trace_id,
+        span_id = %trace_context.span_id,
+        parent_span_id = trace_context.parent_span_id.as_deref(),
         user_agent = extract_user_agent(request.headers()),
         remote_addr = extract_remote_addr(request.headers()),
+        // Label of the API key, recorded by the auth layer
+        api_key = tracing::field::Empty,
+        // Subject of the bearer token, recorded by the auth layer
+        jwt_subject = tracing::field::Empty,
     );
 
+    let request_info = RequestInfo {
+        method: method.to_string(),
+        path: path.clone(),
+        request_id: request_id.clone(),
+        trace_id: trace_context.trace_id.clone(),
+    };
+
     // Process request within the span
+    let completion_span = span.clone();
     let response = async move {
         tracing::info!("Processing request");
 
-        let mut response = next.run(request).await;
+        let mut response = trace_context
+            .scope(error_tracking::scope(request_info, next.run(request)))
+            .await;
 
         // Add request ID to response headers
         response.headers_mut().insert(
@@ -59,24 +135,122 @@ pub async fn request_tracing_middleware<B>(
                 .unwrap_or_else(|_| HeaderValue::from_static("invalid")),
         );
 
-        let duration = start_time.elapsed();
-        let status_code = response.status().as_u16();
+        // Log structured request completion once the body, which may be
+        // streamed, has been sent or abandoned
+        let completion = RequestCompletion {
+            method,
+            path,
+            status_code: response.status().as_u16(),
+            request_id,
+            start_time,
+            request_bytes,
+            response_bytes: 0,
+            span: completion_span,
+            access,
+        };
+        response.map(|body| boxed(CountingBody::new(body, completion)))
+    }
+    .instrument(span)
+    .await;
+
+    response
+}
+
+/// Request details logged when the response body is finished
+struct RequestCompletion {
+    method: Method,
+    path: String,
+    status_code: u16,
+    request_id: String,
+    start_time: Instant,
+    request_bytes: u64,
+    response_bytes: u64,
+    span: Span,
+    /// Set when the access log is enabled
+    access: Option<AccessLogRequest>,
+}
+
+impl Drop for RequestCompletion {
+    fn drop(&mut self) {
+        let _entered = self.span.enter();
+        let duration_ms = self.start_time.elapsed().as_millis() as u64;
+        record_transfer(self.request_bytes, self.response_bytes);
+        record_request(self.status_code);
+
+        let status = self.status_code.to_string();
+        let tags = [
+            ("method", self.method.as_str()),
+            ("status", status.as_str()),
+        ];
+        statsd::count("http.requests", 1, &tags);
+        statsd::timing("http.request.duration", duration_ms, &tags);
+        statsd::count("http.response.bytes", self.response_bytes, &[]);
 
-        // Log structured request completion
         log_request!(
-            method.as_str(),
-            &path,
-            status_code,
-            duration.as_millis() as u64,
-            &request_id
+            self.method.as_str(),
+            &self.path,
+            self.status_code,
+            duration_ms,
+            &self.request_id,
+            self.request_bytes,
+            self.response_bytes
         );
+        if let Some(access) = &self.access {
+            access_log::record(access, self.status_code, self.response_bytes);
+        }
+    }
+}
+
+/// Response body wrapper that counts the bytes sent to the client
+struct CountingBody {
+    inner: BoxBody,
+    completion: RequestCompletion,
+}
 
-        response
+impl CountingBody {
+    fn new(inner: BoxBody, completion: RequestCompletion) -> Self {
+        Self { inner, completion }
     }
-    .instrument(span)
-    .await;
+}
 
-    response
+impl HttpBody for CountingBody {
+    type Data = Bytes;
+    type Error = axum::Error;
+
+    fn poll_data(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<Option<Result<Self::Data, Self::Error>>> {
+        let poll = Pin::new(&mut self.inner).poll_data(cx);
+        if let Poll::Ready(Some(Ok(chunk))) = &poll {
+            self.completion.response_bytes += chunk.len() as u64;
+        }
+        poll
+    }
+
+    fn poll_trailers(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<Result<Option<HeaderMap>, Self::Error>> {
+        Pin::new(&mut self.inner).poll_trailers(cx)
+    }
+
+    fn is_end_stream(&self) -> bool {
+        self.inner.is_end_stream()
+    }
+
+    fn size_hint(&self) -> http_body::SizeHint {
+        self.inner.size_hint()
+    }
+}
+
+/// Request body size from `Content-Length`, or 0 when it is not declared
+fn content_length(headers: &HeaderMap) -> u64 {
+    headers
+        .get(header::CONTENT_LENGTH)
+        .and_then(|v| v.to_str().ok())
+        .and_then(|v| v.parse().ok())
+        .unwrap_or(0)
 }
 
 /// Extract or generate a request correlation ID
@@ -96,6 +270,13 @@ fn extract_or_generate_request_id(headers: &HeaderMap) -> String {
     generate_request_id()
 }
 
+/// Parse the W3C `traceparent` and `tracestate` headers, ignoring invalid ones
+fn extract_trace_context(headers: &HeaderMap) -> Option<TraceContext> {
+    let traceparent = headers.get("traceparent")?.to_str().ok()?;
+    let tracestate = headers.get("tracestate").and_then(|v| v.to_str().ok());
+    TraceContext::from_traceparent(traceparent, tracestate)
+}
+
 /// Extract User-Agent header for logging
 fn extract_user_agent(headers: &HeaderMap) -> Option<&str> {
     headers.get("user-agent").and_then(|v| v.to_str().ok())
@@ -120,7 +301,7 @@ fn extract_remote_addr(headers: &HeaderMap) -> Option<&str> {
 
 /// Error handling middleware that logs errors with context
 pub async fn error_logging_middleware<B>(
-    State(_state): State<Arc<AppState>>,
+    State(state): State<Arc<AppState>>,
     request: Request<B>,
     next: Next<B>,
 ) -> Response {
@@ -140,6 +321,23 @@ pub async fn error_logging_middleware<B>(
             "HTTP error response"
         );
     }
+    if response.status().is_server_error() {
+        let message = match response.extensions().get::<ReportedError>() {
+            Some(reported) => reported.message.clone(),
+            None => response
+                .status()
+                .canonical_reason()
+                .unwrap_or_default()
+                .to_string(),
+        };
+        state.recent_errors.record(ErrorRecord {
+            at: Utc::now(),
+            method: method.to_string(),
+            path,
+            status: response.status().as_u16(),
+            message,
+        });
+    }
 
     response
 }
@@ -166,12 +364,151 @@ pub async fn security_headers_middleware<B>(request: Request<B>, next: Next<B>)
     );
     headers.insert(
         "content-security-policy",
-        HeaderValue::from_static("default-src 'self'; script-src 'self' 'unsafe-inline'; style-src 'self' 'unsafe-inline'; img-src 'self' data: https:; connect-src 'self' https:"),
+        HeaderValue::from_static("default-src 'self'; script-src 'self' 'unsafe-inline' 'wasm-unsafe-eval'; style-src 'self' 'unsafe-inline'; img-src 'self' data: https:; connect-src 'self' https:"),
     );
 
     response
 }
 
+/// Cross-origin isolation middleware
+///
+/// Only active when enabled. Isolated pages may use `SharedArrayBuffer`, e.g.
+/// for WebAssembly workers, but can then only embed cross-origin resources
+/// that opt in with CORS or `Cross-Origin-Resource-Policy`.
+pub async fn cross_origin_isolation_middleware<B>(
+    State(state): State<Arc<AppState>>,
+    request: Request<B>,
+    next: Next<B>,
+) -> Response {
+    let mut response = next.run(request).await;
+    if !state.cross_origin_isolation {
+        return response;
+    }
+
+    let headers = response.headers_mut();
+    headers.insert(
+        "cross-origin-opener-policy",
+        HeaderValue::from_static("same-origin"),
+    );
+    headers.insert(
+        "cross-origin-embedder-policy",
+        HeaderValue::from_static("require-corp"),
+    );
+    headers.insert(
+        "cross-origin-resource-policy",
+        HeaderValue::from_static("same-origin"),
+    );
+
+    response
+}
+
+/// Strict query middleware that rejects unrecognized query parameters
+///
+/// Only active when strict mode is enabled. Must be installed with
+/// `route_layer` so the matched route is known.
+pub async fn strict_query_middleware<B>(
+    State(state): State<Arc<AppState>>,
+    request: Request<B>,
+    next: Next<B>,
+) -> Response {
+    if !state.strict_query {
+        return next.run(request).await;
+    }
+
+    let Some(route) = request.extensions().get::<MatchedPath>() else {
+        return next.run(request).await;
+    };
+    let Some(allowed) = allowed_query_params(route.as_str()) else {
+        return next.run(request).await;
+    };
+
+    let params = Query::<Vec<(String, String)>>::try_from_uri(request.uri())
+        .map(|Query(params)| params)
+        .unwrap_or_default();
+    let mut allowed: Vec<String> = allowed.iter().map(|p| p.to_string()).collect();
+    let mut unknown = unknown_query_params(&params, &allowed);
+
+    // The data proxy also forwards dimension selectors such as `level=850`
+    // and index slices such as `lat_indices=0:360:2`, of its dataset
+    let data_state = match route.as_str() {
+        "/proxy/data" => Some(&state),
+        "/proxy/:dataset/data" => request
+            .uri()
+            .path()
+            .rsplit('/')
+            .nth(1)
+            .and_then(|dataset| state.datasets.state(&state, dataset)),
+        _ => None,
+    };
+    if let (false, Some(data_state)) = (unknown.is_empty(), data_state) {
+        match fetch_metadata(data_state).await {
+            Ok(metadata) => {
+                if let Some(dimensions) = metadata.get("dimensions").and_then(|d| d.as_object()) {
+                    for name in dimensions.keys() {
+                        allowed.push(name.clone());
+                        allowed.push(format!("{}_range", name));
+                    }
+                }
+                allowed.extend(slicing_param_names(&metadata));
+                unknown = unknown_query_params(&params, &allowed);
+            }
+            Err(error) => return error.into_response(),
+        }
+    }
+
+    if unknown.is_empty() {
+        return next.run(request).await;
+    }
+
+    tracing::warn!(
+        route = route.as_str(),
+        unknown = ?unknown,
+        "Rejecting request with unknown query parameters"
+    );
+    AppError::RequestError(format!(
+        "Unknown query parameter{} {}. Allowed parameters: {}",
+        if unknown.len() == 1 { "" } else { "s" },
+        unknown
+            .iter()
+            .map(|p| format!("'{}'", p))
+            .collect::<Vec<_>>()
+            .join(", "),
+        if allowed.is_empty() {
+            "none".to_string()
+        } else {
+            allowed.join(", ")
+        }
+    ))
+    .into_response()
+}
+
+/// Query parameters accepted by a route, or `None` when it is not checked
+fn allowed_query_params(route: &str) -> Option<&'static [&'static str]> {
+    match route {
+        "/proxy/data" | "/proxy/:dataset/data" => Some(&DATA_QUERY_PARAMS),
+        "/proxy/metadata" | "/proxy/:dataset/metadata" => Some(&[]),
+        "/api/sample" => Some(&SAMPLE_QUERY_PARAMS),
+        "/api/time/next" => Some(&NEXT_TIME_QUERY_PARAMS),
+        "/api/time/previous" => Some(&PREVIOUS_TIME_QUERY_PARAMS),
+        route if route.starts_with("/api/") => Some(&[]),
+        route if route.starts_with("/data/weather/") => Some(&EARTH_QUERY_PARAMS),
+        route if route.starts_with("/data/oscar/") => Some(&OSCAR_QUERY_PARAMS),
+        route if route.starts_with("/data/frames/") => Some(&FRAMES_QUERY_PARAMS),
+        _ => None,
+    }
+}
+
+/// Names of query parameters not in `allowed`, without duplicates
+fn unknown_query_params(params: &[(String, String)], allowed: &[String]) -> Vec<String> {
+    let mut unknown: Vec<String> = Vec::new();
+    for (name, _) in params {
+        if !allowed.contains(name) && !unknown.contains(name) {
+            unknown.push(name.clone());
+        }
+    }
+    unknown
+}
+
 /// Health check middleware that provides detailed status information
 pub async fn health_check_middleware<B>(
     State(state): State<Arc<AppState>>,
@@ -186,6 +523,10 @@ pub async fn health_check_middleware<B>(
         let mut sys = System::new();
         sys.refresh_system();
 
+        let mut backend =
+            serde_json::to_value(state.health_probe.check(&state).await).unwrap_or_default();
+        backend["url"] = serde_json::Value::from(state.api_url.as_str());
+
         let health_info = serde_json::json!({
             "status": "healthy",
             "timestamp": SystemTime::now()
@@ -200,10 +541,7 @@ pub async fn health_check_middleware<B>(
                 "used_kb": sys.used_memory(),
                 "available_kb": sys.available_memory()
             },
-            "backend": {
-                "url": state.api_url,
-                "status": "configured"
-            }
+            "backend": backend,
         });
 
         return axum::Json(health_info).into_response();
@@ -275,4 +613,38 @@ mod tests {
         let addr = extract_remote_addr(&headers);
         assert_eq!(addr, None);
     }
+
+    #[test]
+    fn test_allowed_query_params() {
+        assert_eq!(
+            allowed_query_params("/proxy/data"),
+            Some(&DATA_QUERY_PARAMS[..])
+        );
+        assert_eq!(allowed_query_params("/api/cross-section"), Some(&[][..]));
+        assert_eq!(
+            allowed_query_params("/api/time/previous"),
+            Some(&PREVIOUS_TIME_QUERY_PARAMS[..])
+        );
+        assert_eq!(
+            allowed_query_params("/data/weather/current/current-wind-surface-level-gfs-1.0.json"),
+            Some(&EARTH_QUERY_PARAMS[..])
+        );
+        assert_eq!(
+            allowed_query_params("/data/frames/t2m"),
+            Some(&FRAMES_QUERY_PARAMS[..])
+        );
+        assert_eq!(allowed_query_params("/*path"), None);
+    }
+
+    #[test]
+    fn test_unknown_query_params() {
+        let allowed: Vec<String> = DATA_QUERY_PARAMS.iter().map(|p| p.to_string()).collect();
+        let params = vec![
+            ("var".to_string(), "t2m".to_string()),
+            ("time".to_string(), "700464".to_string()),
+            ("var".to_string(), "u10".to_string()),
+        ];
+        assert_eq!(unknown_query_params(&params, &allowed), vec!["var"]);
+        assert!(unknown_query_params(&params[1..2], &allowed).is_empty());
+    }
 }
@@ -0,0 +1,141 @@
+//! OpenAPI description of the HTTP API
+//!
+//! Operations are declared with `#[utoipa::path]` next to their handlers and
+//! collected by [`ApiDoc`]. `GET /api/openapi.json` serves the specification
+//! and `GET /api/docs` a Swagger UI page for it, whose scripts are embedded
+//! with the frontend under `/libs/swagger-ui`.
+
+use axum::{
+    extract::State,
+    http::header,
+    response::{Html, IntoResponse, Json, Response},
+};
+use std::sync::Arc;
+use utoipa::{
+    openapi::{
+        security::{HttpAuthScheme, HttpBuilder, SecurityScheme},
+        server::Server,
+        OpenApi as Specification,
+    },
+    Modify, OpenApi,
+};
+
+use crate::{
+    error::ErrorBody, format::OutputFormat, mask::MaskMode, server::AppState, units::UnitSystem,
+};
+
+/// The Swagger UI page served at `/api/docs`
+const DOCS_PAGE: &str = include_str!("api_docs.html");
+
+/// Security scheme of the admin operations, the `--admin-token` bearer token
+const ADMIN_TOKEN: &str = "admin_token";
+
+/// The operations and schemas of the HTTP API
+#[derive(OpenApi)]
+#[openapi(
+    info(
+        title = "rossby-vis",
+        description = "Visualization frontend and streaming proxy for Rossby data servers"
+    ),
+    paths(
+        crate::handlers::proxy_metadata,
+        crate::handlers::proxy_data,
+        crate::datasets::dataset_metadata,
+        crate::datasets::dataset_data,
+        crate::handlers::earth_wind_data,
+        crate::handlers::earth_temp_data,
+        crate::handlers::earth_current_data,
+        crate::datasets::earth_weather_data,
+        crate::frames::earth_frames,
+        crate::products::products_catalog,
+        crate::oscar::oscar_catalog,
+        crate::oscar::oscar_data,
+        crate::analysis::sample,
+        crate::analysis::cross_section,
+        crate::analysis::trajectories,
+        crate::jobs::submit_job,
+        crate::jobs::job_status,
+        crate::jobs::cancel_job,
+        crate::jobs::job_result,
+        crate::jobs::job_events,
+        crate::timesteps::next_time,
+        crate::timesteps::previous_time,
+        crate::handlers::status,
+        crate::handlers::version_info,
+        crate::datasets::list_datasets,
+        crate::units::preferences,
+        crate::handlers::asset_manifest,
+        crate::handlers::cache_manifest,
+        crate::client_errors::report_client_errors,
+        crate::signing::public_key,
+        crate::admin::overview,
+        crate::admin::get_log_level,
+        crate::admin::set_log_level,
+        crate::admin::cache_stats,
+        crate::admin::purge_cache,
+        crate::admin::list_assets,
+        crate::scheduler::scheduled_tasks,
+    ),
+    // Query parameters refer to these without adding them
+    components(schemas(ErrorBody, MaskMode, OutputFormat, UnitSystem)),
+    modifiers(&AdminToken),
+    tags(
+        (name = "proxy", description = "Backend metadata and data, passed through"),
+        (name = "earth", description = "Data in the layout the Earth frontend loads"),
+        (name = "analysis", description = "Values at points, vertical sections and trajectories"),
+        (name = "jobs", description = "Analyses run in the background"),
+        (name = "time", description = "Navigation along the time axis"),
+        (name = "service", description = "Status, build and deployment settings"),
+        (name = "admin", description = "Operator endpoints, which need the `--admin-token` bearer token"),
+    )
+)]
+pub struct ApiDoc;
+
+/// The operations of builds with the `sqlite` feature
+#[cfg(feature = "sqlite")]
+#[derive(OpenApi)]
+#[openapi(paths(
+    crate::admin::list_api_keys,
+    crate::admin::issue_api_key,
+    crate::admin::revoke_api_key,
+))]
+struct StoreApiDoc;
+
+/// Declares the bearer token of `--admin-token`
+struct AdminToken;
+
+impl Modify for AdminToken {
+    fn modify(&self, openapi: &mut Specification) {
+        openapi
+            .components
+            .get_or_insert_with(Default::default)
+            .add_security_scheme(
+                ADMIN_TOKEN,
+                SecurityScheme::Http(HttpBuilder::new().scheme(HttpAuthScheme::Bearer).build()),
+            );
+    }
+}
+
+/// The specification of a server mounted at `base_path`
+pub fn specification(base_path: &str) -> Specification {
+    let mut specification = ApiDoc::openapi();
+    #[cfg(feature = "sqlite")]
+    specification.merge(StoreApiDoc::openapi());
+    let url = if base_path.is_empty() { "/" } else { base_path };
+    specification.servers = Some(vec![Server::new(url)]);
+    specification
+}
+
+/// Handler for `GET /api/openapi.json`
+pub async fn openapi_json(State(state): State<Arc<AppState>>) -> Json<Specification> {
+    Json(specification(&state.base_path))
+}
+
+/// Handler for `GET /api/docs`
+pub async fn api_docs() -> Response {
+    (
+        [(header::CONTENT_TYPE, "text/html; charset=utf-8")],
+        Html(DOCS_PAGE),
+    )
+        .into_response()
+}
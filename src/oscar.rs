@@ -0,0 +1,372 @@
+//! Ocean currents in the layout of Earth's OSCAR product
+//!
+//! Earth loads ocean currents from `/data/oscar/catalog.json`, a sorted list
+//! of file names prefixed with their `yyyyMMdd` date, and then fetches one of
+//! those files, picking the latest date at or before the one shown. Unlike
+//! the GFS paths there is no hour: OSCAR is a series of multi-day means, one
+//! file per date. These routes build that catalog from the time axis of the
+//! dataset holding the currents and serve each date's first timestep of the
+//! eastward and northward current components as Earth JSON.
+//!
+//! The currents may live on a different backend than the atmospheric data;
+//! `--ocean-currents-url` points at it. Without one, and when the main
+//! dataset has no currents, the bundled sample files are served as before.
+
+use axum::{
+    extract::{Path, Query, State},
+    http::{HeaderMap, Uri},
+    response::{IntoResponse, Response},
+    Json,
+};
+use chrono::NaiveDate;
+use serde_json::Value;
+use std::{borrow::Cow, sync::Arc};
+use tracing::{info, instrument};
+
+use crate::{
+    backend::{BackendCompat, BackendSchema},
+    endpoint::BackendEndpoint,
+    error::{AppError, ErrorBody},
+    format::OutputFormat,
+    freshness::{latest_time, DataFreshness},
+    grid::{coordinate_values, variable_dimensions},
+    handlers::{
+        analyze_metadata_variables, earth_variable_response, fetch_metadata, static_asset,
+        EarthQuery, VariableCategory, VariableInfo, VariableType,
+    },
+    server::AppState,
+    timesteps::to_datetime,
+};
+
+/// Suffix of the OSCAR file names after the date
+const OSCAR_FILE_SUFFIX: &str = "-surface-currents-oscar-0.33.json";
+
+/// Common names of eastward/northward ocean current pairs
+const CURRENT_COMPONENT_NAMES: [(&str, &str); 4] = [
+    ("ust", "vst"),
+    ("u_current", "v_current"),
+    ("uo", "vo"),
+    ("uoe", "von"),
+];
+
+/// Where the ocean currents come from
+#[derive(Debug, Clone, Default)]
+pub struct OceanCurrentsConfig {
+    /// Backend holding the currents; the main backend when not set
+    pub endpoint: Option<BackendEndpoint>,
+    /// Eastward and northward current variables; auto-detected when not set
+    pub components: Option<(String, String)>,
+}
+
+/// Parse `u,v` current component names
+pub fn parse_current_components(value: &str) -> Result<(String, String), AppError> {
+    match value
+        .split(',')
+        .map(str::trim)
+        .collect::<Vec<_>>()
+        .as_slice()
+    {
+        [u, v] if !u.is_empty() && !v.is_empty() => Ok((u.to_string(), v.to_string())),
+        _ => Err(AppError::ConfigError(format!(
+            "Invalid ocean current variables '{}': expected 'u,v'",
+            value
+        ))),
+    }
+}
+
+/// State for requests to the ocean currents backend
+fn ocean_state(state: &AppState) -> Cow<'_, AppState> {
+    match &state.ocean_currents.endpoint {
+        None => Cow::Borrowed(state),
+        Some(endpoint) => {
+            let mut ocean = state.clone();
+            ocean.api_url = endpoint.base_url.clone();
+            ocean.backend_credentials = endpoint.credentials.clone();
+            ocean.backend = BackendCompat::new(BackendSchema::Auto);
+            // A mask file matches the main backend's grid, not this one
+            ocean.land_sea_mask.values = None;
+            Cow::Owned(ocean)
+        }
+    }
+}
+
+/// The eastward and northward current variables of `metadata`, or `None`
+/// when none are configured and none are found
+fn find_current_components(
+    config: &OceanCurrentsConfig,
+    metadata: &Value,
+) -> Result<Option<(String, String)>, AppError> {
+    let has = |name: &str| variable_dimensions(metadata, name).is_some();
+    if let Some((u, v)) = &config.components {
+        return match (has(u), has(v)) {
+            (true, true) => Ok(Some((u.clone(), v.clone()))),
+            _ => Err(AppError::ConfigError(format!(
+                "Ocean current variables '{}' and '{}' are not in the metadata",
+                u, v
+            ))),
+        };
+    }
+
+    let found = CURRENT_COMPONENT_NAMES
+        .iter()
+        .find(|(u, v)| has(u) && has(v))
+        .map(|(u, v)| (u.to_string(), v.to_string()))
+        .or_else(|| {
+            analyze_metadata_variables(metadata)
+                .into_iter()
+                .filter(|info| info.long_name.to_lowercase().contains("current"))
+                .find_map(|info| match info.var_type {
+                    VariableType::Vector {
+                        u_component,
+                        v_component,
+                    } => Some((u_component, v_component)),
+                    VariableType::Scalar => None,
+                })
+        });
+    match found {
+        None if config.endpoint.is_some() => Err(AppError::ConfigError(
+            "The ocean currents backend has no current variables".to_string(),
+        )),
+        found => Ok(found),
+    }
+}
+
+/// The bundled sample file at the request path
+async fn bundled(state: Arc<AppState>, uri: &Uri, headers: HeaderMap) -> Response {
+    let path = uri.path().trim_start_matches('/').to_string();
+    static_asset(State(state), Path(path), uri.clone(), headers).await
+}
+
+/// Dates of the time axis with the first timestep of each, in order
+fn dated_timesteps(times: &[f64]) -> Vec<(NaiveDate, f64)> {
+    let mut dates: Vec<(NaiveDate, f64)> = Vec::new();
+    for &time in times {
+        let date = to_datetime(time).date_naive();
+        match dates.iter_mut().find(|(d, _)| *d == date) {
+            Some((_, first)) if time < *first => *first = time,
+            Some(_) => {}
+            None => dates.push((date, time)),
+        }
+    }
+    dates.sort_by_key(|(date, _)| *date);
+    dates
+}
+
+/// OSCAR file name for a date
+fn oscar_file_name(date: NaiveDate) -> String {
+    format!("{}{}", date.format("%Y%m%d"), OSCAR_FILE_SUFFIX)
+}
+
+/// Date named by an OSCAR file name
+fn parse_oscar_file_name(file: &str) -> Option<NaiveDate> {
+    let date = file
+        .strip_suffix(OSCAR_FILE_SUFFIX)
+        .filter(|date| date.len() == 8 && date.bytes().all(|b| b.is_ascii_digit()))?;
+    NaiveDate::parse_from_str(date, "%Y%m%d").ok()
+}
+
+/// Handler for `GET /data/oscar/catalog.json`
+#[utoipa::path(
+    get,
+    path = "/data/oscar/catalog.json",
+    tag = "earth",
+    summary = "Dated ocean current files",
+    responses(
+        (status = 200, description = "File names, oldest first", body = Vec<String>),
+        (status = 502, description = "The backend failed", body = ErrorBody),
+    )
+)]
+#[instrument(skip(state))]
+pub async fn oscar_catalog(
+    State(state): State<Arc<AppState>>,
+    uri: Uri,
+    headers: HeaderMap,
+) -> Result<Response, AppError> {
+    let metadata = fetch_metadata(&ocean_state(&state)).await?;
+    if find_current_components(&state.ocean_currents, &metadata)?.is_none() {
+        return Ok(bundled(state, &uri, headers).await);
+    }
+
+    let times = coordinate_values(&metadata, "time").unwrap_or_default();
+    let files: Vec<String> = dated_timesteps(&times)
+        .into_iter()
+        .map(|(date, _)| oscar_file_name(date))
+        .collect();
+    Ok(Json(files).into_response())
+}
+
+/// Handler for `GET /data/oscar/{yyyyMMdd}-surface-currents-oscar-0.33.json`
+#[utoipa::path(
+    get,
+    path = "/data/oscar/{file}",
+    tag = "earth",
+    summary = "Ocean currents at a date",
+    params(
+        (
+            "file" = String,
+            Path,
+            description = "`{yyyyMMdd}-surface-currents-oscar-0.33.json`, as listed by the catalog"
+        ),
+        EarthQuery,
+    ),
+    responses(
+        (
+            status = 200,
+            description = "Earth records: a header and a data array per component",
+            content(
+                (serde_json::Value = "application/json"),
+                (serde_json::Value = "application/msgpack"),
+                (serde_json::Value = "application/vnd.rossby.packed+json"),
+            )
+        ),
+        (status = 304, description = "Unchanged since the request's `If-None-Match` or `If-Modified-Since`"),
+        (status = 400, description = "Invalid path or query", body = ErrorBody),
+        (status = 404, description = "No such variable or level", body = ErrorBody),
+        (status = 502, description = "The backend failed", body = ErrorBody),
+    )
+)]
+#[instrument(skip(state, headers))]
+pub async fn oscar_data(
+    State(state): State<Arc<AppState>>,
+    Path(file): Path<String>,
+    Query(query): Query<EarthQuery>,
+    uri: Uri,
+    headers: HeaderMap,
+) -> Result<Response, AppError> {
+    let ocean = ocean_state(&state);
+    let metadata = fetch_metadata(&ocean).await?;
+    let Some((u_component, v_component)) =
+        find_current_components(&state.ocean_currents, &metadata)?
+    else {
+        return Ok(bundled(state.clone(), &uri, headers).await);
+    };
+    let date = parse_oscar_file_name(&file).ok_or_else(|| {
+        AppError::RequestError(format!("Invalid OSCAR data path '{}'", uri.path()))
+    })?;
+    let format = OutputFormat::negotiate(query.format, &headers);
+    let freshness = DataFreshness::new(&metadata, &format.variant(&uri.to_string()));
+    if freshness.matches(&headers) {
+        return Ok(freshness.not_modified());
+    }
+
+    let times = coordinate_values(&metadata, "time").unwrap_or_default();
+    let (_, time) = dated_timesteps(&times)
+        .into_iter()
+        .find(|(d, _)| *d == date)
+        .ok_or_else(|| {
+            AppError::RequestError(format!("No ocean currents for {}", date.format("%Y-%m-%d")))
+        })?;
+    info!(
+        "Serving ocean currents {}/{} for {}",
+        u_component, v_component, date
+    );
+
+    let attributes = metadata
+        .get("variables")
+        .and_then(|v| v.get(&u_component))
+        .and_then(|v| v.get("attributes"));
+    let attribute = |name: &str| {
+        attributes
+            .and_then(|a| a.get(name))
+            .and_then(Value::as_str)
+            .map(str::to_string)
+    };
+    let var_info = VariableInfo {
+        name: u_component.clone(),
+        display_name: "Ocean Currents".to_string(),
+        long_name: attribute("long_name").unwrap_or_else(|| "Ocean currents".to_string()),
+        units: attribute("units").unwrap_or_default(),
+        category: VariableCategory::General,
+        var_type: VariableType::Vector {
+            u_component: u_component.clone(),
+            v_component,
+        },
+        dimensions: variable_dimensions(&metadata, &u_component).unwrap_or_default(),
+    };
+
+    let response = earth_variable_response(
+        &ocean,
+        &metadata,
+        &var_info,
+        &EarthQuery {
+            format: Some(format),
+            ..query
+        },
+        time,
+        None,
+        &freshness,
+    )
+    .await?;
+    Ok(state
+        .cache_policy
+        .apply(response, latest_time(&metadata), Some(time)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::timesteps::from_datetime;
+    use chrono::{TimeZone, Utc};
+    use serde_json::json;
+
+    #[test]
+    fn test_oscar_file_names() {
+        let date = NaiveDate::from_ymd_opt(2014, 1, 31).unwrap();
+        assert_eq!(
+            oscar_file_name(date),
+            "20140131-surface-currents-oscar-0.33.json"
+        );
+        assert_eq!(parse_oscar_file_name(&oscar_file_name(date)), Some(date));
+        assert_eq!(parse_oscar_file_name("20140131-wind.json"), None);
+        assert_eq!(
+            parse_oscar_file_name("2014013-surface-currents-oscar-0.33.json"),
+            None
+        );
+
+        assert_eq!(
+            parse_current_components(" uo , vo").unwrap(),
+            ("uo".into(), "vo".into())
+        );
+        assert!(parse_current_components("uo").is_err());
+    }
+
+    #[test]
+    fn test_dated_timesteps() {
+        let at = |d, h| from_datetime(Utc.with_ymd_and_hms(2014, 1, d, h, 0, 0).unwrap());
+        let dates = dated_timesteps(&[at(6, 12), at(1, 0), at(6, 0)]);
+        assert_eq!(
+            dates,
+            [
+                (NaiveDate::from_ymd_opt(2014, 1, 1).unwrap(), at(1, 0)),
+                (NaiveDate::from_ymd_opt(2014, 1, 6).unwrap(), at(6, 0)),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_find_current_components() {
+        let metadata = json!({
+            "variables": {
+                "u10": {"dimensions": ["latitude", "longitude"], "attributes": {"long_name": "10 metre U wind component"}},
+                "v10": {"dimensions": ["latitude", "longitude"], "attributes": {"long_name": "10 metre V wind component"}},
+                "u": {"dimensions": ["latitude", "longitude"], "attributes": {"long_name": "Ocean Surface Zonal Currents"}},
+                "v": {"dimensions": ["latitude", "longitude"], "attributes": {"long_name": "Ocean Surface Meridional Currents"}}
+            }
+        });
+        let config = OceanCurrentsConfig::default();
+        assert_eq!(
+            find_current_components(&config, &metadata).unwrap(),
+            Some(("u".to_string(), "v".to_string()))
+        );
+
+        let config = OceanCurrentsConfig {
+            components: Some(("uo".to_string(), "vo".to_string())),
+            ..Default::default()
+        };
+        assert!(find_current_components(&config, &metadata).is_err());
+        assert_eq!(
+            find_current_components(&OceanCurrentsConfig::default(), &json!({})).unwrap(),
+            None
+        );
+    }
+}
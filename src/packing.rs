@@ -0,0 +1,259 @@
+//! Quantized, delta-encoded packing of Earth data arrays
+//!
+//! Earth records carry their grid as a JSON array of floats, which costs
+//! several bytes per value even after gzip. With `packed=true` (or
+//! `--packed-data` for every request), each record carries a `packed` field
+//! instead of `data`:
+//!
+//! ```json
+//! {"encoding": "delta", "type": "int16", "scale": 0.01, "length": 65160, "values": "<base64>"}
+//! ```
+//!
+//! Values are rounded to multiples of `scale` (the `precision` parameter),
+//! and each is stored as the difference from the previous non-missing value
+//! in units of `scale`, as a little-endian typed array of the narrowest type
+//! that holds every difference. The smallest value of the type marks a
+//! missing value. Decoding keeps a running sum of the differences and
+//! multiplies it by `scale`; the frontend does so in `µ.loadJson`.
+
+use base64::{engine::general_purpose::STANDARD, Engine};
+use serde::{Deserialize, Serialize};
+
+use crate::error::AppError;
+
+/// Precision of packed values unless configured or requested otherwise
+pub const DEFAULT_PRECISION: f64 = 0.01;
+
+/// Server settings of data packing
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct PackingConfig {
+    /// Pack responses of requests that do not say
+    pub default: bool,
+    /// Precision of requests that do not give one
+    pub precision: f64,
+}
+
+impl Default for PackingConfig {
+    fn default() -> Self {
+        Self {
+            default: false,
+            precision: DEFAULT_PRECISION,
+        }
+    }
+}
+
+impl PackingConfig {
+    /// The precision to pack a response at, `None` for plain JSON arrays
+    ///
+    /// Giving a precision implies packing.
+    pub fn resolve(
+        &self,
+        packed: Option<bool>,
+        precision: Option<f64>,
+    ) -> Result<Option<f64>, AppError> {
+        if !packed.unwrap_or(self.default || precision.is_some()) {
+            return Ok(None);
+        }
+        let precision = precision.unwrap_or(self.precision);
+        if !precision.is_finite() || precision <= 0.0 {
+            return Err(AppError::RequestError(format!(
+                "Invalid precision {}: expected a positive number",
+                precision
+            )));
+        }
+        Ok(Some(precision))
+    }
+}
+
+/// Element type of the packed differences
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum PackedType {
+    Int8,
+    Int16,
+    Int32,
+}
+
+impl PackedType {
+    fn size(self) -> usize {
+        match self {
+            PackedType::Int8 => 1,
+            PackedType::Int16 => 2,
+            PackedType::Int32 => 4,
+        }
+    }
+
+    /// The missing-value marker, the smallest value of the type
+    fn missing(self) -> i64 {
+        match self {
+            PackedType::Int8 => i8::MIN as i64,
+            PackedType::Int16 => i16::MIN as i64,
+            PackedType::Int32 => i32::MIN as i64,
+        }
+    }
+
+    /// The narrowest type holding differences up to `max` in magnitude
+    fn for_magnitude(max: u64) -> Option<Self> {
+        [PackedType::Int8, PackedType::Int16, PackedType::Int32]
+            .into_iter()
+            .find(|t| max < t.missing().unsigned_abs())
+    }
+}
+
+/// A data array packed as base64 deltas of quantized values
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct PackedArray {
+    /// Always `"delta"`
+    pub encoding: String,
+    #[serde(rename = "type")]
+    pub element_type: PackedType,
+    /// Size of one quantization step
+    pub scale: f64,
+    /// Number of values
+    pub length: usize,
+    /// Base64 of the little-endian differences
+    pub values: String,
+}
+
+impl PackedArray {
+    /// Pack `values`, rounding them to multiples of `precision`
+    pub fn pack(values: &[f64], precision: f64) -> Result<Self, AppError> {
+        // Quantized values beyond 2^53 no longer round-trip through f64
+        const LIMIT: f64 = 9_007_199_254_740_992.0;
+
+        let mut previous = 0i64;
+        let mut deltas = Vec::with_capacity(values.len());
+        for value in values {
+            if !value.is_finite() {
+                deltas.push(None);
+                continue;
+            }
+            let quantized = (value / precision).round();
+            if quantized.abs() >= LIMIT {
+                return Err(too_fine(precision));
+            }
+            let quantized = quantized as i64;
+            deltas.push(Some(quantized - previous));
+            previous = quantized;
+        }
+
+        let max = deltas.iter().flatten().map(|d| d.unsigned_abs()).max();
+        let element_type =
+            PackedType::for_magnitude(max.unwrap_or(0)).ok_or_else(|| too_fine(precision))?;
+        let mut bytes = Vec::with_capacity(deltas.len() * element_type.size());
+        for delta in deltas {
+            let delta = delta.unwrap_or(element_type.missing());
+            match element_type {
+                PackedType::Int8 => bytes.extend((delta as i8).to_le_bytes()),
+                PackedType::Int16 => bytes.extend((delta as i16).to_le_bytes()),
+                PackedType::Int32 => bytes.extend((delta as i32).to_le_bytes()),
+            }
+        }
+
+        Ok(Self {
+            encoding: "delta".to_string(),
+            element_type,
+            scale: precision,
+            length: values.len(),
+            values: STANDARD.encode(bytes),
+        })
+    }
+
+    /// The values, with missing ones as NaN
+    pub fn unpack(&self) -> Result<Vec<f64>, AppError> {
+        let invalid =
+            |reason: &str| AppError::RequestError(format!("Invalid packed array: {}", reason));
+        if self.encoding != "delta" {
+            return Err(invalid("unknown encoding"));
+        }
+        let bytes = STANDARD
+            .decode(&self.values)
+            .map_err(|_| invalid("bad base64"))?;
+        let size = self.element_type.size();
+        if bytes.len() != self.length * size {
+            return Err(invalid("length mismatch"));
+        }
+        let missing = self.element_type.missing();
+        let mut sum = 0i64;
+        Ok(bytes
+            .chunks_exact(size)
+            .map(|chunk| {
+                let delta = match self.element_type {
+                    PackedType::Int8 => chunk[0] as i8 as i64,
+                    PackedType::Int16 => i16::from_le_bytes([chunk[0], chunk[1]]) as i64,
+                    PackedType::Int32 => {
+                        i32::from_le_bytes([chunk[0], chunk[1], chunk[2], chunk[3]]) as i64
+                    }
+                };
+                if delta == missing {
+                    return f64::NAN;
+                }
+                sum += delta;
+                sum as f64 * self.scale
+            })
+            .collect())
+    }
+}
+
+fn too_fine(precision: f64) -> AppError {
+    AppError::RequestError(format!(
+        "Precision {} is too fine for the values of this field",
+        precision
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_pack_round_trip() {
+        let values = [273.15, 273.2, f64::NAN, 274.04, 271.0];
+        let packed = PackedArray::pack(&values, 0.01).unwrap();
+        assert_eq!(packed.element_type, PackedType::Int16);
+        assert_eq!(packed.length, 5);
+
+        let unpacked = packed.unpack().unwrap();
+        for (value, unpacked) in values.iter().zip(&unpacked) {
+            if value.is_nan() {
+                assert!(unpacked.is_nan());
+            } else {
+                assert!((value - unpacked).abs() <= 0.005 + 1e-9);
+            }
+        }
+    }
+
+    #[test]
+    fn test_narrowest_type() {
+        // The first value is stored whole, so it decides as much as the steps
+        let packed = PackedArray::pack(&[1.2, 1.21, f64::NAN, 1.19], 0.01).unwrap();
+        assert_eq!(packed.element_type, PackedType::Int8);
+        let packed = PackedArray::pack(&[273.15, 273.16, 273.1], 0.01).unwrap();
+        assert_eq!(packed.element_type, PackedType::Int16);
+        let packed = PackedArray::pack(&[101325.0, 101310.5], 0.01).unwrap();
+        assert_eq!(packed.element_type, PackedType::Int32);
+        // Coarser precision keeps large values small
+        let packed = PackedArray::pack(&[101325.0, 101310.5], 100.0).unwrap();
+        assert_eq!(packed.element_type, PackedType::Int16);
+
+        assert!(PackedArray::pack(&[1e20], 0.01).is_err());
+        assert!(PackedArray::pack(&[1e12, -1e12], 1.0).is_err());
+    }
+
+    #[test]
+    fn test_resolve_precision() {
+        let config = PackingConfig::default();
+        assert_eq!(config.resolve(None, None).unwrap(), None);
+        assert_eq!(config.resolve(Some(true), None).unwrap(), Some(0.01));
+        assert_eq!(config.resolve(None, Some(0.5)).unwrap(), Some(0.5));
+        assert_eq!(config.resolve(Some(false), Some(0.5)).unwrap(), None);
+        assert!(config.resolve(Some(true), Some(0.0)).is_err());
+
+        let packed_by_default = PackingConfig {
+            default: true,
+            precision: 0.1,
+        };
+        assert_eq!(packed_by_default.resolve(None, None).unwrap(), Some(0.1));
+        assert_eq!(packed_by_default.resolve(Some(false), None).unwrap(), None);
+    }
+}
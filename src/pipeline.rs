@@ -0,0 +1,322 @@
+//! The configurable middleware pipeline
+//!
+//! Every request passes through a stack of middleware before reaching its
+//! route. Deployments choose which layers run and in which order with
+//! `--middleware`, listing them outermost first:
+//!
+//! ```text
+//! --middleware trace,request-tracing,error-logging,cors,security-headers,health-check
+//! ```
+//!
+//! Layers left out are not built at all. The default pipeline,
+//! [`DEFAULT_PIPELINE`], is the stack the server has always run with plus
+//! CORS, compression, authentication, load shedding, request deadlines and a
+//! concurrency limit. CORS, authentication, shedding, deadlines and the
+//! limit do nothing until origins are allowed with `--cors-origin`, users,
+//! keys or a token provider added with `--basic-auth`, `--api-key` or
+//! `--jwt-issuer`, thresholds set with `--shed-memory-percent` or
+//! `--shed-cpu-percent`, clients send `X-Request-Timeout`, and
+//! `--max-concurrent-requests` is set. A pipeline without `auth` is refused
+//! while users or keys are configured.
+//! Access control of the `/admin` routes is not part of the pipeline and
+//! cannot be disabled.
+
+use axum::{
+    http::{header, HeaderName, HeaderValue, Method},
+    middleware as axum_middleware, Router,
+};
+use std::{fmt, str::FromStr, sync::Arc};
+use tower_http::{
+    compression::{
+        predicate::{NotForContentType, Predicate, SizeAbove},
+        CompressionLayer,
+    },
+    cors::{AllowOrigin, CorsLayer},
+    trace::TraceLayer,
+};
+
+use crate::{
+    auth::auth_middleware,
+    deadline::{deadline_middleware, REQUEST_TIMEOUT_HEADER},
+    error::AppError,
+    freshness::DATA_VERSION_HEADER,
+    middleware::{
+        cross_origin_isolation_middleware, error_logging_middleware, health_check_middleware,
+        request_tracing_middleware, security_headers_middleware,
+    },
+    server::AppState,
+    shedding::load_shedding_middleware,
+};
+
+/// A middleware layer that can be enabled and ordered
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Middleware {
+    /// `tower_http` request spans
+    Trace,
+    /// Request IDs, trace context, access logging and metrics
+    RequestTracing,
+    /// Warnings for error responses and the admin list of recent errors
+    ErrorLogging,
+    /// COOP/COEP headers, when `--cross-origin-isolation` is set
+    CrossOriginIsolation,
+    /// `X-Frame-Options`, `Content-Security-Policy` and similar headers
+    SecurityHeaders,
+    /// CORS headers for the origins allowed with `--cors-origin`
+    Cors,
+    /// gzip, Brotli or zstd bodies for clients accepting them
+    Compression,
+    /// 401s for requests without a `--basic-auth` user's credentials, an
+    /// API key or a valid bearer token
+    Auth,
+    /// 503s for low-priority requests under memory or CPU pressure
+    LoadShedding,
+    /// 504s for requests outlasting their `X-Request-Timeout`
+    Deadline,
+    /// Answers `/health` and `/healthz`
+    HealthCheck,
+    /// 503s for requests beyond `--max-concurrent-requests` in flight
+    ConcurrencyLimit,
+}
+
+/// The pipeline run unless configured otherwise, outermost first
+pub const DEFAULT_PIPELINE: [Middleware; 12] = [
+    Middleware::Trace,
+    Middleware::RequestTracing,
+    Middleware::ErrorLogging,
+    Middleware::CrossOriginIsolation,
+    Middleware::SecurityHeaders,
+    Middleware::Cors,
+    Middleware::Compression,
+    Middleware::Auth,
+    Middleware::LoadShedding,
+    Middleware::Deadline,
+    Middleware::HealthCheck,
+    Middleware::ConcurrencyLimit,
+];
+
+impl Middleware {
+    /// Name used in the configuration
+    pub fn name(self) -> &'static str {
+        match self {
+            Middleware::Trace => "trace",
+            Middleware::RequestTracing => "request-tracing",
+            Middleware::ErrorLogging => "error-logging",
+            Middleware::CrossOriginIsolation => "cross-origin-isolation",
+            Middleware::SecurityHeaders => "security-headers",
+            Middleware::Cors => "cors",
+            Middleware::Compression => "compression",
+            Middleware::Auth => "auth",
+            Middleware::LoadShedding => "load-shedding",
+            Middleware::Deadline => "deadline",
+            Middleware::HealthCheck => "health-check",
+            Middleware::ConcurrencyLimit => "concurrency-limit",
+        }
+    }
+}
+
+impl fmt::Display for Middleware {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+impl FromStr for Middleware {
+    type Err = AppError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        DEFAULT_PIPELINE
+            .into_iter()
+            .find(|middleware| middleware.name() == s)
+            .ok_or_else(|| {
+                let names: Vec<&str> = DEFAULT_PIPELINE.iter().map(|m| m.name()).collect();
+                AppError::ConfigError(format!(
+                    "Unknown middleware '{}'; expected one of {}",
+                    s,
+                    names.join(", ")
+                ))
+            })
+    }
+}
+
+/// Parse a comma-separated pipeline, outermost layer first. Each layer may
+/// appear once; an empty list disables all of them.
+pub fn parse_pipeline(s: &str) -> Result<Vec<Middleware>, AppError> {
+    let mut pipeline: Vec<Middleware> = Vec::new();
+    for name in s.split(',').map(str::trim).filter(|name| !name.is_empty()) {
+        let middleware: Middleware = name.parse()?;
+        if pipeline.contains(&middleware) {
+            return Err(AppError::ConfigError(format!(
+                "Middleware '{}' is listed twice",
+                name
+            )));
+        }
+        pipeline.push(middleware);
+    }
+    Ok(pipeline)
+}
+
+/// Origins allowed to read responses cross-origin
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct CorsConfig {
+    /// Allowed origins such as `https://maps.example.com`, or `*` for any
+    pub origins: Vec<String>,
+}
+
+impl CorsConfig {
+    /// Check that every origin is a valid header value
+    pub fn validate(&self) -> Result<(), AppError> {
+        self.layer().map(drop)
+    }
+
+    /// The CORS layer, or `None` when no origin is allowed
+    fn layer(&self) -> Result<Option<CorsLayer>, AppError> {
+        if self.origins.is_empty() {
+            return Ok(None);
+        }
+        let allow_origin = if self.origins.iter().any(|origin| origin == "*") {
+            AllowOrigin::any()
+        } else {
+            let origins = self
+                .origins
+                .iter()
+                .map(|origin| {
+                    HeaderValue::from_str(origin.trim_end_matches('/')).map_err(|_| {
+                        AppError::ConfigError(format!("Invalid CORS origin '{}'", origin))
+                    })
+                })
+                .collect::<Result<Vec<_>, _>>()?;
+            AllowOrigin::list(origins)
+        };
+        Ok(Some(
+            CorsLayer::new()
+                .allow_origin(allow_origin)
+                .allow_methods([Method::GET, Method::POST, Method::PUT, Method::DELETE])
+                .allow_headers([
+                    header::CONTENT_TYPE,
+                    header::AUTHORIZATION,
+                    HeaderName::from_static(REQUEST_TIMEOUT_HEADER),
+                ])
+                .expose_headers([
+                    header::ETAG,
+                    HeaderName::from_static(DATA_VERSION_HEADER),
+                    HeaderName::from_static("x-request-id"),
+                ]),
+        ))
+    }
+}
+
+/// Smallest response compressed by default, in bytes
+pub const DEFAULT_COMPRESSION_MIN_SIZE: u16 = 1024;
+
+/// Which responses the compression layer compresses
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CompressionConfig {
+    /// Responses of a known size below this many bytes are sent as they are;
+    /// streamed responses are always compressed
+    pub min_size: u16,
+}
+
+impl Default for CompressionConfig {
+    fn default() -> Self {
+        Self {
+            min_size: DEFAULT_COMPRESSION_MIN_SIZE,
+        }
+    }
+}
+
+impl CompressionConfig {
+    /// The compression layer, choosing the coding from `Accept-Encoding`
+    fn layer(&self) -> CompressionLayer<impl Predicate> {
+        let predicate = SizeAbove::new(self.min_size)
+            .and(NotForContentType::GRPC)
+            .and(NotForContentType::IMAGES)
+            // Compressing would hold events back until a block fills up
+            .and(NotForContentType::const_new("text/event-stream"));
+        CompressionLayer::new().compress_when(predicate)
+    }
+}
+
+/// Wrap `router` in the layers of `pipeline`, the first one outermost
+pub fn apply_pipeline(
+    router: Router<Arc<AppState>>,
+    state: &Arc<AppState>,
+    pipeline: &[Middleware],
+    cors: &CorsConfig,
+    compression: &CompressionConfig,
+) -> Result<Router<Arc<AppState>>, AppError> {
+    let cors = cors.layer()?;
+    let mut router = router;
+    // Each layer wraps the ones added before it
+    for middleware in pipeline.iter().rev() {
+        let state = state.clone();
+        router = match middleware {
+            Middleware::Trace => router.layer(TraceLayer::new_for_http()),
+            Middleware::RequestTracing => router.layer(axum_middleware::from_fn_with_state(
+                state,
+                request_tracing_middleware,
+            )),
+            Middleware::ErrorLogging => router.layer(axum_middleware::from_fn_with_state(
+                state,
+                error_logging_middleware,
+            )),
+            Middleware::CrossOriginIsolation => router.layer(axum_middleware::from_fn_with_state(
+                state,
+                cross_origin_isolation_middleware,
+            )),
+            Middleware::SecurityHeaders => {
+                router.layer(axum_middleware::from_fn(security_headers_middleware))
+            }
+            Middleware::Cors => match &cors {
+                Some(layer) => router.layer(layer.clone()),
+                None => router,
+            },
+            Middleware::Compression => router.layer(compression.layer()),
+            Middleware::Auth => {
+                router.layer(axum_middleware::from_fn_with_state(state, auth_middleware))
+            }
+            Middleware::LoadShedding => router.layer(axum_middleware::from_fn_with_state(
+                state,
+                load_shedding_middleware,
+            )),
+            Middleware::Deadline => router.layer(axum_middleware::from_fn(deadline_middleware)),
+            Middleware::HealthCheck => router.layer(axum_middleware::from_fn_with_state(
+                state,
+                health_check_middleware,
+            )),
+            Middleware::ConcurrencyLimit => state.load_shedder.limit_concurrency(router),
+        };
+    }
+    Ok(router)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_pipeline() {
+        assert_eq!(
+            parse_pipeline("health-check, trace").unwrap(),
+            [Middleware::HealthCheck, Middleware::Trace]
+        );
+        assert_eq!(parse_pipeline("").unwrap(), []);
+        assert!(parse_pipeline("trace,trace").is_err());
+        assert!(parse_pipeline("trace,gzip").is_err());
+
+        let names: Vec<String> = DEFAULT_PIPELINE.iter().map(|m| m.to_string()).collect();
+        assert_eq!(parse_pipeline(&names.join(",")).unwrap(), DEFAULT_PIPELINE);
+    }
+
+    #[test]
+    fn test_cors_origins() {
+        assert!(CorsConfig::default().layer().unwrap().is_none());
+        let config = CorsConfig {
+            origins: vec!["https://maps.example.com/".to_string()],
+        };
+        assert!(config.layer().unwrap().is_some());
+        let config = CorsConfig {
+            origins: vec!["https://bad\nexample.com".to_string()],
+        };
+        assert!(config.layer().is_err());
+    }
+}
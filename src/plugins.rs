@@ -0,0 +1,312 @@
+//! Custom derived variables
+//!
+//! Besides the built-in products of [`crate::derived`], deployments can serve
+//! their own derived variables. Library users implement [`DerivedVariable`]
+//! and register it in the [`DerivedRegistry`] of their `ServerConfig`.
+//! Operators can do the same without recompiling by loading a plugin file
+//! with `--derived-variables`: a JSON array of formulas over dataset
+//! variables, such as
+//!
+//! ```json
+//! [{
+//!     "name": "wave_steepness",
+//!     "long_name": "Significant wave steepness",
+//!     "units": "1",
+//!     "inputs": {"h": ["swh", "VHM0"], "t": ["mwp", "VTM02"]},
+//!     "expression": "2 * pi * h / (9.81 * t^2)"
+//! }]
+//! ```
+//!
+//! Each input lists candidate variable names, the first one in the dataset
+//! being used. The formula syntax is described in [`crate::expr`].
+//! Registered variables appear in the metadata catalog and are served by the
+//! data and Earth routes like the built-in products.
+
+use serde::Deserialize;
+use serde_json::Value;
+use std::{collections::BTreeMap, fmt, path::Path, sync::Arc};
+
+use crate::{analysis::variable_units, derived::DerivedProduct, error::AppError, expr::Expression};
+
+/// An input field of a derived variable at one timestep
+#[derive(Debug, Clone, Copy)]
+pub struct InputField<'a> {
+    /// Dataset variable name
+    pub name: &'a str,
+    /// `units` attribute of the variable, empty when it has none
+    pub units: &'a str,
+    /// Values in the backend layout, NaN where missing
+    pub values: &'a [f64],
+}
+
+/// A variable computed pointwise from dataset variables
+pub trait DerivedVariable: Send + Sync {
+    /// Name under which the variable appears in the catalog
+    fn name(&self) -> &str;
+
+    /// Human-readable description used as the `long_name` attribute
+    fn long_name(&self) -> &str;
+
+    /// The dataset variables to compute from, if the dataset has them. The
+    /// catalog entry takes the dimensions of the first one.
+    fn inputs(&self, metadata: &Value) -> Option<Vec<String>>;
+
+    /// Units of the variable, by default those of its first input
+    fn units(&self, metadata: &Value, inputs: &[String]) -> String {
+        variable_units(metadata, &inputs[0])
+    }
+
+    /// Compute the variable from its inputs, in the order returned by
+    /// [`DerivedVariable::inputs`]. Inputs have equal lengths, and the result
+    /// must have the same length.
+    fn compute(&self, inputs: &[InputField<'_>]) -> Result<Vec<f64>, AppError>;
+}
+
+/// The custom derived variables served by a deployment
+#[derive(Clone, Default)]
+pub struct DerivedRegistry {
+    variables: Vec<Arc<dyn DerivedVariable>>,
+}
+
+impl fmt::Debug for DerivedRegistry {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_list()
+            .entries(self.variables.iter().map(|v| v.name()))
+            .finish()
+    }
+}
+
+impl DerivedRegistry {
+    /// Add a variable. Names must be unique and may not shadow a built-in
+    /// product.
+    pub fn register(&mut self, variable: impl DerivedVariable + 'static) -> Result<(), AppError> {
+        let name = variable.name();
+        if name.is_empty()
+            || !name
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || matches!(c, '_' | '-' | '.'))
+        {
+            return Err(AppError::ConfigError(format!(
+                "Invalid derived variable name '{}'",
+                name
+            )));
+        }
+        if DerivedProduct::from_name(name).is_some() || self.contains(name) {
+            return Err(AppError::ConfigError(format!(
+                "Derived variable '{}' is already defined",
+                name
+            )));
+        }
+        self.variables.push(Arc::new(variable));
+        Ok(())
+    }
+
+    /// Load the formulas of a plugin file, returning the number registered
+    pub fn load(&mut self, path: &Path) -> Result<usize, AppError> {
+        let error = |message: String| {
+            AppError::ConfigError(format!(
+                "Cannot load derived variables from {}: {}",
+                path.display(),
+                message
+            ))
+        };
+        let contents = std::fs::read_to_string(path).map_err(|e| error(e.to_string()))?;
+        let definitions: Vec<ExpressionDefinition> =
+            serde_json::from_str(&contents).map_err(|e| error(e.to_string()))?;
+
+        let count = definitions.len();
+        for definition in definitions {
+            let variable = ExpressionVariable::new(definition).map_err(|e| error(e.to_string()))?;
+            self.register(variable).map_err(|e| error(e.to_string()))?;
+        }
+        Ok(count)
+    }
+
+    /// Look up a variable by name
+    pub fn get(&self, name: &str) -> Option<&dyn DerivedVariable> {
+        self.variables
+            .iter()
+            .find(|v| v.name() == name)
+            .map(|v| v.as_ref())
+    }
+
+    /// Whether a variable of this name is registered
+    pub fn contains(&self, name: &str) -> bool {
+        self.get(name).is_some()
+    }
+
+    /// Registered variables in registration order
+    pub fn iter(&self) -> impl Iterator<Item = &dyn DerivedVariable> {
+        self.variables.iter().map(|v| v.as_ref())
+    }
+
+    /// Number of registered variables
+    pub fn len(&self) -> usize {
+        self.variables.len()
+    }
+
+    /// Whether no variables are registered
+    pub fn is_empty(&self) -> bool {
+        self.variables.is_empty()
+    }
+}
+
+/// A formula as written in a plugin file
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ExpressionDefinition {
+    pub name: String,
+    /// Defaults to the name
+    pub long_name: Option<String>,
+    /// Defaults to the units of the first input
+    pub units: Option<String>,
+    /// Candidate dataset variables for each name used in the expression
+    pub inputs: BTreeMap<String, Vec<String>>,
+    pub expression: String,
+}
+
+/// A derived variable defined by a formula
+#[derive(Debug)]
+pub struct ExpressionVariable {
+    name: String,
+    long_name: String,
+    units: Option<String>,
+    /// Candidates of each input, in the order of the expression's variables
+    candidates: Vec<Vec<String>>,
+    expression: Expression,
+}
+
+impl ExpressionVariable {
+    pub fn new(definition: ExpressionDefinition) -> Result<Self, AppError> {
+        if definition.inputs.is_empty() {
+            return Err(AppError::ConfigError(format!(
+                "Derived variable '{}' has no inputs",
+                definition.name
+            )));
+        }
+        let aliases: Vec<&str> = definition.inputs.keys().map(String::as_str).collect();
+        let expression = Expression::parse(&definition.expression, &aliases)?;
+        Ok(Self {
+            long_name: definition
+                .long_name
+                .unwrap_or_else(|| definition.name.clone()),
+            name: definition.name,
+            units: definition.units,
+            candidates: definition.inputs.into_values().collect(),
+            expression,
+        })
+    }
+}
+
+impl DerivedVariable for ExpressionVariable {
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn long_name(&self) -> &str {
+        &self.long_name
+    }
+
+    fn inputs(&self, metadata: &Value) -> Option<Vec<String>> {
+        let variables = metadata.get("variables")?.as_object()?;
+        self.candidates
+            .iter()
+            .map(|candidates| {
+                candidates
+                    .iter()
+                    .find(|name| variables.contains_key(*name))
+                    .cloned()
+            })
+            .collect()
+    }
+
+    fn units(&self, metadata: &Value, inputs: &[String]) -> String {
+        match &self.units {
+            Some(units) => units.clone(),
+            None => variable_units(metadata, &inputs[0]),
+        }
+    }
+
+    fn compute(&self, inputs: &[InputField<'_>]) -> Result<Vec<f64>, AppError> {
+        let fields: Vec<&[f64]> = inputs.iter().map(|input| input.values).collect();
+        Ok(self.expression.evaluate_fields(&fields))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn steepness() -> ExpressionVariable {
+        ExpressionVariable::new(ExpressionDefinition {
+            name: "wave_steepness".to_string(),
+            long_name: None,
+            units: Some("1".to_string()),
+            inputs: BTreeMap::from([
+                ("h".to_string(), vec!["swh".to_string(), "VHM0".to_string()]),
+                ("t".to_string(), vec!["mwp".to_string()]),
+            ]),
+            expression: "h / t^2".to_string(),
+        })
+        .unwrap()
+    }
+
+    #[test]
+    fn test_expression_variable() {
+        let variable = steepness();
+        let metadata = json!({"variables": {"VHM0": {}, "mwp": {}}});
+        assert_eq!(
+            variable.inputs(&metadata),
+            Some(vec!["VHM0".to_string(), "mwp".to_string()])
+        );
+        assert_eq!(variable.inputs(&json!({"variables": {"swh": {}}})), None);
+        assert_eq!(variable.long_name(), "wave_steepness");
+
+        let values = variable
+            .compute(&[
+                InputField {
+                    name: "VHM0",
+                    units: "m",
+                    values: &[2.0, f64::NAN],
+                },
+                InputField {
+                    name: "mwp",
+                    units: "s",
+                    values: &[2.0, 4.0],
+                },
+            ])
+            .unwrap();
+        assert_eq!(values[0], 0.5);
+        assert!(values[1].is_nan());
+    }
+
+    #[test]
+    fn test_registry_rejects_duplicates() {
+        let mut registry = DerivedRegistry::default();
+        registry.register(steepness()).unwrap();
+        assert!(registry.register(steepness()).is_err());
+        assert!(registry.contains("wave_steepness"));
+        assert_eq!(format!("{:?}", registry), r#"["wave_steepness"]"#);
+
+        let mut shadowing = ExpressionDefinition {
+            name: "wind_chill".to_string(),
+            long_name: None,
+            units: None,
+            inputs: BTreeMap::from([("t".to_string(), vec!["t2m".to_string()])]),
+            expression: "t".to_string(),
+        };
+        let variable = ExpressionVariable::new(shadowing).unwrap();
+        assert!(registry.register(variable).is_err());
+
+        shadowing = ExpressionDefinition {
+            name: "t,u".to_string(),
+            long_name: None,
+            units: None,
+            inputs: BTreeMap::from([("t".to_string(), vec!["t2m".to_string()])]),
+            expression: "t".to_string(),
+        };
+        let variable = ExpressionVariable::new(shadowing).unwrap();
+        assert!(registry.register(variable).is_err());
+    }
+}
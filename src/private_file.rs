@@ -0,0 +1,99 @@
+//! Files holding private keys
+//!
+//! Keys are created readable by their owner only, whatever the umask. They
+//! are written aside and renamed into place, so a crash while writing leaves
+//! the previous file rather than part of a new one.
+
+use std::{
+    ffi::OsString,
+    fs,
+    io::{self, Write},
+    path::{Path, PathBuf},
+};
+
+#[cfg(unix)]
+use std::os::unix::fs::{OpenOptionsExt, PermissionsExt};
+
+/// Permissions of the files written
+#[cfg(unix)]
+const MODE: u32 = 0o600;
+
+/// Replace `path` with `contents`, readable and writable by the owner only
+pub fn write_private(path: &Path, contents: &[u8]) -> io::Result<()> {
+    let temp = temp_path(path);
+    let written = write_new(&temp, contents).and_then(|_| fs::rename(&temp, path));
+    if written.is_err() {
+        let _ = fs::remove_file(&temp);
+    }
+    written
+}
+
+/// Where `path` is written before it is renamed
+fn temp_path(path: &Path) -> PathBuf {
+    let mut name = OsString::from(path.as_os_str());
+    name.push(".tmp");
+    PathBuf::from(name)
+}
+
+fn write_new(path: &Path, contents: &[u8]) -> io::Result<()> {
+    // A file left by an interrupted write keeps its permissions when
+    // opened, so it is replaced
+    match fs::remove_file(path) {
+        Err(e) if e.kind() != io::ErrorKind::NotFound => return Err(e),
+        _ => {}
+    }
+    let mut options = fs::OpenOptions::new();
+    options.write(true).create_new(true);
+    #[cfg(unix)]
+    options.mode(MODE);
+    let mut file = options.open(path)?;
+    // The mode given on creation is masked by the umask
+    #[cfg(unix)]
+    file.set_permissions(fs::Permissions::from_mode(MODE))?;
+    file.write_all(contents)?;
+    file.sync_all()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn temp_dir() -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("rossby-vis-private-{}", uuid::Uuid::new_v4()));
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    #[test]
+    fn test_write_private_replaces_the_file() {
+        let dir = temp_dir();
+        let path = dir.join("key.pem");
+        fs::write(&path, b"old").unwrap();
+        fs::write(temp_path(&path), b"interrupted").unwrap();
+
+        write_private(&path, b"new").unwrap();
+        assert_eq!(fs::read(&path).unwrap(), b"new");
+        assert!(!temp_path(&path).exists());
+        #[cfg(unix)]
+        assert_eq!(
+            fs::metadata(&path).unwrap().permissions().mode() & 0o777,
+            MODE
+        );
+
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn test_failed_write_keeps_the_previous_file() {
+        let dir = temp_dir();
+        // Renaming a file over a directory fails
+        let path = dir.join("key.pem");
+        fs::create_dir(&path).unwrap();
+
+        assert!(write_private(&path, b"new").is_err());
+        assert!(path.is_dir());
+        assert!(!temp_path(&path).exists());
+
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
@@ -0,0 +1,279 @@
+//! Earth product catalog generated from the backend metadata
+//!
+//! Earth's `products.js` describes each overlay it can draw: its type,
+//! whether it is a scalar or vector field, the units and the colour scale.
+//! `/data/products.json` derives those descriptions from the live metadata
+//! catalog, derived products included, so variables added to the backend show
+//! up as overlays without editing the embedded JavaScript.
+
+use axum::{
+    extract::State,
+    http::{HeaderMap, Uri},
+    response::{IntoResponse, Response},
+    Json,
+};
+use serde::Serialize;
+use serde_json::Value;
+use std::sync::Arc;
+
+use crate::{
+    derived::register_derived_variables,
+    error::{AppError, ErrorBody},
+    freshness::DataFreshness,
+    grid::{
+        coordinate_values, is_latitude_dimension, is_longitude_dimension, is_vertical_dimension,
+    },
+    handlers::{analyze_metadata_variables, fetch_metadata, VariableCategory, VariableType},
+    server::AppState,
+};
+
+/// Version of the `/data/products.json` format
+const PRODUCTS_VERSION: u32 = 1;
+
+/// One overlay the frontend can offer
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct EarthProduct {
+    /// Overlay type, the name used in the page's hash and data requests
+    #[serde(rename = "type")]
+    pub product_type: String,
+    /// `"scalar"` or `"vector"`
+    pub field: &'static str,
+    /// Variables requested from `/proxy/data`, u before v for vectors
+    pub vars: Vec<String>,
+    pub name: String,
+    pub units: String,
+    /// Quantity used to pick default units and colour scales, as named by
+    /// the frontend (`"Temperature"`, `"Pressure"`, ...)
+    pub category: &'static str,
+    /// Whether the variable varies in time
+    pub time: bool,
+    /// Vertical levels, for variables on more than one
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub levels: Option<Levels>,
+    /// Colour scale bounds from the variable's valid or actual range
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub bounds: Option<[f64; 2]>,
+    /// Computed by this server from other variables
+    pub derived: bool,
+}
+
+/// Vertical axis of a product
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Levels {
+    pub dimension: String,
+    pub values: Vec<f64>,
+}
+
+/// Body of `/data/products.json`
+#[derive(Debug, Serialize)]
+pub struct ProductCatalog {
+    pub version: u32,
+    pub products: Vec<EarthProduct>,
+}
+
+/// Products for every variable of `metadata` on a latitude/longitude grid.
+/// Derived products must already be registered in the metadata.
+pub fn earth_products(metadata: &Value) -> Vec<EarthProduct> {
+    let mut products: Vec<EarthProduct> = analyze_metadata_variables(metadata)
+        .into_iter()
+        .filter(|info| {
+            info.dimensions.iter().any(|d| is_latitude_dimension(d))
+                && info.dimensions.iter().any(|d| is_longitude_dimension(d))
+        })
+        .map(|info| {
+            let (field, vars, name) = match &info.var_type {
+                VariableType::Vector {
+                    u_component,
+                    v_component,
+                } => (
+                    "vector",
+                    vec![u_component.clone(), v_component.clone()],
+                    match info.category {
+                        VariableCategory::Wind => info.display_name.clone(),
+                        _ => info.long_name.clone(),
+                    },
+                ),
+                VariableType::Scalar => ("scalar", vec![info.name.clone()], info.long_name.clone()),
+            };
+            let levels = info
+                .dimensions
+                .iter()
+                .find(|d| is_vertical_dimension(d))
+                .and_then(|dimension| {
+                    let values = coordinate_values(metadata, dimension)?;
+                    (values.len() > 1).then(|| Levels {
+                        dimension: dimension.clone(),
+                        values,
+                    })
+                });
+            let attributes = metadata
+                .get("variables")
+                .and_then(|v| v.get(&info.name))
+                .and_then(|v| v.get("attributes"));
+
+            EarthProduct {
+                product_type: info.name.clone(),
+                field,
+                vars,
+                name,
+                units: info.units.clone(),
+                category: quantity_name(&info.category),
+                time: info.dimensions.iter().any(|d| d == "time"),
+                levels,
+                bounds: attributes.and_then(value_bounds),
+                derived: attributes.is_some_and(|a| a.get("derived_from").is_some()),
+            }
+        })
+        .collect();
+    products.sort_by(|a, b| a.product_type.cmp(&b.product_type));
+    products
+}
+
+/// The frontend's name for a quantity
+fn quantity_name(category: &VariableCategory) -> &'static str {
+    match category {
+        VariableCategory::Temperature => "Temperature",
+        VariableCategory::Wind => "Wind",
+        VariableCategory::Pressure => "Pressure",
+        VariableCategory::Humidity => "Humidity",
+        VariableCategory::Precipitation => "Precipitation",
+        VariableCategory::Radiation => "Radiation",
+        VariableCategory::Cloud => "Cloud",
+        VariableCategory::General => "General",
+    }
+}
+
+/// Bounds from the CF `valid_min`/`valid_max`, `valid_range` or
+/// `actual_range` attributes
+fn value_bounds(attributes: &Value) -> Option<[f64; 2]> {
+    let number = |name: &str| attributes.get(name).and_then(Value::as_f64);
+    let range = |name: &str| {
+        let range = attributes.get(name)?.as_array()?;
+        match range.as_slice() {
+            [low, high] => Some([low.as_f64()?, high.as_f64()?]),
+            _ => None,
+        }
+    };
+
+    let bounds = match (number("valid_min"), number("valid_max")) {
+        (Some(low), Some(high)) => Some([low, high]),
+        _ => range("valid_range").or_else(|| range("actual_range")),
+    }?;
+    (bounds[0] < bounds[1]).then_some(bounds)
+}
+
+/// Handler for `GET /data/products.json`
+#[utoipa::path(
+    get,
+    path = "/data/products.json",
+    tag = "earth",
+    summary = "Overlay descriptions for the frontend, generated from the metadata",
+    responses(
+        (status = 200, body = serde_json::Value),
+        (status = 304, description = "Unchanged since the request's `If-None-Match` or `If-Modified-Since`"),
+        (status = 502, description = "The backend failed", body = ErrorBody),
+    )
+)]
+pub async fn products_catalog(
+    State(state): State<Arc<AppState>>,
+    uri: Uri,
+    headers: HeaderMap,
+) -> Result<Response, AppError> {
+    let mut metadata = fetch_metadata(&state).await?;
+    let freshness = DataFreshness::new(&metadata, &uri.to_string());
+    if freshness.matches(&headers) {
+        return Ok(freshness.not_modified());
+    }
+    register_derived_variables(&mut metadata, &state.derived_variables);
+
+    let mut products = earth_products(&metadata);
+    let hints = state.render_hints.read().unwrap_or_else(|e| e.into_inner());
+    for product in &mut products {
+        let vars: Vec<&str> = product.vars.iter().map(String::as_str).collect();
+        if let Some(name) = hints.for_field(&vars).display_name {
+            product.name = name;
+        }
+    }
+    drop(hints);
+
+    let catalog = ProductCatalog {
+        version: PRODUCTS_VERSION,
+        products,
+    };
+    Ok(freshness.apply(Json(catalog).into_response()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn test_earth_products() {
+        let mut metadata = json!({
+            "coordinates": {
+                "latitude": [10.0, 0.0],
+                "longitude": [0.0, 10.0],
+                "level": [850.0, 500.0],
+                "time": [700464.0]
+            },
+            "variables": {
+                "t2m": {
+                    "dimensions": ["time", "latitude", "longitude"],
+                    "attributes": {
+                        "long_name": "2 metre temperature",
+                        "units": "K",
+                        "valid_range": [180.0, 340.0]
+                    }
+                },
+                "u10": {
+                    "dimensions": ["time", "latitude", "longitude"],
+                    "attributes": {"long_name": "10 metre U wind component", "units": "m s**-1"}
+                },
+                "v10": {
+                    "dimensions": ["time", "latitude", "longitude"],
+                    "attributes": {"long_name": "10 metre V wind component", "units": "m s**-1"}
+                },
+                "z": {
+                    "dimensions": ["time", "level", "latitude", "longitude"],
+                    "attributes": {"long_name": "Geopotential", "units": "m**2 s**-2"}
+                },
+                "station_id": {"dimensions": ["station"]}
+            }
+        });
+        register_derived_variables(&mut metadata, &Default::default());
+        let products = earth_products(&metadata);
+        let types: Vec<&str> = products.iter().map(|p| p.product_type.as_str()).collect();
+        assert_eq!(types, ["t2m", "u10", "wind_chill", "z"]);
+
+        let t2m = &products[0];
+        assert_eq!(t2m.field, "scalar");
+        assert_eq!(t2m.category, "Temperature");
+        assert_eq!(t2m.bounds, Some([180.0, 340.0]));
+        assert!(t2m.time && !t2m.derived);
+
+        let wind = &products[1];
+        assert_eq!(wind.field, "vector");
+        assert_eq!(wind.vars, ["u10", "v10"]);
+        assert_eq!(wind.name, "Wind");
+
+        assert!(products[2].derived);
+        assert_eq!(
+            products[3].levels,
+            Some(Levels {
+                dimension: "level".to_string(),
+                values: vec![850.0, 500.0],
+            })
+        );
+    }
+
+    #[test]
+    fn test_value_bounds() {
+        assert_eq!(
+            value_bounds(&json!({"valid_min": 0.0, "valid_max": 1.0})),
+            Some([0.0, 1.0])
+        );
+        assert_eq!(value_bounds(&json!({"actual_range": [5, 2]})), None);
+        assert_eq!(value_bounds(&json!({})), None);
+    }
+}
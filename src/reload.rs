@@ -0,0 +1,244 @@
+//! Configuration reload on SIGHUP
+//!
+//! `kill -HUP <pid>`, or `systemctl reload` with
+//! `ExecReload=/bin/kill -HUP $MAINPID`, reads the settings file, the
+//! environment and the command line again and applies the settings that can
+//! change while the server runs, without dropping connections:
+//!
+//! - `--log-level` and `--log-targets`
+//! - `--cache-ttl` and `--disk-cache-ttl`, counted from when each entry was
+//!   stored
+//! - `--client-error-rate`
+//! - the `--render-hints` files, which are read again, display names
+//!   included
+//!
+//! One log line lists what changed and which other options differ from
+//! those the server was started with, which only take effect after a
+//! restart. A configuration that fails to load is rejected as a whole, and
+//! the server keeps running with the previous one.
+
+use std::time::Duration;
+use tracing::warn;
+
+use crate::{error::AppError, hints::RenderHints, server::AppState};
+
+/// Options applied by a reload; the others need a restart
+pub const RELOADABLE_OPTIONS: [&str; 6] = [
+    "log-level",
+    "log-targets",
+    "cache-ttl",
+    "disk-cache-ttl",
+    "client-error-rate",
+    "render-hints",
+];
+
+/// The settings a reload applies
+#[derive(Debug, Clone, PartialEq)]
+pub struct ReloadableSettings {
+    /// Log filter directives, e.g. `info,proxy=debug`
+    pub log_filter: String,
+    /// How long responses are served from memory
+    pub cache_ttl: Duration,
+    /// How long responses are served from disk
+    pub disk_cache_ttl: Duration,
+    /// Client error reports logged per client per minute
+    pub client_error_rate: u32,
+    /// Per-variable rendering hints from the `--render-hints` files
+    pub render_hints: RenderHints,
+}
+
+/// Apply the settings of `next` that differ from `previous` to `state`,
+/// describing each change
+///
+/// Only the settings changed in the configuration are applied, so a log
+/// level set through `/admin/loglevel` is kept unless the configured one
+/// changes too.
+pub fn apply(
+    state: &AppState,
+    previous: &ReloadableSettings,
+    next: &ReloadableSettings,
+) -> Result<Vec<String>, AppError> {
+    let mut changes = Vec::new();
+    if next.log_filter != previous.log_filter {
+        // Applied first, as the only setting that can still be refused
+        let handle = state.log_level.as_ref().ok_or_else(|| {
+            AppError::ConfigError("runtime log level changes are not available".to_string())
+        })?;
+        handle
+            .set(&next.log_filter)
+            .map_err(AppError::ConfigError)?;
+        changes.push(format!(
+            "log level '{}' -> '{}'",
+            previous.log_filter, next.log_filter
+        ));
+    }
+    if (next.cache_ttl, next.disk_cache_ttl) != (previous.cache_ttl, previous.disk_cache_ttl) {
+        state
+            .response_cache
+            .set_ttls(next.cache_ttl, next.disk_cache_ttl);
+        if next.cache_ttl != previous.cache_ttl {
+            changes.push(format!(
+                "cache TTL {:?} -> {:?}",
+                previous.cache_ttl, next.cache_ttl
+            ));
+        }
+        if next.disk_cache_ttl != previous.disk_cache_ttl {
+            changes.push(format!(
+                "disk cache TTL {:?} -> {:?}",
+                previous.disk_cache_ttl, next.disk_cache_ttl
+            ));
+        }
+    }
+    if next.client_error_rate != previous.client_error_rate {
+        state.client_errors.set_rate(next.client_error_rate);
+        changes.push(format!(
+            "client error rate {} -> {} per minute",
+            previous.client_error_rate, next.client_error_rate
+        ));
+    }
+    if next.render_hints != previous.render_hints {
+        *state
+            .render_hints
+            .write()
+            .unwrap_or_else(|e| e.into_inner()) = next.render_hints.clone();
+        changes.push("render hints".to_string());
+    }
+    Ok(changes)
+}
+
+/// The options among `changed` that only take effect after a restart
+pub fn requiring_restart(changed: Vec<String>) -> Vec<String> {
+    changed
+        .into_iter()
+        .filter(|option| !RELOADABLE_OPTIONS.contains(&option.as_str()))
+        .collect()
+}
+
+/// The log line of a reload that applied `changes` and left the options
+/// `restart` for a restart
+pub fn summary(changes: &[String], restart: &[String]) -> String {
+    let mut line = if changes.is_empty() {
+        "Configuration reloaded, nothing changed".to_string()
+    } else {
+        format!("Configuration reloaded, changed {}", changes.join(", "))
+    };
+    if !restart.is_empty() {
+        let options: Vec<String> = restart
+            .iter()
+            .map(|option| format!("--{}", option))
+            .collect();
+        line.push_str(&format!(
+            "; a restart is needed to apply {}",
+            options.join(", ")
+        ));
+    }
+    line
+}
+
+/// Call `reload` on every SIGHUP, for as long as the process runs
+#[cfg(unix)]
+pub async fn on_hangup(mut reload: impl FnMut()) {
+    use tokio::signal::unix::{signal, SignalKind};
+
+    let mut hangups = match signal(SignalKind::hangup()) {
+        Ok(hangups) => hangups,
+        Err(e) => {
+            warn!("Cannot reload the configuration on SIGHUP: {}", e);
+            return;
+        }
+    };
+    while hangups.recv().await.is_some() {
+        reload();
+    }
+}
+
+/// There is no SIGHUP outside Unix, so the configuration is never reloaded
+#[cfg(not(unix))]
+pub async fn on_hangup(_reload: impl FnMut()) {
+    warn!("Reloading the configuration needs SIGHUP, which is Unix only");
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{client_errors::ClientErrorLimiter, logging::log_level_layer};
+    use std::sync::Arc;
+
+    fn settings() -> ReloadableSettings {
+        ReloadableSettings {
+            log_filter: "info".to_string(),
+            cache_ttl: Duration::from_secs(60),
+            disk_cache_ttl: Duration::from_secs(3600),
+            client_error_rate: 60,
+            render_hints: RenderHints::default(),
+        }
+    }
+
+    #[test]
+    fn test_apply_changed_settings() {
+        let (_layer, handle) = log_level_layer("info");
+        let mut state = AppState::new("http://localhost:8000".to_string(), reqwest::Client::new());
+        state.log_level = Some(handle.clone());
+        state.client_errors = Arc::new(ClientErrorLimiter::new(60));
+        let previous = settings();
+        assert!(apply(&state, &previous, &previous).unwrap().is_empty());
+
+        let mut next = settings();
+        next.log_filter = "debug,hyper=warn".to_string();
+        next.cache_ttl = Duration::from_secs(120);
+        next.client_error_rate = 10;
+        next.render_hints
+            .add_json(r#"[{"variable": "t2m", "display_name": "Air temperature"}]"#)
+            .unwrap();
+        assert_eq!(
+            apply(&state, &previous, &next).unwrap(),
+            vec![
+                "log level 'info' -> 'debug,hyper=warn'",
+                "cache TTL 60s -> 120s",
+                "client error rate 60 -> 10 per minute",
+                "render hints",
+            ]
+        );
+        assert_eq!(handle.current(), "debug,hyper=warn");
+        assert_eq!(state.response_cache.ttls().0, Duration::from_secs(120));
+        assert_eq!(state.client_errors.rate(), 10);
+        assert_eq!(
+            state
+                .render_hints
+                .read()
+                .unwrap()
+                .for_field(&["t2m"])
+                .display_name,
+            Some("Air temperature".to_string())
+        );
+
+        // A level set through the admin API outlives unrelated reloads
+        handle.set("trace").unwrap();
+        apply(&state, &next, &next).unwrap();
+        assert_eq!(handle.current(), "trace");
+    }
+
+    #[test]
+    fn test_invalid_log_filter_is_refused_first() {
+        let (_layer, handle) = log_level_layer("info");
+        let mut state = AppState::new("http://localhost:8000".to_string(), reqwest::Client::new());
+        state.log_level = Some(handle);
+        let mut next = settings();
+        next.log_filter = "info,[".to_string();
+        next.client_error_rate = 1;
+
+        assert!(apply(&state, &settings(), &next).is_err());
+        assert_eq!(state.client_errors.rate(), 60);
+    }
+
+    #[test]
+    fn test_summary() {
+        let restart = requiring_restart(vec!["cache-ttl".to_string(), "port".to_string()]);
+        assert_eq!(restart, vec!["port"]);
+        assert_eq!(
+            summary(&["cache TTL 60s -> 120s".to_string()], &restart),
+            "Configuration reloaded, changed cache TTL 60s -> 120s; a restart is needed to apply --port"
+        );
+        assert_eq!(summary(&[], &[]), "Configuration reloaded, nothing changed");
+    }
+}
@@ -0,0 +1,403 @@
+//! Recording and replaying backend interactions
+//!
+//! With `--record <dir>` every request the server makes to the Rossby backend
+//! is written to a fixture file in `<dir>` together with the response. With
+//! `--replay <dir>` those fixtures answer the requests instead, so
+//! integration tests and offline demos run without a live backend and always
+//! see the same data.
+//!
+//! Both modes run a small HTTP server on a loopback port and point the
+//! backend URL at it, so the handlers talk to the backend exactly as they
+//! otherwise would. When recording, responses are read in full before being
+//! passed on. A request without a fixture is answered with `404 Not Found`.
+//!
+//! Each fixture is a JSON document named after the request path and a hash
+//! of the request, e.g. `data-5d41402abc4b2a76.json`:
+//!
+//! ```json
+//! {
+//!     "method": "GET",
+//!     "path": "/data",
+//!     "query": "time=700470&vars=t2m",
+//!     "status": 200,
+//!     "headers": {"content-type": "application/json"},
+//!     "body": "{\"metadata\": ...}"
+//! }
+//! ```
+//!
+//! Query parameters are compared in sorted order. Bodies that are not UTF-8
+//! are stored in `body_base64` instead of `body`.
+
+use axum::{
+    body::{Bytes, Full},
+    extract::State,
+    http::{header, HeaderMap, HeaderName, HeaderValue, Method, StatusCode, Uri},
+    response::{IntoResponse, Response},
+    Router,
+};
+use base64::{engine::general_purpose::STANDARD, Engine};
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use sha2::{Digest, Sha256};
+use std::{
+    collections::{BTreeMap, HashMap},
+    path::{Path, PathBuf},
+    sync::Arc,
+};
+use tokio::net::TcpListener;
+use tracing::{debug, info, warn};
+
+use crate::error::AppError;
+
+/// Where backend interactions are recorded to or replayed from
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Recording {
+    /// Pass requests on to the backend and save them in the directory
+    Record(PathBuf),
+    /// Answer requests from the fixtures in the directory
+    Replay(PathBuf),
+}
+
+/// Headers that belong to one connection rather than to the response
+pub(crate) const HOP_BY_HOP: [&str; 4] =
+    ["connection", "content-length", "host", "transfer-encoding"];
+
+/// A recorded request and its response
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Fixture {
+    pub method: String,
+    pub path: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub query: Option<String>,
+    pub status: u16,
+    #[serde(default)]
+    pub headers: BTreeMap<String, String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub body: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub body_base64: Option<String>,
+}
+
+impl Fixture {
+    /// The key matching requests are looked up by
+    pub fn key(&self) -> String {
+        request_key(&self.method, &self.path, self.query.as_deref())
+    }
+
+    /// File name of the fixture, readable and unique per request
+    pub fn file_name(&self) -> String {
+        let slug: String = self
+            .path
+            .trim_matches('/')
+            .chars()
+            .map(|c| if c.is_ascii_alphanumeric() { c } else { '_' })
+            .collect();
+        let hash: String = Sha256::digest(self.key().as_bytes())[..8]
+            .iter()
+            .map(|b| format!("{:02x}", b))
+            .collect();
+        if slug.is_empty() {
+            format!("root-{}.json", hash)
+        } else {
+            format!("{}-{}.json", slug, hash)
+        }
+    }
+
+    fn body_bytes(&self) -> Result<Vec<u8>, AppError> {
+        match (&self.body, &self.body_base64) {
+            (_, Some(encoded)) => STANDARD
+                .decode(encoded)
+                .map_err(|e| AppError::ConfigError(format!("Invalid body_base64: {}", e))),
+            (Some(body), None) => Ok(body.clone().into_bytes()),
+            (None, None) => Ok(Vec::new()),
+        }
+    }
+
+    fn into_response(self) -> Response {
+        let body = match self.body_bytes() {
+            Ok(body) => body,
+            Err(e) => return e.into_response(),
+        };
+        let mut response = Response::new(Full::from(body));
+        *response.status_mut() =
+            StatusCode::from_u16(self.status).unwrap_or(StatusCode::INTERNAL_SERVER_ERROR);
+        for (name, value) in &self.headers {
+            if let (Ok(name), Ok(value)) = (
+                HeaderName::try_from(name.as_str()),
+                HeaderValue::from_str(value),
+            ) {
+                response.headers_mut().insert(name, value);
+            }
+        }
+        response.into_response()
+    }
+}
+
+/// Identify a request by its method, path and query parameters in sorted
+/// order
+pub fn request_key(method: &str, path: &str, query: Option<&str>) -> String {
+    let mut params: Vec<&str> = query
+        .unwrap_or_default()
+        .split('&')
+        .filter(|param| !param.is_empty())
+        .collect();
+    params.sort_unstable();
+    if params.is_empty() {
+        format!("{} {}", method, path)
+    } else {
+        format!("{} {}?{}", method, path, params.join("&"))
+    }
+}
+
+impl Recording {
+    /// Start serving the backend from loopback, returning the URL to use as
+    /// the backend. `backend_url` and `client` are only used when recording.
+    pub async fn start(
+        &self,
+        backend_url: &str,
+        client: reqwest::Client,
+    ) -> Result<String, AppError> {
+        let app = match self {
+            Recording::Record(dir) => {
+                tokio::fs::create_dir_all(dir).await.map_err(|e| {
+                    AppError::ConfigError(format!(
+                        "Cannot record fixtures in {}: {}",
+                        dir.display(),
+                        e
+                    ))
+                })?;
+                info!("Recording backend responses in {}", dir.display());
+                Router::new()
+                    .fallback(record)
+                    .with_state(Arc::new(Recorder {
+                        dir: dir.clone(),
+                        backend_url: backend_url.trim_end_matches('/').to_string(),
+                        client,
+                    }))
+            }
+            Recording::Replay(dir) => {
+                let fixtures = load_fixtures(dir)?;
+                if fixtures.is_empty() {
+                    warn!("No fixtures found in {}", dir.display());
+                }
+                info!(
+                    "Replaying {} backend responses from {}",
+                    fixtures.len(),
+                    dir.display()
+                );
+                Router::new()
+                    .fallback(replay)
+                    .with_state(Arc::new(fixtures))
+            }
+        };
+
+        serve_loopback(app).await
+    }
+}
+
+/// Serve `app` on a free loopback port in the background, returning its URL
+pub(crate) async fn serve_loopback(app: Router) -> Result<String, AppError> {
+    let listener = TcpListener::bind("127.0.0.1:0").await?;
+    let addr = listener.local_addr()?;
+    let server = axum::Server::from_tcp(listener.into_std()?)
+        .map_err(|e| AppError::ConfigError(format!("Cannot start local backend: {}", e)))?
+        .serve(app.into_make_service());
+    tokio::spawn(async move {
+        if let Err(e) = server.await {
+            warn!("Local backend stopped: {}", e);
+        }
+    });
+    Ok(format!("http://{}", addr))
+}
+
+/// Read every fixture in `dir`, keyed by request
+pub fn load_fixtures(dir: &Path) -> Result<HashMap<String, Fixture>, AppError> {
+    let error = |path: &Path, message: String| {
+        AppError::ConfigError(format!(
+            "Cannot load fixtures from {}: {}",
+            path.display(),
+            message
+        ))
+    };
+    let entries = std::fs::read_dir(dir).map_err(|e| error(dir, e.to_string()))?;
+    let mut fixtures = HashMap::new();
+    for entry in entries {
+        let path = entry.map_err(|e| error(dir, e.to_string()))?.path();
+        if path.extension().and_then(|ext| ext.to_str()) != Some("json") {
+            continue;
+        }
+        let contents = std::fs::read_to_string(&path).map_err(|e| error(&path, e.to_string()))?;
+        let fixture: Fixture =
+            serde_json::from_str(&contents).map_err(|e| error(&path, e.to_string()))?;
+        fixtures.insert(fixture.key(), fixture);
+    }
+    Ok(fixtures)
+}
+
+struct Recorder {
+    dir: PathBuf,
+    backend_url: String,
+    client: reqwest::Client,
+}
+
+impl Recorder {
+    async fn forward(
+        &self,
+        method: Method,
+        uri: &Uri,
+        headers: HeaderMap,
+        body: Bytes,
+    ) -> Result<Fixture, AppError> {
+        let url = format!(
+            "{}{}",
+            self.backend_url,
+            uri.path_and_query().map_or("/", |pq| pq.as_str())
+        );
+        let mut request = self.client.request(method.clone(), url).body(body);
+        for (name, value) in &headers {
+            if !HOP_BY_HOP.contains(&name.as_str()) {
+                request = request.header(name, value);
+            }
+        }
+        let response = request
+            .send()
+            .await
+            .map_err(|e| AppError::ProxyError(e.to_string()))?;
+
+        let status = response.status().as_u16();
+        let headers = response
+            .headers()
+            .iter()
+            .filter(|(name, _)| !HOP_BY_HOP.contains(&name.as_str()))
+            .filter_map(|(name, value)| Some((name.to_string(), value.to_str().ok()?.to_string())))
+            .collect();
+        let bytes = response
+            .bytes()
+            .await
+            .map_err(|e| AppError::ProxyError(e.to_string()))?;
+        let (body, body_base64) = match String::from_utf8(bytes.to_vec()) {
+            Ok(text) => (Some(text), None),
+            Err(_) => (None, Some(STANDARD.encode(&bytes))),
+        };
+        Ok(Fixture {
+            method: method.to_string(),
+            path: uri.path().to_string(),
+            query: uri.query().map(str::to_string),
+            status,
+            headers,
+            body,
+            body_base64,
+        })
+    }
+
+    async fn save(&self, fixture: &Fixture) -> Result<(), AppError> {
+        let path = self.dir.join(fixture.file_name());
+        let contents = serde_json::to_vec_pretty(fixture).unwrap_or_default();
+        tokio::fs::write(&path, contents).await.map_err(|e| {
+            AppError::ServerError(std::io::Error::new(
+                e.kind(),
+                format!("Cannot write fixture {}: {}", path.display(), e),
+            ))
+        })?;
+        debug!("Recorded {} in {}", fixture.key(), path.display());
+        Ok(())
+    }
+}
+
+async fn record(
+    State(recorder): State<Arc<Recorder>>,
+    method: Method,
+    uri: Uri,
+    headers: HeaderMap,
+    body: Bytes,
+) -> Response {
+    let fixture = match recorder.forward(method, &uri, headers, body).await {
+        Ok(fixture) => fixture,
+        Err(e) => {
+            warn!("Cannot record {}: {}", uri, e);
+            return e.into_response();
+        }
+    };
+    if let Err(e) = recorder.save(&fixture).await {
+        warn!("{}", e);
+    }
+    fixture.into_response()
+}
+
+async fn replay(
+    State(fixtures): State<Arc<HashMap<String, Fixture>>>,
+    method: Method,
+    uri: Uri,
+) -> Response {
+    let key = request_key(method.as_str(), uri.path(), uri.query());
+    match fixtures.get(&key) {
+        Some(fixture) => fixture.clone().into_response(),
+        None => {
+            warn!("No recorded response for {}", key);
+            (
+                StatusCode::NOT_FOUND,
+                [(header::CONTENT_TYPE, "application/json")],
+                json!({"error": format!("No recorded response for {}", key)}).to_string(),
+            )
+                .into_response()
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn fixture(path: &str, query: Option<&str>) -> Fixture {
+        Fixture {
+            method: "GET".to_string(),
+            path: path.to_string(),
+            query: query.map(str::to_string),
+            status: 200,
+            headers: BTreeMap::new(),
+            body: None,
+            body_base64: None,
+        }
+    }
+
+    #[test]
+    fn test_request_key_sorts_parameters() {
+        assert_eq!(
+            request_key("GET", "/data", Some("vars=t2m&time=700470")),
+            request_key("GET", "/data", Some("time=700470&vars=t2m"))
+        );
+        assert_eq!(request_key("GET", "/metadata", Some("")), "GET /metadata");
+        assert_ne!(
+            request_key("GET", "/data", Some("vars=t2m")),
+            request_key("GET", "/data", Some("vars=u10"))
+        );
+    }
+
+    #[test]
+    fn test_fixture_file_names() {
+        let data = fixture("/data", Some("vars=t2m"));
+        assert!(data.file_name().starts_with("data-"));
+        assert!(data.file_name().ends_with(".json"));
+        assert_ne!(
+            data.file_name(),
+            fixture("/data", Some("vars=u10")).file_name()
+        );
+        assert_eq!(
+            data.file_name(),
+            fixture("/data", Some("vars=t2m")).file_name()
+        );
+        assert!(fixture("/", None).file_name().starts_with("root-"));
+        assert!(fixture("/v1/metadata", None)
+            .file_name()
+            .starts_with("v1_metadata-"));
+    }
+
+    #[test]
+    fn test_binary_bodies_round_trip() {
+        let mut binary = fixture("/data", None);
+        binary.body_base64 = Some(STANDARD.encode([0xff, 0x00, 0x80]));
+        let text = serde_json::to_string(&binary).unwrap();
+        let parsed: Fixture = serde_json::from_str(&text).unwrap();
+        assert_eq!(parsed.body_bytes().unwrap(), [0xff, 0x00, 0x80]);
+    }
+}
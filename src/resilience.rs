@@ -0,0 +1,306 @@
+//! Circuit breaker around the Rossby backend
+//!
+//! A backend that is down or drowning used to receive every request the
+//! clients made, each waiting for its own timeout and adding to the load.
+//! With `--circuit-breaker-failures N`, the circuit opens after N backend
+//! requests in a row failed to connect, timed out or got a `5xx`. While it
+//! is open, backend requests fail at once with `503 Service Unavailable`,
+//! and responses still in the response cache are served past their TTL
+//! instead.
+//!
+//! After `--circuit-breaker-cooldown` seconds the circuit half-opens: up to
+//! `--circuit-breaker-probes` requests are let through as probes. The first
+//! probe to succeed closes the circuit; a failing probe opens it for
+//! another cooldown.
+
+use serde::Serialize;
+use std::{
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+use tracing::{info, warn};
+
+use crate::{concurrency::Outcome, error::AppError};
+
+/// How long the circuit stays open by default before probing
+pub const DEFAULT_COOLDOWN: Duration = Duration::from_secs(30);
+/// Probes let through a half-open circuit by default
+pub const DEFAULT_PROBES: u32 = 1;
+
+/// When the circuit opens and how it recovers
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CircuitBreakerConfig {
+    /// Consecutive failures opening the circuit, 0 disables the breaker
+    pub failure_threshold: u32,
+    /// How long the circuit stays open before probing the backend
+    pub cooldown: Duration,
+    /// Requests let through at once while half-open
+    pub probes: u32,
+}
+
+impl Default for CircuitBreakerConfig {
+    fn default() -> Self {
+        Self {
+            failure_threshold: 0,
+            cooldown: DEFAULT_COOLDOWN,
+            probes: DEFAULT_PROBES,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Circuit {
+    /// Requests pass; counts the failures in a row
+    Closed { failures: u32 },
+    /// Requests fail until the cooldown ends
+    Open { until: Instant },
+    /// Probes in flight to a backend that may have recovered
+    HalfOpen { probes: u32 },
+}
+
+#[derive(Debug)]
+struct BreakerState {
+    circuit: Circuit,
+    opened: u64,
+    rejected: u64,
+}
+
+/// Current state of the breaker, for the admin overview
+#[derive(Debug, Clone, Serialize)]
+pub struct CircuitStats {
+    pub state: &'static str,
+    pub consecutive_failures: u32,
+    /// Times the circuit opened since startup
+    pub opened: u64,
+    /// Requests failed because the circuit was open
+    pub rejected: u64,
+}
+
+/// Stops backend requests after repeated failures
+#[derive(Debug)]
+pub struct CircuitBreaker {
+    config: CircuitBreakerConfig,
+    state: Mutex<BreakerState>,
+}
+
+impl Default for CircuitBreaker {
+    fn default() -> Self {
+        Self::new(CircuitBreakerConfig::default())
+    }
+}
+
+impl CircuitBreaker {
+    pub fn new(config: CircuitBreakerConfig) -> Self {
+        Self {
+            config,
+            state: Mutex::new(BreakerState {
+                circuit: Circuit::Closed { failures: 0 },
+                opened: 0,
+                rejected: 0,
+            }),
+        }
+    }
+
+    pub fn config(&self) -> CircuitBreakerConfig {
+        self.config
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.config.failure_threshold > 0
+    }
+
+    fn state(&self) -> std::sync::MutexGuard<'_, BreakerState> {
+        self.state.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    /// Whether requests currently pass without restriction
+    pub fn is_closed(&self) -> bool {
+        matches!(self.state().circuit, Circuit::Closed { .. })
+    }
+
+    pub fn stats(&self) -> CircuitStats {
+        let state = self.state();
+        let (name, failures) = match state.circuit {
+            Circuit::Closed { failures } => ("closed", failures),
+            Circuit::Open { .. } => ("open", 0),
+            Circuit::HalfOpen { .. } => ("half-open", 0),
+        };
+        CircuitStats {
+            state: name,
+            consecutive_failures: failures,
+            opened: state.opened,
+            rejected: state.rejected,
+        }
+    }
+
+    /// Let a backend request through, unless the circuit is open or the
+    /// half-open circuit has all its probes in flight
+    pub fn admit(self: &Arc<Self>) -> Result<BreakerPermit, AppError> {
+        let mut state = self.state();
+        let probe = match state.circuit {
+            _ if !self.is_enabled() => false,
+            Circuit::Closed { .. } => false,
+            Circuit::Open { until } if Instant::now() >= until => {
+                info!("Backend circuit half-open, probing the backend");
+                state.circuit = Circuit::HalfOpen { probes: 1 };
+                true
+            }
+            Circuit::HalfOpen { probes } if probes < self.config.probes => {
+                state.circuit = Circuit::HalfOpen { probes: probes + 1 };
+                true
+            }
+            Circuit::Open { until } => {
+                state.rejected += 1;
+                return Err(AppError::Overloaded(format!(
+                    "the backend circuit is open after repeated failures, retrying in {}s",
+                    until.saturating_duration_since(Instant::now()).as_secs() + 1
+                )));
+            }
+            Circuit::HalfOpen { .. } => {
+                state.rejected += 1;
+                return Err(AppError::Overloaded(
+                    "the backend circuit is open while probing the backend".to_string(),
+                ));
+            }
+        };
+        Ok(BreakerPermit {
+            breaker: self.clone(),
+            probe,
+            recorded: false,
+        })
+    }
+
+    fn record(&self, outcome: Outcome, probe: bool) {
+        if !self.is_enabled() {
+            return;
+        }
+        let mut state = self.state();
+        match (state.circuit, outcome) {
+            (Circuit::Open { .. }, _) => {}
+            (Circuit::HalfOpen { probes }, Outcome::Ignored) if probe && probes > 0 => {
+                state.circuit = Circuit::HalfOpen { probes: probes - 1 };
+            }
+            (Circuit::HalfOpen { .. }, Outcome::Success) if probe => {
+                info!("Backend circuit closed, the backend answered a probe");
+                state.circuit = Circuit::Closed { failures: 0 };
+            }
+            (Circuit::HalfOpen { .. }, Outcome::Overloaded) if probe => {
+                warn!(
+                    "Backend probe failed, circuit open for another {}s",
+                    self.config.cooldown.as_secs()
+                );
+                self.open(&mut state);
+            }
+            (Circuit::HalfOpen { .. }, _) => {}
+            (Circuit::Closed { .. }, Outcome::Success) => {
+                state.circuit = Circuit::Closed { failures: 0 };
+            }
+            (Circuit::Closed { failures }, Outcome::Overloaded) => {
+                let failures = failures + 1;
+                if failures >= self.config.failure_threshold {
+                    warn!(
+                        "Backend failed {} times in a row, circuit open for {}s",
+                        failures,
+                        self.config.cooldown.as_secs()
+                    );
+                    self.open(&mut state);
+                } else {
+                    state.circuit = Circuit::Closed { failures };
+                }
+            }
+            (Circuit::Closed { .. }, Outcome::Ignored) => {}
+        }
+    }
+
+    fn open(&self, state: &mut BreakerState) {
+        state.circuit = Circuit::Open {
+            until: Instant::now() + self.config.cooldown,
+        };
+        state.opened += 1;
+    }
+}
+
+/// Admission of one backend request through the breaker
+///
+/// Call [`BreakerPermit::record`] once the backend answers; a permit
+/// dropped without a result counts for nothing, and frees its probe slot.
+#[derive(Debug)]
+pub struct BreakerPermit {
+    breaker: Arc<CircuitBreaker>,
+    probe: bool,
+    recorded: bool,
+}
+
+impl BreakerPermit {
+    /// Record the backend's answer
+    pub fn record(mut self, outcome: Outcome) {
+        self.recorded = true;
+        self.breaker.record(outcome, self.probe);
+    }
+}
+
+impl Drop for BreakerPermit {
+    fn drop(&mut self) {
+        if !self.recorded {
+            self.breaker.record(Outcome::Ignored, self.probe);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn breaker(failure_threshold: u32, cooldown: Duration) -> Arc<CircuitBreaker> {
+        Arc::new(CircuitBreaker::new(CircuitBreakerConfig {
+            failure_threshold,
+            cooldown,
+            probes: 1,
+        }))
+    }
+
+    #[test]
+    fn test_opens_after_consecutive_failures() {
+        let breaker = breaker(3, Duration::from_secs(60));
+        for outcome in [Outcome::Overloaded, Outcome::Overloaded, Outcome::Success] {
+            breaker.admit().unwrap().record(outcome);
+        }
+        assert!(breaker.is_closed());
+        assert_eq!(breaker.stats().consecutive_failures, 0);
+
+        for _ in 0..3 {
+            breaker.admit().unwrap().record(Outcome::Overloaded);
+        }
+        assert_eq!(breaker.stats().state, "open");
+        assert!(matches!(breaker.admit(), Err(AppError::Overloaded(_))));
+        assert_eq!(breaker.stats().rejected, 1);
+        assert_eq!(breaker.stats().opened, 1);
+    }
+
+    #[test]
+    fn test_half_open_probes() {
+        let breaker = breaker(1, Duration::ZERO);
+        breaker.admit().unwrap().record(Outcome::Overloaded);
+
+        // One probe at a time, and a failed one opens the circuit again
+        let probe = breaker.admit().unwrap();
+        assert_eq!(breaker.stats().state, "half-open");
+        assert!(breaker.admit().is_err());
+        probe.record(Outcome::Overloaded);
+        assert_eq!(breaker.stats().opened, 2);
+
+        // An abandoned probe frees its slot
+        drop(breaker.admit().unwrap());
+        breaker.admit().unwrap().record(Outcome::Success);
+        assert!(breaker.is_closed());
+    }
+
+    #[test]
+    fn test_disabled_breaker_never_opens() {
+        let breaker = breaker(0, Duration::from_secs(60));
+        for _ in 0..10 {
+            breaker.admit().unwrap().record(Outcome::Overloaded);
+        }
+        assert!(breaker.is_closed());
+    }
+}
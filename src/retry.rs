@@ -0,0 +1,118 @@
+//! Retries of failed backend requests
+//!
+//! A backend restarting or a load balancer dropping a connection used to
+//! surface as an immediate `502 Bad Gateway`. With `--backend-retries N`,
+//! GET and HEAD requests to the backend are tried up to N more times when
+//! they fail to connect, time out or get a `502`, `503` or `504`. Attempt
+//! `n` waits `--backend-retry-delay-ms × 2^(n-1)`, at most
+//! [`MAX_RETRY_DELAY`], shortened by a random fraction of up to
+//! `--backend-retry-jitter` so clients failing together do not retry
+//! together. No retry is started that would outlast the request deadline.
+//!
+//! Every backend request runs in a `backend_request` span whose `retries`
+//! field records how many retries it took.
+
+use rand::Rng;
+use reqwest::{Method, StatusCode};
+use std::time::Duration;
+
+use crate::deadline::Deadline;
+
+/// Delay before the first retry by default
+pub const DEFAULT_RETRY_DELAY: Duration = Duration::from_millis(100);
+/// Longest delay between two attempts
+pub const MAX_RETRY_DELAY: Duration = Duration::from_secs(5);
+/// Fraction of each delay left to chance by default
+pub const DEFAULT_RETRY_JITTER: f64 = 0.5;
+
+/// When and how often failed backend requests are tried again
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct RetryPolicy {
+    /// Attempts after the first, 0 disables retries
+    pub retries: u32,
+    /// Delay before the first retry, doubled for each further one
+    pub base_delay: Duration,
+    /// Fraction of each delay, between 0 and 1, randomly taken off
+    pub jitter: f64,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        Self {
+            retries: 0,
+            base_delay: DEFAULT_RETRY_DELAY,
+            jitter: DEFAULT_RETRY_JITTER,
+        }
+    }
+}
+
+impl RetryPolicy {
+    /// Whether a request may be sent again without side effects
+    pub fn is_idempotent(request: &reqwest::RequestBuilder) -> bool {
+        request
+            .try_clone()
+            .and_then(|request| request.build().ok())
+            .is_some_and(|request| matches!(*request.method(), Method::GET | Method::HEAD))
+    }
+
+    /// Whether the outcome of an attempt is worth another one
+    pub fn is_transient(result: &reqwest::Result<reqwest::Response>) -> bool {
+        match result {
+            Ok(response) => matches!(
+                response.status(),
+                StatusCode::BAD_GATEWAY
+                    | StatusCode::SERVICE_UNAVAILABLE
+                    | StatusCode::GATEWAY_TIMEOUT
+            ),
+            // Timeouts at a request's deadline end the request instead
+            Err(e) if e.is_timeout() => Deadline::current().is_none_or(|d| !d.is_expired()),
+            Err(e) => e.is_connect(),
+        }
+    }
+
+    /// Delay before retry `retry`, counted from 1
+    pub fn delay(&self, retry: u32) -> Duration {
+        let exponential = self
+            .base_delay
+            .saturating_mul(2u32.saturating_pow(retry.saturating_sub(1)))
+            .min(MAX_RETRY_DELAY);
+        let jitter = self.jitter.clamp(0.0, 1.0);
+        if jitter == 0.0 {
+            return exponential;
+        }
+        exponential.mul_f64(1.0 - rand::thread_rng().gen_range(0.0..=jitter))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_exponential_delays() {
+        let policy = RetryPolicy {
+            retries: 10,
+            base_delay: Duration::from_millis(100),
+            jitter: 0.0,
+        };
+        assert_eq!(policy.delay(1), Duration::from_millis(100));
+        assert_eq!(policy.delay(3), Duration::from_millis(400));
+        assert_eq!(policy.delay(10), MAX_RETRY_DELAY);
+
+        let jittered = RetryPolicy {
+            jitter: 0.5,
+            ..policy
+        };
+        for _ in 0..100 {
+            let delay = jittered.delay(2);
+            assert!(delay >= Duration::from_millis(100) && delay <= Duration::from_millis(200));
+        }
+    }
+
+    #[test]
+    fn test_idempotent_methods() {
+        let client = reqwest::Client::new();
+        assert!(RetryPolicy::is_idempotent(&client.get("http://b/data")));
+        assert!(!RetryPolicy::is_idempotent(&client.post("http://b/data")));
+    }
+}
@@ -0,0 +1,671 @@
+//! Scheduled background tasks
+//!
+//! New model cycles arrive at predictable times, and the first visitors
+//! after an update should not pay for a cold backend. Operators can describe
+//! tasks run by the server itself in a schedule file passed with
+//! `--schedule`:
+//!
+//! ```json
+//! [
+//!     {"task": "refresh-metadata", "every": "10m"},
+//!     {"task": "prefetch", "vars": ["t2m", "u10", "v10"], "every": "6h", "offset": "3h30m"},
+//!     {
+//!         "name": "earth-wind",
+//!         "task": "warm",
+//!         "paths": ["/data/weather/current/current-wind-surface-level-gfs-1.0.json"],
+//!         "at": ["03:45", "09:45", "15:45", "21:45"]
+//!     }
+//! ]
+//! ```
+//!
+//! `refresh-metadata` fetches the catalog and logs when the dataset changes,
+//! `prefetch` fetches the latest timestep of some variables, and `warm`
+//! requests paths of this server, such as Earth overlays, as a client would.
+//! Tasks run `every` interval, aligned to midnight UTC and shifted by an
+//! optional `offset`, or daily `at` the given UTC times. Their last outcome
+//! is listed by `GET /admin/schedule`.
+
+use axum::{
+    body::{Body, HttpBody},
+    extract::State,
+    http::Request,
+    response::Json,
+    Router,
+};
+use chrono::{DateTime, Duration as ChronoDuration, NaiveTime, Utc};
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use std::{
+    fmt,
+    path::Path,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+use tower::ServiceExt;
+use tracing::{info, warn};
+
+use crate::{
+    derived::{fetch_with_derived, is_derived},
+    error::{AppError, ErrorBody},
+    freshness::data_version,
+    grid::coordinate_values,
+    handlers::fetch_metadata,
+    server::AppState,
+    timesteps::to_iso,
+    webhooks::NewData,
+};
+
+/// When a task runs
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Schedule {
+    /// At multiples of `interval` since midnight UTC, shifted by `offset`
+    Every {
+        interval: Duration,
+        offset: Duration,
+    },
+    /// Daily at these UTC times, in ascending order
+    Daily(Vec<NaiveTime>),
+}
+
+impl Schedule {
+    /// The first run strictly after `now`
+    pub fn next_after(&self, now: DateTime<Utc>) -> DateTime<Utc> {
+        match self {
+            Schedule::Every { interval, offset } => {
+                let interval = interval.as_secs() as i64;
+                let offset = offset.as_secs() as i64;
+                let slot = (now.timestamp() - offset).div_euclid(interval) + 1;
+                DateTime::from_timestamp(slot * interval + offset, 0).unwrap_or(now)
+            }
+            Schedule::Daily(times) => {
+                let today = now.date_naive();
+                times
+                    .iter()
+                    .map(|time| today.and_time(*time).and_utc())
+                    .find(|run| *run > now)
+                    .unwrap_or_else(|| {
+                        (today + ChronoDuration::days(1))
+                            .and_time(times[0])
+                            .and_utc()
+                    })
+            }
+        }
+    }
+}
+
+impl fmt::Display for Schedule {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Schedule::Every { interval, offset } if offset.is_zero() => {
+                write!(f, "every {}s", interval.as_secs())
+            }
+            Schedule::Every { interval, offset } => {
+                write!(
+                    f,
+                    "every {}s from {}s",
+                    interval.as_secs(),
+                    offset.as_secs()
+                )
+            }
+            Schedule::Daily(times) => {
+                let times: Vec<String> = times
+                    .iter()
+                    .map(|t| t.format("%H:%M").to_string())
+                    .collect();
+                write!(f, "daily at {} UTC", times.join(", "))
+            }
+        }
+    }
+}
+
+/// What a task does
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+#[serde(tag = "task", rename_all = "kebab-case")]
+pub enum TaskAction {
+    /// Fetch the metadata catalog, logging changes of the dataset
+    RefreshMetadata,
+    /// Fetch the latest timestep of these variables
+    Prefetch { vars: Vec<String> },
+    /// Request these paths of this server
+    Warm { paths: Vec<String> },
+}
+
+impl TaskAction {
+    fn kind(&self) -> &'static str {
+        match self {
+            TaskAction::RefreshMetadata => "refresh-metadata",
+            TaskAction::Prefetch { .. } => "prefetch",
+            TaskAction::Warm { .. } => "warm",
+        }
+    }
+}
+
+/// A task as written in the schedule file
+#[derive(Debug, Deserialize)]
+struct TaskDefinition {
+    name: Option<String>,
+    #[serde(flatten)]
+    action: TaskAction,
+    every: Option<String>,
+    offset: Option<String>,
+    #[serde(default)]
+    at: Vec<String>,
+    #[serde(default)]
+    on_start: bool,
+}
+
+/// A configured task
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ScheduledTask {
+    /// Name shown in logs and reports, by default the task kind
+    pub name: String,
+    pub action: TaskAction,
+    pub schedule: Schedule,
+    /// Also run the task when the server starts
+    pub on_start: bool,
+}
+
+/// The scheduled tasks of a deployment
+#[derive(Debug, Clone, Default)]
+pub struct TaskSchedule {
+    tasks: Vec<ScheduledTask>,
+}
+
+impl TaskSchedule {
+    /// Load the tasks of a JSON file, returning the number loaded
+    pub fn load(&mut self, path: &Path) -> Result<usize, AppError> {
+        let error = |message: String| {
+            AppError::ConfigError(format!(
+                "Cannot load schedule from {}: {}",
+                path.display(),
+                message
+            ))
+        };
+        let contents = std::fs::read_to_string(path).map_err(|e| error(e.to_string()))?;
+        self.add_json(&contents).map_err(|e| error(e.to_string()))
+    }
+
+    /// Add the tasks of a JSON document, returning the number added
+    pub fn add_json(&mut self, json: &str) -> Result<usize, AppError> {
+        let definitions: Vec<TaskDefinition> = serde_json::from_str(json)
+            .map_err(|e| AppError::ConfigError(format!("Invalid schedule: {}", e)))?;
+
+        let count = definitions.len();
+        for definition in definitions {
+            let task = parse_task(definition)?;
+            if self.tasks.iter().any(|other| other.name == task.name) {
+                return Err(AppError::ConfigError(format!(
+                    "Scheduled task '{}' is defined twice; give the tasks distinct names",
+                    task.name
+                )));
+            }
+            self.tasks.push(task);
+        }
+        Ok(count)
+    }
+
+    /// Configured tasks in definition order
+    pub fn tasks(&self) -> &[ScheduledTask] {
+        &self.tasks
+    }
+
+    /// Whether no tasks are configured
+    pub fn is_empty(&self) -> bool {
+        self.tasks.is_empty()
+    }
+
+    /// Add a `refresh-metadata` task running `every` interval unless one is
+    /// configured, returning whether it was added
+    pub fn ensure_metadata_refresh(&mut self, every: Duration) -> bool {
+        if self
+            .tasks
+            .iter()
+            .any(|task| task.action == TaskAction::RefreshMetadata)
+        {
+            return false;
+        }
+        self.tasks.push(ScheduledTask {
+            name: TaskAction::RefreshMetadata.kind().to_string(),
+            action: TaskAction::RefreshMetadata,
+            schedule: Schedule::Every {
+                interval: every,
+                offset: Duration::ZERO,
+            },
+            on_start: true,
+        });
+        true
+    }
+}
+
+fn parse_task(definition: TaskDefinition) -> Result<ScheduledTask, AppError> {
+    let name = definition
+        .name
+        .unwrap_or_else(|| definition.action.kind().to_string());
+    let error = |message: &str| AppError::ConfigError(format!("Task '{}' {}", name, message));
+
+    match &definition.action {
+        TaskAction::Prefetch { vars } if vars.is_empty() => {
+            return Err(error("has no variables to prefetch"))
+        }
+        TaskAction::Warm { paths } if paths.is_empty() => {
+            return Err(error("has no paths to warm"))
+        }
+        TaskAction::Warm { paths } if paths.iter().any(|path| !path.starts_with('/')) => {
+            return Err(error("paths must start with '/'"))
+        }
+        _ => {}
+    }
+
+    let schedule = match (definition.every, definition.at.is_empty()) {
+        (Some(every), true) => {
+            let interval = parse_interval(&every)?;
+            let offset = definition
+                .offset
+                .as_deref()
+                .map(parse_interval)
+                .transpose()?
+                .unwrap_or_default();
+            if offset >= interval {
+                return Err(error("has an offset longer than its interval"));
+            }
+            Schedule::Every { interval, offset }
+        }
+        (None, false) if definition.offset.is_none() => {
+            let mut times = definition
+                .at
+                .iter()
+                .map(|time| {
+                    NaiveTime::parse_from_str(time, "%H:%M").map_err(|_| {
+                        AppError::ConfigError(format!("Invalid time '{}', expected HH:MM", time))
+                    })
+                })
+                .collect::<Result<Vec<_>, _>>()?;
+            times.sort();
+            times.dedup();
+            Schedule::Daily(times)
+        }
+        _ => {
+            return Err(error(
+                "needs either 'every' (with an optional 'offset') or 'at'",
+            ))
+        }
+    };
+
+    Ok(ScheduledTask {
+        name,
+        action: definition.action,
+        schedule,
+        on_start: definition.on_start,
+    })
+}
+
+/// Parse a duration such as `90s`, `15m`, `6h`, `1d` or `3h30m`
+pub fn parse_interval(s: &str) -> Result<Duration, AppError> {
+    let invalid = || {
+        AppError::ConfigError(format!(
+            "Invalid interval '{}', expected e.g. 30s, 15m, 6h or 3h30m",
+            s
+        ))
+    };
+
+    let mut total = 0u64;
+    let mut digits = String::new();
+    for c in s.trim().chars() {
+        if c.is_ascii_digit() {
+            digits.push(c);
+            continue;
+        }
+        let unit = match c {
+            's' => 1,
+            'm' => 60,
+            'h' => 3600,
+            'd' => 86400,
+            _ => return Err(invalid()),
+        };
+        let amount: u64 = digits.parse().map_err(|_| invalid())?;
+        total = amount
+            .checked_mul(unit)
+            .and_then(|secs| total.checked_add(secs))
+            .ok_or_else(invalid)?;
+        digits.clear();
+    }
+    if !digits.is_empty() || total == 0 {
+        return Err(invalid());
+    }
+    Ok(Duration::from_secs(total))
+}
+
+/// Last outcome of a task, returned by `GET /admin/schedule`
+#[derive(Debug, Clone, Serialize)]
+pub struct TaskReport {
+    pub name: String,
+    pub task: &'static str,
+    pub schedule: String,
+    pub next_run: Option<DateTime<Utc>>,
+    pub last_run: Option<DateTime<Utc>>,
+    pub last_duration_ms: Option<u64>,
+    /// What the last successful run found
+    pub last_result: Option<String>,
+    /// Error of the last run, if it failed
+    pub last_error: Option<String>,
+    pub runs: u64,
+    pub failures: u64,
+}
+
+/// Runs the scheduled tasks and keeps their reports
+#[derive(Debug, Default)]
+pub struct Scheduler {
+    tasks: Vec<ScheduledTask>,
+    reports: Mutex<Vec<TaskReport>>,
+    /// The dataset as seen by the last metadata refresh
+    dataset: Mutex<Option<DatasetSnapshot>>,
+}
+
+/// Version and time axis of the dataset at one metadata refresh
+#[derive(Debug)]
+struct DatasetSnapshot {
+    version: String,
+    times: Vec<f64>,
+}
+
+impl Scheduler {
+    pub fn new(schedule: TaskSchedule) -> Self {
+        let reports = schedule
+            .tasks
+            .iter()
+            .map(|task| TaskReport {
+                name: task.name.clone(),
+                task: task.action.kind(),
+                schedule: task.schedule.to_string(),
+                next_run: None,
+                last_run: None,
+                last_duration_ms: None,
+                last_result: None,
+                last_error: None,
+                runs: 0,
+                failures: 0,
+            })
+            .collect();
+        Self {
+            tasks: schedule.tasks,
+            reports: Mutex::new(reports),
+            dataset: Mutex::new(None),
+        }
+    }
+
+    /// Reports of all tasks in definition order
+    pub fn reports(&self) -> Vec<TaskReport> {
+        self.reports.lock().unwrap().clone()
+    }
+
+    fn update(&self, index: usize, update: impl FnOnce(&mut TaskReport)) {
+        if let Some(report) = self.reports.lock().unwrap().get_mut(index) {
+            update(report);
+        }
+    }
+
+    /// Run one task now, recording its outcome
+    pub async fn run_task(&self, state: &AppState, app: Router, index: usize) {
+        let Some(task) = self.tasks.get(index) else {
+            return;
+        };
+        let started_at = Utc::now();
+        let start = Instant::now();
+        let outcome = self.execute(state, app, &task.action).await;
+        let duration_ms = start.elapsed().as_millis() as u64;
+
+        match &outcome {
+            Ok(result) => info!(task = %task.name, duration_ms, "Scheduled task done: {}", result),
+            Err(e) => warn!(task = %task.name, duration_ms, "Scheduled task failed: {}", e),
+        }
+        self.update(index, |report| {
+            report.last_run = Some(started_at);
+            report.last_duration_ms = Some(duration_ms);
+            report.runs += 1;
+            match outcome {
+                Ok(result) => {
+                    report.last_result = Some(result);
+                    report.last_error = None;
+                }
+                Err(e) => {
+                    report.last_error = Some(e.to_string());
+                    report.failures += 1;
+                }
+            }
+        });
+    }
+
+    async fn execute(
+        &self,
+        state: &AppState,
+        app: Router,
+        action: &TaskAction,
+    ) -> Result<String, AppError> {
+        match action {
+            TaskAction::RefreshMetadata => {
+                let metadata = fetch_metadata(state).await?;
+                let version = data_version(&metadata);
+                let latest = latest_time(&metadata)?;
+                let snapshot = DatasetSnapshot {
+                    version: version.clone(),
+                    times: coordinate_values(&metadata, "time").unwrap_or_default(),
+                };
+                let previous = self.dataset.lock().unwrap().replace(snapshot);
+                let mut result = format!(
+                    "dataset version {}, latest time {}",
+                    version,
+                    to_iso(latest)
+                );
+                // The first refresh only records what is there
+                let Some(previous) = previous.filter(|previous| previous.version != version) else {
+                    return Ok(result);
+                };
+                info!(version = %version, latest = %to_iso(latest), "Dataset changed");
+                if !state.webhooks.is_empty() {
+                    if let Some(event) =
+                        NewData::detect(&previous.times, &metadata, &version, &state.site.title)
+                    {
+                        let delivered = state.webhooks.notify(&event).await;
+                        result.push_str(&format!(
+                            ", {} new timesteps, notified {} of {} webhooks",
+                            event.new_times.len(),
+                            delivered,
+                            state.webhooks.len()
+                        ));
+                    }
+                }
+                Ok(result)
+            }
+            TaskAction::Prefetch { vars } => {
+                let metadata = fetch_metadata(state).await?;
+                let latest = latest_time(&metadata)?;
+                let vars: Vec<&str> = vars.iter().map(String::as_str).collect();
+                if let Some(unknown) = vars.iter().find(|var| {
+                    metadata["variables"].get(**var).is_none()
+                        && !is_derived(&state.derived_variables, &metadata, var)
+                }) {
+                    return Err(AppError::RequestError(format!(
+                        "Variable '{}' not found in metadata",
+                        unknown
+                    )));
+                }
+                fetch_with_derived(state, &metadata, &vars, Some(latest)).await?;
+                Ok(format!("fetched {} at {}", vars.join(","), to_iso(latest)))
+            }
+            TaskAction::Warm { paths } => {
+                let mut failed = Vec::new();
+                let mut bytes = 0;
+                for path in paths {
+                    let request = Request::get(path.as_str())
+                        .body(Body::empty())
+                        .map_err(|e| {
+                            AppError::ConfigError(format!("Invalid path {}: {}", path, e))
+                        })?;
+                    let response = app
+                        .clone()
+                        .oneshot(request)
+                        .await
+                        .unwrap_or_else(|e| match e {});
+                    let status = response.status();
+                    let mut body = response.into_body();
+                    while let Some(chunk) = body.data().await {
+                        bytes += chunk.map(|chunk| chunk.len()).unwrap_or_default();
+                    }
+                    if !status.is_success() {
+                        failed.push(format!("{} ({})", path, status));
+                    }
+                }
+                if !failed.is_empty() {
+                    return Err(AppError::ProxyError(format!(
+                        "Warming failed for {}",
+                        failed.join(", ")
+                    )));
+                }
+                Ok(format!("warmed {} paths, {} bytes", paths.len(), bytes))
+            }
+        }
+    }
+}
+
+/// The last timestep of the dataset
+fn latest_time(metadata: &Value) -> Result<f64, AppError> {
+    coordinate_values(metadata, "time")
+        .and_then(|times| times.into_iter().reduce(f64::max))
+        .ok_or_else(|| AppError::ProxyError("Metadata has no time coordinate".to_string()))
+}
+
+/// Start running the scheduled tasks of `state` in the background
+///
+/// `app` is the full router of the server, through which `warm` tasks send
+/// their requests.
+pub fn start(state: Arc<AppState>, app: Router) {
+    let scheduler = state.scheduler.clone();
+    for (index, task) in scheduler.tasks.iter().enumerate() {
+        info!(task = %task.name, "Scheduling {} {}", task.action.kind(), task.schedule);
+        let state = state.clone();
+        let app = app.clone();
+        let scheduler = scheduler.clone();
+        tokio::spawn(async move {
+            let task = &scheduler.tasks[index];
+            if task.on_start {
+                scheduler.run_task(&state, app.clone(), index).await;
+            }
+            loop {
+                let now = Utc::now();
+                let next = task.schedule.next_after(now);
+                scheduler.update(index, |report| report.next_run = Some(next));
+                tokio::time::sleep((next - now).to_std().unwrap_or_default()).await;
+                scheduler.run_task(&state, app.clone(), index).await;
+            }
+        });
+    }
+}
+
+/// List the scheduled tasks and their last outcome
+#[utoipa::path(
+    get,
+    path = "/admin/schedule",
+    tag = "admin",
+    summary = "Scheduled tasks and their last outcome",
+    responses(
+        (status = 200, body = serde_json::Value),
+        (status = 401, description = "Missing or invalid admin token", body = ErrorBody),
+    ),
+    security(("admin_token" = []))
+)]
+pub async fn scheduled_tasks(State(state): State<Arc<AppState>>) -> Json<Value> {
+    Json(json!({ "tasks": state.scheduler.reports() }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::TimeZone;
+
+    fn at(hour: u32, minute: u32, second: u32) -> DateTime<Utc> {
+        Utc.with_ymd_and_hms(2024, 3, 1, hour, minute, second)
+            .unwrap()
+    }
+
+    #[test]
+    fn test_parse_interval() {
+        assert_eq!(parse_interval("90s").unwrap(), Duration::from_secs(90));
+        assert_eq!(parse_interval("15m").unwrap(), Duration::from_secs(900));
+        assert_eq!(parse_interval("3h30m").unwrap(), Duration::from_secs(12600));
+        assert_eq!(parse_interval("1d").unwrap(), Duration::from_secs(86400));
+        for invalid in ["", "0s", "15", "m", "5 minutes", "1w"] {
+            assert!(parse_interval(invalid).is_err(), "{}", invalid);
+        }
+    }
+
+    #[test]
+    fn test_next_run() {
+        let every = Schedule::Every {
+            interval: Duration::from_secs(6 * 3600),
+            offset: Duration::from_secs(3 * 3600 + 1800),
+        };
+        assert_eq!(every.next_after(at(0, 0, 0)), at(3, 30, 0));
+        assert_eq!(every.next_after(at(3, 30, 0)), at(9, 30, 0));
+        assert_eq!(
+            every.next_after(at(22, 0, 0)),
+            at(0, 0, 0) + ChronoDuration::hours(27) + ChronoDuration::minutes(30)
+        );
+
+        let daily = Schedule::Daily(vec![
+            NaiveTime::from_hms_opt(3, 45, 0).unwrap(),
+            NaiveTime::from_hms_opt(15, 45, 0).unwrap(),
+        ]);
+        assert_eq!(daily.next_after(at(3, 44, 59)), at(3, 45, 0));
+        assert_eq!(daily.next_after(at(3, 45, 0)), at(15, 45, 0));
+        assert_eq!(
+            daily.next_after(at(16, 0, 0)),
+            at(3, 45, 0) + ChronoDuration::days(1)
+        );
+        assert_eq!(daily.to_string(), "daily at 03:45, 15:45 UTC");
+    }
+
+    #[test]
+    fn test_schedule_file() {
+        let mut schedule = TaskSchedule::default();
+        let count = schedule
+            .add_json(
+                r#"[
+                    {"task": "refresh-metadata", "every": "10m", "on_start": true},
+                    {"task": "prefetch", "vars": ["t2m"], "at": ["15:45", "03:45"]},
+                    {"name": "wind", "task": "warm", "paths": ["/data/products.json"], "every": "1h"}
+                ]"#,
+            )
+            .unwrap();
+        assert_eq!(count, 3);
+        let tasks = schedule.tasks();
+        assert_eq!(tasks[0].name, "refresh-metadata");
+        assert!(tasks[0].on_start);
+        assert_eq!(tasks[1].schedule.to_string(), "daily at 03:45, 15:45 UTC");
+        assert_eq!(
+            tasks[2].action,
+            TaskAction::Warm {
+                paths: vec!["/data/products.json".to_string()]
+            }
+        );
+
+        for invalid in [
+            r#"[{"task": "refresh-metadata"}]"#,
+            r#"[{"task": "refresh-metadata", "every": "1h", "at": ["03:00"]}]"#,
+            r#"[{"task": "refresh-metadata", "every": "1h", "offset": "2h"}]"#,
+            r#"[{"task": "prefetch", "vars": [], "every": "1h"}]"#,
+            r#"[{"task": "warm", "paths": ["data"], "every": "1h"}]"#,
+            r#"[{"task": "reboot", "every": "1h"}]"#,
+            r#"[{"task": "prefetch", "vars": ["t2m"], "at": ["25:00"]}]"#,
+        ] {
+            assert!(
+                TaskSchedule::default().add_json(invalid).is_err(),
+                "{}",
+                invalid
+            );
+        }
+        // Tasks need distinct names
+        assert!(schedule
+            .add_json(r#"[{"task": "refresh-metadata", "every": "1h"}]"#)
+            .is_err());
+    }
+}
@@ -0,0 +1,234 @@
+//! Rhai scripts rewriting the catalog and data
+//!
+//! Some fixes are beyond the declarative hooks of [`crate::transforms`]:
+//! combining variables, masking one with another, or computing attributes.
+//! Built with the `scripting` feature, `--transforms` also takes
+//! [Rhai](https://rhai.rs) scripts (`.rhai` files) defining any of these
+//! functions:
+//!
+//! ```text
+//! // Backend variables a served variable is made from; none hides it
+//! fn sources(name) {
+//!     switch name {
+//!         "wind_speed" => ["u10", "v10"],
+//!         "lsm_raw" => [],
+//!         _ => [name],
+//!     }
+//! }
+//!
+//! // The metadata document, in the legacy schema
+//! fn metadata(doc) {
+//!     let speed = doc.variables.u10;
+//!     speed.attributes.long_name = "10 metre wind speed";
+//!     doc.variables.wind_speed = speed;
+//!     doc.variables.remove("lsm_raw");
+//!     doc
+//! }
+//!
+//! // A /data response, its values by variable in `data`, missing as ()
+//! fn data(response) {
+//!     if "u10" in response.data {
+//!         let v = response.data.v10;
+//!         response.data.wind_speed = response.data.u10.map(|u, i|
+//!             if u == () || v[i] == () { () } else { sqrt(u * u + v[i] * v[i]) });
+//!     }
+//!     response
+//! }
+//! ```
+//!
+//! Scripts run after the hooks, in the order they were given. `print` and
+//! `debug` write to the server log.
+
+use rhai::{
+    serde::{from_dynamic, to_dynamic},
+    CallFnOptions, Dynamic, Engine, Scope, AST,
+};
+use serde_json::Value;
+use std::fmt;
+use tracing::{debug, info};
+
+use crate::error::AppError;
+
+/// A compiled transform script
+pub struct TransformScript {
+    engine: Engine,
+    ast: AST,
+}
+
+impl fmt::Debug for TransformScript {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let functions: Vec<&str> = self.ast.iter_functions().map(|f| f.name).collect();
+        f.debug_struct("TransformScript")
+            .field("functions", &functions)
+            .finish()
+    }
+}
+
+impl TransformScript {
+    /// Compile the Rhai script `source`
+    pub fn compile(source: &str) -> Result<Self, AppError> {
+        let mut engine = Engine::new();
+        // The same limits as release builds of Rhai, which halves them in
+        // debug builds
+        engine.set_max_expr_depths(64, 32);
+        engine.on_print(|text| info!(target: "transforms", "{}", text));
+        engine.on_debug(|text, _, _| debug!(target: "transforms", "{}", text));
+        let ast = engine
+            .compile(source)
+            .map_err(|e| AppError::ConfigError(format!("Invalid script: {}", e)))?;
+        Ok(Self { engine, ast })
+    }
+
+    fn defines(&self, function: &str) -> bool {
+        self.ast
+            .iter_functions()
+            .any(|f| f.name == function && f.params.len() == 1)
+    }
+
+    /// Call `function` of the script with `argument`
+    fn call(&self, function: &str, argument: Dynamic) -> Result<Dynamic, AppError> {
+        // Top-level statements ran once, when the script was loaded
+        let options = CallFnOptions::new().eval_ast(false);
+        self.engine
+            .call_fn_with_options(options, &mut Scope::new(), &self.ast, function, (argument,))
+            .map_err(|e| AppError::ConfigError(format!("Transform script {}(): {}", function, e)))
+    }
+
+    /// Call `function` with a JSON document, replacing it with the one
+    /// returned; `false` when the script does not define `function`
+    fn rewrite(&self, function: &str, document: &mut Value) -> Result<bool, AppError> {
+        if !self.defines(function) {
+            return Ok(false);
+        }
+        let invalid = |e: Box<rhai::EvalAltResult>| {
+            AppError::ConfigError(format!("Transform script {}(): {}", function, e))
+        };
+        let result = self.call(function, to_dynamic(&*document).map_err(invalid)?)?;
+        if !result.is_map() {
+            return Err(AppError::ConfigError(format!(
+                "Transform script {}() must return a map, not {}",
+                function,
+                result.type_name()
+            )));
+        }
+        *document = from_dynamic(&result).map_err(invalid)?;
+        Ok(true)
+    }
+
+    /// The variables requested from the previous stage to serve `variable`
+    pub fn sources(&self, variable: &str) -> Result<Vec<String>, AppError> {
+        if !self.defines("sources") {
+            return Ok(vec![variable.to_string()]);
+        }
+        let sources = self.call("sources", variable.into())?;
+        sources
+            .into_array()
+            .ok()
+            .and_then(|names| {
+                names
+                    .into_iter()
+                    .map(|name| name.into_string().ok())
+                    .collect::<Option<Vec<_>>>()
+            })
+            .ok_or_else(|| {
+                AppError::ConfigError(format!(
+                    "Transform script sources('{}') must return an array of names",
+                    variable
+                ))
+            })
+    }
+
+    /// Rewrite a metadata document, `false` when the script leaves it alone
+    pub fn apply_metadata(&self, metadata: &mut Value) -> Result<bool, AppError> {
+        self.rewrite("metadata", metadata)
+    }
+
+    /// Rewrite a `/data` response
+    pub fn apply_data(&self, response: &mut Value) -> Result<(), AppError> {
+        self.rewrite("data", response).map(|_| ())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    const SCRIPT: &str = r#"
+        fn sources(name) {
+            switch name {
+                "wind_speed" => ["u10", "v10"],
+                "lsm_raw" => [],
+                _ => [name],
+            }
+        }
+
+        fn metadata(doc) {
+            doc.variables.t2m = doc.variables.remove("VAR_2T");
+            doc.variables.remove("lsm_raw");
+            doc
+        }
+
+        fn data(response) {
+            let data = response.data;
+            if "tp" in data {
+                data.tp = data.tp.map(|x| if x == () { () } else { x * 1000.0 });
+            }
+            if "u10" in data && "v10" in data {
+                let v = data.v10;
+                data.wind_speed = data.u10.map(|u, i|
+                    if u == () || v[i] == () { () } else { sqrt(u * u + v[i] * v[i]) });
+            }
+            response.data = data;
+            response
+        }
+    "#;
+
+    #[test]
+    fn test_sources() {
+        let script = TransformScript::compile(SCRIPT).unwrap();
+        assert_eq!(script.sources("wind_speed").unwrap(), ["u10", "v10"]);
+        assert!(script.sources("lsm_raw").unwrap().is_empty());
+        assert_eq!(script.sources("sp").unwrap(), ["sp"]);
+
+        let identity = TransformScript::compile("fn data(response) { response }").unwrap();
+        assert_eq!(identity.sources("sp").unwrap(), ["sp"]);
+    }
+
+    #[test]
+    fn test_rewrite_documents() {
+        let script = TransformScript::compile(SCRIPT).unwrap();
+
+        let mut metadata =
+            json!({"variables": {"VAR_2T": {"attributes": {"units": "K"}}, "lsm_raw": {}}});
+        assert!(script.apply_metadata(&mut metadata).unwrap());
+        assert_eq!(
+            metadata,
+            json!({"variables": {"t2m": {"attributes": {"units": "K"}}}})
+        );
+
+        let mut response =
+            json!({"data": {"u10": [3.0, 1, null], "v10": [4.0, 0.0, 1.0], "tp": [0.002, null]}});
+        script.apply_data(&mut response).unwrap();
+        assert_eq!(response["data"]["wind_speed"], json!([5.0, 1.0, null]));
+        assert_eq!(response["data"]["tp"], json!([2.0, null]));
+    }
+
+    #[test]
+    fn test_script_errors() {
+        assert!(TransformScript::compile("fn data(response) {").is_err());
+
+        let script = TransformScript::compile("fn metadata(doc) { doc.missing.field }").unwrap();
+        assert!(script.apply_metadata(&mut json!({})).is_err());
+        let script = TransformScript::compile("fn metadata(doc) { 1 }").unwrap();
+        assert!(script.apply_metadata(&mut json!({})).is_err());
+        let script = TransformScript::compile("fn sources(name) { name }").unwrap();
+        assert!(script.sources("t2m").is_err());
+
+        // Functions a script does not define leave documents unchanged
+        let script = TransformScript::compile("let unused = 1;").unwrap();
+        let mut metadata = json!({"variables": {}});
+        assert!(!script.apply_metadata(&mut metadata).unwrap());
+        assert_eq!(metadata, json!({"variables": {}}));
+    }
+}
@@ -1,45 +1,1400 @@
-use axum::{middleware as axum_middleware, routing::get, Router};
-use std::{net::SocketAddr, sync::Arc};
-use tower_http::trace::TraceLayer;
-use tracing::info;
+use axum::{
+    extract::State,
+    http::{Request, StatusCode, Uri},
+    middleware as axum_middleware,
+    routing::{get, post},
+    Router,
+};
+use futures::future::BoxFuture;
+use std::{
+    future::Future,
+    net::{IpAddr, Ipv4Addr, SocketAddr},
+    path::{Path, PathBuf},
+    sync::{Arc, RwLock},
+    time::Duration,
+};
+use tokio::{sync::watch, task::JoinHandle};
+use tower::Layer;
+use tracing::{info, warn, Instrument};
 
+#[cfg(feature = "acme")]
+use crate::acme::{acme_challenge, Acme};
 use crate::{
-    handlers::{
-        earth_dynamic_data, earth_temp_data, earth_wind_data, index, proxy_data, proxy_metadata,
-        static_asset,
+    admin::{
+        admin_auth_middleware, admin_page, cache_stats, get_log_level, list_assets, overview,
+        purge_cache, set_log_level, RecentErrors,
+    },
+    analysis::{cross_section, sample, trajectories},
+    auth::{AuthConfig, Authenticator},
+    backend::{BackendCompat, BackendSchema},
+    cache::{ResponseCache, ResponseCacheConfig},
+    catalog::CatalogService,
+    chaos::ChaosConfig,
+    client::{BackendClientConfig, ClientRecycler},
+    client_errors::{report_client_errors, ClientErrorLimiter},
+    concurrency::{BackendLimiter, ConcurrencyLimit, Outcome, DEFAULT_QUEUE_TIMEOUT},
+    datasets::{
+        dataset_data, dataset_metadata, earth_weather_data, list_datasets, DatasetConfig, Datasets,
     },
-    middleware::{
-        error_logging_middleware, health_check_middleware, request_tracing_middleware,
-        security_headers_middleware,
+    deadline::{self, Deadline, REQUEST_TIMEOUT_HEADER},
+    dev_assets::{dev_events, DevAssets, DEV_EVENTS_PATH},
+    endpoint::{BackendEndpoint, BasicAuth},
+    error::AppError,
+    frames::earth_frames,
+    freshness::{AssetCachePolicy, CachePolicy},
+    grid::EarthGridLimit,
+    handlers::{
+        asset_manifest, cache_manifest, earth_current_data, earth_temp_data, earth_wind_data,
+        index, proxy_data, proxy_metadata, service_worker, static_asset, status, version_info,
+        web_manifest,
     },
+    health::{BackendProbe, HealthProbeConfig},
+    hints::RenderHints,
+    jobs::{cancel_job, job_events, job_result, job_status, submit_job, JobQueue, JobsConfig},
+    limits::{request_limits_middleware, RequestLimits},
+    listen::{self, ListenAddress, DEFAULT_UNIX_SOCKET_MODE},
+    logging::{self, LogLevelHandle},
+    mask::LandSeaMaskConfig,
+    middleware::strict_query_middleware,
+    openapi::{api_docs, openapi_json},
+    oscar::{oscar_catalog, oscar_data, OceanCurrentsConfig},
+    packing::PackingConfig,
+    pipeline::{apply_pipeline, CompressionConfig, CorsConfig, Middleware, DEFAULT_PIPELINE},
+    plugins::DerivedRegistry,
+    products::products_catalog,
+    replay::Recording,
+    resilience::{CircuitBreaker, CircuitBreakerConfig},
+    retry::RetryPolicy,
+    scheduler::{self, scheduled_tasks, Scheduler, TaskSchedule},
+    shedding::{self, LoadShedder, LoadSheddingConfig, DEFAULT_SAMPLE_INTERVAL},
+    signing::{public_key, signing_middleware, ResponseSigner, SigningConfig},
+    site::{parse_base_path, SiteConfig},
+    systemd,
+    timesteps::{next_time, previous_time},
+    tls::{self, AcmeConfig, TlsCertificate, TlsConfig},
+    trace_context::TraceContext,
+    transforms::Transforms,
+    units::{preferences, UnitSystem},
+    webhooks::{WebhookConfig, Webhooks},
+};
+#[cfg(feature = "sqlite")]
+use crate::{
+    admin::{issue_api_key, list_api_keys, revoke_api_key},
+    store::Store,
 };
+#[cfg(feature = "sqlite")]
+use axum::routing::put;
+
+/// How often the metadata is checked for webhooks when no schedule does it
+const DEFAULT_METADATA_REFRESH: Duration = Duration::from_secs(600);
 
 /// Application state shared across all handlers
 #[derive(Clone)]
 pub struct AppState {
+    /// Backend base URL, without credentials or trailing slash
     pub api_url: String,
     pub http_client: reqwest::Client,
+    /// Replaces `http_client` periodically when connection recycling is enabled
+    pub client_recycler: Option<Arc<ClientRecycler>>,
+    /// Basic-auth credentials sent with every backend request
+    pub backend_credentials: Option<BasicAuth>,
+    /// The client's request timeout, kept for requests given a shorter one
+    /// by their deadline
+    pub backend_timeout: Option<Duration>,
+    /// Land-sea mask settings for Earth overlays
+    pub land_sea_mask: LandSeaMaskConfig,
+    /// Schema handling for the backend's metadata and data documents
+    pub backend: BackendCompat,
+    /// Reject requests carrying unrecognized query parameters
+    pub strict_query: bool,
+    /// Size limits of requests to the `/proxy/` routes
+    pub request_limits: RequestLimits,
+    /// Bearer token for the `/admin` endpoints; they are disabled without one
+    pub admin_token: Option<String>,
+    /// Handle for changing the log level at runtime
+    pub log_level: Option<LogLevelHandle>,
+    /// Values substituted into `index.html`
+    pub site: SiteConfig,
+    /// Path every route is mounted under, empty for the root
+    pub base_path: String,
+    /// Frontend files served from disk instead of the embedded bundle
+    pub dev_assets: Option<Arc<DevAssets>>,
+    /// Send COOP/COEP headers so the page is cross-origin isolated
+    pub cross_origin_isolation: bool,
+    /// Serve `index.mobile.html` instead of `index.html` to phones
+    pub mobile_index: bool,
+    /// Limit on the frontend error reports logged per client
+    pub client_errors: Arc<ClientErrorLimiter>,
+    /// Backend and variables of the OSCAR-style ocean currents
+    pub ocean_currents: OceanCurrentsConfig,
+    /// Size limit on the grids served as Earth JSON
+    pub earth_grid_limit: EarthGridLimit,
+    /// Custom derived variables served alongside the built-in products
+    pub derived_variables: DerivedRegistry,
+    /// Hooks rewriting backend variables
+    pub transforms: Transforms,
+    /// Rendering hints added to Earth headers, replaced when the
+    /// configuration is reloaded
+    pub render_hints: Arc<RwLock<RenderHints>>,
+    /// Preferred unit system, published at `/api/prefs`
+    pub units: UnitSystem,
+    /// Cached probe of the backend reported by `/health`
+    pub health_probe: Arc<BackendProbe>,
+    /// Recent backend responses served again to identical requests
+    pub response_cache: Arc<ResponseCache>,
+    /// Named backends served side by side
+    pub datasets: Arc<Datasets>,
+    /// Retries of failed backend requests
+    pub backend_retry: RetryPolicy,
+    /// Stops backend requests after repeated failures
+    pub circuit_breaker: Arc<CircuitBreaker>,
+    /// Background jobs submitted to `/api/jobs`
+    pub jobs: Arc<JobQueue>,
+    /// Periodic tasks such as metadata refreshes and prefetches
+    pub scheduler: Arc<Scheduler>,
+    /// Notified when the metadata refresh finds new timesteps
+    pub webhooks: Webhooks,
+    /// Server errors listed by the admin overview
+    pub recent_errors: Arc<RecentErrors>,
+    /// Sheds low-priority requests under memory or CPU pressure
+    pub load_shedder: Arc<LoadShedder>,
+    /// Caps the requests in flight to the backend
+    pub backend_limiter: Arc<BackendLimiter>,
+    /// How long browsers and CDNs may cache data responses
+    pub cache_policy: CachePolicy,
+    /// How long browsers and CDNs may cache the embedded frontend
+    pub asset_cache: AssetCachePolicy,
+    /// Analyzed variables of the current metadata, shared by the Earth
+    /// handlers
+    pub catalog: Arc<CatalogService>,
+    /// Automatic certificates, when configured
+    #[cfg(feature = "acme")]
+    pub acme: Option<Arc<Acme>>,
+    /// Packing of Earth data arrays
+    pub packing: PackingConfig,
+    /// Signs data responses, when configured
+    pub signer: Option<Arc<ResponseSigner>>,
+    /// Middleware layers wrapping every route, outermost first
+    pub middleware: Vec<Middleware>,
+    /// Origins allowed by the CORS layer
+    pub cors: CorsConfig,
+    /// Responses compressed by the compression layer
+    pub compression: CompressionConfig,
+    /// Credentials checked by the auth layer
+    pub auth: Arc<Authenticator>,
+    /// Persistent store opened from `--database`
+    #[cfg(feature = "sqlite")]
+    pub store: Option<Store>,
+}
+
+impl AppState {
+    /// Create state for a backend URL with default feature settings
+    pub fn new(api_url: String, http_client: reqwest::Client) -> Self {
+        Self {
+            api_url,
+            http_client,
+            client_recycler: None,
+            backend_credentials: None,
+            backend_timeout: None,
+            land_sea_mask: LandSeaMaskConfig::default(),
+            backend: BackendCompat::default(),
+            strict_query: false,
+            request_limits: RequestLimits::default(),
+            admin_token: None,
+            log_level: None,
+            site: SiteConfig::default(),
+            base_path: String::new(),
+            dev_assets: None,
+            cross_origin_isolation: false,
+            mobile_index: false,
+            client_errors: Arc::new(ClientErrorLimiter::default()),
+            ocean_currents: OceanCurrentsConfig::default(),
+            earth_grid_limit: EarthGridLimit::default(),
+            derived_variables: DerivedRegistry::default(),
+            transforms: Transforms::default(),
+            render_hints: Arc::new(RwLock::new(RenderHints::default())),
+            units: UnitSystem::default(),
+            health_probe: Arc::new(BackendProbe::default()),
+            response_cache: Arc::new(ResponseCache::default()),
+            datasets: Arc::new(Datasets::default()),
+            backend_retry: RetryPolicy::default(),
+            circuit_breaker: Arc::new(CircuitBreaker::default()),
+            jobs: Arc::new(JobQueue::default()),
+            scheduler: Arc::new(Scheduler::default()),
+            webhooks: Webhooks::default(),
+            recent_errors: Arc::new(RecentErrors::default()),
+            load_shedder: Arc::new(LoadShedder::default()),
+            backend_limiter: Arc::new(BackendLimiter::default()),
+            cache_policy: CachePolicy::default(),
+            asset_cache: AssetCachePolicy::default(),
+            catalog: Arc::new(CatalogService::default()),
+            #[cfg(feature = "acme")]
+            acme: None,
+            packing: PackingConfig::default(),
+            signer: None,
+            middleware: DEFAULT_PIPELINE.to_vec(),
+            cors: CorsConfig::default(),
+            compression: CompressionConfig::default(),
+            auth: Arc::default(),
+            #[cfg(feature = "sqlite")]
+            store: None,
+        }
+    }
+
+    /// Start a GET request to the backend, with credentials when configured,
+    /// the current W3C trace context and the time left of the request's
+    /// deadline, which replaces the client's timeout when shorter
+    pub fn backend_get(&self, url: &str) -> reqwest::RequestBuilder {
+        let mut request = match &self.client_recycler {
+            Some(recycler) => recycler.client().get(url),
+            None => self.http_client.get(url),
+        };
+        if let Some(context) = TraceContext::current() {
+            request = request.header("traceparent", context.traceparent());
+            if let Some(tracestate) = context.tracestate {
+                request = request.header("tracestate", tracestate);
+            }
+        }
+        if let Some(deadline) = Deadline::current() {
+            let remaining = deadline.remaining();
+            let timeout = self
+                .backend_timeout
+                .map_or(remaining, |timeout| timeout.min(remaining));
+            request = request.timeout(timeout).header(
+                REQUEST_TIMEOUT_HEADER,
+                format!("{}ms", remaining.as_millis()),
+            );
+        }
+        match &self.backend_credentials {
+            Some(auth) => request.basic_auth(&auth.username, auth.password.as_ref()),
+            None => request,
+        }
+    }
+
+    /// Send a backend request once the concurrency limit allows it, feeding
+    /// its latency back into the limit, and retry it as `backend_retry`
+    /// allows
+    ///
+    /// Fails when no slot frees up in time, the request's deadline passes
+    /// or the backend does not answer within the client's timeouts;
+    /// otherwise the backend's own result of the last attempt is returned as
+    /// is.
+    pub async fn backend_send(
+        &self,
+        request: reqwest::RequestBuilder,
+    ) -> Result<reqwest::Result<reqwest::Response>, AppError> {
+        let span = tracing::info_span!("backend_request", retries = 0u32);
+        let retryable = self.backend_retry.retries > 0 && RetryPolicy::is_idempotent(&request);
+        let mut request = request;
+        let mut retries = 0;
+        let result: Result<reqwest::Result<reqwest::Response>, AppError> = async move {
+            loop {
+                let next = if retryable && retries < self.backend_retry.retries {
+                    request.try_clone()
+                } else {
+                    None
+                };
+                let result = self.backend_send_once(request).await?;
+                let Some(next) = next.filter(|_| RetryPolicy::is_transient(&result)) else {
+                    return Ok(result);
+                };
+                retries += 1;
+                let delay = self.backend_retry.delay(retries);
+                if Deadline::current().is_some_and(|deadline| deadline.remaining() <= delay) {
+                    return Ok(result);
+                }
+                let outcome = match &result {
+                    Ok(response) => response.status().to_string(),
+                    Err(e) => e.to_string(),
+                };
+                warn!(
+                    retry = retries,
+                    delay_ms = delay.as_millis() as u64,
+                    "Backend request failed with {}, retrying",
+                    outcome
+                );
+                tracing::Span::current().record("retries", retries);
+                tokio::time::sleep(delay).await;
+                request = next;
+            }
+        }
+        .instrument(span)
+        .await;
+        match result? {
+            Err(e) if e.is_timeout() => Err(AppError::Timeout(format!(
+                "the backend did not answer in time: {}",
+                e
+            ))),
+            result => Ok(result),
+        }
+    }
+
+    async fn backend_send_once(
+        &self,
+        request: reqwest::RequestBuilder,
+    ) -> Result<reqwest::Result<reqwest::Response>, AppError> {
+        deadline::check("contacting the backend")?;
+        let admission = self.circuit_breaker.admit()?;
+        let permit = self.backend_limiter.acquire().await?;
+        let result = request.send().await;
+        permit.record(Outcome::of(&result));
+        admission.record(Outcome::of(&result));
+        match result {
+            Err(e) if e.is_timeout() && Deadline::current().is_some_and(|d| d.is_expired()) => {
+                Err(AppError::DeadlineExceeded(
+                    "the backend did not answer within the request timeout".to_string(),
+                ))
+            }
+            result => Ok(result),
+        }
+    }
+}
+
+/// Startup configuration for the web server
+#[derive(Debug, Clone)]
+pub struct ServerConfig {
+    /// Address to listen on, `127.0.0.1` by default
+    pub bind_address: IpAddr,
+    /// Port to listen on
+    pub port: u16,
+    /// URL of the Rossby backend server
+    pub api_url: String,
+    /// Credentials for the backend, unless the URL carries its own
+    pub backend_credentials: Option<BasicAuth>,
+    /// Land-sea mask settings for Earth overlays
+    pub land_sea_mask: LandSeaMaskConfig,
+    /// Schema of the backend, or `Auto` to probe it from the metadata
+    pub backend_schema: BackendSchema,
+    /// Reject requests carrying unrecognized query parameters
+    pub strict_query: bool,
+    /// Size limits of requests to the `/proxy/` routes
+    pub request_limits: RequestLimits,
+    /// Connection settings for the backend HTTP client
+    pub backend_client: BackendClientConfig,
+    /// Bearer token for the `/admin` endpoints; they are disabled without one
+    pub admin_token: Option<String>,
+    /// Handle returned by `init_logging`, enabling `/admin/loglevel`
+    pub log_level: Option<LogLevelHandle>,
+    /// Values substituted into `index.html`
+    pub site: SiteConfig,
+    /// Mount every route under this path, e.g. `/rossby-vis`
+    pub base_path: String,
+    /// Serve the frontend from this directory, re-reading changed files
+    pub dev_assets: Option<PathBuf>,
+    /// Send COOP/COEP headers so the page is cross-origin isolated
+    pub cross_origin_isolation: bool,
+    /// Serve `index.mobile.html` instead of `index.html` to phones
+    pub mobile_index: bool,
+    /// Frontend error reports logged per client per minute
+    pub client_error_rate: u32,
+    /// Backend and variables of the OSCAR-style ocean currents
+    pub ocean_currents: OceanCurrentsConfig,
+    /// Size limit on the grids served as Earth JSON
+    pub earth_grid_limit: EarthGridLimit,
+    /// Custom derived variables served alongside the built-in products
+    pub derived_variables: DerivedRegistry,
+    /// Hooks rewriting backend variables
+    pub transforms: Transforms,
+    /// Rendering hints added to Earth headers
+    pub render_hints: RenderHints,
+    /// Unit system clients show values in unless asked otherwise
+    pub units: UnitSystem,
+    /// Caching and timeouts of the backend probe of `/health`
+    pub health_probe: HealthProbeConfig,
+    /// Size and lifetime of the in-memory backend response cache
+    pub response_cache: ResponseCacheConfig,
+    /// Named backends served side by side, the first being `api_url`
+    pub datasets: Vec<DatasetConfig>,
+    /// Retries of failed backend requests
+    pub backend_retry: RetryPolicy,
+    /// When repeated backend failures stop further requests
+    pub circuit_breaker: CircuitBreakerConfig,
+    /// Worker and queue limits of the background jobs
+    pub jobs: JobsConfig,
+    /// Tasks run periodically by the server
+    pub schedule: TaskSchedule,
+    /// URLs notified when new timesteps arrive
+    pub webhooks: WebhookConfig,
+    /// SQLite database for persistent state (requires the `sqlite` feature)
+    pub database: Option<PathBuf>,
+    /// Middleware layers wrapping every route, outermost first
+    pub middleware: Vec<Middleware>,
+    /// Origins allowed by the CORS layer
+    pub cors: CorsConfig,
+    /// Responses compressed by the compression layer
+    pub compression: CompressionConfig,
+    /// Users allowed to use the server, checked by the auth layer
+    pub auth: AuthConfig,
+    /// Record backend interactions to fixtures, or replay them
+    pub recording: Option<Recording>,
+    /// Faults injected into backend responses, for testing
+    pub chaos: Option<ChaosConfig>,
+    /// Memory and CPU thresholds for shedding low-priority requests
+    pub load_shedding: LoadSheddingConfig,
+    /// Limit on the requests in flight to the backend
+    pub backend_concurrency: ConcurrencyLimit,
+    /// How long browsers and CDNs may cache data responses
+    pub cache_policy: CachePolicy,
+    /// How long browsers and CDNs may cache the embedded frontend
+    pub asset_cache: AssetCachePolicy,
+    /// Certificates for serving HTTPS, selected by SNI
+    pub tls: TlsConfig,
+    /// Directory for state kept across restarts, such as ACME certificates
+    pub data_dir: PathBuf,
+    /// Addresses to listen on, replacing `bind_address` and `port`
+    pub listeners: Vec<ListenAddress>,
+    /// Serve plain HTTP on this Unix socket; TCP listeners are then only
+    /// opened for `listeners`
+    pub unix_socket: Option<PathBuf>,
+    /// Permissions of the Unix socket file
+    pub unix_socket_mode: u32,
+    /// Notify systemd of readiness and serve the sockets it passes
+    pub systemd: bool,
+    /// How many sockets systemd passed, as taken by
+    /// [`systemd::take_listen_fds`] before the runtime started
+    pub listen_fds: usize,
+    /// Integrity signatures on data responses
+    pub signing: SigningConfig,
+    /// Packing of Earth data arrays
+    pub packing: PackingConfig,
+}
+
+impl ServerConfig {
+    /// Create a configuration with default feature settings
+    pub fn new(port: u16, api_url: String) -> Self {
+        Self {
+            bind_address: IpAddr::V4(Ipv4Addr::LOCALHOST),
+            port,
+            api_url,
+            backend_credentials: None,
+            land_sea_mask: LandSeaMaskConfig::default(),
+            backend_schema: BackendSchema::default(),
+            strict_query: false,
+            request_limits: RequestLimits::default(),
+            backend_client: BackendClientConfig::default(),
+            admin_token: None,
+            log_level: None,
+            site: SiteConfig::default(),
+            base_path: String::new(),
+            dev_assets: None,
+            cross_origin_isolation: false,
+            mobile_index: false,
+            client_error_rate: 60,
+            ocean_currents: OceanCurrentsConfig::default(),
+            earth_grid_limit: EarthGridLimit::default(),
+            derived_variables: DerivedRegistry::default(),
+            transforms: Transforms::default(),
+            render_hints: RenderHints::default(),
+            units: UnitSystem::default(),
+            health_probe: HealthProbeConfig::default(),
+            response_cache: ResponseCacheConfig::default(),
+            datasets: Vec::new(),
+            backend_retry: RetryPolicy::default(),
+            circuit_breaker: CircuitBreakerConfig::default(),
+            jobs: JobsConfig::default(),
+            schedule: TaskSchedule::default(),
+            webhooks: WebhookConfig::default(),
+            database: None,
+            middleware: DEFAULT_PIPELINE.to_vec(),
+            cors: CorsConfig::default(),
+            compression: CompressionConfig::default(),
+            auth: AuthConfig::default(),
+            recording: None,
+            chaos: None,
+            load_shedding: LoadSheddingConfig::default(),
+            backend_concurrency: ConcurrencyLimit::default(),
+            cache_policy: CachePolicy::default(),
+            asset_cache: AssetCachePolicy::default(),
+            tls: TlsConfig::default(),
+            data_dir: PathBuf::from("data"),
+            listeners: Vec::new(),
+            unix_socket: None,
+            unix_socket_mode: DEFAULT_UNIX_SOCKET_MODE,
+            systemd: false,
+            listen_fds: 0,
+            signing: SigningConfig::default(),
+            packing: PackingConfig::default(),
+        }
+    }
+}
+
+impl ServerConfig {
+    /// Start a configuration for the backend at `api_url`, listening on
+    /// `127.0.0.1:8080` unless set otherwise
+    pub fn builder(api_url: impl Into<String>) -> ServerConfigBuilder {
+        ServerConfigBuilder {
+            config: ServerConfig::new(8080, api_url.into()),
+        }
+    }
+
+    /// The address the server listens on
+    pub fn addr(&self) -> SocketAddr {
+        SocketAddr::new(self.bind_address, self.port)
+    }
+
+    /// The configured listeners, or the single one of `bind_address` and
+    /// `port`, serving HTTPS if certificates are configured, unless a Unix
+    /// socket replaces it
+    pub fn listen_addresses(&self) -> Result<Vec<ListenAddress>, AppError> {
+        if self.listeners.is_empty() {
+            if self.unix_socket.is_some() {
+                return Ok(Vec::new());
+            }
+            return Ok(vec![ListenAddress {
+                tls: self.tls.is_enabled(),
+                ..ListenAddress::new(self.addr())
+            }]);
+        }
+        if self.listeners.iter().any(|listener| listener.tls) && !self.tls.is_enabled() {
+            return Err(AppError::ConfigError(
+                "A TLS listener needs a certificate, from --tls-cert or --acme-domain".to_string(),
+            ));
+        }
+        Ok(self.listeners.clone())
+    }
+}
+
+/// Builds a [`ServerConfig`] for applications embedding the server
+///
+/// ```no_run
+/// # async fn run() -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+/// use rossby_vis::{run_server_with_config, ServerConfig};
+///
+/// let config = ServerConfig::builder("http://rossby.internal:8000")
+///     .bind("0.0.0.0:9090".parse()?)
+///     .admin_token("s3cret")
+///     .build()?;
+/// run_server_with_config(config).await?.join().await
+/// # }
+/// ```
+#[derive(Debug, Clone)]
+pub struct ServerConfigBuilder {
+    config: ServerConfig,
+}
+
+impl ServerConfigBuilder {
+    /// Listen on `addr`
+    pub fn bind(mut self, addr: SocketAddr) -> Self {
+        self.config.bind_address = addr.ip();
+        self.config.port = addr.port();
+        self
+    }
+
+    /// Listen on `port`, keeping the bind address
+    pub fn port(mut self, port: u16) -> Self {
+        self.config.port = port;
+        self
+    }
+
+    /// Authenticate to the backend with HTTP Basic auth
+    pub fn backend_credentials(
+        mut self,
+        username: impl Into<String>,
+        password: Option<String>,
+    ) -> Self {
+        self.config.backend_credentials = Some(BasicAuth {
+            username: username.into(),
+            password,
+        });
+        self
+    }
+
+    /// Connection settings of the backend HTTP client
+    pub fn backend_client(mut self, client: BackendClientConfig) -> Self {
+        self.config.backend_client = client;
+        self
+    }
+
+    /// Schema of the backend, instead of probing it
+    pub fn backend_schema(mut self, schema: BackendSchema) -> Self {
+        self.config.backend_schema = schema;
+        self
+    }
+
+    /// Limit on the requests in flight to the backend
+    pub fn backend_concurrency(mut self, limit: ConcurrencyLimit) -> Self {
+        self.config.backend_concurrency = limit;
+        self
+    }
+
+    /// Backend and variables of the ocean currents
+    pub fn ocean_currents(mut self, ocean_currents: OceanCurrentsConfig) -> Self {
+        self.config.ocean_currents = ocean_currents;
+        self
+    }
+
+    /// How long browsers and CDNs may cache data responses
+    pub fn cache_policy(mut self, policy: CachePolicy) -> Self {
+        self.config.cache_policy = policy;
+        self
+    }
+
+    /// How long browsers and CDNs may cache the embedded frontend
+    pub fn asset_cache(mut self, policy: AssetCachePolicy) -> Self {
+        self.config.asset_cache = policy;
+        self
+    }
+
+    /// Listen on `listener`, in addition to those already added; once any is
+    /// added, the `bind` address is no longer used
+    pub fn listen(mut self, listener: ListenAddress) -> Self {
+        self.config.listeners.push(listener);
+        self
+    }
+
+    /// Serve plain HTTP on the Unix socket at `path`, instead of the `bind`
+    /// address unless listeners are added
+    pub fn unix_socket(mut self, path: impl Into<PathBuf>) -> Self {
+        self.config.unix_socket = Some(path.into());
+        self
+    }
+
+    /// Notify systemd of readiness and serve the sockets it passes
+    pub fn systemd(mut self, enabled: bool) -> Self {
+        self.config.systemd = enabled;
+        self
+    }
+
+    /// Serve the `count` sockets taken with [`systemd::take_listen_fds`]
+    pub fn listen_fds(mut self, count: usize) -> Self {
+        self.config.listen_fds = count;
+        self
+    }
+
+    /// Serve HTTPS with `certificate`, in addition to those already added
+    pub fn tls_certificate(mut self, certificate: TlsCertificate) -> Self {
+        self.config.tls.certificates.push(certificate);
+        self
+    }
+
+    /// Obtain and renew a certificate automatically
+    pub fn acme(mut self, acme: AcmeConfig) -> Self {
+        self.config.tls.acme = Some(acme);
+        self
+    }
+
+    /// Keep state across restarts in `dir`
+    pub fn data_dir(mut self, dir: impl Into<PathBuf>) -> Self {
+        self.config.data_dir = dir.into();
+        self
+    }
+
+    /// Sign data responses as configured
+    pub fn signing(mut self, signing: SigningConfig) -> Self {
+        self.config.signing = signing;
+        self
+    }
+
+    /// Pack Earth data arrays as configured
+    pub fn packing(mut self, packing: PackingConfig) -> Self {
+        self.config.packing = packing;
+        self
+    }
+
+    /// Publish `units` as the preferred unit system
+    pub fn units(mut self, units: UnitSystem) -> Self {
+        self.config.units = units;
+        self
+    }
+
+    /// Cache backend responses in memory as configured
+    pub fn response_cache(mut self, cache: ResponseCacheConfig) -> Self {
+        self.config.response_cache = cache;
+        self
+    }
+
+    /// Retry failed backend requests as `policy` allows
+    pub fn backend_retry(mut self, policy: RetryPolicy) -> Self {
+        self.config.backend_retry = policy;
+        self
+    }
+
+    /// Stop backend requests after repeated failures as `config` says
+    pub fn circuit_breaker(mut self, config: CircuitBreakerConfig) -> Self {
+        self.config.circuit_breaker = config;
+        self
+    }
+
+    /// Serve `datasets` side by side, the first one replacing the backend
+    pub fn datasets(mut self, datasets: Vec<DatasetConfig>) -> Self {
+        if let Some(first) = datasets.first() {
+            self.config.api_url = first.api_url.clone();
+        }
+        self.config.datasets = datasets;
+        self
+    }
+
+    /// Enable the `/admin` endpoints behind this bearer token
+    pub fn admin_token(mut self, token: impl Into<String>) -> Self {
+        self.config.admin_token = Some(token.into());
+        self
+    }
+
+    /// Allow pages on `origin`, or `*` for any, to read responses
+    pub fn cors_origin(mut self, origin: impl Into<String>) -> Self {
+        self.config.cors.origins.push(origin.into());
+        self
+    }
+
+    /// Compress responses as `config` says
+    pub fn compression(mut self, config: CompressionConfig) -> Self {
+        self.config.compression = config;
+        self
+    }
+
+    /// Users allowed to use the server
+    pub fn auth(mut self, config: AuthConfig) -> Self {
+        self.config.auth = config;
+        self
+    }
+
+    /// Keep state across restarts in the SQLite database at `path`
+    pub fn database(mut self, path: impl Into<PathBuf>) -> Self {
+        self.config.database = Some(path.into());
+        self
+    }
+
+    /// Middleware layers wrapping every route, outermost first
+    pub fn middleware(mut self, pipeline: Vec<Middleware>) -> Self {
+        self.config.middleware = pipeline;
+        self
+    }
+
+    /// Values substituted into `index.html`
+    pub fn site(mut self, site: SiteConfig) -> Self {
+        self.config.site = site;
+        self
+    }
+
+    /// Mount every route under `path`, e.g. `/rossby-vis`
+    pub fn base_path(mut self, path: impl Into<String>) -> Self {
+        self.config.base_path = path.into();
+        self
+    }
+
+    /// Size limits of requests to the `/proxy/` routes
+    pub fn request_limits(mut self, limits: RequestLimits) -> Self {
+        self.config.request_limits = limits;
+        self
+    }
+
+    /// Memory and CPU thresholds for shedding low-priority requests
+    pub fn load_shedding(mut self, load_shedding: LoadSheddingConfig) -> Self {
+        self.config.load_shedding = load_shedding;
+        self
+    }
+
+    /// Change any other setting of the configuration
+    pub fn configure(mut self, f: impl FnOnce(&mut ServerConfig)) -> Self {
+        f(&mut self.config);
+        self
+    }
+
+    /// The configuration, failing if the backend URL is invalid
+    pub fn build(self) -> Result<ServerConfig, AppError> {
+        self.config.api_url.parse::<BackendEndpoint>()?;
+        Ok(self.config)
+    }
 }
 
 /// Run the web server on the specified port with the given API URL
+///
+/// Kept for compatibility; [`ServerConfig::builder`] exposes every setting.
 pub async fn run_server(
     port: u16,
     api_url: String,
-) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
-    // Create HTTP client for backend requests
-    let http_client = reqwest::Client::new();
-
-    // Create application state
-    let state = Arc::new(AppState {
-        api_url,
-        http_client,
+) -> Result<ServerHandle, Box<dyn std::error::Error + Send + Sync>> {
+    run_server_with_config(ServerConfig::new(port, api_url)).await
+}
+
+/// Run the web server with the full startup configuration
+///
+/// Returns once the server is listening; it keeps running in the background
+/// until [`ServerHandle::shutdown`] is called.
+pub async fn run_server_with_config(
+    config: ServerConfig,
+) -> Result<ServerHandle, Box<dyn std::error::Error + Send + Sync>> {
+    let listeners = config.listen_addresses()?;
+    let systemd = config.systemd;
+    let listen_fds = config.listen_fds;
+    let unix_socket = config.unix_socket.clone();
+    let unix_socket_mode = config.unix_socket_mode;
+    #[cfg(not(unix))]
+    if unix_socket.is_some() {
+        return Err(AppError::ConfigError(
+            "Unix sockets are not supported on this platform".to_string(),
+        )
+        .into());
+    }
+    logging::record_start();
+
+    let tls = config.tls.clone();
+    let state = AppState::from_config(config).await?;
+    #[cfg(feature = "acme")]
+    let tls = tls.server_config(state.acme.clone())?;
+    #[cfg(not(feature = "acme"))]
+    let tls = tls.server_config()?;
+    let app = build_router(state.clone());
+
+    // Bind every address before serving any, so a taken port fails startup;
+    // sockets passed by systemd replace the configured addresses
+    let inherited = if systemd {
+        systemd::listen_fds(listen_fds)?
+    } else {
+        Vec::new()
+    };
+    let sockets = if inherited.is_empty() {
+        listeners
+            .iter()
+            .map(|listener| Ok((*listener, std::net::TcpListener::bind(listener.addr)?)))
+            .collect::<std::io::Result<Vec<_>>>()?
+    } else {
+        systemd::match_listeners(inherited, &listeners)?
+    };
+
+    // A listener of their own takes the admin routes off the others
+    let (public, admin) = if sockets.iter().any(|(listener, _)| listener.admin) {
+        (
+            build_public_router(state.clone()),
+            build_admin_router(state.clone()),
+        )
+    } else {
+        (app.clone(), app.clone())
+    };
+
+    let (shutdown, signal) = watch::channel(false);
+    let mut addrs = Vec::new();
+    let mut servers: Vec<BoxFuture<'static, Result<(), hyper::Error>>> = Vec::new();
+    for (listener, socket) in sockets {
+        let bound = ListenAddress {
+            addr: socket.local_addr()?,
+            ..listener
+        };
+        let router = if listener.admin { &admin } else { &public };
+        let service = router.clone().into_make_service();
+        let signal = shutdown_signal(signal.clone());
+        servers.push(match &tls {
+            Some(tls) if listener.tls => {
+                socket.set_nonblocking(true)?;
+                let incoming =
+                    tls::incoming(tokio::net::TcpListener::from_std(socket)?, tls.clone());
+                Box::pin(
+                    axum::Server::builder(incoming)
+                        .serve(service)
+                        .with_graceful_shutdown(signal),
+                )
+            }
+            _ => Box::pin(
+                axum::Server::from_tcp(socket)?
+                    .serve(service)
+                    .with_graceful_shutdown(signal),
+            ),
+        });
+        info!("Server listening on {}", bound);
+        addrs.push(bound.addr);
+    }
+    #[cfg(unix)]
+    if let Some(path) = &unix_socket {
+        let incoming = listen::unix_incoming(listen::bind_unix(path, unix_socket_mode)?);
+        let socket_file = listen::UnixSocketFile(path.clone());
+        let server = axum::Server::builder(incoming)
+            .serve(public.clone().into_make_service())
+            .with_graceful_shutdown(shutdown_signal(signal.clone()));
+        servers.push(Box::pin(async move {
+            // The socket file goes once the server stops or is dropped
+            let _socket_file = socket_file;
+            server.await
+        }));
+        info!("Server listening on unix:{}", path.display());
+    }
+
+    // Certificates are ordered once the listener can answer challenges
+    #[cfg(feature = "acme")]
+    if let Some(acme) = &state.acme {
+        acme.start();
+    }
+
+    // Start the scheduled tasks, which may request routes of the server
+    scheduler::start(state.clone(), app);
+
+    let supervisor = systemd.then(|| {
+        systemd::ready();
+        tokio::spawn(systemd::supervise(shutdown_signal(signal)))
     });
 
-    // Build our application with routes and middleware layers
-    let app = Router::new()
+    // Run the server; once stopped, systemd has been told so too
+    let task = tokio::spawn(async move {
+        futures::future::try_join_all(servers).await?;
+        if let Some(supervisor) = supervisor {
+            supervisor.await?;
+        }
+        Ok(())
+    });
+
+    Ok(ServerHandle {
+        addrs,
+        unix_socket,
+        state,
+        shutdown,
+        task,
+    })
+}
+
+/// Resolves once [`ServerHandle::shutdown`] is called
+async fn shutdown_signal(mut signal: watch::Receiver<bool>) {
+    while !*signal.borrow_and_update() {
+        if signal.changed().await.is_err() {
+            // The handle was dropped, so the server runs until the process ends
+            std::future::pending::<()>().await;
+        }
+    }
+}
+
+/// A server started by [`run_server_with_config`]
+///
+/// Dropping the handle leaves the server running.
+pub struct ServerHandle {
+    addrs: Vec<SocketAddr>,
+    unix_socket: Option<PathBuf>,
+    state: Arc<AppState>,
+    shutdown: watch::Sender<bool>,
+    task: JoinHandle<Result<(), Box<dyn std::error::Error + Send + Sync>>>,
+}
+
+impl std::fmt::Debug for ServerHandle {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("ServerHandle")
+            .field("addrs", &self.addrs)
+            .field("unix_socket", &self.unix_socket)
+            .finish_non_exhaustive()
+    }
+}
+
+impl ServerHandle {
+    /// The address of the first listener, with the actual port when it was
+    /// started on port 0
+    ///
+    /// Panics when the server listens on a Unix socket only.
+    pub fn addr(&self) -> SocketAddr {
+        self.addrs[0]
+    }
+
+    /// The addresses of all TCP listeners, in the order they were configured
+    pub fn addrs(&self) -> &[SocketAddr] {
+        &self.addrs
+    }
+
+    /// The path of the Unix socket, if the server listens on one
+    pub fn unix_socket(&self) -> Option<&Path> {
+        self.unix_socket.as_deref()
+    }
+
+    /// The state the server runs with, e.g. to reload settings into
+    pub fn state(&self) -> &Arc<AppState> {
+        &self.state
+    }
+
+    /// Stop accepting connections and finish once the open ones are done
+    pub fn shutdown(&self) {
+        self.shutdown.send_replace(true);
+    }
+
+    /// Wait for the server to stop
+    pub async fn join(self) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+        self.task.await??;
+        Ok(())
+    }
+
+    /// Shut the server down once `signal` resolves, e.g. on SIGTERM, and
+    /// wait for it to stop
+    pub async fn stop_on(
+        mut self,
+        signal: impl Future<Output = ()>,
+    ) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+        tokio::select! {
+            // The server failed before it was asked to stop
+            result = &mut self.task => return result?,
+            _ = signal => self.shutdown(),
+        }
+        self.join().await
+    }
+
+    /// Shut the server down and wait for it to stop
+    pub async fn stop(self) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+        self.shutdown();
+        self.join().await
+    }
+}
+
+impl AppState {
+    /// Create the state for `config`
+    ///
+    /// This also starts what the configuration asks for in the background:
+    /// local servers recording, replaying or disturbing backend responses,
+    /// and pressure sampling for load shedding. Scheduled tasks need the
+    /// router and are started with [`scheduler::start`].
+    pub async fn from_config(
+        config: ServerConfig,
+    ) -> Result<Arc<Self>, Box<dyn std::error::Error + Send + Sync>> {
+        // Validate the backend URL before anything else is started
+        let endpoint: BackendEndpoint = config.api_url.parse()?;
+        info!("Using Rossby backend at {}", endpoint);
+
+        // Create HTTP client for backend requests
+        let http_client = config.backend_client.build()?;
+
+        // Recording and replaying put a local server in front of the backend,
+        // reached without the backend's proxy and TLS settings
+        let (backend_url, http_client) = match &config.recording {
+            Some(recording) => (
+                recording.start(&endpoint.base_url, http_client).await?,
+                reqwest::Client::new(),
+            ),
+            None => (endpoint.base_url, http_client),
+        };
+        // Fault injection goes in front of the backend, recorded or not
+        let (backend_url, http_client) = match &config.chaos {
+            Some(chaos) => (
+                chaos.start(&backend_url, http_client).await?,
+                reqwest::Client::new(),
+            ),
+            None => (backend_url, http_client),
+        };
+        let local_backend = config.recording.is_some() || config.chaos.is_some();
+
+        // Create application state
+        let mut state = AppState::new(backend_url, http_client);
+        state.backend_credentials = endpoint.credentials.or(config.backend_credentials);
+        state.backend_timeout = config.backend_client.request_timeout;
+        if let (Some(max_age), false) = (config.backend_client.max_connection_age, local_backend) {
+            state.client_recycler = Some(Arc::new(ClientRecycler::new(
+                config.backend_client.clone(),
+                state.http_client.clone(),
+                max_age,
+            )));
+        }
+        state.land_sea_mask = config.land_sea_mask;
+        state.backend = BackendCompat::new(config.backend_schema);
+        state.strict_query = config.strict_query;
+        state.request_limits = config.request_limits;
+        state.admin_token = config.admin_token;
+        state.log_level = config.log_level;
+        state.site = config.site;
+        state.base_path = parse_base_path(&config.base_path)?;
+        if !state.base_path.is_empty() {
+            info!("Serving under the base path {}", state.base_path);
+        }
+        state.cross_origin_isolation = config.cross_origin_isolation;
+        state.mobile_index = config.mobile_index;
+        state.client_errors = Arc::new(ClientErrorLimiter::new(config.client_error_rate));
+        if let Some(endpoint) = &config.ocean_currents.endpoint {
+            info!("Using ocean currents backend at {}", endpoint);
+        }
+        state.ocean_currents = config.ocean_currents;
+        state.earth_grid_limit = config.earth_grid_limit;
+        if !config.derived_variables.is_empty() {
+            info!("Serving derived variables {:?}", config.derived_variables);
+        }
+        state.derived_variables = config.derived_variables;
+        state.transforms = config.transforms;
+        state.render_hints = Arc::new(RwLock::new(config.render_hints));
+        state.units = config.units;
+        state.health_probe = Arc::new(BackendProbe::new(config.health_probe));
+        if config.response_cache.max_bytes > 0 {
+            info!(
+                "Caching backend responses: {} bytes for {}s",
+                config.response_cache.max_bytes,
+                config.response_cache.ttl.as_secs()
+            );
+        }
+        if let Some(path) = &config.database {
+            #[cfg(feature = "sqlite")]
+            {
+                state.store = Some(Store::open(path)?);
+                info!("Persisting state in {}", path.display());
+            }
+            #[cfg(not(feature = "sqlite"))]
+            return Err(format!(
+                "--database {} requires building with the `sqlite` feature",
+                path.display()
+            )
+            .into());
+        }
+        #[cfg(feature = "sqlite")]
+        let mut response_cache = match &state.store {
+            Some(store) => ResponseCache::with_store(config.response_cache, store)?,
+            None => ResponseCache::new(config.response_cache)?,
+        };
+        #[cfg(not(feature = "sqlite"))]
+        let mut response_cache = ResponseCache::new(config.response_cache)?;
+        if config.circuit_breaker.failure_threshold > 0 {
+            info!(
+                "Opening the backend circuit after {} failures in a row for {}s",
+                config.circuit_breaker.failure_threshold,
+                config.circuit_breaker.cooldown.as_secs()
+            );
+            response_cache = response_cache.keeping_stale();
+        }
+        state.response_cache = Arc::new(response_cache);
+        if config.backend_retry.retries > 0 {
+            info!(
+                "Retrying failed backend requests up to {} times",
+                config.backend_retry.retries
+            );
+        }
+        state.backend_retry = config.backend_retry;
+        state.circuit_breaker = Arc::new(CircuitBreaker::new(config.circuit_breaker));
+        state.jobs = Arc::new(JobQueue::new(config.jobs));
+        let mut schedule = config.schedule;
+        if !config.webhooks.urls.is_empty()
+            && schedule.ensure_metadata_refresh(DEFAULT_METADATA_REFRESH)
+        {
+            info!("Watching the metadata for new timesteps to notify webhooks");
+        }
+        state.scheduler = Arc::new(Scheduler::new(schedule));
+        state.webhooks = Webhooks::new(config.webhooks)?;
+        if config.load_shedding.is_enabled() {
+            info!(
+                "Shedding low-priority requests above {}",
+                config.load_shedding
+            );
+            shedding::start_sampling(DEFAULT_SAMPLE_INTERVAL);
+        }
+        if let Some(max) = config.load_shedding.max_concurrent_requests {
+            if !(1..=shedding::MAX_CONCURRENT_REQUESTS).contains(&max) {
+                return Err(format!(
+                    "--max-concurrent-requests must be between 1 and {}",
+                    shedding::MAX_CONCURRENT_REQUESTS
+                )
+                .into());
+            }
+            info!("Refusing requests beyond {} in flight", max);
+        }
+        state.load_shedder = Arc::new(LoadShedder::new(config.load_shedding));
+        if config.backend_concurrency != ConcurrencyLimit::Unlimited {
+            info!(
+                "Limiting backend concurrency: {}",
+                config.backend_concurrency
+            );
+        }
+        state.backend_limiter = Arc::new(BackendLimiter::new(
+            config.backend_concurrency,
+            DEFAULT_QUEUE_TIMEOUT,
+        ));
+        state.cache_policy = config.cache_policy;
+        state.asset_cache = config.asset_cache;
+        if let Some(acme) = &config.tls.acme {
+            #[cfg(feature = "acme")]
+            {
+                state.acme = Some(Arc::new(Acme::new(acme.clone(), &config.data_dir)?));
+            }
+            #[cfg(not(feature = "acme"))]
+            return Err(format!(
+                "--acme-domain {} requires building with the `acme` feature",
+                acme.domains.join(",")
+            )
+            .into());
+        }
+        state.packing = config.packing;
+        state.signer =
+            ResponseSigner::from_config(&config.signing, &config.data_dir)?.map(Arc::new);
+        if let Some(root) = &config.dev_assets {
+            state.dev_assets = Some(Arc::new(DevAssets::new(root)?));
+        }
+        if config.middleware != DEFAULT_PIPELINE {
+            let names: Vec<&str> = config.middleware.iter().map(|m| m.name()).collect();
+            info!("Middleware pipeline: {}", names.join(", "));
+        }
+        config.cors.validate()?;
+        state.middleware = config.middleware;
+        state.cors = config.cors;
+        state.compression = config.compression;
+        config.auth.validate()?;
+        #[allow(unused_mut)]
+        let mut auth = Authenticator::new(config.auth.clone())?;
+        #[cfg(feature = "sqlite")]
+        if let Some(store) = &state.store {
+            let issued = auth.use_store(store.clone()).await?;
+            if issued > 0 {
+                info!(
+                    "Accepting {} issued API key(s) on the data and API routes",
+                    issued
+                );
+            }
+        }
+        if auth.is_enabled() {
+            // Without its layer, the server would be open to anyone
+            if !state.middleware.contains(&Middleware::Auth) {
+                return Err("Authentication needs the auth middleware in the pipeline".into());
+            }
+            if !config.auth.basic.is_empty() {
+                info!(
+                    "Requiring HTTP Basic authentication of {} user(s)",
+                    config.auth.basic.len()
+                );
+            }
+            if !config.auth.api_keys.is_empty() {
+                info!(
+                    "Accepting {} API key(s) on the data and API routes",
+                    config.auth.api_keys.len()
+                );
+            }
+            if let Some(source) = config.auth.jwt.jwks_url.as_ref().or(config
+                .auth
+                .jwt
+                .issuer
+                .as_ref())
+            {
+                info!(
+                    "Accepting bearer tokens signed by {} on the data and API routes",
+                    source
+                );
+            }
+        }
+        state.auth = Arc::new(auth);
+        if config
+            .datasets
+            .first()
+            .is_some_and(|first| first.api_url != config.api_url)
+        {
+            return Err("The first dataset must be the backend of api_url".into());
+        }
+        state.datasets = Arc::new(Datasets::new(
+            &state,
+            config.datasets,
+            config.backend_schema,
+        )?);
+        Ok(Arc::new(state))
+    }
+}
+
+/// The router serving every route of rossby-vis, wrapped in the state's
+/// middleware pipeline
+///
+/// Applications can serve it themselves or mount it in their own router,
+/// e.g. with `Router::nest`, alongside their own middleware and TLS setup.
+///
+/// # Panics
+///
+/// If the state's CORS origins are invalid; [`AppState::from_config`]
+/// rejects those.
+pub fn build_router(state: Arc<AppState>) -> Router {
+    let app = public_routes(&state).merge(admin_routes(&state));
+    with_pipeline(app, state)
+}
+
+/// The router serving every route but the admin page and endpoints
+///
+/// Panics like [`build_router`].
+pub fn build_public_router(state: Arc<AppState>) -> Router {
+    with_pipeline(public_routes(&state), state)
+}
+
+/// The router serving the admin page and endpoints alone, for a listener
+/// of their own
+///
+/// Panics like [`build_router`].
+pub fn build_admin_router(state: Arc<AppState>) -> Router {
+    with_pipeline(admin_routes(&state), state)
+}
+
+fn with_pipeline(app: Router<Arc<AppState>>, state: Arc<AppState>) -> Router {
+    let app = apply_pipeline(
+        app,
+        &state,
+        &state.middleware,
+        &state.cors,
+        &state.compression,
+    )
+    .expect("invalid CORS origins")
+    .with_state(state.clone());
+    match state.base_path.as_str() {
+        "" => app,
+        // Not `Router::nest`, which leaves `<base_path>/` unrouted
+        base_path => Router::new().fallback_service(
+            axum_middleware::map_request_with_state(base_path.to_string(), strip_base_path)
+                .layer(app),
+        ),
+    }
+}
+
+/// Remove `base_path` from the front of a request's path, refusing requests
+/// outside it
+async fn strip_base_path<B>(
+    State(base_path): State<String>,
+    mut request: Request<B>,
+) -> Result<Request<B>, StatusCode> {
+    let uri = request.uri();
+    let rest = uri
+        .path()
+        .strip_prefix(base_path.as_str())
+        .filter(|rest| rest.is_empty() || rest.starts_with('/'))
+        .ok_or(StatusCode::NOT_FOUND)?;
+    let path = match (rest, uri.query()) {
+        ("", None) => "/".to_string(),
+        ("", Some(query)) => format!("/?{}", query),
+        (rest, None) => rest.to_string(),
+        (rest, Some(query)) => format!("{}?{}", rest, query),
+    };
+    let mut parts = uri.clone().into_parts();
+    parts.path_and_query = Some(path.parse().map_err(|_| StatusCode::BAD_REQUEST)?);
+    *request.uri_mut() = Uri::from_parts(parts).map_err(|_| StatusCode::BAD_REQUEST)?;
+    Ok(request)
+}
+
+/// Operator endpoints, guarded by the admin token, and their page
+fn admin_routes(state: &Arc<AppState>) -> Router<Arc<AppState>> {
+    let router = Router::new();
+    #[cfg(feature = "sqlite")]
+    let router = router.route("/admin/api-keys", get(list_api_keys)).route(
+        "/admin/api-keys/:label",
+        put(issue_api_key).delete(revoke_api_key),
+    );
+    router
+        .route("/admin/loglevel", get(get_log_level).put(set_log_level))
+        .route("/admin/assets", get(list_assets))
+        .route("/admin/schedule", get(scheduled_tasks))
+        .route("/admin/overview", get(overview))
+        .route("/admin/cache", get(cache_stats).delete(purge_cache))
+        .route_layer(axum_middleware::from_fn_with_state(
+            state.clone(),
+            admin_auth_middleware,
+        ))
+        .route("/admin", get(admin_page))
+}
+
+fn public_routes(state: &Arc<AppState>) -> Router<Arc<AppState>> {
+    let router = Router::new();
+    #[cfg(feature = "acme")]
+    let router = router.route("/.well-known/acme-challenge/:token", get(acme_challenge));
+    router
         .route("/", get(index))
         .route("/proxy/metadata", get(proxy_metadata))
         .route("/proxy/data", get(proxy_data))
+        .route("/proxy/:dataset/metadata", get(dataset_metadata))
+        .route("/proxy/:dataset/data", get(dataset_data))
+        .route("/api/cross-section", post(cross_section))
+        .route("/api/trajectories", post(trajectories))
+        .route("/api/sample", post(sample))
+        .route("/api/jobs", post(submit_job))
+        .route("/api/jobs/:id", get(job_status).delete(cancel_job))
+        .route("/api/jobs/:id/result", get(job_result))
+        .route("/api/jobs/:id/events", get(job_events))
+        .route("/api/status", get(status))
+        .route("/api/version", get(version_info))
+        .route("/api/assets", get(asset_manifest))
+        .route("/api/cache-manifest", get(cache_manifest))
+        .route("/api/client-errors", post(report_client_errors))
+        .route("/api/time/next", get(next_time))
+        .route("/api/time/previous", get(previous_time))
+        .route("/api/public-key", get(public_key))
+        .route("/api/prefs", get(preferences))
+        .route("/api/datasets", get(list_datasets))
+        .route("/api/openapi.json", get(openapi_json))
+        .route("/api/docs", get(api_docs))
+        .route("/manifest.json", get(web_manifest))
+        .route("/sw.js", get(service_worker))
+        .route(DEV_EVENTS_PATH, get(dev_events))
         // Earth frontend compatible routes for live Rossby data (MUST come before /*path)
         // Specific routes first (for backward compatibility)
         .route(
@@ -50,41 +1405,60 @@ pub async fn run_server(
             "/data/weather/current/current-temp-surface-level-gfs-1.0.json",
             get(earth_temp_data),
         )
-        // Dynamic route for any variable discovered from metadata
-        .route(
-            "/data/weather/current/current-:variable-surface-level-gfs-1.0.json",
-            get(earth_dynamic_data),
-        )
-        .route("/*path", get(static_asset))
-        .layer(axum_middleware::from_fn_with_state(
+        // Any variable discovered from metadata, at the surface or a pressure level
+        .route("/data/weather/current/:file", get(earth_current_data))
+        // Overlay descriptions for products.js, generated from the metadata
+        .route("/data/products.json", get(products_catalog))
+        // Earth's OSCAR ocean currents: a catalog of dated files
+        .route("/data/oscar/catalog.json", get(oscar_catalog))
+        .route("/data/oscar/:file", get(oscar_data))
+        // Windows of consecutive timesteps for scrubbing
+        .route("/data/frames/:variable", get(earth_frames))
+        // Earth's dated paths, resolved to the nearest timestep, and the
+        // paths of the named datasets
+        .route("/data/weather/:dataset/*path", get(earth_weather_data))
+        .route_layer(axum_middleware::from_fn_with_state(
             state.clone(),
-            health_check_middleware,
+            strict_query_middleware,
         ))
-        .layer(axum_middleware::from_fn(security_headers_middleware))
-        .layer(axum_middleware::from_fn_with_state(
+        .route_layer(axum_middleware::from_fn_with_state(
             state.clone(),
-            error_logging_middleware,
+            request_limits_middleware,
         ))
-        .layer(axum_middleware::from_fn_with_state(
+        .route_layer(axum_middleware::from_fn_with_state(
             state.clone(),
-            request_tracing_middleware,
+            signing_middleware,
         ))
-        .layer(TraceLayer::new_for_http())
-        .with_state(state);
-
-    // Run the server
-    let addr = SocketAddr::from(([127, 0, 0, 1], port));
-    info!("Server listening on http://{}", addr);
-    axum::Server::bind(&addr)
-        .serve(app.into_make_service())
-        .await?;
-
-    Ok(())
+        .route("/*path", get(static_asset))
 }
 
 #[cfg(test)]
 mod tests {
-    // These tests would typically be integration tests
-    // since they'd involve starting a server and making HTTP requests
-    // We'll implement proper integration tests in the tests directory
+    // Tests starting the server live in the tests directory
+    use super::*;
+
+    #[test]
+    fn test_config_builder() {
+        let config = ServerConfig::builder("http://rossby.internal:8000")
+            .bind("0.0.0.0:9090".parse().unwrap())
+            .admin_token("s3cret")
+            .backend_credentials("vis", Some("pw".to_string()))
+            .cors_origin("*")
+            .configure(|config| config.strict_query = true)
+            .build()
+            .unwrap();
+        assert_eq!(config.addr(), "0.0.0.0:9090".parse().unwrap());
+        assert_eq!(config.admin_token.as_deref(), Some("s3cret"));
+        assert_eq!(config.backend_credentials.unwrap().username, "vis");
+        assert_eq!(config.cors.origins, ["*"]);
+        assert!(config.strict_query);
+
+        let defaults = ServerConfig::builder("http://localhost:8000")
+            .build()
+            .unwrap();
+        assert_eq!(defaults.addr(), "127.0.0.1:8080".parse().unwrap());
+        assert_eq!(defaults.middleware, DEFAULT_PIPELINE);
+
+        assert!(ServerConfig::builder("not a url").build().is_err());
+    }
 }
@@ -0,0 +1,401 @@
+//! Load shedding under memory or CPU pressure
+//!
+//! With `--shed-memory-percent` or `--shed-cpu-percent`, system memory and
+//! CPU usage are sampled every few seconds (and whenever the metrics collector
+//! runs). While either is above its threshold, low-priority requests such as
+//! analysis and frame bundles are answered with `503 Service Unavailable`
+//! and a `Retry-After` header instead of adding to the load. Health checks,
+//! admin routes, revalidations of cached responses and ordinary data
+//! requests keep being served.
+//!
+//! With `--max-concurrent-requests`, requests beyond that many in flight are
+//! answered with `503 Service Unavailable` and a `Retry-After` header right
+//! away rather than queued, so a stampede of browsers degrades into quick
+//! refusals instead of a pile of buffered backend responses exhausting
+//! memory. Health checks are answered before the limit applies.
+
+use axum::{
+    error_handling::HandleErrorLayer,
+    extract::State,
+    http::{header, HeaderValue, Method, Request},
+    middleware::Next,
+    response::{IntoResponse, Response},
+    BoxError, Router,
+};
+use serde::Serialize;
+use std::{
+    sync::{
+        atomic::{AtomicBool, AtomicU32, AtomicU64, Ordering},
+        Arc,
+    },
+    time::Duration,
+};
+use tokio::sync::Semaphore;
+use tower::{limit::GlobalConcurrencyLimitLayer, load_shed::LoadShedLayer, ServiceBuilder};
+use tracing::{info, warn};
+
+use crate::{error::AppError, server::AppState};
+
+/// How often pressure is sampled while shedding is enabled
+pub const DEFAULT_SAMPLE_INTERVAL: Duration = Duration::from_secs(5);
+
+/// Seconds clients are asked to wait before retrying a shed request
+const RETRY_AFTER_SECS: u64 = 10;
+
+/// The largest `--max-concurrent-requests`, as many as a semaphore counts
+pub const MAX_CONCURRENT_REQUESTS: usize = Semaphore::MAX_PERMITS;
+
+/// Parse `--max-concurrent-requests`, between 1 and
+/// [`MAX_CONCURRENT_REQUESTS`]
+pub fn parse_max_concurrent_requests(s: &str) -> Result<usize, String> {
+    s.parse()
+        .ok()
+        .filter(|max| (1..=MAX_CONCURRENT_REQUESTS).contains(max))
+        .ok_or_else(|| {
+            format!(
+                "Invalid request limit: {}. Expected 1 to {}",
+                s, MAX_CONCURRENT_REQUESTS
+            )
+        })
+}
+
+/// Thresholds above which low-priority requests are shed
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct LoadSheddingConfig {
+    /// Percentage of system memory in use
+    pub max_memory_percent: Option<f32>,
+    /// Percentage of total CPU in use
+    pub max_cpu_percent: Option<f32>,
+    /// Requests handled at once, of any priority
+    pub max_concurrent_requests: Option<usize>,
+}
+
+impl std::fmt::Display for LoadSheddingConfig {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let limits: Vec<String> = [
+            self.max_memory_percent.map(|p| format!("{}% memory", p)),
+            self.max_cpu_percent.map(|p| format!("{}% CPU", p)),
+        ]
+        .into_iter()
+        .flatten()
+        .collect();
+        write!(f, "{} usage", limits.join(" or "))
+    }
+}
+
+impl LoadSheddingConfig {
+    /// Whether any pressure threshold is set
+    pub fn is_enabled(&self) -> bool {
+        self.max_memory_percent.is_some() || self.max_cpu_percent.is_some()
+    }
+}
+
+/// Latest memory and CPU usage
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+pub struct Pressure {
+    pub memory_percent: f32,
+    pub cpu_percent: f32,
+}
+
+struct PressureReading {
+    memory_percent: AtomicU32,
+    cpu_percent: AtomicU32,
+    sampled: AtomicBool,
+}
+
+static PRESSURE: PressureReading = PressureReading {
+    memory_percent: AtomicU32::new(0),
+    cpu_percent: AtomicU32::new(0),
+    sampled: AtomicBool::new(false),
+};
+
+/// Record a sample of system memory and CPU usage
+pub fn record_pressure(memory_percent: f32, cpu_percent: f32) {
+    PRESSURE
+        .memory_percent
+        .store(memory_percent.to_bits(), Ordering::Relaxed);
+    PRESSURE
+        .cpu_percent
+        .store(cpu_percent.to_bits(), Ordering::Relaxed);
+    PRESSURE.sampled.store(true, Ordering::Relaxed);
+}
+
+/// The latest sample, if one was taken
+pub fn current_pressure() -> Option<Pressure> {
+    PRESSURE.sampled.load(Ordering::Relaxed).then(|| Pressure {
+        memory_percent: f32::from_bits(PRESSURE.memory_percent.load(Ordering::Relaxed)),
+        cpu_percent: f32::from_bits(PRESSURE.cpu_percent.load(Ordering::Relaxed)),
+    })
+}
+
+/// Sample system pressure every `interval` in the background
+pub fn start_sampling(interval: Duration) {
+    tokio::spawn(async move {
+        use sysinfo::{CpuExt, System, SystemExt};
+
+        let mut sys = System::new();
+        let mut ticker = tokio::time::interval(interval);
+        loop {
+            ticker.tick().await;
+            sys.refresh_memory();
+            sys.refresh_cpu();
+            let memory_percent = if sys.total_memory() == 0 {
+                0.0
+            } else {
+                (sys.used_memory() as f64 / sys.total_memory() as f64 * 100.0) as f32
+            };
+            record_pressure(memory_percent, sys.global_cpu_info().cpu_usage());
+        }
+    });
+}
+
+/// How important a request is to keep serving under load
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Priority {
+    /// Never shed: health checks, admin routes and revalidations
+    Essential,
+    /// Data and pages, served as long as the server runs
+    Normal,
+    /// Expensive extras, shed first
+    Low,
+}
+
+/// Priority of a request to `path`, given whether it revalidates a cached
+/// response
+pub fn priority(method: &Method, path: &str, revalidation: bool) -> Priority {
+    if revalidation
+        || matches!(path, "/health" | "/healthz" | "/api/status")
+        || path == "/admin"
+        || path.starts_with("/admin/")
+    {
+        return Priority::Essential;
+    }
+    let analysis = *method == Method::POST
+        && matches!(
+            path,
+            "/api/cross-section" | "/api/trajectories" | "/api/sample" | "/api/jobs"
+        );
+    if analysis || path.starts_with("/data/frames/") {
+        Priority::Low
+    } else {
+        Priority::Normal
+    }
+}
+
+/// Decides which requests to shed and counts them
+#[derive(Debug, Default)]
+pub struct LoadShedder {
+    config: LoadSheddingConfig,
+    shed: AtomicU64,
+    /// Slots of `max_concurrent_requests`, shared by every route
+    slots: Option<Arc<Semaphore>>,
+    /// Requests refused because every slot was taken
+    refused: Arc<AtomicU64>,
+}
+
+impl LoadShedder {
+    pub fn new(config: LoadSheddingConfig) -> Self {
+        let slots = config
+            .max_concurrent_requests
+            .map(|max| Arc::new(Semaphore::new(max)));
+        Self {
+            config,
+            shed: AtomicU64::new(0),
+            slots,
+            refused: Arc::default(),
+        }
+    }
+
+    pub fn config(&self) -> &LoadSheddingConfig {
+        &self.config
+    }
+
+    /// Number of requests shed since startup
+    pub fn shed_count(&self) -> u64 {
+        self.shed.load(Ordering::Relaxed)
+    }
+
+    /// Number of requests refused at the concurrency limit since startup
+    pub fn refused_count(&self) -> u64 {
+        self.refused.load(Ordering::Relaxed)
+    }
+
+    /// Requests in flight under the concurrency limit, if there is one
+    pub fn in_flight(&self) -> Option<usize> {
+        let max = self.config.max_concurrent_requests?;
+        let slots = self.slots.as_ref()?;
+        Some(max - slots.available_permits())
+    }
+
+    /// Wrap `router` in the concurrency limit, if one is configured
+    ///
+    /// A request finding every slot taken is refused at once instead of
+    /// waiting for one. Its slot is freed once the response headers are
+    /// sent, so streamed bodies do not hold it.
+    pub fn limit_concurrency<S>(&self, router: Router<S>) -> Router<S>
+    where
+        S: Clone + Send + Sync + 'static,
+    {
+        let Some(slots) = self.slots.clone() else {
+            return router;
+        };
+        let refused = self.refused.clone();
+        let max = self.config.max_concurrent_requests.unwrap_or_default();
+        router.layer(
+            ServiceBuilder::new()
+                .layer(HandleErrorLayer::new(move |error: BoxError| {
+                    let refused = refused.clone();
+                    async move { concurrency_limit_response(&refused, max, error) }
+                }))
+                .layer(LoadShedLayer::new())
+                .layer(GlobalConcurrencyLimitLayer::with_semaphore(slots)),
+        )
+    }
+
+    /// Why the server counts as overloaded at `pressure`, if it does
+    pub fn overload(&self, pressure: Pressure) -> Option<String> {
+        let over = |name: &str, value: f32, limit: Option<f32>| {
+            limit
+                .filter(|limit| value > *limit)
+                .map(|limit| format!("{} at {:.0}% is above {:.0}%", name, value, limit))
+        };
+        over(
+            "memory usage",
+            pressure.memory_percent,
+            self.config.max_memory_percent,
+        )
+        .or_else(|| {
+            over(
+                "CPU usage",
+                pressure.cpu_percent,
+                self.config.max_cpu_percent,
+            )
+        })
+    }
+}
+
+/// Shed low-priority requests while the system is under pressure
+pub async fn load_shedding_middleware<B>(
+    State(state): State<Arc<AppState>>,
+    request: Request<B>,
+    next: Next<B>,
+) -> Response {
+    let shedder = &state.load_shedder;
+    if !shedder.config.is_enabled() {
+        return next.run(request).await;
+    }
+    let revalidation = request.headers().contains_key(header::IF_NONE_MATCH)
+        || request.headers().contains_key(header::IF_MODIFIED_SINCE);
+    if priority(request.method(), request.uri().path(), revalidation) != Priority::Low {
+        return next.run(request).await;
+    }
+    let Some(reason) = current_pressure().and_then(|pressure| shedder.overload(pressure)) else {
+        return next.run(request).await;
+    };
+
+    let shed = shedder.shed.fetch_add(1, Ordering::Relaxed) + 1;
+    // One warning per hundred shed requests keeps the log readable
+    if shed % 100 == 1 {
+        warn!(
+            shed_total = shed,
+            "Shedding low-priority requests: {}", reason
+        );
+    } else {
+        info!(path = %request.uri().path(), "Shed request: {}", reason);
+    }
+    let mut response = AppError::Overloaded(reason).into_response();
+    response
+        .headers_mut()
+        .insert(header::RETRY_AFTER, HeaderValue::from(RETRY_AFTER_SECS));
+    response
+}
+
+/// The response to a request refused by the concurrency limit
+fn concurrency_limit_response(refused: &AtomicU64, max: usize, error: BoxError) -> Response {
+    if !error.is::<tower::load_shed::error::Overloaded>() {
+        return AppError::ServerError(std::io::Error::other(error.to_string())).into_response();
+    }
+    let refused = refused.fetch_add(1, Ordering::Relaxed) + 1;
+    if refused % 100 == 1 {
+        warn!(
+            refused_total = refused,
+            "Refusing requests beyond {} in flight", max
+        );
+    }
+    let mut response =
+        AppError::Overloaded(format!("more than {} requests are being handled", max))
+            .into_response();
+    response
+        .headers_mut()
+        .insert(header::RETRY_AFTER, HeaderValue::from(RETRY_AFTER_SECS));
+    response
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_priorities() {
+        assert_eq!(
+            priority(&Method::GET, "/health", false),
+            Priority::Essential
+        );
+        assert_eq!(
+            priority(&Method::GET, "/admin/overview", false),
+            Priority::Essential
+        );
+        assert_eq!(
+            priority(&Method::POST, "/api/trajectories", false),
+            Priority::Low
+        );
+        assert_eq!(
+            priority(&Method::GET, "/data/frames/temp", false),
+            Priority::Low
+        );
+        assert_eq!(
+            priority(&Method::GET, "/data/frames/temp", true),
+            Priority::Essential
+        );
+        assert_eq!(
+            priority(&Method::GET, "/proxy/data", false),
+            Priority::Normal
+        );
+        assert_eq!(
+            priority(&Method::GET, "/api/jobs/42", false),
+            Priority::Normal
+        );
+    }
+
+    #[test]
+    fn test_parse_max_concurrent_requests() {
+        assert_eq!(parse_max_concurrent_requests("64"), Ok(64));
+        assert_eq!(
+            parse_max_concurrent_requests(&MAX_CONCURRENT_REQUESTS.to_string()),
+            Ok(MAX_CONCURRENT_REQUESTS)
+        );
+        assert!(parse_max_concurrent_requests("0").is_err());
+        assert!(parse_max_concurrent_requests(&(MAX_CONCURRENT_REQUESTS + 1).to_string()).is_err());
+        assert!(parse_max_concurrent_requests("many").is_err());
+    }
+
+    #[test]
+    fn test_overload_thresholds() {
+        let shedder = LoadShedder::new(LoadSheddingConfig {
+            max_memory_percent: Some(90.0),
+            ..Default::default()
+        });
+        let pressure = |memory_percent, cpu_percent| Pressure {
+            memory_percent,
+            cpu_percent,
+        };
+        assert_eq!(shedder.overload(pressure(80.0, 100.0)), None);
+        assert_eq!(
+            shedder.overload(pressure(93.2, 10.0)).unwrap(),
+            "memory usage at 93% is above 90%"
+        );
+        assert!(LoadShedder::default()
+            .overload(pressure(100.0, 100.0))
+            .is_none());
+    }
+}
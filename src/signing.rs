@@ -0,0 +1,484 @@
+//! Integrity signatures on exported data
+//!
+//! With `--sign-responses`, every successful response carrying data (the
+//! data proxy, Earth files, analyses and job results) is signed, so consumers
+//! can check that what reached them through caches and proxies is what this
+//! server sent. The signature covers the request target and a digest of the
+//! body:
+//!
+//! ```text
+//! {path and query}\n{lowercase hex SHA-256 of the body}
+//! ```
+//!
+//! and is sent as `X-Signature: keyid="<id>", alg="<alg>", sig="<base64>"`
+//! along with `Content-Digest: sha-256=:<base64>:`. With `ed25519`, the
+//! public key to verify it with is served at `/api/public-key`; with
+//! `hmac-sha256`, consumers share the secret instead. Signed bodies are
+//! buffered rather than streamed, and signatures apply to the uncompressed
+//! body.
+
+use axum::{
+    body::{self, Full},
+    extract::{MatchedPath, OriginalUri, State},
+    http::{HeaderValue, Request},
+    middleware::Next,
+    response::{IntoResponse, Response},
+    Json,
+};
+use base64::{engine::general_purpose::STANDARD, Engine};
+use ring::{
+    hmac,
+    rand::SystemRandom,
+    signature::{Ed25519KeyPair, KeyPair},
+};
+use serde_json::json;
+use sha2::{Digest, Sha256};
+use std::{fmt, fs, io, path::Path, str::FromStr, sync::Arc};
+use tracing::{info, warn};
+
+use crate::{
+    error::{AppError, ErrorBody},
+    private_file::write_private,
+    server::AppState,
+};
+
+/// Header carrying the signature of a response
+pub const SIGNATURE_HEADER: &str = "x-signature";
+
+/// Key id of `hmac-sha256` signatures unless one is configured; it is not
+/// derived from the secret, which would let anyone test guesses of it
+pub const DEFAULT_HMAC_KEY_ID: &str = "hmac";
+
+/// Routes whose responses are signed
+const SIGNED_ROUTES: &[&str] = &[
+    "/proxy/data",
+    "/api/cross-section",
+    "/api/trajectories",
+    "/api/sample",
+    "/api/jobs/:id/result",
+    "/data/weather/current/current-wind-surface-level-gfs-1.0.json",
+    "/data/weather/current/current-temp-surface-level-gfs-1.0.json",
+    "/data/weather/current/:file",
+    "/data/oscar/:file",
+    "/data/frames/:variable",
+    "/data/weather/:dataset/*path",
+    "/proxy/:dataset/data",
+];
+
+/// Signature algorithm of `--sign-responses`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum SigningAlgorithm {
+    #[default]
+    None,
+    HmacSha256,
+    Ed25519,
+}
+
+impl FromStr for SigningAlgorithm {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "none" => Ok(SigningAlgorithm::None),
+            "hmac" | "hmac-sha256" => Ok(SigningAlgorithm::HmacSha256),
+            "ed25519" => Ok(SigningAlgorithm::Ed25519),
+            _ => Err(format!(
+                "Invalid signing algorithm: {}. Valid options: none, hmac-sha256, ed25519",
+                s
+            )),
+        }
+    }
+}
+
+impl fmt::Display for SigningAlgorithm {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            SigningAlgorithm::None => "none",
+            SigningAlgorithm::HmacSha256 => "hmac-sha256",
+            SigningAlgorithm::Ed25519 => "ed25519",
+        })
+    }
+}
+
+/// Startup configuration of response signing
+#[derive(Debug, Clone, Default)]
+pub struct SigningConfig {
+    pub algorithm: SigningAlgorithm,
+    /// Shared secret of `hmac-sha256`
+    pub secret: Option<String>,
+    /// PKCS#8 file (PEM or DER) with the `ed25519` key; without one, a key
+    /// is created in the data directory
+    pub key_file: Option<std::path::PathBuf>,
+    /// Key id sent with signatures, instead of [`DEFAULT_HMAC_KEY_ID`] or
+    /// the fingerprint of the Ed25519 public key
+    pub key_id: Option<String>,
+}
+
+enum SigningKey {
+    Hmac(hmac::Key),
+    Ed25519(Ed25519KeyPair),
+}
+
+/// Signs responses with the configured key
+pub struct ResponseSigner {
+    key: SigningKey,
+    key_id: String,
+}
+
+impl fmt::Debug for ResponseSigner {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("ResponseSigner")
+            .field("algorithm", &self.algorithm())
+            .field("key_id", &self.key_id)
+            .finish()
+    }
+}
+
+impl ResponseSigner {
+    /// The signer of `config`, `None` when signing is disabled
+    pub fn from_config(config: &SigningConfig, data_dir: &Path) -> Result<Option<Self>, AppError> {
+        let signer = match config.algorithm {
+            SigningAlgorithm::None => return Ok(None),
+            SigningAlgorithm::HmacSha256 => {
+                let secret = config
+                    .secret
+                    .as_deref()
+                    .filter(|s| !s.is_empty())
+                    .ok_or_else(|| {
+                        AppError::ConfigError("hmac-sha256 signing needs a secret".to_string())
+                    })?;
+                Self::hmac(secret.as_bytes())
+            }
+            SigningAlgorithm::Ed25519 => {
+                let path = match &config.key_file {
+                    Some(path) => path.clone(),
+                    None => data_dir.join("signing-ed25519.key"),
+                };
+                // Only a missing key is created: replacing one that exists
+                // would invalidate the public key clients have pinned
+                let pkcs8 = match fs::read(&path) {
+                    Ok(contents) => pkcs8_der(&contents),
+                    Err(e) if e.kind() == io::ErrorKind::NotFound && config.key_file.is_none() => {
+                        create_key(&path)?
+                    }
+                    Err(e) => {
+                        return Err(AppError::ConfigError(format!(
+                            "Failed to read {}: {}",
+                            path.display(),
+                            e
+                        )))
+                    }
+                };
+                Self::ed25519(&pkcs8)
+                    .map_err(|e| AppError::ConfigError(format!("{}: {}", path.display(), e)))?
+            }
+        };
+        let signer = match &config.key_id {
+            Some(key_id) => signer.with_key_id(parse_key_id(key_id)?),
+            None => signer,
+        };
+        info!(
+            "Signing data responses with {} key {}",
+            signer.algorithm(),
+            signer.key_id
+        );
+        Ok(Some(signer))
+    }
+
+    /// A signer with a shared secret, identified by [`DEFAULT_HMAC_KEY_ID`]
+    pub fn hmac(secret: &[u8]) -> Self {
+        Self {
+            key: SigningKey::Hmac(hmac::Key::new(hmac::HMAC_SHA256, secret)),
+            key_id: DEFAULT_HMAC_KEY_ID.to_string(),
+        }
+    }
+
+    /// A signer with the Ed25519 key of a PKCS#8 document
+    pub fn ed25519(pkcs8: &[u8]) -> Result<Self, AppError> {
+        let key = Ed25519KeyPair::from_pkcs8_maybe_unchecked(pkcs8)
+            .map_err(|e| AppError::ConfigError(format!("Invalid Ed25519 key: {}", e)))?;
+        Ok(Self {
+            key_id: key_id(key.public_key().as_ref()),
+            key: SigningKey::Ed25519(key),
+        })
+    }
+
+    /// The same signer, sending `key_id` with its signatures
+    pub fn with_key_id(mut self, key_id: impl Into<String>) -> Self {
+        self.key_id = key_id.into();
+        self
+    }
+
+    pub fn algorithm(&self) -> SigningAlgorithm {
+        match self.key {
+            SigningKey::Hmac(_) => SigningAlgorithm::HmacSha256,
+            SigningKey::Ed25519(_) => SigningAlgorithm::Ed25519,
+        }
+    }
+
+    /// Identifies the key, so consumers notice when it changes
+    pub fn key_id(&self) -> &str {
+        &self.key_id
+    }
+
+    /// The Ed25519 public key; HMAC has none
+    pub fn public_key(&self) -> Option<&[u8]> {
+        match &self.key {
+            SigningKey::Hmac(_) => None,
+            SigningKey::Ed25519(key) => Some(key.public_key().as_ref()),
+        }
+    }
+
+    /// The `X-Signature` value for `body` served at `target`
+    pub fn sign(&self, target: &str, body: &[u8]) -> String {
+        let message = signed_message(target, body);
+        let signature = match &self.key {
+            SigningKey::Hmac(key) => STANDARD.encode(hmac::sign(key, message.as_bytes())),
+            SigningKey::Ed25519(key) => STANDARD.encode(key.sign(message.as_bytes())),
+        };
+        format!(
+            r#"keyid="{}", alg="{}", sig="{}""#,
+            self.key_id,
+            self.algorithm(),
+            signature
+        )
+    }
+}
+
+/// The string a signature covers
+pub fn signed_message(target: &str, body: &[u8]) -> String {
+    format!("{}\n{}", target, hex(&Sha256::digest(body)))
+}
+
+/// The fingerprint of a public key
+fn key_id(public_key: &[u8]) -> String {
+    hex(&Sha256::digest(public_key)[..8])
+}
+
+/// A configured key id, which goes into a quoted header parameter
+fn parse_key_id(key_id: &str) -> Result<&str, AppError> {
+    if !key_id.is_empty()
+        && key_id
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || "-._:".contains(c))
+    {
+        Ok(key_id)
+    } else {
+        Err(AppError::ConfigError(format!(
+            "Invalid signing key id: {}. Use letters, digits, '-', '.', '_' and ':'",
+            key_id
+        )))
+    }
+}
+
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+/// The DER of a PKCS#8 file, which may be PEM encoded
+fn pkcs8_der(contents: &[u8]) -> Vec<u8> {
+    rustls_pemfile::pkcs8_private_keys(&mut &contents[..])
+        .ok()
+        .and_then(|keys| keys.into_iter().next())
+        .unwrap_or_else(|| contents.to_vec())
+}
+
+/// Generate an Ed25519 key and store it at `path`, readable by the owner only
+fn create_key(path: &Path) -> Result<Vec<u8>, AppError> {
+    let pkcs8 = Ed25519KeyPair::generate_pkcs8(&SystemRandom::new())
+        .map_err(|_| AppError::ConfigError("Failed to generate Ed25519 key".to_string()))?;
+    if let Some(dir) = path.parent() {
+        fs::create_dir_all(dir).map_err(|e| {
+            AppError::StorageError(format!("Failed to create {}: {}", dir.display(), e))
+        })?;
+    }
+    write_private(path, pkcs8.as_ref()).map_err(|e| {
+        AppError::StorageError(format!("Failed to store {}: {}", path.display(), e))
+    })?;
+    warn!("Created Ed25519 signing key {}", path.display());
+    Ok(pkcs8.as_ref().to_vec())
+}
+
+/// Sign successful responses of the data routes
+///
+/// Must be installed with `route_layer` so the matched route is known.
+pub async fn signing_middleware<B>(
+    State(state): State<Arc<AppState>>,
+    request: Request<B>,
+    next: Next<B>,
+) -> Response {
+    let Some(signer) = state.signer.clone() else {
+        return next.run(request).await;
+    };
+    // Routes match with the prefix of a router mounted by an application,
+    // and the signature covers the URL as the client requested it
+    let signed = request
+        .extensions()
+        .get::<MatchedPath>()
+        .is_some_and(|route| SIGNED_ROUTES.iter().any(|r| route.as_str().ends_with(r)));
+    let uri = match request.extensions().get::<OriginalUri>() {
+        Some(OriginalUri(uri)) => uri,
+        None => request.uri(),
+    };
+    let target = uri
+        .path_and_query()
+        .map(|target| target.as_str().to_string())
+        .unwrap_or_default();
+    let response = next.run(request).await;
+    if !signed || !response.status().is_success() {
+        return response;
+    }
+
+    let (mut parts, body) = response.into_parts();
+    let body = match hyper::body::to_bytes(body).await {
+        Ok(body) => body,
+        Err(e) => {
+            return AppError::ProxyError(format!("Failed to read response to sign: {}", e))
+                .into_response()
+        }
+    };
+    let digest = format!(":{}:", STANDARD.encode(Sha256::digest(&body)));
+    for (name, value) in [
+        ("content-digest", format!("sha-256={}", digest)),
+        (SIGNATURE_HEADER, signer.sign(&target, &body)),
+    ] {
+        if let Ok(value) = HeaderValue::from_str(&value) {
+            parts.headers.insert(name, value);
+        }
+    }
+    Response::from_parts(parts, body::boxed(Full::from(body)))
+}
+
+/// Handler for `GET /api/public-key`
+#[utoipa::path(
+    get,
+    path = "/api/public-key",
+    tag = "service",
+    summary = "Public key that verifies signed responses",
+    responses(
+        (status = 200, body = serde_json::Value),
+        (status = 404, description = "Responses are not signed, or signed with a shared secret", body = ErrorBody),
+    )
+)]
+pub async fn public_key(State(state): State<Arc<AppState>>) -> Result<Response, AppError> {
+    let signer = state
+        .signer
+        .as_ref()
+        .ok_or_else(|| AppError::NotFound("Responses are not signed".to_string()))?;
+    let public_key = signer.public_key().ok_or_else(|| {
+        AppError::NotFound("Responses are signed with a shared secret".to_string())
+    })?;
+    Ok(Json(json!({
+        "algorithm": signer.algorithm().to_string(),
+        "key_id": signer.key_id(),
+        "public_key": STANDARD.encode(public_key),
+        "signed_message": "{path and query}\\n{hex SHA-256 of the body}",
+    }))
+    .into_response())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ring::signature::{UnparsedPublicKey, ED25519};
+
+    fn signature(header: &str) -> Vec<u8> {
+        let sig = header.split("sig=\"").nth(1).unwrap().trim_end_matches('"');
+        STANDARD.decode(sig).unwrap()
+    }
+
+    #[test]
+    fn test_ed25519_signature_verifies() {
+        let pkcs8 = Ed25519KeyPair::generate_pkcs8(&SystemRandom::new()).unwrap();
+        let signer = ResponseSigner::ed25519(pkcs8.as_ref()).unwrap();
+
+        let header = signer.sign("/proxy/data?vars=t2m", b"{\"t2m\":[1,2]}");
+        assert!(header.starts_with(&format!(r#"keyid="{}", alg="ed25519""#, signer.key_id())));
+
+        let public_key = UnparsedPublicKey::new(&ED25519, signer.public_key().unwrap());
+        let message = signed_message("/proxy/data?vars=t2m", b"{\"t2m\":[1,2]}");
+        assert!(public_key
+            .verify(message.as_bytes(), &signature(&header))
+            .is_ok());
+        let tampered = signed_message("/proxy/data?vars=t2m", b"{\"t2m\":[1,3]}");
+        assert!(public_key
+            .verify(tampered.as_bytes(), &signature(&header))
+            .is_err());
+    }
+
+    #[test]
+    fn test_hmac_signature_verifies() {
+        let signer = ResponseSigner::hmac(b"shared secret");
+        assert!(signer.public_key().is_none());
+        // Nothing about the secret is published
+        assert_eq!(signer.key_id(), DEFAULT_HMAC_KEY_ID);
+
+        let header = signer.sign("/api/sample", b"[]");
+        let key = hmac::Key::new(hmac::HMAC_SHA256, b"shared secret");
+        let message = signed_message("/api/sample", b"[]");
+        assert!(hmac::verify(&key, message.as_bytes(), &signature(&header)).is_ok());
+    }
+
+    #[test]
+    fn test_signing_config() {
+        let dir = std::env::temp_dir();
+        let disabled = SigningConfig::default();
+        assert!(ResponseSigner::from_config(&disabled, &dir)
+            .unwrap()
+            .is_none());
+
+        let no_secret = SigningConfig {
+            algorithm: SigningAlgorithm::HmacSha256,
+            ..SigningConfig::default()
+        };
+        assert!(ResponseSigner::from_config(&no_secret, &dir).is_err());
+
+        let named = SigningConfig {
+            algorithm: SigningAlgorithm::HmacSha256,
+            secret: Some("shared secret".to_string()),
+            key_id: Some("2026-10".to_string()),
+            ..SigningConfig::default()
+        };
+        let signer = ResponseSigner::from_config(&named, &dir).unwrap().unwrap();
+        assert!(signer
+            .sign("/api/sample", b"[]")
+            .starts_with(r#"keyid="2026-10""#));
+        let quoted = SigningConfig {
+            key_id: Some(r#"a", alg="none"#.to_string()),
+            ..named
+        };
+        assert!(ResponseSigner::from_config(&quoted, &dir).is_err());
+        assert_eq!("hmac".parse(), Ok(SigningAlgorithm::HmacSha256));
+        assert!("rsa".parse::<SigningAlgorithm>().is_err());
+    }
+
+    #[test]
+    fn test_generated_key_is_private_and_kept() {
+        let dir = std::env::temp_dir().join(format!("rossby-vis-signing-{}", uuid::Uuid::new_v4()));
+        let config = SigningConfig {
+            algorithm: SigningAlgorithm::Ed25519,
+            ..SigningConfig::default()
+        };
+
+        let signer = ResponseSigner::from_config(&config, &dir).unwrap().unwrap();
+        let path = dir.join("signing-ed25519.key");
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            assert_eq!(
+                fs::metadata(&path).unwrap().permissions().mode() & 0o777,
+                0o600
+            );
+        }
+        let reloaded = ResponseSigner::from_config(&config, &dir).unwrap().unwrap();
+        assert_eq!(reloaded.key_id(), signer.key_id());
+
+        // A key that cannot be read is reported rather than replaced
+        fs::remove_file(&path).unwrap();
+        fs::create_dir(&path).unwrap();
+        assert!(ResponseSigner::from_config(&config, &dir).is_err());
+        assert!(path.is_dir());
+
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
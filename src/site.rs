@@ -0,0 +1,322 @@
+//! Per-site values substituted into `index.html`
+//!
+//! `index.html` may contain `{{name}}` placeholders that the `index` handler
+//! fills in at serve time, so one embedded bundle serves every deployment
+//! without a rebuild. Unknown placeholders are left as they are. The web app
+//! manifest served at `/manifest.json` is generated from the same values.
+//!
+//! The pages link everything relative to their `<base href>`, which is set
+//! to the path the viewer is mounted at: `--base-path`, behind any prefix a
+//! reverse proxy stripped and named in `X-Forwarded-Prefix`.
+
+use axum::http::HeaderMap;
+use serde_json::{json, Value};
+
+use crate::error::AppError;
+
+/// Lighter page served instead of `index.html` to phones, when enabled
+pub const MOBILE_INDEX: &str = "index.mobile.html";
+
+/// User agents treated as phones and tablets, as in the frontend's `µ.isMobile`
+const MOBILE_AGENTS: [&str; 8] = [
+    "android",
+    "blackberry",
+    "iemobile",
+    "ipad",
+    "iphone",
+    "ipod",
+    "opera mini",
+    "webos",
+];
+
+/// Whether a request comes from a mobile device, by the `Sec-CH-UA-Mobile`
+/// client hint when the browser sends one and the `User-Agent` otherwise
+pub fn is_mobile(headers: &HeaderMap) -> bool {
+    if let Some(hint) = headers
+        .get("sec-ch-ua-mobile")
+        .and_then(|value| value.to_str().ok())
+    {
+        return hint.trim() == "?1";
+    }
+    headers
+        .get(axum::http::header::USER_AGENT)
+        .and_then(|value| value.to_str().ok())
+        .map(|agent| {
+            let agent = agent.to_ascii_lowercase();
+            MOBILE_AGENTS.iter().any(|mobile| agent.contains(mobile))
+        })
+        .unwrap_or(false)
+}
+
+/// Values for the `index.html` placeholders
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SiteConfig {
+    /// `{{api_base}}`: URL prefix the frontend puts before `/proxy/...`
+    /// requests; empty for the serving origin
+    pub api_base: String,
+    /// `{{title}}`: page title
+    pub title: String,
+    /// `{{contact}}`: contact address, rendered as a mailto link
+    pub contact_email: Option<String>,
+    /// `{{analytics}}`: HTML inserted verbatim, e.g. an analytics script.
+    /// Nothing is inserted unless a snippet is configured.
+    pub analytics_snippet: Option<String>,
+}
+
+impl Default for SiteConfig {
+    fn default() -> Self {
+        Self {
+            api_base: String::new(),
+            title: "earth :: an animated map of global wind and weather".to_string(),
+            contact_email: None,
+            analytics_snippet: None,
+        }
+    }
+}
+
+impl SiteConfig {
+    /// Substitute the placeholders in `template`
+    pub fn render(&self, template: &str) -> String {
+        let mut output = String::with_capacity(template.len());
+        let mut rest = template;
+
+        // Substituted values are never scanned again, so they cannot inject
+        // further placeholders
+        while let Some(start) = rest.find("{{") {
+            output.push_str(&rest[..start]);
+            rest = &rest[start..];
+            let Some(end) = rest.find("}}") else {
+                break;
+            };
+            match self.value(rest[2..end].trim()) {
+                Some(value) => output.push_str(&value),
+                None => output.push_str(&rest[..end + 2]),
+            }
+            rest = &rest[end + 2..];
+        }
+
+        output.push_str(rest);
+        output
+    }
+
+    /// Web app manifest that lets browsers install the viewer as a PWA
+    pub fn web_manifest(&self) -> Value {
+        json!({
+            "name": self.title,
+            "short_name": "earth",
+            "description": "an animated map of global wind and weather",
+            // Relative to the manifest, so they follow the base path
+            "start_url": "./",
+            "scope": "./",
+            "display": "standalone",
+            "background_color": "#000000",
+            "theme_color": "#000000",
+            "icons": [
+                {"src": "iphone-icon.png", "sizes": "120x120", "type": "image/png"},
+                {"src": "ipad-icon.png", "sizes": "152x152", "type": "image/png"},
+            ],
+        })
+    }
+
+    fn value(&self, name: &str) -> Option<String> {
+        match name {
+            "api_base" => Some(escape_html(self.api_base.trim_end_matches('/'))),
+            "title" => Some(escape_html(&self.title)),
+            "contact" => Some(match &self.contact_email {
+                Some(email) => {
+                    let email = escape_html(email);
+                    format!(
+                        "<p id=\"contact\">contact: <a href=\"mailto:{}\">{}</a></p>",
+                        email, email
+                    )
+                }
+                None => String::new(),
+            }),
+            "analytics" => Some(self.analytics_snippet.clone().unwrap_or_default()),
+            _ => None,
+        }
+    }
+}
+
+/// `path` as a base path: empty for the root, otherwise starting with and
+/// not ending in `/`, e.g. `/rossby-vis`
+pub fn parse_base_path(path: &str) -> Result<String, AppError> {
+    let path = path.trim().trim_end_matches('/');
+    let valid = path.is_empty()
+        || (path.starts_with('/')
+            && !path.contains("//")
+            && path
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || "/-._~%".contains(c)));
+    if !valid {
+        return Err(AppError::ConfigError(format!(
+            "Invalid base path '{}'; expected a URL path such as /rossby-vis",
+            path
+        )));
+    }
+    Ok(path.to_string())
+}
+
+/// `<base href>` of a page served at `base_path`: the prefix named in
+/// `X-Forwarded-Prefix`, if any and valid, then `base_path`, then `/`
+pub fn base_href(base_path: &str, headers: &HeaderMap) -> String {
+    let forwarded = headers
+        .get("x-forwarded-prefix")
+        .and_then(|value| value.to_str().ok())
+        .and_then(|prefix| parse_base_path(prefix).ok())
+        .unwrap_or_default();
+    format!("{}{}/", forwarded, base_path)
+}
+
+/// `html` with the `href` of its `<base>` tag set to `href`
+pub fn set_base_href(html: &str, href: &str) -> String {
+    let Some(tag) = html.find("<base ") else {
+        return html.to_string();
+    };
+    let Some(start) = html[tag..]
+        .find("href=\"")
+        .map(|start| tag + start + "href=\"".len())
+        .filter(|start| !html[tag..*start].contains('>'))
+    else {
+        return html.to_string();
+    };
+    let Some(len) = html[start..].find('"') else {
+        return html.to_string();
+    };
+    format!(
+        "{}{}{}",
+        &html[..start],
+        escape_html(href),
+        &html[start + len..]
+    )
+}
+
+/// Escape text for use in HTML content and quoted attributes
+fn escape_html(value: &str) -> String {
+    let mut escaped = String::with_capacity(value.len());
+    for c in value.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&#39;"),
+            _ => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_render_defaults() {
+        let rendered = SiteConfig::default().render(
+            "<title>{{title}}</title><meta content=\"{{ api_base }}\"/>{{contact}}{{analytics}}",
+        );
+        assert_eq!(
+            rendered,
+            "<title>earth :: an animated map of global wind and weather</title><meta content=\"\"/>"
+        );
+    }
+
+    #[test]
+    fn test_render_escapes_values() {
+        let site = SiteConfig {
+            api_base: "https://rossby.example.com/vis/".to_string(),
+            title: "Winds & \"Waves\"".to_string(),
+            contact_email: Some("ops@example.com".to_string()),
+            analytics_snippet: Some("<script src=\"/stats.js\"></script>".to_string()),
+        };
+        assert_eq!(
+            site.render("{{api_base}}"),
+            "https://rossby.example.com/vis"
+        );
+        assert_eq!(site.render("{{title}}"), "Winds &amp; &quot;Waves&quot;");
+        assert_eq!(
+            site.render("{{contact}}"),
+            "<p id=\"contact\">contact: <a href=\"mailto:ops@example.com\">ops@example.com</a></p>"
+        );
+        assert_eq!(
+            site.render("{{analytics}}"),
+            "<script src=\"/stats.js\"></script>"
+        );
+    }
+
+    #[test]
+    fn test_is_mobile() {
+        let headers = |pairs: &[(&'static str, &'static str)]| {
+            let mut headers = HeaderMap::new();
+            for (name, value) in pairs {
+                headers.insert(*name, value.parse().unwrap());
+            }
+            headers
+        };
+        let iphone = "Mozilla/5.0 (iPhone; CPU iPhone OS 17_0 like Mac OS X) Mobile/15E148";
+
+        assert!(is_mobile(&headers(&[("user-agent", iphone)])));
+        assert!(!is_mobile(&headers(&[(
+            "user-agent",
+            "Mozilla/5.0 (X11; Linux x86_64) Firefox/118.0"
+        )])));
+        assert!(!is_mobile(&HeaderMap::new()));
+        // The client hint wins over the user agent
+        assert!(!is_mobile(&headers(&[
+            ("user-agent", iphone),
+            ("sec-ch-ua-mobile", "?0")
+        ])));
+        assert!(is_mobile(&headers(&[("sec-ch-ua-mobile", "?1")])));
+    }
+
+    #[test]
+    fn test_web_manifest() {
+        let site = SiteConfig {
+            title: "Rossby Winds".to_string(),
+            ..Default::default()
+        };
+        let manifest = site.web_manifest();
+        assert_eq!(manifest["name"], "Rossby Winds");
+        assert_eq!(manifest["start_url"], "./");
+        assert_eq!(manifest["display"], "standalone");
+        assert_eq!(manifest["icons"].as_array().unwrap().len(), 2);
+    }
+
+    #[test]
+    fn test_base_path_and_href() {
+        assert_eq!(parse_base_path("").unwrap(), "");
+        assert_eq!(parse_base_path("/").unwrap(), "");
+        assert_eq!(parse_base_path("/rossby-vis/").unwrap(), "/rossby-vis");
+        assert!(parse_base_path("rossby-vis").is_err());
+        assert!(parse_base_path("/a//b").is_err());
+        assert!(parse_base_path("/\"><script>").is_err());
+
+        let mut headers = HeaderMap::new();
+        assert_eq!(base_href("", &headers), "/");
+        assert_eq!(base_href("/vis", &headers), "/vis/");
+        headers.insert("x-forwarded-prefix", "/tools/".parse().unwrap());
+        assert_eq!(base_href("/vis", &headers), "/tools/vis/");
+        headers.insert("x-forwarded-prefix", "/\"evil".parse().unwrap());
+        assert_eq!(base_href("", &headers), "/");
+
+        assert_eq!(
+            set_base_href("<head><base href=\"/\"/><link href=\"a.css\"/>", "/vis/"),
+            "<head><base href=\"/vis/\"/><link href=\"a.css\"/>"
+        );
+        assert_eq!(set_base_href("<p>no base</p>", "/vis/"), "<p>no base</p>");
+    }
+
+    #[test]
+    fn test_unknown_and_injected_placeholders_are_kept() {
+        let site = SiteConfig {
+            title: "{{analytics}}".to_string(),
+            analytics_snippet: Some("tracking".to_string()),
+            ..Default::default()
+        };
+        assert_eq!(
+            site.render("{{title}} {{unknown}} {{"),
+            "{{analytics}} {{unknown}} {{"
+        );
+    }
+}
@@ -0,0 +1,392 @@
+//! Index-based slicing on `/proxy/data`
+//!
+//! The backend selects by coordinate value (`time=700464`,
+//! `latitude_range=-10,10`). For strided or partial reads by position,
+//! `/proxy/data` also takes
+//!
+//! - `{dim}_index=N` for a single position, e.g. `time_index=5`
+//! - `{dim}_indices=START:STOP[:STEP]` for a range, Python style with an
+//!   exclusive stop and optional bounds, e.g. `lat_indices=0:360:2`
+//!
+//! where `{dim}` is a dimension of the dataset, or `lat` / `lon` for its
+//! latitude and longitude dimensions. Indices are validated against the
+//! metadata and mapped to the coordinate values at those positions, which
+//! the backend selects as usual; steps are then applied to its response.
+
+use serde_json::Value;
+
+use crate::{
+    error::AppError,
+    grid::{coordinate_values, is_latitude_dimension, is_longitude_dimension},
+};
+
+const INDEX_SUFFIX: &str = "_index";
+const INDICES_SUFFIX: &str = "_indices";
+
+/// Positions selected along one dimension
+#[derive(Debug, Clone, PartialEq)]
+struct Selection {
+    dimension: String,
+    step: usize,
+    /// Backend parameter selecting the coordinates of the positions
+    backend_param: String,
+}
+
+/// The index selections of a data request
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct IndexSlicing {
+    selections: Vec<Selection>,
+}
+
+/// Whether `name` is an index-slicing query parameter
+pub fn is_slicing_param(name: &str) -> bool {
+    name.ends_with(INDEX_SUFFIX) || name.ends_with(INDICES_SUFFIX)
+}
+
+/// The slicing parameter names valid for the dimensions of `metadata`
+pub fn slicing_param_names(metadata: &Value) -> Vec<String> {
+    let mut names = Vec::new();
+    let Some(dimensions) = metadata.get("dimensions").and_then(|d| d.as_object()) else {
+        return names;
+    };
+    for name in dimensions.keys() {
+        let mut aliases = vec![name.as_str()];
+        if is_latitude_dimension(name) {
+            aliases.push("lat");
+        } else if is_longitude_dimension(name) {
+            aliases.push("lon");
+        }
+        for alias in aliases {
+            names.push(format!("{}{}", alias, INDEX_SUFFIX));
+            names.push(format!("{}{}", alias, INDICES_SUFFIX));
+        }
+    }
+    names.sort();
+    names.dedup();
+    names
+}
+
+/// The dimension of `metadata` that `name` refers to
+fn resolve_dimension(metadata: &Value, name: &str) -> Option<(String, usize)> {
+    let dimensions = metadata.get("dimensions")?.as_object()?;
+    let size = |info: &Value| info.get("size").and_then(|s| s.as_u64());
+    if let Some(size) = dimensions.get(name).and_then(size) {
+        return Some((name.to_string(), size as usize));
+    }
+    let matches: fn(&str) -> bool = match name {
+        "lat" => is_latitude_dimension,
+        "lon" => is_longitude_dimension,
+        _ => return None,
+    };
+    dimensions
+        .iter()
+        .find(|(dimension, _)| matches(dimension))
+        .and_then(|(dimension, info)| Some((dimension.clone(), size(info)? as usize)))
+}
+
+/// Parse `START:STOP[:STEP]` for a dimension of `size` positions
+fn parse_range(param: &str, value: &str, size: usize) -> Result<(usize, usize, usize), AppError> {
+    let invalid = |reason: &str| {
+        Err(AppError::RequestError(format!(
+            "Invalid {} '{}': {}",
+            param, value, reason
+        )))
+    };
+    let parts: Vec<&str> = value.split(':').map(str::trim).collect();
+    if !(2..=3).contains(&parts.len()) {
+        return invalid("expected START:STOP or START:STOP:STEP");
+    }
+    let bound = |part: &str, default: usize| -> Option<usize> {
+        if part.is_empty() {
+            Some(default)
+        } else {
+            part.parse().ok()
+        }
+    };
+    let (Some(start), Some(stop), Some(step)) = (
+        bound(parts[0], 0),
+        bound(parts[1], size),
+        bound(parts.get(2).copied().unwrap_or(""), 1),
+    ) else {
+        return invalid("bounds and step must be non-negative integers");
+    };
+    if step == 0 {
+        return invalid("step must be at least 1");
+    }
+    if stop > size {
+        return invalid(&format!("stop is past the {} positions", size));
+    }
+    if start >= stop {
+        return invalid("the range is empty");
+    }
+    Ok((start, stop, step))
+}
+
+impl IndexSlicing {
+    /// Parse and validate the slicing parameters among `params`, ignoring
+    /// the others
+    pub fn parse<'a>(
+        metadata: &Value,
+        params: impl IntoIterator<Item = (&'a String, &'a String)>,
+    ) -> Result<Self, AppError> {
+        let mut selections: Vec<Selection> = Vec::new();
+        for (param, value) in params {
+            let (name, single) = if let Some(name) = param.strip_suffix(INDICES_SUFFIX) {
+                (name, false)
+            } else if let Some(name) = param.strip_suffix(INDEX_SUFFIX) {
+                (name, true)
+            } else {
+                continue;
+            };
+            let (dimension, size) = resolve_dimension(metadata, name).ok_or_else(|| {
+                AppError::RequestError(format!("Unknown dimension '{}' in {}", name, param))
+            })?;
+
+            let (start, stop, step) = if single {
+                let index: usize = value.trim().parse().map_err(|_| {
+                    AppError::RequestError(format!(
+                        "Invalid {} '{}': expected a non-negative integer",
+                        param, value
+                    ))
+                })?;
+                if index >= size {
+                    return Err(AppError::RequestError(format!(
+                        "Invalid {} '{}': {} has {} positions",
+                        param, value, dimension, size
+                    )));
+                }
+                (index, index + 1, 1)
+            } else {
+                parse_range(param, value, size)?
+            };
+
+            if selections.iter().any(|s| s.dimension == dimension) {
+                return Err(AppError::RequestError(format!(
+                    "Dimension {} is sliced more than once",
+                    dimension
+                )));
+            }
+
+            let coordinates = coordinate_values(metadata, &dimension)
+                .filter(|values| values.len() == size)
+                .ok_or_else(|| {
+                    AppError::RequestError(format!(
+                        "Dimension {} has no coordinates to slice by index",
+                        dimension
+                    ))
+                })?;
+            let (first, last) = (coordinates[start], coordinates[stop - 1]);
+            let backend_param = if start + 1 == stop {
+                format!("{}={}", dimension, first)
+            } else {
+                format!(
+                    "{}_range={},{}",
+                    dimension,
+                    first.min(last),
+                    first.max(last)
+                )
+            };
+
+            selections.push(Selection {
+                dimension,
+                step,
+                backend_param,
+            });
+        }
+        Ok(Self { selections })
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.selections.is_empty()
+    }
+
+    /// Names of the sliced dimensions
+    pub fn dimensions(&self) -> impl Iterator<Item = &str> {
+        self.selections.iter().map(|s| s.dimension.as_str())
+    }
+
+    /// Backend query parameters selecting the sliced coordinates
+    pub fn backend_params(&self) -> impl Iterator<Item = &str> {
+        self.selections.iter().map(|s| s.backend_param.as_str())
+    }
+
+    /// Whether the backend response must be strided here
+    pub fn is_strided(&self) -> bool {
+        self.selections.iter().any(|s| s.step > 1)
+    }
+
+    /// Keep every `step`th position of the sliced dimensions of a `/data`
+    /// response in the legacy schema
+    pub fn apply_steps(&self, response: &mut Value) -> Result<(), AppError> {
+        let malformed =
+            || AppError::ProxyError("Backend data response has no shape to slice".to_string());
+        let dimensions: Vec<String> = response
+            .pointer("/metadata/dimensions")
+            .and_then(|d| d.as_array())
+            .ok_or_else(malformed)?
+            .iter()
+            .filter_map(|d| d.as_str().map(String::from))
+            .collect();
+        let shape: Vec<usize> = response
+            .pointer("/metadata/shape")
+            .and_then(|s| s.as_array())
+            .ok_or_else(malformed)?
+            .iter()
+            .filter_map(|s| s.as_u64().map(|s| s as usize))
+            .collect();
+        if shape.len() != dimensions.len() {
+            return Err(malformed());
+        }
+
+        let steps: Vec<usize> = dimensions
+            .iter()
+            .map(|dimension| {
+                self.selections
+                    .iter()
+                    .find(|s| &s.dimension == dimension)
+                    .map_or(1, |s| s.step)
+            })
+            .collect();
+        if steps.iter().all(|&step| step == 1) {
+            return Ok(());
+        }
+
+        // Row-major strides of the response array
+        let mut strides = vec![1; shape.len()];
+        for axis in (0..shape.len().saturating_sub(1)).rev() {
+            strides[axis] = strides[axis + 1] * shape[axis + 1];
+        }
+        let total: usize = shape.iter().product();
+        let keep = |index: usize| {
+            (0..shape.len())
+                .all(|axis| (index / strides[axis] % shape[axis]).is_multiple_of(steps[axis]))
+        };
+
+        if let Some(data) = response.get_mut("data").and_then(|d| d.as_object_mut()) {
+            for (name, values) in data.iter_mut() {
+                let Some(array) = values.as_array_mut() else {
+                    continue;
+                };
+                if array.len() != total {
+                    return Err(AppError::ProxyError(format!(
+                        "Backend returned {} values of {} for shape {:?}",
+                        array.len(),
+                        name,
+                        shape
+                    )));
+                }
+                let mut index = 0;
+                array.retain(|_| {
+                    let kept = keep(index);
+                    index += 1;
+                    kept
+                });
+            }
+        }
+
+        let strided: Vec<usize> = shape
+            .iter()
+            .zip(&steps)
+            .map(|(size, step)| size.div_ceil(*step))
+            .collect();
+        response["metadata"]["shape"] = serde_json::json!(strided);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+    use std::collections::HashMap;
+
+    fn metadata() -> Value {
+        json!({
+            "coordinates": {
+                "latitude": [90.0, 45.0, 0.0, -45.0, -90.0],
+                "longitude": [0.0, 90.0, 180.0, 270.0],
+                "time": [700464.0, 700465.0, 700466.0]
+            },
+            "dimensions": {
+                "latitude": {"size": 5},
+                "longitude": {"size": 4},
+                "time": {"size": 3},
+                "bnds": {"size": 2}
+            }
+        })
+    }
+
+    fn parse(pairs: &[(&str, &str)]) -> Result<IndexSlicing, AppError> {
+        let params: HashMap<String, String> = pairs
+            .iter()
+            .map(|(k, v)| (k.to_string(), v.to_string()))
+            .collect();
+        IndexSlicing::parse(&metadata(), &params)
+    }
+
+    #[test]
+    fn test_backend_params() {
+        let slicing = parse(&[("time_index", "1")]).unwrap();
+        assert_eq!(
+            slicing.backend_params().collect::<Vec<_>>(),
+            ["time=700465"]
+        );
+        assert!(!slicing.is_strided());
+
+        let slicing = parse(&[("lat_indices", "1:4:2")]).unwrap();
+        assert_eq!(
+            slicing.backend_params().collect::<Vec<_>>(),
+            ["latitude_range=-45,45"]
+        );
+        assert!(slicing.is_strided());
+
+        let slicing = parse(&[("longitude_indices", "::3")]).unwrap();
+        assert_eq!(
+            slicing.backend_params().collect::<Vec<_>>(),
+            ["longitude_range=0,270"]
+        );
+
+        assert!(parse(&[("vars", "u10")]).unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_invalid_slicing() {
+        for pairs in [
+            [("time_index", "3")],
+            [("time_index", "-1")],
+            [("lat_indices", "0:6")],
+            [("lat_indices", "3:3")],
+            [("lat_indices", "0:4:0")],
+            [("lat_indices", "1")],
+            [("depth_index", "0")],
+            [("bnds_index", "0")],
+        ] {
+            assert!(
+                matches!(parse(&pairs), Err(AppError::RequestError(_))),
+                "{:?}",
+                pairs
+            );
+        }
+        assert!(parse(&[("lat_index", "0"), ("latitude_indices", "1:3")]).is_err());
+    }
+
+    #[test]
+    fn test_apply_steps() {
+        let slicing = parse(&[("lat_indices", "0:3:2"), ("lon_indices", "0:4:2")]).unwrap();
+        let mut response = json!({
+            "metadata": {"shape": [1, 3, 4], "dimensions": ["time", "latitude", "longitude"]},
+            "data": {"t2m": (0..12).collect::<Vec<_>>()}
+        });
+        slicing.apply_steps(&mut response).unwrap();
+        assert_eq!(response["metadata"]["shape"], json!([1, 2, 2]));
+        assert_eq!(response["data"]["t2m"], json!([0, 2, 8, 10]));
+    }
+
+    #[test]
+    fn test_slicing_param_names() {
+        let names = slicing_param_names(&metadata());
+        assert!(names.contains(&"lat_indices".to_string()));
+        assert!(names.contains(&"longitude_index".to_string()));
+        assert!(names.contains(&"time_index".to_string()));
+    }
+}
@@ -0,0 +1,224 @@
+//! StatsD/DogStatsD metrics emission
+//!
+//! Request and backend counters and timers are pushed over UDP to a StatsD
+//! agent as they happen, and the periodic system metrics are sent as gauges.
+//! With the DogStatsD flavor every metric also carries the configured tags
+//! plus its own (method, status, ...); plain StatsD has no tags, so those are
+//! dropped. Sending never blocks or fails a request: a full socket buffer or
+//! an unreachable agent just loses the metric.
+
+use std::{
+    io,
+    net::{ToSocketAddrs, UdpSocket},
+    str::FromStr,
+    sync::OnceLock,
+};
+
+static CLIENT: OnceLock<StatsdClient> = OnceLock::new();
+
+/// Wire format of the metrics
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StatsdFlavor {
+    /// Plain StatsD, without tags
+    Statsd,
+    /// Datadog's DogStatsD, with `|#key:value` tags
+    Dogstatsd,
+}
+
+impl FromStr for StatsdFlavor {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "statsd" => Ok(StatsdFlavor::Statsd),
+            "dogstatsd" | "datadog" => Ok(StatsdFlavor::Dogstatsd),
+            _ => Err(format!(
+                "Invalid StatsD flavor: {}. Valid options: statsd, dogstatsd",
+                s
+            )),
+        }
+    }
+}
+
+/// StatsD output settings
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct StatsdConfig {
+    /// Agent address, e.g. `127.0.0.1:8125`
+    pub address: String,
+    /// Prepended to every metric name, separated by a dot
+    pub prefix: String,
+    pub flavor: StatsdFlavor,
+    /// Tags added to every metric, e.g. `env:production`
+    pub tags: Vec<String>,
+}
+
+impl StatsdConfig {
+    /// Plain StatsD to `address`, with names prefixed by `rossby_vis`
+    pub fn new(address: &str) -> Self {
+        let address = if address.contains(':') {
+            address.to_string()
+        } else {
+            format!("{}:8125", address)
+        };
+        Self {
+            address,
+            prefix: "rossby_vis".to_string(),
+            flavor: StatsdFlavor::Statsd,
+            tags: Vec::new(),
+        }
+    }
+}
+
+/// Parse comma-separated `key:value` tags
+pub fn parse_tags(s: &str) -> Result<Vec<String>, String> {
+    s.split(',')
+        .map(str::trim)
+        .filter(|tag| !tag.is_empty())
+        .map(|tag| {
+            if tag.contains(['|', '#', '\n']) {
+                Err(format!("Invalid StatsD tag: {}", tag))
+            } else {
+                Ok(tag.to_string())
+            }
+        })
+        .collect()
+}
+
+/// UDP client for a StatsD agent
+#[derive(Debug)]
+pub struct StatsdClient {
+    config: StatsdConfig,
+    socket: UdpSocket,
+}
+
+impl StatsdClient {
+    /// Resolve the agent address and open a non-blocking socket to it
+    pub fn connect(config: StatsdConfig) -> io::Result<Self> {
+        let address = config.address.to_socket_addrs()?.next().ok_or_else(|| {
+            io::Error::new(
+                io::ErrorKind::NotFound,
+                format!("no address for {}", config.address),
+            )
+        })?;
+        let socket = UdpSocket::bind(if address.is_ipv4() {
+            "0.0.0.0:0"
+        } else {
+            "[::]:0"
+        })?;
+        socket.connect(address)?;
+        socket.set_nonblocking(true)?;
+        Ok(Self { config, socket })
+    }
+
+    /// Format one metric line
+    fn format(&self, name: &str, value: &str, kind: &str, tags: &[(&str, &str)]) -> String {
+        let mut line = if self.config.prefix.is_empty() {
+            format!("{}:{}|{}", name, value, kind)
+        } else {
+            format!("{}.{}:{}|{}", self.config.prefix, name, value, kind)
+        };
+
+        if self.config.flavor == StatsdFlavor::Dogstatsd
+            && !(self.config.tags.is_empty() && tags.is_empty())
+        {
+            let tags = self
+                .config
+                .tags
+                .iter()
+                .cloned()
+                .chain(tags.iter().map(|(key, value)| format!("{}:{}", key, value)))
+                .collect::<Vec<_>>();
+            line.push_str("|#");
+            line.push_str(&tags.join(","));
+        }
+        line
+    }
+
+    fn send(&self, name: &str, value: &str, kind: &str, tags: &[(&str, &str)]) {
+        let _ = self
+            .socket
+            .send(self.format(name, value, kind, tags).as_bytes());
+    }
+}
+
+/// Start sending metrics to the configured agent
+pub fn init(config: StatsdConfig) -> io::Result<()> {
+    let client = StatsdClient::connect(config)?;
+    CLIENT.set(client).map_err(|_| {
+        io::Error::new(
+            io::ErrorKind::AlreadyExists,
+            "StatsD is already initialized",
+        )
+    })
+}
+
+/// Add `value` to a counter
+pub fn count(name: &str, value: u64, tags: &[(&str, &str)]) {
+    if let Some(client) = CLIENT.get() {
+        client.send(name, &value.to_string(), "c", tags);
+    }
+}
+
+/// Record a duration in milliseconds
+pub fn timing(name: &str, duration_ms: u64, tags: &[(&str, &str)]) {
+    if let Some(client) = CLIENT.get() {
+        client.send(name, &duration_ms.to_string(), "ms", tags);
+    }
+}
+
+/// Set a gauge
+pub fn gauge(name: &str, value: f64, tags: &[(&str, &str)]) {
+    if let Some(client) = CLIENT.get() {
+        client.send(name, &value.to_string(), "g", tags);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn client(flavor: StatsdFlavor) -> (StatsdClient, UdpSocket) {
+        let agent = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let mut config = StatsdConfig::new(&agent.local_addr().unwrap().to_string());
+        config.flavor = flavor;
+        config.tags = vec!["env:test".to_string()];
+        (StatsdClient::connect(config).unwrap(), agent)
+    }
+
+    #[test]
+    fn test_format() {
+        let (statsd, _agent) = client(StatsdFlavor::Statsd);
+        assert_eq!(
+            statsd.format("http.requests", "1", "c", &[("status", "200")]),
+            "rossby_vis.http.requests:1|c"
+        );
+
+        let (dogstatsd, _agent) = client(StatsdFlavor::Dogstatsd);
+        assert_eq!(
+            dogstatsd.format("http.request.duration", "12", "ms", &[("status", "200")]),
+            "rossby_vis.http.request.duration:12|ms|#env:test,status:200"
+        );
+    }
+
+    #[test]
+    fn test_send() {
+        let (statsd, agent) = client(StatsdFlavor::Dogstatsd);
+        statsd.send("backend.bytes", "512", "c", &[]);
+
+        let mut buf = [0u8; 256];
+        let len = agent.recv(&mut buf).unwrap();
+        assert_eq!(&buf[..len], b"rossby_vis.backend.bytes:512|c|#env:test");
+    }
+
+    #[test]
+    fn test_parse_config() {
+        assert_eq!(StatsdConfig::new("localhost").address, "localhost:8125");
+        assert_eq!("DataDog".parse(), Ok(StatsdFlavor::Dogstatsd));
+        assert!("graphite".parse::<StatsdFlavor>().is_err());
+        assert_eq!(
+            parse_tags("env:prod, region:eu,"),
+            Ok(vec!["env:prod".to_string(), "region:eu".to_string()])
+        );
+        assert!(parse_tags("bad|tag").is_err());
+    }
+}
@@ -0,0 +1,378 @@
+//! Optional SQLite persistence
+//!
+//! Built with the `sqlite` feature and started with `--database <file>`, the
+//! server keeps state that must survive restarts in one SQLite database, so
+//! features share a single store instead of each inventing a file format.
+//! It holds the API keys issued through `/admin/api-keys` (see
+//! [`crate::auth`]) and the keys of the disk cache files (see
+//! [`crate::cache`]). The schema is versioned with SQLite's `user_version` and brought
+//! up to date by [`MIGRATIONS`] when the database is opened; a database
+//! written by a newer release is refused rather than modified.
+//!
+//! Simple per-user documents live in named [`Collection`]s of JSON records.
+//! Features needing their own tables add a migration and query them through
+//! [`Store::call`].
+
+use chrono::{DateTime, Utc};
+use rusqlite::{params, Connection, OptionalExtension};
+use serde::Serialize;
+use serde_json::Value;
+use std::{
+    path::Path,
+    sync::{Arc, Mutex},
+};
+use tracing::info;
+
+use crate::error::AppError;
+
+/// A step of the schema, applied in one transaction
+#[derive(Debug)]
+pub struct Migration {
+    /// Schema version after the migration
+    pub version: u32,
+    pub description: &'static str,
+    pub sql: &'static str,
+}
+
+/// The schema history, in version order
+pub const MIGRATIONS: &[Migration] = &[
+    Migration {
+        version: 1,
+        description: "JSON records grouped in collections",
+        sql: "CREATE TABLE records (
+                  collection TEXT NOT NULL,
+                  owner TEXT NOT NULL,
+                  key TEXT NOT NULL,
+                  value TEXT NOT NULL,
+                  created_at TEXT NOT NULL,
+                  updated_at TEXT NOT NULL,
+                  PRIMARY KEY (collection, owner, key)
+              );",
+    },
+    Migration {
+        version: 2,
+        description: "Keys of the disk cache files",
+        sql: "CREATE TABLE disk_cache (
+                  name TEXT PRIMARY KEY,
+                  key TEXT NOT NULL
+              );",
+    },
+];
+
+/// Owner of records shared by all users
+pub const SHARED: &str = "";
+
+fn storage_error(e: impl std::fmt::Display) -> AppError {
+    AppError::StorageError(e.to_string())
+}
+
+/// Handle to the database, cheap to clone
+#[derive(Clone)]
+pub struct Store {
+    connection: Arc<Mutex<Connection>>,
+}
+
+impl std::fmt::Debug for Store {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let connection = self.connection.lock().unwrap();
+        f.debug_struct("Store")
+            .field("path", &connection.path())
+            .finish()
+    }
+}
+
+impl Store {
+    /// Open or create the database at `path` and migrate it to the current
+    /// schema
+    pub fn open(path: &Path) -> Result<Self, AppError> {
+        let connection = Connection::open(path).map_err(|e| {
+            AppError::ConfigError(format!("Cannot open database {}: {}", path.display(), e))
+        })?;
+        // Readers do not block the writer, and concurrent writers wait
+        connection
+            .pragma_update(None, "journal_mode", "WAL")
+            .and_then(|_| connection.busy_timeout(std::time::Duration::from_secs(5)))
+            .map_err(storage_error)?;
+        Self::new(connection)
+    }
+
+    /// A database in memory, for tests and throwaway deployments
+    pub fn open_in_memory() -> Result<Self, AppError> {
+        Self::new(Connection::open_in_memory().map_err(storage_error)?)
+    }
+
+    fn new(mut connection: Connection) -> Result<Self, AppError> {
+        migrate(&mut connection, MIGRATIONS)?;
+        Ok(Self {
+            connection: Arc::new(Mutex::new(connection)),
+        })
+    }
+
+    /// Version of the database schema
+    pub fn schema_version(&self) -> Result<u32, AppError> {
+        schema_version(&self.connection.lock().unwrap())
+    }
+
+    /// Run `f` with the connection on the blocking thread pool
+    pub async fn call<T, F>(&self, f: F) -> Result<T, AppError>
+    where
+        T: Send + 'static,
+        F: FnOnce(&mut Connection) -> rusqlite::Result<T> + Send + 'static,
+    {
+        let connection = self.connection.clone();
+        tokio::task::spawn_blocking(move || f(&mut connection.lock().unwrap()))
+            .await
+            .map_err(storage_error)?
+            .map_err(storage_error)
+    }
+
+    /// Run `f` with the connection on the current thread, for startup code
+    /// that cannot wait for [`Store::call`]
+    pub fn call_blocking<T>(
+        &self,
+        f: impl FnOnce(&mut Connection) -> rusqlite::Result<T>,
+    ) -> Result<T, AppError> {
+        f(&mut self.connection.lock().unwrap()).map_err(storage_error)
+    }
+
+    /// The collection of records called `name`, e.g. `bookmarks`
+    pub fn collection(&self, name: &str) -> Collection {
+        Collection {
+            store: self.clone(),
+            name: name.to_string(),
+        }
+    }
+}
+
+fn schema_version(connection: &Connection) -> Result<u32, AppError> {
+    connection
+        .pragma_query_value(None, "user_version", |row| row.get(0))
+        .map_err(storage_error)
+}
+
+/// Apply the migrations newer than the database's schema version
+fn migrate(connection: &mut Connection, migrations: &[Migration]) -> Result<(), AppError> {
+    let current = schema_version(connection)?;
+    let latest = migrations.last().map_or(0, |m| m.version);
+    if current > latest {
+        return Err(AppError::ConfigError(format!(
+            "Database schema version {} is newer than this release supports ({})",
+            current, latest
+        )));
+    }
+
+    for migration in migrations.iter().filter(|m| m.version > current) {
+        let transaction = connection.transaction().map_err(storage_error)?;
+        transaction
+            .execute_batch(migration.sql)
+            .and_then(|_| transaction.pragma_update(None, "user_version", migration.version))
+            .and_then(|_| transaction.commit())
+            .map_err(|e| {
+                AppError::StorageError(format!(
+                    "Migration {} ({}) failed: {}",
+                    migration.version, migration.description, e
+                ))
+            })?;
+        info!(
+            version = migration.version,
+            "Migrated database: {}", migration.description
+        );
+    }
+    Ok(())
+}
+
+/// A stored JSON document
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Record {
+    pub key: String,
+    pub value: Value,
+    pub created_at: DateTime<Utc>,
+    pub updated_at: DateTime<Utc>,
+}
+
+impl Record {
+    fn from_row(row: &rusqlite::Row<'_>) -> rusqlite::Result<Self> {
+        let text = |index: usize| row.get::<_, String>(index);
+        let time = |index: usize| {
+            text(index).and_then(|value| {
+                DateTime::parse_from_rfc3339(&value)
+                    .map(|time| time.with_timezone(&Utc))
+                    .map_err(|e| {
+                        rusqlite::Error::FromSqlConversionFailure(
+                            index,
+                            rusqlite::types::Type::Text,
+                            Box::new(e),
+                        )
+                    })
+            })
+        };
+        Ok(Self {
+            key: text(0)?,
+            value: serde_json::from_str(&text(1)?).map_err(|e| {
+                rusqlite::Error::FromSqlConversionFailure(
+                    1,
+                    rusqlite::types::Type::Text,
+                    Box::new(e),
+                )
+            })?,
+            created_at: time(2)?,
+            updated_at: time(3)?,
+        })
+    }
+}
+
+/// Records of one kind, keyed by owner and key
+#[derive(Debug, Clone)]
+pub struct Collection {
+    store: Store,
+    name: String,
+}
+
+impl Collection {
+    /// The record `key` of `owner`
+    pub async fn get(&self, owner: &str, key: &str) -> Result<Option<Record>, AppError> {
+        let (name, owner, key) = (self.name.clone(), owner.to_string(), key.to_string());
+        self.store
+            .call(move |connection| {
+                connection
+                    .query_row(
+                        "SELECT key, value, created_at, updated_at FROM records
+                         WHERE collection = ?1 AND owner = ?2 AND key = ?3",
+                        params![name, owner, key],
+                        Record::from_row,
+                    )
+                    .optional()
+            })
+            .await
+    }
+
+    /// Create or replace the record `key` of `owner`
+    pub async fn put(&self, owner: &str, key: &str, value: &Value) -> Result<Record, AppError> {
+        let (name, owner, key) = (self.name.clone(), owner.to_string(), key.to_string());
+        let value = value.to_string();
+        let now = Utc::now().to_rfc3339();
+        self.store
+            .call(move |connection| {
+                connection.query_row(
+                    "INSERT INTO records (collection, owner, key, value, created_at, updated_at)
+                     VALUES (?1, ?2, ?3, ?4, ?5, ?5)
+                     ON CONFLICT (collection, owner, key)
+                     DO UPDATE SET value = excluded.value, updated_at = excluded.updated_at
+                     RETURNING key, value, created_at, updated_at",
+                    params![name, owner, key, value, now],
+                    Record::from_row,
+                )
+            })
+            .await
+    }
+
+    /// Delete the record `key` of `owner`, returning whether it existed
+    pub async fn delete(&self, owner: &str, key: &str) -> Result<bool, AppError> {
+        let (name, owner, key) = (self.name.clone(), owner.to_string(), key.to_string());
+        self.store
+            .call(move |connection| {
+                connection
+                    .execute(
+                        "DELETE FROM records WHERE collection = ?1 AND owner = ?2 AND key = ?3",
+                        params![name, owner, key],
+                    )
+                    .map(|deleted| deleted > 0)
+            })
+            .await
+    }
+
+    /// All records of `owner`, by key
+    pub async fn list(&self, owner: &str) -> Result<Vec<Record>, AppError> {
+        let (name, owner) = (self.name.clone(), owner.to_string());
+        self.store
+            .call(move |connection| {
+                let mut statement = connection.prepare(
+                    "SELECT key, value, created_at, updated_at FROM records
+                     WHERE collection = ?1 AND owner = ?2 ORDER BY key",
+                )?;
+                let records = statement
+                    .query_map(params![name, owner], Record::from_row)?
+                    .collect();
+                records
+            })
+            .await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn test_migrations_are_ordered() {
+        assert!(MIGRATIONS.windows(2).all(|w| w[0].version < w[1].version));
+        let store = Store::open_in_memory().unwrap();
+        assert_eq!(
+            store.schema_version().unwrap(),
+            MIGRATIONS.last().unwrap().version
+        );
+    }
+
+    #[test]
+    fn test_newer_schema_is_refused() {
+        let mut connection = Connection::open_in_memory().unwrap();
+        migrate(&mut connection, MIGRATIONS).unwrap();
+        connection.pragma_update(None, "user_version", 99).unwrap();
+        assert!(matches!(
+            migrate(&mut connection, MIGRATIONS),
+            Err(AppError::ConfigError(_))
+        ));
+    }
+
+    #[test]
+    fn test_failed_migration_is_rolled_back() {
+        let mut connection = Connection::open_in_memory().unwrap();
+        let broken = [Migration {
+            version: 1,
+            description: "broken",
+            sql: "CREATE TABLE a (x INTEGER); CREATE TABLE nonsense",
+        }];
+        assert!(migrate(&mut connection, &broken).is_err());
+        assert_eq!(schema_version(&connection).unwrap(), 0);
+        assert!(connection.prepare("SELECT x FROM a").is_err());
+    }
+
+    #[tokio::test]
+    async fn test_collection_records() {
+        let store = Store::open_in_memory().unwrap();
+        let bookmarks = store.collection("bookmarks");
+
+        let created = bookmarks
+            .put("alice", "storm", &json!({"lat": 45.0}))
+            .await
+            .unwrap();
+        let updated = bookmarks
+            .put("alice", "storm", &json!({"lat": 46.0}))
+            .await
+            .unwrap();
+        assert_eq!(updated.created_at, created.created_at);
+        assert_eq!(updated.value, json!({"lat": 46.0}));
+        bookmarks.put("alice", "arctic", &json!({})).await.unwrap();
+        bookmarks.put(SHARED, "home", &json!({})).await.unwrap();
+        store
+            .collection("annotations")
+            .put("alice", "storm", &json!("note"))
+            .await
+            .unwrap();
+
+        let keys: Vec<String> = bookmarks
+            .list("alice")
+            .await
+            .unwrap()
+            .into_iter()
+            .map(|record| record.key)
+            .collect();
+        assert_eq!(keys, ["arctic", "storm"]);
+        assert!(bookmarks.get("bob", "storm").await.unwrap().is_none());
+
+        assert!(bookmarks.delete("alice", "storm").await.unwrap());
+        assert!(!bookmarks.delete("alice", "storm").await.unwrap());
+        assert!(bookmarks.get("alice", "storm").await.unwrap().is_none());
+    }
+}
@@ -0,0 +1,366 @@
+//! Syslog (RFC 5424) output for the logging system
+//!
+//! Each log event is sent as one syslog message to the local daemon over
+//! `/dev/log`, or to a remote collector over UDP or TCP. The event itself is
+//! formatted by the normal `fmt` layer, so JSON logs stay JSON inside the
+//! syslog message.
+
+use std::{
+    io::{self, Write},
+    net::{TcpStream, ToSocketAddrs, UdpSocket},
+    str::FromStr,
+    sync::Mutex,
+};
+use sysinfo::{System, SystemExt};
+use tracing::{Level, Metadata};
+use tracing_subscriber::fmt::MakeWriter;
+
+/// Default path of the local syslog socket
+pub const DEFAULT_SOCKET: &str = "/dev/log";
+
+/// Where syslog messages are sent
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum SyslogTarget {
+    /// Local syslog daemon via a Unix datagram socket
+    Local(String),
+    /// Remote collector over UDP, e.g. `logs.example.com:514`
+    Udp(String),
+    /// Remote collector over TCP with octet-counting framing (RFC 6587)
+    Tcp(String),
+}
+
+impl FromStr for SyslogTarget {
+    type Err = String;
+
+    /// Parse `local`, `unix:///path`, `udp://host:port` or `tcp://host:port`
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || {
+            format!(
+                "Invalid syslog target: {}. Valid options: local, unix:///path, udp://host:port, tcp://host:port",
+                s
+            )
+        };
+
+        if s.eq_ignore_ascii_case("local") {
+            return Ok(SyslogTarget::Local(DEFAULT_SOCKET.to_string()));
+        }
+        let (scheme, rest) = s.split_once("://").ok_or_else(invalid)?;
+        if rest.is_empty() {
+            return Err(invalid());
+        }
+        match scheme.to_lowercase().as_str() {
+            "unix" => Ok(SyslogTarget::Local(rest.to_string())),
+            "udp" => Ok(SyslogTarget::Udp(with_default_port(rest))),
+            "tcp" => Ok(SyslogTarget::Tcp(with_default_port(rest))),
+            _ => Err(invalid()),
+        }
+    }
+}
+
+fn with_default_port(address: &str) -> String {
+    let has_port = match address.rsplit_once(':') {
+        // A bracketed IPv6 address has a port only after the closing bracket
+        Some((host, port)) => !port.contains(']') && (!host.contains(':') || host.ends_with(']')),
+        None => false,
+    };
+    if has_port {
+        address.to_string()
+    } else {
+        format!("{}:514", address)
+    }
+}
+
+/// Syslog output settings
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SyslogConfig {
+    pub target: SyslogTarget,
+    /// Syslog facility code; 1 is `user`, 3 is `daemon`, 16–23 are `local0`–`local7`
+    pub facility: u8,
+    /// APP-NAME field of each message
+    pub app_name: String,
+}
+
+impl SyslogConfig {
+    /// Settings for a target with the `daemon` facility
+    pub fn new(target: SyslogTarget, app_name: &str) -> Self {
+        Self {
+            target,
+            facility: 3,
+            app_name: app_name.to_string(),
+        }
+    }
+}
+
+/// Parse a facility name such as `daemon` or `local3`
+pub fn parse_facility(s: &str) -> Result<u8, String> {
+    const NAMES: [(&str, u8); 9] = [
+        ("kern", 0),
+        ("user", 1),
+        ("mail", 2),
+        ("daemon", 3),
+        ("auth", 4),
+        ("syslog", 5),
+        ("lpr", 6),
+        ("news", 7),
+        ("cron", 9),
+    ];
+
+    let name = s.to_lowercase();
+    if let Some((_, code)) = NAMES.iter().find(|(n, _)| *n == name) {
+        return Ok(*code);
+    }
+    name.strip_prefix("local")
+        .and_then(|n| n.parse::<u8>().ok())
+        .filter(|n| *n <= 7)
+        .map(|n| 16 + n)
+        .ok_or_else(|| {
+            format!(
+                "Invalid syslog facility: {}. Valid options: user, daemon, local0-local7, ...",
+                s
+            )
+        })
+}
+
+enum Connection {
+    #[cfg(unix)]
+    Local(std::os::unix::net::UnixDatagram),
+    Udp(UdpSocket),
+    Tcp(Option<TcpStream>),
+}
+
+/// `MakeWriter` that turns each formatted event into one syslog message
+pub struct SyslogWriter {
+    config: SyslogConfig,
+    hostname: String,
+    procid: u32,
+    connection: Mutex<Connection>,
+}
+
+impl SyslogWriter {
+    /// Open the connection to the syslog target
+    pub fn connect(config: SyslogConfig) -> io::Result<Self> {
+        let connection = match &config.target {
+            #[cfg(unix)]
+            SyslogTarget::Local(path) => {
+                let socket = std::os::unix::net::UnixDatagram::unbound()?;
+                socket.connect(path)?;
+                Connection::Local(socket)
+            }
+            #[cfg(not(unix))]
+            SyslogTarget::Local(_) => {
+                return Err(io::Error::new(
+                    io::ErrorKind::Unsupported,
+                    "local syslog requires a Unix socket",
+                ))
+            }
+            SyslogTarget::Udp(address) => {
+                let address = resolve(address)?;
+                let bind = if address.is_ipv4() {
+                    "0.0.0.0:0"
+                } else {
+                    "[::]:0"
+                };
+                let socket = UdpSocket::bind(bind)?;
+                socket.connect(address)?;
+                Connection::Udp(socket)
+            }
+            SyslogTarget::Tcp(address) => Connection::Tcp(Some(TcpStream::connect(address)?)),
+        };
+
+        let hostname = System::new()
+            .host_name()
+            .filter(|h| !h.is_empty() && h.is_ascii() && !h.contains(' '))
+            .unwrap_or_else(|| "-".to_string());
+
+        Ok(Self {
+            config,
+            hostname,
+            procid: std::process::id(),
+            connection: Mutex::new(connection),
+        })
+    }
+
+    /// Format one RFC 5424 message
+    fn format_message(&self, severity: u8, message: &str) -> String {
+        let priority = u16::from(self.config.facility) * 8 + u16::from(severity);
+        format!(
+            "<{}>1 {} {} {} {} - - {}",
+            priority,
+            chrono::Utc::now().to_rfc3339_opts(chrono::SecondsFormat::Micros, true),
+            self.hostname,
+            self.config.app_name,
+            self.procid,
+            message.trim_end()
+        )
+    }
+
+    fn send(&self, severity: u8, message: &str) {
+        if message.trim().is_empty() {
+            return;
+        }
+        let message = self.format_message(severity, message);
+        let mut connection = self.connection.lock().unwrap_or_else(|e| e.into_inner());
+
+        // Delivery failures cannot be logged without recursing, so report
+        // them on stderr and carry on
+        let result = match &mut *connection {
+            #[cfg(unix)]
+            Connection::Local(socket) => socket.send(message.as_bytes()).map(|_| ()),
+            Connection::Udp(socket) => socket.send(message.as_bytes()).map(|_| ()),
+            Connection::Tcp(stream) => send_tcp(stream, &self.config.target, &message),
+        };
+        if let Err(e) = result {
+            eprintln!("Failed to send log message to syslog: {}", e);
+        }
+    }
+}
+
+/// Send over TCP with octet-counting framing, reconnecting once on failure
+fn send_tcp(
+    stream: &mut Option<TcpStream>,
+    target: &SyslogTarget,
+    message: &str,
+) -> io::Result<()> {
+    let frame = format!("{} {}", message.len(), message);
+    if let Some(connected) = stream {
+        if connected.write_all(frame.as_bytes()).is_ok() {
+            return Ok(());
+        }
+    }
+
+    let SyslogTarget::Tcp(address) = target else {
+        unreachable!("TCP connection for a non-TCP target");
+    };
+    *stream = None;
+    let mut reconnected = TcpStream::connect(address)?;
+    reconnected.write_all(frame.as_bytes())?;
+    *stream = Some(reconnected);
+    Ok(())
+}
+
+fn resolve(address: &str) -> io::Result<std::net::SocketAddr> {
+    address.to_socket_addrs()?.next().ok_or_else(|| {
+        io::Error::new(
+            io::ErrorKind::NotFound,
+            format!("no address for {}", address),
+        )
+    })
+}
+
+/// Syslog severity for a tracing level
+fn severity(level: &Level) -> u8 {
+    match *level {
+        Level::ERROR => 3,
+        Level::WARN => 4,
+        Level::INFO => 6,
+        Level::DEBUG | Level::TRACE => 7,
+    }
+}
+
+/// Buffer for one event, sent when the formatter is done with it
+pub struct SyslogEvent<'a> {
+    writer: &'a SyslogWriter,
+    severity: u8,
+    buffer: Vec<u8>,
+}
+
+impl Write for SyslogEvent<'_> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.buffer.extend_from_slice(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+impl Drop for SyslogEvent<'_> {
+    fn drop(&mut self) {
+        self.writer
+            .send(self.severity, &String::from_utf8_lossy(&self.buffer));
+    }
+}
+
+impl<'a> MakeWriter<'a> for SyslogWriter {
+    type Writer = SyslogEvent<'a>;
+
+    fn make_writer(&'a self) -> Self::Writer {
+        SyslogEvent {
+            writer: self,
+            severity: severity(&Level::INFO),
+            buffer: Vec::new(),
+        }
+    }
+
+    fn make_writer_for(&'a self, meta: &Metadata<'_>) -> Self::Writer {
+        SyslogEvent {
+            writer: self,
+            severity: severity(meta.level()),
+            buffer: Vec::new(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_target() {
+        assert_eq!(
+            "local".parse(),
+            Ok(SyslogTarget::Local(DEFAULT_SOCKET.to_string()))
+        );
+        assert_eq!(
+            "unix:///var/run/syslog".parse(),
+            Ok(SyslogTarget::Local("/var/run/syslog".to_string()))
+        );
+        assert_eq!(
+            "udp://logs.example.com".parse(),
+            Ok(SyslogTarget::Udp("logs.example.com:514".to_string()))
+        );
+        assert_eq!(
+            "tcp://[::1]:6514".parse(),
+            Ok(SyslogTarget::Tcp("[::1]:6514".to_string()))
+        );
+        assert_eq!(
+            "udp://[::1]".parse(),
+            Ok(SyslogTarget::Udp("[::1]:514".to_string()))
+        );
+        assert!("logs.example.com:514".parse::<SyslogTarget>().is_err());
+        assert!("http://logs".parse::<SyslogTarget>().is_err());
+    }
+
+    #[test]
+    fn test_parse_facility() {
+        assert_eq!(parse_facility("daemon"), Ok(3));
+        assert_eq!(parse_facility("LOCAL7"), Ok(23));
+        assert!(parse_facility("local8").is_err());
+        assert!(parse_facility("nonsense").is_err());
+    }
+
+    #[test]
+    fn test_udp_message_format() {
+        let collector = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let target = SyslogTarget::Udp(collector.local_addr().unwrap().to_string());
+        let writer = SyslogWriter::connect(SyslogConfig::new(target, "rossby-vis")).unwrap();
+
+        {
+            let mut event = writer.make_writer();
+            event.severity = severity(&Level::WARN);
+            event.write_all(b"backend slow\n").unwrap();
+        }
+
+        let mut buf = [0u8; 1024];
+        let len = collector.recv(&mut buf).unwrap();
+        let message = std::str::from_utf8(&buf[..len]).unwrap();
+
+        // daemon (3) * 8 + warning (4)
+        assert!(message.starts_with("<28>1 "), "{}", message);
+        assert!(message.ends_with(&format!(
+            " rossby-vis {} - - backend slow",
+            std::process::id()
+        )));
+    }
+}
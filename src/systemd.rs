@@ -0,0 +1,242 @@
+//! systemd service integration
+//!
+//! With `--systemd`, the server follows the protocol of `Type=notify` units:
+//! it sends `READY=1` to `$NOTIFY_SOCKET` once every listener is bound,
+//! pings the watchdog when the unit sets `WatchdogSec=`, and sends
+//! `STOPPING=1` once [`crate::ServerHandle::shutdown`] is called, which the
+//! binary does on SIGTERM. Sockets passed by a `.socket` unit through
+//! `LISTEN_FDS` are taken by [`take_listen_fds`] before the runtime starts
+//! and served instead of binding the configured addresses; a socket takes
+//! the `tls` and `admin` options of the listener configured with the same
+//! address, or of the first one.
+//!
+//! Without the environment variables systemd sets, all of this does nothing,
+//! so the flag is harmless when the server is started by hand.
+
+use crate::listen::ListenAddress;
+use std::{future::Future, io, net::TcpListener, time::Duration};
+use tracing::{debug, warn};
+
+/// The first file descriptor passed by socket activation
+#[cfg(unix)]
+const LISTEN_FDS_START: i32 = 3;
+
+/// Send `state`, e.g. `READY=1`, to the service manager
+///
+/// Returns whether it was sent, which it is not outside a notify unit.
+pub fn notify(state: &str) -> io::Result<bool> {
+    let Some(path) = std::env::var_os("NOTIFY_SOCKET") else {
+        return Ok(false);
+    };
+    send(&path, state)?;
+    Ok(true)
+}
+
+#[cfg(unix)]
+fn send(path: &std::ffi::OsStr, state: &str) -> io::Result<()> {
+    use std::os::unix::{ffi::OsStrExt, net::UnixDatagram};
+
+    let socket = UnixDatagram::unbound()?;
+    match path.as_bytes().strip_prefix(b"@") {
+        #[cfg(target_os = "linux")]
+        Some(name) => {
+            use std::os::linux::net::SocketAddrExt;
+            let addr = std::os::unix::net::SocketAddr::from_abstract_name(name)?;
+            socket.send_to_addr(state.as_bytes(), &addr)?;
+        }
+        _ => {
+            socket.send_to(state.as_bytes(), path)?;
+        }
+    }
+    Ok(())
+}
+
+#[cfg(not(unix))]
+fn send(_path: &std::ffi::OsStr, _state: &str) -> io::Result<()> {
+    Err(io::Error::new(
+        io::ErrorKind::Unsupported,
+        "sd_notify needs Unix sockets",
+    ))
+}
+
+/// Send `state`, logging rather than failing when it cannot be sent
+fn notify_or_warn(state: &str) {
+    if let Err(e) = notify(state) {
+        warn!("Cannot notify systemd of {}: {}", state, e);
+    }
+}
+
+/// Tell the service manager the server is ready to accept connections
+pub fn ready() {
+    notify_or_warn(&format!("READY=1\nMAINPID={}", std::process::id()));
+}
+
+/// How often the watchdog expects a ping, from `WATCHDOG_USEC`
+pub fn watchdog_interval() -> Option<Duration> {
+    parse_watchdog(
+        std::env::var("WATCHDOG_USEC").ok().as_deref(),
+        std::env::var("WATCHDOG_PID").ok().as_deref(),
+        std::process::id(),
+    )
+}
+
+/// The watchdog interval, unless it is meant for another process
+fn parse_watchdog(usec: Option<&str>, pid: Option<&str>, own_pid: u32) -> Option<Duration> {
+    if pid.is_some_and(|pid| pid.parse() != Ok(own_pid)) {
+        return None;
+    }
+    match usec?.parse() {
+        Ok(0) | Err(_) => None,
+        Ok(usec) => Some(Duration::from_micros(usec)),
+    }
+}
+
+/// Ping the watchdog until `shutdown` resolves, then send `STOPPING=1`
+///
+/// Pings are sent at half the watchdog interval, as systemd recommends.
+pub async fn supervise(shutdown: impl Future<Output = ()>) {
+    tokio::pin!(shutdown);
+    if let Some(interval) = watchdog_interval() {
+        debug!("Pinging the systemd watchdog every {:?}", interval / 2);
+        let mut ticks = tokio::time::interval(interval / 2);
+        loop {
+            tokio::select! {
+                _ = ticks.tick() => notify_or_warn("WATCHDOG=1"),
+                _ = &mut shutdown => break,
+            }
+        }
+    } else {
+        shutdown.await;
+    }
+    notify_or_warn("STOPPING=1");
+}
+
+/// How many sockets were passed to this process, from `LISTEN_FDS`
+fn parse_listen_fds(pid: Option<&str>, fds: Option<&str>, own_pid: u32) -> usize {
+    if pid.and_then(|pid| pid.parse().ok()) != Some(own_pid) {
+        return 0;
+    }
+    fds.and_then(|fds| fds.parse().ok()).unwrap_or(0)
+}
+
+/// Take the sockets passed by socket activation, returning how many
+///
+/// The variables are removed, so the sockets are taken once and not passed
+/// on to child processes. Changing the environment is only sound while no
+/// other thread may read it, so call this at the start of `main`, before
+/// the async runtime is built.
+pub fn take_listen_fds() -> usize {
+    let count = parse_listen_fds(
+        std::env::var("LISTEN_PID").ok().as_deref(),
+        std::env::var("LISTEN_FDS").ok().as_deref(),
+        std::process::id(),
+    );
+    for name in ["LISTEN_PID", "LISTEN_FDS", "LISTEN_FDNAMES"] {
+        std::env::remove_var(name);
+    }
+    count
+}
+
+/// The `count` sockets taken by [`take_listen_fds`]
+#[cfg(unix)]
+pub fn listen_fds(count: usize) -> io::Result<Vec<TcpListener>> {
+    use std::os::unix::io::FromRawFd;
+
+    (0..count as i32)
+        .map(|offset| {
+            // SAFETY: systemd passes the sockets as descriptors 3 and up,
+            // owned by this process alone once take_listen_fds removed the
+            // variables
+            let socket = unsafe { TcpListener::from_raw_fd(LISTEN_FDS_START + offset) };
+            socket.local_addr().map_err(|e| {
+                io::Error::new(
+                    e.kind(),
+                    format!(
+                        "Socket {} passed by systemd is not a TCP listener: {}",
+                        LISTEN_FDS_START + offset,
+                        e
+                    ),
+                )
+            })?;
+            Ok(socket)
+        })
+        .collect()
+}
+
+#[cfg(not(unix))]
+pub fn listen_fds(_count: usize) -> io::Result<Vec<TcpListener>> {
+    Ok(Vec::new())
+}
+
+/// Pair each passed socket with the configured listener of its address, or
+/// with the options of the first one
+pub fn match_listeners(
+    sockets: Vec<TcpListener>,
+    configured: &[ListenAddress],
+) -> io::Result<Vec<(ListenAddress, TcpListener)>> {
+    sockets
+        .into_iter()
+        .map(|socket| {
+            let addr = socket.local_addr()?;
+            let listener = configured
+                .iter()
+                .find(|listener| listener.addr == addr)
+                .or(configured.first())
+                .copied()
+                .unwrap_or_else(|| ListenAddress::new(addr));
+            Ok((ListenAddress { addr, ..listener }, socket))
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_watchdog() {
+        assert_eq!(
+            parse_watchdog(Some("30000000"), None, 42),
+            Some(Duration::from_secs(30))
+        );
+        assert_eq!(
+            parse_watchdog(Some("500000"), Some("42"), 42),
+            Some(Duration::from_millis(500))
+        );
+        assert_eq!(parse_watchdog(Some("500000"), Some("7"), 42), None);
+        assert_eq!(parse_watchdog(Some("0"), None, 42), None);
+        assert_eq!(parse_watchdog(Some("soon"), None, 42), None);
+        assert_eq!(parse_watchdog(None, None, 42), None);
+    }
+
+    #[test]
+    fn test_parse_listen_fds() {
+        assert_eq!(parse_listen_fds(Some("42"), Some("2"), 42), 2);
+        assert_eq!(parse_listen_fds(Some("7"), Some("2"), 42), 0);
+        assert_eq!(parse_listen_fds(None, Some("2"), 42), 0);
+        assert_eq!(parse_listen_fds(Some("42"), None, 42), 0);
+        assert_eq!(parse_listen_fds(Some("42"), Some("many"), 42), 0);
+    }
+
+    #[test]
+    fn test_match_listeners() {
+        let public = TcpListener::bind("127.0.0.1:0").unwrap();
+        let admin = TcpListener::bind("127.0.0.1:0").unwrap();
+        let other = TcpListener::bind("127.0.0.1:0").unwrap();
+        let public_addr = public.local_addr().unwrap();
+        let admin_addr = admin.local_addr().unwrap();
+        let other_addr = other.local_addr().unwrap();
+        let configured: Vec<ListenAddress> = vec![
+            format!("{},tls", public_addr).parse().unwrap(),
+            format!("{},admin", admin_addr).parse().unwrap(),
+        ];
+
+        let matched = match_listeners(vec![public, admin, other], &configured).unwrap();
+
+        assert_eq!(matched[0].0, configured[0]);
+        assert_eq!(matched[1].0, configured[1]);
+        // Unknown addresses take the options of the first listener
+        assert_eq!(matched[2].0.addr, other_addr);
+        assert!(matched[2].0.tls && !matched[2].0.admin);
+    }
+}
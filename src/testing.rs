@@ -0,0 +1,357 @@
+//! A fake Rossby backend for tests
+//!
+//! Built with the `testing` feature. [`MockBackend`] serves `/metadata` and
+//! `/data` like a Rossby server would, over a configurable grid, so tests of
+//! this crate, and of applications embedding its router, can exercise the
+//! real HTTP client path without a live backend:
+//!
+//! ```no_run
+//! # async fn example() {
+//! use rossby_vis::testing::MockBackend;
+//!
+//! let backend = MockBackend::regular(181, 360)
+//!     .with_latency(std::time::Duration::from_millis(50))
+//!     .start()
+//!     .await;
+//! let config = rossby_vis::ServerConfig::new(0, backend.url().to_string());
+//! # }
+//! ```
+//!
+//! Every variable covers `time × latitude × longitude`, with the values
+//! `1, 2, 3, ...` in row-major order over the requested timesteps.
+
+use axum::{
+    extract::{Query, State},
+    http::StatusCode,
+    response::{IntoResponse, Json, Response},
+    routing::get,
+    Router,
+};
+use serde_json::{json, Map, Value};
+use std::{
+    collections::HashMap,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc,
+    },
+    time::Duration,
+};
+use tokio::net::TcpListener;
+
+/// A variable served by the mock backend
+#[derive(Debug, Clone, PartialEq)]
+pub struct MockVariable {
+    pub name: String,
+    pub long_name: String,
+    pub units: String,
+}
+
+impl MockVariable {
+    pub fn new(name: &str, long_name: &str, units: &str) -> Self {
+        Self {
+            name: name.to_string(),
+            long_name: long_name.to_string(),
+            units: units.to_string(),
+        }
+    }
+}
+
+/// Configuration of a fake Rossby backend
+#[derive(Debug, Clone, PartialEq)]
+pub struct MockBackend {
+    /// Latitudes, north to south
+    pub latitudes: Vec<f64>,
+    pub longitudes: Vec<f64>,
+    /// Time values in hours since 1900-01-01
+    pub times: Vec<f64>,
+    pub variables: Vec<MockVariable>,
+    /// Delay before each response
+    pub latency: Duration,
+}
+
+impl Default for MockBackend {
+    /// A 3×3 grid near the North Pole with two timesteps of 10 m wind
+    fn default() -> Self {
+        Self {
+            latitudes: vec![90.0, 89.75, 89.5],
+            longitudes: vec![0.0, 0.25, 0.5],
+            times: vec![700464.0, 700465.0],
+            variables: vec![
+                MockVariable::new("u10", "10 metre U wind component", "m s**-1"),
+                MockVariable::new("v10", "10 metre V wind component", "m s**-1"),
+            ],
+            latency: Duration::ZERO,
+        }
+    }
+}
+
+impl MockBackend {
+    /// A global grid of `lat_count` latitudes from 90 to -90 and `lon_count`
+    /// longitudes from 0 eastwards
+    pub fn regular(lat_count: usize, lon_count: usize) -> Self {
+        let lat_step = 180.0 / lat_count.saturating_sub(1).max(1) as f64;
+        let lon_step = 360.0 / lon_count.max(1) as f64;
+        Self {
+            latitudes: (0..lat_count).map(|i| 90.0 - i as f64 * lat_step).collect(),
+            longitudes: (0..lon_count).map(|i| i as f64 * lon_step).collect(),
+            ..Self::default()
+        }
+    }
+
+    /// Serve `times` instead of the default timesteps
+    pub fn with_times(mut self, times: Vec<f64>) -> Self {
+        self.times = times;
+        self
+    }
+
+    /// Serve `variables` instead of 10 m wind
+    pub fn with_variables(mut self, variables: Vec<MockVariable>) -> Self {
+        self.variables = variables;
+        self
+    }
+
+    /// Delay every response by `latency`
+    pub fn with_latency(mut self, latency: Duration) -> Self {
+        self.latency = latency;
+        self
+    }
+
+    /// The `/metadata` document
+    pub fn metadata(&self) -> Value {
+        let variables: Map<String, Value> = self
+            .variables
+            .iter()
+            .map(|variable| {
+                (
+                    variable.name.clone(),
+                    json!({
+                        "dimensions": ["time", "latitude", "longitude"],
+                        "attributes": {
+                            "long_name": variable.long_name,
+                            "units": variable.units
+                        }
+                    }),
+                )
+            })
+            .collect();
+        json!({
+            "coordinates": {
+                "latitude": self.latitudes,
+                "longitude": self.longitudes,
+                "time": self.times
+            },
+            "dimensions": {
+                "latitude": {"size": self.latitudes.len()},
+                "longitude": {"size": self.longitudes.len()},
+                "time": {"size": self.times.len()}
+            },
+            "variables": variables
+        })
+    }
+
+    /// The `/data` response for the query `params`
+    pub fn data(&self, params: &HashMap<String, String>) -> Result<Value, String> {
+        let times: Vec<f64> = match (params.get("time"), params.get("time_range")) {
+            (Some(time), _) => {
+                let time: f64 = time
+                    .parse()
+                    .map_err(|_| format!("Invalid time '{}'", time))?;
+                if !self.times.contains(&time) {
+                    return Err(format!("Time {} not found", time));
+                }
+                vec![time]
+            }
+            (None, Some(range)) => {
+                let bounds: Vec<f64> = range.split(',').filter_map(|t| t.parse().ok()).collect();
+                let [start, end] = bounds[..] else {
+                    return Err(format!("Invalid time_range '{}'", range));
+                };
+                self.times
+                    .iter()
+                    .copied()
+                    .filter(|time| (start..=end).contains(time))
+                    .collect()
+            }
+            (None, None) => self.times.clone(),
+        };
+
+        let latitudes = select_axis(&self.latitudes, "latitude", params)?;
+        let longitudes = select_axis(&self.longitudes, "longitude", params)?;
+
+        let names = params
+            .get("vars")
+            .ok_or_else(|| "Missing vars parameter".to_string())?;
+        let points = times.len() * latitudes.len() * longitudes.len();
+        let mut data = Map::new();
+        let mut variables = Map::new();
+        for name in names.split(',') {
+            let variable = self
+                .variables
+                .iter()
+                .find(|variable| variable.name == name)
+                .ok_or_else(|| format!("Variable '{}' not found", name))?;
+            let values: Vec<f64> = (1..=points).map(|value| value as f64).collect();
+            data.insert(name.to_string(), json!(values));
+            variables.insert(
+                name.to_string(),
+                json!({"units": variable.units, "long_name": variable.long_name}),
+            );
+        }
+
+        Ok(json!({
+            "metadata": {
+                "query": params,
+                "shape": [times.len(), latitudes.len(), longitudes.len()],
+                "dimensions": ["time", "latitude", "longitude"],
+                "variables": variables
+            },
+            "data": data
+        }))
+    }
+
+    /// Serve the backend on a loopback port
+    pub async fn start(self) -> RunningMockBackend {
+        let requests = Arc::new(AtomicUsize::new(0));
+        let shared = Arc::new(Shared {
+            backend: self,
+            requests: requests.clone(),
+        });
+        let app = Router::new()
+            .route("/metadata", get(metadata))
+            .route("/data", get(data))
+            .with_state(shared);
+
+        let listener = TcpListener::bind("127.0.0.1:0")
+            .await
+            .expect("bind mock backend");
+        let addr = listener.local_addr().expect("mock backend address");
+        let server = axum::Server::from_tcp(listener.into_std().expect("mock backend listener"))
+            .expect("start mock backend")
+            .serve(app.into_make_service());
+        tokio::spawn(server);
+
+        RunningMockBackend {
+            url: format!("http://{}", addr),
+            requests,
+        }
+    }
+}
+
+/// The coordinates of `axis` selected by `{name}=` or `{name}_range=`
+fn select_axis(
+    axis: &[f64],
+    name: &str,
+    params: &HashMap<String, String>,
+) -> Result<Vec<f64>, String> {
+    if let Some(value) = params.get(name) {
+        let value: f64 = value
+            .parse()
+            .map_err(|_| format!("Invalid {} '{}'", name, value))?;
+        if !axis.contains(&value) {
+            return Err(format!("{} {} not found", name, value));
+        }
+        return Ok(vec![value]);
+    }
+    if let Some(range) = params.get(&format!("{}_range", name)) {
+        let bounds: Vec<f64> = range.split(',').filter_map(|v| v.parse().ok()).collect();
+        let [start, end] = bounds[..] else {
+            return Err(format!("Invalid {}_range '{}'", name, range));
+        };
+        return Ok(axis
+            .iter()
+            .copied()
+            .filter(|value| (start..=end).contains(value))
+            .collect());
+    }
+    Ok(axis.to_vec())
+}
+
+/// A started [`MockBackend`], which keeps serving until the runtime stops
+#[derive(Debug, Clone)]
+pub struct RunningMockBackend {
+    url: String,
+    requests: Arc<AtomicUsize>,
+}
+
+impl RunningMockBackend {
+    /// Base URL to pass as the backend URL
+    pub fn url(&self) -> &str {
+        &self.url
+    }
+
+    /// Number of requests received so far
+    pub fn requests(&self) -> usize {
+        self.requests.load(Ordering::SeqCst)
+    }
+}
+
+struct Shared {
+    backend: MockBackend,
+    requests: Arc<AtomicUsize>,
+}
+
+impl Shared {
+    async fn received(&self) {
+        self.requests.fetch_add(1, Ordering::SeqCst);
+        if !self.backend.latency.is_zero() {
+            tokio::time::sleep(self.backend.latency).await;
+        }
+    }
+}
+
+async fn metadata(State(shared): State<Arc<Shared>>) -> Json<Value> {
+    shared.received().await;
+    Json(shared.backend.metadata())
+}
+
+async fn data(
+    State(shared): State<Arc<Shared>>,
+    Query(params): Query<HashMap<String, String>>,
+) -> Response {
+    shared.received().await;
+    match shared.backend.data(&params) {
+        Ok(data) => Json(data).into_response(),
+        Err(message) => (StatusCode::BAD_REQUEST, Json(json!({"error": message}))).into_response(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn query(pairs: &[(&str, &str)]) -> HashMap<String, String> {
+        pairs
+            .iter()
+            .map(|(k, v)| (k.to_string(), v.to_string()))
+            .collect()
+    }
+
+    #[test]
+    fn test_regular_grid() {
+        let backend = MockBackend::regular(181, 360);
+        assert_eq!(backend.latitudes.first(), Some(&90.0));
+        assert_eq!(backend.latitudes.last(), Some(&-90.0));
+        assert_eq!(backend.longitudes[1], 1.0);
+        assert_eq!(backend.metadata()["dimensions"]["longitude"]["size"], 360);
+    }
+
+    #[test]
+    fn test_data_selects_times_and_variables() {
+        let backend = MockBackend::default();
+        let data = backend
+            .data(&query(&[("vars", "u10,v10"), ("time", "700465")]))
+            .unwrap();
+        assert_eq!(data["metadata"]["shape"], json!([1, 3, 3]));
+        assert_eq!(data["data"]["v10"].as_array().unwrap().len(), 9);
+
+        let data = backend
+            .data(&query(&[("vars", "u10"), ("time_range", "700000,800000")]))
+            .unwrap();
+        assert_eq!(data["metadata"]["shape"], json!([2, 3, 3]));
+
+        assert!(backend.data(&query(&[("vars", "t2m")])).is_err());
+        assert!(backend
+            .data(&query(&[("vars", "u10"), ("time", "1")]))
+            .is_err());
+    }
+}
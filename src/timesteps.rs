@@ -0,0 +1,436 @@
+//! The dataset's time axis
+//!
+//! Rossby encodes times as hours since 1900-01-01 UTC. Clients may name a
+//! timestep either by that raw value, which must be on the axis, or by an
+//! ISO-8601 date or date-time, which resolves to the nearest timestep. The
+//! Earth routes report the timestep actually served in `X-Data-Time`.
+//!
+//! `/api/time/next` and `/api/time/previous` step along the axis from any
+//! time, so the frontend's arrow buttons follow the dataset's actual
+//! timesteps, gaps included, rather than fixed increments.
+
+use axum::{
+    extract::{Query, State},
+    http::HeaderValue,
+    response::Response,
+    Json,
+};
+use chrono::{DateTime, Duration, NaiveDate, NaiveDateTime, TimeZone, Utc};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::sync::Arc;
+use utoipa::{IntoParams, ToSchema};
+
+use crate::{
+    derived::register_derived_variables,
+    error::{AppError, ErrorBody},
+    grid::{coordinate_values, variable_dimensions},
+    handlers::fetch_metadata,
+    metadata::invalid_metadata_error,
+    server::AppState,
+};
+
+/// Upper bound on timesteps moved by one navigation request
+const MAX_NAVIGATION_STEPS: usize = 1000;
+
+/// Header carrying the timestep served, as an ISO-8601 date-time
+pub const DATA_TIME_HEADER: &str = "x-data-time";
+
+/// Tolerance when matching raw time values against the axis
+const TIME_EPSILON: f64 = 1e-9;
+
+/// Start of the Rossby time encoding
+fn epoch() -> DateTime<Utc> {
+    Utc.with_ymd_and_hms(1900, 1, 1, 0, 0, 0).unwrap()
+}
+
+/// The instant a backend time value stands for
+pub fn to_datetime(value: f64) -> DateTime<Utc> {
+    epoch() + Duration::seconds((value * 3600.0).round() as i64)
+}
+
+/// A backend time value as an ISO-8601 date-time
+pub fn to_iso(value: f64) -> String {
+    to_datetime(value).to_rfc3339()
+}
+
+/// The backend time value of an instant
+pub fn from_datetime(datetime: DateTime<Utc>) -> f64 {
+    (datetime - epoch()).num_seconds() as f64 / 3600.0
+}
+
+/// A time as a client asked for it
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum TimeRequest {
+    /// Raw backend value, which must be on the axis
+    Value(f64),
+    /// Instant resolved to the nearest timestep
+    Instant(DateTime<Utc>),
+}
+
+impl TimeRequest {
+    /// Parse a raw value, an RFC 3339 date-time, a date-time without offset
+    /// (taken as UTC, seconds optional) or a bare date (midnight UTC)
+    pub fn parse(s: &str) -> Result<Self, AppError> {
+        let s = s.trim();
+        if let Ok(value) = s.parse::<f64>() {
+            if value.is_finite() {
+                return Ok(TimeRequest::Value(value));
+            }
+        }
+        if let Ok(datetime) = DateTime::parse_from_rfc3339(s) {
+            return Ok(TimeRequest::Instant(datetime.with_timezone(&Utc)));
+        }
+        for format in ["%Y-%m-%dT%H:%M:%S", "%Y-%m-%dT%H:%M", "%Y-%m-%d %H:%M"] {
+            if let Ok(datetime) = NaiveDateTime::parse_from_str(s, format) {
+                return Ok(TimeRequest::Instant(datetime.and_utc()));
+            }
+        }
+        if let Ok(date) = NaiveDate::parse_from_str(s, "%Y-%m-%d") {
+            return Ok(TimeRequest::Instant(
+                date.and_hms_opt(0, 0, 0).unwrap().and_utc(),
+            ));
+        }
+        Err(AppError::RequestError(format!(
+            "Invalid time value '{}'; expected a backend time value or an ISO-8601 date",
+            s
+        )))
+    }
+}
+
+/// The timestep of `times` closest to `target`; the earlier one on a tie
+pub fn nearest(times: &[f64], target: f64) -> Option<f64> {
+    times.iter().copied().min_by(|a, b| {
+        (a - target)
+            .abs()
+            .total_cmp(&(b - target).abs())
+            .then(a.total_cmp(b))
+    })
+}
+
+/// The timestep a request asks for: `requested` resolved against the
+/// metadata time axis, or the first timestep when no time is requested
+pub fn select_time(metadata: &Value, requested: Option<&str>) -> Result<f64, AppError> {
+    let times = coordinate_values(metadata, "time").unwrap_or_default();
+    let Some(requested) = requested else {
+        return times
+            .first()
+            .copied()
+            .ok_or_else(|| invalid_metadata_error(metadata, "Metadata has no time coordinate"));
+    };
+
+    match TimeRequest::parse(requested)? {
+        TimeRequest::Value(time) => match (times.first(), times.last()) {
+            (Some(first), Some(last)) if !times.iter().any(|t| (t - time).abs() < TIME_EPSILON) => {
+                Err(AppError::RequestError(format!(
+                    "Time {} is not available; the dataset has {} timesteps from {} to {}",
+                    time,
+                    times.len(),
+                    first,
+                    last
+                )))
+            }
+            _ => Ok(time),
+        },
+        TimeRequest::Instant(datetime) => nearest(&times, from_datetime(datetime))
+            .ok_or_else(|| invalid_metadata_error(metadata, "Metadata has no time coordinate")),
+    }
+}
+
+/// Add the timestep served to `response`
+pub fn with_data_time(mut response: Response, time: f64) -> Response {
+    if let Ok(value) = HeaderValue::from_str(&to_iso(time)) {
+        response.headers_mut().insert(DATA_TIME_HEADER, value);
+    }
+    response
+}
+
+/// Query of `GET /api/time/next`
+#[derive(Debug, Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct NextTimeQuery {
+    /// Reference time, a backend time value or an ISO-8601 date; need not be
+    /// on the axis. Without one the first timestep is returned.
+    pub after: Option<String>,
+    /// Variable the timestep is for; it must have a time dimension
+    pub var: Option<String>,
+    /// Timesteps to move, default 1
+    pub steps: Option<usize>,
+}
+
+/// Query of `GET /api/time/previous`
+#[derive(Debug, Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct PreviousTimeQuery {
+    /// Reference time; without one the last timestep is returned
+    pub before: Option<String>,
+    /// Variable the timestep is for; it must have a time dimension
+    pub var: Option<String>,
+    /// Timesteps to move, default 1
+    pub steps: Option<usize>,
+}
+
+/// Timestep returned by the navigation endpoints
+#[derive(Debug, Clone, PartialEq, Serialize, ToSchema)]
+pub struct TimeStep {
+    /// Backend time value; `null` when there is no timestep in that direction
+    pub time: Option<f64>,
+    pub iso: Option<String>,
+    /// Position on the time axis
+    pub index: Option<usize>,
+    /// Timesteps in the dataset
+    pub count: usize,
+    /// No further timestep exists in the direction moved
+    pub at_end: bool,
+}
+
+/// Move `steps` timesteps forward or backward from `reference`, stopping at
+/// the end of the axis
+fn step_from(times: &[f64], reference: Option<f64>, steps: usize, forward: bool) -> TimeStep {
+    let candidates: Vec<usize> = match (reference, forward) {
+        (None, _) => (0..times.len()).collect(),
+        (Some(reference), true) => (0..times.len())
+            .filter(|&i| times[i] > reference + TIME_EPSILON)
+            .collect(),
+        (Some(reference), false) => (0..times.len())
+            .filter(|&i| times[i] < reference - TIME_EPSILON)
+            .collect(),
+    };
+    // Without a reference the first step lands on the first (or last) timestep
+    let offset = steps.max(1) - 1;
+    let chosen = if forward {
+        candidates.get(offset.min(candidates.len().saturating_sub(1)))
+    } else {
+        candidates
+            .len()
+            .checked_sub(offset + 1)
+            .and_then(|i| candidates.get(i))
+            .or(candidates.first())
+    };
+
+    match chosen {
+        Some(&index) => TimeStep {
+            time: Some(times[index]),
+            iso: Some(to_iso(times[index])),
+            index: Some(index),
+            count: times.len(),
+            at_end: if forward {
+                index + 1 == times.len()
+            } else {
+                index == 0
+            },
+        },
+        None => TimeStep {
+            time: None,
+            iso: None,
+            index: None,
+            count: times.len(),
+            at_end: true,
+        },
+    }
+}
+
+async fn navigate(
+    state: &AppState,
+    reference: Option<&str>,
+    var: Option<&str>,
+    steps: Option<usize>,
+    forward: bool,
+) -> Result<TimeStep, AppError> {
+    let steps = steps.unwrap_or(1);
+    if steps == 0 || steps > MAX_NAVIGATION_STEPS {
+        return Err(AppError::RequestError(format!(
+            "steps must be between 1 and {}",
+            MAX_NAVIGATION_STEPS
+        )));
+    }
+    let reference = reference
+        .map(|r| {
+            TimeRequest::parse(r).map(|request| match request {
+                TimeRequest::Value(value) => value,
+                TimeRequest::Instant(datetime) => from_datetime(datetime),
+            })
+        })
+        .transpose()?;
+
+    let mut metadata = fetch_metadata(state).await?;
+    if let Some(var) = var {
+        register_derived_variables(&mut metadata, &state.derived_variables);
+        let dimensions = variable_dimensions(&metadata, var).ok_or_else(|| {
+            AppError::RequestError(format!("Variable '{}' not found in metadata", var))
+        })?;
+        if !dimensions.iter().any(|d| d == "time") {
+            return Err(AppError::RequestError(format!(
+                "Variable '{}' has no time dimension",
+                var
+            )));
+        }
+    }
+
+    let times = coordinate_values(&metadata, "time").unwrap_or_default();
+    Ok(step_from(&times, reference, steps, forward))
+}
+
+/// Handler for `GET /api/time/next`
+#[utoipa::path(
+    get,
+    path = "/api/time/next",
+    tag = "time",
+    summary = "The timestep after a time",
+    params(NextTimeQuery),
+    responses(
+        (status = 200, body = TimeStep),
+        (status = 400, description = "Invalid time or variable", body = ErrorBody),
+        (status = 502, description = "The backend failed", body = ErrorBody),
+    )
+)]
+pub async fn next_time(
+    State(state): State<Arc<AppState>>,
+    Query(query): Query<NextTimeQuery>,
+) -> Result<Json<TimeStep>, AppError> {
+    navigate(
+        &state,
+        query.after.as_deref(),
+        query.var.as_deref(),
+        query.steps,
+        true,
+    )
+    .await
+    .map(Json)
+}
+
+/// Handler for `GET /api/time/previous`
+#[utoipa::path(
+    get,
+    path = "/api/time/previous",
+    tag = "time",
+    summary = "The timestep before a time",
+    params(PreviousTimeQuery),
+    responses(
+        (status = 200, body = TimeStep),
+        (status = 400, description = "Invalid time or variable", body = ErrorBody),
+        (status = 502, description = "The backend failed", body = ErrorBody),
+    )
+)]
+pub async fn previous_time(
+    State(state): State<Arc<AppState>>,
+    Query(query): Query<PreviousTimeQuery>,
+) -> Result<Json<TimeStep>, AppError> {
+    navigate(
+        &state,
+        query.before.as_deref(),
+        query.var.as_deref(),
+        query.steps,
+        false,
+    )
+    .await
+    .map(Json)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn test_metadata() -> Value {
+        json!({"coordinates": {"time": [700464.0, 700467.0, 700476.0]}})
+    }
+
+    #[test]
+    fn test_encoding_round_trip() {
+        assert_eq!(to_iso(700464.0), "1979-11-29T00:00:00+00:00");
+        assert_eq!(to_iso(700464.5), "1979-11-29T00:30:00+00:00");
+        assert_eq!(from_datetime(to_datetime(700467.0)), 700467.0);
+    }
+
+    #[test]
+    fn test_parse() {
+        assert_eq!(
+            TimeRequest::parse("700467").unwrap(),
+            TimeRequest::Value(700467.0)
+        );
+        let expected = TimeRequest::Instant(to_datetime(700467.0));
+        for s in [
+            "1979-11-29T03:00:00Z",
+            "1979-11-29T04:00:00+01:00",
+            "1979-11-29T03:00",
+            "1979-11-29 03:00",
+        ] {
+            assert_eq!(TimeRequest::parse(s).unwrap(), expected, "{}", s);
+        }
+        assert_eq!(
+            TimeRequest::parse("1979-11-29").unwrap(),
+            TimeRequest::Instant(to_datetime(700464.0))
+        );
+        assert!(TimeRequest::parse("yesterday").is_err());
+        assert!(TimeRequest::parse("NaN").is_err());
+    }
+
+    #[test]
+    fn test_step_from() {
+        let times = [700464.0, 700467.0, 700476.0];
+        let time = |step: TimeStep| step.time;
+
+        assert_eq!(
+            time(step_from(&times, Some(700464.0), 1, true)),
+            Some(700467.0)
+        );
+        // Across the gap and from between timesteps
+        assert_eq!(
+            time(step_from(&times, Some(700467.0), 1, true)),
+            Some(700476.0)
+        );
+        assert_eq!(
+            time(step_from(&times, Some(700470.0), 1, false)),
+            Some(700467.0)
+        );
+        assert_eq!(
+            time(step_from(&times, Some(700464.0), 5, true)),
+            Some(700476.0)
+        );
+        assert_eq!(
+            time(step_from(&times, Some(700476.0), 2, false)),
+            Some(700464.0)
+        );
+        assert_eq!(time(step_from(&times, None, 1, true)), Some(700464.0));
+        assert_eq!(time(step_from(&times, None, 1, false)), Some(700476.0));
+
+        let last = step_from(&times, Some(700467.0), 1, true);
+        assert_eq!(last.index, Some(2));
+        assert!(last.at_end);
+        let beyond = step_from(&times, Some(700476.0), 1, true);
+        assert_eq!(beyond.time, None);
+        assert!(beyond.at_end);
+        assert_eq!(beyond.count, 3);
+    }
+
+    #[test]
+    fn test_select_time() {
+        let metadata = test_metadata();
+        assert_eq!(select_time(&metadata, None).unwrap(), 700464.0);
+        assert_eq!(select_time(&metadata, Some("700467")).unwrap(), 700467.0);
+        assert!(matches!(
+            select_time(&metadata, Some("700470")),
+            Err(AppError::RequestError(message)) if message.contains("3 timesteps from 700464 to 700476")
+        ));
+
+        // Dates resolve to the nearest timestep, across gaps and past the ends
+        assert_eq!(
+            select_time(&metadata, Some("1979-11-29T04:00:00Z")).unwrap(),
+            700467.0
+        );
+        assert_eq!(
+            select_time(&metadata, Some("1979-11-29T07:00:00Z")).unwrap(),
+            700467.0
+        );
+        assert_eq!(
+            select_time(&metadata, Some("1979-11-29T08:00:00Z")).unwrap(),
+            700476.0
+        );
+        assert_eq!(
+            select_time(&metadata, Some("2030-01-01")).unwrap(),
+            700476.0
+        );
+        assert!(select_time(&json!({"coordinates": {}}), None).is_err());
+    }
+}
@@ -0,0 +1,444 @@
+//! TLS termination with certificates selected by SNI
+//!
+//! One instance can serve several host names, each with its own certificate:
+//! the certificate is chosen by the server name the client asks for in its
+//! TLS handshake. A certificate configured without host names is the default,
+//! served to clients asking for a name no other certificate covers, or for
+//! none at all. Without a default, such handshakes are refused. Names
+//! configured for ACME get the certificate obtained for them once it has
+//! been issued, in builds with the `acme` feature.
+
+use hyper::server::accept::{self, Accept};
+use rustls::{
+    server::{ClientHello, ResolvesServerCert},
+    sign::{self, CertifiedKey},
+    Certificate, PrivateKey,
+};
+use std::{
+    collections::HashMap,
+    fmt, fs,
+    io::{self, BufReader},
+    path::PathBuf,
+    str::FromStr,
+    sync::Arc,
+    time::Duration,
+};
+use tokio::{
+    net::{TcpListener, TcpStream},
+    sync::mpsc,
+};
+use tokio_rustls::{server::TlsStream, TlsAcceptor};
+use tracing::{debug, info, warn};
+
+#[cfg(feature = "acme")]
+use crate::acme::Acme;
+use crate::error::AppError;
+
+/// Time a client has to complete its TLS handshake
+const HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// A certificate and its key, with the host names it is served for
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TlsCertificate {
+    /// Host names, possibly wildcards such as `*.example.org`; empty for
+    /// the default certificate
+    pub hosts: Vec<String>,
+    /// PEM file with the certificate chain, leaf first
+    pub cert_path: PathBuf,
+    /// PEM file with the private key
+    pub key_path: PathBuf,
+}
+
+impl FromStr for TlsCertificate {
+    type Err = String;
+
+    /// Parse `[HOST[,HOST...]=]CERT_FILE:KEY_FILE`
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (hosts, files) = match s.split_once('=') {
+            Some((hosts, files)) => (hosts, files),
+            None => ("", s),
+        };
+        let (cert_path, key_path) = files
+            .split_once(':')
+            .filter(|(cert, key)| !cert.trim().is_empty() && !key.trim().is_empty())
+            .ok_or_else(|| {
+                format!(
+                    "Invalid TLS certificate: {}. Expected '[HOST[,HOST...]=]CERT_FILE:KEY_FILE'",
+                    s
+                )
+            })?;
+        let hosts = hosts
+            .split(',')
+            .map(|host| host.trim().to_ascii_lowercase())
+            .filter(|host| !host.is_empty())
+            .collect();
+        Ok(Self {
+            hosts,
+            cert_path: PathBuf::from(cert_path.trim()),
+            key_path: PathBuf::from(key_path.trim()),
+        })
+    }
+}
+
+impl fmt::Display for TlsCertificate {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.hosts.is_empty() {
+            write!(f, "{} (default)", self.cert_path.display())
+        } else {
+            write!(
+                f,
+                "{} ({})",
+                self.cert_path.display(),
+                self.hosts.join(", ")
+            )
+        }
+    }
+}
+
+/// Production directory of Let's Encrypt
+pub const LETS_ENCRYPT_DIRECTORY: &str = "https://acme-v02.api.letsencrypt.org/directory";
+
+/// Staging directory of Let's Encrypt, issuing untrusted test certificates
+pub const LETS_ENCRYPT_STAGING_DIRECTORY: &str =
+    "https://acme-staging-v02.api.letsencrypt.org/directory";
+
+/// ALPN protocol of `tls-alpn-01` validation handshakes
+pub const ACME_TLS_ALPN: &[u8] = b"acme-tls/1";
+
+/// How control of a host name is proven to the authority
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum AcmeChallenge {
+    /// A special certificate served on the HTTPS port
+    #[default]
+    TlsAlpn01,
+    /// A token served over plain HTTP on port 80
+    Http01,
+}
+
+impl AcmeChallenge {
+    pub(crate) fn as_str(&self) -> &'static str {
+        match self {
+            AcmeChallenge::TlsAlpn01 => "tls-alpn-01",
+            AcmeChallenge::Http01 => "http-01",
+        }
+    }
+}
+
+impl FromStr for AcmeChallenge {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "tls-alpn-01" => Ok(AcmeChallenge::TlsAlpn01),
+            "http-01" => Ok(AcmeChallenge::Http01),
+            _ => Err(format!(
+                "Invalid ACME challenge: {}. Valid options: tls-alpn-01, http-01",
+                s
+            )),
+        }
+    }
+}
+
+impl fmt::Display for AcmeChallenge {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+/// Startup configuration of automatic certificates
+#[derive(Debug, Clone)]
+pub struct AcmeConfig {
+    /// Host names the certificate is ordered for
+    pub domains: Vec<String>,
+    /// Email addresses the authority may contact about the account
+    pub contact: Vec<String>,
+    /// Directory URL of the authority
+    pub directory_url: String,
+    pub challenge: AcmeChallenge,
+}
+
+impl AcmeConfig {
+    /// Certificates for `domains` from Let's Encrypt
+    pub fn new(domains: Vec<String>) -> Self {
+        Self {
+            domains,
+            contact: Vec::new(),
+            directory_url: LETS_ENCRYPT_DIRECTORY.to_string(),
+            challenge: AcmeChallenge::default(),
+        }
+    }
+}
+
+/// TLS settings of the listener; plain HTTP when no certificate is set
+#[derive(Debug, Clone, Default)]
+pub struct TlsConfig {
+    pub certificates: Vec<TlsCertificate>,
+    /// Certificate obtained automatically for some host names
+    pub acme: Option<AcmeConfig>,
+}
+
+impl TlsConfig {
+    pub fn is_enabled(&self) -> bool {
+        !self.certificates.is_empty() || self.acme.is_some()
+    }
+
+    /// Load the certificates into a rustls configuration, `None` when TLS
+    /// is disabled
+    pub fn server_config(
+        &self,
+        #[cfg(feature = "acme")] acme: Option<Arc<Acme>>,
+    ) -> Result<Option<Arc<rustls::ServerConfig>>, AppError> {
+        if !self.is_enabled() {
+            return Ok(None);
+        }
+        let resolver = SniResolver::load(&self.certificates)?;
+        #[cfg(feature = "acme")]
+        let resolver = SniResolver {
+            acme: acme.clone(),
+            ..resolver
+        };
+        let mut config = rustls::ServerConfig::builder()
+            .with_safe_defaults()
+            .with_no_client_auth()
+            .with_cert_resolver(Arc::new(resolver));
+        config.alpn_protocols = vec![b"http/1.1".to_vec()];
+        #[cfg(feature = "acme")]
+        if acme.is_some_and(|acme| acme.config().challenge == AcmeChallenge::TlsAlpn01) {
+            config.alpn_protocols.push(ACME_TLS_ALPN.to_vec());
+        }
+        Ok(Some(Arc::new(config)))
+    }
+}
+
+/// Chooses the certificate for the server name of each handshake
+struct SniResolver {
+    hosts: HashMap<String, Arc<CertifiedKey>>,
+    default: Option<Arc<CertifiedKey>>,
+    #[cfg(feature = "acme")]
+    acme: Option<Arc<Acme>>,
+}
+
+impl SniResolver {
+    fn load(certificates: &[TlsCertificate]) -> Result<Self, AppError> {
+        let mut resolver = Self {
+            hosts: HashMap::new(),
+            default: None,
+            #[cfg(feature = "acme")]
+            acme: None,
+        };
+        for certificate in certificates {
+            let key = Arc::new(load_certified_key(certificate)?);
+            info!("Loaded TLS certificate {}", certificate);
+            if certificate.hosts.is_empty() {
+                if resolver.default.replace(key).is_some() {
+                    return Err(AppError::ConfigError(
+                        "Only one TLS certificate can be configured without host names".to_string(),
+                    ));
+                }
+                continue;
+            }
+            for host in &certificate.hosts {
+                let host = host.to_ascii_lowercase();
+                if resolver.hosts.insert(host.clone(), key.clone()).is_some() {
+                    return Err(AppError::ConfigError(format!(
+                        "Host {} is configured with more than one TLS certificate",
+                        host
+                    )));
+                }
+            }
+        }
+        Ok(resolver)
+    }
+
+    /// The certificate for `name`, exact names taking precedence over
+    /// wildcards
+    fn lookup(&self, name: &str) -> Option<&Arc<CertifiedKey>> {
+        let name = name.to_ascii_lowercase();
+        self.hosts.get(&name).or_else(|| {
+            let (_, parent) = name.split_once('.')?;
+            self.hosts.get(&format!("*.{}", parent))
+        })
+    }
+}
+
+impl ResolvesServerCert for SniResolver {
+    fn resolve(&self, client_hello: ClientHello) -> Option<Arc<CertifiedKey>> {
+        let name = client_hello.server_name();
+        #[cfg(feature = "acme")]
+        if let Some(acme) = &self.acme {
+            let validation = client_hello
+                .alpn()
+                .is_some_and(|mut protocols| protocols.any(|p| p == ACME_TLS_ALPN));
+            if validation {
+                return name.and_then(|name| acme.challenge_certificate(name));
+            }
+        }
+        let key = name
+            .and_then(|name| {
+                let key = self.lookup(name).cloned();
+                #[cfg(feature = "acme")]
+                let key =
+                    key.or_else(|| self.acme.as_ref().and_then(|acme| acme.certificate(name)));
+                key
+            })
+            .or_else(|| self.default.clone());
+        if key.is_none() {
+            debug!(server_name = ?name, "No TLS certificate for requested server name");
+        }
+        key
+    }
+}
+
+fn load_certified_key(certificate: &TlsCertificate) -> Result<CertifiedKey, AppError> {
+    let read_error = |path: &PathBuf, e: io::Error| {
+        AppError::ConfigError(format!("Failed to read {}: {}", path.display(), e))
+    };
+
+    let pem =
+        fs::read(&certificate.cert_path).map_err(|e| read_error(&certificate.cert_path, e))?;
+    let chain: Vec<Certificate> = rustls_pemfile::certs(&mut BufReader::new(pem.as_slice()))
+        .map_err(|e| read_error(&certificate.cert_path, e))?
+        .into_iter()
+        .map(Certificate)
+        .collect();
+    if chain.is_empty() {
+        return Err(AppError::ConfigError(format!(
+            "No certificate found in {}",
+            certificate.cert_path.display()
+        )));
+    }
+
+    let pem = fs::read(&certificate.key_path).map_err(|e| read_error(&certificate.key_path, e))?;
+    let mut reader = BufReader::new(pem.as_slice());
+    let key = loop {
+        match rustls_pemfile::read_one(&mut reader)
+            .map_err(|e| read_error(&certificate.key_path, e))?
+        {
+            Some(
+                rustls_pemfile::Item::PKCS8Key(key)
+                | rustls_pemfile::Item::RSAKey(key)
+                | rustls_pemfile::Item::ECKey(key),
+            ) => break PrivateKey(key),
+            Some(_) => continue,
+            None => {
+                return Err(AppError::ConfigError(format!(
+                    "No private key found in {}",
+                    certificate.key_path.display()
+                )))
+            }
+        }
+    };
+    let key = sign::any_supported_type(&key).map_err(|e| {
+        AppError::ConfigError(format!(
+            "Unsupported private key in {}: {}",
+            certificate.key_path.display(),
+            e
+        ))
+    })?;
+
+    Ok(CertifiedKey::new(chain, key))
+}
+
+/// Connections accepted on `listener`, handed over once their TLS handshake
+/// has completed
+///
+/// Handshakes run concurrently, so a slow client does not hold up others.
+/// Accepting stops when the returned stream is dropped.
+pub(crate) fn incoming(
+    listener: TcpListener,
+    config: Arc<rustls::ServerConfig>,
+) -> impl Accept<Conn = TlsStream<TcpStream>, Error = io::Error> {
+    let acceptor = TlsAcceptor::from(config);
+    let (sender, mut receiver) = mpsc::channel(64);
+
+    tokio::spawn(async move {
+        loop {
+            let (stream, peer) = tokio::select! {
+                accepted = listener.accept() => match accepted {
+                    Ok(accepted) => accepted,
+                    Err(e) => {
+                        // Out of file descriptors and the like: back off
+                        // instead of spinning
+                        warn!(error = %e, "Failed to accept connection");
+                        tokio::time::sleep(Duration::from_secs(1)).await;
+                        continue;
+                    }
+                },
+                _ = sender.closed() => break,
+            };
+            let acceptor = acceptor.clone();
+            let sender = sender.clone();
+            tokio::spawn(async move {
+                match tokio::time::timeout(HANDSHAKE_TIMEOUT, acceptor.accept(stream)).await {
+                    // Validation handshakes of ACME end with the handshake
+                    Ok(Ok(stream)) if stream.get_ref().1.alpn_protocol() == Some(ACME_TLS_ALPN) => {
+                        debug!(peer = %peer, "Answered ACME validation handshake");
+                    }
+                    Ok(Ok(stream)) => {
+                        let _ = sender.send(stream).await;
+                    }
+                    Ok(Err(e)) => debug!(peer = %peer, error = %e, "TLS handshake failed"),
+                    Err(_) => debug!(peer = %peer, "TLS handshake timed out"),
+                }
+            });
+        }
+    });
+
+    accept::from_stream(futures::stream::poll_fn(move |cx| {
+        receiver.poll_recv(cx).map(|stream| stream.map(Ok))
+    }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// The rustls configuration of `config`, without ACME certificates
+    fn server_config(config: &TlsConfig) -> Result<Option<Arc<rustls::ServerConfig>>, AppError> {
+        #[cfg(feature = "acme")]
+        return config.server_config(None);
+        #[cfg(not(feature = "acme"))]
+        return config.server_config();
+    }
+
+    #[test]
+    fn test_parse_certificate() {
+        let certificate: TlsCertificate =
+            "Weather.example.org, *.ocean.example.org=/etc/tls/weather.pem:/etc/tls/weather.key"
+                .parse()
+                .unwrap();
+        assert_eq!(
+            certificate.hosts,
+            vec!["weather.example.org", "*.ocean.example.org"]
+        );
+        assert_eq!(certificate.cert_path, PathBuf::from("/etc/tls/weather.pem"));
+        assert_eq!(certificate.key_path, PathBuf::from("/etc/tls/weather.key"));
+
+        let default: TlsCertificate = "cert.pem:key.pem".parse().unwrap();
+        assert!(default.hosts.is_empty());
+        assert_eq!(default.to_string(), "cert.pem (default)");
+
+        assert!("cert.pem".parse::<TlsCertificate>().is_err());
+        assert!("example.org=cert.pem:".parse::<TlsCertificate>().is_err());
+    }
+
+    #[test]
+    fn test_parse_challenge() {
+        assert_eq!("http-01".parse(), Ok(AcmeChallenge::Http01));
+        assert_eq!("TLS-ALPN-01".parse(), Ok(AcmeChallenge::TlsAlpn01));
+        assert!("dns-01".parse::<AcmeChallenge>().is_err());
+    }
+
+    #[test]
+    fn test_missing_files_are_reported() {
+        let config = TlsConfig {
+            certificates: vec!["/nonexistent/cert.pem:/nonexistent/key.pem"
+                .parse()
+                .unwrap()],
+            acme: None,
+        };
+        let error = server_config(&config).unwrap_err();
+        assert!(error.to_string().contains("/nonexistent/cert.pem"));
+        assert!(server_config(&TlsConfig::default()).unwrap().is_none());
+    }
+}
@@ -0,0 +1,187 @@
+//! W3C Trace Context (`traceparent`/`tracestate`) propagation
+//!
+//! The tracing middleware parses the incoming `traceparent`, or starts a new
+//! trace whose ID is derived from the request ID, and makes it available to
+//! backend requests for the rest of the request. Each hop through rossby-vis
+//! gets its own span ID so traces stitch together with the caller's.
+
+use std::future::Future;
+
+tokio::task_local! {
+    static CURRENT: TraceContext;
+}
+
+/// Trace context of the request being handled
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TraceContext {
+    /// 32 lowercase hex digits identifying the whole trace
+    pub trace_id: String,
+    /// 16 lowercase hex digits identifying this hop
+    pub span_id: String,
+    /// Span ID of the caller, when the request carried a `traceparent`
+    pub parent_span_id: Option<String>,
+    /// Trace flags; bit 0 is the sampled flag
+    pub flags: u8,
+    /// Vendor-specific `tracestate`, forwarded unchanged
+    pub tracestate: Option<String>,
+}
+
+impl TraceContext {
+    /// Continue the trace described by an incoming `traceparent` header.
+    ///
+    /// Returns `None` for malformed headers, which the spec says to ignore.
+    pub fn from_traceparent(traceparent: &str, tracestate: Option<&str>) -> Option<Self> {
+        let parts: Vec<&str> = traceparent.trim().split('-').collect();
+        let [version, trace_id, parent_id, flags, rest @ ..] = parts.as_slice() else {
+            return None;
+        };
+
+        // Version 00 has exactly four fields; later versions may append more
+        if !is_hex(version, 2) || *version == "ff" || (*version == "00" && !rest.is_empty()) {
+            return None;
+        }
+        if !is_hex(trace_id, 32)
+            || is_zero(trace_id)
+            || !is_hex(parent_id, 16)
+            || is_zero(parent_id)
+        {
+            return None;
+        }
+        if !is_hex(flags, 2) {
+            return None;
+        }
+
+        Some(Self {
+            trace_id: trace_id.to_string(),
+            span_id: new_span_id(),
+            parent_span_id: Some(parent_id.to_string()),
+            flags: u8::from_str_radix(flags, 16).ok()?,
+            tracestate: tracestate
+                .map(str::trim)
+                .filter(|s| !s.is_empty())
+                .map(str::to_string),
+        })
+    }
+
+    /// Start a new sampled trace, reusing a UUID request ID as the trace ID so
+    /// logs and traces can be joined on the same value
+    pub fn from_request_id(request_id: &str) -> Self {
+        let hex: String = request_id.chars().filter(|c| *c != '-').collect();
+        let trace_id = if is_hex(&hex.to_ascii_lowercase(), 32) && !is_zero(&hex) {
+            hex.to_ascii_lowercase()
+        } else {
+            uuid::Uuid::new_v4().simple().to_string()
+        };
+
+        Self {
+            trace_id,
+            span_id: new_span_id(),
+            parent_span_id: None,
+            flags: 0x01,
+            tracestate: None,
+        }
+    }
+
+    /// The `traceparent` header for requests made by this hop
+    pub fn traceparent(&self) -> String {
+        format!("00-{}-{}-{:02x}", self.trace_id, self.span_id, self.flags)
+    }
+
+    /// Run `future` with this as the current trace context
+    pub async fn scope<F: Future>(self, future: F) -> F::Output {
+        CURRENT.scope(self, future).await
+    }
+
+    /// The trace context of the request being handled, if any
+    pub fn current() -> Option<Self> {
+        CURRENT.try_with(Clone::clone).ok()
+    }
+}
+
+fn new_span_id() -> String {
+    loop {
+        let id = uuid::Uuid::new_v4().simple().to_string()[..16].to_string();
+        if !is_zero(&id) {
+            return id;
+        }
+    }
+}
+
+fn is_hex(value: &str, len: usize) -> bool {
+    value.len() == len
+        && value
+            .bytes()
+            .all(|b| matches!(b, b'0'..=b'9' | b'a'..=b'f'))
+}
+
+fn is_zero(value: &str) -> bool {
+    value.bytes().all(|b| b == b'0')
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const TRACEPARENT: &str = "00-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-01";
+
+    #[test]
+    fn test_parse_traceparent() {
+        let context =
+            TraceContext::from_traceparent(TRACEPARENT, Some("congo=t61rcWkgMzE")).unwrap();
+        assert_eq!(context.trace_id, "4bf92f3577b34da6a3ce929d0e0e4736");
+        assert_eq!(context.parent_span_id.as_deref(), Some("00f067aa0ba902b7"));
+        assert_eq!(context.flags, 1);
+        assert_eq!(context.tracestate.as_deref(), Some("congo=t61rcWkgMzE"));
+
+        // The outgoing header keeps the trace but names this hop as parent
+        let outgoing = context.traceparent();
+        assert!(outgoing.starts_with("00-4bf92f3577b34da6a3ce929d0e0e4736-"));
+        assert!(outgoing.ends_with("-01"));
+        assert!(!outgoing.contains("00f067aa0ba902b7"));
+    }
+
+    #[test]
+    fn test_rejects_invalid_traceparent() {
+        for invalid in [
+            "",
+            "00-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7",
+            "ff-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-01",
+            "00-4BF92F3577B34DA6A3CE929D0E0E4736-00f067aa0ba902b7-01",
+            "00-00000000000000000000000000000000-00f067aa0ba902b7-01",
+            "00-4bf92f3577b34da6a3ce929d0e0e4736-0000000000000000-01",
+            "00-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-01-extra",
+        ] {
+            assert!(
+                TraceContext::from_traceparent(invalid, None).is_none(),
+                "{}",
+                invalid
+            );
+        }
+
+        // Future versions may carry extra fields
+        assert!(TraceContext::from_traceparent(
+            "01-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-01-extra",
+            None
+        )
+        .is_some());
+    }
+
+    #[test]
+    fn test_trace_id_from_request_id() {
+        let context = TraceContext::from_request_id("550e8400-e29b-41d4-a716-446655440000");
+        assert_eq!(context.trace_id, "550e8400e29b41d4a716446655440000");
+        assert_eq!(context.parent_span_id, None);
+
+        let context = TraceContext::from_request_id("custom-id");
+        assert!(is_hex(&context.trace_id, 32));
+    }
+
+    #[tokio::test]
+    async fn test_scope() {
+        assert!(TraceContext::current().is_none());
+        let context = TraceContext::from_request_id("custom-id");
+        let expected = context.clone();
+        let current = context.scope(async { TraceContext::current() }).await;
+        assert_eq!(current, Some(expected));
+    }
+}
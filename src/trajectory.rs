@@ -0,0 +1,272 @@
+//! Trajectory integration through gridded wind or current fields
+//!
+//! Particles are advected with a fourth-order Runge-Kutta scheme. Velocities
+//! are bilinearly interpolated in space and linearly interpolated between the
+//! available timesteps.
+
+use serde::Serialize;
+
+use crate::{
+    geo::{GeoPoint, EARTH_RADIUS_KM},
+    grid::LatLonGrid,
+};
+
+/// Seconds per hour, the unit of the Rossby time axis
+const SECONDS_PER_HOUR: f64 = 3600.0;
+/// Minimum cosine of latitude used to avoid blow-ups near the poles
+const MIN_COS_LATITUDE: f64 = 0.01;
+
+/// Horizontal velocity components (m/s) on the grid at one timestep
+#[derive(Debug, Clone)]
+pub struct VelocityFrame {
+    /// Backend time value in hours
+    pub time: f64,
+    /// Eastward component in latitude-major order
+    pub u: Vec<f64>,
+    /// Northward component in latitude-major order
+    pub v: Vec<f64>,
+}
+
+/// A time-varying velocity field on a latitude/longitude grid
+#[derive(Debug, Clone)]
+pub struct VelocityField {
+    grid: LatLonGrid,
+    frames: Vec<VelocityFrame>,
+}
+
+/// Why a trajectory stopped
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Termination {
+    /// The requested time range was fully integrated
+    Completed,
+    /// The particle left the grid or reached missing data
+    LeftDomain,
+}
+
+/// An integrated particle path
+#[derive(Debug, Clone)]
+pub struct Trajectory {
+    /// Positions paired with the backend time (hours) at which they were reached
+    pub points: Vec<(GeoPoint, f64)>,
+    /// Reason the integration stopped
+    pub termination: Termination,
+}
+
+impl VelocityField {
+    /// Create a field from frames, which are sorted by time
+    pub fn new(grid: LatLonGrid, mut frames: Vec<VelocityFrame>) -> Self {
+        frames.sort_by(|a, b| a.time.total_cmp(&b.time));
+        Self { grid, frames }
+    }
+
+    /// Interpolated velocity (u, v) in m/s, or `None` outside the grid or on missing data.
+    ///
+    /// Times before the first or after the last frame use the nearest frame.
+    pub fn velocity(&self, point: GeoPoint, time: f64) -> Option<(f64, f64)> {
+        let first = self.frames.first()?;
+        let last = self.frames.last()?;
+
+        if self.frames.len() == 1 || time <= first.time {
+            return self.frame_velocity(first, point);
+        }
+        if time >= last.time {
+            return self.frame_velocity(last, point);
+        }
+
+        let upper = self.frames.partition_point(|f| f.time <= time);
+        let (before, after) = (&self.frames[upper - 1], &self.frames[upper]);
+        let weight = (time - before.time) / (after.time - before.time);
+
+        let (u0, v0) = self.frame_velocity(before, point)?;
+        let (u1, v1) = self.frame_velocity(after, point)?;
+        Some((u0 + (u1 - u0) * weight, v0 + (v1 - v0) * weight))
+    }
+
+    fn frame_velocity(&self, frame: &VelocityFrame, point: GeoPoint) -> Option<(f64, f64)> {
+        let u = self.grid.bilinear(&frame.u, point.lat, point.lon)?;
+        let v = self.grid.bilinear(&frame.v, point.lat, point.lon)?;
+        Some((u, v))
+    }
+
+    /// Position tendency in degrees per hour at a point and time
+    fn tendency(&self, point: GeoPoint, time: f64) -> Option<(f64, f64)> {
+        let (u, v) = self.velocity(point, time)?;
+        let radius_m = EARTH_RADIUS_KM * 1000.0;
+        let cos_lat = point.lat.to_radians().cos().max(MIN_COS_LATITUDE);
+
+        let dlat = (v * SECONDS_PER_HOUR / radius_m).to_degrees();
+        let dlon = (u * SECONDS_PER_HOUR / (radius_m * cos_lat)).to_degrees();
+        Some((dlat, dlon))
+    }
+}
+
+/// Integrate a particle from `start_time` to `end_time` (hours) with a fixed RK4 step.
+///
+/// Integration runs backward in time when `end_time` is before `start_time`.
+/// `step_hours` must be positive; its sign is chosen from the direction.
+pub fn integrate_rk4(
+    field: &VelocityField,
+    seed: GeoPoint,
+    start_time: f64,
+    end_time: f64,
+    step_hours: f64,
+) -> Trajectory {
+    let direction = if end_time >= start_time { 1.0 } else { -1.0 };
+    let mut points = vec![(normalize(seed), start_time)];
+    let mut position = seed;
+    let mut time = start_time;
+
+    while (end_time - time) * direction > 1e-9 {
+        let step = direction * step_hours.min((end_time - time).abs());
+
+        match rk4_step(field, position, time, step) {
+            Some(next) if next.lat.abs() <= 90.0 => {
+                position = next;
+                time += step;
+                points.push((normalize(position), time));
+            }
+            _ => {
+                return Trajectory {
+                    points,
+                    termination: Termination::LeftDomain,
+                }
+            }
+        }
+    }
+
+    Trajectory {
+        points,
+        termination: Termination::Completed,
+    }
+}
+
+fn rk4_step(field: &VelocityField, point: GeoPoint, time: f64, step: f64) -> Option<GeoPoint> {
+    let offset =
+        |k: (f64, f64), scale: f64| GeoPoint::new(point.lat + k.0 * scale, point.lon + k.1 * scale);
+
+    let k1 = field.tendency(point, time)?;
+    let k2 = field.tendency(offset(k1, step / 2.0), time + step / 2.0)?;
+    let k3 = field.tendency(offset(k2, step / 2.0), time + step / 2.0)?;
+    let k4 = field.tendency(offset(k3, step), time + step)?;
+
+    Some(GeoPoint::new(
+        point.lat + step / 6.0 * (k1.0 + 2.0 * k2.0 + 2.0 * k3.0 + k4.0),
+        point.lon + step / 6.0 * (k1.1 + 2.0 * k2.1 + 2.0 * k3.1 + k4.1),
+    ))
+}
+
+/// Wrap longitude into [-180, 180)
+fn normalize(point: GeoPoint) -> GeoPoint {
+    GeoPoint::new(point.lat, (point.lon + 180.0).rem_euclid(360.0) - 180.0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn global_grid() -> LatLonGrid {
+        LatLonGrid {
+            latitudes: (0..=36).map(|i| 90.0 - i as f64 * 5.0).collect(),
+            longitudes: (0..72).map(|i| i as f64 * 5.0).collect(),
+        }
+    }
+
+    fn uniform_field(u: f64, v: f64) -> VelocityField {
+        let grid = global_grid();
+        let n = grid.len();
+        VelocityField::new(
+            grid,
+            vec![VelocityFrame {
+                time: 0.0,
+                u: vec![u; n],
+                v: vec![v; n],
+            }],
+        )
+    }
+
+    #[test]
+    fn test_eastward_flow_on_equator() {
+        let field = uniform_field(10.0, 0.0);
+        let trajectory = integrate_rk4(&field, GeoPoint::new(0.0, 0.0), 0.0, 24.0, 1.0);
+
+        assert_eq!(trajectory.termination, Termination::Completed);
+        assert_eq!(trajectory.points.len(), 25);
+
+        // 10 m/s for 24 h is 864 km, about 7.77 degrees of longitude at the equator
+        let (end, time) = trajectory.points.last().unwrap();
+        let expected = (864.0 / EARTH_RADIUS_KM).to_degrees();
+        assert!((end.lon - expected).abs() < 1e-6);
+        assert!(end.lat.abs() < 1e-9);
+        assert_eq!(*time, 24.0);
+    }
+
+    #[test]
+    fn test_backward_integration() {
+        let field = uniform_field(0.0, 10.0);
+        let trajectory = integrate_rk4(&field, GeoPoint::new(0.0, 0.0), 10.0, 0.0, 2.5);
+
+        let (end, time) = trajectory.points.last().unwrap();
+        assert!(end.lat < 0.0);
+        assert_eq!(*time, 0.0);
+    }
+
+    #[test]
+    fn test_partial_final_step() {
+        let field = uniform_field(5.0, 5.0);
+        let trajectory = integrate_rk4(&field, GeoPoint::new(0.0, 0.0), 0.0, 2.5, 1.0);
+
+        let times: Vec<f64> = trajectory.points.iter().map(|(_, t)| *t).collect();
+        assert_eq!(times, vec![0.0, 1.0, 2.0, 2.5]);
+    }
+
+    #[test]
+    fn test_stops_on_missing_data() {
+        let grid = global_grid();
+        let n = grid.len();
+        let field = VelocityField::new(
+            grid,
+            vec![VelocityFrame {
+                time: 0.0,
+                u: vec![f64::NAN; n],
+                v: vec![0.0; n],
+            }],
+        );
+
+        let trajectory = integrate_rk4(&field, GeoPoint::new(0.0, 0.0), 0.0, 6.0, 1.0);
+        assert_eq!(trajectory.termination, Termination::LeftDomain);
+        assert_eq!(trajectory.points.len(), 1);
+    }
+
+    #[test]
+    fn test_velocity_time_interpolation() {
+        let grid = global_grid();
+        let n = grid.len();
+        let field = VelocityField::new(
+            grid,
+            vec![
+                VelocityFrame {
+                    time: 6.0,
+                    u: vec![4.0; n],
+                    v: vec![0.0; n],
+                },
+                VelocityFrame {
+                    time: 0.0,
+                    u: vec![0.0; n],
+                    v: vec![0.0; n],
+                },
+            ],
+        );
+
+        let point = GeoPoint::new(10.0, 10.0);
+        assert_eq!(field.velocity(point, 3.0), Some((2.0, 0.0)));
+        assert_eq!(field.velocity(point, -1.0), Some((0.0, 0.0)));
+        assert_eq!(field.velocity(point, 9.0), Some((4.0, 0.0)));
+    }
+
+    #[test]
+    fn test_longitude_normalization() {
+        assert_eq!(normalize(GeoPoint::new(0.0, 190.0)).lon, -170.0);
+        assert_eq!(normalize(GeoPoint::new(0.0, -180.0)).lon, -180.0);
+    }
+}
@@ -0,0 +1,532 @@
+//! Per-deployment hooks rewriting backend variables
+//!
+//! Some datasets need small fixes before they work well in the frontend:
+//! GRIB-converted variables called `VAR_2T`, precipitation in metres rather
+//! than millimetres, or fill values of 9999 that are not declared as
+//! missing. Rather than changing the dataset or the proxy, operators can
+//! describe such fixes in a hooks file passed with `--transforms`:
+//!
+//! ```json
+//! [
+//!     {"variable": "VAR_2T", "rename": "t2m"},
+//!     {"variable": "tp", "expression": "x * 1000", "attributes": {"units": "mm"}},
+//!     {"variable": "sst", "expression": "if(x >= 9999, nan, x)"},
+//!     {"variable": "lsm_raw", "hide": true}
+//! ]
+//! ```
+//!
+//! Hooks apply to the metadata catalog and to every `/data` response, so the
+//! frontend, the Earth routes and the derived products all see the rewritten
+//! variables. An `expression` maps each value `x` with the formula syntax of
+//! [`crate::expr`]; declared value ranges are mapped with it too. Hidden
+//! variables are neither listed nor served.
+//!
+//! Fixes the hooks cannot describe, such as combining or masking variables,
+//! are written as Rhai scripts, which run after the hooks. Scripts need the
+//! `scripting` feature; see `crate::scripting`.
+
+use serde::Deserialize;
+use serde_json::{json, Map, Value};
+use std::path::Path;
+#[cfg(feature = "scripting")]
+use std::sync::Arc;
+
+#[cfg(feature = "scripting")]
+use crate::scripting::TransformScript;
+use crate::{error::AppError, expr::Expression};
+
+/// Attributes holding values of the variable, mapped through expressions
+const VALUE_ATTRIBUTES: [&str; 4] = ["valid_range", "actual_range", "valid_min", "valid_max"];
+
+/// A hook as written in the hooks file
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct HookDefinition {
+    variable: String,
+    rename: Option<String>,
+    expression: Option<String>,
+    #[serde(default)]
+    attributes: Map<String, Value>,
+    #[serde(default)]
+    hide: bool,
+}
+
+/// Rewrites of one backend variable
+#[derive(Debug, Clone)]
+struct VariableHook {
+    /// Name of the variable in the backend
+    variable: String,
+    /// Name served to clients
+    served_as: String,
+    /// Formula over the value `x`
+    expression: Option<Expression>,
+    /// Attributes added to or replacing those of the catalog entry
+    attributes: Map<String, Value>,
+    /// Drop the variable from the catalog
+    hide: bool,
+}
+
+impl VariableHook {
+    fn map(&self, value: f64) -> f64 {
+        match &self.expression {
+            Some(expression) => expression.evaluate(&[value]),
+            None => value,
+        }
+    }
+}
+
+/// The variable hooks and scripts of a deployment
+#[derive(Debug, Clone, Default)]
+pub struct Transforms {
+    hooks: Vec<VariableHook>,
+    #[cfg(feature = "scripting")]
+    scripts: Vec<Arc<TransformScript>>,
+}
+
+impl Transforms {
+    /// Load the hooks of a JSON file or a Rhai script (`.rhai`), returning
+    /// the number of hooks or scripts loaded
+    pub fn load(&mut self, path: &Path) -> Result<usize, AppError> {
+        let error = |message: String| {
+            AppError::ConfigError(format!(
+                "Cannot load transforms from {}: {}",
+                path.display(),
+                message
+            ))
+        };
+        let contents = std::fs::read_to_string(path).map_err(|e| error(e.to_string()))?;
+        if path
+            .extension()
+            .is_some_and(|extension| extension == "rhai")
+        {
+            return self
+                .add_script(&contents)
+                .map(|_| 1)
+                .map_err(|e| error(e.to_string()));
+        }
+        self.add_json(&contents).map_err(|e| error(e.to_string()))
+    }
+
+    /// Add a Rhai script, run after the hooks and the scripts added before
+    #[cfg(feature = "scripting")]
+    pub fn add_script(&mut self, source: &str) -> Result<(), AppError> {
+        self.scripts
+            .push(Arc::new(TransformScript::compile(source)?));
+        Ok(())
+    }
+
+    /// Add a Rhai script, run after the hooks and the scripts added before
+    #[cfg(not(feature = "scripting"))]
+    pub fn add_script(&mut self, _source: &str) -> Result<(), AppError> {
+        Err(AppError::ConfigError(
+            "Rhai scripts require building with the `scripting` feature".to_string(),
+        ))
+    }
+
+    fn has_scripts(&self) -> bool {
+        #[cfg(feature = "scripting")]
+        return !self.scripts.is_empty();
+        #[cfg(not(feature = "scripting"))]
+        false
+    }
+
+    /// Add the hooks of a JSON document, returning the number added
+    pub fn add_json(&mut self, json: &str) -> Result<usize, AppError> {
+        let definitions: Vec<HookDefinition> = serde_json::from_str(json)
+            .map_err(|e| AppError::ConfigError(format!("Invalid transforms: {}", e)))?;
+
+        let count = definitions.len();
+        for definition in definitions {
+            let served_as = definition
+                .rename
+                .unwrap_or_else(|| definition.variable.clone());
+            // Each backend and served name must belong to a single hook
+            let overlaps = self.hooks.iter().any(|hook| {
+                hook.variable == definition.variable
+                    || hook.served_as == served_as
+                    || hook.served_as == definition.variable
+                    || hook.variable == served_as
+            });
+            if overlaps {
+                return Err(AppError::ConfigError(format!(
+                    "Transforms of '{}' overlap another hook",
+                    definition.variable
+                )));
+            }
+            let expression = definition
+                .expression
+                .map(|source| Expression::parse(&source, &["x"]))
+                .transpose()?;
+            self.hooks.push(VariableHook {
+                variable: definition.variable,
+                served_as,
+                expression,
+                attributes: definition.attributes,
+                hide: definition.hide,
+            });
+        }
+        Ok(count)
+    }
+
+    /// Whether no hooks or scripts are configured
+    pub fn is_empty(&self) -> bool {
+        self.hooks.is_empty() && !self.has_scripts()
+    }
+
+    /// Whether any of the served variables is rewritten; scripts may
+    /// rewrite any of them
+    pub fn affects(&self, variables: &[&str]) -> bool {
+        self.has_scripts()
+            || variables
+                .iter()
+                .any(|name| self.hooks.iter().any(|hook| hook.served_as == *name))
+    }
+
+    /// Fail with `NotFound` if any of the variables is hidden
+    pub fn check_served(&self, variables: &[&str]) -> Result<(), AppError> {
+        for name in variables {
+            if self.sources(name)?.is_empty() {
+                return Err(not_served(name));
+            }
+        }
+        Ok(())
+    }
+
+    /// The backend variables requested to serve `variable`, none when it
+    /// is hidden
+    fn sources(&self, variable: &str) -> Result<Vec<String>, AppError> {
+        #[allow(unused_mut)]
+        let mut names = vec![variable.to_string()];
+        // Each script names variables as served by the stage before it
+        #[cfg(feature = "scripting")]
+        for script in self.scripts.iter().rev() {
+            let mut sources = Vec::new();
+            for name in &names {
+                sources.extend(script.sources(name)?);
+            }
+            names = sources;
+        }
+
+        let mut sources: Vec<String> = Vec::new();
+        for name in names {
+            let source = match self.hooks.iter().find(|hook| hook.served_as == name) {
+                Some(hook) if hook.hide => continue,
+                Some(hook) => hook.variable.clone(),
+                None => name,
+            };
+            if !sources.contains(&source) {
+                sources.push(source);
+            }
+        }
+        Ok(sources)
+    }
+
+    /// Rewrite the catalog of a metadata document in the legacy schema,
+    /// returning the number of variables changed by hooks and of scripts
+    /// that rewrote the document
+    pub fn apply_metadata(&self, metadata: &mut Value) -> Result<usize, AppError> {
+        #[allow(unused_mut)]
+        let mut changed = self.apply_hooks_to_metadata(metadata)?;
+        #[cfg(feature = "scripting")]
+        for script in &self.scripts {
+            if script.apply_metadata(metadata)? {
+                changed += 1;
+            }
+        }
+        Ok(changed)
+    }
+
+    fn apply_hooks_to_metadata(&self, metadata: &mut Value) -> Result<usize, AppError> {
+        let Some(variables) = metadata
+            .get_mut("variables")
+            .and_then(|v| v.as_object_mut())
+        else {
+            return Ok(0);
+        };
+
+        let mut changed = 0;
+        for hook in &self.hooks {
+            let Some(mut entry) = variables.remove(&hook.variable) else {
+                continue;
+            };
+            changed += 1;
+            if hook.hide {
+                continue;
+            }
+            let Some(fields) = entry.as_object_mut() else {
+                return Err(AppError::ProxyError(format!(
+                    "Backend metadata of variable '{}' is not an object",
+                    hook.variable
+                )));
+            };
+            let attributes = fields.entry("attributes").or_insert_with(|| json!({}));
+            if !attributes.is_object() {
+                *attributes = json!({});
+            }
+            if let Value::Object(attributes) = attributes {
+                if hook.expression.is_some() {
+                    for name in VALUE_ATTRIBUTES {
+                        if let Some(value) = attributes.get_mut(name) {
+                            map_attribute(hook, value);
+                        }
+                    }
+                }
+                for (name, value) in &hook.attributes {
+                    attributes.insert(name.clone(), value.clone());
+                }
+            }
+            variables.insert(hook.served_as.clone(), entry);
+        }
+        Ok(changed)
+    }
+
+    /// A backend `/data` query string for a query naming served variables,
+    /// failing with `NotFound` for hidden variables
+    pub fn backend_query(&self, query: &str) -> Result<String, AppError> {
+        let pairs = query
+            .split('&')
+            .map(|pair| match pair.strip_prefix("vars=") {
+                Some(vars) => {
+                    let mut backend_vars: Vec<String> = Vec::new();
+                    for name in vars.split(',') {
+                        let sources = self.sources(name)?;
+                        if sources.is_empty() {
+                            return Err(not_served(name));
+                        }
+                        for source in sources {
+                            if !backend_vars.contains(&source) {
+                                backend_vars.push(source);
+                            }
+                        }
+                    }
+                    Ok(format!("vars={}", backend_vars.join(",")))
+                }
+                None => Ok(pair.to_string()),
+            });
+        Ok(pairs.collect::<Result<Vec<_>, _>>()?.join("&"))
+    }
+
+    /// Rewrite a `/data` response in the legacy schema for the served names
+    /// of `query`, the query given to [`Transforms::backend_query`]
+    pub fn apply_data(&self, query: &str, response: &mut Value) -> Result<(), AppError> {
+        self.apply_hooks_to_data(response);
+        if !self.has_scripts() {
+            return Ok(());
+        }
+        #[cfg(feature = "scripting")]
+        for script in &self.scripts {
+            script.apply_data(response)?;
+        }
+        // Variables fetched only to compute others are not returned
+        let requested: Vec<&str> = query
+            .split('&')
+            .filter_map(|pair| pair.strip_prefix("vars="))
+            .flat_map(|vars| vars.split(','))
+            .collect();
+        if let Some(data) = response.get_mut("data").and_then(|d| d.as_object_mut()) {
+            data.retain(|name, _| requested.contains(&name.as_str()));
+        }
+        Ok(())
+    }
+
+    fn apply_hooks_to_data(&self, response: &mut Value) {
+        let Some(data) = response.get_mut("data").and_then(|d| d.as_object_mut()) else {
+            return;
+        };
+        for hook in &self.hooks {
+            let Some(mut values) = data.remove(&hook.variable) else {
+                continue;
+            };
+            if hook.expression.is_some() {
+                if let Some(values) = values.as_array_mut() {
+                    for value in values {
+                        let mapped = hook.map(value.as_f64().unwrap_or(f64::NAN));
+                        *value = if mapped.is_finite() {
+                            json!(mapped)
+                        } else {
+                            Value::Null
+                        };
+                    }
+                }
+            }
+            data.insert(hook.served_as.clone(), values);
+        }
+    }
+}
+
+fn not_served(variable: &str) -> AppError {
+    AppError::NotFound(format!("Variable '{}' not found in metadata", variable))
+}
+
+/// Map a numeric attribute or pair of numbers through a hook's expression,
+/// keeping ranges in ascending order
+fn map_attribute(hook: &VariableHook, value: &mut Value) {
+    match value {
+        Value::Number(number) => {
+            if let Some(mapped) = number.as_f64().map(|v| hook.map(v)) {
+                *value = json!(mapped);
+            }
+        }
+        Value::Array(items) => {
+            let mut mapped: Vec<f64> = items
+                .iter()
+                .filter_map(Value::as_f64)
+                .map(|v| hook.map(v))
+                .collect();
+            if mapped.len() == items.len() {
+                mapped.sort_by(f64::total_cmp);
+                *value = json!(mapped);
+            }
+        }
+        _ => {}
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn transforms() -> Transforms {
+        let mut transforms = Transforms::default();
+        let count = transforms
+            .add_json(
+                r#"[
+                    {"variable": "VAR_2T", "rename": "t2m"},
+                    {"variable": "tp", "expression": "if(x < 0, nan, x * -1000)", "attributes": {"units": "mm"}},
+                    {"variable": "junk", "hide": true}
+                ]"#,
+            )
+            .unwrap();
+        assert_eq!(count, 3);
+        transforms
+    }
+
+    #[test]
+    fn test_apply_metadata() {
+        let mut metadata = json!({"variables": {
+            "VAR_2T": {"dimensions": ["latitude", "longitude"], "attributes": {"units": "K"}},
+            "tp": {"attributes": {"units": "m", "valid_range": [0.0, 0.5], "valid_max": 0.5}},
+            "junk": {},
+            "sp": {}
+        }});
+        assert_eq!(transforms().apply_metadata(&mut metadata).unwrap(), 3);
+
+        let variables = metadata["variables"].as_object().unwrap();
+        let names: Vec<&str> = variables.keys().map(String::as_str).collect();
+        assert_eq!(names, ["sp", "t2m", "tp"]);
+        assert_eq!(variables["t2m"]["attributes"]["units"], "K");
+        assert_eq!(
+            variables["tp"]["attributes"],
+            json!({"units": "mm", "valid_range": [-500.0, -0.0], "valid_max": -500.0})
+        );
+
+        // Invalid backend metadata fails the request instead of panicking
+        let mut metadata = json!({"variables": {"VAR_2T": "K", "tp": [1, 2]}});
+        assert!(matches!(
+            transforms().apply_metadata(&mut metadata),
+            Err(AppError::ProxyError(_))
+        ));
+    }
+
+    #[test]
+    fn test_data_round_trip() {
+        let transforms = transforms();
+        assert!(transforms.affects(&["sp", "t2m"]));
+        assert!(!transforms.affects(&["VAR_2T"]));
+        assert_eq!(
+            transforms
+                .backend_query("vars=t2m,tp,sp&time=1&format=json")
+                .unwrap(),
+            "vars=VAR_2T,tp,sp&time=1&format=json"
+        );
+
+        let mut response = json!({"data": {
+            "VAR_2T": [280.0, null],
+            "tp": [0.002, -1.0, null],
+            "sp": [1.0]
+        }});
+        transforms
+            .apply_data("vars=t2m,tp,sp&time=1", &mut response)
+            .unwrap();
+        assert_eq!(
+            response["data"],
+            json!({"t2m": [280.0, null], "tp": [-2.0, null, null], "sp": [1.0]})
+        );
+    }
+
+    #[test]
+    fn test_hidden_variables_are_not_served() {
+        let transforms = transforms();
+        assert!(transforms.check_served(&["t2m", "sp"]).is_ok());
+        assert!(matches!(
+            transforms.check_served(&["t2m", "junk"]),
+            Err(AppError::NotFound(_))
+        ));
+        assert!(matches!(
+            transforms.backend_query("vars=junk&time=1"),
+            Err(AppError::NotFound(_))
+        ));
+    }
+
+    #[cfg(feature = "scripting")]
+    #[test]
+    fn test_scripts_after_hooks() {
+        let mut transforms = transforms();
+        transforms
+            .add_script(
+                r#"
+                fn sources(name) {
+                    switch name {
+                        "wind_speed" => ["u10", "v10"],
+                        "tp" => [],
+                        _ => [name],
+                    }
+                }
+
+                fn data(response) {
+                    let v = response.data.v10;
+                    response.data.wind_speed = response.data.u10.map(|u, i| sqrt(u * u + v[i] * v[i]));
+                    response
+                }
+                "#,
+            )
+            .unwrap();
+        assert!(transforms.affects(&["sp"]));
+        assert_eq!(
+            transforms
+                .backend_query("vars=wind_speed,t2m&time=1")
+                .unwrap(),
+            "vars=u10,v10,VAR_2T&time=1"
+        );
+        assert!(transforms.check_served(&["tp"]).is_err());
+
+        let mut response = json!({"data": {
+            "VAR_2T": [280.0],
+            "u10": [3.0],
+            "v10": [4.0]
+        }});
+        transforms
+            .apply_data("vars=wind_speed,t2m&time=1", &mut response)
+            .unwrap();
+        assert_eq!(
+            response["data"],
+            json!({"t2m": [280.0], "wind_speed": [5.0]})
+        );
+    }
+
+    #[test]
+    fn test_conflicting_hooks() {
+        let mut transforms = Transforms::default();
+        assert!(transforms
+            .add_json(r#"[{"variable": "a", "rename": "b"}, {"variable": "c", "rename": "b"}]"#)
+            .is_err());
+        let mut transforms = Transforms::default();
+        assert!(transforms
+            .add_json(r#"[{"variable": "a"}, {"variable": "a", "hide": true}]"#)
+            .is_err());
+        let mut transforms = Transforms::default();
+        assert!(transforms
+            .add_json(r#"[{"variable": "a", "expression": "y * 2"}]"#)
+            .is_err());
+    }
+}
@@ -0,0 +1,240 @@
+//! Unit systems of served values
+//!
+//! Datasets are served in the units they are stored in, usually SI: kelvin,
+//! metres per second and pascals. Clients that show values to people who
+//! think in other units can ask for them with `?units=` on `/proxy/data`,
+//! the Earth data routes and the frame bundles:
+//!
+//! | `units`    | Temperature | Speed | Pressure |
+//! |------------|-------------|-------|----------|
+//! | `si`       | as stored   | as stored | as stored |
+//! | `metric`   | °C          | km/h  | hPa      |
+//! | `imperial` | °F          | mph   | inHg     |
+//! | `kt`       | °C          | kn    | hPa      |
+//!
+//! Values in other units are left as they are. The deployment's preferred
+//! system, `--units`, is published at `/api/prefs` for clients to default to;
+//! responses without `?units=` are not converted.
+
+use axum::{extract::State, Json};
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use std::{fmt, str::FromStr, sync::Arc};
+use utoipa::ToSchema;
+
+use crate::{error::AppError, server::AppState};
+
+/// A system of display units
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize, ToSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum UnitSystem {
+    /// Values as stored in the dataset
+    #[default]
+    Si,
+    Metric,
+    Imperial,
+    /// Knots for speeds, otherwise metric
+    #[serde(rename = "kt", alias = "nautical")]
+    Nautical,
+}
+
+impl FromStr for UnitSystem {
+    type Err = AppError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "si" => Ok(UnitSystem::Si),
+            "metric" => Ok(UnitSystem::Metric),
+            "imperial" => Ok(UnitSystem::Imperial),
+            "kt" | "nautical" => Ok(UnitSystem::Nautical),
+            _ => Err(AppError::ConfigError(format!(
+                "Unknown unit system '{}', expected si, metric, imperial or kt",
+                s
+            ))),
+        }
+    }
+}
+
+impl fmt::Display for UnitSystem {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            UnitSystem::Si => "si",
+            UnitSystem::Metric => "metric",
+            UnitSystem::Imperial => "imperial",
+            UnitSystem::Nautical => "kt",
+        })
+    }
+}
+
+/// Kinds of quantity with display units
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Quantity {
+    Temperature,
+    Speed,
+    Pressure,
+}
+
+impl Quantity {
+    /// The quantity measured in SI `units`, as written in CF attributes
+    fn of(units: &str) -> Option<Self> {
+        match units.trim() {
+            "K" | "kelvin" | "Kelvin" => Some(Quantity::Temperature),
+            "m s**-1" | "m s-1" | "m s^-1" | "m/s" | "m.s-1" => Some(Quantity::Speed),
+            "Pa" | "pascal" | "Pascal" => Some(Quantity::Pressure),
+            _ => None,
+        }
+    }
+}
+
+/// A linear change of units, `value * scale + offset`
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Conversion {
+    /// The units after conversion
+    pub units: &'static str,
+    pub scale: f64,
+    pub offset: f64,
+}
+
+impl Conversion {
+    const fn new(units: &'static str, scale: f64, offset: f64) -> Self {
+        Self {
+            units,
+            scale,
+            offset,
+        }
+    }
+
+    pub fn apply(&self, value: f64) -> f64 {
+        value * self.scale + self.offset
+    }
+}
+
+const CELSIUS: Conversion = Conversion::new("°C", 1.0, -273.15);
+const FAHRENHEIT: Conversion = Conversion::new("°F", 1.8, -459.67);
+const KILOMETRES_PER_HOUR: Conversion = Conversion::new("km/h", 3.6, 0.0);
+const MILES_PER_HOUR: Conversion = Conversion::new("mph", 3600.0 / 1609.344, 0.0);
+const KNOTS: Conversion = Conversion::new("kn", 3600.0 / 1852.0, 0.0);
+const HECTOPASCALS: Conversion = Conversion::new("hPa", 0.01, 0.0);
+const INCHES_OF_MERCURY: Conversion = Conversion::new("inHg", 1.0 / 3386.389, 0.0);
+
+impl UnitSystem {
+    /// How values in `units` are shown in this system, `None` when they are
+    /// served as they are
+    pub fn conversion(self, units: &str) -> Option<Conversion> {
+        let quantity = Quantity::of(units)?;
+        match (self, quantity) {
+            (UnitSystem::Si, _) => None,
+            (UnitSystem::Imperial, Quantity::Temperature) => Some(FAHRENHEIT),
+            (_, Quantity::Temperature) => Some(CELSIUS),
+            (UnitSystem::Metric, Quantity::Speed) => Some(KILOMETRES_PER_HOUR),
+            (UnitSystem::Imperial, Quantity::Speed) => Some(MILES_PER_HOUR),
+            (UnitSystem::Nautical, Quantity::Speed) => Some(KNOTS),
+            (UnitSystem::Imperial, Quantity::Pressure) => Some(INCHES_OF_MERCURY),
+            (_, Quantity::Pressure) => Some(HECTOPASCALS),
+        }
+    }
+
+    /// Convert the variables of a `/data` response in the legacy schema,
+    /// updating their units in its metadata
+    pub fn convert_data_response(self, response: &mut Value) {
+        let Some(variables) = response
+            .pointer_mut("/metadata/variables")
+            .and_then(|v| v.as_object_mut())
+        else {
+            return;
+        };
+        let mut conversions = Vec::new();
+        for (name, info) in variables.iter_mut() {
+            let Some(conversion) = info
+                .get("units")
+                .and_then(|u| u.as_str())
+                .and_then(|units| self.conversion(units))
+            else {
+                continue;
+            };
+            info["units"] = Value::from(conversion.units);
+            conversions.push((name.clone(), conversion));
+        }
+
+        for (name, conversion) in conversions {
+            let Some(values) = response
+                .get_mut("data")
+                .and_then(|d| d.get_mut(&name))
+                .and_then(|v| v.as_array_mut())
+            else {
+                continue;
+            };
+            for value in values.iter_mut() {
+                if let Some(number) = value.as_f64() {
+                    *value = Value::from(conversion.apply(number));
+                }
+            }
+        }
+    }
+}
+
+/// Handler for `/api/prefs` - the display defaults of the deployment
+#[utoipa::path(
+    get,
+    path = "/api/prefs",
+    tag = "service",
+    summary = "Display defaults of the deployment",
+    responses((status = 200, body = serde_json::Value))
+)]
+pub async fn preferences(State(state): State<Arc<AppState>>) -> Json<Value> {
+    Json(json!({"units": state.units}))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_conversions() {
+        let celsius = UnitSystem::Metric.conversion("K").unwrap();
+        assert_eq!(celsius.units, "°C");
+        assert!((celsius.apply(273.15)).abs() < 1e-9);
+
+        let fahrenheit = UnitSystem::Imperial.conversion("K").unwrap();
+        assert!((fahrenheit.apply(273.15) - 32.0).abs() < 1e-9);
+
+        let knots = UnitSystem::Nautical.conversion("m s**-1").unwrap();
+        assert!((knots.apply(10.0) - 19.438445).abs() < 1e-6);
+        assert_eq!(UnitSystem::Nautical.conversion("Pa").unwrap().units, "hPa");
+
+        let inhg = UnitSystem::Imperial.conversion("Pa").unwrap();
+        assert!((inhg.apply(101325.0) - 29.921).abs() < 1e-3);
+
+        assert_eq!(UnitSystem::Si.conversion("K"), None);
+        assert_eq!(UnitSystem::Imperial.conversion("kg m**-2"), None);
+    }
+
+    #[test]
+    fn test_parse() {
+        assert_eq!("kt".parse::<UnitSystem>().unwrap(), UnitSystem::Nautical);
+        assert_eq!(
+            serde_json::from_value::<UnitSystem>(json!("nautical")).unwrap(),
+            UnitSystem::Nautical
+        );
+        assert_eq!(UnitSystem::Nautical.to_string(), "kt");
+        assert!("furlongs".parse::<UnitSystem>().is_err());
+    }
+
+    #[test]
+    fn test_convert_data_response() {
+        let mut response = json!({
+            "metadata": {
+                "variables": {
+                    "t2m": {"units": "K"},
+                    "tp": {"units": "m"}
+                }
+            },
+            "data": {"t2m": [273.15, null], "tp": [0.5]}
+        });
+        UnitSystem::Metric.convert_data_response(&mut response);
+        assert_eq!(response["metadata"]["variables"]["t2m"]["units"], "°C");
+        assert!(response["data"]["t2m"][0].as_f64().unwrap().abs() < 1e-9);
+        assert!(response["data"]["t2m"][1].is_null());
+        assert_eq!(response["data"]["tp"], json!([0.5]));
+    }
+}
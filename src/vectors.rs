@@ -0,0 +1,240 @@
+//! Pairing of vector components
+//!
+//! Wind and current fields come as separate eastward and northward
+//! variables. Besides the `u10`/`v10` style names, datasets converted from
+//! GRIB or produced by ocean models use names like `UGRD`/`VGRD`, `10u`/`10v`
+//! or `water_u`/`water_v`, and the pairing is often only evident from their
+//! CF `standard_name` (`eastward_wind`/`northward_wind`) or `long_name`
+//! (`10 metre U wind component`/`10 metre V wind component`). Components are
+//! paired by those attributes first and by their names last.
+
+use serde_json::{Map, Value};
+
+/// Attribute naming the northward partner of an eastward component in the
+/// metadata served to the frontend
+pub const NORTHWARD_COMPONENT_ATTRIBUTE: &str = "northward_component";
+
+/// Words marking an eastward component and their northward counterparts
+const COMPONENT_WORDS: [(&str, &str); 3] = [
+    ("u", "v"),
+    ("eastward", "northward"),
+    ("zonal", "meridional"),
+];
+
+/// Text of a variable compared between components, by name and variable
+type PairingKey = fn(&str, &Value) -> Option<String>;
+
+/// Standard names, then long names, then the names themselves
+const PAIRING_KEYS: [PairingKey; 3] = [
+    |_, variable| attribute(variable, "standard_name"),
+    |_, variable| attribute(variable, "long_name").map(|n| n.to_lowercase()),
+    |name, _| Some(name.to_string()),
+];
+
+/// The eastward and northward components of each vector in `variables`,
+/// in the order of the eastward components
+pub fn pair_vector_components(variables: &Map<String, Value>) -> Vec<(String, String)> {
+    let mut pairs: Vec<(String, String)> = Vec::new();
+    for (name, variable) in variables {
+        let paired = |candidate: &str| pairs.iter().any(|(u, v)| u == candidate || v == candidate);
+        if paired(name) {
+            continue;
+        }
+
+        let by_name = northward_name(name);
+        let partner = PAIRING_KEYS.iter().find_map(|rule| {
+            let northward = swap_component_word(&rule(name, variable)?)?;
+            let matches: Vec<&String> = variables
+                .iter()
+                .filter(|(other, other_variable)| {
+                    *other != name
+                        && !paired(other)
+                        && same_dimensions(variable, other_variable)
+                        && rule(other, other_variable).as_ref() == Some(&northward)
+                })
+                .map(|(other, _)| other)
+                .collect();
+            match matches.as_slice() {
+                [single] => Some((*single).clone()),
+                _ => matches
+                    .into_iter()
+                    .find(|other| by_name.as_ref() == Some(*other))
+                    .cloned(),
+            }
+        });
+        let partner = partner.or_else(|| {
+            by_name.filter(|v| {
+                variables
+                    .get(v)
+                    .is_some_and(|other| !paired(v) && same_dimensions(variable, other))
+            })
+        });
+        if let Some(partner) = partner {
+            pairs.push((name.clone(), partner));
+        }
+    }
+    pairs
+}
+
+/// Record each eastward component's partner under
+/// [`NORTHWARD_COMPONENT_ATTRIBUTE`], returning the number of pairs
+pub fn register_vector_pairs(metadata: &mut Value) -> usize {
+    let Some(variables) = metadata
+        .get_mut("variables")
+        .and_then(|v| v.as_object_mut())
+    else {
+        return 0;
+    };
+
+    let pairs = pair_vector_components(variables);
+    for (u, v) in &pairs {
+        let variable = &mut variables[u];
+        if !variable.get("attributes").is_some_and(Value::is_object) {
+            variable["attributes"] = Value::Object(Map::new());
+        }
+        variable["attributes"][NORTHWARD_COMPONENT_ATTRIBUTE] = Value::String(v.clone());
+    }
+    pairs.len()
+}
+
+/// The northward name of a `u`-prefixed eastward component, such as `UGRD`
+fn northward_name(name: &str) -> Option<String> {
+    swap_component_word(name).or_else(|| {
+        (name.starts_with('u') || name.starts_with('U'))
+            .then(|| name.replacen('u', "v", 1).replacen('U', "V", 1))
+    })
+}
+
+/// `text` with its first eastward word replaced by the northward one, in the
+/// same case. Words may carry digits, as in `10u` or `u850`.
+fn swap_component_word(text: &str) -> Option<String> {
+    text.split(|c: char| !c.is_ascii_alphanumeric())
+        .find_map(|token| {
+            let word = token.trim_matches(|c: char| c.is_ascii_digit());
+            let (_, northward) = COMPONENT_WORDS
+                .iter()
+                .find(|(eastward, _)| word.eq_ignore_ascii_case(eastward))?;
+            let start = word.as_ptr() as usize - text.as_ptr() as usize;
+            Some((start, word.len(), matching_case(word, northward)))
+        })
+        .map(|(start, len, northward)| {
+            format!("{}{}{}", &text[..start], northward, &text[start + len..])
+        })
+}
+
+/// `word` in the case of `like`: upper, capitalized or lower
+fn matching_case(like: &str, word: &str) -> String {
+    if like.chars().all(|c| c.is_ascii_uppercase()) {
+        word.to_uppercase()
+    } else if like.starts_with(|c: char| c.is_ascii_uppercase()) {
+        word[..1].to_uppercase() + &word[1..]
+    } else {
+        word.to_string()
+    }
+}
+
+fn attribute(variable: &Value, name: &str) -> Option<String> {
+    variable
+        .get("attributes")?
+        .get(name)?
+        .as_str()
+        .map(str::to_string)
+}
+
+/// Whether two variables share their dimensions, when both declare them
+fn same_dimensions(a: &Value, b: &Value) -> bool {
+    match (a.get("dimensions"), b.get("dimensions")) {
+        (Some(a), Some(b)) => a == b,
+        _ => true,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn pairs(variables: Value) -> Vec<(String, String)> {
+        pair_vector_components(variables.as_object().unwrap())
+    }
+
+    fn pair(u: &str, v: &str) -> (String, String) {
+        (u.to_string(), v.to_string())
+    }
+
+    #[test]
+    fn test_swap_component_word() {
+        assert_eq!(
+            swap_component_word("10 metre u wind component").as_deref(),
+            Some("10 metre v wind component")
+        );
+        assert_eq!(
+            swap_component_word("surface_eastward_sea_water_velocity").as_deref(),
+            Some("surface_northward_sea_water_velocity")
+        );
+        assert_eq!(swap_component_word("10u").as_deref(), Some("10v"));
+        assert_eq!(swap_component_word("water_U").as_deref(), Some("water_V"));
+        assert_eq!(swap_component_word("UGRD"), None);
+        assert_eq!(northward_name("UGRD").as_deref(), Some("VGRD"));
+    }
+
+    #[test]
+    fn test_pairs_by_attributes_and_names() {
+        let dims = json!(["time", "latitude", "longitude"]);
+        let found = pairs(json!({
+            "10u": {"dimensions": dims},
+            "10v": {"dimensions": dims},
+            "UGRD": {"dimensions": dims},
+            "VGRD": {"dimensions": dims},
+            "east": {"dimensions": dims, "attributes": {"standard_name": "eastward_sea_water_velocity"}},
+            "north": {"dimensions": dims, "attributes": {"standard_name": "northward_sea_water_velocity"}},
+            "var165": {"dimensions": dims, "attributes": {"long_name": "10 metre U wind component"}},
+            "var166": {"dimensions": dims, "attributes": {"long_name": "10 metre V wind component"}},
+            "water_u": {"dimensions": dims},
+            "water_v": {"dimensions": dims}
+        }));
+        assert_eq!(
+            found,
+            [
+                pair("10u", "10v"),
+                pair("UGRD", "VGRD"),
+                pair("east", "north"),
+                pair("var165", "var166"),
+                pair("water_u", "water_v"),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_ambiguous_long_names_fall_back_to_names() {
+        let on_levels = json!(["time", "level", "latitude", "longitude"]);
+        let surface = json!(["time", "latitude", "longitude"]);
+        let found = pairs(json!({
+            "u": {"dimensions": on_levels, "attributes": {"long_name": "U component of wind"}},
+            "u_ens": {"dimensions": on_levels, "attributes": {"long_name": "U component of wind"}},
+            "v": {"dimensions": on_levels, "attributes": {"long_name": "V component of wind"}},
+            "v_ens": {"dimensions": on_levels, "attributes": {"long_name": "V component of wind"}},
+            "u10": {"dimensions": surface},
+            "v10": {"dimensions": on_levels}
+        }));
+        assert_eq!(found, [pair("u", "v"), pair("u_ens", "v_ens")]);
+    }
+
+    #[test]
+    fn test_register_vector_pairs() {
+        let mut metadata = json!({
+            "variables": {
+                "UGRD": {"dimensions": ["latitude", "longitude"]},
+                "VGRD": {"dimensions": ["latitude", "longitude"], "attributes": {"units": "m/s"}}
+            }
+        });
+        assert_eq!(register_vector_pairs(&mut metadata), 1);
+        assert_eq!(
+            metadata["variables"]["UGRD"]["attributes"][NORTHWARD_COMPONENT_ATTRIBUTE],
+            "VGRD"
+        );
+        assert!(metadata["variables"]["VGRD"]["attributes"]
+            .get(NORTHWARD_COMPONENT_ATTRIBUTE)
+            .is_none());
+    }
+}
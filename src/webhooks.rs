@@ -0,0 +1,313 @@
+//! Webhook notifications when new data arrives
+//!
+//! The `refresh-metadata` task of the [`crate::scheduler`] watches the
+//! backend catalog. When it finds timesteps that were not there before, every
+//! URL configured with `--webhook` receives a `POST` describing them:
+//!
+//! ```json
+//! {
+//!     "event": "new_data",
+//!     "dataset": "GFS 0.25°",
+//!     "data_version": "3f2a9c0e5b7d1a44",
+//!     "variables": ["t2m", "u10", "v10"],
+//!     "new_times": ["2024-03-01T06:00:00+00:00", "2024-03-01T09:00:00+00:00"],
+//!     "latest_time": "2024-03-01T09:00:00+00:00"
+//! }
+//! ```
+//!
+//! Chat services expect their own payloads, so `--webhook-template` replaces
+//! this body with a JSON document whose strings may contain `{dataset}`,
+//! `{data_version}`, `{variables}`, `{new_times}`, `{latest_time}` and
+//! `{count}` placeholders, e.g. `{"text": "{count} new {dataset} timesteps up
+//! to {latest_time}"}`. A string that is exactly `"{variables}"` or
+//! `"{new_times}"` becomes a JSON array.
+
+use futures::future::join_all;
+use serde_json::{json, Value};
+use std::{path::Path, time::Duration};
+use tracing::{info, warn};
+
+use crate::{error::AppError, timesteps::to_iso};
+
+/// Time allowed for each webhook delivery
+const DELIVERY_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Startup configuration of the webhooks
+#[derive(Debug, Clone, Default)]
+pub struct WebhookConfig {
+    /// URLs notified of new data
+    pub urls: Vec<reqwest::Url>,
+    /// Body sent instead of the default payload
+    pub template: Option<Value>,
+}
+
+impl WebhookConfig {
+    /// Add a webhook URL, which must be `http` or `https`
+    pub fn add_url(&mut self, url: &str) -> Result<(), AppError> {
+        let parsed = reqwest::Url::parse(url)
+            .ok()
+            .filter(|url| matches!(url.scheme(), "http" | "https"))
+            .ok_or_else(|| AppError::ConfigError(format!("Invalid webhook URL '{}'", url)))?;
+        self.urls.push(parsed);
+        Ok(())
+    }
+
+    /// Load the payload template from a JSON file
+    pub fn load_template(&mut self, path: &Path) -> Result<(), AppError> {
+        let error = |message: String| {
+            AppError::ConfigError(format!(
+                "Cannot load webhook template from {}: {}",
+                path.display(),
+                message
+            ))
+        };
+        let contents = std::fs::read_to_string(path).map_err(|e| error(e.to_string()))?;
+        self.template = Some(serde_json::from_str(&contents).map_err(|e| error(e.to_string()))?);
+        Ok(())
+    }
+}
+
+/// New timesteps found in the dataset
+#[derive(Debug, Clone, PartialEq)]
+pub struct NewData {
+    pub dataset: String,
+    pub data_version: String,
+    /// Variables with a time dimension
+    pub variables: Vec<String>,
+    /// Backend time values not seen before, in ascending order
+    pub new_times: Vec<f64>,
+}
+
+impl NewData {
+    /// Compare the time axis of `metadata` with the times seen before,
+    /// returning the new ones if there are any
+    pub fn detect(
+        previous_times: &[f64],
+        metadata: &Value,
+        data_version: &str,
+        default_dataset: &str,
+    ) -> Option<Self> {
+        let mut new_times: Vec<f64> = metadata
+            .pointer("/coordinates/time")
+            .and_then(Value::as_array)?
+            .iter()
+            .filter_map(Value::as_f64)
+            .filter(|time| !previous_times.contains(time))
+            .collect();
+        if new_times.is_empty() {
+            return None;
+        }
+        new_times.sort_by(f64::total_cmp);
+
+        let variables = metadata
+            .get("variables")
+            .and_then(Value::as_object)
+            .map(|variables| {
+                variables
+                    .iter()
+                    .filter(|(name, variable)| {
+                        name.as_str() != "time"
+                            && variable
+                                .get("dimensions")
+                                .and_then(Value::as_array)
+                                .is_some_and(|dims| dims.iter().any(|dim| dim == "time"))
+                    })
+                    .map(|(name, _)| name.clone())
+                    .collect()
+            })
+            .unwrap_or_default();
+        let dataset = metadata
+            .pointer("/global_attributes/title")
+            .and_then(Value::as_str)
+            .unwrap_or(default_dataset)
+            .to_string();
+
+        Some(Self {
+            dataset,
+            data_version: data_version.to_string(),
+            variables,
+            new_times,
+        })
+    }
+
+    fn new_times_iso(&self) -> Vec<String> {
+        self.new_times.iter().map(|time| to_iso(*time)).collect()
+    }
+
+    fn latest_time_iso(&self) -> String {
+        self.new_times
+            .last()
+            .map(|time| to_iso(*time))
+            .unwrap_or_default()
+    }
+
+    /// The default webhook payload
+    pub fn payload(&self) -> Value {
+        json!({
+            "event": "new_data",
+            "dataset": self.dataset,
+            "data_version": self.data_version,
+            "variables": self.variables,
+            "new_times": self.new_times_iso(),
+            "latest_time": self.latest_time_iso(),
+        })
+    }
+
+    /// `template` with its placeholders filled in
+    pub fn render(&self, template: &Value) -> Value {
+        match template {
+            Value::String(s) if s == "{variables}" => json!(self.variables),
+            Value::String(s) if s == "{new_times}" => json!(self.new_times_iso()),
+            Value::String(s) => Value::String(
+                s.replace("{dataset}", &self.dataset)
+                    .replace("{data_version}", &self.data_version)
+                    .replace("{variables}", &self.variables.join(", "))
+                    .replace("{new_times}", &self.new_times_iso().join(", "))
+                    .replace("{latest_time}", &self.latest_time_iso())
+                    .replace("{count}", &self.new_times.len().to_string()),
+            ),
+            Value::Array(items) => Value::Array(items.iter().map(|v| self.render(v)).collect()),
+            Value::Object(fields) => Value::Object(
+                fields
+                    .iter()
+                    .map(|(name, value)| (name.clone(), self.render(value)))
+                    .collect(),
+            ),
+            other => other.clone(),
+        }
+    }
+}
+
+/// Delivers new data notifications
+#[derive(Debug, Clone, Default)]
+pub struct Webhooks {
+    config: WebhookConfig,
+    /// Separate from the backend client, whose credentials and headers must
+    /// not reach third parties
+    client: reqwest::Client,
+}
+
+impl Webhooks {
+    pub fn new(config: WebhookConfig) -> Result<Self, AppError> {
+        let client = reqwest::Client::builder()
+            .timeout(DELIVERY_TIMEOUT)
+            .user_agent(concat!("rossby-vis/", env!("CARGO_PKG_VERSION")))
+            .build()
+            .map_err(|e| AppError::ConfigError(format!("Cannot create webhook client: {}", e)))?;
+        Ok(Self { config, client })
+    }
+
+    /// Whether no webhook is configured
+    pub fn is_empty(&self) -> bool {
+        self.config.urls.is_empty()
+    }
+
+    /// Number of configured webhooks
+    pub fn len(&self) -> usize {
+        self.config.urls.len()
+    }
+
+    /// Send `event` to every webhook, returning the number that accepted it
+    pub async fn notify(&self, event: &NewData) -> usize {
+        let body = match &self.config.template {
+            Some(template) => event.render(template),
+            None => event.payload(),
+        };
+        let deliveries = self.config.urls.iter().map(|url| {
+            let request = self.client.post(url.clone()).json(&body);
+            async move {
+                match request.send().await {
+                    Ok(response) if response.status().is_success() => {
+                        info!(webhook = %url, "Notified webhook of new data");
+                        true
+                    }
+                    Ok(response) => {
+                        warn!(webhook = %url, status = %response.status(), "Webhook refused notification");
+                        false
+                    }
+                    Err(e) => {
+                        warn!(webhook = %url, "Webhook notification failed: {}", e);
+                        false
+                    }
+                }
+            }
+        });
+        join_all(deliveries)
+            .await
+            .into_iter()
+            .filter(|delivered| *delivered)
+            .count()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn metadata() -> Value {
+        json!({
+            "global_attributes": {"title": "GFS"},
+            "coordinates": {"time": [700470.0, 700464.0, 700476.0]},
+            "variables": {
+                "time": {"dimensions": ["time"]},
+                "lsm": {"dimensions": ["latitude", "longitude"]},
+                "t2m": {"dimensions": ["time", "latitude", "longitude"]}
+            }
+        })
+    }
+
+    #[test]
+    fn test_detect_new_times() {
+        assert_eq!(
+            NewData::detect(&[700464.0, 700470.0, 700476.0], &metadata(), "v1", "x"),
+            None
+        );
+
+        let event = NewData::detect(&[700464.0], &metadata(), "v2", "Rossby").unwrap();
+        assert_eq!(event.dataset, "GFS");
+        assert_eq!(event.variables, ["t2m"]);
+        assert_eq!(event.new_times, [700470.0, 700476.0]);
+        assert_eq!(
+            event.payload(),
+            json!({
+                "event": "new_data",
+                "dataset": "GFS",
+                "data_version": "v2",
+                "variables": ["t2m"],
+                "new_times": ["1979-11-29T06:00:00+00:00", "1979-11-29T12:00:00+00:00"],
+                "latest_time": "1979-11-29T12:00:00+00:00"
+            })
+        );
+    }
+
+    #[test]
+    fn test_render_template() {
+        let event = NewData::detect(&[], &metadata(), "v1", "Rossby").unwrap();
+        let template = json!({
+            "text": "{count} new {dataset} timesteps up to {latest_time}",
+            "fields": {"vars": "{variables}", "ok": true},
+            "times": "{new_times}"
+        });
+        assert_eq!(
+            event.render(&template),
+            json!({
+                "text": "3 new GFS timesteps up to 1979-11-29T12:00:00+00:00",
+                "fields": {"vars": ["t2m"], "ok": true},
+                "times": [
+                    "1979-11-29T00:00:00+00:00",
+                    "1979-11-29T06:00:00+00:00",
+                    "1979-11-29T12:00:00+00:00"
+                ]
+            })
+        );
+    }
+
+    #[test]
+    fn test_webhook_urls() {
+        let mut config = WebhookConfig::default();
+        assert!(config.add_url("https://hooks.example.com/rossby").is_ok());
+        assert!(config.add_url("ftp://example.com").is_err());
+        assert!(config.add_url("not a url").is_err());
+        assert_eq!(config.urls.len(), 1);
+    }
+}
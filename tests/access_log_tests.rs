@@ -0,0 +1,63 @@
+//! Integration tests for the Apache-style access log
+
+use axum::{
+    body::Body,
+    http::{Request, StatusCode},
+    middleware,
+    routing::get,
+    Router,
+};
+use rossby_vis::{
+    access_log::{self, AccessLogConfig, AccessLogTarget},
+    middleware::request_tracing_middleware,
+    server::AppState,
+};
+use std::sync::Arc;
+use tower::ServiceExt;
+
+#[tokio::test]
+async fn test_writes_combined_line_per_request() {
+    let path = std::env::temp_dir()
+        .join(format!("rossby-vis-access-{}", uuid::Uuid::new_v4()))
+        .join("access.log");
+    access_log::init(&AccessLogConfig::new(AccessLogTarget::File(path.clone()))).unwrap();
+    assert!(access_log::enabled());
+
+    let state = Arc::new(AppState::new(
+        "http://localhost:8000".to_string(),
+        reqwest::Client::new(),
+    ));
+    let app = Router::new()
+        .route("/test", get(|| async { "test response" }))
+        .layer(middleware::from_fn_with_state(
+            state.clone(),
+            request_tracing_middleware,
+        ))
+        .with_state(state);
+
+    let request = Request::builder()
+        .uri("/test?vars=t2m")
+        .header("x-forwarded-for", "203.0.113.7, 10.0.0.1")
+        .header("referer", "https://example.org/")
+        .header("user-agent", "test-agent")
+        .body(Body::empty())
+        .unwrap();
+    let response = app.oneshot(request).await.unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    // The line is written once the body has been sent
+    hyper::body::to_bytes(response.into_body()).await.unwrap();
+    access_log::flush();
+
+    let log = std::fs::read_to_string(&path).unwrap();
+    let line = log.lines().next().unwrap();
+    assert!(line.starts_with("203.0.113.7 - - ["), "{}", line);
+    assert!(
+        line.ends_with(
+            "] \"GET /test?vars=t2m HTTP/1.1\" 200 13 \"https://example.org/\" \"test-agent\""
+        ),
+        "{}",
+        line
+    );
+
+    std::fs::remove_dir_all(path.parent().unwrap()).unwrap();
+}
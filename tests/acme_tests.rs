@@ -0,0 +1,388 @@
+//! Integration tests for certificates obtained through ACME
+//!
+//! A mock authority issues certificates signed by a test CA. It validates the
+//! `tls-alpn-01` challenge like a real authority would, by connecting to the
+//! server with the `acme-tls/1` protocol and checking the key authorization
+//! in the certificate it gets.
+
+#![cfg(feature = "acme")]
+
+use axum::{
+    extract::State,
+    http::{header, HeaderMap, StatusCode},
+    response::{IntoResponse, Response},
+    routing::{get, post},
+    Json, Router,
+};
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine};
+use rcgen::{BasicConstraints, Certificate, CertificateParams, DnType, IsCa};
+use rossby_vis::{
+    run_server_with_config,
+    testing::MockBackend,
+    tls::{AcmeConfig, ACME_TLS_ALPN},
+    ServerConfig,
+};
+use rustls::{
+    client::{HandshakeSignatureValid, ServerCertVerified, ServerCertVerifier},
+    ServerName,
+};
+use serde_json::{json, Value};
+use sha2::{Digest, Sha256};
+use std::{
+    net::SocketAddr,
+    path::PathBuf,
+    sync::{Arc, Mutex},
+    time::{Duration, SystemTime},
+};
+
+const DOMAIN: &str = "weather.example.org";
+const TOKEN: &str = "challenge-token";
+
+/// OID of the `acmeIdentifier` extension of RFC 8737
+const ACME_IDENTIFIER_OID: &str = "1.3.6.1.5.5.7.1.31";
+
+#[derive(Default)]
+struct Progress {
+    thumbprint: String,
+    authorized: bool,
+    chain: Option<String>,
+}
+
+struct MockAuthority {
+    url: String,
+    ca: Certificate,
+    /// Where the server being validated listens
+    server: Mutex<Option<SocketAddr>>,
+    progress: Mutex<Progress>,
+}
+
+type Authority = State<Arc<MockAuthority>>;
+
+impl MockAuthority {
+    async fn start() -> Arc<Self> {
+        let mut params = CertificateParams::new(Vec::new());
+        params.is_ca = IsCa::Ca(BasicConstraints::Unconstrained);
+        params
+            .distinguished_name
+            .push(DnType::CommonName, "rossby-vis test ACME CA");
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let authority = Arc::new(Self {
+            url: format!("http://{}", listener.local_addr().unwrap()),
+            ca: Certificate::from_params(params).unwrap(),
+            server: Mutex::new(None),
+            progress: Mutex::new(Progress::default()),
+        });
+
+        let app = Router::new()
+            .route("/directory", get(directory))
+            .route("/nonce", get(nonce).head(nonce))
+            .route("/account", post(account))
+            .route("/order", post(new_order))
+            .route("/order/1", post(order))
+            .route("/authz/1", post(authorization))
+            .route("/challenge/1", post(challenge))
+            .route("/finalize/1", post(finalize))
+            .route("/certificate/1", post(certificate))
+            .with_state(authority.clone());
+        let server = axum::Server::from_tcp(listener)
+            .unwrap()
+            .serve(app.into_make_service());
+        tokio::spawn(server);
+        authority
+    }
+
+    fn directory_url(&self) -> String {
+        format!("{}/directory", self.url)
+    }
+
+    fn ca_pem(&self) -> String {
+        self.ca.serialize_pem().unwrap()
+    }
+
+    fn order_json(&self) -> Value {
+        let progress = self.progress.lock().unwrap();
+        let status = match (&progress.chain, progress.authorized) {
+            (Some(_), _) => "valid",
+            (None, true) => "ready",
+            (None, false) => "pending",
+        };
+        json!({
+            "status": status,
+            "identifiers": [{"type": "dns", "value": DOMAIN}],
+            "authorizations": [format!("{}/authz/1", self.url)],
+            "finalize": format!("{}/finalize/1", self.url),
+            "certificate": progress.chain.as_ref().map(|_| format!("{}/certificate/1", self.url)),
+        })
+    }
+
+    fn authorization_json(&self) -> Value {
+        let status = if self.progress.lock().unwrap().authorized {
+            "valid"
+        } else {
+            "pending"
+        };
+        json!({
+            "status": status,
+            "identifier": {"type": "dns", "value": DOMAIN},
+            "challenges": [
+                {"type": "http-01", "url": format!("{}/challenge/2", self.url), "token": "other", "status": "pending"},
+                {"type": "tls-alpn-01", "url": format!("{}/challenge/1", self.url), "token": TOKEN, "status": status},
+            ],
+        })
+    }
+
+    /// Connect like a validating authority and check the key authorization
+    /// in the certificate served for `acme-tls/1`
+    async fn validate(&self) -> bool {
+        let addr = loop {
+            if let Some(addr) = *self.server.lock().unwrap() {
+                break addr;
+            }
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        };
+        let mut config = rustls::ClientConfig::builder()
+            .with_safe_defaults()
+            .with_custom_certificate_verifier(Arc::new(AcceptAnyCertificate))
+            .with_no_client_auth();
+        config.alpn_protocols = vec![ACME_TLS_ALPN.to_vec()];
+        let connector = tokio_rustls::TlsConnector::from(Arc::new(config));
+        let stream = tokio::net::TcpStream::connect(addr).await.unwrap();
+        let Ok(stream) = connector
+            .connect(ServerName::try_from(DOMAIN).unwrap(), stream)
+            .await
+        else {
+            return false;
+        };
+        let (_, connection) = stream.get_ref();
+        let Some(certificate) = connection.peer_certificates().and_then(|c| c.first()) else {
+            return false;
+        };
+
+        let key_authorization = format!("{}.{}", TOKEN, self.progress.lock().unwrap().thumbprint);
+        let digest = Sha256::digest(key_authorization.as_bytes());
+        let (_, parsed) = x509_parser::parse_x509_certificate(&certificate.0).unwrap();
+        parsed.extensions().iter().any(|extension| {
+            extension.oid.to_id_string() == ACME_IDENTIFIER_OID
+                && extension.critical
+                && extension.value.ends_with(&digest)
+        })
+    }
+}
+
+struct AcceptAnyCertificate;
+
+impl ServerCertVerifier for AcceptAnyCertificate {
+    fn verify_server_cert(
+        &self,
+        _end_entity: &rustls::Certificate,
+        _intermediates: &[rustls::Certificate],
+        _server_name: &ServerName,
+        _scts: &mut dyn Iterator<Item = &[u8]>,
+        _ocsp_response: &[u8],
+        _now: SystemTime,
+    ) -> Result<ServerCertVerified, rustls::Error> {
+        Ok(ServerCertVerified::assertion())
+    }
+
+    // Validation certificates carry a critical extension webpki does not
+    // know, so the handshake signatures are not checked against them either
+    fn verify_tls12_signature(
+        &self,
+        _message: &[u8],
+        _cert: &rustls::Certificate,
+        _dss: &rustls::DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, rustls::Error> {
+        Ok(HandshakeSignatureValid::assertion())
+    }
+
+    fn verify_tls13_signature(
+        &self,
+        _message: &[u8],
+        _cert: &rustls::Certificate,
+        _dss: &rustls::DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, rustls::Error> {
+        Ok(HandshakeSignatureValid::assertion())
+    }
+}
+
+/// Decode a member of a flattened JWS
+fn jws_part(body: &Value, part: &str) -> Value {
+    let encoded = body[part].as_str().unwrap();
+    if encoded.is_empty() {
+        return Value::Null;
+    }
+    serde_json::from_slice(&URL_SAFE_NO_PAD.decode(encoded).unwrap()).unwrap()
+}
+
+fn nonce_headers() -> HeaderMap {
+    let mut headers = HeaderMap::new();
+    headers.insert(
+        "replay-nonce",
+        uuid::Uuid::new_v4().to_string().parse().unwrap(),
+    );
+    headers
+}
+
+fn created(location: String, body: Value) -> Response {
+    let mut headers = nonce_headers();
+    headers.insert(header::LOCATION, location.parse().unwrap());
+    (StatusCode::CREATED, headers, Json(body)).into_response()
+}
+
+async fn directory(State(authority): Authority) -> Json<Value> {
+    Json(json!({
+        "newNonce": format!("{}/nonce", authority.url),
+        "newAccount": format!("{}/account", authority.url),
+        "newOrder": format!("{}/order", authority.url),
+    }))
+}
+
+async fn nonce() -> impl IntoResponse {
+    (StatusCode::OK, nonce_headers())
+}
+
+async fn account(State(authority): Authority, Json(body): Json<Value>) -> Response {
+    let protected = jws_part(&body, "protected");
+    let payload = jws_part(&body, "payload");
+    assert_eq!(payload["termsOfServiceAgreed"], true);
+    assert_eq!(payload["contact"], json!(["mailto:ops@example.org"]));
+
+    let jwk = &protected["jwk"];
+    let canonical = format!(
+        r#"{{"crv":"{}","kty":"{}","x":"{}","y":"{}"}}"#,
+        jwk["crv"].as_str().unwrap(),
+        jwk["kty"].as_str().unwrap(),
+        jwk["x"].as_str().unwrap(),
+        jwk["y"].as_str().unwrap(),
+    );
+    authority.progress.lock().unwrap().thumbprint =
+        URL_SAFE_NO_PAD.encode(Sha256::digest(canonical.as_bytes()));
+    created(
+        format!("{}/account/1", authority.url),
+        json!({"status": "valid"}),
+    )
+}
+
+async fn new_order(State(authority): Authority, Json(body): Json<Value>) -> Response {
+    let protected = jws_part(&body, "protected");
+    assert_eq!(protected["kid"], format!("{}/account/1", authority.url));
+    let payload = jws_part(&body, "payload");
+    assert_eq!(
+        payload["identifiers"],
+        json!([{"type": "dns", "value": DOMAIN}])
+    );
+    created(format!("{}/order/1", authority.url), authority.order_json())
+}
+
+async fn order(State(authority): Authority) -> Response {
+    (nonce_headers(), Json(authority.order_json())).into_response()
+}
+
+async fn authorization(State(authority): Authority) -> Response {
+    (nonce_headers(), Json(authority.authorization_json())).into_response()
+}
+
+async fn challenge(State(authority): Authority) -> Response {
+    if authority.validate().await {
+        authority.progress.lock().unwrap().authorized = true;
+    }
+    let body = json!({"type": "tls-alpn-01", "token": TOKEN, "status": "processing"});
+    (nonce_headers(), Json(body)).into_response()
+}
+
+async fn finalize(State(authority): Authority, Json(body): Json<Value>) -> Response {
+    let csr = URL_SAFE_NO_PAD
+        .decode(jws_part(&body, "payload")["csr"].as_str().unwrap())
+        .unwrap();
+    let csr = rcgen::CertificateSigningRequest::from_der(&csr).unwrap();
+    let chain = csr.serialize_pem_with_signer(&authority.ca).unwrap();
+    authority.progress.lock().unwrap().chain = Some(chain);
+    (nonce_headers(), Json(authority.order_json())).into_response()
+}
+
+async fn certificate(State(authority): Authority) -> Response {
+    let chain = authority.progress.lock().unwrap().chain.clone().unwrap();
+    (nonce_headers(), chain).into_response()
+}
+
+async fn https_get(ca_pem: &str, addr: SocketAddr) -> reqwest::Result<reqwest::Response> {
+    reqwest::Client::builder()
+        .add_root_certificate(reqwest::Certificate::from_pem(ca_pem.as_bytes())?)
+        .resolve(DOMAIN, addr)
+        .build()?
+        .get(format!("https://{}:{}/health", DOMAIN, addr.port()))
+        .send()
+        .await
+}
+
+fn acme_config(directory_url: String) -> AcmeConfig {
+    let mut acme = AcmeConfig::new(vec![DOMAIN.to_string()]);
+    acme.contact = vec!["ops@example.org".to_string()];
+    acme.directory_url = directory_url;
+    acme
+}
+
+#[tokio::test]
+async fn test_certificate_obtained_and_stored() {
+    let backend = MockBackend::default().start().await;
+    let authority = MockAuthority::start().await;
+    let data_dir: PathBuf =
+        std::env::temp_dir().join(format!("rossby-vis-acme-{}", uuid::Uuid::new_v4()));
+
+    let config = ServerConfig::builder(backend.url())
+        .port(0)
+        .data_dir(&data_dir)
+        .acme(acme_config(authority.directory_url()))
+        .build()
+        .unwrap();
+    let server = run_server_with_config(config).await.unwrap();
+    *authority.server.lock().unwrap() = Some(server.addr());
+
+    // The certificate is served as soon as it has been issued
+    let mut response = None;
+    for _ in 0..100 {
+        if let Ok(ok) = https_get(&authority.ca_pem(), server.addr()).await {
+            response = Some(ok);
+            break;
+        }
+        tokio::time::sleep(Duration::from_millis(100)).await;
+    }
+    assert!(response.expect("certificate issued").status().is_success());
+    assert!(authority.progress.lock().unwrap().authorized);
+    server.stop().await.unwrap();
+
+    // A restart serves the stored certificate without ordering again
+    assert!(data_dir.join("acme/cert.pem").exists());
+    assert!(data_dir.join("acme/account.key").exists());
+    let config = ServerConfig::builder(backend.url())
+        .port(0)
+        .data_dir(&data_dir)
+        .acme(acme_config("http://127.0.0.1:1/directory".to_string()))
+        .build()
+        .unwrap();
+    let server = run_server_with_config(config).await.unwrap();
+    let response = https_get(&authority.ca_pem(), server.addr()).await.unwrap();
+    assert!(response.status().is_success());
+    server.stop().await.unwrap();
+
+    let _ = std::fs::remove_dir_all(&data_dir);
+}
+
+#[tokio::test]
+async fn test_unknown_http_challenge_not_found() {
+    let backend = MockBackend::default().start().await;
+    let server = rossby_vis::run_server(0, backend.url().to_string())
+        .await
+        .unwrap();
+
+    let response = reqwest::get(format!(
+        "http://{}/.well-known/acme-challenge/{}",
+        server.addr(),
+        TOKEN
+    ))
+    .await
+    .unwrap();
+    assert_eq!(response.status(), StatusCode::NOT_FOUND);
+
+    server.stop().await.unwrap();
+}
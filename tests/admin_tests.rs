@@ -0,0 +1,364 @@
+//! Integration tests for the admin endpoints
+
+use axum::{
+    body::{Body, Bytes},
+    http::{Method, Request, StatusCode},
+    middleware,
+    routing::get,
+    Router,
+};
+use serde_json::{json, Value};
+use std::sync::Arc;
+use tower::ServiceExt;
+use tracing_subscriber::{layer::SubscriberExt, Registry};
+
+use rossby_vis::{
+    admin::{
+        admin_auth_middleware, admin_page, cache_stats, get_log_level, list_assets, overview,
+        purge_cache, set_log_level,
+    },
+    cache::{ResponseCache, ResponseCacheConfig},
+    handlers::proxy_metadata,
+    logging::{log_level_layer, LogLevelHandle},
+    middleware::error_logging_middleware,
+    server::AppState,
+};
+
+fn create_test_router(admin_token: Option<&str>, log_level: LogLevelHandle) -> Router {
+    let mut state = AppState::new("http://localhost:8000".to_string(), reqwest::Client::new());
+    state.admin_token = admin_token.map(str::to_string);
+    state.log_level = Some(log_level);
+    let state = Arc::new(state);
+
+    Router::new()
+        .route("/admin/loglevel", get(get_log_level).put(set_log_level))
+        .route("/admin/assets", get(list_assets))
+        .route_layer(middleware::from_fn_with_state(
+            state.clone(),
+            admin_auth_middleware,
+        ))
+        .with_state(state)
+}
+
+async fn send(
+    app: Router,
+    method: Method,
+    token: Option<&str>,
+    body: Value,
+) -> (StatusCode, Value) {
+    let mut request = Request::builder()
+        .method(method)
+        .uri("/admin/loglevel")
+        .header("content-type", "application/json");
+    if let Some(token) = token {
+        request = request.header("authorization", format!("Bearer {}", token));
+    }
+    let request = request.body(Body::from(body.to_string())).unwrap();
+
+    let response = app.oneshot(request).await.unwrap();
+    let status = response.status();
+    let bytes = hyper::body::to_bytes(response.into_body()).await.unwrap();
+    (
+        status,
+        serde_json::from_slice(&bytes).unwrap_or(Value::Null),
+    )
+}
+
+#[tokio::test]
+async fn test_admin_api_disabled_without_token() {
+    let (layer, handle) = log_level_layer("info");
+    let _subscriber = Registry::default().with(layer);
+    let app = create_test_router(None, handle);
+
+    let (status, body) = send(app, Method::GET, Some("anything"), Value::Null).await;
+
+    assert_eq!(status, StatusCode::UNAUTHORIZED);
+    assert!(body["error"].as_str().unwrap().contains("--admin-token"));
+}
+
+#[tokio::test]
+async fn test_admin_api_rejects_wrong_token() {
+    let (layer, handle) = log_level_layer("info");
+    let _subscriber = Registry::default().with(layer);
+    let app = create_test_router(Some("secret"), handle);
+
+    let (status, _) = send(app.clone(), Method::GET, None, Value::Null).await;
+    assert_eq!(status, StatusCode::UNAUTHORIZED);
+
+    let (status, _) = send(app, Method::GET, Some("wrong"), Value::Null).await;
+    assert_eq!(status, StatusCode::UNAUTHORIZED);
+}
+
+#[tokio::test]
+async fn test_change_log_level() {
+    let (layer, handle) = log_level_layer("info");
+    let _subscriber = Registry::default().with(layer);
+    let app = create_test_router(Some("secret"), handle.clone());
+
+    let (status, body) = send(
+        app.clone(),
+        Method::PUT,
+        Some("secret"),
+        json!({"level": "debug", "duration_secs": 300}),
+    )
+    .await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(
+        body,
+        json!({"level": "debug", "previous": "info", "revert_after_secs": 300})
+    );
+    assert_eq!(handle.current(), "debug");
+
+    let (status, body) = send(app.clone(), Method::GET, Some("secret"), Value::Null).await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(body["level"], "debug");
+
+    let (status, _) = send(app, Method::PUT, Some("secret"), json!({"level": "=bad="})).await;
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+}
+
+#[tokio::test]
+async fn test_list_assets() {
+    let (_layer, handle) = log_level_layer("info");
+    let app = create_test_router(Some("secret"), handle);
+
+    let request = Request::builder()
+        .uri("/admin/assets?prefix=/libs/d3")
+        .header("authorization", "Bearer secret")
+        .body(Body::empty())
+        .unwrap();
+    let response = app.clone().oneshot(request).await.unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    let bytes = hyper::body::to_bytes(response.into_body()).await.unwrap();
+    let body: Value = serde_json::from_slice(&bytes).unwrap();
+
+    let assets = body["assets"].as_array().unwrap();
+    assert_eq!(body["count"], assets.len());
+    let d3 = assets
+        .iter()
+        .find(|asset| asset["path"] == "/libs/d3/3.3.10/d3.js")
+        .unwrap();
+    assert_eq!(d3["mime_type"], "text/javascript");
+    assert_eq!(d3["encodings"], json!(["gzip"]));
+    assert!(body["total_bytes"].as_u64().unwrap() >= d3["size"].as_u64().unwrap());
+    assert!(body.get("dev_dir").is_none());
+
+    let request = Request::builder()
+        .uri("/admin/assets")
+        .body(Body::empty())
+        .unwrap();
+    let response = app.oneshot(request).await.unwrap();
+    assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+}
+
+#[tokio::test]
+async fn test_overview_reports_backend_and_errors() {
+    // Nothing listens on port 1, so the backend is unreachable
+    let mut state = AppState::new("http://127.0.0.1:1".to_string(), reqwest::Client::new());
+    state.admin_token = Some("secret".to_string());
+    let state = Arc::new(state);
+    let app = Router::new()
+        .route("/admin/overview", get(overview))
+        .route_layer(middleware::from_fn_with_state(
+            state.clone(),
+            admin_auth_middleware,
+        ))
+        .route("/admin", get(admin_page))
+        .route("/proxy/metadata", get(proxy_metadata))
+        .layer(middleware::from_fn_with_state(
+            state.clone(),
+            error_logging_middleware,
+        ))
+        .with_state(state);
+
+    let get = |uri: &str, token: Option<&str>| {
+        let mut request = Request::builder().uri(uri);
+        if let Some(token) = token {
+            request = request.header("authorization", format!("Bearer {}", token));
+        }
+        app.clone().oneshot(request.body(Body::empty()).unwrap())
+    };
+
+    // The page itself is public, its data is not
+    let response = get("/admin", None).await.unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(response.headers()["cache-control"], "no-store");
+    let page = hyper::body::to_bytes(response.into_body()).await.unwrap();
+    assert!(String::from_utf8_lossy(&page).contains("/admin/overview"));
+    let response = get("/admin/overview", None).await.unwrap();
+    assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+
+    let response = get("/proxy/metadata", None).await.unwrap();
+    assert_eq!(response.status(), StatusCode::BAD_GATEWAY);
+
+    let response = get("/admin/overview", Some("secret")).await.unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    let bytes = hyper::body::to_bytes(response.into_body()).await.unwrap();
+    let overview: Value = serde_json::from_slice(&bytes).unwrap();
+    assert_eq!(overview["backend"]["reachable"], false);
+    assert_eq!(overview["jobs"]["workers"], 2);
+    assert_eq!(overview["config"]["api_url"], "http://127.0.0.1:1");
+    assert!(overview["config"].get("admin_token").is_none());
+
+    let errors = overview["recent_errors"].as_array().unwrap();
+    assert_eq!(errors.len(), 1);
+    assert_eq!(errors[0]["path"], "/proxy/metadata");
+    assert_eq!(errors[0]["status"], 502);
+    assert!(errors[0]["message"]
+        .as_str()
+        .unwrap()
+        .starts_with("Proxy error"));
+}
+
+#[tokio::test]
+async fn test_inspect_and_purge_cache() {
+    let mut state = AppState::new("http://localhost:8000".to_string(), reqwest::Client::new());
+    state.admin_token = Some("secret".to_string());
+    state.response_cache = Arc::new(
+        ResponseCache::new(ResponseCacheConfig {
+            max_bytes: 1024,
+            ..Default::default()
+        })
+        .unwrap(),
+    );
+    let cache = state.response_cache.clone();
+    let state = Arc::new(state);
+    let app = Router::new()
+        .route("/admin/cache", get(cache_stats).delete(purge_cache))
+        .route_layer(middleware::from_fn_with_state(
+            state.clone(),
+            admin_auth_middleware,
+        ))
+        .with_state(state);
+
+    for url in [
+        "http://localhost:8000/data?vars=t2m&time=0",
+        "http://localhost:8000/data?vars=u10,v10&time=0",
+        "http://localhost:8000/metadata",
+    ] {
+        cache.insert(url, Bytes::from_static(b"body")).await;
+    }
+    cache.get("http://localhost:8000/metadata").await.unwrap();
+    assert!(cache.get("http://localhost:8000/other").await.is_none());
+
+    let send = |method: Method, uri: &str| {
+        let request = Request::builder()
+            .method(method)
+            .uri(uri)
+            .header("authorization", "Bearer secret")
+            .body(Body::empty())
+            .unwrap();
+        let app = app.clone();
+        async move {
+            let response = app.oneshot(request).await.unwrap();
+            assert_eq!(response.status(), StatusCode::OK);
+            let bytes = hyper::body::to_bytes(response.into_body()).await.unwrap();
+            serde_json::from_slice::<Value>(&bytes).unwrap()
+        }
+    };
+
+    let stats = send(Method::GET, "/admin/cache").await;
+    assert_eq!(stats["enabled"], true);
+    assert_eq!(stats["memory"]["entries"], 3);
+    assert_eq!(stats["memory"]["bytes"], 12);
+    assert_eq!(stats["hit_rate"], 0.5);
+    assert!(stats.get("disk").is_none());
+
+    let purged = send(Method::DELETE, "/admin/cache?variable=v10").await;
+    assert_eq!(purged["purged"], json!({"memory": 1, "disk": 0}));
+    assert_eq!(purged["cache"]["memory"]["entries"], 2);
+
+    let purged = send(Method::DELETE, "/admin/cache?pattern=*/metadata").await;
+    assert_eq!(purged["purged"]["memory"], 1);
+
+    let purged = send(Method::DELETE, "/admin/cache").await;
+    assert_eq!(purged["purged"]["memory"], 1);
+    assert!(cache.is_empty());
+}
+
+#[cfg(feature = "sqlite")]
+#[tokio::test]
+async fn test_issue_and_revoke_api_keys() {
+    use rossby_vis::{build_router, ServerConfig};
+
+    let database =
+        std::env::temp_dir().join(format!("rossby-vis-keys-{}.db", uuid::Uuid::new_v4()));
+    let app = || async {
+        let config = ServerConfig::builder("http://127.0.0.1:9")
+            .admin_token("secret")
+            .database(&database)
+            .build()
+            .unwrap();
+        build_router(AppState::from_config(config).await.unwrap())
+    };
+    let send = |app: Router, method: Method, uri: &str, headers: &[(&str, &str)]| {
+        let mut request = Request::builder().method(method).uri(uri);
+        for (name, value) in headers {
+            request = request.header(*name, *value);
+        }
+        let request = request.body(Body::empty()).unwrap();
+        async move {
+            let response = app.oneshot(request).await.unwrap();
+            let status = response.status();
+            let bytes = hyper::body::to_bytes(response.into_body()).await.unwrap();
+            (
+                status,
+                serde_json::from_slice(&bytes).unwrap_or(Value::Null),
+            )
+        }
+    };
+    let admin = [("authorization", "Bearer secret")];
+
+    let server = app().await;
+    // Without keys the data routes are open; the backend is unreachable
+    let (status, _) = send(server.clone(), Method::GET, "/proxy/metadata", &[]).await;
+    assert_eq!(status, StatusCode::BAD_GATEWAY);
+
+    let (status, issued) = send(
+        server.clone(),
+        Method::PUT,
+        "/admin/api-keys/station",
+        &admin,
+    )
+    .await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(issued["label"], "station");
+    let key = issued["key"].as_str().unwrap().to_string();
+
+    let (status, _) = send(server.clone(), Method::GET, "/proxy/metadata", &[]).await;
+    assert_eq!(status, StatusCode::UNAUTHORIZED);
+    drop(server);
+
+    // Issued keys survive a restart, and are listed without the key
+    let server = app().await;
+    let with_key = [("x-api-key", key.as_str())];
+    let (status, _) = send(server.clone(), Method::GET, "/proxy/metadata", &with_key).await;
+    assert_eq!(status, StatusCode::BAD_GATEWAY);
+    let (status, listed) = send(server.clone(), Method::GET, "/admin/api-keys", &admin).await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(listed.as_array().unwrap().len(), 1);
+    assert!(listed[0].get("key").is_none());
+
+    let (status, _) = send(
+        server.clone(),
+        Method::DELETE,
+        "/admin/api-keys/station",
+        &admin,
+    )
+    .await;
+    assert_eq!(status, StatusCode::NO_CONTENT);
+    let (status, _) = send(
+        server.clone(),
+        Method::DELETE,
+        "/admin/api-keys/station",
+        &admin,
+    )
+    .await;
+    assert_eq!(status, StatusCode::NOT_FOUND);
+    let (status, _) = send(server, Method::GET, "/proxy/metadata", &with_key).await;
+    assert_eq!(status, StatusCode::BAD_GATEWAY);
+
+    for suffix in ["", "-wal", "-shm"] {
+        let _ = std::fs::remove_file(format!("{}{}", database.display(), suffix));
+    }
+}
@@ -0,0 +1,324 @@
+//! Integration tests for the server-side analysis endpoints
+//!
+//! A mock Rossby server with a small pressure-level dataset is started on an
+//! ephemeral port and the analysis handlers are exercised through a router.
+
+use axum::{
+    body::Body,
+    http::{header, Method, Request, StatusCode},
+    routing::post,
+    Router,
+};
+use serde_json::{json, Value};
+use std::sync::Arc;
+use tower::ServiceExt;
+
+use rossby_vis::{
+    analysis::{cross_section, sample, trajectories},
+    server::AppState,
+};
+
+/// Mock Rossby server with a 2 × 2 × 3 × 4 (time, level, lat, lon) dataset
+mod mock_server {
+    use axum::{extract::Query, response::Json, routing::get, Router};
+    use serde_json::{json, Value};
+    use std::collections::HashMap;
+    use tokio::net::TcpListener;
+
+    pub async fn start() -> String {
+        let app = Router::new()
+            .route("/metadata", get(metadata))
+            .route("/data", get(data));
+
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+
+        tokio::spawn(async move {
+            axum::Server::from_tcp(listener.into_std().unwrap())
+                .unwrap()
+                .serve(app.into_make_service())
+                .await
+                .unwrap();
+        });
+
+        format!("http://{}", addr)
+    }
+
+    async fn metadata() -> Json<Value> {
+        Json(json!({
+            "coordinates": {
+                "latitude": [10.0, 0.0, -10.0],
+                "longitude": [0.0, 90.0, 180.0, 270.0],
+                "level": [850.0, 500.0],
+                "time": [700464.0, 700465.0]
+            },
+            "dimensions": {
+                "latitude": {"size": 3},
+                "longitude": {"size": 4},
+                "level": {"size": 2},
+                "time": {"size": 2}
+            },
+            "variables": {
+                "t": {
+                    "dimensions": ["time", "level", "latitude", "longitude"],
+                    "attributes": {"long_name": "Temperature", "units": "K"}
+                },
+                "t2m": {
+                    "dimensions": ["time", "latitude", "longitude"],
+                    "attributes": {"long_name": "2 metre temperature", "units": "K"}
+                },
+                "u10": {
+                    "dimensions": ["time", "latitude", "longitude"],
+                    "attributes": {"long_name": "10 metre U wind component", "units": "m s**-1"}
+                },
+                "v10": {
+                    "dimensions": ["time", "latitude", "longitude"],
+                    "attributes": {"long_name": "10 metre V wind component", "units": "m s**-1"}
+                }
+            }
+        }))
+    }
+
+    /// `t` encodes the level: 280 at 850 hPa and 250 at 500 hPa.
+    /// Surface winds are a uniform 10 m/s westerly.
+    async fn data(Query(params): Query<HashMap<String, String>>) -> Json<Value> {
+        let vars = params.get("vars").cloned().unwrap_or_default();
+        if vars == "t" {
+            let mut values = Vec::new();
+            for _time in 0..2 {
+                values.extend([280.0; 12]);
+                values.extend([250.0; 12]);
+            }
+
+            return Json(json!({
+                "metadata": {
+                    "query": params,
+                    "shape": [2, 2, 3, 4],
+                    "dimensions": ["time", "level", "latitude", "longitude"]
+                },
+                "data": {"t": values}
+            }));
+        }
+
+        let mut data = serde_json::Map::new();
+        for var in vars.split(',') {
+            let value = if var.starts_with('u') { 10.0 } else { 0.0 };
+            data.insert(var.to_string(), json!(vec![value; 12]));
+        }
+
+        Json(json!({
+            "metadata": {
+                "query": params,
+                "shape": [1, 3, 4],
+                "dimensions": ["time", "latitude", "longitude"]
+            },
+            "data": data
+        }))
+    }
+}
+
+async fn create_test_router() -> Router {
+    let state = Arc::new(AppState::new(
+        mock_server::start().await,
+        reqwest::Client::new(),
+    ));
+
+    Router::new()
+        .route("/api/cross-section", post(cross_section))
+        .route("/api/trajectories", post(trajectories))
+        .route("/api/sample", post(sample))
+        .with_state(state)
+}
+
+async fn post_json(app: Router, uri: &str, body: Value) -> (StatusCode, Value) {
+    post_body(app, uri, "application/json", body.to_string()).await
+}
+
+async fn post_body(
+    app: Router,
+    uri: &str,
+    content_type: &str,
+    body: String,
+) -> (StatusCode, Value) {
+    let request = Request::builder()
+        .method(Method::POST)
+        .uri(uri)
+        .header(header::CONTENT_TYPE, content_type)
+        .body(Body::from(body))
+        .unwrap();
+
+    let response = app.oneshot(request).await.unwrap();
+    let status = response.status();
+    let bytes = hyper::body::to_bytes(response.into_body()).await.unwrap();
+    (status, serde_json::from_slice(&bytes).unwrap())
+}
+
+#[tokio::test]
+async fn test_cross_section_returns_distance_by_level_matrix() {
+    let app = create_test_router().await;
+
+    let (status, body) = post_json(
+        app,
+        "/api/cross-section",
+        json!({
+            "variable": "t",
+            "path": [{"lat": 5.0, "lon": 10.0}, {"lat": -5.0, "lon": 80.0}],
+            "samples": 4
+        }),
+    )
+    .await;
+
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(body["level_dimension"], "level");
+    assert_eq!(body["levels"], json!([850.0, 500.0]));
+    assert_eq!(body["time"], json!(700464.0));
+    assert_eq!(body["distances_km"].as_array().unwrap().len(), 4);
+    for (level, expected) in [(0, 280.0), (1, 250.0)] {
+        let values = body["values"][level].as_array().unwrap();
+        assert_eq!(values.len(), 4);
+        assert!(values
+            .iter()
+            .all(|v| (v.as_f64().unwrap() - expected).abs() < 1e-9));
+    }
+}
+
+#[tokio::test]
+async fn test_cross_section_rejects_variable_without_levels() {
+    let app = create_test_router().await;
+
+    let (status, body) = post_json(
+        app,
+        "/api/cross-section",
+        json!({
+            "variable": "t2m",
+            "path": [{"lat": 5.0, "lon": 10.0}, {"lat": -5.0, "lon": 80.0}]
+        }),
+    )
+    .await;
+
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+    assert!(body["error"]
+        .as_str()
+        .unwrap()
+        .contains("no vertical dimension"));
+}
+
+#[tokio::test]
+async fn test_cross_section_rejects_short_path() {
+    let app = create_test_router().await;
+
+    let (status, _) = post_json(
+        app,
+        "/api/cross-section",
+        json!({"variable": "t", "path": [{"lat": 5.0, "lon": 10.0}]}),
+    )
+    .await;
+
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+}
+
+#[tokio::test]
+async fn test_trajectories_return_geojson_paths() {
+    let app = create_test_router().await;
+
+    let (status, body) = post_json(
+        app,
+        "/api/trajectories",
+        json!({
+            "seeds": [{"lat": 0.0, "lon": 10.0}, {"lat": 5.0, "lon": 100.0}],
+            "step_minutes": 15
+        }),
+    )
+    .await;
+
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(body["type"], "FeatureCollection");
+    assert_eq!(body["u_variable"], "u10");
+    assert_eq!(body["v_variable"], "v10");
+
+    let features = body["features"].as_array().unwrap();
+    assert_eq!(features.len(), 2);
+    for feature in features {
+        assert_eq!(feature["geometry"]["type"], "LineString");
+        assert_eq!(feature["properties"]["termination"], "completed");
+
+        // One hour between the two timesteps at 15 minute steps
+        let coordinates = feature["geometry"]["coordinates"].as_array().unwrap();
+        assert_eq!(coordinates.len(), 5);
+        let start_lon = coordinates[0][0].as_f64().unwrap();
+        let end_lon = coordinates[4][0].as_f64().unwrap();
+        assert!(end_lon > start_lon, "westerly wind should move seeds east");
+    }
+}
+
+#[tokio::test]
+async fn test_trajectories_reject_partial_component_pair() {
+    let app = create_test_router().await;
+
+    let (status, _) = post_json(
+        app,
+        "/api/trajectories",
+        json!({"seeds": [{"lat": 0.0, "lon": 10.0}], "u_variable": "u10"}),
+    )
+    .await;
+
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+}
+
+#[tokio::test]
+async fn test_sample_json_points() {
+    let app = create_test_router().await;
+
+    let (status, body) = post_json(
+        app,
+        "/api/sample",
+        json!({
+            "points": [{"lat": 5.0, "lon": 45.0, "id": "buoy-1"}, {"lat": -2.0, "lon": 300.0}],
+            "vars": ["u10", "t2m"],
+            "times": [700464.0, 700465.0]
+        }),
+    )
+    .await;
+
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(body["times"], json!([700464.0, 700465.0]));
+    assert_eq!(body["units"]["t2m"], "K");
+    assert_eq!(body["points"][0]["id"], "buoy-1");
+    assert_eq!(body["points"][0]["values"]["u10"], json!([10.0, 10.0]));
+    assert_eq!(body["points"][1]["values"]["t2m"], json!([0.0, 0.0]));
+    assert!(body["points"][1].get("id").is_none());
+}
+
+#[tokio::test]
+async fn test_sample_csv_upload() {
+    let app = create_test_router().await;
+
+    let (status, body) = post_body(
+        app,
+        "/api/sample?vars=u10",
+        "text/csv",
+        "id,lat,lon\nA,0,0\nB,20,0\n".to_string(),
+    )
+    .await;
+
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(body["times"], json!([700464.0]));
+    assert_eq!(body["points"][0]["values"]["u10"], json!([10.0]));
+    // Latitude 20 is outside the 10..-10 mock grid
+    assert_eq!(body["points"][1]["values"]["u10"], json!([null]));
+}
+
+#[tokio::test]
+async fn test_sample_unknown_variable() {
+    let app = create_test_router().await;
+
+    let (status, body) = post_json(
+        app,
+        "/api/sample",
+        json!({"points": [{"lat": 0.0, "lon": 0.0}], "vars": ["nope"]}),
+    )
+    .await;
+
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+    assert!(body["error"].as_str().unwrap().contains("nope"));
+}
@@ -0,0 +1,442 @@
+//! Integration tests for serving the embedded frontend assets
+
+use axum::{
+    body::Body,
+    http::{Request, StatusCode},
+    middleware,
+    routing::get,
+    Router,
+};
+use hyper::body::HttpBody;
+use serde_json::Value;
+use std::sync::Arc;
+use tower::ServiceExt;
+
+use rossby_vis::{
+    dev_assets::{dev_events, DevAssets, DEV_EVENTS_PATH},
+    handlers::{asset_manifest, index, service_worker, static_asset, web_manifest},
+    middleware::cross_origin_isolation_middleware,
+    server::AppState,
+    site::SiteConfig,
+};
+
+fn create_test_router(site: SiteConfig) -> Router {
+    let mut state = AppState::new("http://localhost:8000".to_string(), reqwest::Client::new());
+    state.site = site;
+
+    Router::new()
+        .route("/", get(index))
+        .route("/api/assets", get(asset_manifest))
+        .route("/manifest.json", get(web_manifest))
+        .route("/sw.js", get(service_worker))
+        .route("/*path", get(static_asset))
+        .with_state(Arc::new(state))
+}
+
+async fn get_body(app: Router, uri: &str) -> (StatusCode, String) {
+    let response = app
+        .oneshot(Request::builder().uri(uri).body(Body::empty()).unwrap())
+        .await
+        .unwrap();
+    let status = response.status();
+    let bytes = hyper::body::to_bytes(response.into_body()).await.unwrap();
+    (status, String::from_utf8(bytes.to_vec()).unwrap())
+}
+
+#[tokio::test]
+async fn test_index_has_integrity_attributes() {
+    let app = create_test_router(SiteConfig::default());
+
+    let (status, manifest) = get_body(app.clone(), "/api/assets").await;
+    assert_eq!(status, StatusCode::OK);
+    let manifest: Value = serde_json::from_str(&manifest).unwrap();
+    assert_eq!(manifest["algorithm"], "sha256");
+    let earth = manifest["assets"]["/libs/earth/1.0.0/earth.js"]
+        .as_str()
+        .unwrap();
+    assert!(earth.starts_with("sha256-"));
+    assert!(manifest["assets"]["/index.html"].is_null());
+
+    let (status, html) = get_body(app, "/").await;
+    assert_eq!(status, StatusCode::OK);
+    assert!(html.contains(&format!(
+        "<script src=\"libs/earth/1.0.0/earth.js\" charset=\"utf-8\" integrity=\"{}\"></script>",
+        earth
+    )));
+    // Scripts from other origins are not ours to hash
+    assert!(html.contains(
+        "<script src=\"//cdnjs.cloudflare.com/ajax/libs/d3/3.3.10/d3.min.js\" charset=\"utf-8\"></script>"
+    ));
+}
+
+#[tokio::test]
+async fn test_index_placeholders_are_substituted() {
+    let app = create_test_router(SiteConfig {
+        api_base: "https://vis.example.com/".to_string(),
+        title: "Ocean <Currents>".to_string(),
+        contact_email: Some("ops@example.com".to_string()),
+        analytics_snippet: Some(
+            "<script src=\"https://stats.example.com/a.js\"></script>".to_string(),
+        ),
+    });
+
+    for uri in ["/", "/index.html"] {
+        let (status, html) = get_body(app.clone(), uri).await;
+        assert_eq!(status, StatusCode::OK);
+        assert!(html.contains("<title>Ocean &lt;Currents&gt;</title>"));
+        assert!(
+            html.contains("<meta name=\"rossby-api-base\" content=\"https://vis.example.com\"/>")
+        );
+        assert!(html.contains("<a href=\"mailto:ops@example.com\">ops@example.com</a>"));
+        assert!(html.contains("<script src=\"https://stats.example.com/a.js\"></script>"));
+        assert!(!html.contains("{{"));
+    }
+}
+
+#[tokio::test]
+async fn test_index_defaults() {
+    let (_, html) = get_body(create_test_router(SiteConfig::default()), "/").await;
+    assert!(html.contains("<title>earth :: an animated map of global wind and weather</title>"));
+    assert!(html.contains("<meta name=\"rossby-api-base\" content=\"\"/>"));
+    assert!(!html.contains("mailto:"));
+    assert!(!html.contains("{{"));
+}
+
+#[tokio::test]
+async fn test_precompressed_asset_negotiation() {
+    let app = create_test_router(SiteConfig::default());
+    let send = |accept_encoding: Option<&'static str>| {
+        let app = app.clone();
+        async move {
+            let mut request = Request::builder().uri("/libs/d3/3.3.10/d3.js");
+            if let Some(accept_encoding) = accept_encoding {
+                request = request.header("accept-encoding", accept_encoding);
+            }
+            app.oneshot(request.body(Body::empty()).unwrap())
+                .await
+                .unwrap()
+        }
+    };
+
+    let gzip = send(Some("gzip, deflate")).await;
+    assert_eq!(gzip.status(), StatusCode::OK);
+    assert_eq!(gzip.headers()["content-encoding"], "gzip");
+    assert_eq!(gzip.headers()["vary"], "Accept-Encoding");
+    assert_eq!(gzip.headers()["content-type"], "text/javascript");
+    let gzip_etag = gzip.headers()["etag"].clone();
+
+    let identity = send(None).await;
+    assert!(identity.headers().get("content-encoding").is_none());
+    assert_eq!(identity.headers()["vary"], "Accept-Encoding");
+    assert_ne!(identity.headers()["etag"], gzip_etag);
+    let bytes = hyper::body::to_bytes(identity.into_body()).await.unwrap();
+    assert!(bytes.starts_with(b"d3 = function()"));
+
+    // Assets without variants do not vary
+    let response = app
+        .oneshot(
+            Request::builder()
+                .uri("/styles/styles.css")
+                .header("accept-encoding", "gzip")
+                .body(Body::empty())
+                .unwrap(),
+        )
+        .await
+        .unwrap();
+    assert!(response.headers().get("vary").is_none());
+    assert!(response.headers().get("content-encoding").is_none());
+}
+
+#[tokio::test]
+async fn test_conditional_asset_requests() {
+    let app = create_test_router(SiteConfig::default());
+    let send = |header: Option<(&'static str, String)>| {
+        let app = app.clone();
+        async move {
+            let mut request = Request::builder()
+                .uri("/libs/earth/1.0.0/earth.js")
+                .header("accept-encoding", "gzip");
+            if let Some((name, value)) = header {
+                request = request.header(name, value);
+            }
+            app.oneshot(request.body(Body::empty()).unwrap())
+                .await
+                .unwrap()
+        }
+    };
+
+    let full = send(None).await;
+    assert_eq!(full.status(), StatusCode::OK);
+    let etag = full.headers()["etag"].to_str().unwrap().to_string();
+    let last_modified = full.headers()["last-modified"]
+        .to_str()
+        .unwrap()
+        .to_string();
+
+    let cached = send(Some(("if-none-match", etag.clone()))).await;
+    assert_eq!(cached.status(), StatusCode::NOT_MODIFIED);
+    assert_eq!(cached.headers()["etag"], etag.as_str());
+    assert!(cached.headers().get("content-type").is_none());
+    let body = hyper::body::to_bytes(cached.into_body()).await.unwrap();
+    assert!(body.is_empty());
+
+    let cached = send(Some(("if-modified-since", last_modified))).await;
+    assert_eq!(cached.status(), StatusCode::NOT_MODIFIED);
+
+    let changed = send(Some(("if-none-match", "\"stale\"".to_string()))).await;
+    assert_eq!(changed.status(), StatusCode::OK);
+}
+
+#[tokio::test]
+async fn test_asset_cache_control() {
+    let mut state = AppState::new("http://localhost:8000".to_string(), reqwest::Client::new());
+    state.asset_cache.add_override("css=no-store").unwrap();
+    let app = Router::new()
+        .route("/", get(index))
+        .route("/sw.js", get(service_worker))
+        .route("/*path", get(static_asset))
+        .with_state(Arc::new(state));
+    let cache_control = |uri: String| {
+        let app = app.clone();
+        async move {
+            let response = app
+                .oneshot(Request::builder().uri(uri).body(Body::empty()).unwrap())
+                .await
+                .unwrap();
+            assert_eq!(response.status(), StatusCode::OK);
+            response.headers()["cache-control"]
+                .to_str()
+                .unwrap()
+                .to_string()
+        }
+    };
+
+    let earth = "/libs/earth/1.0.0/earth.js";
+    let hash = rossby_vis::embed::asset_listing(earth)[0].sha256.clone();
+    assert_eq!(
+        cache_control(earth.to_string()).await,
+        "public, max-age=3600"
+    );
+    assert_eq!(
+        cache_control(format!("{}?v={}", earth, &hash[..12])).await,
+        "public, max-age=31536000, immutable"
+    );
+    // A version naming other contents gets the ordinary policy
+    assert_eq!(
+        cache_control(format!("{}?v=0123456789abcdef", earth)).await,
+        "public, max-age=3600"
+    );
+    assert_eq!(
+        cache_control("/styles/styles.css".to_string()).await,
+        "no-store"
+    );
+    assert_eq!(cache_control("/".to_string()).await, "public, max-age=60");
+    assert_eq!(cache_control("/sw.js".to_string()).await, "no-cache");
+}
+
+#[tokio::test]
+async fn test_dev_mode_serves_files_from_disk() {
+    let root = std::env::temp_dir().join(format!("rossby-vis-public-{}", uuid::Uuid::new_v4()));
+    std::fs::create_dir_all(&root).unwrap();
+    std::fs::write(
+        root.join("index.html"),
+        "<title>{{title}}</title><script src=\"/app.js\"></script>",
+    )
+    .unwrap();
+    std::fs::write(root.join("app.js"), "console.log('dev');").unwrap();
+
+    let mut state = AppState::new("http://localhost:8000".to_string(), reqwest::Client::new());
+    state.dev_assets = Some(Arc::new(DevAssets::new(&root).unwrap()));
+    // Held past the last request, as a server would, to keep the watcher
+    let state = Arc::new(state);
+    let app = Router::new()
+        .route("/", get(index))
+        .route(DEV_EVENTS_PATH, get(dev_events))
+        .route("/*path", get(static_asset))
+        .with_state(state.clone());
+
+    let response = app
+        .clone()
+        .oneshot(
+            Request::builder()
+                .uri("/app.js")
+                .body(Body::empty())
+                .unwrap(),
+        )
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(response.headers()["cache-control"], "no-store");
+    let bytes = hyper::body::to_bytes(response.into_body()).await.unwrap();
+    assert_eq!(&bytes[..], b"console.log('dev');");
+
+    // Templates still apply, integrity hashes do not, and the page reloads
+    // on file changes
+    let (status, html) = get_body(app.clone(), "/").await;
+    assert_eq!(status, StatusCode::OK);
+    assert!(html.starts_with(
+        "<title>earth :: an animated map of global wind and weather</title><script src=\"/app.js\"></script><script>"
+    ));
+    assert!(html.contains(DEV_EVENTS_PATH.trim_start_matches('/')));
+
+    // Embedded-only files are not served in dev mode
+    let (status, _) = get_body(app.clone(), "/libs/d3/3.3.10/d3.js").await;
+    assert_eq!(status, StatusCode::NOT_FOUND);
+
+    let response = app
+        .oneshot(
+            Request::builder()
+                .uri(DEV_EVENTS_PATH)
+                .body(Body::empty())
+                .unwrap(),
+        )
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(response.headers()["content-type"], "text/event-stream");
+    let mut body = response.into_body();
+    std::fs::write(root.join("app.js"), "console.log('edited');").unwrap();
+    let chunk = tokio::time::timeout(std::time::Duration::from_secs(5), body.data())
+        .await
+        .unwrap()
+        .unwrap()
+        .unwrap();
+    assert_eq!(
+        std::str::from_utf8(&chunk).unwrap(),
+        "event:change\ndata:app.js\n\n"
+    );
+    drop(state);
+
+    std::fs::remove_dir_all(root).unwrap();
+}
+
+#[tokio::test]
+async fn test_pwa_manifest_and_service_worker() {
+    let app = create_test_router(SiteConfig {
+        title: "Rossby Winds".to_string(),
+        ..Default::default()
+    });
+
+    let response = app
+        .clone()
+        .oneshot(
+            Request::builder()
+                .uri("/manifest.json")
+                .body(Body::empty())
+                .unwrap(),
+        )
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(
+        response.headers()["content-type"],
+        "application/manifest+json"
+    );
+    let bytes = hyper::body::to_bytes(response.into_body()).await.unwrap();
+    let manifest: Value = serde_json::from_slice(&bytes).unwrap();
+    assert_eq!(manifest["name"], "Rossby Winds");
+
+    let response = app
+        .clone()
+        .oneshot(
+            Request::builder()
+                .uri("/sw.js")
+                .body(Body::empty())
+                .unwrap(),
+        )
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(response.headers()["service-worker-allowed"], "/");
+    assert_eq!(response.headers()["cache-control"], "no-cache");
+    assert_eq!(response.headers()["content-type"], "text/javascript");
+
+    let (_, html) = get_body(app, "/").await;
+    assert!(html.contains("<link rel=\"manifest\" href=\"manifest.json\"/>"));
+    assert!(html.contains("navigator.serviceWorker.register(\"sw.js\")"));
+}
+
+#[tokio::test]
+async fn test_wasm_and_cross_origin_isolation() {
+    let root = std::env::temp_dir().join(format!("rossby-vis-wasm-{}", uuid::Uuid::new_v4()));
+    std::fs::create_dir_all(&root).unwrap();
+    std::fs::write(root.join("render.wasm"), b"\0asm\x01\0\0\0").unwrap();
+
+    for isolated in [false, true] {
+        let mut state = AppState::new("http://localhost:8000".to_string(), reqwest::Client::new());
+        state.dev_assets = Some(Arc::new(DevAssets::new(&root).unwrap()));
+        state.cross_origin_isolation = isolated;
+        let state = Arc::new(state);
+        let app = Router::new()
+            .route("/*path", get(static_asset))
+            .layer(middleware::from_fn_with_state(
+                state.clone(),
+                cross_origin_isolation_middleware,
+            ))
+            .with_state(state);
+
+        let response = app
+            .oneshot(
+                Request::builder()
+                    .uri("/render.wasm")
+                    .body(Body::empty())
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(response.headers()["content-type"], "application/wasm");
+
+        let headers = response.headers();
+        if isolated {
+            assert_eq!(headers["cross-origin-opener-policy"], "same-origin");
+            assert_eq!(headers["cross-origin-embedder-policy"], "require-corp");
+            assert_eq!(headers["cross-origin-resource-policy"], "same-origin");
+        } else {
+            assert!(!headers.contains_key("cross-origin-embedder-policy"));
+        }
+    }
+
+    std::fs::remove_dir_all(root).unwrap();
+}
+
+#[tokio::test]
+async fn test_mobile_index_variant() {
+    let iphone = "Mozilla/5.0 (iPhone; CPU iPhone OS 17_0 like Mac OS X) Mobile/15E148";
+
+    for enabled in [false, true] {
+        let mut state = AppState::new("http://localhost:8000".to_string(), reqwest::Client::new());
+        state.mobile_index = enabled;
+        let app = Router::new()
+            .route("/", get(index))
+            .with_state(Arc::new(state));
+
+        for (agent, mobile) in [(iphone, true), ("Mozilla/5.0 (X11; Linux x86_64)", false)] {
+            let response = app
+                .clone()
+                .oneshot(
+                    Request::builder()
+                        .uri("/")
+                        .header("user-agent", agent)
+                        .body(Body::empty())
+                        .unwrap(),
+                )
+                .await
+                .unwrap();
+            assert_eq!(response.status(), StatusCode::OK);
+            let varies_by_agent = response
+                .headers()
+                .get_all("vary")
+                .iter()
+                .any(|value| value.to_str().unwrap().contains("User-Agent"));
+            assert_eq!(varies_by_agent, enabled, "vary when enabled");
+            let bytes = hyper::body::to_bytes(response.into_body()).await.unwrap();
+            let html = String::from_utf8(bytes.to_vec()).unwrap();
+
+            let served_mobile = html.contains("\"data/earth-topo-mobile.json?v2\"");
+            assert_eq!(served_mobile, enabled && mobile, "{} {}", enabled, agent);
+            assert_eq!(html.contains("serviceWorker"), !served_mobile);
+            assert!(html.contains("<title>earth :: an animated map"));
+        }
+    }
+}
@@ -0,0 +1,146 @@
+//! Integration tests for HTTP Basic authentication of the whole server
+
+use axum::{
+    body::Body,
+    http::{Request, StatusCode},
+    response::Response,
+    Router,
+};
+use base64::{engine::general_purpose::STANDARD, Engine as _};
+use tower::ServiceExt;
+
+use rossby_vis::{
+    auth::AuthConfig, build_router, pipeline::Middleware, testing::MockBackend, AppState,
+    ServerConfig,
+};
+
+fn auth_config() -> AuthConfig {
+    let hash = bcrypt::hash("correct horse", 4).unwrap();
+    AuthConfig {
+        basic: vec![format!("alice:{}", hash).parse().unwrap()],
+        ..Default::default()
+    }
+}
+
+async fn send(app: &Router, uri: &str, credentials: Option<&str>) -> Response {
+    let mut request = Request::builder().uri(uri);
+    if let Some(credentials) = credentials {
+        request = request.header(
+            "authorization",
+            format!("Basic {}", STANDARD.encode(credentials)),
+        );
+    }
+    app.clone()
+        .oneshot(request.body(Body::empty()).unwrap())
+        .await
+        .unwrap()
+}
+
+#[tokio::test]
+async fn test_basic_auth_protects_every_route_but_health() {
+    let backend = MockBackend::default().start().await;
+    let config = ServerConfig::builder(backend.url())
+        .auth(auth_config())
+        .build()
+        .unwrap();
+    let app = build_router(AppState::from_config(config).await.unwrap());
+
+    for uri in ["/", "/proxy/metadata", "/libs/earth/1.0.0/earth.js"] {
+        let response = send(&app, uri, None).await;
+        assert_eq!(response.status(), StatusCode::UNAUTHORIZED, "{}", uri);
+        assert_eq!(
+            response.headers()["www-authenticate"],
+            "Basic realm=\"rossby-vis\", charset=\"UTF-8\""
+        );
+        let response = send(&app, uri, Some("alice:correct horse")).await;
+        assert_eq!(response.status(), StatusCode::OK, "{}", uri);
+    }
+
+    let wrong = send(&app, "/", Some("alice:battery staple")).await;
+    assert_eq!(wrong.status(), StatusCode::UNAUTHORIZED);
+    let unknown = send(&app, "/", Some("mallory:correct horse")).await;
+    assert_eq!(unknown.status(), StatusCode::UNAUTHORIZED);
+
+    assert_eq!(send(&app, "/health", None).await.status(), StatusCode::OK);
+}
+
+#[tokio::test]
+async fn test_users_need_the_auth_layer() {
+    let backend = MockBackend::default().start().await;
+    let config = ServerConfig::builder(backend.url())
+        .auth(auth_config())
+        .middleware(vec![Middleware::Trace, Middleware::HealthCheck])
+        .build()
+        .unwrap();
+    assert!(AppState::from_config(config).await.is_err());
+}
+
+#[tokio::test]
+async fn test_api_keys_open_the_data_routes() {
+    let backend = MockBackend::default().start().await;
+    let mut auth = auth_config();
+    auth.api_keys = vec!["partner=0123456789abcdef".parse().unwrap()];
+    let config = ServerConfig::builder(backend.url())
+        .auth(auth)
+        .build()
+        .unwrap();
+    let app = build_router(AppState::from_config(config).await.unwrap());
+    let send_key = |uri: &'static str, name: &'static str, value: &'static str| {
+        let app = app.clone();
+        async move {
+            app.oneshot(
+                Request::builder()
+                    .uri(uri)
+                    .header(name, value)
+                    .body(Body::empty())
+                    .unwrap(),
+            )
+            .await
+            .unwrap()
+            .status()
+        }
+    };
+
+    assert_eq!(
+        send_key("/proxy/metadata", "x-api-key", "0123456789abcdef").await,
+        StatusCode::OK
+    );
+    assert_eq!(
+        send_key(
+            "/proxy/metadata",
+            "authorization",
+            "Bearer 0123456789abcdef"
+        )
+        .await,
+        StatusCode::OK
+    );
+    assert_eq!(
+        send_key("/proxy/metadata", "x-api-key", "0123456789abcdeX").await,
+        StatusCode::UNAUTHORIZED
+    );
+    // Keys do not open the viewer, which still needs a user's credentials
+    assert_eq!(
+        send_key("/", "x-api-key", "0123456789abcdef").await,
+        StatusCode::UNAUTHORIZED
+    );
+    let response = send(&app, "/proxy/metadata", Some("alice:correct horse")).await;
+    assert_eq!(response.status(), StatusCode::OK);
+}
+
+#[tokio::test]
+async fn test_api_keys_alone_leave_the_viewer_open() {
+    let backend = MockBackend::default().start().await;
+    let config = ServerConfig::builder(backend.url())
+        .auth(AuthConfig {
+            api_keys: vec!["partner=0123456789abcdef".parse().unwrap()],
+            ..Default::default()
+        })
+        .build()
+        .unwrap();
+    let app = build_router(AppState::from_config(config).await.unwrap());
+
+    assert_eq!(send(&app, "/", None).await.status(), StatusCode::OK);
+    let response = send(&app, "/proxy/metadata", None).await;
+    assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+    assert_eq!(response.headers()["www-authenticate"], "Bearer");
+}
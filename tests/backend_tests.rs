@@ -0,0 +1,141 @@
+//! Integration tests for backend schema compatibility
+//!
+//! A mock Rossby server speaking schema version 2 is started on an
+//! ephemeral port; the proxy must present it in the legacy schema.
+
+use axum::{
+    body::Body,
+    http::{Method, Request, StatusCode},
+    routing::get,
+    Router,
+};
+use serde_json::{json, Value};
+use std::sync::Arc;
+use tower::ServiceExt;
+
+use rossby_vis::{
+    backend::{BackendCompat, BackendSchema, SchemaVersion},
+    handlers::{earth_dynamic_data, proxy_data, proxy_metadata},
+    server::AppState,
+};
+
+/// Mock Rossby server using the version 2 schema
+mod mock_server {
+    use axum::{extract::Query, response::Json, routing::get, Router};
+    use serde_json::{json, Value};
+    use std::collections::HashMap;
+    use tokio::net::TcpListener;
+
+    pub async fn start() -> String {
+        let app = Router::new()
+            .route("/metadata", get(metadata))
+            .route("/data", get(data));
+
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+
+        tokio::spawn(async move {
+            axum::Server::from_tcp(listener.into_std().unwrap())
+                .unwrap()
+                .serve(app.into_make_service())
+                .await
+                .unwrap();
+        });
+
+        format!("http://{}", addr)
+    }
+
+    async fn metadata() -> Json<Value> {
+        Json(json!({
+            "schema_version": 2,
+            "coordinates": {
+                "latitude": {"values": [10.0, 0.0], "units": "degrees_north"},
+                "longitude": {"values": [0.0, 10.0], "units": "degrees_east"},
+                "time": {"values": [700464.0], "units": "hours since 1900-01-01"}
+            },
+            "dimensions": {"latitude": 2, "longitude": 2, "time": 1},
+            "variables": [
+                {
+                    "name": "t2m",
+                    "dimensions": ["time", "latitude", "longitude"],
+                    "attributes": {"long_name": "2 metre temperature", "units": "K"}
+                }
+            ]
+        }))
+    }
+
+    async fn data(Query(params): Query<HashMap<String, String>>) -> Json<Value> {
+        Json(json!({
+            "schema_version": 2,
+            "query": params,
+            "variables": {
+                "t2m": {
+                    "dimensions": ["time", "latitude", "longitude"],
+                    "shape": [1, 2, 2],
+                    "values": [280.0, 281.0, 282.0, null],
+                    "units": "K"
+                }
+            }
+        }))
+    }
+}
+
+async fn create_test_router(schema: BackendSchema) -> Router {
+    let mut state = AppState::new(mock_server::start().await, reqwest::Client::new());
+    state.backend = BackendCompat::new(schema);
+
+    Router::new()
+        .route("/proxy/metadata", get(proxy_metadata))
+        .route("/proxy/data", get(proxy_data))
+        .route("/earth/:variable", get(earth_dynamic_data))
+        .with_state(Arc::new(state))
+}
+
+async fn get_json(app: Router, uri: &str) -> (StatusCode, Value) {
+    let request = Request::builder()
+        .method(Method::GET)
+        .uri(uri)
+        .body(Body::empty())
+        .unwrap();
+
+    let response = app.oneshot(request).await.unwrap();
+    let status = response.status();
+    let bytes = hyper::body::to_bytes(response.into_body()).await.unwrap();
+    (status, serde_json::from_slice(&bytes).unwrap())
+}
+
+#[tokio::test]
+async fn test_v2_metadata_is_presented_in_legacy_schema() {
+    let app = create_test_router(BackendSchema::Auto).await;
+
+    let (status, body) = get_json(app, "/proxy/metadata").await;
+
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(body["coordinates"]["latitude"], json!([10.0, 0.0]));
+    assert_eq!(body["dimensions"]["time"]["size"], 1);
+    assert_eq!(body["variables"]["t2m"]["attributes"]["units"], "K");
+}
+
+#[tokio::test]
+async fn test_v2_data_is_translated_after_probing() {
+    let app = create_test_router(BackendSchema::Auto).await;
+
+    // Probing happens when the metadata is first seen
+    get_json(app.clone(), "/proxy/metadata").await;
+    let (status, body) = get_json(app, "/proxy/data?vars=t2m&time=700464").await;
+
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(body["metadata"]["shape"], json!([1, 2, 2]));
+    assert_eq!(body["data"]["t2m"], json!([280.0, 281.0, 282.0, null]));
+}
+
+#[tokio::test]
+async fn test_configured_v2_schema_for_earth_data() {
+    let app = create_test_router(BackendSchema::Fixed(SchemaVersion::V2)).await;
+
+    let (status, body) = get_json(app, "/earth/t2m").await;
+
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(body[0]["header"]["nx"], 2);
+    assert_eq!(body[0]["data"], json!([280.0, 281.0, 282.0, null]));
+}
@@ -0,0 +1,72 @@
+//! Integration tests for serving the viewer under a base path
+
+use axum::{
+    body::Body,
+    http::{Request, StatusCode},
+    Router,
+};
+use tower::ServiceExt;
+
+use rossby_vis::{build_router, testing::MockBackend, AppState, ServerConfig};
+
+async fn app(url: &str, base_path: &str) -> Router {
+    let config = ServerConfig::builder(url)
+        .base_path(base_path)
+        .build()
+        .unwrap();
+    build_router(AppState::from_config(config).await.unwrap())
+}
+
+async fn get(app: &Router, uri: &str, prefix: Option<&str>) -> (StatusCode, String) {
+    let mut request = Request::builder().uri(uri);
+    if let Some(prefix) = prefix {
+        request = request.header("x-forwarded-prefix", prefix);
+    }
+    let response = app
+        .clone()
+        .oneshot(request.body(Body::empty()).unwrap())
+        .await
+        .unwrap();
+    let status = response.status();
+    let bytes = hyper::body::to_bytes(response.into_body()).await.unwrap();
+    (status, String::from_utf8_lossy(&bytes).into_owned())
+}
+
+#[tokio::test]
+async fn test_routes_are_mounted_under_the_base_path() {
+    let backend = MockBackend::default().start().await;
+    let app = app(backend.url(), "/rossby-vis/").await;
+
+    let (status, html) = get(&app, "/rossby-vis/", None).await;
+    assert_eq!(status, StatusCode::OK);
+    assert!(html.contains("<base href=\"/rossby-vis/\"/>"));
+
+    for uri in [
+        "/rossby-vis",
+        "/rossby-vis/libs/earth/1.0.0/earth.js",
+        "/rossby-vis/proxy/metadata",
+        "/rossby-vis/manifest.json",
+    ] {
+        assert_eq!(get(&app, uri, None).await.0, StatusCode::OK, "{}", uri);
+    }
+    assert_eq!(
+        get(&app, "/libs/earth/1.0.0/earth.js", None).await.0,
+        StatusCode::NOT_FOUND
+    );
+}
+
+#[tokio::test]
+async fn test_forwarded_prefix_sets_the_base_href() {
+    let backend = MockBackend::default().start().await;
+    let app = app(backend.url(), "").await;
+
+    let (_, html) = get(&app, "/", None).await;
+    assert!(html.contains("<base href=\"/\"/>"));
+
+    // A proxy that stripped /tools before passing the request on
+    let (_, html) = get(&app, "/", Some("/tools")).await;
+    assert!(html.contains("<base href=\"/tools/\"/>"));
+
+    let (_, html) = get(&app, "/", Some("/\"><script>")).await;
+    assert!(html.contains("<base href=\"/\"/>"));
+}
@@ -0,0 +1,87 @@
+//! Integration tests for the in-memory backend response cache
+
+use axum::{
+    body::Body,
+    http::{Request, StatusCode},
+    Router,
+};
+use serde_json::Value;
+use std::time::Duration;
+use tower::ServiceExt;
+
+use rossby_vis::{
+    build_router,
+    cache::ResponseCacheConfig,
+    testing::{MockBackend, RunningMockBackend},
+    AppState, ServerConfig,
+};
+
+async fn router(max_bytes: usize, ttl: Duration) -> (Router, RunningMockBackend) {
+    let backend = MockBackend::default().start().await;
+    let config = ServerConfig::builder(backend.url())
+        .response_cache(ResponseCacheConfig {
+            max_bytes,
+            ttl,
+            ..ResponseCacheConfig::default()
+        })
+        .build()
+        .unwrap();
+    (
+        build_router(AppState::from_config(config).await.unwrap()),
+        backend,
+    )
+}
+
+async fn get_json(app: &Router, uri: &str) -> (StatusCode, Value) {
+    let response = app
+        .clone()
+        .oneshot(Request::builder().uri(uri).body(Body::empty()).unwrap())
+        .await
+        .unwrap();
+    let status = response.status();
+    let body = hyper::body::to_bytes(response.into_body()).await.unwrap();
+    (status, serde_json::from_slice(&body).unwrap_or(Value::Null))
+}
+
+#[tokio::test]
+async fn test_repeated_requests_are_served_from_cache() {
+    let (app, backend) = router(1024 * 1024, Duration::from_secs(60)).await;
+
+    let (status, metadata) = get_json(&app, "/proxy/metadata").await;
+    assert_eq!(status, StatusCode::OK);
+    let (_, again) = get_json(&app, "/proxy/metadata").await;
+    assert_eq!(metadata, again);
+    let after_metadata = backend.requests();
+
+    // Parameter order does not matter to the cache
+    let (status, data) = get_json(&app, "/proxy/data?vars=u10&time=700464").await;
+    assert_eq!(status, StatusCode::OK);
+    let after_data = backend.requests();
+    assert!(after_data > after_metadata);
+    let (_, again) = get_json(&app, "/proxy/data?time=700464&vars=u10").await;
+    assert_eq!(data, again);
+    assert_eq!(backend.requests(), after_data);
+
+    let earth = "/data/weather/current/current-u10-surface-level-gfs-1.0.json";
+    let (status, first) = get_json(&app, earth).await;
+    assert_eq!(status, StatusCode::OK);
+    let after_earth = backend.requests();
+    let (_, second) = get_json(&app, earth).await;
+    assert_eq!(first, second);
+    assert_eq!(backend.requests(), after_earth);
+}
+
+#[tokio::test]
+async fn test_cache_disabled_or_expired() {
+    let (app, backend) = router(0, Duration::from_secs(60)).await;
+    get_json(&app, "/proxy/metadata").await;
+    let first = backend.requests();
+    get_json(&app, "/proxy/metadata").await;
+    assert!(backend.requests() > first);
+
+    let (app, backend) = router(1024 * 1024, Duration::ZERO).await;
+    get_json(&app, "/proxy/data?vars=u10").await;
+    let first = backend.requests();
+    get_json(&app, "/proxy/data?vars=u10").await;
+    assert!(backend.requests() > first);
+}
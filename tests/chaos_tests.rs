@@ -0,0 +1,78 @@
+//! Integration tests for fault injection
+//!
+//! Each test puts the chaos proxy in front of the mock backend with one
+//! fault that always fires.
+
+use axum::{
+    body::Body,
+    http::{Request, StatusCode},
+    routing::get,
+    Router,
+};
+use std::{
+    sync::Arc,
+    time::{Duration, Instant},
+};
+use tower::ServiceExt;
+
+use rossby_vis::{
+    chaos::ChaosConfig,
+    handlers::proxy_metadata,
+    products::products_catalog,
+    server::AppState,
+    testing::{MockBackend, RunningMockBackend},
+};
+
+async fn app(faults: &str, backend: &RunningMockBackend) -> Router {
+    let chaos: ChaosConfig = faults.parse().unwrap();
+    let api_url = chaos
+        .start(backend.url(), reqwest::Client::new())
+        .await
+        .unwrap();
+    Router::new()
+        .route("/proxy/metadata", get(proxy_metadata))
+        .route("/data/products.json", get(products_catalog))
+        .with_state(Arc::new(AppState::new(api_url, reqwest::Client::new())))
+}
+
+async fn status(app: &Router, uri: &str) -> StatusCode {
+    let response = app
+        .clone()
+        .oneshot(Request::builder().uri(uri).body(Body::empty()).unwrap())
+        .await
+        .unwrap();
+    response.status()
+}
+
+#[tokio::test]
+async fn test_no_faults_pass_through() {
+    let backend = MockBackend::default().start().await;
+    let app = app("latency=100ms", &backend).await;
+    let started = Instant::now();
+    assert_eq!(status(&app, "/proxy/metadata").await, StatusCode::OK);
+    assert!(started.elapsed() >= Duration::from_millis(100));
+    assert_eq!(status(&app, "/data/products.json").await, StatusCode::OK);
+}
+
+#[tokio::test]
+async fn test_injected_errors_skip_the_backend() {
+    let backend = MockBackend::default().start().await;
+    let app = app("errors=1,status=500", &backend).await;
+    assert!(status(&app, "/proxy/metadata").await.is_server_error());
+    assert_eq!(backend.requests(), 0);
+}
+
+#[tokio::test]
+async fn test_truncated_and_malformed_responses() {
+    let backend = MockBackend::default().start().await;
+    let truncated = app("truncate=1", &backend).await;
+    assert_eq!(
+        status(&truncated, "/proxy/metadata").await,
+        StatusCode::BAD_GATEWAY
+    );
+
+    let malformed = app("malformed=1", &backend).await;
+    assert!(status(&malformed, "/data/products.json")
+        .await
+        .is_server_error());
+}
@@ -0,0 +1,95 @@
+//! Integration tests for the frontend error reporting endpoint
+
+use axum::{
+    body::Body,
+    http::{Method, Request, StatusCode},
+    routing::post,
+    Router,
+};
+use serde_json::{json, Value};
+use std::sync::Arc;
+use tower::ServiceExt;
+
+use rossby_vis::{
+    client_errors::{report_client_errors, ClientErrorLimiter},
+    server::AppState,
+};
+
+fn create_test_router(per_minute: u32) -> Router {
+    let mut state = AppState::new("http://localhost:8000".to_string(), reqwest::Client::new());
+    state.client_errors = Arc::new(ClientErrorLimiter::new(per_minute));
+
+    Router::new()
+        .route("/api/client-errors", post(report_client_errors))
+        .with_state(Arc::new(state))
+}
+
+async fn send(app: Router, client: &str, body: Value) -> (StatusCode, Value) {
+    let request = Request::builder()
+        .method(Method::POST)
+        .uri("/api/client-errors")
+        .header("content-type", "application/json")
+        .header("x-forwarded-for", client)
+        .body(Body::from(body.to_string()))
+        .unwrap();
+
+    let response = app.oneshot(request).await.unwrap();
+    let status = response.status();
+    let bytes = hyper::body::to_bytes(response.into_body()).await.unwrap();
+    (
+        status,
+        serde_json::from_slice(&bytes).unwrap_or(Value::Null),
+    )
+}
+
+fn batch(count: usize) -> Value {
+    let errors: Vec<Value> = (0..count)
+        .map(|i| {
+            json!({
+                "message": format!("TypeError: grid is undefined ({})", i),
+                "source": "/libs/earth/1.0.0/earth.js",
+                "line": 120,
+                "column": 17,
+                "request_id": "req-123"
+            })
+        })
+        .collect();
+    json!({ "errors": errors })
+}
+
+#[tokio::test]
+async fn test_reports_are_accepted_and_rate_limited() {
+    let app = create_test_router(5);
+
+    let (status, body) = send(app.clone(), "203.0.113.7", batch(3)).await;
+    assert_eq!(status, StatusCode::ACCEPTED);
+    assert_eq!(body, json!({"accepted": 3, "dropped": 0}));
+
+    let (status, body) = send(app.clone(), "203.0.113.7", batch(3)).await;
+    assert_eq!(status, StatusCode::ACCEPTED);
+    assert_eq!(body, json!({"accepted": 2, "dropped": 1}));
+
+    let (status, _) = send(app.clone(), "203.0.113.7", batch(1)).await;
+    assert_eq!(status, StatusCode::TOO_MANY_REQUESTS);
+
+    // Other clients have their own allowance
+    let (status, body) = send(app, "198.51.100.2", batch(1)).await;
+    assert_eq!(status, StatusCode::ACCEPTED);
+    assert_eq!(body["accepted"], 1);
+}
+
+#[tokio::test]
+async fn test_oversized_batches_and_bad_bodies() {
+    let app = create_test_router(100);
+
+    let (status, body) = send(app.clone(), "203.0.113.7", batch(25)).await;
+    assert_eq!(status, StatusCode::ACCEPTED);
+    assert_eq!(body, json!({"accepted": 20, "dropped": 5}));
+
+    let (status, body) = send(app.clone(), "203.0.113.7", json!({"errors": []})).await;
+    assert_eq!(status, StatusCode::ACCEPTED);
+    assert_eq!(body, json!({"accepted": 0, "dropped": 0}));
+
+    let (status, _) = send(app, "203.0.113.7", json!({"errors": [{"line": 1}]})).await;
+    assert_eq!(status, StatusCode::UNPROCESSABLE_ENTITY);
+}
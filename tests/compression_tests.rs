@@ -0,0 +1,79 @@
+//! Integration tests for compressing responses
+
+use axum::{
+    body::Body,
+    http::{header, Request},
+    response::Response,
+    Router,
+};
+use flate2::read::GzDecoder;
+use serde_json::Value;
+use std::io::Read;
+use tower::ServiceExt;
+
+use rossby_vis::{
+    build_router,
+    pipeline::CompressionConfig,
+    testing::{MockBackend, RunningMockBackend},
+    AppState, ServerConfig,
+};
+
+async fn app(min_size: u16) -> (Router, RunningMockBackend) {
+    let backend = MockBackend::default().start().await;
+    let config = ServerConfig::builder(backend.url())
+        .compression(CompressionConfig { min_size })
+        .build()
+        .unwrap();
+    (
+        build_router(AppState::from_config(config).await.unwrap()),
+        backend,
+    )
+}
+
+async fn get(app: &Router, uri: &str, accept_encoding: Option<&str>) -> Response {
+    let mut request = Request::builder().uri(uri);
+    if let Some(accept_encoding) = accept_encoding {
+        request = request.header(header::ACCEPT_ENCODING, accept_encoding);
+    }
+    app.clone()
+        .oneshot(request.body(Body::empty()).unwrap())
+        .await
+        .unwrap()
+}
+
+fn encoding(response: &Response) -> Option<&str> {
+    response
+        .headers()
+        .get(header::CONTENT_ENCODING)
+        .map(|value| value.to_str().unwrap())
+}
+
+#[tokio::test]
+async fn test_responses_follow_accept_encoding() {
+    let (app, _backend) = app(32).await;
+    for uri in [
+        "/proxy/metadata",
+        "/proxy/data?vars=u10&time=700464",
+        "/data/weather/current/current-wind-surface-level-gfs-1.0.json",
+    ] {
+        let response = get(&app, uri, Some("gzip")).await;
+        assert_eq!(encoding(&response), Some("gzip"), "{}", uri);
+        let body = hyper::body::to_bytes(response.into_body()).await.unwrap();
+        let mut json = String::new();
+        GzDecoder::new(&body[..]).read_to_string(&mut json).unwrap();
+        assert!(serde_json::from_str::<Value>(&json).is_ok(), "{}", uri);
+
+        assert_eq!(encoding(&get(&app, uri, Some("br")).await), Some("br"));
+        assert_eq!(encoding(&get(&app, uri, Some("zstd")).await), Some("zstd"));
+        assert_eq!(encoding(&get(&app, uri, None).await), None);
+    }
+}
+
+#[tokio::test]
+async fn test_small_responses_are_not_compressed() {
+    let (app, _backend) = app(u16::MAX).await;
+    let response = get(&app, "/proxy/metadata", Some("gzip, br")).await;
+    assert_eq!(encoding(&response), None);
+    let body = hyper::body::to_bytes(response.into_body()).await.unwrap();
+    assert!(serde_json::from_slice::<Value>(&body).is_ok());
+}
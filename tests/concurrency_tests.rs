@@ -0,0 +1,82 @@
+//! Integration tests for the backend concurrency limit
+
+use axum::{
+    body::Body,
+    http::{Request, StatusCode},
+    routing::get,
+    Router,
+};
+use std::{
+    sync::Arc,
+    time::{Duration, Instant},
+};
+use tower::ServiceExt;
+
+use rossby_vis::{
+    concurrency::{BackendLimiter, ConcurrencyLimit},
+    handlers::proxy_metadata,
+    server::AppState,
+    testing::MockBackend,
+};
+
+async fn app(latency: Duration, limit: ConcurrencyLimit, queue_timeout: Duration) -> Router {
+    let backend = MockBackend::default().with_latency(latency).start().await;
+    let mut state = AppState::new(backend.url().to_string(), reqwest::Client::new());
+    state.backend_limiter = Arc::new(BackendLimiter::new(limit, queue_timeout));
+    Router::new()
+        .route("/proxy/metadata", get(proxy_metadata))
+        .with_state(Arc::new(state))
+}
+
+async fn status(app: &Router) -> StatusCode {
+    app.clone()
+        .oneshot(
+            Request::builder()
+                .uri("/proxy/metadata")
+                .body(Body::empty())
+                .unwrap(),
+        )
+        .await
+        .unwrap()
+        .status()
+}
+
+#[tokio::test]
+async fn test_fixed_limit_queues_requests() {
+    let app = app(
+        Duration::from_millis(100),
+        ConcurrencyLimit::Fixed(1),
+        Duration::from_secs(5),
+    )
+    .await;
+    let started = Instant::now();
+    let (first, second) = tokio::join!(status(&app), status(&app));
+    assert_eq!((first, second), (StatusCode::OK, StatusCode::OK));
+    assert!(started.elapsed() >= Duration::from_millis(200));
+}
+
+#[tokio::test]
+async fn test_requests_over_the_limit_time_out() {
+    let app = app(
+        Duration::from_millis(300),
+        ConcurrencyLimit::Fixed(1),
+        Duration::from_millis(50),
+    )
+    .await;
+    let (first, second) = tokio::join!(status(&app), status(&app));
+    let mut statuses = [first, second];
+    statuses.sort();
+    assert_eq!(statuses, [StatusCode::OK, StatusCode::SERVICE_UNAVAILABLE]);
+}
+
+#[tokio::test]
+async fn test_adaptive_limit_serves_requests() {
+    let app = app(
+        Duration::from_millis(10),
+        ConcurrencyLimit::Adaptive { min: 1, max: 4 },
+        Duration::from_secs(5),
+    )
+    .await;
+    let statuses = futures::future::join_all((0..8).map(|_| status(&app))).await;
+    assert!(statuses.iter().all(|status| *status == StatusCode::OK));
+}
@@ -0,0 +1,145 @@
+//! Integration tests for settings files
+
+use clap::{Arg, ArgAction, Command};
+use std::{ffi::OsString, path::PathBuf};
+
+use rossby_vis::config::layered_args;
+
+fn command() -> Command {
+    Command::new("rossby-vis")
+        .arg(Arg::new("config").long("config"))
+        .arg(Arg::new("port").long("port"))
+        .arg(Arg::new("api_url").long("api-url"))
+        .arg(Arg::new("log_level").long("log-level"))
+        .arg(
+            Arg::new("cors_origin")
+                .long("cors-origin")
+                .action(ArgAction::Append),
+        )
+}
+
+fn settings_file(name: &str, contents: &str) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!("rossby-vis-config-{}", uuid::Uuid::new_v4()));
+    std::fs::create_dir_all(&dir).unwrap();
+    let path = dir.join(name);
+    std::fs::write(&path, contents).unwrap();
+    path
+}
+
+fn matches(args: Vec<OsString>) -> clap::ArgMatches {
+    command().try_get_matches_from(args).unwrap()
+}
+
+fn value<'a>(matches: &'a clap::ArgMatches, id: &str) -> &'a str {
+    matches.get_one::<String>(id).unwrap()
+}
+
+#[test]
+fn test_toml_and_yaml_files() {
+    let toml = settings_file(
+        "rossby-vis.toml",
+        r#"
+            api_url = "http://rossby:8000"
+            cors_origin = ["https://a.example", "https://b.example"]
+
+            [log]
+            level = "debug"
+        "#,
+    );
+    let yaml = settings_file(
+        "rossby-vis.yaml",
+        "api-url: http://rossby:8000\nlog:\n  level: debug\ncors-origin:\n  - https://a.example\n  - https://b.example\n",
+    );
+
+    for path in [toml, yaml] {
+        let cli = vec!["rossby-vis".into(), "--config".into(), path.into()];
+        let matches = matches(layered_args(&command(), cli, Vec::new()).unwrap());
+        assert_eq!(value(&matches, "api_url"), "http://rossby:8000");
+        assert_eq!(value(&matches, "log_level"), "debug");
+        assert_eq!(
+            matches.get_many::<String>("cors_origin").unwrap().count(),
+            2
+        );
+    }
+}
+
+#[test]
+fn test_overrides() {
+    let path = settings_file(
+        "rossby-vis.toml",
+        "port = 8080\nlog_level = \"debug\"\ncors_origin = [\"https://a.example\"]\n",
+    );
+    let env = vec![
+        ("ROSSBY_VIS_CONFIG".to_string(), path.display().to_string()),
+        ("ROSSBY_VIS_LOG_LEVEL".to_string(), "warn".to_string()),
+        ("ROSSBY_VIS_PORT".to_string(), "8081".to_string()),
+    ];
+    let cli: Vec<OsString> = ["rossby-vis", "--port=9000", "--cors-origin", "*"]
+        .iter()
+        .map(OsString::from)
+        .collect();
+
+    let matches = matches(layered_args(&command(), cli, env).unwrap());
+    assert_eq!(value(&matches, "port"), "9000");
+    assert_eq!(value(&matches, "log_level"), "warn");
+    // Repeated options are replaced, not merged
+    let origins: Vec<&String> = matches.get_many("cors_origin").unwrap().collect();
+    assert_eq!(origins, ["*"]);
+}
+
+#[test]
+fn test_invalid_files() {
+    for (name, contents) in [
+        ("rossby-vis.toml", "prot = 8080\n"),
+        ("rossby-vis.toml", "port = \n"),
+        ("rossby-vis.json", "{\"port\": 8080}"),
+    ] {
+        let path = settings_file(name, contents);
+        let cli = vec![
+            "rossby-vis".into(),
+            format!("--config={}", path.display()).into(),
+        ];
+        assert!(
+            layered_args(&command(), cli, Vec::new()).is_err(),
+            "{}",
+            contents
+        );
+    }
+
+    let cli = vec![
+        "rossby-vis".into(),
+        "--config".into(),
+        "/nonexistent.toml".into(),
+    ];
+    assert!(layered_args(&command(), cli, Vec::new()).is_err());
+}
+
+#[test]
+fn test_binary_rejects_oversized_cache_size() {
+    let output = std::process::Command::new(env!("CARGO_BIN_EXE_rossby-vis"))
+        .args(["--api-url", "http://127.0.0.1:1", "--cache-size"])
+        .arg(usize::MAX.to_string())
+        .output()
+        .unwrap();
+
+    assert!(!output.status.success());
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains("--cache-size is too large"), "{}", stderr);
+}
+
+#[test]
+fn test_binary_rejects_oversized_disk_cache_size() {
+    let output = std::process::Command::new(env!("CARGO_BIN_EXE_rossby-vis"))
+        .args(["--api-url", "http://127.0.0.1:1", "--disk-cache-size"])
+        .arg(u64::MAX.to_string())
+        .output()
+        .unwrap();
+
+    assert!(!output.status.success());
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(
+        stderr.contains("--disk-cache-size is too large"),
+        "{}",
+        stderr
+    );
+}
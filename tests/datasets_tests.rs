@@ -0,0 +1,171 @@
+//! Integration tests for serving several backends as named datasets
+
+use axum::{
+    body::Body,
+    http::{Request, StatusCode},
+    Router,
+};
+use serde_json::{json, Value};
+use tower::ServiceExt;
+
+use rossby_vis::{
+    build_router,
+    datasets::DatasetConfig,
+    testing::{MockBackend, MockVariable, RunningMockBackend},
+    AppState, ServerConfig,
+};
+
+struct Fixture {
+    app: Router,
+    era5: RunningMockBackend,
+    gfs: RunningMockBackend,
+}
+
+async fn fixture(strict_query: bool) -> Fixture {
+    let era5 = MockBackend::default().start().await;
+    let gfs = MockBackend::default()
+        .with_variables(vec![MockVariable::new("t2m", "2 metre temperature", "K")])
+        .start()
+        .await;
+    let dataset = |name: &str, backend: &RunningMockBackend| DatasetConfig {
+        name: name.to_string(),
+        api_url: backend.url().to_string(),
+    };
+    let config = ServerConfig::builder("http://unused:8000")
+        .datasets(vec![dataset("era5", &era5), dataset("gfs", &gfs)])
+        .configure(|c| c.strict_query = strict_query)
+        .build()
+        .unwrap();
+    Fixture {
+        app: build_router(AppState::from_config(config).await.unwrap()),
+        era5,
+        gfs,
+    }
+}
+
+async fn get_json(app: &Router, uri: &str) -> (StatusCode, Value) {
+    let response = app
+        .clone()
+        .oneshot(Request::builder().uri(uri).body(Body::empty()).unwrap())
+        .await
+        .unwrap();
+    let status = response.status();
+    let body = hyper::body::to_bytes(response.into_body()).await.unwrap();
+    (status, serde_json::from_slice(&body).unwrap_or(Value::Null))
+}
+
+fn variable_names(metadata: &Value) -> Vec<&str> {
+    let mut names: Vec<&str> = metadata["variables"]
+        .as_object()
+        .unwrap()
+        .keys()
+        .map(String::as_str)
+        .filter(|name| !name.starts_with("wind") && *name != "temp")
+        .collect();
+    names.sort();
+    names
+}
+
+#[tokio::test]
+async fn test_list_datasets() {
+    let Fixture { app, .. } = fixture(false).await;
+    let (status, datasets) = get_json(&app, "/api/datasets").await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(
+        datasets,
+        json!({"datasets": [
+            {"name": "era5", "default": true},
+            {"name": "gfs", "default": false}
+        ]})
+    );
+}
+
+#[tokio::test]
+async fn test_proxy_routes_follow_the_dataset() {
+    let Fixture { app, era5, gfs } = fixture(false).await;
+
+    let (_, default) = get_json(&app, "/proxy/metadata").await;
+    let (_, named) = get_json(&app, "/proxy/era5/metadata").await;
+    assert_eq!(variable_names(&default), vec!["u10", "v10"]);
+    assert_eq!(variable_names(&named), vec!["u10", "v10"]);
+
+    let before = era5.requests();
+    let (status, metadata) = get_json(&app, "/proxy/gfs/metadata").await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(variable_names(&metadata), vec!["t2m"]);
+    assert!(gfs.requests() > 0);
+    assert_eq!(era5.requests(), before);
+
+    let (status, data) = get_json(&app, "/proxy/gfs/data?vars=t2m").await;
+    assert_eq!(status, StatusCode::OK);
+    assert!(data["data"]["t2m"].is_array());
+
+    let (status, _) = get_json(&app, "/proxy/cmip6/metadata").await;
+    assert_eq!(status, StatusCode::NOT_FOUND);
+}
+
+#[tokio::test]
+async fn test_earth_routes_follow_the_dataset() {
+    let Fixture { app, .. } = fixture(false).await;
+
+    let (status, records) = get_json(
+        &app,
+        "/data/weather/gfs/current/current-t2m-surface-level-gfs-1.0.json",
+    )
+    .await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(records[0]["header"]["parameterUnit"], "K");
+
+    let (status, _) = get_json(
+        &app,
+        "/data/weather/gfs/1979/11/29/0100-t2m-surface-level-gfs-1.0.json",
+    )
+    .await;
+    assert_eq!(status, StatusCode::OK);
+
+    // The default dataset keeps Earth's own paths
+    let (status, records) = get_json(
+        &app,
+        "/data/weather/1979/11/29/0100-wind-surface-level-gfs-1.0.json",
+    )
+    .await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(records.as_array().unwrap().len(), 2);
+
+    let (status, _) = get_json(
+        &app,
+        "/data/weather/cmip6/current/current-t2m-surface-level-gfs-1.0.json",
+    )
+    .await;
+    assert_eq!(status, StatusCode::NOT_FOUND);
+    let (status, _) = get_json(&app, "/data/weather/gfs/1979/11").await;
+    assert_eq!(status, StatusCode::NOT_FOUND);
+}
+
+#[tokio::test]
+async fn test_strict_query_uses_dataset_dimensions() {
+    let Fixture { app, .. } = fixture(true).await;
+    let (status, _) = get_json(&app, "/proxy/gfs/data?vars=t2m&lat_index=0").await;
+    assert_eq!(status, StatusCode::OK);
+    let (status, _) = get_json(&app, "/proxy/gfs/data?vars=t2m&color=red").await;
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+}
+
+#[tokio::test]
+async fn test_invalid_datasets() {
+    let backend = MockBackend::default().start().await;
+    for names in [["era5", "era5"], ["era5", "current"]] {
+        let datasets = names
+            .iter()
+            .map(|name| DatasetConfig {
+                name: name.to_string(),
+                api_url: backend.url().to_string(),
+            })
+            .collect();
+        let config = ServerConfig::builder(backend.url())
+            .datasets(datasets)
+            .build()
+            .unwrap();
+        assert!(AppState::from_config(config).await.is_err());
+    }
+}
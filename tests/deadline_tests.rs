@@ -0,0 +1,76 @@
+//! Integration tests for request deadlines
+
+use axum::{
+    body::Body,
+    http::{Request, StatusCode},
+    routing::get,
+    Router,
+};
+use std::{
+    sync::Arc,
+    time::{Duration, Instant},
+};
+use tower::ServiceExt;
+
+use rossby_vis::{
+    handlers::proxy_metadata,
+    pipeline::{apply_pipeline, CompressionConfig, CorsConfig, DEFAULT_PIPELINE},
+    server::AppState,
+    testing::MockBackend,
+};
+
+async fn app(latency: Duration) -> Router {
+    let backend = MockBackend::default().with_latency(latency).start().await;
+    let state = Arc::new(AppState::new(
+        backend.url().to_string(),
+        reqwest::Client::new(),
+    ));
+    let router = Router::new().route("/proxy/metadata", get(proxy_metadata));
+    apply_pipeline(
+        router,
+        &state,
+        &DEFAULT_PIPELINE,
+        &CorsConfig::default(),
+        &CompressionConfig::default(),
+    )
+    .unwrap()
+    .with_state(state)
+}
+
+async fn status(app: &Router, timeout: Option<&str>) -> StatusCode {
+    let mut request = Request::builder().uri("/proxy/metadata");
+    if let Some(timeout) = timeout {
+        request = request.header("x-request-timeout", timeout);
+    }
+    app.clone()
+        .oneshot(request.body(Body::empty()).unwrap())
+        .await
+        .unwrap()
+        .status()
+}
+
+#[tokio::test]
+async fn test_slow_backend_fails_within_the_timeout() {
+    let app = app(Duration::from_millis(500)).await;
+    let started = Instant::now();
+    assert_eq!(
+        status(&app, Some("100ms")).await,
+        StatusCode::GATEWAY_TIMEOUT
+    );
+    assert!(started.elapsed() < Duration::from_millis(400));
+}
+
+#[tokio::test]
+async fn test_requests_within_the_timeout_succeed() {
+    let app = app(Duration::from_millis(50)).await;
+    assert_eq!(status(&app, None).await, StatusCode::OK);
+    assert_eq!(status(&app, Some("5")).await, StatusCode::OK);
+    assert_eq!(status(&app, Some("2s")).await, StatusCode::OK);
+}
+
+#[tokio::test]
+async fn test_invalid_timeout_is_rejected() {
+    let app = app(Duration::ZERO).await;
+    assert_eq!(status(&app, Some("soon")).await, StatusCode::BAD_REQUEST);
+    assert_eq!(status(&app, Some("0")).await, StatusCode::BAD_REQUEST);
+}
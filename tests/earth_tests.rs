@@ -0,0 +1,351 @@
+//! Integration tests for the Earth-format data routes
+//!
+//! A mock Rossby server with a tiny 2 × 2 grid is started on an ephemeral
+//! port and the Earth handlers are exercised through a router.
+
+use axum::{
+    body::Body,
+    http::{Method, Request, StatusCode},
+    routing::get,
+    Router,
+};
+use serde_json::{json, Value};
+use std::sync::Arc;
+use tower::ServiceExt;
+
+use rossby_vis::{
+    derived::wind_chill_celsius,
+    handlers::{earth_dated_data, earth_dynamic_data, proxy_data, proxy_metadata},
+    products::products_catalog,
+    server::AppState,
+};
+
+/// Mock Rossby server with sea surface temperature, a land-sea mask and
+/// surface temperature and wind
+mod mock_server {
+    use axum::{extract::Query, response::Json, routing::get, Router};
+    use serde_json::{json, Value};
+    use std::collections::HashMap;
+    use tokio::net::TcpListener;
+
+    pub async fn start() -> String {
+        let app = Router::new()
+            .route("/metadata", get(metadata))
+            .route("/data", get(data));
+
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+
+        tokio::spawn(async move {
+            axum::Server::from_tcp(listener.into_std().unwrap())
+                .unwrap()
+                .serve(app.into_make_service())
+                .await
+                .unwrap();
+        });
+
+        format!("http://{}", addr)
+    }
+
+    async fn metadata() -> Json<Value> {
+        Json(json!({
+            "coordinates": {
+                "latitude": [10.0, 0.0],
+                "longitude": [0.0, 10.0],
+                "time": [700464.0, 700470.0]
+            },
+            "dimensions": {
+                "latitude": {"size": 2},
+                "longitude": {"size": 2},
+                "time": {"size": 2}
+            },
+            "variables": {
+                "sst": {
+                    "dimensions": ["time", "latitude", "longitude"],
+                    "attributes": {"long_name": "Sea surface temperature", "units": "K"}
+                },
+                "lsm": {
+                    "dimensions": ["time", "latitude", "longitude"],
+                    "attributes": {"long_name": "Land-sea mask", "units": "(0 - 1)"}
+                },
+                "t2m": {
+                    "dimensions": ["time", "latitude", "longitude"],
+                    "attributes": {"long_name": "2 metre temperature", "units": "K"}
+                },
+                "u10": {
+                    "dimensions": ["time", "latitude", "longitude"],
+                    "attributes": {"long_name": "10 metre U wind component", "units": "m s**-1"}
+                },
+                "v10": {
+                    "dimensions": ["time", "latitude", "longitude"],
+                    "attributes": {"long_name": "10 metre V wind component", "units": "m s**-1"}
+                }
+            }
+        }))
+    }
+
+    async fn data(Query(params): Query<HashMap<String, String>>) -> Json<Value> {
+        let vars = params.get("vars").cloned().unwrap_or_default();
+        let mut data = serde_json::Map::new();
+        for var in vars.split(',') {
+            let values = match var {
+                "lsm" => json!([1.0, 0.0, 0.0, 1.0]),
+                // -20 °C, with a 10 m/s wind in the first two cells only
+                "t2m" => json!([253.15, 253.15, 253.15, 303.15]),
+                "u10" => json!([6.0, 0.0, 0.0, 6.0]),
+                "v10" => json!([8.0, 10.0, 0.0, 8.0]),
+                _ if params.get("time").map(String::as_str) == Some("700470") => {
+                    json!([310.0, 311.0, 312.0, 313.0])
+                }
+                _ => json!([300.0, 301.0, 302.0, 303.0]),
+            };
+            data.insert(var.to_string(), values);
+        }
+
+        Json(json!({
+            "metadata": {
+                "query": params,
+                "shape": [1, 2, 2],
+                "dimensions": ["time", "latitude", "longitude"]
+            },
+            "data": data
+        }))
+    }
+}
+
+async fn create_test_router(configure: impl FnOnce(&mut AppState)) -> Router {
+    let mut state = AppState::new(mock_server::start().await, reqwest::Client::new());
+    configure(&mut state);
+
+    Router::new()
+        .route("/earth/:variable", get(earth_dynamic_data))
+        .route(
+            "/data/weather/:year/:month/:day/:file",
+            get(earth_dated_data),
+        )
+        .route("/proxy/metadata", get(proxy_metadata))
+        .route("/proxy/data", get(proxy_data))
+        .route("/data/products.json", get(products_catalog))
+        .with_state(Arc::new(state))
+}
+
+async fn get_json(app: Router, uri: &str) -> (StatusCode, Value) {
+    let request = Request::builder()
+        .method(Method::GET)
+        .uri(uri)
+        .body(Body::empty())
+        .unwrap();
+
+    let response = app.oneshot(request).await.unwrap();
+    let status = response.status();
+    let bytes = hyper::body::to_bytes(response.into_body()).await.unwrap();
+    (status, serde_json::from_slice(&bytes).unwrap())
+}
+
+#[tokio::test]
+async fn test_unmasked_scalar_field() {
+    let app = create_test_router(|_| {}).await;
+
+    let (status, body) = get_json(app, "/earth/sst").await;
+
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(body[0]["data"], json!([300.0, 301.0, 302.0, 303.0]));
+}
+
+#[tokio::test]
+async fn test_requested_timestep() {
+    let app = create_test_router(|_| {}).await;
+
+    let (status, body) = get_json(app.clone(), "/earth/sst?time=700470").await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(body[0]["data"], json!([310.0, 311.0, 312.0, 313.0]));
+    assert_eq!(body[0]["header"]["refTime"], "1979-11-29T06:00:00+00:00");
+
+    // Times between the dataset's timesteps are rejected
+    let (status, body) = get_json(app, "/earth/sst?time=700465").await;
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+    assert!(body["error"]
+        .as_str()
+        .unwrap()
+        .contains("2 timesteps from 700464 to 700470"));
+}
+
+#[tokio::test]
+async fn test_iso_times_resolve_to_nearest_timestep() {
+    let app = create_test_router(|_| {}).await;
+
+    // Timesteps are at 00:00 and 06:00 on 1979-11-29
+    for (uri, data_time, first_value) in [
+        ("/earth/sst?time=1979-11-29T05:00:00Z", "06:00", 310.0),
+        ("/earth/sst?time=1979-11-29T02:00", "00:00", 300.0),
+        (
+            "/data/weather/1979/11/29/0500-sst-surface-level-gfs-1.0.json",
+            "06:00",
+            310.0,
+        ),
+        (
+            "/data/weather/1979/11/29/0100-wind-surface-level-gfs-1.0.json",
+            "00:00",
+            6.0,
+        ),
+        ("/proxy/data?vars=sst&time=1979-11-30", "06:00", 310.0),
+    ] {
+        let response = app
+            .clone()
+            .oneshot(Request::builder().uri(uri).body(Body::empty()).unwrap())
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK, "{}", uri);
+        assert_eq!(
+            response.headers()["x-data-time"],
+            format!("1979-11-29T{}:00+00:00", data_time).as_str(),
+            "{}",
+            uri
+        );
+        let bytes = hyper::body::to_bytes(response.into_body()).await.unwrap();
+        let body: Value = serde_json::from_slice(&bytes).unwrap();
+        let first = if uri.starts_with("/proxy") {
+            body["data"]["sst"][0].clone()
+        } else {
+            body[0]["data"][0].clone()
+        };
+        assert_eq!(first, json!(first_value), "{}", uri);
+    }
+
+    let (status, _) = get_json(
+        app,
+        "/data/weather/1979/13/29/0500-sst-surface-level-gfs-1.0.json",
+    )
+    .await;
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+}
+
+#[tokio::test]
+async fn test_grids_over_the_size_limit() {
+    let app = create_test_router(|state| state.earth_grid_limit.max_points = 3).await;
+
+    let (status, body) = get_json(app, "/earth/sst").await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(body[0]["header"]["nx"], 1);
+    assert_eq!(body[0]["header"]["ny"], 1);
+    assert_eq!(body[0]["header"]["dx"], 20.0);
+    assert_eq!(body[0]["data"], json!([300.0]));
+
+    let app = create_test_router(|state| {
+        state.earth_grid_limit.max_points = 3;
+        state.earth_grid_limit.downsample = false;
+    })
+    .await;
+    let (status, body) = get_json(app, "/earth/sst").await;
+    assert_eq!(status, StatusCode::INTERNAL_SERVER_ERROR);
+    assert!(body["error"]
+        .as_str()
+        .unwrap()
+        .contains("more than the limit of 3"));
+}
+
+#[tokio::test]
+async fn test_explicit_land_mask() {
+    let app = create_test_router(|_| {}).await;
+
+    let (status, body) = get_json(app, "/earth/sst?mask=land").await;
+
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(body[0]["data"], json!([null, 301.0, 302.0, null]));
+}
+
+#[tokio::test]
+async fn test_automatic_mask_for_ocean_variable() {
+    let app = create_test_router(|state| state.land_sea_mask.auto_mask = true).await;
+
+    let (_, body) = get_json(app.clone(), "/earth/sst").await;
+    assert_eq!(body[0]["data"], json!([null, 301.0, 302.0, null]));
+
+    let (_, body) = get_json(app, "/earth/sst?mask=none").await;
+    assert_eq!(body[0]["data"], json!([300.0, 301.0, 302.0, 303.0]));
+}
+
+#[tokio::test]
+async fn test_auxiliary_mask_values() {
+    let app = create_test_router(|state| {
+        state.land_sea_mask.values = Some(vec![0.0, 0.0, 1.0, 1.0]);
+    })
+    .await;
+
+    let (_, body) = get_json(app, "/earth/sst?mask=ocean").await;
+    assert_eq!(body[0]["data"], json!([null, null, 302.0, 303.0]));
+}
+
+#[tokio::test]
+async fn test_derived_products_registered_in_catalog() {
+    let app = create_test_router(|_| {}).await;
+
+    let (status, body) = get_json(app, "/proxy/metadata").await;
+
+    assert_eq!(status, StatusCode::OK);
+    let wind_chill = &body["variables"]["wind_chill"];
+    assert_eq!(wind_chill["attributes"]["units"], "K");
+    assert_eq!(
+        wind_chill["attributes"]["derived_from"],
+        json!(["t2m", "u10", "v10"])
+    );
+    // No humidity field, so no heat index
+    assert!(body["variables"].get("heat_index").is_none());
+
+    // Wind components are paired for the frontend
+    assert_eq!(
+        body["variables"]["u10"]["attributes"]["northward_component"],
+        "v10"
+    );
+}
+
+#[tokio::test]
+async fn test_derived_product_data() {
+    let app = create_test_router(|_| {}).await;
+    let expected = wind_chill_celsius(-20.0, 36.0) + 273.15;
+
+    let (status, body) = get_json(
+        app.clone(),
+        "/proxy/data?vars=wind_chill&time=700464&format=json",
+    )
+    .await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(body["metadata"]["shape"], json!([1, 2, 2]));
+    assert!(body["data"].get("t2m").is_none());
+    let values = body["data"]["wind_chill"].as_array().unwrap();
+    assert!((values[0].as_f64().unwrap() - expected).abs() < 1e-9);
+    assert_eq!(values[2], json!(253.15));
+    assert_eq!(values[3], json!(303.15));
+
+    let (status, body) = get_json(app, "/earth/wind_chill").await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(
+        body[0]["header"]["parameterNumberName"],
+        "Wind chill temperature"
+    );
+    let values = body[0]["data"].as_array().unwrap();
+    assert!((values[1].as_f64().unwrap() - expected).abs() < 1e-9);
+}
+
+#[tokio::test]
+async fn test_products_catalog() {
+    let app = create_test_router(|_| {}).await;
+
+    let (status, body) = get_json(app, "/data/products.json").await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(body["version"], 1);
+    let products = body["products"].as_array().unwrap();
+    let types: Vec<&str> = products
+        .iter()
+        .map(|p| p["type"].as_str().unwrap())
+        .collect();
+    assert_eq!(types, ["lsm", "sst", "t2m", "u10", "wind_chill"]);
+
+    let sst = &products[1];
+    assert_eq!(sst["field"], "scalar");
+    assert_eq!(sst["name"], "Sea surface temperature");
+    assert_eq!(sst["category"], "Temperature");
+    assert_eq!(sst["units"], "K");
+    assert_eq!(products[3]["vars"], json!(["u10", "v10"]));
+    assert_eq!(products[4]["derived"], true);
+}
@@ -0,0 +1,87 @@
+//! Integration tests for backend URL handling
+
+use axum::{
+    body::Body,
+    http::{Method, Request, StatusCode},
+    routing::get,
+    Router,
+};
+use std::sync::Arc;
+use tower::ServiceExt;
+
+use rossby_vis::{
+    endpoint::BackendEndpoint,
+    handlers::proxy_metadata,
+    server::{run_server_with_config, AppState, ServerConfig},
+};
+
+/// Mock Rossby server that requires basic auth
+mod mock_server {
+    use axum::{http::HeaderMap, http::StatusCode, response::Json, routing::get, Router};
+    use serde_json::{json, Value};
+    use tokio::net::TcpListener;
+
+    pub async fn start() -> String {
+        let app = Router::new().route("/rossby/metadata", get(metadata));
+
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+
+        tokio::spawn(async move {
+            axum::Server::from_tcp(listener.into_std().unwrap())
+                .unwrap()
+                .serve(app.into_make_service())
+                .await
+                .unwrap();
+        });
+
+        format!("http://{}", addr)
+    }
+
+    async fn metadata(headers: HeaderMap) -> Result<Json<Value>, StatusCode> {
+        // base64("user:p@ss")
+        if headers.get("authorization").and_then(|v| v.to_str().ok()) != Some("Basic dXNlcjpwQHNz")
+        {
+            return Err(StatusCode::UNAUTHORIZED);
+        }
+
+        Ok(Json(json!({
+            "coordinates": {"latitude": [0.0], "longitude": [0.0]},
+            "dimensions": {"latitude": {"size": 1}, "longitude": {"size": 1}},
+            "variables": {}
+        })))
+    }
+}
+
+#[tokio::test]
+async fn test_embedded_credentials_are_sent_as_basic_auth() {
+    let server_url = mock_server::start().await;
+    let api_url = server_url.replace("http://", "http://user:p%40ss@") + "/rossby/";
+
+    let endpoint: BackendEndpoint = api_url.parse().unwrap();
+    assert_eq!(endpoint.base_url, format!("{}/rossby", server_url));
+
+    let mut state = AppState::new(endpoint.base_url, reqwest::Client::new());
+    state.backend_credentials = endpoint.credentials;
+    let app = Router::new()
+        .route("/proxy/metadata", get(proxy_metadata))
+        .with_state(Arc::new(state));
+
+    let request = Request::builder()
+        .method(Method::GET)
+        .uri("/proxy/metadata")
+        .body(Body::empty())
+        .unwrap();
+    let response = app.oneshot(request).await.unwrap();
+
+    assert_eq!(response.status(), StatusCode::OK);
+}
+
+#[tokio::test]
+async fn test_server_rejects_invalid_api_url_at_startup() {
+    let config = ServerConfig::new(0, "localhost:8000".to_string());
+
+    let error = run_server_with_config(config).await.unwrap_err();
+
+    assert!(error.to_string().contains("missing scheme"));
+}
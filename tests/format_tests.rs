@@ -0,0 +1,79 @@
+//! Integration tests for output format negotiation on the Earth routes
+
+use axum::{
+    body::Body,
+    http::{header, response::Parts, Request, StatusCode},
+    Router,
+};
+use serde_json::Value;
+use std::sync::Arc;
+use tower::ServiceExt;
+
+use rossby_vis::{build_router, testing::MockBackend, AppState};
+
+const WIND: &str = "/data/weather/current/current-wind-surface-level-gfs-1.0.json";
+
+async fn get(app: &Router, uri: &str, accept: Option<&str>) -> (Parts, Vec<u8>) {
+    let mut request = Request::builder().uri(uri);
+    if let Some(accept) = accept {
+        request = request.header(header::ACCEPT, accept);
+    }
+    let response = app
+        .clone()
+        .oneshot(request.body(Body::empty()).unwrap())
+        .await
+        .unwrap();
+    let (parts, body) = response.into_parts();
+    (parts, hyper::body::to_bytes(body).await.unwrap().to_vec())
+}
+
+#[tokio::test]
+async fn test_formats_of_the_same_product() {
+    let backend = MockBackend::default().start().await;
+    let app = build_router(Arc::new(AppState::new(
+        backend.url().to_string(),
+        reqwest::Client::new(),
+    )));
+
+    let (json, json_body) = get(&app, WIND, None).await;
+    assert_eq!(json.status, StatusCode::OK);
+    assert_eq!(json.headers[header::CONTENT_TYPE], "application/json");
+    assert_eq!(json.headers[header::VARY], "accept");
+    let records: Value = serde_json::from_slice(&json_body).unwrap();
+
+    // MessagePack carries the same document
+    for (uri, accept) in [
+        (format!("{}?format=msgpack", WIND), None),
+        (WIND.to_string(), Some("application/msgpack")),
+    ] {
+        let (msgpack, body) = get(&app, &uri, accept).await;
+        assert_eq!(msgpack.status, StatusCode::OK);
+        assert_eq!(msgpack.headers[header::CONTENT_TYPE], "application/msgpack");
+        let decoded: Value = rmp_serde::from_slice(&body).unwrap();
+        assert_eq!(decoded, records);
+    }
+
+    let (packed, body) = get(&app, WIND, Some("application/vnd.rossby.packed+json")).await;
+    assert_eq!(
+        packed.headers[header::CONTENT_TYPE],
+        "application/vnd.rossby.packed+json"
+    );
+    let packed_records: Value = serde_json::from_slice(&body).unwrap();
+    assert_eq!(packed_records[0]["header"], records[0]["header"]);
+    assert_eq!(packed_records[0]["packed"]["encoding"], "delta");
+
+    // Representations of one URL are validated separately
+    let (msgpack, _) = get(&app, WIND, Some("application/msgpack")).await;
+    assert_ne!(msgpack.headers[header::ETAG], json.headers[header::ETAG]);
+    let (browser, _) = get(&app, WIND, Some("text/html,*/*;q=0.8")).await;
+    assert_eq!(browser.headers[header::ETAG], json.headers[header::ETAG]);
+
+    let (invalid, _) = get(&app, &format!("{}?format=xml", WIND), None).await;
+    assert_eq!(invalid.status, StatusCode::BAD_REQUEST);
+
+    // Frame bundles negotiate the same way
+    let (frames, body) = get(&app, "/data/frames/wind?count=2&format=msgpack", None).await;
+    assert_eq!(frames.status, StatusCode::OK);
+    let bundle: Value = rmp_serde::from_slice(&body).unwrap();
+    assert_eq!(bundle["frames"].as_array().unwrap().len(), 2);
+}
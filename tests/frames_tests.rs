@@ -0,0 +1,197 @@
+//! Integration tests for the Earth frame bundles
+//!
+//! The mock Rossby server has three timesteps six hours apart and answers
+//! `time_range` requests with every timestep in the range, so a bundle can
+//! be checked frame by frame.
+
+use axum::{
+    body::Body,
+    http::{Request, StatusCode},
+    routing::get,
+    Router,
+};
+use serde_json::{json, Value};
+use std::sync::Arc;
+use tower::ServiceExt;
+
+use rossby_vis::{frames::earth_frames, server::AppState};
+
+mod mock_server {
+    use axum::{extract::Query, response::Json, routing::get, Router};
+    use serde_json::{json, Value};
+    use std::collections::HashMap;
+    use tokio::net::TcpListener;
+
+    pub const TIMES: [f64; 3] = [700464.0, 700470.0, 700476.0];
+
+    pub async fn start() -> String {
+        let app = Router::new()
+            .route("/metadata", get(metadata))
+            .route("/data", get(data));
+
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+
+        tokio::spawn(async move {
+            axum::Server::from_tcp(listener.into_std().unwrap())
+                .unwrap()
+                .serve(app.into_make_service())
+                .await
+                .unwrap();
+        });
+
+        format!("http://{}", addr)
+    }
+
+    async fn metadata() -> Json<Value> {
+        let dims = json!(["time", "latitude", "longitude"]);
+        Json(json!({
+            "coordinates": {
+                "latitude": [10.0, 0.0],
+                "longitude": [0.0, 10.0],
+                "time": TIMES
+            },
+            "dimensions": {
+                "latitude": {"size": 2},
+                "longitude": {"size": 2},
+                "time": {"size": 3}
+            },
+            "variables": {
+                "t2m": {"dimensions": dims, "attributes": {"long_name": "2 metre temperature", "units": "K"}},
+                "u10": {"dimensions": dims, "attributes": {"long_name": "10 metre U wind component", "units": "m s**-1"}},
+                "v10": {"dimensions": dims, "attributes": {"long_name": "10 metre V wind component", "units": "m s**-1"}}
+            }
+        }))
+    }
+
+    /// Temperatures rise by one kelvin per timestep; the last cell is missing
+    /// at the second. Winds are constant.
+    async fn data(Query(params): Query<HashMap<String, String>>) -> Json<Value> {
+        let times: Vec<f64> = match (params.get("time_range"), params.get("time")) {
+            (Some(range), _) => {
+                let (first, last) = range.split_once(',').unwrap();
+                let (first, last): (f64, f64) = (first.parse().unwrap(), last.parse().unwrap());
+                TIMES
+                    .into_iter()
+                    .filter(|t| *t >= first && *t <= last)
+                    .collect()
+            }
+            (None, Some(time)) => vec![time.parse().unwrap()],
+            (None, None) => TIMES.to_vec(),
+        };
+
+        let mut data = serde_json::Map::new();
+        for var in params["vars"].split(',') {
+            let values: Vec<Value> = times
+                .iter()
+                .flat_map(|time| {
+                    let step = TIMES.iter().position(|t| t == time).unwrap() as f64;
+                    match var {
+                        "t2m" => vec![
+                            json!(280.0 + step),
+                            json!(281.0 + step),
+                            json!(282.0 + step),
+                            if step == 1.0 {
+                                Value::Null
+                            } else {
+                                json!(283.0 + step)
+                            },
+                        ],
+                        _ => vec![json!(5.0); 4],
+                    }
+                })
+                .collect();
+            data.insert(var.to_string(), Value::Array(values));
+        }
+
+        Json(json!({
+            "metadata": {
+                "shape": [times.len(), 2, 2],
+                "dimensions": ["time", "latitude", "longitude"]
+            },
+            "data": data
+        }))
+    }
+}
+
+async fn create_test_router() -> Router {
+    let state = AppState::new(mock_server::start().await, reqwest::Client::new());
+
+    Router::new()
+        .route("/data/frames/:variable", get(earth_frames))
+        .with_state(Arc::new(state))
+}
+
+async fn get_json(app: &Router, uri: &str) -> (StatusCode, Value) {
+    let response = app
+        .clone()
+        .oneshot(Request::builder().uri(uri).body(Body::empty()).unwrap())
+        .await
+        .unwrap();
+    let status = response.status();
+    let bytes = hyper::body::to_bytes(response.into_body()).await.unwrap();
+    (status, serde_json::from_slice(&bytes).unwrap())
+}
+
+#[tokio::test]
+async fn test_frame_window() {
+    let app = create_test_router().await;
+
+    let (status, body) = get_json(&app, "/data/frames/t2m?start=700470&count=5").await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(body["encoding"], "full");
+
+    // The window stops at the end of the time axis
+    let frames = body["frames"].as_array().unwrap();
+    assert_eq!(frames.len(), 2);
+    assert_eq!(frames[0]["time"], 700470.0);
+    assert_eq!(frames[1]["date"], "1979-11-29T12:00:00+00:00");
+    assert_eq!(
+        frames[1]["records"][0]["header"]["refTime"],
+        frames[1]["date"]
+    );
+    assert_eq!(
+        frames[0]["records"][0]["data"],
+        json!([281.0, 282.0, 283.0, null])
+    );
+    assert_eq!(
+        frames[1]["records"][0]["data"],
+        json!([282.0, 283.0, 284.0, 285.0])
+    );
+
+    let (status, body) = get_json(&app, "/data/frames/u10?count=2").await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(body["frames"][1]["records"].as_array().unwrap().len(), 2);
+    assert_eq!(body["frames"][0]["time"], 700464.0);
+}
+
+#[tokio::test]
+async fn test_delta_encoded_frames() {
+    let app = create_test_router().await;
+
+    let (status, body) = get_json(&app, "/data/frames/t2m?count=3&delta=true").await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(body["encoding"], "delta");
+    let data = |frame: usize| body["frames"][frame]["records"][0]["data"].clone();
+    assert_eq!(data(0), json!([280.0, 281.0, 282.0, 283.0]));
+    assert_eq!(data(1), json!([1.0, 1.0, 1.0, null]));
+    // After a missing value the full value is sent
+    assert_eq!(data(2), json!([1.0, 1.0, 1.0, 285.0]));
+
+    // Derived products are computed frame by frame
+    let (status, body) = get_json(&app, "/data/frames/wind_chill?count=2").await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(body["frames"].as_array().unwrap().len(), 2);
+}
+
+#[tokio::test]
+async fn test_invalid_frame_requests() {
+    let app = create_test_router().await;
+
+    let (status, _) = get_json(&app, "/data/frames/t2m?count=0").await;
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+    let (status, _) = get_json(&app, "/data/frames/t2m?count=49").await;
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+    let (status, _) = get_json(&app, "/data/frames/t2m?level=850").await;
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+}
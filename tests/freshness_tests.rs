@@ -0,0 +1,194 @@
+//! Integration tests for the data freshness headers and `/api/cache-manifest`
+
+use axum::{
+    body::Body,
+    http::{Request, StatusCode},
+    response::Response,
+    routing::get,
+    Router,
+};
+use serde_json::Value;
+use std::sync::Arc;
+use tower::ServiceExt;
+
+use rossby_vis::{
+    freshness::CachePolicy,
+    handlers::{cache_manifest, earth_dynamic_data, proxy_data, proxy_metadata},
+    server::AppState,
+    testing::MockBackend,
+};
+
+/// Mock Rossby server with a single 2 × 2 temperature field
+mod mock_server {
+    use axum::{response::Json, routing::get, Router};
+    use serde_json::{json, Value};
+    use tokio::net::TcpListener;
+
+    pub async fn start() -> String {
+        let app = Router::new()
+            .route("/metadata", get(metadata))
+            .route("/data", get(data));
+
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+
+        tokio::spawn(async move {
+            axum::Server::from_tcp(listener.into_std().unwrap())
+                .unwrap()
+                .serve(app.into_make_service())
+                .await
+                .unwrap();
+        });
+
+        format!("http://{}", addr)
+    }
+
+    async fn metadata() -> Json<Value> {
+        Json(json!({
+            "coordinates": {
+                "latitude": [10.0, 0.0],
+                "longitude": [0.0, 10.0],
+                "time": [700464.0]
+            },
+            "dimensions": {
+                "latitude": {"size": 2},
+                "longitude": {"size": 2},
+                "time": {"size": 1}
+            },
+            "variables": {
+                "t2m": {
+                    "dimensions": ["time", "latitude", "longitude"],
+                    "attributes": {"long_name": "2 metre temperature", "units": "K"}
+                }
+            }
+        }))
+    }
+
+    async fn data() -> Json<Value> {
+        Json(json!({
+            "metadata": {"shape": [1, 2, 2], "dimensions": ["time", "latitude", "longitude"]},
+            "data": {"t2m": [280.0, 281.0, 282.0, 283.0]}
+        }))
+    }
+}
+
+async fn create_test_router() -> Router {
+    let state = AppState::new(mock_server::start().await, reqwest::Client::new());
+
+    Router::new()
+        .route("/proxy/metadata", get(proxy_metadata))
+        .route("/proxy/data", get(proxy_data))
+        .route("/earth/:variable", get(earth_dynamic_data))
+        .route("/api/cache-manifest", get(cache_manifest))
+        .with_state(Arc::new(state))
+}
+
+async fn send(app: &Router, uri: &str, if_none_match: Option<&str>) -> Response {
+    let mut request = Request::builder().uri(uri);
+    if let Some(etag) = if_none_match {
+        request = request.header("if-none-match", etag);
+    }
+    app.clone()
+        .oneshot(request.body(Body::empty()).unwrap())
+        .await
+        .unwrap()
+}
+
+fn header<'a>(response: &'a Response, name: &str) -> &'a str {
+    response.headers()[name].to_str().unwrap()
+}
+
+#[tokio::test]
+async fn test_data_responses_are_versioned() {
+    let app = create_test_router().await;
+
+    let metadata = send(&app, "/proxy/metadata", None).await;
+    assert_eq!(metadata.status(), StatusCode::OK);
+    let version = header(&metadata, "x-data-version").to_string();
+    assert_eq!(version.len(), 16);
+
+    for uri in ["/proxy/data?vars=t2m", "/earth/t2m"] {
+        let response = send(&app, uri, None).await;
+        assert_eq!(response.status(), StatusCode::OK, "{}", uri);
+        assert_eq!(header(&response, "x-data-version"), version);
+        assert_eq!(header(&response, "cache-control"), "no-cache");
+        let etag = header(&response, "etag").to_string();
+        assert!(etag.starts_with("W/\""));
+        assert_ne!(etag, header(&metadata, "etag"));
+
+        // A client holding the current response is not sent it again
+        let revalidated = send(&app, uri, Some(&etag)).await;
+        assert_eq!(revalidated.status(), StatusCode::NOT_MODIFIED, "{}", uri);
+        assert_eq!(header(&revalidated, "etag"), etag);
+        let bytes = hyper::body::to_bytes(revalidated.into_body())
+            .await
+            .unwrap();
+        assert!(bytes.is_empty());
+
+        let stale = send(&app, uri, Some("W/\"0000000000000000-00000000\"")).await;
+        assert_eq!(stale.status(), StatusCode::OK, "{}", uri);
+    }
+}
+
+#[tokio::test]
+async fn test_cache_manifest() {
+    let app = create_test_router().await;
+    let version = header(&send(&app, "/proxy/metadata", None).await, "x-data-version").to_string();
+
+    let response = send(&app, "/api/cache-manifest", None).await;
+    assert_eq!(response.status(), StatusCode::OK);
+    let bytes = hyper::body::to_bytes(response.into_body()).await.unwrap();
+    let manifest: Value = serde_json::from_slice(&bytes).unwrap();
+
+    assert_eq!(manifest["version"], 1);
+    assert_eq!(manifest["data_version"], version);
+    let routes = manifest["routes"].as_array().unwrap();
+    let data = routes
+        .iter()
+        .find(|route| route["prefix"] == "/proxy/data")
+        .unwrap();
+    assert_eq!(data["kind"], "data");
+    assert_eq!(data["validator"], "etag");
+    assert_eq!(routes.last().unwrap()["prefix"], "/");
+}
+
+#[tokio::test]
+async fn test_cache_policy_headers() {
+    let backend = MockBackend::default().start().await;
+    let mut state = AppState::new(backend.url().to_string(), reqwest::Client::new());
+    state.cache_policy = CachePolicy {
+        historical_max_age: Some(86400),
+        recent_max_age: Some(60),
+    };
+    let app = Router::new()
+        .route("/proxy/metadata", get(proxy_metadata))
+        .route("/proxy/data", get(proxy_data))
+        .route("/earth/:variable", get(earth_dynamic_data))
+        .with_state(Arc::new(state));
+
+    // The first of two timesteps, named in the request, is historical
+    for uri in ["/earth/u10?time=700464", "/proxy/data?vars=u10&time=700464"] {
+        let response = send(&app, uri, None).await;
+        assert_eq!(response.status(), StatusCode::OK, "{}", uri);
+        assert_eq!(
+            header(&response, "cache-control"),
+            "public, max-age=86400, immutable"
+        );
+        assert_eq!(
+            header(&response, "last-modified"),
+            "Thu, 29 Nov 1979 00:00:00 GMT"
+        );
+    }
+
+    for uri in [
+        "/proxy/metadata",
+        "/earth/u10",
+        "/earth/u10?time=700465",
+        "/proxy/data?vars=u10",
+    ] {
+        let response = send(&app, uri, None).await;
+        assert_eq!(response.status(), StatusCode::OK, "{}", uri);
+        assert_eq!(header(&response, "cache-control"), "public, max-age=60");
+        assert!(!response.headers().contains_key("last-modified"));
+    }
+}
@@ -0,0 +1,102 @@
+//! Integration tests for the backend probe of `/health`
+
+use axum::{body::Body, http::Request, Router};
+use serde_json::Value;
+use std::{sync::Arc, time::Duration};
+use tower::ServiceExt;
+
+use rossby_vis::{
+    build_router,
+    health::{BackendProbe, BackendStatus, HealthProbeConfig},
+    testing::MockBackend,
+    AppState, ServerConfig,
+};
+
+async fn health(app: &Router) -> Value {
+    let response = app
+        .clone()
+        .oneshot(
+            Request::builder()
+                .uri("/health")
+                .body(Body::empty())
+                .unwrap(),
+        )
+        .await
+        .unwrap();
+    assert!(response.status().is_success());
+    let body = hyper::body::to_bytes(response.into_body()).await.unwrap();
+    serde_json::from_slice(&body).unwrap()
+}
+
+#[tokio::test]
+async fn test_health_reports_backend() {
+    let backend = MockBackend::default().start().await;
+    let config = ServerConfig::builder(backend.url()).build().unwrap();
+    let app = build_router(AppState::from_config(config).await.unwrap());
+
+    let first = health(&app).await;
+    assert_eq!(first["backend"]["status"], "healthy");
+    assert_eq!(first["backend"]["url"], backend.url());
+    assert!(first["backend"]["last_success"].is_string());
+
+    // Results are reused until they expire
+    let requests = backend.requests();
+    let second = health(&app).await;
+    assert_eq!(
+        second["backend"]["checked_at"],
+        first["backend"]["checked_at"]
+    );
+    assert_eq!(backend.requests(), requests);
+}
+
+#[tokio::test]
+async fn test_unreachable_backend() {
+    let state = AppState::new("http://127.0.0.1:9".to_string(), reqwest::Client::new());
+    let app = build_router(Arc::new(state));
+
+    let report = health(&app).await;
+    assert_eq!(report["backend"]["status"], "unreachable");
+    assert!(report["backend"]["error"].is_string());
+    assert!(report["backend"]["last_success"].is_null());
+}
+
+#[tokio::test]
+async fn test_probe_keeps_last_success() {
+    let backend = MockBackend::default().start().await;
+    let mut state = AppState::new(backend.url().to_string(), reqwest::Client::new());
+    let probe = BackendProbe::new(HealthProbeConfig {
+        ttl: Duration::ZERO,
+        ..HealthProbeConfig::default()
+    });
+    let healthy = probe.check(&state).await;
+    assert_eq!(healthy.status, BackendStatus::Healthy);
+    assert_eq!(healthy.last_success, Some(healthy.checked_at));
+
+    state.api_url = "http://127.0.0.1:9".to_string();
+    let unreachable = probe.check(&state).await;
+    assert_eq!(unreachable.status, BackendStatus::Unreachable);
+    assert_eq!(unreachable.latency_ms, None);
+    assert_eq!(unreachable.last_success, healthy.last_success);
+}
+
+#[tokio::test]
+async fn test_slow_backend_is_degraded() {
+    let backend = MockBackend::default()
+        .with_latency(Duration::from_millis(200))
+        .start()
+        .await;
+    let state = AppState::new(backend.url().to_string(), reqwest::Client::new());
+    let probe = BackendProbe::new(HealthProbeConfig {
+        slow_threshold: Duration::from_millis(50),
+        ..HealthProbeConfig::default()
+    });
+    let result = probe.check(&state).await;
+    assert_eq!(result.status, BackendStatus::Degraded);
+    assert!(result.latency_ms.unwrap() >= 200);
+
+    let probe = BackendProbe::new(HealthProbeConfig {
+        timeout: Duration::from_millis(50),
+        ..HealthProbeConfig::default()
+    });
+    assert_eq!(probe.check(&state).await.status, BackendStatus::Unreachable);
+}
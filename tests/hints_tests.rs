@@ -0,0 +1,96 @@
+//! Integration tests for rendering hints in Earth headers
+
+use axum::{body::Body, http::Request, Router};
+use serde_json::Value;
+use tower::ServiceExt;
+
+use rossby_vis::{
+    build_router,
+    hints::RenderHints,
+    testing::{MockBackend, MockVariable},
+    AppState, ServerConfig,
+};
+
+async fn get_json(app: &Router, uri: &str) -> Value {
+    let response = app
+        .clone()
+        .oneshot(Request::builder().uri(uri).body(Body::empty()).unwrap())
+        .await
+        .unwrap();
+    let body = hyper::body::to_bytes(response.into_body()).await.unwrap();
+    serde_json::from_slice(&body).unwrap()
+}
+
+#[tokio::test]
+async fn test_hints_in_earth_headers() {
+    let backend = MockBackend::default()
+        .with_variables(vec![
+            MockVariable::new("u10", "10 metre U wind component", "m s**-1"),
+            MockVariable::new("v10", "10 metre V wind component", "m s**-1"),
+            MockVariable::new("t2m", "2 metre temperature", "K"),
+            MockVariable::new("sp", "Surface pressure", "Pa"),
+        ])
+        .start()
+        .await;
+    let mut hints = RenderHints::default();
+    hints
+        .add_json(
+            r#"[
+                {"variable": "v10", "particle_density": 0.5, "interpolation": "nearest"},
+                {"variable": "t2m", "display_offset": -273.15, "particle_density": 3,
+                 "display_name": "Air temperature"}
+            ]"#,
+        )
+        .unwrap();
+    let config = ServerConfig::builder(backend.url())
+        .configure(|config| config.render_hints = hints)
+        .build()
+        .unwrap();
+    let app = build_router(AppState::from_config(config).await.unwrap());
+
+    // Both components of a vector field carry its hints
+    let wind = get_json(
+        &app,
+        "/data/weather/current/current-wind-surface-level-gfs-1.0.json",
+    )
+    .await;
+    for record in wind.as_array().unwrap() {
+        assert_eq!(record["header"]["particleDensity"], 0.5);
+        assert_eq!(record["header"]["interpolation"], "nearest");
+        assert!(record["header"].get("displayOffset").is_none());
+    }
+
+    // Particle density only applies to vector fields
+    let temp = get_json(
+        &app,
+        "/data/weather/current/current-t2m-surface-level-gfs-1.0.json",
+    )
+    .await;
+    assert_eq!(temp[0]["header"]["displayOffset"], -273.15);
+    assert!(temp[0]["header"].get("particleDensity").is_none());
+    assert_eq!(temp[0]["header"]["parameterNumberName"], "Air temperature");
+    assert!(temp[0]["header"].get("display_name").is_none());
+
+    // Display names also name the overlays
+    let products = get_json(&app, "/data/products.json").await;
+    let name = |product: &str| {
+        products["products"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .find(|p| p["type"] == product)
+            .unwrap()["name"]
+            .clone()
+    };
+    assert_eq!(name("t2m"), "Air temperature");
+    assert_eq!(name("sp"), "Surface pressure");
+
+    // Fields without hints keep the plain Earth header
+    let pressure = get_json(
+        &app,
+        "/data/weather/current/current-sp-surface-level-gfs-1.0.json",
+    )
+    .await;
+    assert_eq!(pressure[0]["header"]["nx"], 3);
+    assert!(pressure[0]["header"].get("interpolation").is_none());
+}
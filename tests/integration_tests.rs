@@ -49,7 +49,7 @@ mod integration_tests {
 
         // Test 1: Check that index.html is served at root
         let index_response = client
-            .get(&format!("http://localhost:{}", port))
+            .get(format!("http://localhost:{}", port))
             .send()
             .expect("Failed to request index");
 
@@ -70,7 +70,7 @@ mod integration_tests {
 
         // Test 2: Check that a static asset path works (even if it returns 404)
         let css_response = client
-            .get(&format!("http://localhost:{}/styles/styles.css", port))
+            .get(format!("http://localhost:{}/styles/styles.css", port))
             .send()
             .expect("Failed to request CSS file");
 
@@ -90,7 +90,7 @@ mod integration_tests {
 
         // Test 3: Check that 404 is returned for non-existent assets
         let not_found_response = client
-            .get(&format!("http://localhost:{}/nonexistent.file", port))
+            .get(format!("http://localhost:{}/nonexistent.file", port))
             .send()
             .expect("Failed to request non-existent file");
 
@@ -0,0 +1,214 @@
+//! Integration tests for the background job routes
+//!
+//! Jobs run the sampling analysis against a mock Rossby server with a small
+//! surface dataset.
+
+use axum::{
+    body::Body,
+    http::{header, Method, Request, StatusCode},
+    routing::{get, post},
+    Router,
+};
+use serde_json::{json, Value};
+use std::{sync::Arc, time::Duration};
+use tower::ServiceExt;
+
+use rossby_vis::{
+    jobs::{cancel_job, job_events, job_result, job_status, submit_job},
+    server::AppState,
+};
+
+mod mock_server {
+    use axum::{extract::Query, response::Json, routing::get, Router};
+    use serde_json::{json, Value};
+    use std::collections::HashMap;
+    use tokio::net::TcpListener;
+
+    pub async fn start() -> String {
+        let app = Router::new()
+            .route("/metadata", get(metadata))
+            .route("/data", get(data));
+
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+
+        tokio::spawn(async move {
+            axum::Server::from_tcp(listener.into_std().unwrap())
+                .unwrap()
+                .serve(app.into_make_service())
+                .await
+                .unwrap();
+        });
+
+        format!("http://{}", addr)
+    }
+
+    async fn metadata() -> Json<Value> {
+        Json(json!({
+            "coordinates": {
+                "latitude": [10.0, -10.0],
+                "longitude": [0.0, 90.0],
+                "time": [700464.0]
+            },
+            "dimensions": {
+                "latitude": {"size": 2},
+                "longitude": {"size": 2},
+                "time": {"size": 1}
+            },
+            "variables": {
+                "t2m": {
+                    "dimensions": ["time", "latitude", "longitude"],
+                    "attributes": {"long_name": "2 metre temperature", "units": "K"}
+                }
+            }
+        }))
+    }
+
+    async fn data(Query(params): Query<HashMap<String, String>>) -> Json<Value> {
+        Json(json!({
+            "metadata": {
+                "query": params,
+                "shape": [1, 2, 2],
+                "dimensions": ["time", "latitude", "longitude"]
+            },
+            "data": {"t2m": [280.0, 280.0, 280.0, 280.0]}
+        }))
+    }
+}
+
+async fn create_test_router() -> Router {
+    let state = AppState::new(mock_server::start().await, reqwest::Client::new());
+
+    Router::new()
+        .route("/api/jobs", post(submit_job))
+        .route("/api/jobs/:id", get(job_status).delete(cancel_job))
+        .route("/api/jobs/:id/result", get(job_result))
+        .route("/api/jobs/:id/events", get(job_events))
+        .with_state(Arc::new(state))
+}
+
+async fn send(app: &Router, method: Method, uri: &str, body: Option<Value>) -> (StatusCode, Value) {
+    let request = Request::builder()
+        .method(method)
+        .uri(uri)
+        .header(header::CONTENT_TYPE, "application/json")
+        .body(body.map_or_else(Body::empty, |body| Body::from(body.to_string())))
+        .unwrap();
+    let response = app.clone().oneshot(request).await.unwrap();
+    let status = response.status();
+    let bytes = hyper::body::to_bytes(response.into_body()).await.unwrap();
+    (status, serde_json::from_slice(&bytes).unwrap())
+}
+
+/// Submit a sampling job, returning its status URI
+async fn submit_sample(app: &Router, vars: Value) -> String {
+    let (status, body) = send(
+        app,
+        Method::POST,
+        "/api/jobs",
+        Some(json!({
+            "kind": "sample",
+            "request": {"points": [{"lat": 0.0, "lon": 45.0}], "vars": vars},
+            "priority": "high"
+        })),
+    )
+    .await;
+    assert_eq!(status, StatusCode::ACCEPTED);
+    assert_eq!(body["kind"], "sample");
+    assert_eq!(body["priority"], "high");
+    format!("/api/jobs/{}", body["id"].as_str().unwrap())
+}
+
+/// Poll a job until it finishes
+async fn wait_for(app: &Router, uri: &str) -> Value {
+    for _ in 0..100 {
+        let (status, body) = send(app, Method::GET, uri, None).await;
+        assert_eq!(status, StatusCode::OK);
+        if !matches!(body["state"].as_str(), Some("queued" | "running")) {
+            return body;
+        }
+        tokio::time::sleep(Duration::from_millis(20)).await;
+    }
+    panic!("Job {} did not finish", uri);
+}
+
+#[tokio::test]
+async fn test_job_runs_to_completion() {
+    let app = create_test_router().await;
+    let uri = submit_sample(&app, json!(["t2m"])).await;
+
+    let status = wait_for(&app, &uri).await;
+    assert_eq!(status["state"], "succeeded");
+    assert!(status["started_at"].is_string());
+    assert!(status["finished_at"].is_string());
+
+    let (code, result) = send(&app, Method::GET, &format!("{}/result", uri), None).await;
+    assert_eq!(code, StatusCode::OK);
+    assert_eq!(result["units"]["t2m"], "K");
+    assert_eq!(result["points"][0]["values"]["t2m"], json!([280.0]));
+
+    // The event stream of a finished job holds its final status and ends
+    let response = app
+        .clone()
+        .oneshot(
+            Request::builder()
+                .uri(format!("{}/events", uri))
+                .body(Body::empty())
+                .unwrap(),
+        )
+        .await
+        .unwrap();
+    assert_eq!(
+        response.headers()[header::CONTENT_TYPE],
+        "text/event-stream"
+    );
+    let bytes = hyper::body::to_bytes(response.into_body()).await.unwrap();
+    let events = String::from_utf8(bytes.to_vec()).unwrap();
+    assert!(events.starts_with("event:status\ndata:{"));
+    assert_eq!(events.matches("data:").count(), 1);
+    assert!(events.contains(r#""state":"succeeded""#));
+}
+
+#[tokio::test]
+async fn test_failed_job_keeps_its_error() {
+    let app = create_test_router().await;
+    let uri = submit_sample(&app, json!(["nope"])).await;
+
+    let status = wait_for(&app, &uri).await;
+    assert_eq!(status["state"], "failed");
+    assert!(status["error"].as_str().unwrap().contains("nope"));
+
+    let (code, result) = send(&app, Method::GET, &format!("{}/result", uri), None).await;
+    assert_eq!(code, StatusCode::BAD_REQUEST);
+    assert!(result["error"].as_str().unwrap().contains("nope"));
+
+    // Cancelling a finished job changes nothing
+    let (code, status) = send(&app, Method::DELETE, &uri, None).await;
+    assert_eq!(code, StatusCode::OK);
+    assert_eq!(status["state"], "failed");
+}
+
+#[tokio::test]
+async fn test_invalid_and_unknown_jobs() {
+    let app = create_test_router().await;
+
+    let (status, _) = send(
+        &app,
+        Method::POST,
+        "/api/jobs",
+        Some(json!({"kind": "trajectories", "request": {"seeds": "everywhere"}})),
+    )
+    .await;
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+
+    let (status, _) = send(&app, Method::GET, "/api/jobs/not-a-job", None).await;
+    assert_eq!(status, StatusCode::NOT_FOUND);
+    let (status, _) = send(
+        &app,
+        Method::GET,
+        "/api/jobs/4b8e6d3c-96a4-4cf5-9c47-3b0b2f3f8b1e/result",
+        None,
+    )
+    .await;
+    assert_eq!(status, StatusCode::NOT_FOUND);
+}
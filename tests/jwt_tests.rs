@@ -0,0 +1,173 @@
+//! Integration tests for bearer tokens of an OpenID Connect provider
+
+use axum::{
+    body::Body,
+    http::{Request, StatusCode},
+    routing::get,
+    Json, Router,
+};
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine as _};
+use jsonwebtoken::{encode, Algorithm, EncodingKey, Header};
+use ring::signature::{Ed25519KeyPair, KeyPair};
+use serde_json::json;
+use std::sync::{
+    atomic::{AtomicUsize, Ordering},
+    Arc,
+};
+use std::time::{SystemTime, UNIX_EPOCH};
+use tokio::net::TcpListener;
+use tower::ServiceExt;
+
+use rossby_vis::{
+    auth::AuthConfig, build_router, jwt::JwtConfig, testing::MockBackend, AppState, ServerConfig,
+};
+
+/// A provider serving its discovery document and one Ed25519 signing key
+struct Provider {
+    issuer: String,
+    key: EncodingKey,
+    fetches: Arc<AtomicUsize>,
+}
+
+impl Provider {
+    async fn start() -> Self {
+        let pkcs8 = Ed25519KeyPair::generate_pkcs8(&ring::rand::SystemRandom::new()).unwrap();
+        let pair = Ed25519KeyPair::from_pkcs8(pkcs8.as_ref()).unwrap();
+        let jwks = json!({"keys": [{
+            "kty": "OKP",
+            "crv": "Ed25519",
+            "x": URL_SAFE_NO_PAD.encode(pair.public_key().as_ref()),
+            "kid": "k1",
+            "alg": "EdDSA",
+            "use": "sig"
+        }]});
+
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let issuer = format!("http://{}", listener.local_addr().unwrap());
+        let fetches = Arc::new(AtomicUsize::new(0));
+        let discovery = json!({"issuer": issuer, "jwks_uri": format!("{}/jwks", issuer)});
+        let counter = fetches.clone();
+        let app = Router::new()
+            .route(
+                "/.well-known/openid-configuration",
+                get(move || async move { Json(discovery) }),
+            )
+            .route(
+                "/jwks",
+                get(move || async move {
+                    counter.fetch_add(1, Ordering::SeqCst);
+                    Json(jwks)
+                }),
+            );
+        tokio::spawn(
+            axum::Server::from_tcp(listener.into_std().unwrap())
+                .unwrap()
+                .serve(app.into_make_service()),
+        );
+
+        Self {
+            issuer,
+            key: EncodingKey::from_ed_der(pkcs8.as_ref()),
+            fetches,
+        }
+    }
+
+    fn token(&self, claims: serde_json::Value) -> String {
+        let header = Header {
+            kid: Some("k1".to_string()),
+            ..Header::new(Algorithm::EdDSA)
+        };
+        encode(&header, &claims, &self.key).unwrap()
+    }
+}
+
+fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap()
+        .as_secs()
+}
+
+async fn status(app: &Router, uri: &str, token: Option<&str>) -> StatusCode {
+    let mut request = Request::builder().uri(uri);
+    if let Some(token) = token {
+        request = request.header("authorization", format!("Bearer {}", token));
+    }
+    app.clone()
+        .oneshot(request.body(Body::empty()).unwrap())
+        .await
+        .unwrap()
+        .status()
+}
+
+#[tokio::test]
+async fn test_jwt_protects_the_data_routes() {
+    let backend = MockBackend::default().start().await;
+    let provider = Provider::start().await;
+    let config = ServerConfig::builder(backend.url())
+        .auth(AuthConfig {
+            jwt: JwtConfig {
+                issuer: Some(provider.issuer.clone()),
+                audiences: vec!["rossby-vis".to_string()],
+                ..Default::default()
+            },
+            ..Default::default()
+        })
+        .build()
+        .unwrap();
+    let app = build_router(AppState::from_config(config).await.unwrap());
+
+    let valid = provider.token(json!({
+        "sub": "alice",
+        "iss": provider.issuer,
+        "aud": "rossby-vis",
+        "exp": now() + 300,
+    }));
+    assert_eq!(
+        status(&app, "/proxy/metadata", Some(&valid)).await,
+        StatusCode::OK
+    );
+    assert_eq!(
+        status(&app, "/proxy/metadata", Some(&valid)).await,
+        StatusCode::OK
+    );
+    // The signing keys were fetched once and then reused
+    assert_eq!(provider.fetches.load(Ordering::SeqCst), 1);
+
+    let wrong_audience = provider.token(json!({
+        "iss": provider.issuer,
+        "aud": "another-app",
+        "exp": now() + 300,
+    }));
+    let wrong_issuer = provider.token(json!({
+        "iss": "https://sso.example.com",
+        "aud": "rossby-vis",
+        "exp": now() + 300,
+    }));
+    let expired = provider.token(json!({
+        "iss": provider.issuer,
+        "aud": "rossby-vis",
+        "exp": now() - 3600,
+    }));
+    for token in [&wrong_audience, &wrong_issuer, &expired] {
+        assert_eq!(
+            status(&app, "/proxy/metadata", Some(token)).await,
+            StatusCode::UNAUTHORIZED
+        );
+    }
+    assert_eq!(
+        status(&app, "/proxy/metadata", None).await,
+        StatusCode::UNAUTHORIZED
+    );
+    assert_eq!(
+        status(&app, "/proxy/metadata", Some("not.a.token")).await,
+        StatusCode::UNAUTHORIZED
+    );
+
+    // Static assets stay public
+    assert_eq!(status(&app, "/", None).await, StatusCode::OK);
+    assert_eq!(
+        status(&app, "/libs/earth/1.0.0/earth.js", None).await,
+        StatusCode::OK
+    );
+}
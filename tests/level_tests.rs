@@ -0,0 +1,173 @@
+//! Integration tests for Earth paths naming a level, such as
+//! `current-wind-isobaric-850hPa-gfs-1.0.json`
+//!
+//! The mock Rossby server stores its pressure levels in Pa and returns the
+//! level it was asked for in every value, so the tests can check which slice
+//! was selected.
+
+use axum::{
+    body::Body,
+    http::{Request, StatusCode},
+    routing::get,
+    Router,
+};
+use serde_json::{json, Value};
+use std::sync::Arc;
+use tower::ServiceExt;
+
+use rossby_vis::{
+    handlers::{earth_current_data, earth_dated_data},
+    server::AppState,
+};
+
+mod mock_server {
+    use axum::{extract::Query, response::Json, routing::get, Router};
+    use serde_json::{json, Value};
+    use std::collections::HashMap;
+    use tokio::net::TcpListener;
+
+    pub async fn start() -> String {
+        let app = Router::new()
+            .route("/metadata", get(metadata))
+            .route("/data", get(data));
+
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+
+        tokio::spawn(async move {
+            axum::Server::from_tcp(listener.into_std().unwrap())
+                .unwrap()
+                .serve(app.into_make_service())
+                .await
+                .unwrap();
+        });
+
+        format!("http://{}", addr)
+    }
+
+    async fn metadata() -> Json<Value> {
+        let on_levels = json!(["time", "plev", "latitude", "longitude"]);
+        let surface = json!(["time", "latitude", "longitude"]);
+        Json(json!({
+            "coordinates": {
+                "latitude": [10.0, 0.0],
+                "longitude": [0.0, 10.0],
+                "plev": [85000.0, 50000.0],
+                "time": [700464.0]
+            },
+            "dimensions": {
+                "latitude": {"size": 2},
+                "longitude": {"size": 2},
+                "plev": {"size": 2},
+                "time": {"size": 1}
+            },
+            "variables": {
+                "t": {"dimensions": on_levels, "attributes": {"long_name": "Temperature", "units": "K"}},
+                "t2m": {"dimensions": surface, "attributes": {"long_name": "2 metre temperature", "units": "K"}},
+                "u": {"dimensions": on_levels, "attributes": {"long_name": "U component of wind", "units": "m s**-1"}},
+                "v": {"dimensions": on_levels, "attributes": {"long_name": "V component of wind", "units": "m s**-1"}},
+                "u10": {"dimensions": surface, "attributes": {"long_name": "10 metre U wind component", "units": "m s**-1"}},
+                "v10": {"dimensions": surface, "attributes": {"long_name": "10 metre V wind component", "units": "m s**-1"}}
+            }
+        }))
+    }
+
+    /// Every value is the requested `plev`, or 0 without one
+    async fn data(Query(params): Query<HashMap<String, String>>) -> Json<Value> {
+        let level: f64 = params.get("plev").map_or(0.0, |p| p.parse().unwrap());
+        let vars = params.get("vars").cloned().unwrap_or_default();
+        let data: serde_json::Map<String, Value> = vars
+            .split(',')
+            .map(|var| (var.to_string(), json!([level, level, level, level])))
+            .collect();
+
+        Json(json!({
+            "metadata": {"shape": [1, 2, 2], "dimensions": ["time", "latitude", "longitude"]},
+            "data": data
+        }))
+    }
+}
+
+async fn create_test_router() -> Router {
+    let state = AppState::new(mock_server::start().await, reqwest::Client::new());
+
+    Router::new()
+        .route("/data/weather/current/:file", get(earth_current_data))
+        .route(
+            "/data/weather/:year/:month/:day/:file",
+            get(earth_dated_data),
+        )
+        .with_state(Arc::new(state))
+}
+
+async fn get_json(app: &Router, uri: &str) -> (StatusCode, Value) {
+    let response = app
+        .clone()
+        .oneshot(Request::builder().uri(uri).body(Body::empty()).unwrap())
+        .await
+        .unwrap();
+    let status = response.status();
+    let bytes = hyper::body::to_bytes(response.into_body()).await.unwrap();
+    (status, serde_json::from_slice(&bytes).unwrap())
+}
+
+#[tokio::test]
+async fn test_isobaric_wind_selects_level() {
+    let app = create_test_router().await;
+
+    let (status, body) = get_json(
+        &app,
+        "/data/weather/current/current-wind-isobaric-850hPa-gfs-1.0.json",
+    )
+    .await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(body[0]["header"]["parameterNumberName"], "U-component");
+    assert_eq!(body[0]["data"][0], json!(85000.0));
+    assert_eq!(body[1]["data"][0], json!(85000.0));
+
+    // Surface paths keep using the single-level fields
+    let (status, body) = get_json(
+        &app,
+        "/data/weather/current/current-wind-surface-level-gfs-1.0.json",
+    )
+    .await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(body[0]["data"][0], json!(0.0));
+}
+
+#[tokio::test]
+async fn test_dated_isobaric_temperature() {
+    let app = create_test_router().await;
+
+    let (status, body) = get_json(
+        &app,
+        "/data/weather/1979/11/29/0000-temp-isobaric-500hPa-gfs-1.0.json",
+    )
+    .await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(body[0]["header"]["parameterNumberName"], "Temperature");
+    assert_eq!(body[0]["data"][0], json!(50000.0));
+}
+
+#[tokio::test]
+async fn test_unavailable_levels() {
+    let app = create_test_router().await;
+
+    let (status, body) = get_json(
+        &app,
+        "/data/weather/current/current-wind-isobaric-700hPa-gfs-1.0.json",
+    )
+    .await;
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+    assert!(body["error"].as_str().unwrap().contains("850hPa, 500hPa"));
+
+    let (status, _) = get_json(
+        &app,
+        "/data/weather/current/current-t2m-isobaric-850hPa-gfs-1.0.json",
+    )
+    .await;
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+
+    let (status, _) = get_json(&app, "/data/weather/current/current-wind.json").await;
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+}
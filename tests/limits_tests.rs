@@ -0,0 +1,64 @@
+//! Integration tests for the size limits of proxied requests
+
+use axum::{
+    body::Body,
+    http::{Request, StatusCode},
+    Router,
+};
+use tower::ServiceExt;
+
+use rossby_vis::{
+    build_router, limits::RequestLimits, testing::MockBackend, AppState, ServerConfig,
+};
+
+async fn send(app: &Router, request: Request<Body>) -> (StatusCode, String) {
+    let response = app.clone().oneshot(request).await.unwrap();
+    let status = response.status();
+    let bytes = hyper::body::to_bytes(response.into_body()).await.unwrap();
+    (status, String::from_utf8_lossy(&bytes).into_owned())
+}
+
+fn get(uri: &str) -> Request<Body> {
+    Request::builder().uri(uri).body(Body::empty()).unwrap()
+}
+
+#[tokio::test]
+async fn test_oversized_proxy_requests_never_reach_the_backend() {
+    let backend = MockBackend::default().start().await;
+    let config = ServerConfig::builder(backend.url())
+        .request_limits(RequestLimits {
+            max_query_length: 256,
+            max_vars: 2,
+            max_header_bytes: 1024,
+        })
+        .build()
+        .unwrap();
+    let app = build_router(AppState::from_config(config).await.unwrap());
+    let (status, _) = send(&app, get("/proxy/metadata")).await;
+    assert_eq!(status, StatusCode::OK);
+    let requests = backend.requests();
+
+    let (status, body) = send(&app, get("/proxy/data?vars=a,b,c&time=0")).await;
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+    assert!(body.contains("3 variables requested"), "{}", body);
+
+    let long = format!("/proxy/data?vars=a&time={}", "9".repeat(300));
+    let (status, body) = send(&app, get(&long)).await;
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+    assert!(body.contains("limit of 256 bytes"), "{}", body);
+
+    let request = Request::builder()
+        .uri("/proxy/metadata")
+        .header("cookie", "x".repeat(2048))
+        .body(Body::empty())
+        .unwrap();
+    let (status, body) = send(&app, request).await;
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+    assert!(body.contains("limit of 1024 bytes"), "{}", body);
+
+    assert_eq!(backend.requests(), requests);
+
+    // Other routes are not limited
+    let (status, _) = send(&app, get(&format!("/?q={}", "9".repeat(300)))).await;
+    assert_eq!(status, StatusCode::OK);
+}
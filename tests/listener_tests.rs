@@ -0,0 +1,162 @@
+//! Integration tests for serving on several listeners at once
+
+use rossby_vis::{
+    listen::ListenAddress, run_server_with_config, testing::MockBackend, ServerConfig,
+};
+
+async fn status(url: String) -> u16 {
+    reqwest::Client::new()
+        .get(url)
+        .bearer_auth("s3cret")
+        .send()
+        .await
+        .unwrap()
+        .status()
+        .as_u16()
+}
+
+#[tokio::test]
+async fn test_dual_stack_and_admin_listeners() {
+    let backend = MockBackend::default().start().await;
+    let config = ServerConfig::builder(backend.url())
+        .listen("127.0.0.1:0".parse().unwrap())
+        .listen("[::1]:0".parse().unwrap())
+        .listen("127.0.0.1:0,admin".parse().unwrap())
+        .admin_token("s3cret")
+        .build()
+        .unwrap();
+    let server = run_server_with_config(config).await.unwrap();
+    let [ipv4, ipv6, admin] = *server.addrs() else {
+        panic!("expected three listeners, got {:?}", server.addrs());
+    };
+    assert!(ipv6.is_ipv6());
+
+    for addr in [ipv4, ipv6] {
+        assert_eq!(status(format!("http://{}/api/status", addr)).await, 200);
+        assert_eq!(status(format!("http://{}/admin/overview", addr)).await, 404);
+    }
+
+    assert_eq!(
+        status(format!("http://{}/admin/overview", admin)).await,
+        200
+    );
+    assert_eq!(status(format!("http://{}/admin", admin)).await, 200);
+    assert_eq!(status(format!("http://{}/api/status", admin)).await, 404);
+    assert_eq!(status(format!("http://{}/health", admin)).await, 200);
+
+    // Shutting down stops every listener
+    server.stop().await.unwrap();
+    for addr in [ipv4, ipv6, admin] {
+        assert!(reqwest::get(format!("http://{}/health", addr))
+            .await
+            .is_err());
+    }
+}
+
+#[tokio::test]
+async fn test_tls_listener_requires_certificate() {
+    let backend = MockBackend::default().start().await;
+    let config = ServerConfig::builder(backend.url())
+        .listen(ListenAddress::new("127.0.0.1:0".parse().unwrap()))
+        .listen("127.0.0.1:0,tls".parse().unwrap())
+        .build()
+        .unwrap();
+
+    let error = run_server_with_config(config).await.unwrap_err();
+
+    assert!(error
+        .to_string()
+        .contains("TLS listener needs a certificate"));
+}
+
+#[cfg(unix)]
+#[tokio::test]
+async fn test_unix_socket_listener() {
+    use std::os::unix::fs::{FileTypeExt, PermissionsExt};
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+    let path = std::env::temp_dir().join(format!("rossby-vis-{}.sock", uuid::Uuid::new_v4()));
+    // A socket left over from an earlier run is replaced
+    drop(std::os::unix::net::UnixListener::bind(&path).unwrap());
+
+    let backend = MockBackend::default().start().await;
+    let config = ServerConfig::builder(backend.url())
+        .unix_socket(&path)
+        .build()
+        .unwrap();
+    let server = run_server_with_config(config).await.unwrap();
+    assert!(server.addrs().is_empty());
+    assert_eq!(server.unix_socket(), Some(path.as_path()));
+
+    let metadata = std::fs::metadata(&path).unwrap();
+    assert!(metadata.file_type().is_socket());
+    assert_eq!(metadata.permissions().mode() & 0o777, 0o660);
+
+    let mut stream = tokio::net::UnixStream::connect(&path).await.unwrap();
+    stream
+        .write_all(b"GET /health HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n")
+        .await
+        .unwrap();
+    let mut response = String::new();
+    stream.read_to_string(&mut response).await.unwrap();
+    assert!(response.starts_with("HTTP/1.1 200"), "{}", response);
+
+    // The socket file is removed on shutdown
+    server.stop().await.unwrap();
+    assert!(!path.exists());
+}
+
+#[cfg(unix)]
+#[tokio::test]
+async fn test_unix_socket_does_not_replace_other_files() {
+    let path = std::env::temp_dir().join(format!("rossby-vis-{}.sock", uuid::Uuid::new_v4()));
+    std::fs::write(&path, "not a socket").unwrap();
+
+    let backend = MockBackend::default().start().await;
+    let config = ServerConfig::builder(backend.url())
+        .unix_socket(&path)
+        .build()
+        .unwrap();
+    let error = run_server_with_config(config).await.unwrap_err();
+
+    assert!(error.to_string().contains("is not a socket"));
+    assert_eq!(std::fs::read_to_string(&path).unwrap(), "not a socket");
+    std::fs::remove_file(&path).unwrap();
+}
+
+#[cfg(unix)]
+#[tokio::test]
+async fn test_binary_removes_unix_socket_on_sigterm() {
+    use std::{process::Command, time::Duration};
+
+    let path = std::env::temp_dir().join(format!("rossby-vis-{}.sock", uuid::Uuid::new_v4()));
+    let backend = MockBackend::default().start().await;
+    let mut child = Command::new(env!("CARGO_BIN_EXE_rossby-vis"))
+        .arg("--api-url")
+        .arg(backend.url())
+        .arg("--unix-socket")
+        .arg(&path)
+        .spawn()
+        .unwrap();
+
+    for _ in 0..100 {
+        if path.exists() {
+            break;
+        }
+        tokio::time::sleep(Duration::from_millis(100)).await;
+    }
+    assert!(path.exists(), "the socket was not created within 10s");
+
+    let status = Command::new("kill")
+        .args(["-TERM", &child.id().to_string()])
+        .status()
+        .unwrap();
+    assert!(status.success());
+    let status = tokio::task::spawn_blocking(move || child.wait())
+        .await
+        .unwrap()
+        .unwrap();
+
+    assert!(status.success(), "{}", status);
+    assert!(!path.exists());
+}
@@ -14,17 +14,19 @@ use std::sync::Arc;
 use tower::ServiceExt;
 
 use rossby_vis::{
-    logging::{generate_request_id, init_logging, LogFormat, LoggingConfig},
+    logging::{
+        generate_request_id, init_logging, transfer_totals, LogFormat, LoggingConfig, OtlpProtocol,
+    },
     middleware::{request_tracing_middleware, security_headers_middleware},
     server::AppState,
 };
 
 /// Test helper to create a test AppState
 fn create_test_state() -> Arc<AppState> {
-    Arc::new(AppState {
-        api_url: "http://localhost:8000".to_string(),
-        http_client: reqwest::Client::new(),
-    })
+    Arc::new(AppState::new(
+        "http://localhost:8000".to_string(),
+        reqwest::Client::new(),
+    ))
 }
 
 /// Test helper to create a basic test router with middleware
@@ -73,13 +75,22 @@ async fn test_logging_config_from_env() {
 async fn test_logging_initialization() {
     let config = LoggingConfig {
         level: "info".to_string(),
+        targets: Vec::new(),
         format: LogFormat::Text,
         enable_request_tracing: true,
         enable_metrics: false, // Disable metrics to avoid spawning background task
         enable_distributed_tracing: false,
-        jaeger_endpoint: None,
+        otlp_endpoint: None,
+        otlp_protocol: OtlpProtocol::Grpc,
+        trace_sample_ratio: 1.0,
         service_name: "test-service".to_string(),
         environment: "test".to_string(),
+        sentry_dsn: None,
+        syslog: None,
+        file: None,
+        statsd: None,
+        access_log: None,
+        heartbeat_interval: None,
     };
 
     // This should not panic and should initialize successfully
@@ -326,13 +337,14 @@ async fn test_middleware_ordering() {
 
 #[test]
 fn test_logging_config_validation() {
-    let mut config = LoggingConfig::default();
-
     // Test valid configurations
-    config.level = "trace".to_string();
-    config.format = LogFormat::Json;
-    config.enable_request_tracing = true;
-    config.enable_metrics = true;
+    let config = LoggingConfig {
+        level: "trace".to_string(),
+        format: LogFormat::Json,
+        enable_request_tracing: true,
+        enable_metrics: true,
+        ..Default::default()
+    };
 
     // These should not panic
     assert_eq!(config.level, "trace");
@@ -393,3 +405,82 @@ async fn test_large_request_handling() {
     assert_eq!(response.status(), StatusCode::OK);
     assert!(response.headers().contains_key("x-request-id"));
 }
+
+#[tokio::test]
+async fn test_response_bytes_are_counted() {
+    let app = create_test_router();
+    let before = transfer_totals();
+
+    let request = Request::builder()
+        .method(Method::GET)
+        .uri("/test")
+        .header("content-length", "0")
+        .body(Body::empty())
+        .unwrap();
+    let response = app.oneshot(request).await.unwrap();
+
+    // The size is recorded once the body has been consumed
+    assert_eq!(response.headers()["content-length"], "13");
+    let body = hyper::body::to_bytes(response.into_body()).await.unwrap();
+    assert_eq!(body, "test response");
+
+    let after = transfer_totals();
+    assert!(after.response_bytes >= before.response_bytes + body.len() as u64);
+}
+
+#[cfg(unix)]
+#[tokio::test]
+async fn test_binary_flushes_log_file_on_sigterm() {
+    use std::{process::Command, time::Duration};
+
+    let dir = std::env::temp_dir().join(format!("rossby-vis-logs-{}", uuid::Uuid::new_v4()));
+    std::fs::create_dir(&dir).unwrap();
+    let backend = rossby_vis::testing::MockBackend::default().start().await;
+    let mut child = Command::new(env!("CARGO_BIN_EXE_rossby-vis"))
+        .args([
+            "--port",
+            "0",
+            "--log-rotation",
+            "never",
+            "--api-url",
+            backend.url(),
+        ])
+        .arg("--log-file")
+        .arg(dir.join("rossby-vis.log"))
+        .spawn()
+        .unwrap();
+    tokio::time::sleep(Duration::from_secs(2)).await;
+
+    let status = Command::new("kill")
+        .args(["-TERM", &child.id().to_string()])
+        .status()
+        .unwrap();
+    assert!(status.success());
+    let status = tokio::task::spawn_blocking(move || child.wait())
+        .await
+        .unwrap()
+        .unwrap();
+    assert!(status.success(), "{}", status);
+
+    // The last line logged before exiting is in the file
+    let log = std::fs::read_to_string(dir.join("rossby-vis.log")).unwrap();
+    assert!(log.contains("Received SIGTERM, shutting down"), "{}", log);
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn test_binary_rejects_oversized_log_max_size() {
+    let dir = std::env::temp_dir().join(format!("rossby-vis-logs-{}", uuid::Uuid::new_v4()));
+    let output = std::process::Command::new(env!("CARGO_BIN_EXE_rossby-vis"))
+        .args(["--api-url", "http://127.0.0.1:1", "--log-max-size"])
+        .arg(u64::MAX.to_string())
+        .arg("--log-file")
+        .arg(dir.join("rossby-vis.log"))
+        .output()
+        .unwrap();
+
+    assert!(!output.status.success());
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains("--log-max-size is too large"), "{}", stderr);
+    let _ = std::fs::remove_dir_all(&dir);
+}
@@ -6,29 +6,22 @@
 //! 3. Replaces hardcoded elements with dynamic ones
 //! 4. Provides graceful fallback when metadata is unavailable
 
-use reqwest;
 use serde_json::{json, Value};
-use std::time::Duration;
-use tokio;
 
 /// Test the metadata service initialization
 #[tokio::test]
 async fn test_metadata_service_initialization() {
     // Start the server
-    let server_handle = tokio::spawn(async {
-        rossby_vis::run_server(8081, "http://mock-rossby:8000".to_string())
-            .await
-            .unwrap()
-    });
-
-    // Give server time to start
-    tokio::time::sleep(Duration::from_millis(500)).await;
+    let server = rossby_vis::run_server(0, "http://mock-rossby:8000".to_string())
+        .await
+        .unwrap();
+    let server_url = format!("http://{}", server.addr());
 
     let client = reqwest::Client::new();
 
     // Test metadata endpoint
     let response = client
-        .get("http://127.0.0.1:8081/proxy/metadata")
+        .get(format!("{}/proxy/metadata", server_url))
         .send()
         .await;
 
@@ -70,7 +63,7 @@ async fn test_metadata_service_initialization() {
 
     // Test main page loads with metadata-ui.js
     let response = client
-        .get("http://127.0.0.1:8081/")
+        .get(format!("{}/", server_url))
         .send()
         .await
         .expect("Should be able to fetch main page");
@@ -89,7 +82,7 @@ async fn test_metadata_service_initialization() {
 
     // Test metadata-ui.js file is accessible
     let response = client
-        .get("http://127.0.0.1:8081/libs/earth/1.0.0/metadata-ui.js")
+        .get(format!("{}/libs/earth/1.0.0/metadata-ui.js", server_url))
         .send()
         .await
         .expect("Should be able to fetch metadata-ui.js");
@@ -122,7 +115,7 @@ async fn test_metadata_service_initialization() {
     println!("✓ metadata-ui.js contains all required components");
 
     // Cleanup
-    server_handle.abort();
+    server.stop().await.unwrap();
 }
 
 /// Test variable analysis and mapping
@@ -349,19 +342,16 @@ fn create_display_name(var_name: &str, long_name: Option<&str>) -> String {
 #[tokio::test]
 async fn test_graceful_fallback() {
     // Start server without backend (will cause metadata fetch to fail)
-    let server_handle = tokio::spawn(async {
-        rossby_vis::run_server(8082, "http://nonexistent:8000".to_string())
-            .await
-            .unwrap()
-    });
-
-    tokio::time::sleep(Duration::from_millis(500)).await;
+    let server = rossby_vis::run_server(0, "http://nonexistent:8000".to_string())
+        .await
+        .unwrap();
+    let server_url = format!("http://{}", server.addr());
 
     let client = reqwest::Client::new();
 
     // Verify main page still loads even if metadata fails
     let response = client
-        .get("http://127.0.0.1:8082/")
+        .get(format!("{}/", server_url))
         .send()
         .await
         .expect("Should be able to fetch main page even without backend");
@@ -380,7 +370,7 @@ async fn test_graceful_fallback() {
     println!("✓ Graceful fallback works - page loads with default UI when metadata unavailable");
 
     // Cleanup
-    server_handle.abort();
+    server.stop().await.unwrap();
 }
 
 /// Integration test that verifies the complete Phase 3a workflow
@@ -0,0 +1,147 @@
+//! Integration tests for the OpenAPI specification and its documentation page
+
+use axum::{
+    body::Body,
+    http::{Method, Request, StatusCode},
+    Router,
+};
+use serde_json::Value;
+use tower::ServiceExt;
+
+use rossby_vis::{build_router, testing::MockBackend, AppState, ServerConfig};
+
+async fn app(url: &str, base_path: &str) -> Router {
+    let config = ServerConfig::builder(url)
+        .base_path(base_path)
+        .build()
+        .unwrap();
+    build_router(AppState::from_config(config).await.unwrap())
+}
+
+async fn send(app: &Router, method: Method, uri: &str) -> (StatusCode, String) {
+    let request = Request::builder()
+        .method(method)
+        .uri(uri)
+        .body(Body::empty())
+        .unwrap();
+    let response = app.clone().oneshot(request).await.unwrap();
+    let status = response.status();
+    let bytes = hyper::body::to_bytes(response.into_body()).await.unwrap();
+    (status, String::from_utf8_lossy(&bytes).into_owned())
+}
+
+async fn specification(app: &Router, uri: &str) -> Value {
+    let (status, body) = send(app, Method::GET, uri).await;
+    assert_eq!(status, StatusCode::OK);
+    serde_json::from_str(&body).unwrap()
+}
+
+/// Every `$ref` within `value`
+fn references<'a>(value: &'a Value, found: &mut Vec<&'a str>) {
+    match value {
+        Value::Object(object) => {
+            if let Some(Value::String(reference)) = object.get("$ref") {
+                found.push(reference);
+            }
+            object.values().for_each(|value| references(value, found));
+        }
+        Value::Array(values) => values.iter().for_each(|value| references(value, found)),
+        _ => {}
+    }
+}
+
+#[tokio::test]
+async fn test_specification_describes_the_api() {
+    let backend = MockBackend::default().start().await;
+    let app = app(backend.url(), "/rossby-vis").await;
+    let spec = specification(&app, "/rossby-vis/api/openapi.json").await;
+
+    assert_eq!(spec["servers"][0]["url"], "/rossby-vis");
+    assert_eq!(spec["info"]["version"], env!("CARGO_PKG_VERSION"));
+
+    let paths = &spec["paths"];
+    let parameters: Vec<&str> = paths["/proxy/data"]["get"]["parameters"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .filter_map(|parameter| parameter["name"].as_str())
+        .collect();
+    assert_eq!(parameters, ["vars", "time", "time_range", "units"]);
+    assert!(paths["/data/weather/current/{file}"]["get"]["parameters"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .any(|parameter| parameter["name"] == "mask"));
+    assert_eq!(
+        paths["/api/sample"]["post"]["requestBody"]["content"]["text/csv"]["schema"]["type"],
+        "string"
+    );
+    assert_eq!(
+        paths["/admin/cache"]["delete"]["security"][0]["admin_token"],
+        Value::Array(vec![])
+    );
+    assert_eq!(
+        spec["components"]["securitySchemes"]["admin_token"]["scheme"],
+        "bearer"
+    );
+
+    let mut found = Vec::new();
+    references(&spec, &mut found);
+    assert!(!found.is_empty());
+    for reference in found {
+        let name = reference
+            .strip_prefix("#/components/schemas/")
+            .unwrap_or_else(|| panic!("unexpected reference {}", reference));
+        assert!(
+            spec["components"]["schemas"].get(name).is_some(),
+            "{} is not defined",
+            reference
+        );
+    }
+}
+
+#[tokio::test]
+async fn test_documented_operations_are_routed() {
+    let backend = MockBackend::default().start().await;
+    let app = app(backend.url(), "").await;
+    let spec = specification(&app, "/api/openapi.json").await;
+
+    let paths = spec["paths"].as_object().unwrap();
+    assert!(paths.len() >= 30);
+    for (path, operations) in paths {
+        // Placeholder values are enough to reach the handler, but for the
+        // OSCAR files, which fall back to the bundled ones
+        let uri = path
+            .replace("{file}", "20140131-surface-currents-oscar-0.33.json")
+            .replace(['{', '}'], "");
+        for method in operations.as_object().unwrap().keys() {
+            let method = Method::from_bytes(method.to_uppercase().as_bytes()).unwrap();
+            let (status, body) = send(&app, method.clone(), &uri).await;
+            assert_ne!(
+                status,
+                StatusCode::METHOD_NOT_ALLOWED,
+                "{} {}",
+                method,
+                path
+            );
+            assert_ne!(body, "Asset not found", "{} {}", method, path);
+        }
+    }
+}
+
+#[tokio::test]
+async fn test_docs_page_loads_the_embedded_swagger_ui() {
+    let backend = MockBackend::default().start().await;
+    let app = app(backend.url(), "/rossby-vis").await;
+
+    let (status, html) = send(&app, Method::GET, "/rossby-vis/api/docs").await;
+    assert_eq!(status, StatusCode::OK);
+    assert!(html.contains("url: \"openapi.json\""));
+
+    for asset in ["swagger-ui-bundle.js", "swagger-ui.css"] {
+        let reference = format!("../libs/swagger-ui/5.17.14/{}", asset);
+        assert!(html.contains(&reference), "{}", asset);
+        let uri = format!("/rossby-vis/libs/swagger-ui/5.17.14/{}", asset);
+        assert_eq!(send(&app, Method::GET, &uri).await.0, StatusCode::OK);
+    }
+}
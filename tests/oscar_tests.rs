@@ -0,0 +1,184 @@
+//! Integration tests for the OSCAR ocean currents routes
+//!
+//! Two mock Rossby servers are started: an atmospheric one without ocean
+//! currents and an ocean one whose currents come in multi-day means.
+
+use axum::{
+    body::Body,
+    http::{Request, StatusCode},
+    routing::get,
+    Router,
+};
+use serde_json::{json, Value};
+use std::sync::Arc;
+use tower::ServiceExt;
+
+use rossby_vis::{
+    endpoint::BackendEndpoint,
+    oscar::{oscar_catalog, oscar_data, OceanCurrentsConfig},
+    server::AppState,
+};
+
+mod mock_server {
+    use axum::{extract::Query, response::Json, routing::get, Router};
+    use serde_json::{json, Value};
+    use std::collections::HashMap;
+    use tokio::net::TcpListener;
+
+    async fn serve(app: Router) -> String {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+
+        tokio::spawn(async move {
+            axum::Server::from_tcp(listener.into_std().unwrap())
+                .unwrap()
+                .serve(app.into_make_service())
+                .await
+                .unwrap();
+        });
+
+        format!("http://{}", addr)
+    }
+
+    /// Atmospheric backend with surface temperature only
+    pub async fn start_atmosphere() -> String {
+        serve(Router::new().route(
+            "/metadata",
+            get(|| async {
+                Json(json!({
+                    "coordinates": {
+                        "latitude": [10.0, 0.0],
+                        "longitude": [0.0, 10.0],
+                        "time": [700464.0]
+                    },
+                    "dimensions": {"latitude": {"size": 2}, "longitude": {"size": 2}, "time": {"size": 1}},
+                    "variables": {
+                        "t2m": {
+                            "dimensions": ["time", "latitude", "longitude"],
+                            "attributes": {"long_name": "2 metre temperature", "units": "K"}
+                        }
+                    }
+                }))
+            }),
+        ))
+        .await
+    }
+
+    /// Ocean backend with currents on 1979-11-29, 1979-12-04 (twice) and
+    /// 1979-12-09
+    pub async fn start_ocean() -> String {
+        serve(
+            Router::new()
+                .route("/metadata", get(metadata))
+                .route("/data", get(data)),
+        )
+        .await
+    }
+
+    async fn metadata() -> Json<Value> {
+        Json(json!({
+            "coordinates": {
+                "latitude": [10.0, 0.0],
+                "longitude": [0.0, 10.0],
+                "time": [700464.0, 700584.0, 700596.0, 700704.0]
+            },
+            "dimensions": {"latitude": {"size": 2}, "longitude": {"size": 2}, "time": {"size": 4}},
+            "variables": {
+                "uo": {
+                    "dimensions": ["time", "latitude", "longitude"],
+                    "attributes": {"long_name": "Eastward sea water velocity", "units": "m s-1"}
+                },
+                "vo": {
+                    "dimensions": ["time", "latitude", "longitude"],
+                    "attributes": {"long_name": "Northward sea water velocity", "units": "m s-1"}
+                }
+            }
+        }))
+    }
+
+    async fn data(Query(params): Query<HashMap<String, String>>) -> Json<Value> {
+        let time: f64 = params["time"].parse().unwrap();
+        let base = (time - 700464.0) / 1000.0;
+        Json(json!({
+            "metadata": {"shape": [1, 2, 2], "dimensions": ["time", "latitude", "longitude"]},
+            "data": {
+                "uo": [base, base + 0.1, base + 0.2, base + 0.3],
+                "vo": [-base, -base - 0.1, -base - 0.2, -base - 0.3]
+            }
+        }))
+    }
+}
+
+async fn create_test_router(ocean_currents: OceanCurrentsConfig) -> Router {
+    let mut state = AppState::new(
+        mock_server::start_atmosphere().await,
+        reqwest::Client::new(),
+    );
+    state.ocean_currents = ocean_currents;
+
+    Router::new()
+        .route("/data/oscar/catalog.json", get(oscar_catalog))
+        .route("/data/oscar/:file", get(oscar_data))
+        .with_state(Arc::new(state))
+}
+
+async fn get_json(app: &Router, uri: &str) -> (StatusCode, Value) {
+    let response = app
+        .clone()
+        .oneshot(Request::builder().uri(uri).body(Body::empty()).unwrap())
+        .await
+        .unwrap();
+    let status = response.status();
+    let bytes = hyper::body::to_bytes(response.into_body()).await.unwrap();
+    (status, serde_json::from_slice(&bytes).unwrap())
+}
+
+#[tokio::test]
+async fn test_currents_from_ocean_backend() {
+    let endpoint: BackendEndpoint = mock_server::start_ocean().await.parse().unwrap();
+    let app = create_test_router(OceanCurrentsConfig {
+        endpoint: Some(endpoint),
+        components: None,
+    })
+    .await;
+
+    let (status, body) = get_json(&app, "/data/oscar/catalog.json").await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(
+        body,
+        json!([
+            "19791129-surface-currents-oscar-0.33.json",
+            "19791204-surface-currents-oscar-0.33.json",
+            "19791209-surface-currents-oscar-0.33.json"
+        ])
+    );
+
+    // The first timestep of the day is served
+    let (status, body) = get_json(
+        &app,
+        "/data/oscar/19791204-surface-currents-oscar-0.33.json",
+    )
+    .await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(body.as_array().unwrap().len(), 2);
+    assert_eq!(body[0]["header"]["refTime"], "1979-12-04T00:00:00+00:00");
+    assert_eq!(body[0]["header"]["nx"], 2);
+    assert_eq!(body[0]["data"], json!([0.12, 0.22, 0.32, 0.42]));
+    assert_eq!(body[1]["data"][0], json!(-0.12));
+
+    let (status, _) = get_json(
+        &app,
+        "/data/oscar/19791205-surface-currents-oscar-0.33.json",
+    )
+    .await;
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+}
+
+#[tokio::test]
+async fn test_bundled_sample_without_currents() {
+    let app = create_test_router(OceanCurrentsConfig::default()).await;
+
+    let (status, body) = get_json(&app, "/data/oscar/catalog.json").await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(body, json!(["20140131-surface-currents-oscar-0.33.json"]));
+}
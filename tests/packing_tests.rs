@@ -0,0 +1,113 @@
+//! Integration tests for packed Earth data arrays
+
+use axum::{
+    body::Body,
+    http::{Request, StatusCode},
+    Router,
+};
+use serde_json::Value;
+use tower::ServiceExt;
+
+use rossby_vis::{
+    build_router,
+    packing::{PackedArray, PackingConfig},
+    testing::MockBackend,
+    AppState, ServerConfig,
+};
+
+const WIND: &str = "/data/weather/current/current-wind-surface-level-gfs-1.0.json";
+
+async fn router(backend: &str, packing: PackingConfig) -> Router {
+    let config = ServerConfig::builder(backend)
+        .packing(packing)
+        .build()
+        .unwrap();
+    build_router(AppState::from_config(config).await.unwrap())
+}
+
+async fn get_json(app: &Router, uri: &str) -> (StatusCode, Value) {
+    let response = app
+        .clone()
+        .oneshot(Request::builder().uri(uri).body(Body::empty()).unwrap())
+        .await
+        .unwrap();
+    let status = response.status();
+    let body = hyper::body::to_bytes(response.into_body()).await.unwrap();
+    (status, serde_json::from_slice(&body).unwrap_or(Value::Null))
+}
+
+fn unpack(record: &Value) -> Vec<f64> {
+    assert!(record.get("data").is_none());
+    let packed: PackedArray = serde_json::from_value(record["packed"].clone()).unwrap();
+    packed.unpack().unwrap()
+}
+
+fn values(record: &Value) -> Vec<f64> {
+    record["data"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|v| v.as_f64().unwrap())
+        .collect()
+}
+
+#[tokio::test]
+async fn test_packed_on_request() {
+    let backend = MockBackend::default().start().await;
+    let app = router(backend.url(), PackingConfig::default()).await;
+
+    let (status, plain) = get_json(&app, WIND).await;
+    assert_eq!(status, StatusCode::OK);
+    assert!(plain[0].get("packed").is_none());
+
+    let (status, packed) = get_json(&app, &format!("{}?packed=true", WIND)).await;
+    assert_eq!(status, StatusCode::OK);
+    for (plain, packed) in plain
+        .as_array()
+        .unwrap()
+        .iter()
+        .zip(packed.as_array().unwrap())
+    {
+        assert_eq!(packed["header"], plain["header"]);
+        assert_eq!(packed["packed"]["encoding"], "delta");
+        assert_eq!(packed["packed"]["scale"], 0.01);
+        let unpacked = unpack(packed);
+        for (expected, actual) in values(plain).iter().zip(&unpacked) {
+            assert!((expected - actual).abs() < 0.005 + 1e-9);
+        }
+    }
+
+    // A coarse precision rounds the values
+    let (_, coarse) = get_json(&app, &format!("{}?precision=5", WIND)).await;
+    assert_eq!(coarse[0]["packed"]["type"], "int8");
+    assert_eq!(unpack(&coarse[0])[..3], [0.0, 0.0, 5.0]);
+
+    let (status, _) = get_json(&app, &format!("{}?precision=-1", WIND)).await;
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+}
+
+#[tokio::test]
+async fn test_packed_by_default() {
+    let backend = MockBackend::default().start().await;
+    let packing = PackingConfig {
+        default: true,
+        precision: 0.5,
+    };
+    let app = router(backend.url(), packing).await;
+
+    let (_, packed) = get_json(&app, WIND).await;
+    assert_eq!(packed[0]["packed"]["scale"], 0.5);
+    let (_, plain) = get_json(&app, &format!("{}?packed=false", WIND)).await;
+    assert!(plain[0]["data"].is_array());
+
+    // Frame bundles pack every record of every frame
+    let (status, bundle) = get_json(&app, "/data/frames/wind?count=2").await;
+    assert_eq!(status, StatusCode::OK);
+    let frames = bundle["frames"].as_array().unwrap();
+    assert_eq!(frames.len(), 2);
+    for frame in frames {
+        for record in frame["records"].as_array().unwrap() {
+            assert_eq!(unpack(record).len(), 9);
+        }
+    }
+}
@@ -0,0 +1,122 @@
+//! Integration tests for the configurable middleware pipeline
+
+use axum::{
+    body::Body,
+    http::{header, Method, Request, StatusCode},
+    routing::get,
+    Router,
+};
+use std::sync::Arc;
+use tower::ServiceExt;
+
+use rossby_vis::{
+    handlers::status,
+    pipeline::{apply_pipeline, parse_pipeline, CompressionConfig, CorsConfig, DEFAULT_PIPELINE},
+    server::AppState,
+};
+
+fn create_test_router(pipeline: &str, cors_origins: &[&str]) -> Router {
+    let state = Arc::new(AppState::new(
+        "http://localhost:8000".to_string(),
+        reqwest::Client::new(),
+    ));
+    let cors = CorsConfig {
+        origins: cors_origins.iter().map(|o| o.to_string()).collect(),
+    };
+    let pipeline = match pipeline {
+        "default" => DEFAULT_PIPELINE.to_vec(),
+        pipeline => parse_pipeline(pipeline).unwrap(),
+    };
+    let router = Router::new().route("/api/status", get(status));
+    apply_pipeline(
+        router,
+        &state,
+        &pipeline,
+        &cors,
+        &CompressionConfig::default(),
+    )
+    .unwrap()
+    .with_state(state)
+}
+
+async fn get_status(app: &Router, uri: &str, origin: Option<&str>) -> axum::response::Response {
+    let mut request = Request::builder().uri(uri);
+    if let Some(origin) = origin {
+        request = request.header(header::ORIGIN, origin);
+    }
+    app.clone()
+        .oneshot(request.body(Body::empty()).unwrap())
+        .await
+        .unwrap()
+}
+
+#[tokio::test]
+async fn test_default_pipeline() {
+    let app = create_test_router("default", &[]);
+
+    let response = get_status(&app, "/api/status", Some("https://elsewhere.example")).await;
+    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(response.headers()["x-frame-options"], "DENY");
+    assert!(response.headers().contains_key("x-request-id"));
+    // No origins are allowed by default
+    assert!(!response
+        .headers()
+        .contains_key(header::ACCESS_CONTROL_ALLOW_ORIGIN));
+
+    let response = get_status(&app, "/health", None).await;
+    assert_eq!(response.status(), StatusCode::OK);
+}
+
+#[tokio::test]
+async fn test_disabled_layers_are_skipped() {
+    let app = create_test_router("request-tracing", &[]);
+
+    let response = get_status(&app, "/api/status", None).await;
+    assert_eq!(response.status(), StatusCode::OK);
+    assert!(response.headers().contains_key("x-request-id"));
+    assert!(!response.headers().contains_key("x-frame-options"));
+
+    // Without the health check layer there is no /health route
+    let response = get_status(&app, "/health", None).await;
+    assert_eq!(response.status(), StatusCode::NOT_FOUND);
+}
+
+#[tokio::test]
+async fn test_cors_for_allowed_origins() {
+    let app = create_test_router("default", &["https://maps.example.com"]);
+
+    let response = get_status(&app, "/api/status", Some("https://maps.example.com")).await;
+    assert_eq!(
+        response.headers()[header::ACCESS_CONTROL_ALLOW_ORIGIN],
+        "https://maps.example.com"
+    );
+    assert!(response.headers()[header::ACCESS_CONTROL_EXPOSE_HEADERS]
+        .to_str()
+        .unwrap()
+        .contains("x-data-version"));
+
+    let response = get_status(&app, "/api/status", Some("https://other.example.com")).await;
+    assert!(!response
+        .headers()
+        .contains_key(header::ACCESS_CONTROL_ALLOW_ORIGIN));
+
+    // Preflight requests are answered by the CORS layer
+    let response = app
+        .clone()
+        .oneshot(
+            Request::builder()
+                .method(Method::OPTIONS)
+                .uri("/api/status")
+                .header(header::ORIGIN, "https://maps.example.com")
+                .header(header::ACCESS_CONTROL_REQUEST_METHOD, "POST")
+                .body(Body::empty())
+                .unwrap(),
+        )
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    assert!(response.headers()[header::ACCESS_CONTROL_ALLOW_METHODS]
+        .to_str()
+        .unwrap()
+        .contains("POST"));
+}
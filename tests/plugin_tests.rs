@@ -0,0 +1,208 @@
+//! Integration tests for custom derived variables
+//!
+//! The mock Rossby server has wave height and period fields. One custom
+//! variable is implemented in Rust and one is loaded from a plugin file.
+
+use axum::{
+    body::Body,
+    http::{Request, StatusCode},
+    routing::get,
+    Router,
+};
+use serde_json::{json, Value};
+use std::sync::Arc;
+use tower::ServiceExt;
+
+use rossby_vis::{
+    handlers::{earth_current_data, proxy_data, proxy_metadata},
+    plugins::{DerivedRegistry, DerivedVariable, InputField},
+    server::AppState,
+    AppError,
+};
+
+mod mock_server {
+    use axum::{extract::Query, response::Json, routing::get, Router};
+    use serde_json::{json, Value};
+    use std::collections::HashMap;
+    use tokio::net::TcpListener;
+
+    pub async fn start() -> String {
+        let app = Router::new()
+            .route("/metadata", get(metadata))
+            .route("/data", get(data));
+
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+
+        tokio::spawn(async move {
+            axum::Server::from_tcp(listener.into_std().unwrap())
+                .unwrap()
+                .serve(app.into_make_service())
+                .await
+                .unwrap();
+        });
+
+        format!("http://{}", addr)
+    }
+
+    async fn metadata() -> Json<Value> {
+        let dims = json!(["time", "latitude", "longitude"]);
+        Json(json!({
+            "coordinates": {
+                "latitude": [10.0, 0.0],
+                "longitude": [0.0, 10.0],
+                "time": [700464.0]
+            },
+            "dimensions": {
+                "latitude": {"size": 2},
+                "longitude": {"size": 2},
+                "time": {"size": 1}
+            },
+            "variables": {
+                "VHM0": {"dimensions": dims, "attributes": {"long_name": "Significant wave height", "units": "m"}},
+                "VTM02": {"dimensions": dims, "attributes": {"long_name": "Mean wave period", "units": "s"}}
+            }
+        }))
+    }
+
+    async fn data(Query(params): Query<HashMap<String, String>>) -> Json<Value> {
+        let mut data = serde_json::Map::new();
+        for var in params["vars"].split(',') {
+            let values = match var {
+                "VHM0" => json!([1.0, 2.0, 4.0, null]),
+                "VTM02" => json!([5.0, 10.0, 10.0, 8.0]),
+                _ => continue,
+            };
+            data.insert(var.to_string(), values);
+        }
+
+        Json(json!({
+            "metadata": {"shape": [1, 2, 2], "dimensions": ["time", "latitude", "longitude"]},
+            "data": data
+        }))
+    }
+}
+
+/// Deep-water wave energy flux, P = ρg²H²T / 64π
+struct WavePower;
+
+impl DerivedVariable for WavePower {
+    fn name(&self) -> &str {
+        "wave_power"
+    }
+
+    fn long_name(&self) -> &str {
+        "Wave energy flux"
+    }
+
+    fn inputs(&self, _metadata: &Value) -> Option<Vec<String>> {
+        Some(vec!["VHM0".to_string(), "VTM02".to_string()])
+    }
+
+    fn units(&self, _metadata: &Value, _inputs: &[String]) -> String {
+        "kW m**-1".to_string()
+    }
+
+    fn compute(&self, inputs: &[InputField<'_>]) -> Result<Vec<f64>, AppError> {
+        assert_eq!(inputs[0].units, "m");
+        Ok(inputs[0]
+            .values
+            .iter()
+            .zip(inputs[1].values)
+            .map(|(h, t)| 0.49 * h * h * t)
+            .collect())
+    }
+}
+
+fn registry() -> DerivedRegistry {
+    let path = std::env::temp_dir().join(format!("rossby-vis-plugins-{}.json", std::process::id()));
+    std::fs::write(
+        &path,
+        json!([{
+            "name": "wave_steepness",
+            "long_name": "Significant wave steepness",
+            "units": "1",
+            "inputs": {"h": ["swh", "VHM0"], "t": ["mwp", "VTM02"]},
+            "expression": "2 * pi * h / (9.81 * t^2)"
+        }, {
+            "name": "swell_energy",
+            "inputs": {"h": ["shts"]},
+            "expression": "h^2"
+        }])
+        .to_string(),
+    )
+    .unwrap();
+
+    let mut registry = DerivedRegistry::default();
+    registry.register(WavePower).unwrap();
+    assert_eq!(registry.load(&path).unwrap(), 2);
+    std::fs::remove_file(path).unwrap();
+    registry
+}
+
+async fn create_test_router() -> Router {
+    let mut state = AppState::new(mock_server::start().await, reqwest::Client::new());
+    state.derived_variables = registry();
+
+    Router::new()
+        .route("/proxy/metadata", get(proxy_metadata))
+        .route("/proxy/data", get(proxy_data))
+        .route("/data/weather/current/:file", get(earth_current_data))
+        .with_state(Arc::new(state))
+}
+
+async fn get_json(app: &Router, uri: &str) -> (StatusCode, Value) {
+    let response = app
+        .clone()
+        .oneshot(Request::builder().uri(uri).body(Body::empty()).unwrap())
+        .await
+        .unwrap();
+    let status = response.status();
+    let bytes = hyper::body::to_bytes(response.into_body()).await.unwrap();
+    (status, serde_json::from_slice(&bytes).unwrap())
+}
+
+#[tokio::test]
+async fn test_custom_variables_in_catalog() {
+    let app = create_test_router().await;
+
+    let (status, metadata) = get_json(&app, "/proxy/metadata").await;
+    assert_eq!(status, StatusCode::OK);
+    let variables = &metadata["variables"];
+    assert_eq!(
+        variables["wave_power"]["attributes"],
+        json!({
+            "long_name": "Wave energy flux",
+            "units": "kW m**-1",
+            "derived_from": ["VHM0", "VTM02"]
+        })
+    );
+    assert_eq!(
+        variables["wave_steepness"]["dimensions"],
+        json!(["time", "latitude", "longitude"])
+    );
+    assert_eq!(variables["wave_steepness"]["attributes"]["units"], "1");
+    // Its input is missing from the dataset
+    assert!(variables.get("swell_energy").is_none());
+}
+
+#[tokio::test]
+async fn test_custom_variables_computed() {
+    let app = create_test_router().await;
+
+    let (status, body) = get_json(&app, "/proxy/data?vars=wave_power,VHM0&time=700464").await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(body["data"]["wave_power"], json!([2.45, 19.6, 78.4, null]));
+    assert_eq!(body["data"]["VHM0"], json!([1.0, 2.0, 4.0, null]));
+    assert!(body["data"].get("VTM02").is_none());
+
+    let (status, body) = get_json(
+        &app,
+        "/data/weather/current/current-wave_steepness-surface-level-gfs-1.0.json",
+    )
+    .await;
+    assert_eq!(status, StatusCode::OK);
+    let steepness = body[0]["data"][0].as_f64().unwrap();
+    let expected = 2.0 * std::f64::consts::PI * 1.0 / (9.81 * 25.0);
+    assert!((steepness - expected).abs() < 1e-9);
+}
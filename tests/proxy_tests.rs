@@ -1,135 +1,33 @@
-use reqwest;
+use axum::{
+    body::Body,
+    http::{Request, StatusCode},
+};
+use rossby_vis::{
+    build_router, client::BackendClientConfig, testing::MockBackend, AppState, ServerConfig,
+    ServerHandle,
+};
 use serde_json::Value;
 use std::time::Duration;
-use tokio::time::sleep;
-
-/// Mock Rossby server for testing
-mod mock_server {
-    use axum::{extract::Query, response::Json, routing::get, Router};
-    use serde_json::{json, Value};
-    use std::collections::HashMap;
-    use tokio::net::TcpListener;
-
-    /// Start a mock Rossby server for testing
-    pub async fn start_mock_rossby_server() -> String {
-        let app = Router::new()
-            .route("/metadata", get(mock_metadata))
-            .route("/data", get(mock_data));
-
-        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
-        let addr = listener.local_addr().unwrap();
-        let server_url = format!("http://{}", addr);
-
-        tokio::spawn(async move {
-            axum::Server::from_tcp(listener.into_std().unwrap())
-                .unwrap()
-                .serve(app.into_make_service())
-                .await
-                .unwrap();
-        });
-
-        // Give the server a moment to start
-        tokio::time::sleep(tokio::time::Duration::from_millis(100)).await;
-
-        server_url
-    }
-
-    /// Mock metadata endpoint
-    async fn mock_metadata() -> Json<Value> {
-        Json(json!({
-            "coordinates": {
-                "latitude": [90.0, 89.75, 89.5],
-                "longitude": [0.0, 0.25, 0.5],
-                "time": [700464.0, 700465.0]
-            },
-            "dimensions": {
-                "latitude": {"size": 3},
-                "longitude": {"size": 3},
-                "time": {"size": 2}
-            },
-            "variables": {
-                "u10": {
-                    "attributes": {
-                        "long_name": "10 metre U wind component",
-                        "units": "m s**-1"
-                    }
-                },
-                "v10": {
-                    "attributes": {
-                        "long_name": "10 metre V wind component",
-                        "units": "m s**-1"
-                    }
-                }
-            }
-        }))
-    }
-
-    /// Mock data endpoint
-    async fn mock_data(Query(params): Query<HashMap<String, String>>) -> Json<Value> {
-        let vars = params.get("vars").unwrap_or(&"u10".to_string()).clone();
-        let variables: Vec<&str> = vars.split(',').collect();
-
-        let mut data = serde_json::Map::new();
-        for var in variables {
-            data.insert(
-                var.to_string(),
-                json!([
-                    1.0, 2.0, 3.0, 4.0, 5.0, 6.0, 7.0, 8.0, 9.0, 10.0, 11.0, 12.0, 13.0, 14.0,
-                    15.0, 16.0, 17.0, 18.0
-                ]),
-            );
-        }
-
-        Json(json!({
-            "metadata": {
-                "query": params,
-                "shape": [2, 3, 3],
-                "dimensions": ["time", "latitude", "longitude"],
-                "variables": {
-                    "u10": {
-                        "units": "m s**-1",
-                        "long_name": "10 metre U wind component"
-                    },
-                    "v10": {
-                        "units": "m s**-1",
-                        "long_name": "10 metre V wind component"
-                    }
-                }
-            },
-            "data": data
-        }))
-    }
-}
+use tower::ServiceExt;
 
 /// Integration test helper
-async fn setup_test_environment() -> (String, String) {
-    let mock_rossby_url = mock_server::start_mock_rossby_server().await;
-
-    // For integration tests, we'll start the server on a known port
-    // In a real test, we'd want to use random ports
-    let test_port = 18080;
-    let server_url = format!("http://127.0.0.1:{}", test_port);
+async fn setup_test_environment() -> (String, ServerHandle) {
+    let mock_rossby_url = MockBackend::default().start().await.url().to_string();
 
-    // Start rossby-vis server in background
-    tokio::spawn(async move {
-        rossby_vis::run_server(test_port, mock_rossby_url)
-            .await
-            .unwrap();
-    });
+    // Start rossby-vis on a port chosen by the system
+    let server = rossby_vis::run_server(0, mock_rossby_url).await.unwrap();
+    let server_url = format!("http://{}", server.addr());
 
-    // Give servers time to start
-    sleep(Duration::from_millis(300)).await;
-
-    (server_url, "mock_url".to_string())
+    (server_url, server)
 }
 
 #[tokio::test]
 async fn test_proxy_metadata_endpoint() {
-    let (server_url, _) = setup_test_environment().await;
+    let (server_url, _server) = setup_test_environment().await;
 
     let client = reqwest::Client::new();
     let response = client
-        .get(&format!("{}/proxy/metadata", server_url))
+        .get(format!("{}/proxy/metadata", server_url))
         .send()
         .await;
 
@@ -145,11 +43,11 @@ async fn test_proxy_metadata_endpoint() {
 
 #[tokio::test]
 async fn test_proxy_data_endpoint_single_variable() {
-    let (server_url, _) = setup_test_environment().await;
+    let (server_url, _server) = setup_test_environment().await;
 
     let client = reqwest::Client::new();
     let response = client
-        .get(&format!("{}/proxy/data?vars=u10&time=700464", server_url))
+        .get(format!("{}/proxy/data?vars=u10&time=700464", server_url))
         .send()
         .await;
 
@@ -167,11 +65,11 @@ async fn test_proxy_data_endpoint_single_variable() {
 
 #[tokio::test]
 async fn test_proxy_data_endpoint_multiple_variables() {
-    let (server_url, _) = setup_test_environment().await;
+    let (server_url, _server) = setup_test_environment().await;
 
     let client = reqwest::Client::new();
     let response = client
-        .get(&format!(
+        .get(format!(
             "{}/proxy/data?vars=u10,v10&time=700464",
             server_url
         ))
@@ -193,11 +91,11 @@ async fn test_proxy_data_endpoint_multiple_variables() {
 
 #[tokio::test]
 async fn test_streaming_response_headers() {
-    let (server_url, _) = setup_test_environment().await;
+    let (server_url, _server) = setup_test_environment().await;
 
     let client = reqwest::Client::new();
     let response = client
-        .get(&format!("{}/proxy/data?vars=u10&time=700464", server_url))
+        .get(format!("{}/proxy/data?vars=u10&time=700464", server_url))
         .send()
         .await;
 
@@ -217,20 +115,15 @@ async fn test_streaming_response_headers() {
 
 #[tokio::test]
 async fn test_error_handling_invalid_backend() {
-    // Test with an invalid backend URL
-    let test_port = 18081;
-    let server_url = format!("http://127.0.0.1:{}", test_port);
-
-    // Start rossby-vis with invalid backend
-    tokio::spawn(async move {
-        let _ = rossby_vis::run_server(test_port, "http://localhost:99999".to_string()).await;
-    });
-
-    sleep(Duration::from_millis(200)).await;
+    // Start rossby-vis with a backend nothing listens on
+    let server = rossby_vis::run_server(0, "http://127.0.0.1:1".to_string())
+        .await
+        .unwrap();
+    let server_url = format!("http://{}", server.addr());
 
     let client = reqwest::Client::new();
     let response = client
-        .get(&format!("{}/proxy/data?vars=u10", server_url))
+        .get(format!("{}/proxy/data?vars=u10", server_url))
         .send()
         .await;
 
@@ -238,11 +131,13 @@ async fn test_error_handling_invalid_backend() {
         // Should return an error status
         assert!(!resp.status().is_success());
     }
+
+    server.stop().await.unwrap();
 }
 
 #[tokio::test]
 async fn test_static_assets_still_work() {
-    let (server_url, _) = setup_test_environment().await;
+    let (server_url, _server) = setup_test_environment().await;
 
     let client = reqwest::Client::new();
     let response = client.get(&server_url).send().await;
@@ -253,3 +148,59 @@ async fn test_static_assets_still_work() {
         // NOT_FOUND is acceptable if index.html isn't embedded yet
     }
 }
+
+#[tokio::test]
+async fn test_mock_backend_grid_and_latency() {
+    let backend = MockBackend::regular(5, 8)
+        .with_latency(Duration::from_millis(100))
+        .start()
+        .await;
+
+    let started = std::time::Instant::now();
+    let response = reqwest::get(format!("{}/data?vars=u10&time=700465", backend.url()))
+        .await
+        .unwrap();
+    assert!(started.elapsed() >= Duration::from_millis(100));
+    let data: Value = response.json().await.unwrap();
+    assert_eq!(data["metadata"]["shape"], serde_json::json!([1, 5, 8]));
+    assert_eq!(data["data"]["u10"][39], 40.0);
+
+    let response = reqwest::get(format!("{}/data?vars=t2m", backend.url()))
+        .await
+        .unwrap();
+    assert_eq!(response.status(), reqwest::StatusCode::BAD_REQUEST);
+    assert_eq!(backend.requests(), 2);
+}
+
+#[tokio::test]
+async fn test_backend_request_timeout() {
+    let backend = MockBackend::default()
+        .with_latency(Duration::from_millis(500))
+        .start()
+        .await;
+    let config = ServerConfig::builder(backend.url())
+        .backend_client(BackendClientConfig {
+            request_timeout: Some(Duration::from_millis(50)),
+            ..Default::default()
+        })
+        .build()
+        .unwrap();
+    let app = build_router(AppState::from_config(config).await.unwrap());
+
+    let response = app
+        .oneshot(
+            Request::builder()
+                .uri("/proxy/metadata")
+                .body(Body::empty())
+                .unwrap(),
+        )
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::GATEWAY_TIMEOUT);
+    let body = hyper::body::to_bytes(response.into_body()).await.unwrap();
+    let error: Value = serde_json::from_slice(&body).unwrap();
+    assert!(error["error"]
+        .as_str()
+        .unwrap()
+        .starts_with("Backend timeout"));
+}
@@ -0,0 +1,166 @@
+//! Integration tests for recording and replaying backend interactions
+//!
+//! Responses of a mock Rossby server are recorded to a temporary directory,
+//! then replayed with the mock server no longer consulted.
+
+use axum::{
+    body::Body,
+    http::{Request, StatusCode},
+    routing::get,
+    Router,
+};
+use serde_json::Value;
+use std::{
+    path::Path,
+    sync::{atomic::Ordering, Arc},
+};
+use tower::ServiceExt;
+
+use rossby_vis::{
+    handlers::{proxy_data, proxy_metadata},
+    replay::{load_fixtures, Recording},
+    server::AppState,
+};
+
+mod mock_server {
+    use axum::{extract::Query, response::Json, routing::get, Router};
+    use serde_json::{json, Value};
+    use std::{
+        collections::HashMap,
+        sync::{
+            atomic::{AtomicUsize, Ordering},
+            Arc,
+        },
+    };
+    use tokio::net::TcpListener;
+
+    pub async fn start() -> (String, Arc<AtomicUsize>) {
+        let requests = Arc::new(AtomicUsize::new(0));
+        let (metadata_counter, data_counter) = (requests.clone(), requests.clone());
+        let app = Router::new()
+            .route(
+                "/metadata",
+                get(move || {
+                    metadata_counter.fetch_add(1, Ordering::SeqCst);
+                    metadata()
+                }),
+            )
+            .route(
+                "/data",
+                get(move |query: Query<HashMap<String, String>>| {
+                    data_counter.fetch_add(1, Ordering::SeqCst);
+                    data(query)
+                }),
+            );
+
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+
+        tokio::spawn(async move {
+            axum::Server::from_tcp(listener.into_std().unwrap())
+                .unwrap()
+                .serve(app.into_make_service())
+                .await
+                .unwrap();
+        });
+
+        (format!("http://{}", addr), requests)
+    }
+
+    async fn metadata() -> Json<Value> {
+        Json(json!({
+            "coordinates": {
+                "latitude": [10.0, -10.0],
+                "longitude": [0.0, 90.0],
+                "time": [700464.0, 700470.0]
+            },
+            "variables": {
+                "t2m": {
+                    "dimensions": ["time", "latitude", "longitude"],
+                    "attributes": {"units": "K"}
+                }
+            }
+        }))
+    }
+
+    async fn data(Query(params): Query<HashMap<String, String>>) -> Json<Value> {
+        Json(json!({
+            "metadata": {"shape": [1, 2, 2], "dimensions": ["time", "latitude", "longitude"]},
+            "data": {params["vars"].clone(): [280.0, 281.0, 282.0, 283.0]}
+        }))
+    }
+}
+
+async fn app(recording: &Recording, backend_url: &str) -> Router {
+    let api_url = recording
+        .start(backend_url, reqwest::Client::new())
+        .await
+        .unwrap();
+    let state = Arc::new(AppState::new(api_url, reqwest::Client::new()));
+    Router::new()
+        .route("/proxy/metadata", get(proxy_metadata))
+        .route("/proxy/data", get(proxy_data))
+        .with_state(state)
+}
+
+async fn get_json(app: &Router, uri: &str) -> (StatusCode, Value) {
+    let response = app
+        .clone()
+        .oneshot(Request::builder().uri(uri).body(Body::empty()).unwrap())
+        .await
+        .unwrap();
+    let status = response.status();
+    let bytes = hyper::body::to_bytes(response.into_body()).await.unwrap();
+    (
+        status,
+        serde_json::from_slice(&bytes).unwrap_or(Value::Null),
+    )
+}
+
+fn fixture_count(dir: &Path) -> usize {
+    load_fixtures(dir).unwrap().len()
+}
+
+#[tokio::test]
+async fn test_record_then_replay() {
+    let (backend_url, backend_requests) = mock_server::start().await;
+    let dir = std::env::temp_dir().join(format!("rossby-vis-fixtures-{}", uuid::Uuid::new_v4()));
+
+    let recording = app(&Recording::Record(dir.clone()), &backend_url).await;
+    let (status, recorded_metadata) = get_json(&recording, "/proxy/metadata").await;
+    assert_eq!(status, StatusCode::OK);
+    let (status, recorded_data) =
+        get_json(&recording, "/proxy/data?vars=t2m&time=700470&format=json").await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(recorded_data["data"]["t2m"][3], 283.0);
+    assert_eq!(fixture_count(&dir), 2);
+    let requests_while_recording = backend_requests.load(Ordering::SeqCst);
+
+    // The backend URL is never contacted while replaying
+    let replaying = app(&Recording::Replay(dir.clone()), "http://127.0.0.1:1").await;
+    let (status, metadata) = get_json(&replaying, "/proxy/metadata").await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(metadata, recorded_metadata);
+    let (status, data) = get_json(&replaying, "/proxy/data?format=json&time=700470&vars=t2m").await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(data, recorded_data);
+
+    // Requests that were not recorded fail instead of reaching the backend
+    let (status, _) = get_json(&replaying, "/proxy/data?vars=u10&time=700470&format=json").await;
+    assert!(!status.is_success());
+    assert_eq!(
+        backend_requests.load(Ordering::SeqCst),
+        requests_while_recording
+    );
+
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[tokio::test]
+async fn test_replay_requires_fixture_directory() {
+    let dir = std::env::temp_dir().join(format!("rossby-vis-missing-{}", uuid::Uuid::new_v4()));
+    let result = Recording::Replay(dir)
+        .start("http://127.0.0.1:1", reqwest::Client::new())
+        .await;
+    assert!(result.is_err());
+}
@@ -0,0 +1,124 @@
+//! Integration tests for the circuit breaker around the backend
+
+use axum::{
+    body::Body,
+    extract::State,
+    http::{Request, StatusCode},
+    response::{IntoResponse, Response},
+    routing::get,
+    Json, Router,
+};
+use std::{
+    sync::{
+        atomic::{AtomicBool, AtomicUsize, Ordering},
+        Arc,
+    },
+    time::Duration,
+};
+use tokio::net::TcpListener;
+use tower::ServiceExt;
+
+use rossby_vis::{
+    build_router, cache::ResponseCacheConfig, resilience::CircuitBreakerConfig,
+    testing::MockBackend, AppState, ServerConfig,
+};
+
+/// A backend answering `503` to everything while `down`
+#[derive(Default)]
+struct SwitchableBackend {
+    down: AtomicBool,
+    requests: AtomicUsize,
+}
+
+async fn metadata(State(backend): State<Arc<SwitchableBackend>>) -> Response {
+    backend.requests.fetch_add(1, Ordering::SeqCst);
+    if backend.down.load(Ordering::SeqCst) {
+        StatusCode::SERVICE_UNAVAILABLE.into_response()
+    } else {
+        Json(MockBackend::default().metadata()).into_response()
+    }
+}
+
+async fn start_backend() -> (String, Arc<SwitchableBackend>) {
+    let backend = Arc::new(SwitchableBackend::default());
+    let app = Router::new()
+        .route("/metadata", get(metadata))
+        .with_state(backend.clone());
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    tokio::spawn(async move {
+        axum::Server::from_tcp(listener.into_std().unwrap())
+            .unwrap()
+            .serve(app.into_make_service())
+            .await
+            .unwrap();
+    });
+    (format!("http://{}", addr), backend)
+}
+
+async fn app(url: &str, failure_threshold: u32, cooldown: Duration) -> Router {
+    let config = ServerConfig::builder(url)
+        .circuit_breaker(CircuitBreakerConfig {
+            failure_threshold,
+            cooldown,
+            probes: 1,
+        })
+        // Responses expire at once, leaving only stale ones to fall back to
+        .response_cache(ResponseCacheConfig {
+            max_bytes: 1024 * 1024,
+            ttl: Duration::ZERO,
+            disk: None,
+        })
+        .build()
+        .unwrap();
+    build_router(AppState::from_config(config).await.unwrap())
+}
+
+async fn status(app: &Router, uri: &str) -> StatusCode {
+    app.clone()
+        .oneshot(Request::builder().uri(uri).body(Body::empty()).unwrap())
+        .await
+        .unwrap()
+        .status()
+}
+
+#[tokio::test]
+async fn test_open_circuit_serves_stale_responses() {
+    let (url, backend) = start_backend().await;
+    let app = app(&url, 2, Duration::from_secs(60)).await;
+    assert_eq!(status(&app, "/proxy/metadata").await, StatusCode::OK);
+
+    backend.down.store(true, Ordering::SeqCst);
+    assert_eq!(
+        status(&app, "/proxy/metadata").await,
+        StatusCode::BAD_GATEWAY
+    );
+    // The second failure opens the circuit, and the expired response stands in
+    assert_eq!(status(&app, "/proxy/metadata").await, StatusCode::OK);
+    let requests = backend.requests.load(Ordering::SeqCst);
+    assert_eq!(requests, 3);
+
+    // While open, the backend is left alone
+    assert_eq!(status(&app, "/proxy/metadata").await, StatusCode::OK);
+    assert_eq!(
+        status(&app, "/proxy/data?vars=u10").await,
+        StatusCode::SERVICE_UNAVAILABLE
+    );
+    assert_eq!(backend.requests.load(Ordering::SeqCst), requests);
+}
+
+#[tokio::test]
+async fn test_circuit_closes_after_a_successful_probe() {
+    let (url, backend) = start_backend().await;
+    let app = app(&url, 1, Duration::ZERO).await;
+
+    backend.down.store(true, Ordering::SeqCst);
+    assert_eq!(
+        status(&app, "/proxy/metadata").await,
+        StatusCode::BAD_GATEWAY
+    );
+    backend.down.store(false, Ordering::SeqCst);
+    assert_eq!(status(&app, "/proxy/metadata").await, StatusCode::OK);
+    assert_eq!(status(&app, "/proxy/metadata").await, StatusCode::OK);
+    assert_eq!(backend.requests.load(Ordering::SeqCst), 3);
+}
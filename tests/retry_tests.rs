@@ -0,0 +1,95 @@
+//! Integration tests for retrying failed backend requests
+
+use axum::{
+    body::Body,
+    extract::State,
+    http::{Request, StatusCode},
+    response::{IntoResponse, Response},
+    routing::get,
+    Json, Router,
+};
+use std::{
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc,
+    },
+    time::Duration,
+};
+use tokio::net::TcpListener;
+use tower::ServiceExt;
+
+use rossby_vis::{build_router, retry::RetryPolicy, testing::MockBackend, AppState, ServerConfig};
+
+/// A backend answering `503` to its first `failures` metadata requests
+struct FlakyBackend {
+    failures: usize,
+    requests: AtomicUsize,
+}
+
+async fn metadata(State(backend): State<Arc<FlakyBackend>>) -> Response {
+    if backend.requests.fetch_add(1, Ordering::SeqCst) < backend.failures {
+        StatusCode::SERVICE_UNAVAILABLE.into_response()
+    } else {
+        Json(MockBackend::default().metadata()).into_response()
+    }
+}
+
+async fn start_flaky(failures: usize) -> (String, Arc<FlakyBackend>) {
+    let backend = Arc::new(FlakyBackend {
+        failures,
+        requests: AtomicUsize::new(0),
+    });
+    let app = Router::new()
+        .route("/metadata", get(metadata))
+        .with_state(backend.clone());
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    tokio::spawn(async move {
+        axum::Server::from_tcp(listener.into_std().unwrap())
+            .unwrap()
+            .serve(app.into_make_service())
+            .await
+            .unwrap();
+    });
+    (format!("http://{}", addr), backend)
+}
+
+async fn metadata_status(url: &str, retries: u32) -> StatusCode {
+    let config = ServerConfig::builder(url)
+        .backend_retry(RetryPolicy {
+            retries,
+            base_delay: Duration::from_millis(10),
+            jitter: 0.0,
+        })
+        .build()
+        .unwrap();
+    let app = build_router(AppState::from_config(config).await.unwrap());
+    app.oneshot(
+        Request::builder()
+            .uri("/proxy/metadata")
+            .body(Body::empty())
+            .unwrap(),
+    )
+    .await
+    .unwrap()
+    .status()
+}
+
+#[tokio::test]
+async fn test_transient_failures_are_retried() {
+    let (url, backend) = start_flaky(2).await;
+    assert_eq!(metadata_status(&url, 2).await, StatusCode::OK);
+    assert_eq!(backend.requests.load(Ordering::SeqCst), 3);
+}
+
+#[tokio::test]
+async fn test_retries_are_limited() {
+    let (url, backend) = start_flaky(2).await;
+    assert_eq!(metadata_status(&url, 1).await, StatusCode::BAD_GATEWAY);
+    assert_eq!(backend.requests.load(Ordering::SeqCst), 2);
+
+    // Without retries the first failure is the answer
+    let (url, backend) = start_flaky(1).await;
+    assert_eq!(metadata_status(&url, 0).await, StatusCode::BAD_GATEWAY);
+    assert_eq!(backend.requests.load(Ordering::SeqCst), 1);
+}
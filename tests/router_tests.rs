@@ -0,0 +1,64 @@
+//! Integration tests for mounting the rossby-vis router in another application
+
+use axum::{
+    body::Body,
+    http::{Request, StatusCode},
+    routing::get,
+    Router,
+};
+use std::sync::Arc;
+use tower::ServiceExt;
+
+use rossby_vis::{build_router, testing::MockBackend, AppState, ServerConfig};
+
+async fn get_status(app: &Router, uri: &str) -> StatusCode {
+    app.clone()
+        .oneshot(Request::builder().uri(uri).body(Body::empty()).unwrap())
+        .await
+        .unwrap()
+        .status()
+}
+
+#[tokio::test]
+async fn test_router_mounted_under_a_prefix() {
+    let backend = MockBackend::default().start().await;
+    let state = Arc::new(AppState::new(
+        backend.url().to_string(),
+        reqwest::Client::new(),
+    ));
+    let app = Router::new()
+        .route("/", get(|| async { "host application" }))
+        .nest("/weather", build_router(state));
+
+    assert_eq!(get_status(&app, "/").await, StatusCode::OK);
+    assert_eq!(
+        get_status(&app, "/weather/api/status").await,
+        StatusCode::OK
+    );
+    assert_eq!(
+        get_status(&app, "/weather/proxy/metadata").await,
+        StatusCode::OK
+    );
+    assert_eq!(backend.requests(), 1);
+}
+
+#[tokio::test]
+async fn test_state_from_config() {
+    let backend = MockBackend::default().start().await;
+    let config = ServerConfig::builder(backend.url())
+        .cors_origin("https://maps.example.com")
+        .build()
+        .unwrap();
+    let state = AppState::from_config(config).await.unwrap();
+    assert_eq!(state.cors.origins, ["https://maps.example.com"]);
+
+    let app = build_router(state);
+    assert_eq!(get_status(&app, "/health").await, StatusCode::OK);
+    assert_eq!(get_status(&app, "/proxy/metadata").await, StatusCode::OK);
+
+    let invalid = ServerConfig::builder(backend.url())
+        .cors_origin("https://bad\norigin")
+        .build()
+        .unwrap();
+    assert!(AppState::from_config(invalid).await.is_err());
+}
@@ -0,0 +1,151 @@
+//! Integration tests for the scheduled tasks
+//!
+//! Tasks are run on demand against a mock Rossby server that counts the
+//! data requests it receives.
+
+use axum::{
+    body::Body,
+    http::{Request, StatusCode},
+    routing::get,
+    Router,
+};
+use serde_json::Value;
+use std::sync::{atomic::Ordering, Arc};
+use tower::ServiceExt;
+
+use rossby_vis::{
+    handlers::proxy_metadata,
+    scheduler::{scheduled_tasks, Scheduler, TaskSchedule},
+    server::AppState,
+};
+
+mod mock_server {
+    use axum::{extract::Query, response::Json, routing::get, Router};
+    use serde_json::{json, Value};
+    use std::{
+        collections::HashMap,
+        sync::{
+            atomic::{AtomicUsize, Ordering},
+            Arc,
+        },
+    };
+    use tokio::net::TcpListener;
+
+    pub async fn start() -> (String, Arc<AtomicUsize>) {
+        let requests = Arc::new(AtomicUsize::new(0));
+        let counter = requests.clone();
+        let app = Router::new().route("/metadata", get(metadata)).route(
+            "/data",
+            get(move |query: Query<HashMap<String, String>>| {
+                counter.fetch_add(1, Ordering::SeqCst);
+                data(query)
+            }),
+        );
+
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+
+        tokio::spawn(async move {
+            axum::Server::from_tcp(listener.into_std().unwrap())
+                .unwrap()
+                .serve(app.into_make_service())
+                .await
+                .unwrap();
+        });
+
+        (format!("http://{}", addr), requests)
+    }
+
+    async fn metadata() -> Json<Value> {
+        Json(json!({
+            "coordinates": {
+                "latitude": [10.0, -10.0],
+                "longitude": [0.0, 90.0],
+                "time": [700464.0, 700470.0]
+            },
+            "dimensions": {
+                "latitude": {"size": 2},
+                "longitude": {"size": 2},
+                "time": {"size": 2}
+            },
+            "variables": {
+                "t2m": {
+                    "dimensions": ["time", "latitude", "longitude"],
+                    "attributes": {"units": "K"}
+                }
+            }
+        }))
+    }
+
+    async fn data(Query(params): Query<HashMap<String, String>>) -> Json<Value> {
+        assert_eq!(params["time"], "700470");
+        Json(json!({
+            "metadata": {"shape": [1, 2, 2], "dimensions": ["time", "latitude", "longitude"]},
+            "data": {"t2m": [280.0, 280.0, 280.0, 280.0]}
+        }))
+    }
+}
+
+#[tokio::test]
+async fn test_scheduled_tasks_run_and_report() {
+    let (api_url, data_requests) = mock_server::start().await;
+    let mut schedule = TaskSchedule::default();
+    schedule
+        .add_json(
+            r#"[
+                {"task": "refresh-metadata", "every": "10m"},
+                {"task": "prefetch", "vars": ["t2m", "nope"], "at": ["03:30"]},
+                {"name": "latest", "task": "prefetch", "vars": ["t2m"], "at": ["03:30"]},
+                {"task": "warm", "paths": ["/proxy/metadata", "/missing"], "every": "1h"}
+            ]"#,
+        )
+        .unwrap();
+
+    let mut state = AppState::new(api_url, reqwest::Client::new());
+    state.scheduler = Arc::new(Scheduler::new(schedule));
+    let state = Arc::new(state);
+    let app = Router::new()
+        .route("/proxy/metadata", get(proxy_metadata))
+        .route("/admin/schedule", get(scheduled_tasks))
+        .with_state(state.clone());
+
+    for index in 0..4 {
+        state.scheduler.run_task(&state, app.clone(), index).await;
+    }
+    assert_eq!(data_requests.load(Ordering::SeqCst), 1);
+
+    let response = app
+        .clone()
+        .oneshot(
+            Request::builder()
+                .uri("/admin/schedule")
+                .body(Body::empty())
+                .unwrap(),
+        )
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    let bytes = hyper::body::to_bytes(response.into_body()).await.unwrap();
+    let body: Value = serde_json::from_slice(&bytes).unwrap();
+    let tasks = body["tasks"].as_array().unwrap();
+    assert_eq!(tasks.len(), 4);
+
+    assert_eq!(tasks[0]["name"], "refresh-metadata");
+    assert_eq!(tasks[0]["schedule"], "every 600s");
+    assert!(tasks[0]["last_result"]
+        .as_str()
+        .unwrap()
+        .ends_with("latest time 1979-11-29T06:00:00+00:00"));
+
+    // Unknown variables fail the task without reaching the backend
+    assert_eq!(tasks[1]["failures"], 1);
+    assert!(tasks[1]["last_error"].as_str().unwrap().contains("nope"));
+    assert_eq!(tasks[2]["runs"], 1);
+    assert!(tasks[2]["last_error"].is_null());
+
+    assert_eq!(tasks[3]["task"], "warm");
+    assert!(tasks[3]["last_error"]
+        .as_str()
+        .unwrap()
+        .contains("/missing (404 Not Found)"));
+}
@@ -0,0 +1,41 @@
+//! Integration tests for controlling a running server through its handle
+
+use rossby_vis::{run_server, run_server_with_config, testing::MockBackend, ServerConfig};
+
+#[tokio::test]
+async fn test_server_on_ephemeral_port_shuts_down() {
+    let backend = MockBackend::default().start().await;
+    let server = run_server(0, backend.url().to_string()).await.unwrap();
+    let addr = server.addr();
+    assert_ne!(addr.port(), 0);
+
+    let client = reqwest::Client::new();
+    let response = client
+        .get(format!("http://{}/health", addr))
+        .send()
+        .await
+        .unwrap();
+    assert!(response.status().is_success());
+
+    server.stop().await.unwrap();
+
+    assert!(client
+        .get(format!("http://{}/health", addr))
+        .send()
+        .await
+        .is_err());
+}
+
+#[tokio::test]
+async fn test_server_reports_address_in_use() {
+    let backend = MockBackend::default().start().await;
+    let server = run_server(0, backend.url().to_string()).await.unwrap();
+
+    let config = ServerConfig::builder(backend.url())
+        .bind(server.addr())
+        .build()
+        .unwrap();
+    assert!(run_server_with_config(config).await.is_err());
+
+    server.stop().await.unwrap();
+}
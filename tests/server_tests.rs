@@ -46,7 +46,7 @@ fn test_index_page() {
     let client = Client::new();
 
     let response = client
-        .get(&format!("http://localhost:{}", port))
+        .get(format!("http://localhost:{}", port))
         .send()
         .expect("Failed to send request");
 
@@ -72,7 +72,7 @@ fn test_static_asset() {
 
     // Test requesting a static asset that should exist
     let response = client
-        .get(&format!("http://localhost:{}/index.html", port))
+        .get(format!("http://localhost:{}/index.html", port))
         .send()
         .expect("Failed to send request");
 
@@ -95,7 +95,7 @@ fn test_not_found() {
 
     // Test requesting a non-existent asset
     let response = client
-        .get(&format!("http://localhost:{}/nonexistent.file", port))
+        .get(format!("http://localhost:{}/nonexistent.file", port))
         .send()
         .expect("Failed to send request");
 
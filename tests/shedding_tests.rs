@@ -0,0 +1,184 @@
+//! Integration tests for load shedding
+//!
+//! The recorded system pressure is process-wide, so every check runs in one
+//! test with memory usage reported above the threshold.
+
+use axum::{
+    body::Body,
+    http::{header, Method, Request, StatusCode},
+    routing::{get, post},
+    Router,
+};
+use std::sync::Arc;
+use tower::ServiceExt;
+
+use rossby_vis::{
+    analysis::sample,
+    frames::earth_frames,
+    handlers::proxy_metadata,
+    pipeline::{apply_pipeline, CompressionConfig, CorsConfig, DEFAULT_PIPELINE},
+    server::AppState,
+    shedding::{record_pressure, LoadShedder, LoadSheddingConfig},
+    testing::MockBackend,
+};
+
+async fn send(app: &Router, method: Method, uri: &str, etag: Option<&str>) -> StatusCode {
+    let mut request = Request::builder().method(method).uri(uri);
+    if let Some(etag) = etag {
+        request = request.header(header::IF_NONE_MATCH, etag);
+    }
+    let response = app
+        .clone()
+        .oneshot(request.body(Body::from("{}")).unwrap())
+        .await
+        .unwrap();
+    if response.status() == StatusCode::SERVICE_UNAVAILABLE {
+        assert_eq!(response.headers()[header::RETRY_AFTER], "10");
+    }
+    response.status()
+}
+
+#[tokio::test]
+async fn test_low_priority_requests_are_shed_under_pressure() {
+    let backend = MockBackend::default().start().await;
+    let mut state = AppState::new(backend.url().to_string(), reqwest::Client::new());
+    state.load_shedder = Arc::new(LoadShedder::new(LoadSheddingConfig {
+        max_memory_percent: Some(90.0),
+        ..Default::default()
+    }));
+    let state = Arc::new(state);
+    let router = Router::new()
+        .route("/proxy/metadata", get(proxy_metadata))
+        .route("/api/sample", post(sample))
+        .route("/data/frames/:variable", get(earth_frames));
+    let app = apply_pipeline(
+        router,
+        &state,
+        &DEFAULT_PIPELINE,
+        &CorsConfig::default(),
+        &CompressionConfig::default(),
+    )
+    .unwrap()
+    .with_state(state.clone());
+
+    // Below the threshold nothing is shed
+    record_pressure(50.0, 99.0);
+    assert_ne!(
+        send(&app, Method::POST, "/api/sample", None).await,
+        StatusCode::SERVICE_UNAVAILABLE
+    );
+
+    record_pressure(95.0, 10.0);
+    assert_eq!(
+        send(&app, Method::POST, "/api/sample", None).await,
+        StatusCode::SERVICE_UNAVAILABLE
+    );
+    assert_eq!(
+        send(&app, Method::GET, "/data/frames/temp", None).await,
+        StatusCode::SERVICE_UNAVAILABLE
+    );
+    assert_eq!(state.load_shedder.shed_count(), 2);
+
+    // Revalidations, data and health checks keep working
+    assert_ne!(
+        send(&app, Method::GET, "/data/frames/temp", Some("\"v1\"")).await,
+        StatusCode::SERVICE_UNAVAILABLE
+    );
+    assert_eq!(
+        send(&app, Method::GET, "/proxy/metadata", None).await,
+        StatusCode::OK
+    );
+    assert_eq!(
+        send(&app, Method::GET, "/health", None).await,
+        StatusCode::OK
+    );
+    assert_eq!(state.load_shedder.shed_count(), 2);
+}
+
+#[tokio::test]
+async fn test_requests_beyond_the_concurrency_limit_are_refused() {
+    let backend = MockBackend::default().start().await;
+    let mut state = AppState::new(backend.url().to_string(), reqwest::Client::new());
+    state.load_shedder = Arc::new(LoadShedder::new(LoadSheddingConfig {
+        max_concurrent_requests: Some(1),
+        ..Default::default()
+    }));
+    let state = Arc::new(state);
+    let (started, release) = (
+        Arc::new(tokio::sync::Notify::new()),
+        Arc::new(tokio::sync::Notify::new()),
+    );
+    let (handler_started, handler_release) = (started.clone(), release.clone());
+    let router = Router::new()
+        .route(
+            "/slow",
+            get(move || async move {
+                handler_started.notify_one();
+                handler_release.notified().await;
+                "done"
+            }),
+        )
+        .route("/fast", get(|| async { "done" }));
+    let app = apply_pipeline(
+        router,
+        &state,
+        &DEFAULT_PIPELINE,
+        &CorsConfig::default(),
+        &CompressionConfig::default(),
+    )
+    .unwrap()
+    .with_state(state.clone());
+
+    let slow = tokio::spawn({
+        let app = app.clone();
+        async move {
+            app.oneshot(Request::get("/slow").body(Body::empty()).unwrap())
+                .await
+                .unwrap()
+                .status()
+        }
+    });
+    started.notified().await;
+    assert_eq!(state.load_shedder.in_flight(), Some(1));
+
+    // The one slot is taken, whichever route a request is for
+    let response = app
+        .clone()
+        .oneshot(Request::get("/fast").body(Body::empty()).unwrap())
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::SERVICE_UNAVAILABLE);
+    assert_eq!(response.headers()[header::RETRY_AFTER], "10");
+    let body = hyper::body::to_bytes(response.into_body()).await.unwrap();
+    let body: serde_json::Value = serde_json::from_slice(&body).unwrap();
+    assert!(body.is_object(), "{}", body);
+    assert_eq!(state.load_shedder.refused_count(), 1);
+    // Health checks are answered before the limit
+    assert_eq!(
+        send(&app, Method::GET, "/health", None).await,
+        StatusCode::OK
+    );
+
+    release.notify_one();
+    assert_eq!(slow.await.unwrap(), StatusCode::OK);
+    assert_eq!(send(&app, Method::GET, "/fast", None).await, StatusCode::OK);
+    assert_eq!(state.load_shedder.in_flight(), Some(0));
+}
+
+#[tokio::test]
+async fn test_concurrency_limit_out_of_range_is_rejected() {
+    let backend = MockBackend::default().start().await;
+    for max in [0, rossby_vis::shedding::MAX_CONCURRENT_REQUESTS + 1] {
+        let mut config = rossby_vis::ServerConfig::new(0, backend.url().to_string());
+        config.load_shedding.max_concurrent_requests = Some(max);
+
+        let Err(error) = AppState::from_config(config).await else {
+            panic!("a limit of {} was accepted", max);
+        };
+        assert!(
+            error.to_string().contains("--max-concurrent-requests"),
+            "{}",
+            error
+        );
+    }
+}
@@ -0,0 +1,143 @@
+//! Integration tests for integrity signatures on data responses
+
+use axum::{
+    body::Body,
+    http::{response::Parts, Request, StatusCode},
+    Router,
+};
+use base64::{engine::general_purpose::STANDARD, Engine};
+use ring::{
+    hmac,
+    signature::{UnparsedPublicKey, ED25519},
+};
+use serde_json::Value;
+use std::path::PathBuf;
+use tower::ServiceExt;
+
+use rossby_vis::{
+    build_router,
+    signing::{signed_message, SigningAlgorithm, SigningConfig},
+    testing::MockBackend,
+    AppState, ServerConfig,
+};
+
+async fn router(backend: &str, signing: SigningConfig, data_dir: &PathBuf) -> Router {
+    let config = ServerConfig::builder(backend)
+        .signing(signing)
+        .data_dir(data_dir)
+        .build()
+        .unwrap();
+    build_router(AppState::from_config(config).await.unwrap())
+}
+
+async fn get(app: &Router, uri: &str) -> (Parts, Vec<u8>) {
+    let response = app
+        .clone()
+        .oneshot(Request::builder().uri(uri).body(Body::empty()).unwrap())
+        .await
+        .unwrap();
+    let (parts, body) = response.into_parts();
+    let body = hyper::body::to_bytes(body).await.unwrap().to_vec();
+    (parts, body)
+}
+
+/// The `sig` parameter of an `X-Signature` header
+fn signature(response: &Parts) -> Vec<u8> {
+    let header = response.headers["x-signature"].to_str().unwrap();
+    let sig = header.split("sig=\"").nth(1).unwrap().trim_end_matches('"');
+    STANDARD.decode(sig).unwrap()
+}
+
+fn temp_dir() -> PathBuf {
+    std::env::temp_dir().join(format!("rossby-vis-signing-{}", uuid::Uuid::new_v4()))
+}
+
+#[tokio::test]
+async fn test_ed25519_signatures_verify_with_public_key() {
+    let backend = MockBackend::default().start().await;
+    let data_dir = temp_dir();
+    let signing = SigningConfig {
+        algorithm: SigningAlgorithm::Ed25519,
+        ..SigningConfig::default()
+    };
+    let app = router(backend.url(), signing.clone(), &data_dir).await;
+
+    let (response, key) = get(&app, "/api/public-key").await;
+    assert_eq!(response.status, StatusCode::OK);
+    let key: Value = serde_json::from_slice(&key).unwrap();
+    assert_eq!(key["algorithm"], "ed25519");
+    let public_key = STANDARD
+        .decode(key["public_key"].as_str().unwrap())
+        .unwrap();
+    let public_key = UnparsedPublicKey::new(&ED25519, public_key);
+
+    let uri = "/proxy/data?vars=u10&time=700464";
+    let (response, body) = get(&app, uri).await;
+    assert_eq!(response.status, StatusCode::OK);
+    let header = response.headers["x-signature"].to_str().unwrap();
+    assert!(header.contains(&format!(r#"keyid="{}""#, key["key_id"].as_str().unwrap())));
+    assert!(response.headers["content-digest"]
+        .to_str()
+        .unwrap()
+        .starts_with("sha-256=:"));
+    let message = signed_message(uri, &body);
+    assert!(public_key
+        .verify(message.as_bytes(), &signature(&response))
+        .is_ok());
+
+    // The signature does not carry over to other data
+    let other = signed_message("/proxy/data?vars=u10&time=700465", &body);
+    assert!(public_key
+        .verify(other.as_bytes(), &signature(&response))
+        .is_err());
+
+    // Only data is signed
+    let (response, _) = get(&app, "/api/status").await;
+    assert!(response.headers.get("x-signature").is_none());
+
+    // The generated key is kept across restarts
+    let restarted = router(backend.url(), signing, &data_dir).await;
+    let (_, restarted_key) = get(&restarted, "/api/public-key").await;
+    let restarted_key: Value = serde_json::from_slice(&restarted_key).unwrap();
+    assert_eq!(restarted_key["key_id"], key["key_id"]);
+
+    std::fs::remove_dir_all(data_dir).unwrap();
+}
+
+#[tokio::test]
+async fn test_hmac_signatures() {
+    let backend = MockBackend::default().start().await;
+    let signing = SigningConfig {
+        algorithm: SigningAlgorithm::HmacSha256,
+        secret: Some("shared secret".to_string()),
+        ..SigningConfig::default()
+    };
+    let app = router(backend.url(), signing, &temp_dir()).await;
+
+    let uri = "/proxy/data?vars=v10";
+    let (response, body) = get(&app, uri).await;
+    assert_eq!(response.status, StatusCode::OK);
+    assert!(response.headers["x-signature"]
+        .to_str()
+        .unwrap()
+        .contains(r#"alg="hmac-sha256""#));
+    let key = hmac::Key::new(hmac::HMAC_SHA256, b"shared secret");
+    let message = signed_message(uri, &body);
+    assert!(hmac::verify(&key, message.as_bytes(), &signature(&response)).is_ok());
+
+    // There is no public key to hand out
+    let (response, _) = get(&app, "/api/public-key").await;
+    assert_eq!(response.status, StatusCode::NOT_FOUND);
+}
+
+#[tokio::test]
+async fn test_unsigned_by_default() {
+    let backend = MockBackend::default().start().await;
+    let app = router(backend.url(), SigningConfig::default(), &temp_dir()).await;
+
+    let (response, _) = get(&app, "/proxy/data?vars=u10").await;
+    assert_eq!(response.status, StatusCode::OK);
+    assert!(response.headers.get("x-signature").is_none());
+    let (response, _) = get(&app, "/api/public-key").await;
+    assert_eq!(response.status, StatusCode::NOT_FOUND);
+}
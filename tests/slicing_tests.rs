@@ -0,0 +1,78 @@
+//! Integration tests for index slicing on the data proxy
+
+use axum::{
+    body::Body,
+    http::{Request, StatusCode},
+    Router,
+};
+use serde_json::{json, Value};
+use std::sync::Arc;
+use tower::ServiceExt;
+
+use rossby_vis::{build_router, testing::MockBackend, AppState};
+
+async fn router() -> Router {
+    let backend = MockBackend::regular(181, 360).start().await;
+    let mut state = AppState::new(backend.url().to_string(), reqwest::Client::new());
+    state.strict_query = true;
+    build_router(Arc::new(state))
+}
+
+async fn get_json(app: &Router, uri: &str) -> (StatusCode, Value) {
+    let response = app
+        .clone()
+        .oneshot(Request::builder().uri(uri).body(Body::empty()).unwrap())
+        .await
+        .unwrap();
+    let status = response.status();
+    let body = hyper::body::to_bytes(response.into_body()).await.unwrap();
+    (status, serde_json::from_slice(&body).unwrap_or(Value::Null))
+}
+
+#[tokio::test]
+async fn test_index_slicing() {
+    let app = router().await;
+
+    // A single timestep by position
+    let (status, data) = get_json(&app, "/proxy/data?vars=u10&time_index=1").await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(data["metadata"]["query"]["time"], "700465");
+    assert_eq!(data["metadata"]["shape"], json!([1, 181, 360]));
+
+    // Ranges become coordinate ranges, steps are applied by the proxy
+    let (status, data) = get_json(
+        &app,
+        "/proxy/data?vars=u10&time_index=0&lat_indices=0:4:2&lon_indices=:3",
+    )
+    .await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(data["metadata"]["query"]["latitude_range"], "87,90");
+    assert_eq!(data["metadata"]["query"]["longitude_range"], "0,2");
+    assert_eq!(data["metadata"]["shape"], json!([1, 2, 3]));
+    assert_eq!(data["data"]["u10"], json!([1.0, 2.0, 3.0, 7.0, 8.0, 9.0]));
+}
+
+#[tokio::test]
+async fn test_invalid_index_slicing() {
+    let app = router().await;
+
+    for uri in [
+        "/proxy/data?vars=u10&time_index=4",
+        "/proxy/data?vars=u10&lat_indices=10:5",
+        "/proxy/data?vars=u10&lon_indices=0:360:0",
+        "/proxy/data?vars=u10&time=700464&time_index=0",
+        "/proxy/data?vars=u10&latitude_range=0,10&lat_indices=0:10",
+    ] {
+        let (status, body) = get_json(&app, uri).await;
+        assert_eq!(status, StatusCode::BAD_REQUEST, "{}", uri);
+        assert!(body["error"].is_string());
+    }
+
+    // Slices of unknown dimensions are unknown parameters in strict mode
+    let (status, body) = get_json(&app, "/proxy/data?vars=u10&depth_index=0").await;
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+    assert!(body["error"]
+        .as_str()
+        .unwrap()
+        .contains("Unknown query parameter 'depth_index'"));
+}
@@ -0,0 +1,72 @@
+//! Integration tests for the status and version endpoints
+
+use axum::{
+    body::Body,
+    http::{Request, StatusCode},
+    routing::get,
+    Router,
+};
+use serde_json::Value;
+use tower::ServiceExt;
+
+use rossby_vis::{
+    embed::bundle_version,
+    handlers::{status, version_info},
+    logging::record_request,
+};
+
+#[tokio::test]
+async fn test_status_reports_activity() {
+    record_request(200);
+    record_request(404);
+
+    let app = Router::new().route("/api/status", get(status));
+    let response = app
+        .oneshot(
+            Request::builder()
+                .uri("/api/status")
+                .body(Body::empty())
+                .unwrap(),
+        )
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+
+    let bytes = hyper::body::to_bytes(response.into_body()).await.unwrap();
+    let body: Value = serde_json::from_slice(&bytes).unwrap();
+    assert_eq!(body["service"], "rossby-vis");
+
+    let activity = &body["activity"];
+    assert!(activity["uptime_secs"].is_u64());
+    assert!(activity["requests"]["total"].as_u64().unwrap() >= 2);
+    assert!(activity["requests"]["client_errors"].as_u64().unwrap() >= 1);
+    assert_eq!(activity["backend"]["status"], "unknown");
+    assert!(activity["backend"]["last_success_secs_ago"].is_null());
+    assert!(activity["transfer"]["response_bytes"].is_u64());
+}
+
+#[tokio::test]
+async fn test_version_identifies_the_build() {
+    let app = Router::new().route("/api/version", get(version_info));
+    let response = app
+        .oneshot(
+            Request::builder()
+                .uri("/api/version")
+                .body(Body::empty())
+                .unwrap(),
+        )
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+
+    let bytes = hyper::body::to_bytes(response.into_body()).await.unwrap();
+    let body: Value = serde_json::from_slice(&bytes).unwrap();
+    assert_eq!(body["service"], "rossby-vis");
+    assert_eq!(body["version"], env!("CARGO_PKG_VERSION"));
+    assert!(body["git_sha"].as_str().is_some_and(|sha| !sha.is_empty()));
+    assert!(body["built_at"]
+        .as_str()
+        .is_some_and(|at| chrono::DateTime::parse_from_rfc3339(at).is_ok()));
+    assert!(body["features"].is_array());
+    assert_eq!(body["assets_version"], bundle_version());
+}
@@ -0,0 +1,156 @@
+//! Integration tests for strict query parameter checking
+
+use axum::{
+    body::Body,
+    http::{Method, Request, StatusCode},
+    middleware,
+    routing::{get, post},
+    Router,
+};
+use serde_json::Value;
+use std::sync::Arc;
+use tower::ServiceExt;
+
+use rossby_vis::{
+    analysis::cross_section, handlers::proxy_data, middleware::strict_query_middleware,
+    server::AppState,
+};
+
+/// Mock Rossby server for testing
+mod mock_server {
+    use axum::{extract::Query, response::Json, routing::get, Router};
+    use serde_json::{json, Value};
+    use std::collections::HashMap;
+    use tokio::net::TcpListener;
+
+    pub async fn start() -> String {
+        let app = Router::new()
+            .route("/metadata", get(metadata))
+            .route("/data", get(data));
+
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+
+        tokio::spawn(async move {
+            axum::Server::from_tcp(listener.into_std().unwrap())
+                .unwrap()
+                .serve(app.into_make_service())
+                .await
+                .unwrap();
+        });
+
+        format!("http://{}", addr)
+    }
+
+    async fn metadata() -> Json<Value> {
+        Json(json!({
+            "coordinates": {
+                "latitude": [10.0, 0.0],
+                "longitude": [0.0, 10.0],
+                "level": [850.0],
+                "time": [700464.0]
+            },
+            "dimensions": {
+                "latitude": {"size": 2},
+                "longitude": {"size": 2},
+                "level": {"size": 1},
+                "time": {"size": 1}
+            },
+            "variables": {
+                "t": {
+                    "dimensions": ["time", "level", "latitude", "longitude"],
+                    "attributes": {"units": "K"}
+                }
+            }
+        }))
+    }
+
+    async fn data(Query(params): Query<HashMap<String, String>>) -> Json<Value> {
+        Json(json!({
+            "metadata": {"query": params, "shape": [1, 1, 2, 2]},
+            "data": {"t": [280.0, 281.0, 282.0, 283.0]}
+        }))
+    }
+}
+
+async fn create_test_router(strict_query: bool) -> Router {
+    let mut state = AppState::new(mock_server::start().await, reqwest::Client::new());
+    state.strict_query = strict_query;
+    let state = Arc::new(state);
+
+    Router::new()
+        .route("/proxy/data", get(proxy_data))
+        .route("/api/cross-section", post(cross_section))
+        .route_layer(middleware::from_fn_with_state(
+            state.clone(),
+            strict_query_middleware,
+        ))
+        .with_state(state)
+}
+
+async fn send(app: Router, method: Method, uri: &str) -> (StatusCode, Value) {
+    let request = Request::builder()
+        .method(method)
+        .uri(uri)
+        .header("content-type", "application/json")
+        .body(Body::from("{}"))
+        .unwrap();
+
+    let response = app.oneshot(request).await.unwrap();
+    let status = response.status();
+    let bytes = hyper::body::to_bytes(response.into_body()).await.unwrap();
+    (
+        status,
+        serde_json::from_slice(&bytes).unwrap_or(Value::Null),
+    )
+}
+
+#[tokio::test]
+async fn test_typo_is_forwarded_without_strict_mode() {
+    let app = create_test_router(false).await;
+
+    let (status, body) = send(app, Method::GET, "/proxy/data?var=t&time=700464").await;
+
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(body["metadata"]["query"]["var"], "t");
+}
+
+#[tokio::test]
+async fn test_strict_mode_rejects_unknown_data_parameters() {
+    let app = create_test_router(true).await;
+
+    let (status, body) = send(app, Method::GET, "/proxy/data?var=t&time=700464").await;
+
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+    let message = body["error"].as_str().unwrap();
+    assert!(message.contains("Unknown query parameter 'var'"));
+    assert!(message.contains("vars, time, time_range, format"));
+}
+
+#[tokio::test]
+async fn test_strict_mode_allows_dimension_selectors() {
+    let app = create_test_router(true).await;
+
+    let (status, body) = send(
+        app,
+        Method::GET,
+        "/proxy/data?vars=t&time=700464&level=850&format=json",
+    )
+    .await;
+
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(body["metadata"]["query"]["level"], "850");
+}
+
+#[tokio::test]
+async fn test_strict_mode_rejects_parameters_on_api_endpoints() {
+    let app = create_test_router(true).await;
+
+    let (status, body) = send(app, Method::POST, "/api/cross-section?debug=1").await;
+
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+    assert!(body["error"]
+        .as_str()
+        .unwrap()
+        .contains("Allowed parameters: none"));
+}
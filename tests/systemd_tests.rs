@@ -0,0 +1,86 @@
+//! Integration tests for the systemd notify protocol
+
+#![cfg(unix)]
+
+use rossby_vis::{run_server_with_config, testing::MockBackend, ServerConfig};
+use std::time::Duration;
+use tokio::net::UnixDatagram;
+
+async fn receive(socket: &UnixDatagram) -> String {
+    let mut buffer = [0; 256];
+    let len = tokio::time::timeout(Duration::from_secs(5), socket.recv(&mut buffer))
+        .await
+        .expect("no notification within 5s")
+        .unwrap();
+    String::from_utf8_lossy(&buffer[..len]).into_owned()
+}
+
+#[tokio::test]
+async fn test_notifies_readiness_watchdog_and_stopping() {
+    let path = std::env::temp_dir().join(format!("rossby-vis-notify-{}", uuid::Uuid::new_v4()));
+    let socket = UnixDatagram::bind(&path).unwrap();
+    std::env::set_var("NOTIFY_SOCKET", &path);
+    std::env::set_var("WATCHDOG_USEC", "200000");
+    std::env::set_var("WATCHDOG_PID", std::process::id().to_string());
+
+    let backend = MockBackend::default().start().await;
+    let config = ServerConfig::builder(backend.url())
+        .port(0)
+        .systemd(true)
+        .build()
+        .unwrap();
+    let server = run_server_with_config(config).await.unwrap();
+
+    // Readiness is reported once the server accepts connections
+    let ready = receive(&socket).await;
+    assert!(ready.starts_with("READY=1\n"), "{}", ready);
+    assert!(ready.contains(&format!("MAINPID={}", std::process::id())));
+    let health = reqwest::get(format!("http://{}/health", server.addr()))
+        .await
+        .unwrap();
+    assert_eq!(health.status(), 200);
+
+    assert_eq!(receive(&socket).await, "WATCHDOG=1");
+
+    server.stop().await.unwrap();
+    let mut message = receive(&socket).await;
+    while message == "WATCHDOG=1" {
+        message = receive(&socket).await;
+    }
+    assert_eq!(message, "STOPPING=1");
+
+    std::fs::remove_file(&path).unwrap();
+}
+
+#[tokio::test]
+async fn test_binary_notifies_stopping_on_sigterm() {
+    use std::process::Command;
+
+    let path = std::env::temp_dir().join(format!("rossby-vis-notify-{}", uuid::Uuid::new_v4()));
+    let socket = UnixDatagram::bind(&path).unwrap();
+    let backend = MockBackend::default().start().await;
+    let mut child = Command::new(env!("CARGO_BIN_EXE_rossby-vis"))
+        .args(["--systemd", "--port", "0", "--api-url", backend.url()])
+        .env("NOTIFY_SOCKET", &path)
+        .env_remove("WATCHDOG_USEC")
+        .spawn()
+        .unwrap();
+
+    let ready = receive(&socket).await;
+    assert!(ready.starts_with("READY=1\n"), "{}", ready);
+    assert!(ready.contains(&format!("MAINPID={}", child.id())));
+
+    let status = Command::new("kill")
+        .args(["-TERM", &child.id().to_string()])
+        .status()
+        .unwrap();
+    assert!(status.success());
+    assert_eq!(receive(&socket).await, "STOPPING=1");
+    let status = tokio::task::spawn_blocking(move || child.wait())
+        .await
+        .unwrap()
+        .unwrap();
+    assert!(status.success(), "{}", status);
+
+    std::fs::remove_file(&path).unwrap();
+}
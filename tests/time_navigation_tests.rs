@@ -0,0 +1,141 @@
+//! Integration tests for `/api/time/next` and `/api/time/previous`
+
+use axum::{
+    body::Body,
+    http::{Request, StatusCode},
+    routing::get,
+    Router,
+};
+use serde_json::Value;
+use std::sync::Arc;
+use tower::ServiceExt;
+
+use rossby_vis::{
+    server::AppState,
+    timesteps::{next_time, previous_time},
+};
+
+/// Mock Rossby server whose time axis has a gap: 3-hourly steps, then a
+/// 9-hour jump to the last one
+mod mock_server {
+    use axum::{response::Json, routing::get, Router};
+    use serde_json::{json, Value};
+    use tokio::net::TcpListener;
+
+    pub async fn start() -> String {
+        let app = Router::new().route("/metadata", get(metadata));
+
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+
+        tokio::spawn(async move {
+            axum::Server::from_tcp(listener.into_std().unwrap())
+                .unwrap()
+                .serve(app.into_make_service())
+                .await
+                .unwrap();
+        });
+
+        format!("http://{}", addr)
+    }
+
+    async fn metadata() -> Json<Value> {
+        Json(json!({
+            "coordinates": {
+                "latitude": [10.0, 0.0],
+                "longitude": [0.0, 10.0],
+                "time": [700464.0, 700467.0, 700476.0]
+            },
+            "dimensions": {
+                "latitude": {"size": 2},
+                "longitude": {"size": 2},
+                "time": {"size": 3}
+            },
+            "variables": {
+                "t2m": {
+                    "dimensions": ["time", "latitude", "longitude"],
+                    "attributes": {"long_name": "2 metre temperature", "units": "K"}
+                },
+                "lsm": {
+                    "dimensions": ["latitude", "longitude"],
+                    "attributes": {"long_name": "Land-sea mask", "units": "(0 - 1)"}
+                }
+            }
+        }))
+    }
+}
+
+async fn create_test_router() -> Router {
+    let state = AppState::new(mock_server::start().await, reqwest::Client::new());
+
+    Router::new()
+        .route("/api/time/next", get(next_time))
+        .route("/api/time/previous", get(previous_time))
+        .with_state(Arc::new(state))
+}
+
+async fn get_json(app: &Router, uri: &str) -> (StatusCode, Value) {
+    let response = app
+        .clone()
+        .oneshot(Request::builder().uri(uri).body(Body::empty()).unwrap())
+        .await
+        .unwrap();
+    let status = response.status();
+    let bytes = hyper::body::to_bytes(response.into_body()).await.unwrap();
+    (status, serde_json::from_slice(&bytes).unwrap())
+}
+
+#[tokio::test]
+async fn test_next_crosses_gaps_and_stops_at_end() {
+    let app = create_test_router().await;
+
+    let (status, body) = get_json(&app, "/api/time/next?after=700467&var=t2m").await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(body["time"], 700476.0);
+    assert_eq!(body["iso"], "1979-11-29T12:00:00+00:00");
+    assert_eq!(body["index"], 2);
+    assert_eq!(body["count"], 3);
+    assert_eq!(body["at_end"], true);
+
+    // ISO times between timesteps step to the following one
+    let (_, body) = get_json(&app, "/api/time/next?after=1979-11-29T01:30:00Z").await;
+    assert_eq!(body["time"], 700467.0);
+    assert_eq!(body["at_end"], false);
+
+    let (status, body) = get_json(&app, "/api/time/next?after=700476").await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(body["time"], Value::Null);
+    assert_eq!(body["at_end"], true);
+}
+
+#[tokio::test]
+async fn test_previous_and_steps() {
+    let app = create_test_router().await;
+
+    let (_, body) = get_json(&app, "/api/time/previous").await;
+    assert_eq!(body["time"], 700476.0);
+
+    let (_, body) = get_json(&app, "/api/time/previous?before=700476&steps=2").await;
+    assert_eq!(body["time"], 700464.0);
+    assert_eq!(body["at_end"], true);
+
+    let (_, body) = get_json(&app, "/api/time/previous?before=700464").await;
+    assert_eq!(body["time"], Value::Null);
+
+    let (status, _) = get_json(&app, "/api/time/next?steps=0").await;
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+}
+
+#[tokio::test]
+async fn test_variable_must_have_time() {
+    let app = create_test_router().await;
+
+    let (status, _) = get_json(&app, "/api/time/next?var=missing").await;
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+    let (status, body) = get_json(&app, "/api/time/next?var=lsm").await;
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+    assert!(body["error"]
+        .as_str()
+        .unwrap()
+        .contains("no time dimension"));
+}
@@ -0,0 +1,190 @@
+//! Integration tests for HTTPS with certificates selected by SNI
+
+use rcgen::{BasicConstraints, Certificate, CertificateParams, DnType, IsCa};
+use rossby_vis::{
+    run_server_with_config,
+    testing::{MockBackend, RunningMockBackend},
+    tls::TlsCertificate,
+    ServerConfig, ServerHandle,
+};
+use std::{net::SocketAddr, path::PathBuf};
+
+/// A certificate authority issuing certificates into a temporary directory
+struct TestCa {
+    ca: Certificate,
+    dir: PathBuf,
+}
+
+impl TestCa {
+    fn new() -> Self {
+        let mut params = CertificateParams::new(Vec::new());
+        params.is_ca = IsCa::Ca(BasicConstraints::Unconstrained);
+        params
+            .distinguished_name
+            .push(DnType::CommonName, "rossby-vis test CA");
+        let dir = std::env::temp_dir().join(format!("rossby-vis-tls-{}", uuid::Uuid::new_v4()));
+        std::fs::create_dir_all(&dir).unwrap();
+        Self {
+            ca: Certificate::from_params(params).unwrap(),
+            dir,
+        }
+    }
+
+    /// Issue a certificate for `names`, configured to be served for `hosts`
+    fn issue(&self, names: &[&str], hosts: &[&str]) -> TlsCertificate {
+        let mut params = CertificateParams::new(
+            names
+                .iter()
+                .map(|name| name.to_string())
+                .collect::<Vec<_>>(),
+        );
+        params.distinguished_name.push(DnType::CommonName, names[0]);
+        let certificate = Certificate::from_params(params).unwrap();
+        let id = uuid::Uuid::new_v4();
+        let cert_path = self.dir.join(format!("{}.pem", id));
+        let key_path = self.dir.join(format!("{}.key", id));
+        std::fs::write(
+            &cert_path,
+            certificate.serialize_pem_with_signer(&self.ca).unwrap(),
+        )
+        .unwrap();
+        std::fs::write(&key_path, certificate.serialize_private_key_pem()).unwrap();
+        TlsCertificate {
+            hosts: hosts.iter().map(|host| host.to_string()).collect(),
+            cert_path,
+            key_path,
+        }
+    }
+
+    /// GET `/health` from `host`, verifying the certificate against this CA
+    async fn get(&self, addr: SocketAddr, host: &str) -> reqwest::Result<reqwest::Response> {
+        let root = reqwest::Certificate::from_pem(self.ca.serialize_pem().unwrap().as_bytes())?;
+        reqwest::Client::builder()
+            .add_root_certificate(root)
+            .resolve(host, addr)
+            .build()?
+            .get(format!("https://{}:{}/health", host, addr.port()))
+            .send()
+            .await
+    }
+}
+
+impl Drop for TestCa {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_dir_all(&self.dir);
+    }
+}
+
+async fn start(backend: &RunningMockBackend, certificates: Vec<TlsCertificate>) -> ServerHandle {
+    let mut builder = ServerConfig::builder(backend.url()).port(0);
+    for certificate in certificates {
+        builder = builder.tls_certificate(certificate);
+    }
+    run_server_with_config(builder.build().unwrap())
+        .await
+        .unwrap()
+}
+
+#[tokio::test]
+async fn test_certificate_selected_by_server_name() {
+    let backend = MockBackend::default().start().await;
+    let ca = TestCa::new();
+    let server = start(
+        &backend,
+        vec![
+            ca.issue(&["weather.example.org"], &["weather.example.org"]),
+            ca.issue(&["*.ocean.example.org"], &["*.ocean.example.org"]),
+            ca.issue(&["fallback.example.org"], &[]),
+        ],
+    )
+    .await;
+    let addr = server.addr();
+
+    for host in [
+        "weather.example.org",
+        "currents.ocean.example.org",
+        "fallback.example.org",
+    ] {
+        let response = ca.get(addr, host).await.unwrap();
+        assert!(response.status().is_success(), "{}", host);
+    }
+
+    // The default certificate does not cover other names
+    assert!(ca.get(addr, "weather.example.com").await.is_err());
+
+    server.stop().await.unwrap();
+}
+
+#[tokio::test]
+async fn test_unknown_server_name_refused_without_default() {
+    let backend = MockBackend::default().start().await;
+    let ca = TestCa::new();
+    let server = start(
+        &backend,
+        vec![ca.issue(&["weather.example.org"], &["weather.example.org"])],
+    )
+    .await;
+
+    assert!(ca.get(server.addr(), "weather.example.org").await.is_ok());
+    assert!(ca.get(server.addr(), "ocean.example.org").await.is_err());
+
+    server.stop().await.unwrap();
+}
+
+#[tokio::test]
+async fn test_host_with_two_certificates_rejected() {
+    let backend = MockBackend::default().start().await;
+    let ca = TestCa::new();
+    let config = ServerConfig::builder(backend.url())
+        .port(0)
+        .tls_certificate(ca.issue(&["weather.example.org"], &["weather.example.org"]))
+        .tls_certificate(ca.issue(&["weather.example.org"], &["Weather.example.org"]))
+        .build()
+        .unwrap();
+
+    let error = run_server_with_config(config).await.unwrap_err();
+
+    assert!(error.to_string().contains("more than one TLS certificate"));
+}
+
+#[tokio::test]
+async fn test_plain_and_tls_listeners_side_by_side() {
+    let backend = MockBackend::default().start().await;
+    let ca = TestCa::new();
+    let config = ServerConfig::builder(backend.url())
+        .listen("127.0.0.1:0".parse().unwrap())
+        .listen("127.0.0.1:0,tls".parse().unwrap())
+        .tls_certificate(ca.issue(&["weather.example.org"], &["weather.example.org"]))
+        .build()
+        .unwrap();
+    let server = run_server_with_config(config).await.unwrap();
+    let [plain, secure] = *server.addrs() else {
+        panic!("expected two listeners, got {:?}", server.addrs());
+    };
+
+    let response = reqwest::get(format!("http://{}/health", plain))
+        .await
+        .unwrap();
+    assert!(response.status().is_success());
+    let response = ca.get(secure, "weather.example.org").await.unwrap();
+    assert!(response.status().is_success());
+
+    server.stop().await.unwrap();
+}
+
+#[cfg(not(feature = "acme"))]
+#[tokio::test]
+async fn test_acme_requires_the_feature() {
+    let backend = MockBackend::default().start().await;
+    let config = ServerConfig::builder(backend.url())
+        .port(0)
+        .acme(rossby_vis::tls::AcmeConfig::new(vec![
+            "weather.example.org".to_string(),
+        ]))
+        .build()
+        .unwrap();
+
+    let error = run_server_with_config(config).await.unwrap_err();
+
+    assert!(error.to_string().contains("`acme` feature"));
+}
@@ -0,0 +1,116 @@
+//! Integration tests for W3C trace context propagation to the backend
+
+use axum::{
+    body::Body,
+    http::{Method, Request, StatusCode},
+    middleware,
+    routing::get,
+    Router,
+};
+use serde_json::Value;
+use std::sync::Arc;
+use tower::ServiceExt;
+
+use rossby_vis::{
+    handlers::proxy_metadata, middleware::request_tracing_middleware, server::AppState,
+};
+
+/// Mock Rossby server that reports the trace headers it received
+mod mock_server {
+    use axum::{http::HeaderMap, response::Json, routing::get, Router};
+    use serde_json::{json, Value};
+    use tokio::net::TcpListener;
+
+    pub async fn start() -> String {
+        let app = Router::new().route("/metadata", get(metadata));
+
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+
+        tokio::spawn(async move {
+            axum::Server::from_tcp(listener.into_std().unwrap())
+                .unwrap()
+                .serve(app.into_make_service())
+                .await
+                .unwrap();
+        });
+
+        format!("http://{}", addr)
+    }
+
+    async fn metadata(headers: HeaderMap) -> Json<Value> {
+        let header = |name: &str| headers.get(name).and_then(|v| v.to_str().ok());
+        Json(json!({
+            "coordinates": {"latitude": [0.0], "longitude": [0.0]},
+            "dimensions": {"latitude": {"size": 1}, "longitude": {"size": 1}},
+            "variables": {},
+            "global_attributes": {
+                "traceparent": header("traceparent"),
+                "tracestate": header("tracestate")
+            }
+        }))
+    }
+}
+
+async fn create_test_router() -> Router {
+    let state = Arc::new(AppState::new(
+        mock_server::start().await,
+        reqwest::Client::new(),
+    ));
+
+    Router::new()
+        .route("/proxy/metadata", get(proxy_metadata))
+        .layer(middleware::from_fn_with_state(
+            state.clone(),
+            request_tracing_middleware,
+        ))
+        .with_state(state)
+}
+
+async fn backend_trace_headers(headers: &[(&str, &str)]) -> Value {
+    let mut request = Request::builder()
+        .method(Method::GET)
+        .uri("/proxy/metadata");
+    for (name, value) in headers {
+        request = request.header(*name, *value);
+    }
+
+    let response = create_test_router()
+        .await
+        .oneshot(request.body(Body::empty()).unwrap())
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+
+    let bytes = hyper::body::to_bytes(response.into_body()).await.unwrap();
+    let body: Value = serde_json::from_slice(&bytes).unwrap();
+    body["global_attributes"].clone()
+}
+
+#[tokio::test]
+async fn test_incoming_trace_is_continued() {
+    let received = backend_trace_headers(&[
+        (
+            "traceparent",
+            "00-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-01",
+        ),
+        ("tracestate", "congo=t61rcWkgMzE"),
+    ])
+    .await;
+
+    let traceparent = received["traceparent"].as_str().unwrap();
+    assert!(traceparent.starts_with("00-4bf92f3577b34da6a3ce929d0e0e4736-"));
+    assert!(traceparent.ends_with("-01"));
+    assert!(!traceparent.contains("00f067aa0ba902b7"));
+    assert_eq!(received["tracestate"], "congo=t61rcWkgMzE");
+}
+
+#[tokio::test]
+async fn test_new_trace_uses_request_id() {
+    let received =
+        backend_trace_headers(&[("x-request-id", "550e8400-e29b-41d4-a716-446655440000")]).await;
+
+    let traceparent = received["traceparent"].as_str().unwrap();
+    assert!(traceparent.starts_with("00-550e8400e29b41d4a716446655440000-"));
+    assert!(received["tracestate"].is_null());
+}
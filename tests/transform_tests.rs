@@ -0,0 +1,240 @@
+//! Integration tests for the variable transform hooks
+//!
+//! The mock Rossby server names its temperature `VAR_2T`, has precipitation
+//! in metres and a variable that should not be listed. It only knows its own
+//! variable names, so every request must be translated back.
+
+use axum::{
+    body::Body,
+    http::{Request, StatusCode},
+    routing::get,
+    Router,
+};
+use serde_json::{json, Value};
+use std::sync::Arc;
+use tower::ServiceExt;
+
+use rossby_vis::{
+    derived::wind_chill_celsius,
+    handlers::{proxy_data, proxy_metadata},
+    server::AppState,
+};
+
+mod mock_server {
+    use axum::{extract::Query, http::StatusCode, response::Json, routing::get, Router};
+    use serde_json::{json, Value};
+    use std::collections::HashMap;
+    use tokio::net::TcpListener;
+
+    pub async fn start() -> String {
+        let app = Router::new()
+            .route("/metadata", get(metadata))
+            .route("/data", get(data));
+
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+
+        tokio::spawn(async move {
+            axum::Server::from_tcp(listener.into_std().unwrap())
+                .unwrap()
+                .serve(app.into_make_service())
+                .await
+                .unwrap();
+        });
+
+        format!("http://{}", addr)
+    }
+
+    async fn metadata() -> Json<Value> {
+        let dims = json!(["time", "latitude", "longitude"]);
+        Json(json!({
+            "coordinates": {
+                "latitude": [10.0, 0.0],
+                "longitude": [0.0, 10.0],
+                "time": [700464.0]
+            },
+            "dimensions": {
+                "latitude": {"size": 2},
+                "longitude": {"size": 2},
+                "time": {"size": 1}
+            },
+            "variables": {
+                "VAR_2T": {"dimensions": dims, "attributes": {"units": "K"}},
+                "u10": {"dimensions": dims, "attributes": {"units": "m s**-1"}},
+                "v10": {"dimensions": dims, "attributes": {"units": "m s**-1"}},
+                "tp": {"dimensions": dims, "attributes": {"units": "m", "valid_range": [0.0, 0.1]}},
+                "junk": {"dimensions": dims}
+            }
+        }))
+    }
+
+    async fn data(
+        Query(params): Query<HashMap<String, String>>,
+    ) -> Result<Json<Value>, StatusCode> {
+        let mut data = serde_json::Map::new();
+        for var in params["vars"].split(',') {
+            let values = match var {
+                "VAR_2T" => json!([253.15, 253.15, 300.0, null]),
+                "u10" => json!([10.0, 0.0, 10.0, 10.0]),
+                "v10" => json!([0.0, 0.0, 0.0, 0.0]),
+                "tp" => json!([0.001, 0.0, 9999.0, null]),
+                _ => return Err(StatusCode::BAD_REQUEST),
+            };
+            data.insert(var.to_string(), values);
+        }
+
+        Ok(Json(json!({
+            "metadata": {"shape": [1, 2, 2], "dimensions": ["time", "latitude", "longitude"]},
+            "data": data
+        })))
+    }
+}
+
+async fn create_test_router() -> Router {
+    create_router_with(|_| {}).await
+}
+
+async fn create_router_with(configure: impl FnOnce(&mut AppState)) -> Router {
+    let mut state = AppState::new(mock_server::start().await, reqwest::Client::new());
+    state
+        .transforms
+        .add_json(
+            &json!([
+                {"variable": "VAR_2T", "rename": "t2m", "attributes": {"long_name": "2 metre temperature"}},
+                {"variable": "tp", "expression": "if(x >= 9999, nan, x * 1000)", "attributes": {"units": "mm"}},
+                {"variable": "junk", "hide": true}
+            ])
+            .to_string(),
+        )
+        .unwrap();
+    configure(&mut state);
+
+    Router::new()
+        .route("/proxy/metadata", get(proxy_metadata))
+        .route("/proxy/data", get(proxy_data))
+        .with_state(Arc::new(state))
+}
+
+async fn get_json(app: &Router, uri: &str) -> (StatusCode, Value) {
+    let response = app
+        .clone()
+        .oneshot(Request::builder().uri(uri).body(Body::empty()).unwrap())
+        .await
+        .unwrap();
+    let status = response.status();
+    let bytes = hyper::body::to_bytes(response.into_body()).await.unwrap();
+    (status, serde_json::from_slice(&bytes).unwrap())
+}
+
+#[tokio::test]
+async fn test_transformed_catalog() {
+    let app = create_test_router().await;
+
+    let (status, metadata) = get_json(&app, "/proxy/metadata").await;
+    assert_eq!(status, StatusCode::OK);
+    let variables = &metadata["variables"];
+    assert!(variables.get("VAR_2T").is_none());
+    assert!(variables.get("junk").is_none());
+    assert_eq!(
+        variables["t2m"]["attributes"],
+        json!({"units": "K", "long_name": "2 metre temperature"})
+    );
+    assert_eq!(
+        variables["tp"]["attributes"],
+        json!({"units": "mm", "valid_range": [0.0, 100.0]})
+    );
+    // The renamed temperature is recognized as a derived product input
+    assert_eq!(
+        variables["wind_chill"]["attributes"]["derived_from"],
+        json!(["t2m", "u10", "v10"])
+    );
+}
+
+#[tokio::test]
+async fn test_transformed_data() {
+    let app = create_test_router().await;
+
+    let (status, body) = get_json(&app, "/proxy/data?vars=t2m,tp,u10&time=700464").await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(
+        body["data"],
+        json!({
+            "t2m": [253.15, 253.15, 300.0, null],
+            "tp": [1.0, 0.0, null, null],
+            "u10": [10.0, 0.0, 10.0, 10.0]
+        })
+    );
+
+    let (status, body) = get_json(&app, "/proxy/data?vars=wind_chill&time=700464").await;
+    assert_eq!(status, StatusCode::OK);
+    let expected = wind_chill_celsius(-20.0, 36.0) + 273.15;
+    assert!((body["data"]["wind_chill"][0].as_f64().unwrap() - expected).abs() < 1e-9);
+}
+
+#[tokio::test]
+async fn test_hidden_variables_are_not_served() {
+    let app = create_test_router().await;
+
+    for uri in [
+        "/proxy/data?vars=junk&time=700464",
+        "/proxy/data?vars=t2m,junk&time=700464",
+        "/proxy/data?vars=wind_chill,junk&time=700464",
+    ] {
+        let (status, body) = get_json(&app, uri).await;
+        assert_eq!(status, StatusCode::NOT_FOUND, "{}", uri);
+        assert!(body["error"].as_str().unwrap().contains("junk"));
+    }
+}
+
+#[cfg(feature = "scripting")]
+#[tokio::test]
+async fn test_transform_scripts() {
+    let script = r#"
+        fn sources(name) {
+            switch name {
+                "wind_speed" => ["u10", "v10"],
+                "v10" => [],
+                _ => [name],
+            }
+        }
+
+        fn metadata(doc) {
+            let speed = doc.variables.u10;
+            speed.attributes.long_name = "10 metre wind speed";
+            doc.variables.wind_speed = speed;
+            doc.variables.remove("v10");
+            doc
+        }
+
+        fn data(response) {
+            if "u10" in response.data && "v10" in response.data {
+                let v = response.data.v10;
+                response.data.wind_speed = response.data.u10.map(|u, i| sqrt(u * u + v[i] * v[i]));
+            }
+            response
+        }
+    "#;
+    let app = create_router_with(|state| state.transforms.add_script(script).unwrap()).await;
+
+    let (status, metadata) = get_json(&app, "/proxy/metadata").await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(
+        metadata["variables"]["wind_speed"]["attributes"]["long_name"],
+        "10 metre wind speed"
+    );
+    assert!(metadata["variables"].get("v10").is_none());
+
+    // Scripts run after the hooks and see the renamed temperature
+    let (status, body) = get_json(&app, "/proxy/data?vars=wind_speed,t2m&time=700464").await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(
+        body["data"],
+        json!({
+            "t2m": [253.15, 253.15, 300.0, null],
+            "wind_speed": [10.0, 0.0, 10.0, 10.0]
+        })
+    );
+
+    let (status, _) = get_json(&app, "/proxy/data?vars=v10&time=700464").await;
+    assert_eq!(status, StatusCode::NOT_FOUND);
+}
@@ -0,0 +1,100 @@
+//! Integration tests for unit system selection
+
+use axum::{
+    body::Body,
+    http::{Request, StatusCode},
+    Router,
+};
+use serde_json::{json, Value};
+use tower::ServiceExt;
+
+use rossby_vis::{
+    build_router,
+    testing::{MockBackend, MockVariable},
+    units::UnitSystem,
+    AppState, ServerConfig,
+};
+
+async fn router(units: UnitSystem) -> Router {
+    let backend = MockBackend::default()
+        .with_variables(vec![
+            MockVariable::new("u10", "10 metre U wind component", "m s**-1"),
+            MockVariable::new("v10", "10 metre V wind component", "m s**-1"),
+            MockVariable::new("t2m", "2 metre temperature", "K"),
+        ])
+        .start()
+        .await;
+    let config = ServerConfig::builder(backend.url())
+        .units(units)
+        .build()
+        .unwrap();
+    build_router(AppState::from_config(config).await.unwrap())
+}
+
+async fn get_json(app: &Router, uri: &str) -> (StatusCode, Value) {
+    let response = app
+        .clone()
+        .oneshot(Request::builder().uri(uri).body(Body::empty()).unwrap())
+        .await
+        .unwrap();
+    let status = response.status();
+    let body = hyper::body::to_bytes(response.into_body()).await.unwrap();
+    (status, serde_json::from_slice(&body).unwrap_or(Value::Null))
+}
+
+fn first_value(values: &Value) -> f64 {
+    values[0].as_f64().unwrap()
+}
+
+#[tokio::test]
+async fn test_units_on_earth_routes() {
+    let app = router(UnitSystem::Si).await;
+    let temp = "/data/weather/current/current-t2m-surface-level-gfs-1.0.json";
+
+    let (_, stored) = get_json(&app, temp).await;
+    assert_eq!(stored[0]["header"]["parameterUnit"], "K");
+
+    let (status, imperial) = get_json(&app, &format!("{}?units=imperial", temp)).await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(imperial[0]["header"]["parameterUnit"], "°F");
+    let kelvin = first_value(&stored[0]["data"]);
+    assert!((first_value(&imperial[0]["data"]) - (kelvin * 1.8 - 459.67)).abs() < 1e-9);
+
+    let (_, wind) = get_json(
+        &app,
+        "/data/weather/current/current-wind-surface-level-gfs-1.0.json?units=kt",
+    )
+    .await;
+    for record in wind.as_array().unwrap() {
+        assert_eq!(record["header"]["parameterUnit"], "kn");
+    }
+
+    let (status, _) = get_json(&app, &format!("{}?units=furlongs", temp)).await;
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+}
+
+#[tokio::test]
+async fn test_units_on_data_proxy() {
+    let app = router(UnitSystem::Si).await;
+
+    let (status, data) = get_json(&app, "/proxy/data?vars=t2m,u10&units=metric").await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(data["metadata"]["variables"]["t2m"]["units"], "°C");
+    assert_eq!(data["metadata"]["variables"]["u10"]["units"], "km/h");
+    assert!((first_value(&data["data"]["t2m"]) - (1.0 - 273.15)).abs() < 1e-9);
+    assert!((first_value(&data["data"]["u10"]) - 3.6).abs() < 1e-9);
+
+    // Without a unit system values are served as stored
+    let (_, data) = get_json(&app, "/proxy/data?vars=t2m").await;
+    assert_eq!(data["metadata"]["variables"]["t2m"]["units"], "K");
+}
+
+#[tokio::test]
+async fn test_preferred_units() {
+    let (status, prefs) = get_json(&router(UnitSystem::Nautical).await, "/api/prefs").await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(prefs, json!({"units": "kt"}));
+
+    let (_, prefs) = get_json(&router(UnitSystem::Si).await, "/api/prefs").await;
+    assert_eq!(prefs["units"], "si");
+}
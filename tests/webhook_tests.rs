@@ -0,0 +1,168 @@
+//! Integration tests for the new data webhooks
+//!
+//! The mock Rossby server gains a timestep between two metadata refreshes,
+//! and a mock receiver records the notifications it is sent.
+
+use axum::{extract::State, routing::post, Json, Router};
+use serde_json::{json, Value};
+use std::sync::{
+    atomic::{AtomicBool, Ordering},
+    Arc, Mutex,
+};
+use tokio::net::TcpListener;
+
+use rossby_vis::{
+    scheduler::{Scheduler, TaskSchedule},
+    server::AppState,
+    webhooks::{WebhookConfig, Webhooks},
+};
+
+mod mock_server {
+    use axum::{extract::State, response::Json, routing::get, Router};
+    use serde_json::{json, Value};
+    use std::sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    };
+    use tokio::net::TcpListener;
+
+    /// Start the backend; its dataset gains a timestep once `updated` is set
+    pub async fn start(updated: Arc<AtomicBool>) -> String {
+        let app = Router::new()
+            .route("/metadata", get(metadata))
+            .with_state(updated);
+
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+
+        tokio::spawn(async move {
+            axum::Server::from_tcp(listener.into_std().unwrap())
+                .unwrap()
+                .serve(app.into_make_service())
+                .await
+                .unwrap();
+        });
+
+        format!("http://{}", addr)
+    }
+
+    async fn metadata(State(updated): State<Arc<AtomicBool>>) -> Json<Value> {
+        let times = if updated.load(Ordering::SeqCst) {
+            json!([700464.0, 700470.0])
+        } else {
+            json!([700464.0])
+        };
+        Json(json!({
+            "global_attributes": {"title": "Test forecast"},
+            "coordinates": {
+                "latitude": [10.0, -10.0],
+                "longitude": [0.0, 90.0],
+                "time": times
+            },
+            "dimensions": {
+                "latitude": {"size": 2},
+                "longitude": {"size": 2},
+                "time": {"size": times.as_array().unwrap().len()}
+            },
+            "variables": {
+                "t2m": {"dimensions": ["time", "latitude", "longitude"]},
+                "lsm": {"dimensions": ["latitude", "longitude"]}
+            }
+        }))
+    }
+}
+
+/// Start a webhook receiver recording the bodies it is sent
+async fn start_receiver() -> (String, Arc<Mutex<Vec<Value>>>) {
+    let received = Arc::new(Mutex::new(Vec::new()));
+    let app = Router::new()
+        .route(
+            "/hook",
+            post(
+                |State(received): State<Arc<Mutex<Vec<Value>>>>, Json(body): Json<Value>| async move {
+                    received.lock().unwrap().push(body);
+                },
+            ),
+        )
+        .with_state(received.clone());
+
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    tokio::spawn(async move {
+        axum::Server::from_tcp(listener.into_std().unwrap())
+            .unwrap()
+            .serve(app.into_make_service())
+            .await
+            .unwrap();
+    });
+
+    (format!("http://{}/hook", addr), received)
+}
+
+async fn create_state(
+    template: Option<Value>,
+) -> (Arc<AppState>, Arc<AtomicBool>, Arc<Mutex<Vec<Value>>>) {
+    let updated = Arc::new(AtomicBool::new(false));
+    let (hook_url, received) = start_receiver().await;
+
+    let mut config = WebhookConfig::default();
+    config.add_url(&hook_url).unwrap();
+    config.template = template;
+    let mut schedule = TaskSchedule::default();
+    schedule
+        .add_json(r#"[{"task": "refresh-metadata", "every": "10m"}]"#)
+        .unwrap();
+
+    let mut state = AppState::new(
+        mock_server::start(updated.clone()).await,
+        reqwest::Client::new(),
+    );
+    state.scheduler = Arc::new(Scheduler::new(schedule));
+    state.webhooks = Webhooks::new(config).unwrap();
+    (Arc::new(state), updated, received)
+}
+
+#[tokio::test]
+async fn test_webhook_notified_of_new_timesteps() {
+    let (state, updated, received) = create_state(None).await;
+    let app = Router::new();
+
+    // The first refresh only records the dataset, an unchanged one notifies nobody
+    state.scheduler.run_task(&state, app.clone(), 0).await;
+    state.scheduler.run_task(&state, app.clone(), 0).await;
+    assert!(received.lock().unwrap().is_empty());
+
+    updated.store(true, Ordering::SeqCst);
+    state.scheduler.run_task(&state, app.clone(), 0).await;
+    let received = received.lock().unwrap().clone();
+    assert_eq!(received.len(), 1);
+    assert_eq!(received[0]["event"], "new_data");
+    assert_eq!(received[0]["dataset"], "Test forecast");
+    assert_eq!(received[0]["variables"], json!(["t2m"]));
+    assert_eq!(
+        received[0]["new_times"],
+        json!(["1979-11-29T06:00:00+00:00"])
+    );
+
+    let report = &state.scheduler.reports()[0];
+    assert_eq!(report.runs, 3);
+    assert!(report
+        .last_result
+        .as_deref()
+        .unwrap()
+        .ends_with("1 new timesteps, notified 1 of 1 webhooks"));
+}
+
+#[tokio::test]
+async fn test_webhook_template() {
+    let template = json!({"text": "{count} new {dataset} timestep(s): {new_times}"});
+    let (state, updated, received) = create_state(Some(template)).await;
+
+    state.scheduler.run_task(&state, Router::new(), 0).await;
+    updated.store(true, Ordering::SeqCst);
+    state.scheduler.run_task(&state, Router::new(), 0).await;
+    assert_eq!(
+        received.lock().unwrap().as_slice(),
+        [json!({"text": "1 new Test forecast timestep(s): 1979-11-29T06:00:00+00:00"})]
+    );
+}